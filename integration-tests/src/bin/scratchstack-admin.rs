@@ -0,0 +1,124 @@
+//! Administrative commands for a Scratchstack deployment.
+//!
+//! `scratchstack-admin smoke` runs smoke test scenarios (see [scratchstack_integration_tests::smoke]) against a live
+//! deployment after it is deployed. The bootstrap credentials are read from `AWS_ACCESS_KEY_ID` and
+//! `AWS_SECRET_ACCESS_KEY`.
+use {
+    getopts::Options,
+    scratchstack_integration_tests::{
+        smoke::{junit_report, CaseOutcome, Scenario, SmokeRunner, SmokeTarget},
+        Credentials,
+    },
+    std::{
+        collections::HashMap,
+        env, fs,
+        io::{self, Write},
+        process::exit,
+    },
+};
+
+#[allow(unused_must_use)]
+fn print_usage(stream: &mut dyn Write, program: &str, opts: Options) {
+    let brief = format!("Usage: {program} smoke [options] SCENARIO.yaml...");
+    write!(stream, "{}", opts.usage(&brief));
+}
+
+#[tokio::main]
+async fn main() {
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this usage information");
+    opts.optopt("", "iam-url", "IAM endpoint of the deployment", "URL");
+    opts.optopt("", "sts-url", "STS endpoint of the deployment", "URL");
+    opts.optopt("", "region", "region to sign requests for (default: us-east-1)", "REGION");
+    opts.optopt("", "junit", "write a JUnit XML report to FILENAME", "FILENAME");
+
+    if args.get(1).map(String::as_str) != Some("smoke") {
+        print_usage(&mut io::stderr(), &program, opts);
+        exit(2);
+    }
+
+    let matches = match opts.parse(&args[2..]) {
+        Ok(m) => m,
+        Err(f) => {
+            eprintln!("{f}");
+            exit(2);
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(&mut io::stdout(), &program, opts);
+        return;
+    }
+
+    if matches.free.is_empty() {
+        print_usage(&mut io::stderr(), &program, opts);
+        exit(2);
+    }
+
+    let credentials = match (env::var("AWS_ACCESS_KEY_ID"), env::var("AWS_SECRET_ACCESS_KEY")) {
+        (Ok(access_key_id), Ok(secret_access_key)) => Credentials {
+            access_key_id,
+            secret_access_key,
+            session_token: None,
+        },
+        _ => {
+            eprintln!("AWS_ACCESS_KEY_ID and AWS_SECRET_ACCESS_KEY must be set to the bootstrap credentials");
+            exit(2);
+        }
+    };
+
+    let mut endpoints = HashMap::new();
+    for service in ["iam", "sts"] {
+        if let Some(url) = matches.opt_str(&format!("{service}-url")) {
+            endpoints.insert(service.to_string(), url);
+        }
+    }
+    if endpoints.is_empty() {
+        eprintln!("At least one of --iam-url and --sts-url must be given");
+        exit(2);
+    }
+
+    let mut scenarios = Vec::with_capacity(matches.free.len());
+    for filename in &matches.free {
+        match Scenario::read_file(filename) {
+            Ok(scenario) => scenarios.push(scenario),
+            Err(e) => {
+                eprintln!("Unable to read scenario {filename}: {e}");
+                exit(2);
+            }
+        }
+    }
+
+    let runner = SmokeRunner::new(SmokeTarget {
+        endpoints,
+        region: matches.opt_str("region").unwrap_or_else(|| "us-east-1".to_string()),
+        credentials,
+    });
+
+    let mut results = Vec::with_capacity(scenarios.len());
+    for scenario in &scenarios {
+        let result = runner.run(scenario).await;
+        for case in &result.cases {
+            match &case.outcome {
+                CaseOutcome::Passed => println!("ok      {} / {}", result.name, case.name),
+                CaseOutcome::Skipped => println!("skipped {} / {}", result.name, case.name),
+                CaseOutcome::Failed(message) => println!("FAILED  {} / {}: {message}", result.name, case.name),
+            }
+        }
+        results.push(result);
+    }
+
+    if let Some(filename) = matches.opt_str("junit") {
+        if let Err(e) = fs::write(&filename, junit_report(&results)) {
+            eprintln!("Unable to write JUnit report to {filename}: {e}");
+            exit(2);
+        }
+    }
+
+    if !results.iter().all(|result| result.passed()) {
+        exit(1);
+    }
+}
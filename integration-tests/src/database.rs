@@ -0,0 +1,195 @@
+use {
+    crate::signer::Credentials,
+    scratchstack_service_common::{
+        access_key::AccessKeyPrefixes,
+        bootstrap::{create_account, DEFAULT_ADMIN_USER_NAME},
+        store::SqlStore,
+    },
+    sqlx::{postgres::PgPoolOptions, AnyPool, Executor, PgPool},
+    std::sync::Arc,
+    testcontainers::{clients::Cli, images::postgres::Postgres, Container},
+};
+
+const LIMITSTORE_MIGRATIONS: &[&str] =
+    &[include_str!("../../migrations/limitstore/postgresql/20210319223838_limitstore.up.sql")];
+
+const IAM_MIGRATIONS: &[&str] = &[
+    include_str!("../../migrations/iam/postgresql/20210319233431_iam.up.sql"),
+    include_str!("../../migrations/iam/postgresql/20210402234420_add_pk_to_history_tables.up.sql"),
+    include_str!("../../migrations/iam/postgresql/20221014000000_add_role_max_session_duration.up.sql"),
+    include_str!("../../migrations/iam/postgresql/20221014000001_add_schema_version.up.sql"),
+    include_str!("../../migrations/iam/postgresql/20221014000002_add_lease.up.sql"),
+    include_str!("../../migrations/iam/postgresql/20221014000003_add_policy_limits.up.sql"),
+    include_str!("../../migrations/iam/postgresql/20221014000004_add_credential_revocation.up.sql"),
+    include_str!("../../migrations/iam/postgresql/20221014000005_add_tags.up.sql"),
+];
+
+/// A PostgreSQL container with the Scratchstack schemas applied.
+pub struct TestDatabase<'d> {
+    _container: Container<'d, Postgres>,
+    url: String,
+    pool: PgPool,
+}
+
+impl<'d> TestDatabase<'d> {
+    pub async fn start(docker: &'d Cli) -> Self {
+        let container = docker.run(Postgres::default());
+        let port = container.get_host_port_ipv4(5432);
+        let url = format!("postgres://postgres@127.0.0.1:{port}/postgres");
+        let pool = PgPoolOptions::new().max_connections(2).connect(&url).await.expect("Unable to connect to database");
+
+        // The IAM migration populates limitstore tables, so these must be applied first.
+        for migration in LIMITSTORE_MIGRATIONS.iter().chain(IAM_MIGRATIONS) {
+            pool.execute(*migration).await.expect("Unable to apply migration");
+        }
+
+        Self {
+            _container: container,
+            url,
+            pool,
+        }
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// Create an account the way `scratchstack-service-iam --create-account` does, returning the credentials of its
+    /// administrator, who is allowed every action. Other principals can then be created through the IAM API.
+    pub async fn create_account(&self, account_id: &str) -> Credentials {
+        let pool = AnyPool::connect(&self.url).await.expect("Unable to connect to database");
+        let store = SqlStore::new(Arc::new(pool));
+        let email = format!("{account_id}@example.com");
+        let bootstrap =
+            create_account(&store, &AccessKeyPrefixes::default(), &email, Some(account_id), DEFAULT_ADMIN_USER_NAME)
+                .await
+                .expect("Unable to create account");
+
+        Credentials {
+            access_key_id: bootstrap.access_key_id,
+            secret_access_key: bootstrap.secret_access_key,
+            session_token: None,
+        }
+    }
+
+    /// Insert an account, an IAM user, and an access key for that user directly into the database.
+    ///
+    /// `access_key_id` is the full key id (including the `AKIA` prefix); the database stores only the portion
+    /// following the prefix.
+    pub async fn seed_user(
+        &self,
+        account_id: &str,
+        user_id: &str,
+        user_name: &str,
+        access_key_id: &str,
+        secret_key: &str,
+    ) {
+        sqlx::query("INSERT INTO iam.account(account_id, email, active) VALUES($1, $2, TRUE) ON CONFLICT DO NOTHING")
+            .bind(account_id)
+            .bind(format!("{account_id}@example.com"))
+            .execute(&self.pool)
+            .await
+            .expect("Unable to create account");
+
+        sqlx::query(
+            "INSERT INTO iam.iam_user(user_id, account_id, user_name_lower, user_name_cased, path, created_at) \
+             VALUES($1, $2, $3, $4, '/', CURRENT_TIMESTAMP)",
+        )
+        .bind(user_id)
+        .bind(account_id)
+        .bind(user_name.to_lowercase())
+        .bind(user_name)
+        .execute(&self.pool)
+        .await
+        .expect("Unable to create user");
+
+        sqlx::query(
+            "INSERT INTO iam.iam_user_credential(user_id, access_key_id, secret_key, active, created_at) \
+             VALUES($1, $2, $3, TRUE, CURRENT_TIMESTAMP)",
+        )
+        .bind(user_id)
+        .bind(access_key_id.strip_prefix("AKIA").unwrap_or(access_key_id))
+        .bind(secret_key)
+        .execute(&self.pool)
+        .await
+        .expect("Unable to create access key");
+    }
+
+    /// Give an IAM user seeded by [TestDatabase::seed_user] an inline policy, which the services authorize its
+    /// requests against.
+    pub async fn seed_user_policy(&self, user_id: &str, policy_name: &str, policy_document: &str) {
+        sqlx::query(
+            "INSERT INTO iam.iam_user_inline_policy(user_id, policy_name_lower, policy_name_cased, policy_document) \
+             VALUES($1, $2, $3, $4)",
+        )
+        .bind(user_id)
+        .bind(policy_name.to_lowercase())
+        .bind(policy_name)
+        .bind(policy_document)
+        .execute(&self.pool)
+        .await
+        .expect("Unable to create user policy");
+    }
+
+    /// Give an IAM role seeded by [TestDatabase::seed_role] an inline policy, which the services authorize requests
+    /// made with its sessions against.
+    pub async fn seed_role_policy(&self, role_id: &str, policy_name: &str, policy_document: &str) {
+        sqlx::query(
+            "INSERT INTO iam.iam_role_inline_policy(role_id, policy_name_lower, policy_name_cased, policy_document) \
+             VALUES($1, $2, $3, $4)",
+        )
+        .bind(role_id)
+        .bind(policy_name.to_lowercase())
+        .bind(policy_name)
+        .bind(policy_document)
+        .execute(&self.pool)
+        .await
+        .expect("Unable to create role policy");
+    }
+
+    /// Insert an AES-256-GCM session token key, valid from an hour ago for a day, which STS seals the tokens of new
+    /// sessions with. The services load token keys when they start, so this must be called before
+    /// [Stack::start][crate::Stack::start].
+    pub async fn seed_token_key(&self, key_id: &str, key: &[u8; 32]) {
+        sqlx::query(
+            "INSERT INTO iam.iam_role_token_key(access_key_id, encryption_algorithm, encryption_key, valid_at, \
+             expires_at) VALUES($1, 'AES-256-GCM', $2, CURRENT_TIMESTAMP - INTERVAL '1 hour', \
+             CURRENT_TIMESTAMP + INTERVAL '1 day')",
+        )
+        .bind(key_id)
+        .bind(key.to_vec())
+        .execute(&self.pool)
+        .await
+        .expect("Unable to create token key");
+    }
+
+    /// Insert an IAM role at path `/` with the given trust policy and `MaxSessionDuration` directly into the
+    /// database. The account must already exist, e.g. from [TestDatabase::seed_user].
+    pub async fn seed_role(
+        &self,
+        account_id: &str,
+        role_id: &str,
+        role_name: &str,
+        assume_role_policy_document: &str,
+        max_session_duration: i32,
+    ) {
+        sqlx::query(
+            "INSERT INTO iam.iam_role(role_id, account_id, role_name_lower, role_name_cased, path, \
+             assume_role_policy_document, max_session_duration, created_at) \
+             VALUES($1, $2, $3, $4, '/', $5, $6, CURRENT_TIMESTAMP)",
+        )
+        .bind(role_id)
+        .bind(account_id)
+        .bind(role_name.to_lowercase())
+        .bind(role_name)
+        .bind(assume_role_policy_document)
+        .bind(max_session_duration)
+        .execute(&self.pool)
+        .await
+        .expect("Unable to create role");
+    }
+}
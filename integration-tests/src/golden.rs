@@ -0,0 +1,215 @@
+//! Golden responses: response bodies compared against recorded AWS responses to catch drift in element names,
+//! ordering, and namespaces that SDKs depend on.
+//!
+//! The files under `golden/` are AWS responses to the same requests, with account-specific values replaced by the
+//! test fixtures. Before comparing, both sides are normalized: XML declarations and the whitespace AWS pretty-prints
+//! with are removed, and volatile fields such as request ids are masked. Setting [UPDATE_ENV] rewrites the files
+//! from the actual responses instead, for responses that have no AWS counterpart.
+use std::{
+    env,
+    fs::{create_dir_all, read_to_string, write},
+    path::PathBuf,
+};
+
+/// Set this environment variable to record the actual responses as the new golden files.
+pub const UPDATE_ENV: &str = "SCRATCHSTACK_UPDATE_GOLDEN";
+
+/// What masked values are replaced with.
+pub const MASK: &str = "*";
+
+/// Fields that differ between otherwise identical responses, masked in every comparison.
+pub const VOLATILE_FIELDS: &[&str] = &[
+    "AccessKeyId",
+    "Expiration",
+    "RequestId",
+    "RequestID",
+    "SecretAccessKey",
+    "SessionToken",
+    "StartedAt",
+    "UptimeSeconds",
+];
+
+/// A golden response file and how to normalize responses before comparing them with it.
+#[derive(Clone, Debug)]
+pub struct Golden {
+    name: String,
+    masked: Vec<String>,
+    dropped: Vec<String>,
+}
+
+impl Golden {
+    /// The golden file `golden/<name>`, e.g. `sts/GetCallerIdentity.xml`.
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+            masked: VOLATILE_FIELDS.iter().map(ToString::to_string).collect(),
+            dropped: Vec::new(),
+        }
+    }
+
+    /// Also mask the value of `field`.
+    pub fn mask(mut self, field: &str) -> Self {
+        self.masked.push(field.to_string());
+        self
+    }
+
+    /// Remove the XML element `element` entirely, for optional elements whose presence depends on the build.
+    pub fn drop_element(mut self, element: &str) -> Self {
+        self.dropped.push(element.to_string());
+        self
+    }
+
+    pub fn path(&self) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("golden").join(&self.name)
+    }
+
+    /// `body` as it is compared: compacted, with dropped elements removed and masked fields replaced by [MASK].
+    pub fn normalize(&self, body: &str) -> String {
+        let mut body = body.lines().map(str::trim).collect::<Vec<_>>().join("");
+        if body.starts_with("<?xml") {
+            if let Some(end) = body.find("?>") {
+                body = body[end + 2..].to_string();
+            }
+        }
+
+        for element in &self.dropped {
+            body = drop_xml_element(&body, element);
+        }
+
+        for field in &self.masked {
+            body = if body.starts_with('{') {
+                mask_json_field(&body, field)
+            } else {
+                mask_xml_element(&body, field)
+            };
+        }
+
+        body
+    }
+
+    /// Panic unless `body` matches the golden file once both are normalized.
+    pub fn assert_matches(&self, body: &str) {
+        let path = self.path();
+        let actual = self.normalize(body);
+        if env::var_os(UPDATE_ENV).is_some() {
+            if let Some(parent) = path.parent() {
+                create_dir_all(parent).unwrap_or_else(|e| panic!("Unable to create {}: {e}", parent.display()));
+            }
+            write(&path, format!("{actual}\n")).unwrap_or_else(|e| panic!("Unable to write {}: {e}", path.display()));
+            return;
+        }
+
+        let expected = read_to_string(&path)
+            .unwrap_or_else(|e| panic!("Unable to read {} (set {UPDATE_ENV} to record it): {e}", path.display()));
+        assert_eq!(actual, self.normalize(&expected), "Response does not match {}", path.display());
+    }
+}
+
+/// Replace the text of every `<element>` with [MASK].
+fn mask_xml_element(xml: &str, element: &str) -> String {
+    let open = format!("<{element}>");
+    let close = format!("</{element}>");
+    let mut result = String::with_capacity(xml.len());
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let content = start + open.len();
+        let end = match rest[content..].find(&close) {
+            Some(end) => end,
+            None => break,
+        };
+        result.push_str(&rest[..content]);
+        result.push_str(MASK);
+        rest = &rest[content + end..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Remove every `<element>`, including its contents.
+fn drop_xml_element(xml: &str, element: &str) -> String {
+    let open = format!("<{element}>");
+    let close = format!("</{element}>");
+    let mut result = String::with_capacity(xml.len());
+    let mut rest = xml;
+    while let Some(start) = rest.find(&open) {
+        let end = match rest[start..].find(&close) {
+            Some(end) => end,
+            None => break,
+        };
+        result.push_str(&rest[..start]);
+        rest = &rest[start + end + close.len()..];
+    }
+    result.push_str(rest);
+    result
+}
+
+/// Replace every string or number value of `"field"` with [MASK]. Objects and arrays are left alone.
+fn mask_json_field(json: &str, field: &str) -> String {
+    let key = format!("\"{field}\":");
+    let mut result = String::with_capacity(json.len());
+    let mut rest = json;
+    while let Some(start) = rest.find(&key) {
+        let value = start + key.len();
+        result.push_str(&rest[..value]);
+        rest = &rest[value..];
+
+        let end = if rest.starts_with('"') {
+            // Find the closing quote, skipping escaped characters.
+            let mut escaped = false;
+            rest.char_indices()
+                .skip(1)
+                .find(|(_, c)| {
+                    let close = !escaped && *c == '"';
+                    escaped = !escaped && *c == '\\';
+                    close
+                })
+                .map(|(i, _)| i + 1)
+        } else if rest.starts_with(|c: char| c == '-' || c.is_ascii_digit()) {
+            Some(rest.find(|c: char| c == ',' || c == '}' || c == ']').unwrap_or(rest.len()))
+        } else {
+            None
+        };
+
+        if let Some(end) = end {
+            result.push_str(&format!("\"{MASK}\""));
+            rest = &rest[end..];
+        }
+    }
+    result.push_str(rest);
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::Golden, pretty_assertions::assert_eq};
+
+    #[test_log::test]
+    fn test_normalize() {
+        let golden = Golden::new("sts/Example.xml").drop_element("GitSha");
+        let recorded = r#"<?xml version="1.0" encoding="UTF-8"?>
+            <ExampleResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+              <ExampleResult>
+                <Arn>arn:aws:iam::123456789012:user/Alice</Arn>
+                <GitSha>0123abcd</GitSha>
+              </ExampleResult>
+              <ResponseMetadata>
+                <RequestId>01234567-89ab-cdef-0123-456789abcdef</RequestId>
+              </ResponseMetadata>
+            </ExampleResponse>"#;
+        assert_eq!(
+            golden.normalize(recorded),
+            r#"<ExampleResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/"><ExampleResult><Arn>arn:aws:iam::123456789012:user/Alice</Arn></ExampleResult><ResponseMetadata><RequestId>*</RequestId></ResponseMetadata></ExampleResponse>"#
+        );
+
+        let golden = Golden::new("example.json").mask("version");
+        assert_eq!(
+            golden
+                .normalize(r#"{"service":"sts","version":"1.2.3","uptime_seconds":17,"message":"a \"quoted\" word"}"#),
+            r#"{"service":"sts","version":"*","uptime_seconds":17,"message":"a \"quoted\" word"}"#
+        );
+        assert_eq!(
+            Golden::new("example.json").mask("message").normalize(r#"{"message":"a \"quoted\" word","n":[1]}"#),
+            r#"{"message":"*","n":[1]}"#
+        );
+    }
+}
@@ -0,0 +1,19 @@
+//! Harness for end-to-end tests that run the Scratchstack services against a temporary PostgreSQL database.
+//!
+//! The tests in this crate that start the services require Docker and are ignored by default; run them with
+//! `cargo test -p scratchstack-integration-tests -- --ignored`. The SigV4 vector tests in [sigv4] run without it.
+//!
+//! [smoke] runs declarative smoke test scenarios against a live deployment; see the `scratchstack-admin` binary.
+mod database;
+mod golden;
+mod signer;
+pub mod sigv4;
+pub mod smoke;
+mod stack;
+
+pub use self::{
+    database::TestDatabase,
+    golden::{Golden, MASK, UPDATE_ENV, VOLATILE_FIELDS},
+    signer::{Credentials, Signer},
+    stack::{ServiceProcess, Stack},
+};
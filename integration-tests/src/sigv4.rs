@@ -0,0 +1,361 @@
+//! AWS SigV4 test vectors, run against a canonicalization profile.
+//!
+//! Vectors use the layout of the AWS SigV4 test suite: a directory per vector holding `<name>.req`, the request as
+//! sent, and the expected `<name>.creq` (canonical request), `<name>.sts` (string to sign) and `<name>.authz`
+//! (Authorization header), signed with [SUITE_CREDENTIALS] for [SUITE_REGION] and [SUITE_SERVICE]. The vectors under
+//! `sigv4/aws` are from the AWS suite; others are written in the same layout for behavior the suite does not cover.
+//!
+//! A service asserts that it canonicalizes requests the same way with one line in its test suite:
+//!
+//! ```ignore
+//! assert_sigv4_vectors("aws", &CanonicalizationProfile::STANDARD);
+//! ```
+//!
+//! Canonical URIs are built with [SignatureOptions::canonical_uri_path] from `scratchstack-service-common`, the same
+//! code the services use when they re-sign SigV2 requests, so the vectors check that implementation directly.
+//!
+//! [SigV4Vector::request] gives the signed request itself, for sending through a service's verification stack.
+use {
+    crate::signer::{hmac, signing_key, Credentials},
+    hyper::{Body, Request},
+    percent_encoding::percent_decode_str,
+    scratchstack_service_common::signing::{uri_encode, SignatureOptions},
+    sha2::{Digest, Sha256},
+    std::{
+        fmt::{Display, Formatter, Result as FmtResult},
+        fs::{read_dir, read_to_string},
+        io::{Error as IOError, ErrorKind},
+        path::{Path, PathBuf},
+    },
+};
+
+/// The credentials the AWS test suite vectors are signed with.
+pub const SUITE_CREDENTIALS: (&str, &str) = ("AKIDEXAMPLE", "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY");
+
+/// The region in the credential scope of the suite vectors.
+pub const SUITE_REGION: &str = "us-east-1";
+
+/// The service in the credential scope of the suite vectors.
+pub const SUITE_SERVICE: &str = "service";
+
+/// How a service turns the request path into the canonical URI.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct CanonicalizationProfile {
+    pub name: &'static str,
+    pub options: SignatureOptions,
+}
+
+impl CanonicalizationProfile {
+    /// Most services, including IAM and STS.
+    pub const STANDARD: Self = Self {
+        name: "standard",
+        options: SignatureOptions::STANDARD,
+    };
+
+    /// S3, where object keys may contain `//`, `.` and `..` and are encoded once.
+    pub const S3: Self = Self {
+        name: "s3",
+        options: SignatureOptions::S3,
+    };
+
+    /// API Gateway's `execute-api`, which passes the path to the integration as sent.
+    pub const API_GATEWAY: Self = Self {
+        name: "api-gateway",
+        options: SignatureOptions {
+            uri_path_normalization: false,
+            double_uri_encode: true,
+        },
+    };
+}
+
+/// A request as written in a `.req` file.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RawRequest {
+    pub method: String,
+
+    /// The path and query string.
+    pub target: String,
+
+    /// Header names and values in file order. Continuation lines become further values of the same header.
+    pub headers: Vec<(String, String)>,
+    pub body: String,
+}
+
+impl RawRequest {
+    pub fn parse(contents: &str) -> Result<Self, IOError> {
+        let (head, body) = match contents.split_once("\n\n") {
+            Some((head, body)) => (head, body),
+            None => (contents.trim_end_matches('\n'), ""),
+        };
+        let mut lines = head.lines();
+        let request_line = lines.next().unwrap_or_default();
+        let (method, target) = match request_line
+            .split_once(' ')
+            .and_then(|(method, rest)| rest.rsplit_once(' ').map(|(target, _version)| (method, target)))
+        {
+            Some(parts) => parts,
+            None => return Err(invalid(format!("Invalid request line: {request_line}"))),
+        };
+
+        let mut headers: Vec<(String, String)> = Vec::new();
+        for line in lines {
+            if line.starts_with(' ') || line.starts_with('\t') {
+                match headers.last() {
+                    Some((name, _)) => headers.push((name.clone(), line.to_string())),
+                    None => return Err(invalid(format!("Continuation line before any header: {line}"))),
+                }
+            } else {
+                match line.split_once(':') {
+                    Some((name, value)) => headers.push((name.to_string(), value.to_string())),
+                    None => return Err(invalid(format!("Invalid header line: {line}"))),
+                }
+            }
+        }
+
+        Ok(Self {
+            method: method.to_string(),
+            target: target.to_string(),
+            headers,
+            body: body.to_string(),
+        })
+    }
+
+    /// The value of the first header named `name`, ignoring case.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers.iter().find(|(n, _)| n.eq_ignore_ascii_case(name)).map(|(_, v)| v.as_str())
+    }
+}
+
+/// One test vector; see the [module documentation][self].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SigV4Vector {
+    pub name: String,
+    pub request: RawRequest,
+    pub canonical_request: String,
+    pub string_to_sign: String,
+    pub authorization: String,
+}
+
+impl SigV4Vector {
+    /// Load the vector in `dir`, whose files are named after the directory.
+    pub fn load<P: AsRef<Path>>(dir: P) -> Result<Self, IOError> {
+        let dir = dir.as_ref();
+        let name = match dir.file_name().and_then(|name| name.to_str()) {
+            Some(name) => name.to_string(),
+            None => return Err(invalid(format!("Invalid vector directory: {}", dir.display()))),
+        };
+        let file = |extension: &str| read_to_string(dir.join(format!("{name}.{extension}")));
+        let expected = |extension: &str| file(extension).map(|contents| contents.trim_end_matches('\n').to_string());
+
+        Ok(Self {
+            request: RawRequest::parse(&file("req")?)?,
+            canonical_request: expected("creq")?,
+            string_to_sign: expected("sts")?,
+            authorization: expected("authz")?,
+            name,
+        })
+    }
+
+    /// Load every vector under `dir`, including those in nested directories, sorted by path.
+    pub fn load_all<P: AsRef<Path>>(dir: P) -> Result<Vec<Self>, IOError> {
+        let mut dirs = Vec::new();
+        find_vector_dirs(dir.as_ref(), &mut dirs)?;
+        dirs.sort();
+        dirs.iter().map(Self::load).collect()
+    }
+
+    /// The request with its Authorization header, for sending to a service.
+    pub fn request(&self) -> Request<Body> {
+        let mut builder = Request::builder().method(self.request.method.as_str()).uri(self.request.target.as_str());
+        for (name, value) in &self.request.headers {
+            builder = builder.header(name.as_str(), value.trim());
+        }
+        builder
+            .header("Authorization", self.authorization.as_str())
+            .body(Body::from(self.request.body.clone()))
+            .unwrap()
+    }
+
+    /// Recompute the canonical request, string to sign and Authorization header under `profile` and compare each
+    /// with the expected value.
+    pub fn check(&self, profile: &CanonicalizationProfile, credentials: &Credentials) -> Result<(), VectorFailure> {
+        let failure = |step, expected: &str, actual: String| VectorFailure {
+            name: self.name.clone(),
+            step,
+            expected: expected.to_string(),
+            actual,
+        };
+
+        let canonical_request = canonical_request(&self.request, profile);
+        if canonical_request != self.canonical_request {
+            return Err(failure("canonical request", &self.canonical_request, canonical_request));
+        }
+
+        let amz_date = self.request.header("X-Amz-Date").unwrap_or_default().trim();
+        let date = amz_date.get(..8).unwrap_or_default();
+        let scope = format!("{date}/{SUITE_REGION}/{SUITE_SERVICE}/aws4_request");
+        let string_to_sign = format!(
+            "AWS4-HMAC-SHA256\n{amz_date}\n{scope}\n{}",
+            hex::encode(Sha256::digest(canonical_request.as_bytes()))
+        );
+        if string_to_sign != self.string_to_sign {
+            return Err(failure("string to sign", &self.string_to_sign, string_to_sign));
+        }
+
+        let key = signing_key(&credentials.secret_access_key, date, SUITE_REGION, SUITE_SERVICE);
+        let signature = hex::encode(hmac(&key, string_to_sign.as_bytes()));
+        let authorization = format!(
+            "AWS4-HMAC-SHA256 Credential={}/{scope}, SignedHeaders={}, Signature={signature}",
+            credentials.access_key_id,
+            signed_headers(&self.request)
+        );
+        if authorization != self.authorization {
+            return Err(failure("authorization", &self.authorization, authorization));
+        }
+
+        Ok(())
+    }
+}
+
+/// A vector whose recomputed value differs from the expected one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VectorFailure {
+    pub name: String,
+    pub step: &'static str,
+    pub expected: String,
+    pub actual: String,
+}
+
+impl Display for VectorFailure {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{}: {} differs\nexpected:\n{}\nactual:\n{}", self.name, self.step, self.expected, self.actual)
+    }
+}
+
+/// The directory holding the vector set `name` (e.g. `aws`) in this crate.
+pub fn vector_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("sigv4").join(name)
+}
+
+/// Check every vector in the set `name` under `profile`, panicking with all failures if any vector fails.
+pub fn assert_sigv4_vectors(name: &str, profile: &CanonicalizationProfile) {
+    let vectors = SigV4Vector::load_all(vector_path(name)).expect("Unable to load SigV4 vectors");
+    assert!(!vectors.is_empty(), "No SigV4 vectors found in {}", vector_path(name).display());
+
+    let credentials = Credentials {
+        access_key_id: SUITE_CREDENTIALS.0.to_string(),
+        secret_access_key: SUITE_CREDENTIALS.1.to_string(),
+        session_token: None,
+    };
+    let failures = vectors
+        .iter()
+        .filter_map(|vector| vector.check(profile, &credentials).err())
+        .map(|failure| failure.to_string())
+        .collect::<Vec<_>>();
+    assert!(
+        failures.is_empty(),
+        "{} of {} SigV4 vectors failed under the {} profile:\n\n{}",
+        failures.len(),
+        vectors.len(),
+        profile.name,
+        failures.join("\n\n")
+    );
+}
+
+/// The canonical request for `request` under `profile`, signing every header in the request.
+pub fn canonical_request(request: &RawRequest, profile: &CanonicalizationProfile) -> String {
+    let (path, query) = match request.target.split_once('?') {
+        Some((path, query)) => (path, query),
+        None => (request.target.as_str(), ""),
+    };
+
+    format!(
+        "{}\n{}\n{}\n{}\n{}\n{}",
+        request.method,
+        profile.options.canonical_uri_path(path),
+        canonical_query(query),
+        canonical_headers(request),
+        signed_headers(request),
+        hex::encode(Sha256::digest(request.body.as_bytes()))
+    )
+}
+
+fn canonical_query(query: &str) -> String {
+    let mut parameters = query
+        .split('&')
+        .filter(|parameter| !parameter.is_empty())
+        .map(|parameter| {
+            let (key, value) = parameter.split_once('=').unwrap_or((parameter, ""));
+            let decode = |s: &str| percent_decode_str(s).decode_utf8_lossy().into_owned();
+            (uri_encode(&decode(key)), uri_encode(&decode(value)))
+        })
+        .collect::<Vec<_>>();
+    parameters.sort();
+    parameters.iter().map(|(key, value)| format!("{key}={value}")).collect::<Vec<_>>().join("&")
+}
+
+fn canonical_headers(request: &RawRequest) -> String {
+    let mut names = header_names(request);
+    names.dedup();
+    let mut canonical = String::new();
+    for name in names {
+        let values = request
+            .headers
+            .iter()
+            .filter(|(n, _)| n.to_ascii_lowercase() == name)
+            .map(|(_, value)| value.split_whitespace().collect::<Vec<_>>().join(" "))
+            .collect::<Vec<_>>();
+        canonical.push_str(&format!("{name}:{}\n", values.join(",")));
+    }
+    canonical
+}
+
+fn signed_headers(request: &RawRequest) -> String {
+    let mut names = header_names(request);
+    names.dedup();
+    names.join(";")
+}
+
+fn header_names(request: &RawRequest) -> Vec<String> {
+    let mut names = request.headers.iter().map(|(name, _)| name.to_ascii_lowercase()).collect::<Vec<_>>();
+    names.sort();
+    names
+}
+
+fn find_vector_dirs(dir: &Path, dirs: &mut Vec<PathBuf>) -> Result<(), IOError> {
+    for entry in read_dir(dir)? {
+        let path = entry?.path();
+        if !path.is_dir() {
+            continue;
+        }
+
+        let name = path.file_name().and_then(|name| name.to_str()).unwrap_or_default().to_string();
+        if path.join(format!("{name}.req")).is_file() {
+            dirs.push(path.clone());
+        }
+        find_vector_dirs(&path, dirs)?;
+    }
+    Ok(())
+}
+
+fn invalid(message: String) -> IOError {
+    IOError::new(ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{canonical_uri, CanonicalizationProfile},
+        pretty_assertions::assert_eq,
+    };
+
+    #[test_log::test]
+    fn test_canonical_uri() {
+        let path = "/example//photos/../my%20photo.jpg";
+        assert_eq!(canonical_uri(path, &CanonicalizationProfile::STANDARD), "/example/my%2520photo.jpg");
+        assert_eq!(canonical_uri(path, &CanonicalizationProfile::S3), "/example//photos/../my%20photo.jpg");
+        assert_eq!(canonical_uri(path, &CanonicalizationProfile::API_GATEWAY), "/example//photos/../my%2520photo.jpg");
+        assert_eq!(canonical_uri("/example1/example2/../..", &CanonicalizationProfile::STANDARD), "/");
+        assert_eq!(canonical_uri("/a/b/", &CanonicalizationProfile::STANDARD), "/a/b/");
+    }
+}
@@ -0,0 +1,453 @@
+//! Declarative smoke tests that run against a live deployment.
+//!
+//! A [Scenario] is a YAML file listing query protocol requests and what each should return. `scratchstack-admin
+//! smoke` runs scenarios against the endpoints of a deployment with bootstrap credentials and writes the results as
+//! JUnit XML, so a deploy pipeline can verify the deployment the same way it reports its other tests:
+//!
+//! ```yaml
+//! name: basic
+//! steps:
+//!   - name: create user
+//!     service: iam
+//!     action: CreateUser
+//!     params: { UserName: "smoke-${run_id}" }
+//!   - name: create access key
+//!     service: iam
+//!     action: CreateAccessKey
+//!     params: { UserName: "smoke-${run_id}" }
+//!     capture: { key_id: AccessKeyId, secret_key: SecretAccessKey }
+//!   - name: new key is accepted
+//!     service: sts
+//!     action: GetCallerIdentity
+//!     credentials: { access_key_id: "${key_id}", secret_access_key: "${secret_key}" }
+//!     retries: 5
+//!     expect: { contains: ["user/smoke-${run_id}"] }
+//! ```
+//!
+//! `${name}` in parameters, credentials and expectations is replaced with a captured value, or with `run_id`, which
+//! is unique to each run. A step expects a 2xx status unless it says otherwise; `expect: { error: AccessDenied }`
+//! expects a denial. Once a step fails, the remaining steps are skipped unless they are marked `always`, which is
+//! meant for cleanup.
+use {
+    crate::signer::{Credentials, Signer},
+    chrono::Utc,
+    hyper::{body::to_bytes, client::HttpConnector, Client, Uri},
+    hyper_rustls::{HttpsConnector, HttpsConnectorBuilder},
+    scratchstack_service_common::signing::uri_encode,
+    serde::Deserialize,
+    std::{
+        collections::{BTreeMap, HashMap},
+        fmt::Write,
+        fs::read_to_string,
+        io::{Error as IOError, ErrorKind},
+        path::Path,
+        time::{Duration, Instant},
+    },
+    tokio::time::sleep,
+};
+
+/// A named list of steps, run in order.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Scenario {
+    pub name: String,
+    pub steps: Vec<Step>,
+}
+
+impl Scenario {
+    pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Self, IOError> {
+        Self::from_yaml(&read_to_string(path)?)
+    }
+
+    pub fn from_yaml(yaml: &str) -> Result<Self, IOError> {
+        serde_yaml::from_str(yaml).map_err(|e| IOError::new(ErrorKind::InvalidData, e))
+    }
+}
+
+/// One request and its expected outcome.
+#[derive(Clone, Debug, Deserialize)]
+pub struct Step {
+    pub name: String,
+
+    /// The service to call, e.g. `iam`; must be one of the target's endpoints.
+    pub service: String,
+    pub action: String,
+
+    /// Request parameters besides `Action`. `Version` defaults to the service's API version.
+    #[serde(default)]
+    pub params: BTreeMap<String, String>,
+
+    /// Credentials to sign with instead of the bootstrap credentials.
+    pub credentials: Option<StepCredentials>,
+
+    #[serde(default)]
+    pub expect: Expect,
+
+    /// Values to capture from the response, by variable name and XML element name.
+    #[serde(default)]
+    pub capture: BTreeMap<String, String>,
+
+    /// How many more times to try the step if it fails, e.g. while a new access key propagates.
+    #[serde(default)]
+    pub retries: u32,
+
+    #[serde(default = "Step::default_retry_delay_ms")]
+    pub retry_delay_ms: u64,
+
+    /// Run the step even after an earlier step failed.
+    #[serde(default)]
+    pub always: bool,
+}
+
+impl Step {
+    fn default_retry_delay_ms() -> u64 {
+        1000
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct StepCredentials {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+/// What a step's response must look like. With nothing set, any 2xx status is expected.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Expect {
+    pub status: Option<u16>,
+
+    /// The error code, e.g. `AccessDenied`. Implies a 4xx or 5xx status.
+    pub error: Option<String>,
+
+    /// Substrings the response body must contain.
+    #[serde(default)]
+    pub contains: Vec<String>,
+}
+
+/// The deployment a scenario runs against.
+#[derive(Clone, Debug)]
+pub struct SmokeTarget {
+    /// Endpoint URLs by service name, e.g. `iam` → `https://iam.example.internal:8443`.
+    pub endpoints: HashMap<String, String>,
+    pub region: String,
+    pub credentials: Credentials,
+}
+
+/// The result of one step.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CaseResult {
+    pub name: String,
+    pub duration: Duration,
+    pub outcome: CaseOutcome,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CaseOutcome {
+    Passed,
+    Failed(String),
+
+    /// Not run because an earlier step failed.
+    Skipped,
+}
+
+/// The results of running a scenario.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SuiteResult {
+    pub name: String,
+    pub cases: Vec<CaseResult>,
+}
+
+impl SuiteResult {
+    pub fn failures(&self) -> usize {
+        self.cases.iter().filter(|case| matches!(case.outcome, CaseOutcome::Failed(_))).count()
+    }
+
+    pub fn skipped(&self) -> usize {
+        self.cases.iter().filter(|case| case.outcome == CaseOutcome::Skipped).count()
+    }
+
+    pub fn passed(&self) -> bool {
+        self.failures() == 0
+    }
+}
+
+/// `suites` as a JUnit XML report.
+pub fn junit_report(suites: &[SuiteResult]) -> String {
+    let mut out = String::from("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites>\n");
+    for suite in suites {
+        let time: f64 = suite.cases.iter().map(|case| case.duration.as_secs_f64()).sum();
+        let _ = writeln!(
+            out,
+            "  <testsuite name=\"{}\" tests=\"{}\" failures=\"{}\" skipped=\"{}\" time=\"{:.3}\">",
+            escape_xml(&suite.name),
+            suite.cases.len(),
+            suite.failures(),
+            suite.skipped(),
+            time
+        );
+        for case in &suite.cases {
+            let _ = write!(
+                out,
+                "    <testcase name=\"{}\" classname=\"smoke.{}\" time=\"{:.3}\"",
+                escape_xml(&case.name),
+                escape_xml(&suite.name),
+                case.duration.as_secs_f64()
+            );
+            match &case.outcome {
+                CaseOutcome::Passed => out.push_str("/>\n"),
+                CaseOutcome::Skipped => out.push_str("><skipped/></testcase>\n"),
+                CaseOutcome::Failed(message) => {
+                    let _ = writeln!(out, "><failure message=\"{}\"/></testcase>", escape_xml(message));
+                }
+            }
+        }
+        out.push_str("  </testsuite>\n");
+    }
+    out.push_str("</testsuites>\n");
+    out
+}
+
+/// Runs scenarios against a [SmokeTarget].
+#[derive(Clone, Debug)]
+pub struct SmokeRunner {
+    target: SmokeTarget,
+    client: Client<HttpsConnector<HttpConnector>>,
+}
+
+impl SmokeRunner {
+    pub fn new(target: SmokeTarget) -> Self {
+        let connector = HttpsConnectorBuilder::new().with_native_roots().https_or_http().enable_http1().build();
+        Self {
+            target,
+            client: Client::builder().build(connector),
+        }
+    }
+
+    pub async fn run(&self, scenario: &Scenario) -> SuiteResult {
+        let mut variables = HashMap::new();
+        variables.insert("run_id".to_string(), Utc::now().format("%Y%m%d%H%M%S%3f").to_string());
+        let mut failed = false;
+        let mut cases = Vec::with_capacity(scenario.steps.len());
+
+        for step in &scenario.steps {
+            if failed && !step.always {
+                cases.push(CaseResult {
+                    name: step.name.clone(),
+                    duration: Duration::ZERO,
+                    outcome: CaseOutcome::Skipped,
+                });
+                continue;
+            }
+
+            let start = Instant::now();
+            let mut attempt = 0;
+            let outcome = loop {
+                match self.run_step(step, &mut variables).await {
+                    Ok(()) => break CaseOutcome::Passed,
+                    Err(_) if attempt < step.retries => {
+                        attempt += 1;
+                        sleep(Duration::from_millis(step.retry_delay_ms)).await;
+                    }
+                    Err(message) => break CaseOutcome::Failed(message),
+                }
+            };
+            failed |= matches!(outcome, CaseOutcome::Failed(_));
+            cases.push(CaseResult {
+                name: step.name.clone(),
+                duration: start.elapsed(),
+                outcome,
+            });
+        }
+
+        SuiteResult {
+            name: scenario.name.clone(),
+            cases,
+        }
+    }
+
+    async fn run_step(&self, step: &Step, variables: &mut HashMap<String, String>) -> Result<(), String> {
+        let endpoint = match self.target.endpoints.get(&step.service) {
+            Some(endpoint) => endpoint.trim_end_matches('/'),
+            None => return Err(format!("No endpoint for service {}", step.service)),
+        };
+        let host = match endpoint.parse::<Uri>().ok().and_then(|uri| uri.authority().map(ToString::to_string)) {
+            Some(host) => host,
+            None => return Err(format!("Invalid endpoint URL {endpoint}")),
+        };
+
+        let credentials = match &step.credentials {
+            None => self.target.credentials.clone(),
+            Some(credentials) => Credentials {
+                access_key_id: substitute(&credentials.access_key_id, variables)?,
+                secret_access_key: substitute(&credentials.secret_access_key, variables)?,
+                session_token: None,
+            },
+        };
+
+        let mut params = vec![("Action".to_string(), step.action.clone())];
+        if !step.params.contains_key("Version") {
+            if let Some(version) = api_version(&step.service) {
+                params.push(("Version".to_string(), version.to_string()));
+            }
+        }
+        for (key, value) in &step.params {
+            params.push((key.clone(), substitute(value, variables)?));
+        }
+        let body = params
+            .iter()
+            .map(|(key, value)| format!("{}={}", uri_encode(key), uri_encode(value)))
+            .collect::<Vec<_>>()
+            .join("&");
+
+        let signer = Signer::new(credentials, &self.target.region, &step.service);
+        let request = signer.sign_form_post(endpoint, &host, &body, Utc::now());
+        let response = self.client.request(request).await.map_err(|e| format!("Request failed: {e}"))?;
+        let status = response.status();
+        let body = to_bytes(response.into_body()).await.map_err(|e| format!("Unable to read response: {e}"))?;
+        let body = String::from_utf8_lossy(&body);
+
+        check(&step.expect, status.as_u16(), &body, variables)?;
+        for (name, element) in &step.capture {
+            match xml_element(&body, element) {
+                Some(value) => {
+                    variables.insert(name.clone(), value.to_string());
+                }
+                None => return Err(format!("Response has no <{element}> to capture as {name}")),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compare a response with what the step expects.
+fn check(expect: &Expect, status: u16, body: &str, variables: &HashMap<String, String>) -> Result<(), String> {
+    let code = xml_element(body, "Code");
+    match (&expect.status, &expect.error) {
+        (Some(expected), _) if *expected != status => {
+            return Err(format!("Expected status {expected}, got {status}: {}", code.unwrap_or(body)))
+        }
+        (_, Some(error)) if code != Some(error.as_str()) => {
+            return Err(format!("Expected error {error}, got status {status} {}", code.unwrap_or("without an error")))
+        }
+        (None, None) if !(200..300).contains(&status) => {
+            return Err(format!("Expected success, got status {status}: {}", code.unwrap_or(body)))
+        }
+        _ => (),
+    }
+
+    for expected in &expect.contains {
+        let expected = substitute(expected, variables)?;
+        if !body.contains(&expected) {
+            return Err(format!("Response does not contain {expected:?}"));
+        }
+    }
+    Ok(())
+}
+
+/// `template` with each `${name}` replaced by the value of `name`.
+fn substitute(template: &str, variables: &HashMap<String, String>) -> Result<String, String> {
+    let mut result = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("${") {
+        result.push_str(&rest[..start]);
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            None => return Err(format!("Unterminated variable in {template:?}")),
+        };
+        let name = &rest[start + 2..end];
+        match variables.get(name) {
+            Some(value) => result.push_str(value),
+            None => return Err(format!("Undefined variable {name} in {template:?}")),
+        }
+        rest = &rest[end + 1..];
+    }
+    result.push_str(rest);
+    Ok(result)
+}
+
+/// The API version sent when a step does not give one.
+fn api_version(service: &str) -> Option<&'static str> {
+    match service {
+        "iam" => Some("2010-05-08"),
+        "sts" => Some("2011-06-15"),
+        _ => None,
+    }
+}
+
+/// Returns the text of the first `<name>` element in `xml`.
+fn xml_element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{name}>");
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&format!("</{name}>"))?;
+    Some(&xml[start..start + end])
+}
+
+fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{check, junit_report, substitute, CaseOutcome, CaseResult, Expect, Scenario, SuiteResult},
+        pretty_assertions::assert_eq,
+        std::{collections::HashMap, time::Duration},
+    };
+
+    #[test_log::test]
+    fn test_scenario() {
+        let scenario = Scenario::read_file(concat!(env!("CARGO_MANIFEST_DIR"), "/smoke/basic.yaml")).unwrap();
+        assert_eq!(scenario.name, "basic");
+        assert_eq!(scenario.steps[1].capture["key_id"], "AccessKeyId");
+        assert!(scenario.steps.last().unwrap().always);
+
+        let variables = HashMap::from([("run_id".to_string(), "42".to_string())]);
+        assert_eq!(substitute("smoke-${run_id}", &variables).unwrap(), "smoke-42");
+        assert!(substitute("${nope}", &variables).is_err());
+
+        let deny = Expect {
+            error: Some("AccessDenied".to_string()),
+            ..Expect::default()
+        };
+        let denied = "<ErrorResponse><Error><Code>AccessDenied</Code></Error></ErrorResponse>";
+        assert_eq!(check(&deny, 403, denied, &variables), Ok(()));
+        assert!(check(&deny, 200, "<GetUserResponse/>", &variables).is_err());
+        assert!(check(&Expect::default(), 403, denied, &variables).unwrap_err().contains("AccessDenied"));
+    }
+
+    #[test_log::test]
+    fn test_junit_report() {
+        let suite = SuiteResult {
+            name: "basic".to_string(),
+            cases: vec![
+                CaseResult {
+                    name: "create user".to_string(),
+                    duration: Duration::from_millis(12),
+                    outcome: CaseOutcome::Passed,
+                },
+                CaseResult {
+                    name: "sign <request>".to_string(),
+                    duration: Duration::from_millis(3),
+                    outcome: CaseOutcome::Failed("Expected status 200, got 403".to_string()),
+                },
+                CaseResult {
+                    name: "delete user".to_string(),
+                    duration: Duration::ZERO,
+                    outcome: CaseOutcome::Skipped,
+                },
+            ],
+        };
+
+        assert_eq!(
+            junit_report(&[suite]),
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<testsuites>
+  <testsuite name="basic" tests="3" failures="1" skipped="1" time="0.015">
+    <testcase name="create user" classname="smoke.basic" time="0.012"/>
+    <testcase name="sign &lt;request&gt;" classname="smoke.basic" time="0.003"><failure message="Expected status 200, got 403"/></testcase>
+    <testcase name="delete user" classname="smoke.basic" time="0.000"><skipped/></testcase>
+  </testsuite>
+</testsuites>
+"#
+        );
+    }
+}
@@ -0,0 +1,140 @@
+use {
+    crate::TestDatabase,
+    escargot::CargoBuild,
+    log::info,
+    std::{
+        fs::write,
+        net::TcpListener as StdTcpListener,
+        path::Path,
+        process::{Child, Stdio},
+        time::Duration,
+    },
+    tempfile::TempDir,
+    tokio::{net::TcpStream, time::sleep},
+};
+
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// A service binary running as a child process. The process is killed when this is dropped.
+pub struct ServiceProcess {
+    name: String,
+    child: Child,
+    port: u16,
+}
+
+impl ServiceProcess {
+    /// Build (if necessary) and start the named workspace binary with the given configuration file, waiting until
+    /// it accepts connections on `port`.
+    pub async fn start(package: &str, config_path: &Path, port: u16) -> Self {
+        let run = CargoBuild::new()
+            .package(package)
+            .bin(package)
+            .current_release()
+            .run()
+            .unwrap_or_else(|e| panic!("Unable to build {package}: {e}"));
+
+        let child = run
+            .command()
+            .arg("--config")
+            .arg(config_path)
+            .env("RUST_LOG", "debug")
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .unwrap_or_else(|e| panic!("Unable to start {package}: {e}"));
+
+        let mut process = Self {
+            name: package.to_string(),
+            child,
+            port,
+        };
+        process.wait_for_listener().await;
+        process
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn endpoint(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
+    pub fn host(&self) -> String {
+        format!("127.0.0.1:{}", self.port)
+    }
+
+    async fn wait_for_listener(&mut self) {
+        let mut waited = Duration::ZERO;
+        while TcpStream::connect(("127.0.0.1", self.port)).await.is_err() {
+            if let Ok(Some(status)) = self.child.try_wait() {
+                panic!("{} exited during startup: {}", self.name, status);
+            }
+
+            if waited >= STARTUP_TIMEOUT {
+                panic!("{} did not start listening on port {} within {:?}", self.name, self.port, STARTUP_TIMEOUT);
+            }
+
+            sleep(Duration::from_millis(100)).await;
+            waited += Duration::from_millis(100);
+        }
+
+        info!("{} is listening on port {}", self.name, self.port);
+    }
+}
+
+impl Drop for ServiceProcess {
+    fn drop(&mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+/// The IAM and STS services running against a [TestDatabase].
+pub struct Stack {
+    _config_dir: TempDir,
+    pub iam: ServiceProcess,
+    pub sts: ServiceProcess,
+}
+
+impl Stack {
+    pub async fn start(database: &TestDatabase<'_>) -> Self {
+        let iam_port = unused_port();
+        let sts_port = unused_port();
+        let url = database.url();
+
+        let config_dir = TempDir::new().expect("Unable to create temporary directory");
+        let config_path = config_dir.path().join("scratchstack.cfg");
+        let config = format!(
+            r#"[service.iam]
+region = "local"
+port = {iam_port}
+
+[service.iam.database]
+url = "{url}"
+
+[service.sts]
+region = "local"
+port = {sts_port}
+
+[service.sts.database]
+url = "{url}"
+"#
+        );
+        write(&config_path, config).expect("Unable to write configuration file");
+
+        let iam = ServiceProcess::start("scratchstack-service-iam", &config_path, iam_port).await;
+        let sts = ServiceProcess::start("scratchstack-service-sts", &config_path, sts_port).await;
+
+        Self {
+            _config_dir: config_dir,
+            iam,
+            sts,
+        }
+    }
+}
+
+fn unused_port() -> u16 {
+    let listener = StdTcpListener::bind("127.0.0.1:0").expect("Unable to bind to an ephemeral port");
+    listener.local_addr().unwrap().port()
+}
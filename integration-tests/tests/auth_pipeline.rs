@@ -0,0 +1,238 @@
+use {
+    chrono::{Duration, Utc},
+    hyper::{body::to_bytes, Client, StatusCode},
+    pretty_assertions::assert_eq,
+    scratchstack_integration_tests::{Credentials, Signer, Stack, TestDatabase},
+    scratchstack_service_common::signing::uri_encode,
+    testcontainers::clients::Cli,
+};
+
+const ACCOUNT_ID: &str = "123456789012";
+const TRUST_POLICY: &str =
+    r#"{"Statement": {"Effect": "Allow", "Principal": {"AWS": "123456789012"}, "Action": "sts:AssumeRole"}}"#;
+const ASSUME_ROLE_POLICY: &str = r#"{"Statement": {"Effect": "Allow", "Action": "sts:AssumeRole", "Resource": "*"}}"#;
+const ASSUME_ROLE_BODY: &str = "Action=AssumeRole&Version=2011-06-15&\
+                                RoleArn=arn%3Aaws%3Aiam%3A%3A123456789012%3Arole%2FDeployer&RoleSessionName=build";
+
+#[test_log::test(tokio::test)]
+#[ignore = "requires Docker"]
+async fn test_get_caller_identity() {
+    let docker = Cli::default();
+    let database = TestDatabase::start(&docker).await;
+    let admin = database.create_account(ACCOUNT_ID).await;
+    let stack = Stack::start(&database).await;
+    let client = Client::new();
+
+    let credentials = create_user(&stack, &admin, "Alice").await;
+    let signer = Signer::new(credentials.clone(), "local", "sts");
+    let request = signer.sign_form_post(
+        &stack.sts.endpoint(),
+        &stack.sts.host(),
+        "Action=GetCallerIdentity&Version=2011-06-15",
+        Utc::now(),
+    );
+
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap();
+    assert!(body.contains("<Arn>arn:aws:iam::123456789012:user/Alice</Arn>"), "Unexpected body: {body}");
+    assert!(body.contains("<Account>123456789012</Account>"), "Unexpected body: {body}");
+
+    // Alice has no policies, so IAM denies her requests even though they are signed correctly.
+    let (status, body) = call(&stack, &credentials, "iam", "Action=ListUsers&Version=2010-05-08").await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(body.contains("AccessDenied"), "Unexpected body: {body}");
+
+    // The same request signed with the wrong secret key must be rejected.
+    let bad_signer = Signer::new(
+        Credentials {
+            secret_access_key: "not-the-secret-key".to_string(),
+            ..credentials
+        },
+        "local",
+        "sts",
+    );
+    let request = bad_signer.sign_form_post(
+        &stack.sts.endpoint(),
+        &stack.sts.host(),
+        "Action=GetCallerIdentity&Version=2011-06-15",
+        Utc::now(),
+    );
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let body = String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap();
+    assert!(body.contains("SignatureDoesNotMatch"), "Unexpected body: {body}");
+}
+
+#[test_log::test(tokio::test)]
+#[ignore = "requires Docker"]
+async fn test_presigned_get_caller_identity() {
+    let docker = Cli::default();
+    let database = TestDatabase::start(&docker).await;
+    let admin = database.create_account(ACCOUNT_ID).await;
+    let stack = Stack::start(&database).await;
+    let client = Client::new();
+
+    let signer = Signer::new(create_user(&stack, &admin, "Alice").await, "local", "sts");
+    let parameters = [("Action", "GetCallerIdentity"), ("Version", "2011-06-15")];
+
+    let request = signer.presign_get(&stack.sts.endpoint(), &stack.sts.host(), &parameters, 300, Utc::now());
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap();
+    assert!(body.contains("<Arn>arn:aws:iam::123456789012:user/Alice</Arn>"), "Unexpected body: {body}");
+
+    // A URL used after X-Amz-Expires has passed is rejected, even though its signature is valid.
+    let signed_at = Utc::now() - Duration::minutes(10);
+    let request = signer.presign_get(&stack.sts.endpoint(), &stack.sts.host(), &parameters, 60, signed_at);
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let body = String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap();
+    assert!(body.contains("RequestExpired"), "Unexpected body: {body}");
+}
+
+#[test_log::test(tokio::test)]
+#[ignore = "requires Docker"]
+async fn test_assume_role_credentials() {
+    let docker = Cli::default();
+    let database = TestDatabase::start(&docker).await;
+    let admin = database.create_account(ACCOUNT_ID).await;
+    database.seed_token_key("TOKENKEY00000001", &[7; 32]).await;
+    let stack = Stack::start(&database).await;
+    let client = Client::new();
+
+    let credentials = create_user(&stack, &admin, "Alice").await;
+    iam(
+        &stack,
+        &admin,
+        &format!(
+            "Action=CreateRole&Version=2010-05-08&RoleName=Deployer&MaxSessionDuration=3600&\
+             AssumeRolePolicyDocument={}",
+            uri_encode(TRUST_POLICY)
+        ),
+    )
+    .await;
+    iam(
+        &stack,
+        &admin,
+        &format!(
+            "Action=PutRolePolicy&Version=2010-05-08&RoleName=Deployer&PolicyName=AssumeRoles&PolicyDocument={}",
+            uri_encode(ASSUME_ROLE_POLICY)
+        ),
+    )
+    .await;
+
+    // The role trusts the account, but Alice's own policies must also allow sts:AssumeRole.
+    let (status, body) = call(&stack, &credentials, "sts", ASSUME_ROLE_BODY).await;
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    assert!(body.contains("AccessDenied"), "Unexpected body: {body}");
+
+    iam(
+        &stack,
+        &admin,
+        &format!(
+            "Action=PutUserPolicy&Version=2010-05-08&UserName=Alice&PolicyName=AssumeRoles&PolicyDocument={}",
+            uri_encode(ASSUME_ROLE_POLICY)
+        ),
+    )
+    .await;
+    let signer = Signer::new(credentials, "local", "sts");
+    let request = signer.sign_form_post(&stack.sts.endpoint(), &stack.sts.host(), ASSUME_ROLE_BODY, Utc::now());
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap();
+    let session = Credentials {
+        access_key_id: xml_element(&body, "AccessKeyId").to_string(),
+        secret_access_key: xml_element(&body, "SecretAccessKey").to_string(),
+        session_token: Some(xml_element(&body, "SessionToken").to_string()),
+    };
+    assert!(session.access_key_id.starts_with("ASIA"), "Unexpected body: {body}");
+
+    // Requests signed with the temporary credentials are made as the role session.
+    let signer = Signer::new(session.clone(), "local", "sts");
+    let request = signer.sign_form_post(
+        &stack.sts.endpoint(),
+        &stack.sts.host(),
+        "Action=GetCallerIdentity&Version=2011-06-15",
+        Utc::now(),
+    );
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::OK);
+    let body = String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap();
+    assert!(
+        body.contains("<Arn>arn:aws:sts::123456789012:assumed-role/Deployer/build</Arn>"),
+        "Unexpected body: {body}"
+    );
+
+    // Assuming a role with them is role chaining, which limits the session to an hour.
+    let request = signer.sign_form_post(
+        &stack.sts.endpoint(),
+        &stack.sts.host(),
+        &format!("{ASSUME_ROLE_BODY}&DurationSeconds=7200"),
+        Utc::now(),
+    );
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    let body = String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap();
+    assert!(body.contains("1 hour session limit for roles assumed by role chaining"), "Unexpected body: {body}");
+
+    // The secret key is only accepted with the session token it was issued in.
+    let signer = Signer::new(
+        Credentials {
+            session_token: None,
+            ..session
+        },
+        "local",
+        "sts",
+    );
+    let request = signer.sign_form_post(
+        &stack.sts.endpoint(),
+        &stack.sts.host(),
+        "Action=GetCallerIdentity&Version=2011-06-15",
+        Utc::now(),
+    );
+    let response = client.request(request).await.unwrap();
+    assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    let body = String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap();
+    assert!(body.contains("InvalidClientTokenId"), "Unexpected body: {body}");
+}
+
+/// Sign `body` with `credentials` and send it to `service` (`iam` or `sts`), returning the response status and body.
+async fn call(stack: &Stack, credentials: &Credentials, service: &str, body: &str) -> (StatusCode, String) {
+    let process = match service {
+        "iam" => &stack.iam,
+        _ => &stack.sts,
+    };
+    let signer = Signer::new(credentials.clone(), "local", service);
+    let request = signer.sign_form_post(&process.endpoint(), &process.host(), body, Utc::now());
+    let response = Client::new().request(request).await.unwrap();
+    let status = response.status();
+    let body = String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap();
+    (status, body)
+}
+
+/// Make an IAM request that must succeed, returning the response body.
+async fn iam(stack: &Stack, credentials: &Credentials, body: &str) -> String {
+    let (status, body) = call(stack, credentials, "iam", body).await;
+    assert_eq!(status, StatusCode::OK, "Unexpected body: {body}");
+    body
+}
+
+/// Create `user_name` and an access key for it through the IAM API, signed by the account's administrator.
+async fn create_user(stack: &Stack, admin: &Credentials, user_name: &str) -> Credentials {
+    iam(stack, admin, &format!("Action=CreateUser&Version=2010-05-08&UserName={user_name}")).await;
+    let body = iam(stack, admin, &format!("Action=CreateAccessKey&Version=2010-05-08&UserName={user_name}")).await;
+    Credentials {
+        access_key_id: xml_element(&body, "AccessKeyId").to_string(),
+        secret_access_key: xml_element(&body, "SecretAccessKey").to_string(),
+        session_token: None,
+    }
+}
+
+/// The text of the first `<name>` element in `xml`, panicking if there is none.
+fn xml_element<'a>(xml: &'a str, name: &str) -> &'a str {
+    let open = format!("<{name}>");
+    let start = xml.find(&open).unwrap_or_else(|| panic!("No {name} in {xml}")) + open.len();
+    let end = xml[start..].find(&format!("</{name}>")).unwrap_or_else(|| panic!("No {name} in {xml}"));
+    &xml[start..start + end]
+}
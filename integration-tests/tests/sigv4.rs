@@ -0,0 +1,12 @@
+//! Runs the SigV4 vectors under `sigv4/` against each canonicalization profile.
+use scratchstack_integration_tests::sigv4::{assert_sigv4_vectors, CanonicalizationProfile};
+
+#[test_log::test]
+fn test_standard_vectors() {
+    assert_sigv4_vectors("aws", &CanonicalizationProfile::STANDARD);
+}
+
+#[test_log::test]
+fn test_s3_vectors() {
+    assert_sigv4_vectors("s3", &CanonicalizationProfile::S3);
+}
@@ -0,0 +1,26 @@
+-- Restore the previous delete trigger function.
+CREATE OR REPLACE FUNCTION iam.on_delete_iam_role() RETURNS TRIGGER AS $body$
+BEGIN
+    INSERT INTO iam.deleted_iam_role(
+        role_id, account_id, role_name_lower, role_name_cased,
+        path, permissions_boundary_managed_policy_id, description,
+        assume_role_policy_document, created_at, deleted_at)
+    VALUES(
+        old.role_id, old.account_id, old.role_name_lower, old.role_name_cased,
+        old.path, old.permissions_boundary_managed_policy_id, old.description,
+        old.created_at, CURRENT_TIMESTAMP AT TIME ZONE 'UTC');
+    RETURN old;
+END
+$body$ LANGUAGE plpgsql;
+
+ALTER TABLE iam.deleted_iam_role
+DROP COLUMN IF EXISTS deleted_at;
+
+ALTER TABLE iam.deleted_iam_role
+DROP COLUMN IF EXISTS max_session_duration;
+
+ALTER TABLE iam.iam_role
+DROP CONSTRAINT IF EXISTS ck_iam_role_max_session_duration;
+
+ALTER TABLE iam.iam_role
+DROP COLUMN IF EXISTS max_session_duration;
@@ -0,0 +1,30 @@
+-- Add max_session_duration to iam_role. New roles default to one hour, as in AWS.
+ALTER TABLE iam.iam_role
+ADD COLUMN max_session_duration INTEGER NOT NULL DEFAULT 3600;
+
+ALTER TABLE iam.iam_role
+ADD CONSTRAINT ck_iam_role_max_session_duration
+CHECK (max_session_duration BETWEEN 3600 AND 43200);
+
+-- Carry the column into the history table. deleted_at was referenced by the delete trigger but never created.
+ALTER TABLE iam.deleted_iam_role
+ADD COLUMN max_session_duration INTEGER;
+
+ALTER TABLE iam.deleted_iam_role
+ADD COLUMN deleted_at TIMESTAMP(6);
+
+CREATE OR REPLACE FUNCTION iam.on_delete_iam_role() RETURNS TRIGGER AS $body$
+BEGIN
+    INSERT INTO iam.deleted_iam_role(
+        role_id, account_id, role_name_lower, role_name_cased,
+        path, permissions_boundary_managed_policy_id, description,
+        assume_role_policy_document, max_session_duration, created_at,
+        deleted_at)
+    VALUES(
+        old.role_id, old.account_id, old.role_name_lower, old.role_name_cased,
+        old.path, old.permissions_boundary_managed_policy_id, old.description,
+        old.assume_role_policy_document, old.max_session_duration, old.created_at,
+        CURRENT_TIMESTAMP AT TIME ZONE 'UTC');
+    RETURN old;
+END
+$body$ LANGUAGE plpgsql;
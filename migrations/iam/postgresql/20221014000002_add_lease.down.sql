@@ -0,0 +1,4 @@
+UPDATE iam.schema_version SET version = 4, updated_at = CURRENT_TIMESTAMP AT TIME ZONE 'UTC'
+WHERE schema_version_id = 1;
+
+DROP TABLE IF EXISTS iam.lease;
@@ -0,0 +1,11 @@
+DELETE FROM limitstore.account_limit
+WHERE limit_id IN (
+    SELECT limit_id FROM limitstore.limit_definition
+    WHERE service_name = 'iam'
+    AND limit_name IN ('AttachedPoliciesPerUser', 'AttachedPoliciesPerGroup', 'AttachedPoliciesPerRole',
+                       'VersionsPerPolicy'));
+
+DELETE FROM limitstore.limit_definition
+WHERE service_name = 'iam'
+AND limit_name IN ('AttachedPoliciesPerUser', 'AttachedPoliciesPerGroup', 'AttachedPoliciesPerRole',
+                   'VersionsPerPolicy');
@@ -0,0 +1,13 @@
+-- Quotas on managed policy attachments and versions. Accounts can raise these with rows in limitstore.account_limit.
+INSERT INTO limitstore.limit_definition(
+    limit_id, service_name, limit_name,
+    description, value_type, default_int_value, min_value, max_value)
+VALUES
+    (nextval('limitstore.seq_limit_id'), 'iam', 'AttachedPoliciesPerUser',
+     'Managed policies attached to a user', 'INTEGER', 10, 1, 20),
+    (nextval('limitstore.seq_limit_id'), 'iam', 'AttachedPoliciesPerGroup',
+     'Managed policies attached to a group', 'INTEGER', 10, 1, 10),
+    (nextval('limitstore.seq_limit_id'), 'iam', 'AttachedPoliciesPerRole',
+     'Managed policies attached to a role', 'INTEGER', 10, 1, 20),
+    (nextval('limitstore.seq_limit_id'), 'iam', 'VersionsPerPolicy',
+     'Versions kept for a managed policy', 'INTEGER', 5, 1, 5);
@@ -0,0 +1,5 @@
+UPDATE iam.schema_version SET version = 5, updated_at = CURRENT_TIMESTAMP AT TIME ZONE 'UTC'
+WHERE schema_version_id = 1;
+
+DROP TABLE IF EXISTS iam.revocation_ack;
+DROP TABLE IF EXISTS iam.credential_revocation;
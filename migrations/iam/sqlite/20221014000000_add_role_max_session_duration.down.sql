@@ -0,0 +1,20 @@
+-- Restore the previous delete trigger.
+DROP TRIGGER IF EXISTS trig_delete_iam_role;
+CREATE TRIGGER trig_delete_iam_role
+AFTER DELETE ON iam_role
+FOR EACH ROW
+BEGIN
+    INSERT INTO deleted_iam_role(
+        role_id, account_id, role_name_lower, role_name_cased,
+        path, permissions_boundary_managed_policy_id, description,
+        assume_role_policy_document, created_at, deleted_at)
+    VALUES(
+        old.role_id, old.account_id, old.role_name_lower, old.role_name_cased,
+        old.path, old.permissions_boundary_managed_policy_id, old.description,
+        old.created_at, datetime('now'));
+END;
+
+-- Remove max_session_duration from iam_role and deleted_iam_role.
+-- SQLite cannot drop a column with a CHECK constraint; the columns are left in place.
+-- ALTER TABLE iam_role
+-- DROP COLUMN max_session_duration;
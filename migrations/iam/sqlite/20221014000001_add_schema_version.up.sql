@@ -0,0 +1,17 @@
+-- Track the schema version so services can refuse to run against a schema they were not written for.
+-- version is the number of the latest applied migration that changes the schema; min_compatible_version is the
+-- oldest version that code may expect and still use this schema safely. Migrations that only add objects leave
+-- min_compatible_version unchanged; migrations that remove or change objects raise it.
+--
+-- Versions: 1 = iam, 2 = add_pk_to_history_tables, 3 = add_role_max_session_duration, 4 = add_schema_version.
+CREATE TABLE schema_version(
+    schema_version_id           INTEGER NOT NULL,
+    version                     BIGINT NOT NULL,
+    min_compatible_version      BIGINT NOT NULL,
+    updated_at                  TIMESTAMP(6) NOT NULL,
+    CONSTRAINT pk_schema_version PRIMARY KEY (schema_version_id),
+    CONSTRAINT ck_schema_version_single_row CHECK (schema_version_id = 1)
+);
+
+INSERT INTO schema_version(schema_version_id, version, min_compatible_version, updated_at)
+VALUES(1, 4, 1, datetime('now'));
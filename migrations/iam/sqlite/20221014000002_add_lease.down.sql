@@ -0,0 +1,3 @@
+UPDATE schema_version SET version = 4, updated_at = datetime('now') WHERE schema_version_id = 1;
+
+DROP TABLE IF EXISTS lease;
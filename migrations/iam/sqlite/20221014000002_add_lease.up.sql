@@ -0,0 +1,11 @@
+-- Leases coordinate work between replicas: a scheduled job or one-time setup step runs only in the replica holding
+-- its lease. A lease is held until expires_at; holders renew it while they work and delete it when done.
+CREATE TABLE lease(
+    lease_name                  VARCHAR(256) NOT NULL,
+    holder                      VARCHAR(256) NOT NULL,
+    acquired_at                 TIMESTAMP(6) NOT NULL,
+    expires_at                  TIMESTAMP(6) NOT NULL,
+    CONSTRAINT pk_lease PRIMARY KEY (lease_name)
+);
+
+UPDATE schema_version SET version = 5, updated_at = datetime('now') WHERE schema_version_id = 1;
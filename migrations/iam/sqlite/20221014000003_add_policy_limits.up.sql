@@ -0,0 +1,3 @@
+-- Quotas on managed policy attachments and versions are defined in limitstore on PostgreSQL. SQLite databases have
+-- no limitstore schema, so the defaults compiled into the services apply.
+SELECT 1;
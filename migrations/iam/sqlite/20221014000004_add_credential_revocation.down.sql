@@ -0,0 +1,4 @@
+UPDATE schema_version SET version = 5, updated_at = datetime('now') WHERE schema_version_id = 1;
+
+DROP TABLE IF EXISTS revocation_ack;
+DROP TABLE IF EXISTS credential_revocation;
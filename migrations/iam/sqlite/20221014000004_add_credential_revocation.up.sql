@@ -0,0 +1,18 @@
+-- Access keys deactivated for incident response. Each replica polls this table, stops accepting the listed keys
+-- immediately, and records the last revocation it has applied in revocation_ack so the caller can wait until every
+-- live replica has seen it.
+CREATE TABLE credential_revocation(
+    revocation_id               BIGINT NOT NULL,
+    access_key_id               VARCHAR(256) NOT NULL,
+    revoked_at                  TIMESTAMP(6) NOT NULL,
+    CONSTRAINT pk_credential_revocation PRIMARY KEY (revocation_id)
+);
+
+CREATE TABLE revocation_ack(
+    replica_id                  VARCHAR(256) NOT NULL,
+    revocation_id               BIGINT NOT NULL,
+    seen_at                     TIMESTAMP(6) NOT NULL,
+    CONSTRAINT pk_revocation_ack PRIMARY KEY (replica_id)
+);
+
+UPDATE schema_version SET version = 6, updated_at = datetime('now') WHERE schema_version_id = 1;
@@ -0,0 +1,4 @@
+UPDATE schema_version SET version = 6, updated_at = datetime('now') WHERE schema_version_id = 1;
+
+DROP TABLE IF EXISTS iam_role_tag;
+DROP TABLE IF EXISTS iam_user_tag;
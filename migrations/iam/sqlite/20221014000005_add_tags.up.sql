@@ -0,0 +1,23 @@
+-- Tags on users and roles. Keys are unique per entity ignoring case, but are returned with the case they were
+-- created with.
+CREATE TABLE iam_user_tag(
+    user_id                     CHAR(16) NOT NULL,
+    tag_key_lower               VARCHAR(128) NOT NULL,
+    tag_key_cased               VARCHAR(128) NOT NULL,
+    tag_value                   VARCHAR(256) NOT NULL,
+    CONSTRAINT pk_iam_user_tag PRIMARY KEY (user_id, tag_key_lower),
+    CONSTRAINT fk_iam_user_tag_user_id
+    FOREIGN KEY (user_id) REFERENCES iam_user(user_id)
+);
+
+CREATE TABLE iam_role_tag(
+    role_id                     CHAR(16) NOT NULL,
+    tag_key_lower               VARCHAR(128) NOT NULL,
+    tag_key_cased               VARCHAR(128) NOT NULL,
+    tag_value                   VARCHAR(256) NOT NULL,
+    CONSTRAINT pk_iam_role_tag PRIMARY KEY (role_id, tag_key_lower),
+    CONSTRAINT fk_iam_role_tag_role_id
+    FOREIGN KEY (role_id) REFERENCES iam_role(role_id)
+);
+
+UPDATE schema_version SET version = 7, updated_at = datetime('now') WHERE schema_version_id = 1;
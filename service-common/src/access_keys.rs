@@ -0,0 +1,440 @@
+//! IAM access key operations: `CreateAccessKey`, `ListAccessKeys`, and `DeleteAccessKey`, written against
+//! [ControlPlaneStore].
+//!
+//! New access key ids are generated with the long-term prefix the deployment issues, and secret keys with
+//! [generate_secret_key]; both use the system's secure random number generator. The store keeps long-term key ids
+//! without their prefix (see [crate::access_key]), so ids given by callers are converted before they are looked
+//! up, and stored ids are shown with the generated prefix.
+//!
+//! As in AWS, a user has at most [MAX_ACCESS_KEYS_PER_USER] keys, and the user name defaults to the caller's. The
+//! `active` flag of a key is reported as its `Status`: inactive keys are still listed and can be deleted, but the
+//! signing key lookup refuses them.
+use {
+    crate::{
+        access_key::{generate_secret_key, AccessKeyKind, AccessKeyPrefixes},
+        context::RequestContext,
+        operation::{output::query_response, ValidationError},
+        operation_input, operation_output,
+        protocol::IAM_XML_NS,
+        store::{AccessKey, ControlPlaneStore, EntityKind, StoreError, User},
+        users::caller_user_name,
+    },
+    chrono::{DateTime, Utc},
+    http::StatusCode,
+    std::{
+        error::Error,
+        fmt::{Debug, Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// The number of access keys a user may have.
+pub const MAX_ACCESS_KEYS_PER_USER: usize = 2;
+
+/// The number of access keys returned by ListAccessKeys when `MaxItems` is not given.
+pub const DEFAULT_LIST_ACCESS_KEYS_MAX_ITEMS: usize = 100;
+
+operation_input! {
+    /// Input for the CreateAccessKey operation. Without a user name, the key is created for the caller.
+    pub struct CreateAccessKeyInput {
+        "UserName" => pub user_name: Option<String> where length(1, 128), pattern(r"[\w+=,.@-]+"),
+    }
+}
+
+operation_input! {
+    /// Input for the ListAccessKeys operation. Without a user name, the caller's keys are listed.
+    pub struct ListAccessKeysInput {
+        "UserName" => pub user_name: Option<String> where length(1, 128), pattern(r"[\w+=,.@-]+"),
+        "Marker" => pub marker: Option<String> where length(1, 320),
+        "MaxItems" => pub max_items: Option<i64> where range(1, 1000),
+    }
+}
+
+operation_input! {
+    /// Input for the DeleteAccessKey operation. Without a user name, the key must belong to the caller.
+    pub struct DeleteAccessKeyInput {
+        "UserName" => pub user_name: Option<String> where length(1, 128), pattern(r"[\w+=,.@-]+"),
+        "AccessKeyId" => pub access_key_id: String where length(16, 128), pattern(r"\w+"),
+    }
+}
+
+/// Errors from access key operations.
+#[derive(Debug)]
+pub enum AccessKeyError {
+    Validation(ValidationError),
+
+    /// No user name was given and the caller is not an IAM user.
+    UserNameRequired,
+    Store(StoreError),
+}
+
+impl AccessKeyError {
+    /// The IAM error code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Validation(e) => e.code(),
+            Self::UserNameRequired => "ValidationError",
+            Self::Store(e) => e.code(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Validation(_) | Self::UserNameRequired => StatusCode::BAD_REQUEST,
+            Self::Store(e) => e.status(),
+        }
+    }
+}
+
+impl Error for AccessKeyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Validation(e) => Some(e),
+            Self::UserNameRequired => None,
+            Self::Store(e) => Some(e),
+        }
+    }
+}
+
+impl Display for AccessKeyError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Validation(e) => write!(f, "{e}"),
+            Self::UserNameRequired => f.write_str("Must specify userName when calling with non-User credentials"),
+            Self::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<ValidationError> for AccessKeyError {
+    fn from(e: ValidationError) -> Self {
+        Self::Validation(e)
+    }
+}
+
+impl From<StoreError> for AccessKeyError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+/// An access key as shown to callers, without its secret.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AccessKeyMetadata {
+    pub user_name: String,
+
+    /// The access key id, spelled with the generated prefix.
+    pub access_key_id: String,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl AccessKeyMetadata {
+    fn new(user: &User, access_key: &AccessKey, prefixes: &AccessKeyPrefixes) -> Self {
+        Self {
+            user_name: user.user_name.clone(),
+            access_key_id: prefixes.display_stored_id(&access_key.access_key_id),
+            active: access_key.active,
+            created_at: access_key.created_at,
+        }
+    }
+
+    /// The `Status` IAM reports for the key.
+    pub fn status(&self) -> &'static str {
+        if self.active {
+            "Active"
+        } else {
+            "Inactive"
+        }
+    }
+}
+
+/// A newly created access key, the only time its secret is returned.
+#[derive(Clone, Eq, PartialEq)]
+pub struct CreateAccessKeyOutput {
+    pub access_key: AccessKeyMetadata,
+    pub secret_access_key: String,
+}
+
+impl Debug for CreateAccessKeyOutput {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("CreateAccessKeyOutput")
+            .field("access_key", &self.access_key)
+            .field("secret_access_key", &"<redacted>")
+            .finish()
+    }
+}
+
+impl CreateAccessKeyOutput {
+    /// The `CreateAccessKeyResponse` body.
+    pub fn to_xml(&self, request_id: &str) -> String {
+        let result = CreateAccessKeyResult {
+            access_key: AccessKeyElement::new(&self.access_key, Some(&self.secret_access_key)),
+        };
+        query_response(IAM_XML_NS, "CreateAccessKey", &result, request_id)
+    }
+}
+
+/// One page of a user's access keys, as returned by ListAccessKeys.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ListAccessKeysOutput {
+    pub user_name: String,
+    pub access_keys: Vec<AccessKeyMetadata>,
+
+    /// If the list is truncated, the marker to pass for the next page.
+    pub marker: Option<String>,
+}
+
+impl ListAccessKeysOutput {
+    /// The `ListAccessKeysResponse` body.
+    pub fn to_xml(&self, request_id: &str) -> String {
+        let result = ListAccessKeysResult {
+            user_name: &self.user_name,
+            access_key_metadata: self
+                .access_keys
+                .iter()
+                .map(|access_key| AccessKeyElement::new(access_key, None))
+                .collect(),
+            is_truncated: self.marker.is_some(),
+            marker: self.marker.as_deref(),
+        };
+        query_response(IAM_XML_NS, "ListAccessKeys", &result, request_id)
+    }
+}
+
+operation_output! {
+    /// An access key, with the children in the order AWS writes them. The secret is only present when the key is
+    /// created.
+    struct AccessKeyElement<'a> {
+        "UserName" => user_name: &'a str,
+        "AccessKeyId" => access_key_id: &'a str,
+        "Status" => status: &'a str,
+        "SecretAccessKey" => secret_access_key: Option<&'a str>,
+        "CreateDate" => create_date: DateTime<Utc>,
+    }
+}
+
+impl<'a> AccessKeyElement<'a> {
+    fn new(access_key: &'a AccessKeyMetadata, secret_access_key: Option<&'a str>) -> Self {
+        Self {
+            user_name: &access_key.user_name,
+            access_key_id: &access_key.access_key_id,
+            status: access_key.status(),
+            secret_access_key,
+            create_date: access_key.created_at,
+        }
+    }
+}
+
+operation_output! {
+    struct CreateAccessKeyResult<'a> {
+        "AccessKey" => access_key: AccessKeyElement<'a>,
+    }
+}
+
+operation_output! {
+    struct ListAccessKeysResult<'a> {
+        "UserName" => user_name: &'a str,
+        "AccessKeyMetadata" => access_key_metadata: Vec<AccessKeyElement<'a>>,
+        "IsTruncated" => is_truncated: bool,
+        "Marker" => marker: Option<&'a str>,
+    }
+}
+
+/// The user named in a request, or the caller if none is named.
+async fn target_user(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    user_name: Option<&str>,
+) -> Result<User, AccessKeyError> {
+    let user_name = match user_name {
+        Some(user_name) => user_name.to_string(),
+        None => caller_user_name(context).ok_or(AccessKeyError::UserNameRequired)?,
+    };
+
+    let account_id = context.account_id().unwrap_or_default();
+    Ok(store.get_user(&account_id, &user_name).await?)
+}
+
+/// Create an active access key for a user. The quota is checked before the key is stored, so two concurrent
+/// requests may both succeed.
+pub async fn create_access_key(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    prefixes: &AccessKeyPrefixes,
+    input: &CreateAccessKeyInput,
+) -> Result<CreateAccessKeyOutput, AccessKeyError> {
+    let user = target_user(store, context, input.user_name.as_deref()).await?;
+    if store.list_access_keys(&user.user_id).await?.len() >= MAX_ACCESS_KEYS_PER_USER {
+        return Err(StoreError::limit_exceeded(EntityKind::User, &user.user_name, MAX_ACCESS_KEYS_PER_USER).into());
+    }
+
+    let generated = prefixes.generate(AccessKeyKind::LongTerm);
+    let access_key = AccessKey {
+        user_id: user.user_id.clone(),
+        access_key_id: prefixes.stored_id(&generated).unwrap_or(&generated).to_string(),
+        secret_key: generate_secret_key(),
+        active: true,
+        created_at: Utc::now(),
+    };
+    store.create_access_key(&access_key).await?;
+
+    Ok(CreateAccessKeyOutput {
+        access_key: AccessKeyMetadata::new(&user, &access_key, prefixes),
+        secret_access_key: access_key.secret_key,
+    })
+}
+
+/// List one page of a user's access keys, oldest first. The marker is the id of the first key of the next page; if
+/// that key has since been deleted, the page is empty.
+pub async fn list_access_keys(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    prefixes: &AccessKeyPrefixes,
+    input: &ListAccessKeysInput,
+) -> Result<ListAccessKeysOutput, AccessKeyError> {
+    let user = target_user(store, context, input.user_name.as_deref()).await?;
+    let access_keys: Vec<AccessKeyMetadata> = store
+        .list_access_keys(&user.user_id)
+        .await?
+        .iter()
+        .map(|access_key| AccessKeyMetadata::new(&user, access_key, prefixes))
+        .collect();
+    let start = match &input.marker {
+        Some(marker) => {
+            access_keys.iter().position(|access_key| access_key.access_key_id == *marker).unwrap_or(access_keys.len())
+        }
+        None => 0,
+    };
+    let max_items = input.max_items.map(|max_items| max_items as usize).unwrap_or(DEFAULT_LIST_ACCESS_KEYS_MAX_ITEMS);
+
+    let mut access_keys = access_keys.into_iter().skip(start);
+    let page: Vec<AccessKeyMetadata> = access_keys.by_ref().take(max_items).collect();
+    Ok(ListAccessKeysOutput {
+        user_name: user.user_name,
+        access_keys: page,
+        marker: access_keys.next().map(|access_key| access_key.access_key_id),
+    })
+}
+
+/// Delete one of a user's access keys, given in any accepted spelling.
+pub async fn delete_access_key(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    prefixes: &AccessKeyPrefixes,
+    input: &DeleteAccessKeyInput,
+) -> Result<(), AccessKeyError> {
+    let user = target_user(store, context, input.user_name.as_deref()).await?;
+    let not_found = || StoreError::no_such_entity(EntityKind::AccessKey, &input.access_key_id);
+    let stored_id = prefixes.stored_id(&input.access_key_id).ok_or_else(not_found)?;
+
+    match store.delete_access_key(&user.user_id, stored_id).await {
+        Ok(()) => Ok(()),
+        // Report the key as the caller spelled it, not as it is stored.
+        Err(StoreError::NoSuchEntity {
+            ..
+        }) => Err(not_found().into()),
+        Err(e) => Err(e.into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            create_access_key, delete_access_key, list_access_keys, CreateAccessKeyInput, DeleteAccessKeyInput,
+            ListAccessKeysInput,
+        },
+        crate::{
+            access_key::AccessKeyPrefixes,
+            context::RequestContext,
+            operation::{parameters, FromParameters},
+            store::{ControlPlaneStore, MemoryStore, User},
+        },
+        chrono::Utc,
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{Principal, PrincipalIdentity, User as PrincipalUser},
+        std::collections::HashMap,
+    };
+
+    #[test_log::test(tokio::test)]
+    async fn test_access_key_lifecycle() {
+        let store = MemoryStore::new();
+        store
+            .create_user(
+                &User {
+                    user_id: "AIDAEXAMPLEUSER1".to_string(),
+                    account_id: "123456789012".to_string(),
+                    user_name: "Alice".to_string(),
+                    path: "/".to_string(),
+                    permissions_boundary: None,
+                    created_at: Utc::now(),
+                },
+                &[],
+            )
+            .await
+            .unwrap();
+        let alice = PrincipalUser::new("aws", "123456789012", "/", "Alice").unwrap();
+        let context =
+            RequestContext::builder().principal(Principal::from(vec![PrincipalIdentity::from(alice)])).build().unwrap();
+        let prefixes = AccessKeyPrefixes::new("SKIA", "SSIA").unwrap();
+
+        // Keys are created for the caller by default, shown with the generated prefix, and stored without it.
+        let input = CreateAccessKeyInput::from_parameters(&HashMap::new()).unwrap();
+        let first = create_access_key(&store, &context, &prefixes, &input).await.unwrap();
+        assert!(first.access_key.access_key_id.starts_with("SKIA"));
+        assert_eq!(first.access_key.access_key_id.len(), 20);
+        assert_eq!(first.secret_access_key.len(), 40);
+        let stored = store.get_access_key(&first.access_key.access_key_id[4..]).await.unwrap();
+        assert_eq!((stored.user_id.as_str(), stored.active), ("AIDAEXAMPLEUSER1", true));
+        assert_eq!(stored.secret_key, first.secret_access_key);
+        let xml = first.to_xml("01234567-89ab-cdef-0123-456789abcdef");
+        assert!(xml.contains("<UserName>Alice</UserName>"), "{xml}");
+        assert!(xml.contains("<Status>Active</Status>"), "{xml}");
+        assert!(!format!("{first:?}").contains(&first.secret_access_key));
+
+        let input = CreateAccessKeyInput::from_parameters(&parameters(&[("UserName", "alice")])).unwrap();
+        let second = create_access_key(&store, &context, &prefixes, &input).await.unwrap();
+        assert_ne!(second.access_key.access_key_id, first.access_key.access_key_id);
+        let e = create_access_key(&store, &context, &prefixes, &input).await.unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("LimitExceeded", 409));
+
+        store.set_access_key_active("AIDAEXAMPLEUSER1", &second.access_key.access_key_id[4..], false).await.unwrap();
+        let input = ListAccessKeysInput::from_parameters(&parameters(&[("MaxItems", "1")])).unwrap();
+        let page = list_access_keys(&store, &context, &prefixes, &input).await.unwrap();
+        assert_eq!(page.access_keys.len(), 1);
+        let marker = page.marker.clone().unwrap();
+        let input = ListAccessKeysInput::from_parameters(&parameters(&[("Marker", marker.as_str())])).unwrap();
+        let next = list_access_keys(&store, &context, &prefixes, &input).await.unwrap();
+        assert_eq!(next.access_keys[0].access_key_id, marker);
+        assert_eq!(next.marker, None);
+        assert!(next.to_xml("r").contains("<IsTruncated>false</IsTruncated>"));
+        let statuses: Vec<_> = page
+            .access_keys
+            .iter()
+            .chain(&next.access_keys)
+            .map(|key| (key.access_key_id.clone(), key.status()))
+            .collect();
+        assert!(statuses.contains(&(second.access_key.access_key_id.clone(), "Inactive")), "{statuses:?}");
+        assert!(statuses.contains(&(first.access_key.access_key_id.clone(), "Active")), "{statuses:?}");
+
+        // Ids are accepted in canonical spelling too; unknown and temporary ids are not found.
+        let canonical = format!("AKIA{}", &first.access_key.access_key_id[4..]);
+        for (access_key_id, code) in [
+            (canonical.as_str(), None),
+            (canonical.as_str(), Some("NoSuchEntity")),
+            ("SSIAIOSFODNN7EXAMPLE", Some("NoSuchEntity")),
+        ] {
+            let input = DeleteAccessKeyInput::from_parameters(&parameters(&[("AccessKeyId", access_key_id)])).unwrap();
+            let result = delete_access_key(&store, &context, &prefixes, &input).await;
+            assert_eq!(result.as_ref().err().map(|e| e.code()), code);
+            if let Err(e) = result {
+                assert!(e.to_string().contains(access_key_id), "{e}");
+            }
+        }
+
+        let anonymous = RequestContext::builder().principal(Principal::from(vec![])).build().unwrap();
+        let input = ListAccessKeysInput::from_parameters(&HashMap::new()).unwrap();
+        let e = list_access_keys(&store, &anonymous, &prefixes, &input).await.unwrap_err();
+        assert_eq!(e.code(), "ValidationError");
+    }
+}
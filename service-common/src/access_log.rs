@@ -0,0 +1,364 @@
+//! One structured log line per request.
+//!
+//! [WithAccessLog] wraps the per-connection service outside the signature verifier, so requests that are rejected
+//! before reaching the service implementation are logged too. Each line, logged to [ACCESS_LOG_TARGET], holds the
+//! method, path, status, latency, request id and source IP of the request and, if its signature was verified, the ARN
+//! of the principal that made it. Requests on TLS connections also carry the negotiated protocol version and cipher
+//! suite.
+//!
+//! The principal is only known inside the HTTP framework, so it is passed out the same way signature verification
+//! metrics are: [RecordPrincipal], which wraps the service implementation, fills in a slot that [WithAccessLog] put in
+//! the request extensions.
+use {
+    crate::{
+        context::RequestContext,
+        net::{ConnectionInfo, TlsSession},
+    },
+    hyper::{service::Service, Body, Request, Response},
+    log::info,
+    scratchstack_aws_principal::Principal,
+    scratchstack_http_framework::RequestId,
+    serde::{Deserialize, Serialize},
+    std::{
+        fmt::Write,
+        future::Future,
+        net::IpAddr,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+        time::Instant,
+    },
+    tower::BoxError,
+};
+
+/// The log target for access log lines, so they can be routed separately from the service log.
+pub const ACCESS_LOG_TARGET: &str = "scratchstack::access";
+
+/// How access log lines are written.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AccessLogFormat {
+    /// `key=value` pairs separated by spaces.
+    #[default]
+    Logfmt,
+
+    /// A JSON object.
+    Json,
+}
+
+/// Settings for the access log.
+#[derive(Clone, Debug, Deserialize)]
+pub struct AccessLogConfig {
+    #[serde(default = "AccessLogConfig::default_enabled")]
+    pub enabled: bool,
+
+    #[serde(default)]
+    pub format: AccessLogFormat,
+}
+
+impl AccessLogConfig {
+    fn default_enabled() -> bool {
+        true
+    }
+}
+
+impl Default for AccessLogConfig {
+    fn default() -> Self {
+        Self {
+            enabled: Self::default_enabled(),
+            format: AccessLogFormat::default(),
+        }
+    }
+}
+
+/// One request, as it is logged.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct AccessLogEntry {
+    pub method: String,
+    pub path: String,
+    pub status: u16,
+    pub latency_ms: u64,
+    pub request_id: String,
+
+    /// The ARN of the principal whose signature was verified, if any.
+    pub principal: Option<String>,
+    pub source_ip: Option<IpAddr>,
+
+    /// The TLS protocol version and cipher suite of the connection, if it uses TLS.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_version: Option<String>,
+
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tls_cipher: Option<String>,
+}
+
+impl AccessLogEntry {
+    /// The entry as a line in `format`.
+    pub fn format(&self, format: AccessLogFormat) -> String {
+        match format {
+            AccessLogFormat::Json => serde_json::to_string(self).expect("Unable to serialize access log entry"),
+            AccessLogFormat::Logfmt => {
+                let mut line = String::new();
+                let _ = write!(
+                    line,
+                    "method={} path={} status={} latency_ms={} request_id={}",
+                    logfmt_value(&self.method),
+                    logfmt_value(&self.path),
+                    self.status,
+                    self.latency_ms,
+                    logfmt_value(&self.request_id)
+                );
+                if let Some(principal) = &self.principal {
+                    let _ = write!(line, " principal={}", logfmt_value(principal));
+                }
+                if let Some(source_ip) = &self.source_ip {
+                    let _ = write!(line, " source_ip={source_ip}");
+                }
+                if let Some(tls_version) = &self.tls_version {
+                    let _ = write!(line, " tls_version={}", logfmt_value(tls_version));
+                }
+                if let Some(tls_cipher) = &self.tls_cipher {
+                    let _ = write!(line, " tls_cipher={}", logfmt_value(tls_cipher));
+                }
+                line
+            }
+        }
+    }
+}
+
+/// `value`, quoted if it is empty or contains spaces, quotes, `=` or control characters.
+fn logfmt_value(value: &str) -> String {
+    if !value.is_empty() && !value.chars().any(|c| c == ' ' || c == '"' || c == '=' || c.is_control()) {
+        return value.to_string();
+    }
+
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{{{:x}}}", c as u32);
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// The principal of a request, filled in by [RecordPrincipal] once the signature has been verified.
+#[derive(Clone, Debug, Default)]
+struct PrincipalSlot(Arc<Mutex<Option<String>>>);
+
+/// Wraps a make-service (such as `SpawnService`) so each per-connection service writes an access log line for every
+/// request. The service implementation should be wrapped in [RecordPrincipal]; otherwise lines have no principal.
+///
+/// Wrap this inside `WithConnectionInfo`, which provides the source IP, and outside `WithRequestIds`, which uses the
+/// request id assigned here.
+#[derive(Clone, Debug)]
+pub struct WithAccessLog<M> {
+    inner: M,
+    config: Arc<AccessLogConfig>,
+}
+
+impl<M> WithAccessLog<M> {
+    pub fn new(inner: M, config: &AccessLogConfig) -> Self {
+        Self {
+            inner,
+            config: Arc::new(config.clone()),
+        }
+    }
+}
+
+impl<T, M> Service<T> for WithAccessLog<M>
+where
+    M: Service<T>,
+    M::Future: Send + 'static,
+{
+    type Response = AccessLog<M::Response>;
+    type Error = M::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let config = self.config.clone();
+        let future = self.inner.call(target);
+        Box::pin(async move {
+            Ok(AccessLog {
+                inner: future.await?,
+                config,
+            })
+        })
+    }
+}
+
+/// A per-connection service that logs each request once its response is ready.
+#[derive(Clone, Debug)]
+pub struct AccessLog<S> {
+    inner: S,
+    config: Arc<AccessLogConfig>,
+}
+
+impl<S> Service<Request<Body>> for AccessLog<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        if !self.config.enabled {
+            let future = self.inner.call(req);
+            return Box::pin(async move { future.await.map_err(Into::into) });
+        }
+
+        let start = Instant::now();
+        let request_id = match req.extensions().get::<RequestId>() {
+            Some(request_id) => *request_id,
+            None => {
+                let request_id = RequestId::new();
+                req.extensions_mut().insert(request_id);
+                request_id
+            }
+        };
+        let slot = PrincipalSlot::default();
+        req.extensions_mut().insert(slot.clone());
+        let method = req.method().to_string();
+        let path = req.uri().path().to_string();
+        let source_ip = req.extensions().get::<ConnectionInfo>().map(|info| info.source_ip());
+        let tls = req
+            .extensions()
+            .get::<TlsSession>()
+            .map(|session| (session.protocol_version().to_string(), session.cipher_suite().to_string()));
+        let format = self.config.format;
+        let future = self.inner.call(req);
+
+        Box::pin(async move {
+            let result = future.await.map_err(Into::into);
+            let status = match &result {
+                Ok(response) => response.status().as_u16(),
+                // The connection is dropped without a response.
+                Err(_) => 0,
+            };
+            let principal = slot.0.lock().expect("principal slot poisoned").take();
+            let entry = AccessLogEntry {
+                method,
+                path,
+                status,
+                latency_ms: start.elapsed().as_millis().try_into().unwrap_or(u64::MAX),
+                request_id: request_id.to_string(),
+                principal,
+                source_ip,
+                tls_version: tls.as_ref().map(|(version, _)| version.clone()),
+                tls_cipher: tls.map(|(_, cipher)| cipher),
+            };
+            info!(target: ACCESS_LOG_TARGET, "{}", entry.format(format));
+            result
+        })
+    }
+}
+
+/// A service wrapper for the service implementation that records the verified principal for the access log.
+#[derive(Clone, Debug)]
+pub struct RecordPrincipal<S> {
+    inner: S,
+}
+
+impl<S> RecordPrincipal<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+        }
+    }
+}
+
+impl<S, B> Service<Request<B>> for RecordPrincipal<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        if let (Some(slot), Some(principal)) =
+            (req.extensions().get::<PrincipalSlot>(), req.extensions().get::<Principal>())
+        {
+            let arn = RequestContext::builder()
+                .principal(principal.clone())
+                .build()
+                .ok()
+                .and_then(|context| context.caller_arn())
+                .map(|arn| arn.to_string());
+            *slot.0.lock().expect("principal slot poisoned") = arn;
+        }
+        self.inner.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{AccessLogEntry, AccessLogFormat},
+        pretty_assertions::assert_eq,
+    };
+
+    #[test_log::test]
+    fn test_format() {
+        let mut entry = AccessLogEntry {
+            method: "POST".to_string(),
+            path: "/".to_string(),
+            status: 200,
+            latency_ms: 12,
+            request_id: "0e5b1a3c-8f3c-4d6b-9a4e-3f0f5c1d2b7a".to_string(),
+            principal: Some("arn:aws:iam::123456789012:user/alice".to_string()),
+            source_ip: Some("192.0.2.10".parse().unwrap()),
+            tls_version: None,
+            tls_cipher: None,
+        };
+
+        assert_eq!(
+            entry.format(AccessLogFormat::Logfmt),
+            "method=POST path=/ status=200 latency_ms=12 request_id=0e5b1a3c-8f3c-4d6b-9a4e-3f0f5c1d2b7a \
+             principal=arn:aws:iam::123456789012:user/alice source_ip=192.0.2.10"
+        );
+        assert_eq!(
+            entry.format(AccessLogFormat::Json),
+            r#"{"method":"POST","path":"/","status":200,"latency_ms":12,"request_id":"0e5b1a3c-8f3c-4d6b-9a4e-3f0f5c1d2b7a","principal":"arn:aws:iam::123456789012:user/alice","source_ip":"192.0.2.10"}"#
+        );
+
+        entry.tls_version = Some("TLSv1.3".to_string());
+        entry.tls_cipher = Some("TLS13_AES_128_GCM_SHA256".to_string());
+        assert!(entry
+            .format(AccessLogFormat::Logfmt)
+            .ends_with(" tls_version=TLSv1.3 tls_cipher=TLS13_AES_128_GCM_SHA256"));
+
+        entry.path = "/a b=\"c\"".to_string();
+        entry.principal = None;
+        entry.source_ip = None;
+        entry.tls_version = None;
+        entry.tls_cipher = None;
+        assert_eq!(
+            entry.format(AccessLogFormat::Logfmt),
+            "method=POST path=\"/a b=\\\"c\\\"\" status=200 latency_ms=12 \
+             request_id=0e5b1a3c-8f3c-4d6b-9a4e-3f0f5c1d2b7a"
+        );
+    }
+}
@@ -0,0 +1,227 @@
+//! IAM account alias operations: `CreateAccountAlias`, `DeleteAccountAlias`, and `ListAccountAliases`, written
+//! against [ControlPlaneStore].
+//!
+//! As in AWS, an account has at most one alias, and creating an alias replaces the existing one. Aliases are unique
+//! across accounts, so an alias taken by another account is reported as `EntityAlreadyExists`.
+use {
+    crate::{
+        operation::{output::query_response, ParameterViolation, ValidationError},
+        operation_input, operation_output,
+        protocol::IAM_XML_NS,
+        store::{ControlPlaneStore, EntityKind, StoreError},
+    },
+    http::StatusCode,
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+operation_input! {
+    /// Input for the CreateAccountAlias operation.
+    pub struct CreateAccountAliasInput {
+        "AccountAlias" => pub account_alias: String where length(3, 63), pattern(r"[a-z0-9]([a-z0-9]|-[a-z0-9])*"),
+    }
+}
+
+operation_input! {
+    /// Input for the DeleteAccountAlias operation.
+    pub struct DeleteAccountAliasInput {
+        "AccountAlias" => pub account_alias: String where length(3, 63), pattern(r"[a-z0-9]([a-z0-9]|-[a-z0-9])*"),
+    }
+}
+
+operation_input! {
+    /// Input for the ListAccountAliases operation. An account has at most one alias, so there is never more than one
+    /// page.
+    pub struct ListAccountAliasesInput {
+        "Marker" => pub marker: Option<String> where length(1, 320),
+        "MaxItems" => pub max_items: Option<i64> where range(1, 1000),
+    }
+}
+
+/// Errors from account alias operations.
+#[derive(Debug)]
+pub enum AccountAliasError {
+    Validation(ValidationError),
+    Store(StoreError),
+}
+
+impl AccountAliasError {
+    /// The IAM error code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Validation(e) => e.code(),
+            Self::Store(e) => e.code(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Validation(_) => StatusCode::BAD_REQUEST,
+            Self::Store(e) => e.status(),
+        }
+    }
+}
+
+impl Error for AccountAliasError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Validation(e) => Some(e),
+            Self::Store(e) => Some(e),
+        }
+    }
+}
+
+impl Display for AccountAliasError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Validation(e) => write!(f, "{e}"),
+            Self::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<ValidationError> for AccountAliasError {
+    fn from(e: ValidationError) -> Self {
+        Self::Validation(e)
+    }
+}
+
+impl From<StoreError> for AccountAliasError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+/// The aliases of an account, as returned by ListAccountAliases.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ListAccountAliasesOutput {
+    pub account_aliases: Vec<String>,
+}
+
+impl ListAccountAliasesOutput {
+    /// The `ListAccountAliasesResponse` body. An account has at most one alias, so the list is never truncated.
+    pub fn to_xml(&self, request_id: &str) -> String {
+        let result = ListAccountAliasesResult {
+            account_aliases: &self.account_aliases,
+            is_truncated: false,
+        };
+        query_response(IAM_XML_NS, "ListAccountAliases", &result, request_id)
+    }
+}
+
+operation_output! {
+    struct ListAccountAliasesResult<'a> {
+        "AccountAliases" => account_aliases: &'a [String],
+        "IsTruncated" => is_truncated: bool,
+    }
+}
+
+/// Give an account the alias in `input`, replacing any alias it already has.
+pub async fn create_account_alias(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    input: &CreateAccountAliasInput,
+) -> Result<(), AccountAliasError> {
+    // AWS refuses aliases that look like account ids, which would make sign-in URLs ambiguous.
+    if input.account_alias.bytes().all(|b| b.is_ascii_digit()) {
+        return Err(ValidationError::from(ParameterViolation::new(
+            "AccountAlias",
+            Some(input.account_alias.clone()),
+            "not consist only of digits",
+        ))
+        .into());
+    }
+
+    Ok(store.set_account_alias(account_id, Some(&input.account_alias)).await?)
+}
+
+/// Remove the alias in `input` from an account. This fails with `NoSuchEntity` unless it is the account's alias.
+pub async fn delete_account_alias(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    input: &DeleteAccountAliasInput,
+) -> Result<(), AccountAliasError> {
+    let account = store.get_account(account_id).await?;
+    if account.alias.as_deref() != Some(input.account_alias.as_str()) {
+        return Err(StoreError::no_such_entity(EntityKind::AccountAlias, &input.account_alias).into());
+    }
+
+    Ok(store.set_account_alias(account_id, None).await?)
+}
+
+pub async fn list_account_aliases(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    _input: &ListAccountAliasesInput,
+) -> Result<ListAccountAliasesOutput, AccountAliasError> {
+    let account = store.get_account(account_id).await?;
+    Ok(ListAccountAliasesOutput {
+        account_aliases: account.alias.into_iter().collect(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            create_account_alias, delete_account_alias, list_account_aliases, CreateAccountAliasInput,
+            DeleteAccountAliasInput, ListAccountAliasesInput,
+        },
+        crate::{
+            operation::FromParameters,
+            store::{Account, ControlPlaneStore, MemoryStore},
+        },
+        pretty_assertions::assert_eq,
+        std::collections::HashMap,
+    };
+
+    fn alias_parameters(alias: &str) -> HashMap<String, String> {
+        [("AccountAlias".to_string(), alias.to_string())].into_iter().collect()
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_account_alias_lifecycle() {
+        let store = MemoryStore::new();
+        for (account_id, email) in [("123456789012", "alice@example.com"), ("210987654321", "bob@example.com")] {
+            let account = Account {
+                account_id: account_id.to_string(),
+                email: email.to_string(),
+                active: true,
+                alias: None,
+            };
+            store.create_account(&account).await.unwrap();
+        }
+
+        for alias in ["-example", "example-", "ex--ample", "Example", "ex"] {
+            let e = CreateAccountAliasInput::from_parameters(&alias_parameters(alias)).unwrap_err();
+            assert_eq!(e.violations()[0].name(), "AccountAlias", "{alias}");
+        }
+        let input = CreateAccountAliasInput::from_parameters(&alias_parameters("123456")).unwrap();
+        let e = create_account_alias(&store, "123456789012", &input).await.unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("ValidationError", 400));
+
+        let input = CreateAccountAliasInput::from_parameters(&alias_parameters("example-corp")).unwrap();
+        create_account_alias(&store, "123456789012", &input).await.unwrap();
+        let e = create_account_alias(&store, "210987654321", &input).await.unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("EntityAlreadyExists", 409));
+
+        // A new alias replaces the old one.
+        let input = CreateAccountAliasInput::from_parameters(&alias_parameters("example-2")).unwrap();
+        create_account_alias(&store, "123456789012", &input).await.unwrap();
+        let input = ListAccountAliasesInput::from_parameters(&HashMap::new()).unwrap();
+        let output = list_account_aliases(&store, "123456789012", &input).await.unwrap();
+        assert_eq!(output.account_aliases, vec!["example-2".to_string()]);
+        let xml = output.to_xml("01234567-89ab-cdef-0123-456789abcdef");
+        assert!(xml.contains("<AccountAliases><member>example-2</member></AccountAliases>"), "{xml}");
+
+        let input = DeleteAccountAliasInput::from_parameters(&alias_parameters("example-corp")).unwrap();
+        let e = delete_account_alias(&store, "123456789012", &input).await.unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("NoSuchEntity", 404));
+        let input = DeleteAccountAliasInput::from_parameters(&alias_parameters("example-2")).unwrap();
+        delete_account_alias(&store, "123456789012", &input).await.unwrap();
+        let input = ListAccountAliasesInput::from_parameters(&HashMap::new()).unwrap();
+        assert!(list_account_aliases(&store, "123456789012", &input).await.unwrap().account_aliases.is_empty());
+    }
+}
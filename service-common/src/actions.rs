@@ -0,0 +1,316 @@
+//! The action registry: the actions the services implement and the service-specific condition keys each one adds to
+//! the policy evaluation context.
+//!
+//! Global keys such as `aws:SourceIp` come from the request itself. Keys like `iam:PolicyARN` depend on the
+//! parameters of a particular action, so the handler supplies them: it gets a [ConditionKeys] for its action from
+//! [ActionDefinition::condition_keys], inserts the values it knows, and passes the result to
+//! [RequestContext::add_condition_keys][crate::context::RequestContext::add_condition_keys] before policies are
+//! evaluated. Only keys declared for the action are accepted, so a handler cannot set global keys or keys that
+//! belong to other actions.
+//!
+//! Keys that are plain request parameters are also read by the authorizer with
+//! [ActionDefinition::condition_keys_from_parameters], so identity policies can use them before any handler runs.
+use {
+    crate::trust::{STS_EXTERNAL_ID, STS_SOURCE_IDENTITY},
+    scratchstack_aws_principal::{SessionData, SessionValue},
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// The ARN of the managed policy being attached or detached.
+pub const IAM_POLICY_ARN: &str = "iam:PolicyARN";
+
+/// The ARN of the permissions boundary being set on, or already applied to, the user or role.
+pub const IAM_PERMISSIONS_BOUNDARY: &str = "iam:PermissionsBoundary";
+
+/// The `RoleSessionName` parameter of AssumeRole.
+pub const STS_ROLE_SESSION_NAME: &str = "sts:RoleSessionName";
+
+/// The request parameter each service-specific condition key is read from, for keys that are plain parameters.
+const PARAMETER_KEYS: &[(&str, &str)] = &[
+    (IAM_POLICY_ARN, "PolicyArn"),
+    (IAM_PERMISSIONS_BOUNDARY, "PermissionsBoundary"),
+    (STS_EXTERNAL_ID, "ExternalId"),
+    (STS_ROLE_SESSION_NAME, "RoleSessionName"),
+    (STS_SOURCE_IDENTITY, "SourceIdentity"),
+];
+
+/// An action and the service-specific condition keys its handler supplies.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ActionDefinition {
+    pub service_name: &'static str,
+    pub action_name: &'static str,
+    pub condition_keys: &'static [&'static str],
+}
+
+impl ActionDefinition {
+    /// The action as written in policies, e.g. `iam:AttachUserPolicy`.
+    pub fn name(&self) -> String {
+        format!("{}:{}", self.service_name, self.action_name)
+    }
+
+    /// The declared spelling of `key`, if it is declared for this action. Condition keys are case-insensitive.
+    pub fn declared_key(&self, key: &str) -> Option<&'static str> {
+        self.condition_keys.iter().find(|declared| declared.eq_ignore_ascii_case(key)).copied()
+    }
+
+    /// Start collecting condition key values for a request to this action.
+    pub fn condition_keys(&self) -> ConditionKeys {
+        ConditionKeys {
+            action: *self,
+            values: Vec::new(),
+        }
+    }
+
+    /// The condition keys of this action whose values are request parameters, read with `parameter`. Keys that
+    /// depend on stored state, such as a permissions boundary already applied to a role, are left to the handler.
+    pub fn condition_keys_from_parameters<'a>(&self, parameter: impl Fn(&str) -> Option<&'a str>) -> ConditionKeys {
+        let mut keys = self.condition_keys();
+        for &(key, name) in PARAMETER_KEYS {
+            if let (Some(key), Some(value)) = (self.declared_key(key), parameter(name)) {
+                keys.values.push((key, SessionValue::String(value.to_string())));
+            }
+        }
+        keys
+    }
+}
+
+macro_rules! actions {
+    ($($(#[$meta:meta])* $const:ident = $service:literal, $action:literal [$($key:expr),* $(,)?];)*) => {
+        $(
+            $(#[$meta])*
+            pub const $const: ActionDefinition = ActionDefinition {
+                service_name: $service,
+                action_name: $action,
+                condition_keys: &[$($key),*],
+            };
+        )*
+
+        /// Every registered action.
+        pub const ACTIONS: &[ActionDefinition] = &[$($const),*];
+    };
+}
+
+actions! {
+    ATTACH_GROUP_POLICY = "iam", "AttachGroupPolicy" [IAM_POLICY_ARN];
+    ATTACH_ROLE_POLICY = "iam", "AttachRolePolicy" [IAM_POLICY_ARN, IAM_PERMISSIONS_BOUNDARY];
+    ATTACH_USER_POLICY = "iam", "AttachUserPolicy" [IAM_POLICY_ARN, IAM_PERMISSIONS_BOUNDARY];
+    CREATE_ACCESS_KEY = "iam", "CreateAccessKey" [];
+    CREATE_ACCOUNT_ALIAS = "iam", "CreateAccountAlias" [];
+    CREATE_POLICY_VERSION = "iam", "CreatePolicyVersion" [];
+    CREATE_ROLE = "iam", "CreateRole" [];
+    CREATE_SERVICE_SPECIFIC_CREDENTIAL = "iam", "CreateServiceSpecificCredential" [];
+    CREATE_USER = "iam", "CreateUser" [];
+    DELETE_ACCESS_KEY = "iam", "DeleteAccessKey" [];
+    DELETE_ACCOUNT_ALIAS = "iam", "DeleteAccountAlias" [];
+    DELETE_GROUP_POLICY = "iam", "DeleteGroupPolicy" [];
+    DELETE_POLICY = "iam", "DeletePolicy" [];
+    DELETE_ROLE_POLICY = "iam", "DeleteRolePolicy" [IAM_PERMISSIONS_BOUNDARY];
+    DELETE_SERVICE_SPECIFIC_CREDENTIAL = "iam", "DeleteServiceSpecificCredential" [];
+    DELETE_SSH_PUBLIC_KEY = "iam", "DeleteSSHPublicKey" [];
+    DELETE_USER = "iam", "DeleteUser" [];
+    DELETE_USER_POLICY = "iam", "DeleteUserPolicy" [IAM_PERMISSIONS_BOUNDARY];
+    DETACH_GROUP_POLICY = "iam", "DetachGroupPolicy" [IAM_POLICY_ARN];
+    DETACH_ROLE_POLICY = "iam", "DetachRolePolicy" [IAM_POLICY_ARN, IAM_PERMISSIONS_BOUNDARY];
+    DETACH_USER_POLICY = "iam", "DetachUserPolicy" [IAM_POLICY_ARN, IAM_PERMISSIONS_BOUNDARY];
+
+    /// A Scratchstack extension; see [crate::api_docs].
+    GET_IAM_API_DOCS = "iam", "GetApiDocs" [];
+    GET_GROUP_POLICY = "iam", "GetGroupPolicy" [];
+    GET_ROLE_POLICY = "iam", "GetRolePolicy" [];
+    GET_USER = "iam", "GetUser" [];
+    GET_USER_POLICY = "iam", "GetUserPolicy" [];
+    LIST_ACCESS_KEYS = "iam", "ListAccessKeys" [];
+    LIST_ACCOUNT_ALIASES = "iam", "ListAccountAliases" [];
+    LIST_ATTACHED_GROUP_POLICIES = "iam", "ListAttachedGroupPolicies" [];
+    LIST_ATTACHED_ROLE_POLICIES = "iam", "ListAttachedRolePolicies" [];
+    LIST_ATTACHED_USER_POLICIES = "iam", "ListAttachedUserPolicies" [];
+    LIST_GROUP_POLICIES = "iam", "ListGroupPolicies" [];
+    LIST_ROLE_POLICIES = "iam", "ListRolePolicies" [];
+    LIST_SSH_PUBLIC_KEYS = "iam", "ListSSHPublicKeys" [];
+    LIST_USER_POLICIES = "iam", "ListUserPolicies" [];
+    LIST_USERS = "iam", "ListUsers" [];
+    PUT_GROUP_POLICY = "iam", "PutGroupPolicy" [];
+    PUT_ROLE_POLICY = "iam", "PutRolePolicy" [IAM_PERMISSIONS_BOUNDARY];
+    PUT_USER_POLICY = "iam", "PutUserPolicy" [IAM_PERMISSIONS_BOUNDARY];
+    RESET_SERVICE_SPECIFIC_CREDENTIAL = "iam", "ResetServiceSpecificCredential" [];
+    SIMULATE_CUSTOM_POLICY = "iam", "SimulateCustomPolicy" [];
+    SIMULATE_PRINCIPAL_POLICY = "iam", "SimulatePrincipalPolicy" [];
+    UPDATE_SSH_PUBLIC_KEY = "iam", "UpdateSSHPublicKey" [];
+    UPDATE_USER = "iam", "UpdateUser" [];
+    UPLOAD_SSH_PUBLIC_KEY = "iam", "UploadSSHPublicKey" [];
+
+    ASSUME_ROLE = "sts", "AssumeRole" [STS_EXTERNAL_ID, STS_ROLE_SESSION_NAME, STS_SOURCE_IDENTITY];
+    ASSUME_ROLE_WITH_WEB_IDENTITY = "sts", "AssumeRoleWithWebIdentity" [STS_ROLE_SESSION_NAME];
+
+    /// A Scratchstack extension; see [crate::api_docs].
+    GET_STS_API_DOCS = "sts", "GetApiDocs" [];
+    GET_CALLER_IDENTITY = "sts", "GetCallerIdentity" [];
+
+    /// A Scratchstack extension; see the STS service.
+    GET_DEPLOYMENT_INFO = "sts", "GetDeploymentInfo" [];
+}
+
+/// The registered definition of `action_name` in `service_name`, e.g. `("iam", "AttachUserPolicy")`.
+pub fn lookup(service_name: &str, action_name: &str) -> Option<&'static ActionDefinition> {
+    ACTIONS.iter().find(|action| action.service_name == service_name && action.action_name == action_name)
+}
+
+/// Condition key values supplied by a handler for one request.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConditionKeys {
+    action: ActionDefinition,
+    values: Vec<(&'static str, SessionValue)>,
+}
+
+impl ConditionKeys {
+    pub fn action(&self) -> &ActionDefinition {
+        &self.action
+    }
+
+    /// Set `key`, replacing any earlier value. Fails if the key is not declared for the action.
+    pub fn insert(&mut self, key: &str, value: SessionValue) -> Result<(), ConditionKeyError> {
+        let key = self.declared(key)?;
+        self.values.retain(|(existing, _)| *existing != key);
+        self.values.push((key, value));
+        Ok(())
+    }
+
+    /// Set `key` to a string value, if one is given. Optional request parameters are left out of the context, so
+    /// `Null` conditions see them as absent.
+    pub fn insert_str(&mut self, key: &str, value: Option<&str>) -> Result<(), ConditionKeyError> {
+        match value {
+            Some(value) => self.insert(key, SessionValue::String(value.to_string())),
+            None => self.declared(key).map(|_| ()),
+        }
+    }
+
+    fn declared(&self, key: &str) -> Result<&'static str, ConditionKeyError> {
+        self.action.declared_key(key).ok_or_else(|| ConditionKeyError::Undeclared {
+            action: self.action.name(),
+            key: key.to_string(),
+        })
+    }
+
+    pub fn get(&self, key: &str) -> Option<&SessionValue> {
+        self.values.iter().find(|(existing, _)| existing.eq_ignore_ascii_case(key)).map(|(_, value)| value)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    /// Add the values to `session_data` under their declared names.
+    pub fn add_to_session_data(self, session_data: &mut SessionData) {
+        for (key, value) in self.values {
+            session_data.insert(key, value);
+        }
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum ConditionKeyError {
+    /// A handler supplied a key that the action registry does not declare for its action.
+    Undeclared {
+        action: String,
+        key: String,
+    },
+}
+
+impl Error for ConditionKeyError {}
+
+impl Display for ConditionKeyError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Undeclared {
+                action,
+                key,
+            } => write!(f, "Condition key {key} is not declared for {action}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            lookup, ConditionKeyError, ACTIONS, ASSUME_ROLE, ATTACH_USER_POLICY, CREATE_USER, IAM_PERMISSIONS_BOUNDARY,
+            IAM_POLICY_ARN,
+        },
+        crate::{context::RequestContext, trust::STS_EXTERNAL_ID},
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{Principal, PrincipalIdentity, SessionValue, User},
+        std::collections::{HashMap, HashSet},
+    };
+
+    #[test_log::test]
+    fn test_registry() {
+        let mut names = HashSet::new();
+        for action in ACTIONS {
+            assert!(names.insert(action.name()), "{} is registered twice", action.name());
+
+            // Services only declare their own keys; global keys cannot be supplied by handlers.
+            for key in action.condition_keys {
+                assert!(key.starts_with(&format!("{}:", action.service_name)), "{key} declared for {}", action.name());
+            }
+        }
+
+        assert_eq!(lookup("iam", "AttachUserPolicy"), Some(&ATTACH_USER_POLICY));
+        assert_eq!(lookup("sts", "AttachUserPolicy"), None);
+    }
+
+    #[test_log::test]
+    fn test_condition_keys() {
+        let mut keys = ATTACH_USER_POLICY.condition_keys();
+        keys.insert_str("iam:policyarn", Some("arn:aws:iam::aws:policy/ReadOnlyAccess")).unwrap();
+        keys.insert_str(IAM_POLICY_ARN, Some("arn:aws:iam::aws:policy/AdministratorAccess")).unwrap();
+        keys.insert_str("iam:PermissionsBoundary", None).unwrap();
+        assert_eq!(
+            keys.insert_str(STS_EXTERNAL_ID, Some("partner-7")).unwrap_err(),
+            ConditionKeyError::Undeclared {
+                action: "iam:AttachUserPolicy".to_string(),
+                key: STS_EXTERNAL_ID.to_string(),
+            }
+        );
+        assert!(keys.insert("aws:SourceIp", SessionValue::String("192.0.2.1".to_string())).is_err());
+
+        let user = User::new("aws", "123456789012", "/", "alice").unwrap();
+        let mut context =
+            RequestContext::builder().principal(Principal::from(vec![PrincipalIdentity::from(user)])).build().unwrap();
+        context.add_condition_keys(keys);
+        assert_eq!(
+            context.session_data().get(IAM_POLICY_ARN),
+            Some(&SessionValue::String("arn:aws:iam::aws:policy/AdministratorAccess".to_string()))
+        );
+        assert_eq!(context.session_data().get("iam:PermissionsBoundary"), None);
+
+        let mut keys = ASSUME_ROLE.condition_keys();
+        keys.insert_str(STS_EXTERNAL_ID, Some("partner-7")).unwrap();
+        assert_eq!(keys.get("sts:externalid"), Some(&SessionValue::String("partner-7".to_string())));
+    }
+
+    #[test_log::test]
+    fn test_condition_keys_from_parameters() {
+        let parameters: HashMap<&str, &str> =
+            [("PolicyArn", "arn:aws:iam::aws:policy/ReadOnlyAccess"), ("UserName", "bob"), ("ExternalId", "partner-7")]
+                .into_iter()
+                .collect();
+        let parameter = |name: &str| parameters.get(name).copied();
+
+        // Only the keys declared for the action are read; ExternalId belongs to AssumeRole.
+        let keys = ATTACH_USER_POLICY.condition_keys_from_parameters(parameter);
+        assert_eq!(
+            keys.get(IAM_POLICY_ARN),
+            Some(&SessionValue::String("arn:aws:iam::aws:policy/ReadOnlyAccess".to_string()))
+        );
+        assert_eq!(keys.get(IAM_PERMISSIONS_BOUNDARY), None);
+        assert_eq!(keys.get(STS_EXTERNAL_ID), None);
+        assert!(CREATE_USER.condition_keys_from_parameters(parameter).is_empty());
+        assert_eq!(
+            ASSUME_ROLE.condition_keys_from_parameters(parameter).get(STS_EXTERNAL_ID),
+            Some(&SessionValue::String("partner-7".to_string()))
+        );
+    }
+}
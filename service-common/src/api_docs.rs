@@ -0,0 +1,127 @@
+//! Machine-readable descriptions of the operations a service implements.
+//!
+//! Each service answers the `GetApiDocs` action — a Scratchstack extension, not part of the AWS APIs — with an
+//! [ApiDocs] document in JSON. It lists the actions the service dispatches, the parameters each one accepts with
+//! their types and validation constraints, and the condition keys it supplies to policy evaluation. Parameters come
+//! from [FromParameters::PARAMETERS], which [operation_input!][crate::operation_input] generates from the same
+//! declarations used to validate requests, and the operations from the table [operations!][crate::operations]
+//! builds the dispatcher from, so the document cannot drift from what the service actually enforces.
+use {
+    crate::{
+        actions::ActionDefinition,
+        operation::{FromParameters, ParameterSpec},
+    },
+    serde::Serialize,
+};
+
+/// One operation of a service.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct OperationDoc {
+    pub name: &'static str,
+    pub parameters: &'static [ParameterSpec],
+    pub condition_keys: &'static [&'static str],
+}
+
+impl OperationDoc {
+    /// The operation for `action`, taking the parameters of `I`.
+    pub fn new<I: FromParameters>(action: &ActionDefinition) -> Self {
+        Self {
+            name: action.action_name,
+            parameters: I::PARAMETERS,
+            condition_keys: action.condition_keys,
+        }
+    }
+
+    /// The operation for `action`, which takes no parameters besides `Action` and `Version`.
+    pub fn without_input(action: &ActionDefinition) -> Self {
+        Self {
+            name: action.action_name,
+            parameters: &[],
+            condition_keys: action.condition_keys,
+        }
+    }
+}
+
+/// The operations of one service and API version.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct ApiDocs {
+    pub service: &'static str,
+    pub version: &'static str,
+
+    /// How requests are encoded; always `query` for the services here.
+    pub protocol: &'static str,
+    pub operations: Vec<OperationDoc>,
+}
+
+impl ApiDocs {
+    pub fn new(service: &'static str, version: &'static str) -> Self {
+        Self {
+            service,
+            version,
+            protocol: "query",
+            operations: Vec::new(),
+        }
+    }
+
+    pub fn with_operation(mut self, operation: OperationDoc) -> Self {
+        self.operations.push(operation);
+        self
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("ApiDocs always serializes")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{ApiDocs, OperationDoc},
+        crate::{
+            actions::{DELETE_USER, GET_IAM_API_DOCS},
+            users::DeleteUserInput,
+        },
+        pretty_assertions::assert_eq,
+        serde_json::json,
+    };
+
+    #[test_log::test]
+    fn test_api_docs() {
+        let docs = ApiDocs::new("iam", "2010-05-08")
+            .with_operation(OperationDoc::new::<DeleteUserInput>(&DELETE_USER))
+            .with_operation(OperationDoc::without_input(&GET_IAM_API_DOCS));
+        let json: serde_json::Value = serde_json::from_str(&docs.to_json()).unwrap();
+
+        assert_eq!(
+            json,
+            json!({
+                "service": "iam",
+                "version": "2010-05-08",
+                "protocol": "query",
+                "operations": [
+                    {
+                        "name": "DeleteUser",
+                        "parameters": [
+                            {
+                                "name": "UserName",
+                                "type": "string",
+                                "required": true,
+                                "constraints": [
+                                    {"type": "length", "min": 1, "max": 128},
+                                    {"type": "pattern", "pattern": r"[\w+=,.@-]+"},
+                                ],
+                            },
+                        ],
+                        "conditionKeys": [],
+                    },
+                    {
+                        "name": "GetApiDocs",
+                        "parameters": [],
+                        "conditionKeys": [],
+                    },
+                ],
+            })
+        );
+    }
+}
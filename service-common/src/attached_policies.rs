@@ -0,0 +1,480 @@
+//! Managed policy attachment operations for users, groups, and roles: `Attach*Policy`, `Detach*Policy`, and
+//! `ListAttached*Policies`.
+//!
+//! Policies are named by ARN. Only customer managed policies in the caller's account can be attached; any other
+//! ARN, or one whose path does not match the policy's, is reported as `NoSuchEntity`, as AWS does for a policy that
+//! does not exist or is not attachable. The number of policies attached to a holder is limited by the account's
+//! `AttachedPoliciesPer*` quota; see [crate::policies].
+//!
+//! `ListAttached*Policies` pages through policies by lowercase name. As with `ListUsers`, the marker of a truncated
+//! page is the lowercase name of the next policy.
+use {
+    crate::{
+        inline_policies::resolve_holder,
+        limits::Limits,
+        operation::{output::query_response, ValidationError},
+        operation_input, operation_output, policies,
+        protocol::IAM_XML_NS,
+        store::{ControlPlaneStore, EntityKind, ManagedPolicy, PolicyHolder, StoreError},
+    },
+    http::StatusCode,
+    scratchstack_arn::Arn,
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+operation_input! {
+    /// Input for the AttachUserPolicy and DetachUserPolicy operations.
+    pub struct UserPolicyAttachmentInput {
+        "UserName" => pub user_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+        "PolicyArn" => pub policy_arn: String where length(20, 2048),
+    }
+}
+
+operation_input! {
+    /// Input for the ListAttachedUserPolicies operation.
+    pub struct ListAttachedUserPoliciesInput {
+        "UserName" => pub user_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+        "PathPrefix" => pub path_prefix: Option<String> where length(1, 512), pattern(r"/[\x21-\x7e]*"),
+        "Marker" => pub marker: Option<String> where length(1, 320),
+        "MaxItems" => pub max_items: Option<i64> where range(1, 1000),
+    }
+}
+
+operation_input! {
+    /// Input for the AttachGroupPolicy and DetachGroupPolicy operations.
+    pub struct GroupPolicyAttachmentInput {
+        "GroupName" => pub group_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+        "PolicyArn" => pub policy_arn: String where length(20, 2048),
+    }
+}
+
+operation_input! {
+    /// Input for the ListAttachedGroupPolicies operation.
+    pub struct ListAttachedGroupPoliciesInput {
+        "GroupName" => pub group_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+        "PathPrefix" => pub path_prefix: Option<String> where length(1, 512), pattern(r"/[\x21-\x7e]*"),
+        "Marker" => pub marker: Option<String> where length(1, 320),
+        "MaxItems" => pub max_items: Option<i64> where range(1, 1000),
+    }
+}
+
+operation_input! {
+    /// Input for the AttachRolePolicy and DetachRolePolicy operations.
+    pub struct RolePolicyAttachmentInput {
+        "RoleName" => pub role_name: String where length(1, 64), pattern(r"[\w+=,.@-]+"),
+        "PolicyArn" => pub policy_arn: String where length(20, 2048),
+    }
+}
+
+operation_input! {
+    /// Input for the ListAttachedRolePolicies operation.
+    pub struct ListAttachedRolePoliciesInput {
+        "RoleName" => pub role_name: String where length(1, 64), pattern(r"[\w+=,.@-]+"),
+        "PathPrefix" => pub path_prefix: Option<String> where length(1, 512), pattern(r"/[\x21-\x7e]*"),
+        "Marker" => pub marker: Option<String> where length(1, 320),
+        "MaxItems" => pub max_items: Option<i64> where range(1, 1000),
+    }
+}
+
+/// Errors from policy attachment operations.
+#[derive(Debug)]
+pub enum AttachedPolicyError {
+    Validation(ValidationError),
+
+    /// The policy ARN could not be parsed, or does not name an IAM policy.
+    InvalidPolicyArn(String),
+
+    /// The policy does not exist, or cannot be attached from this account.
+    NotAttachable(String),
+
+    /// The policy is not attached to the holder it is being detached from.
+    NotAttached(String),
+
+    /// The holder already has as many attached policies as its quota allows.
+    LimitExceeded {
+        holder: PolicyHolder,
+        limit: usize,
+    },
+    Store(StoreError),
+}
+
+impl AttachedPolicyError {
+    /// The IAM error code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Validation(e) => e.code(),
+            Self::InvalidPolicyArn(_) => "InvalidInput",
+            Self::NotAttachable(_) | Self::NotAttached(_) => "NoSuchEntity",
+            Self::LimitExceeded {
+                ..
+            } => "LimitExceeded",
+            Self::Store(e) => e.code(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Validation(_) | Self::InvalidPolicyArn(_) => StatusCode::BAD_REQUEST,
+            Self::NotAttachable(_) | Self::NotAttached(_) => StatusCode::NOT_FOUND,
+            Self::LimitExceeded {
+                ..
+            } => StatusCode::CONFLICT,
+            Self::Store(e) => e.status(),
+        }
+    }
+}
+
+impl Error for AttachedPolicyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Validation(e) => Some(e),
+            Self::Store(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for AttachedPolicyError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Validation(e) => write!(f, "{e}"),
+            Self::InvalidPolicyArn(arn) => write!(f, "ARN {arn} is not valid."),
+            Self::NotAttachable(arn) => write!(f, "Policy {arn} does not exist or is not attachable."),
+            Self::NotAttached(arn) => write!(f, "Policy {arn} was not found."),
+            Self::LimitExceeded {
+                holder,
+                limit,
+            } => write!(f, "Cannot exceed quota for PoliciesPer{}: {limit}.", holder_element(*holder)),
+            Self::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<ValidationError> for AttachedPolicyError {
+    fn from(e: ValidationError) -> Self {
+        Self::Validation(e)
+    }
+}
+
+impl From<StoreError> for AttachedPolicyError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+/// The number of policies returned by ListAttached*Policies when `MaxItems` is not given.
+pub const DEFAULT_LIST_ATTACHED_POLICIES_MAX_ITEMS: usize = 100;
+
+/// One page of the managed policies attached to a holder, as returned by ListAttached*Policies.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ListAttachedPoliciesOutput {
+    pub holder: PolicyHolder,
+    pub policies: Vec<ManagedPolicy>,
+
+    /// If the list is truncated, the marker to pass for the next page.
+    pub marker: Option<String>,
+}
+
+impl ListAttachedPoliciesOutput {
+    /// The `ListAttached{User,Group,Role}PoliciesResponse` body.
+    pub fn to_xml(&self, partition: &str, request_id: &str) -> String {
+        let result = ListAttachedPoliciesResult {
+            attached_policies: self
+                .policies
+                .iter()
+                .map(|policy| AttachedPolicyElement {
+                    policy_name: &policy.policy_name,
+                    policy_arn: policy_arn(partition, policy),
+                })
+                .collect(),
+            is_truncated: self.marker.is_some(),
+            marker: self.marker.as_deref(),
+        };
+        let operation = format!("ListAttached{}Policies", holder_element(self.holder));
+        query_response(IAM_XML_NS, &operation, &result, request_id)
+    }
+}
+
+operation_output! {
+    struct AttachedPolicyElement<'a> {
+        "PolicyName" => policy_name: &'a str,
+        "PolicyArn" => policy_arn: String,
+    }
+}
+
+operation_output! {
+    struct ListAttachedPoliciesResult<'a> {
+        "AttachedPolicies" => attached_policies: Vec<AttachedPolicyElement<'a>>,
+        "IsTruncated" => is_truncated: bool,
+        "Marker" => marker: Option<&'a str>,
+    }
+}
+
+/// `User`, `Group`, or `Role`, as the holder appears in operation and quota names.
+pub(crate) fn holder_element(holder: PolicyHolder) -> &'static str {
+    match holder {
+        PolicyHolder::User => "User",
+        PolicyHolder::Group => "Group",
+        PolicyHolder::Role => "Role",
+    }
+}
+
+/// The ARN of a managed policy.
+pub fn policy_arn(partition: &str, policy: &ManagedPolicy) -> String {
+    format!("arn:{partition}:iam::{}:policy{}{}", policy.account_id, policy.path, policy.policy_name)
+}
+
+/// The account, path, and name of the managed policy `policy_arn`, or `None` if it does not name an IAM policy.
+pub fn parse_policy_arn(policy_arn: &str) -> Option<(String, String, String)> {
+    let arn = Arn::from_str(policy_arn).ok()?;
+    if arn.service() != "iam" || !arn.region().is_empty() {
+        return None;
+    }
+
+    // policy/<path>/<name>
+    let resource = arn.resource().strip_prefix("policy/")?;
+    let (path, policy_name) = match resource.rsplit_once('/') {
+        Some((path, policy_name)) => (format!("/{path}/"), policy_name),
+        None => ("/".to_string(), resource),
+    };
+    if policy_name.is_empty() {
+        return None;
+    }
+
+    Some((arn.account_id().to_string(), path, policy_name.to_string()))
+}
+
+/// Look up the managed policy in `account_id` named by `policy_arn`.
+pub async fn attachable_policy(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    policy_arn: &str,
+) -> Result<ManagedPolicy, AttachedPolicyError> {
+    let (policy_account_id, path, policy_name) =
+        parse_policy_arn(policy_arn).ok_or_else(|| AttachedPolicyError::InvalidPolicyArn(policy_arn.to_string()))?;
+    if policy_account_id != account_id {
+        return Err(AttachedPolicyError::NotAttachable(policy_arn.to_string()));
+    }
+
+    match store.get_policy(account_id, &policy_name).await {
+        Ok(policy) if policy.path == path => Ok(policy),
+        Ok(_)
+        | Err(StoreError::NoSuchEntity {
+            ..
+        }) => Err(AttachedPolicyError::NotAttachable(policy_arn.to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Attach the managed policy named by `policy_arn` to the named holder, enforcing the account's attachment quota.
+/// Attaching a policy that is already attached does nothing.
+pub async fn attach_policy(
+    store: &dyn ControlPlaneStore,
+    limits: &Limits,
+    account_id: &str,
+    holder: PolicyHolder,
+    holder_name: &str,
+    policy_arn: &str,
+) -> Result<(), AttachedPolicyError> {
+    let holder_id = resolve_holder(store, account_id, holder, holder_name).await?;
+    let policy = attachable_policy(store, account_id, policy_arn).await?;
+    match policies::attach_policy(store, limits, account_id, holder, &holder_id, &policy.managed_policy_id).await {
+        Ok(()) => Ok(()),
+        Err(StoreError::LimitExceeded {
+            limit,
+            ..
+        }) => Err(AttachedPolicyError::LimitExceeded {
+            holder,
+            limit,
+        }),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Detach the managed policy named by `policy_arn` from the named holder.
+pub async fn detach_policy(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    holder: PolicyHolder,
+    holder_name: &str,
+    policy_arn: &str,
+) -> Result<(), AttachedPolicyError> {
+    let holder_id = resolve_holder(store, account_id, holder, holder_name).await?;
+    let policy = attachable_policy(store, account_id, policy_arn).await?;
+    match store.detach_policy(holder, &holder_id, &policy.managed_policy_id).await {
+        Ok(()) => Ok(()),
+        Err(StoreError::NoSuchEntity {
+            kind: EntityKind::Policy,
+            ..
+        }) => Err(AttachedPolicyError::NotAttached(policy_arn.to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// List one page of the managed policies attached to the named holder whose path starts with `path_prefix`.
+pub async fn list_attached_policies(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    holder: PolicyHolder,
+    holder_name: &str,
+    path_prefix: Option<&str>,
+    marker: Option<&str>,
+    max_items: Option<i64>,
+) -> Result<ListAttachedPoliciesOutput, AttachedPolicyError> {
+    let holder_id = resolve_holder(store, account_id, holder, holder_name).await?;
+    let attached = store.list_attached_policies(holder, &holder_id).await?;
+
+    // The store lists policies by lowercase name, so the attached ones stay in that order.
+    let policies: Vec<ManagedPolicy> = store
+        .list_policies(account_id, path_prefix.unwrap_or("/"))
+        .await?
+        .into_iter()
+        .filter(|policy| attached.contains(&policy.managed_policy_id))
+        .collect();
+    let start = match marker {
+        Some(marker) => {
+            policies.iter().take_while(|policy| policy.policy_name.to_lowercase().as_str() < marker).count()
+        }
+        None => 0,
+    };
+    let max_items = max_items.map(|max_items| max_items as usize).unwrap_or(DEFAULT_LIST_ATTACHED_POLICIES_MAX_ITEMS);
+
+    let mut policies = policies.into_iter().skip(start);
+    let page: Vec<ManagedPolicy> = policies.by_ref().take(max_items).collect();
+    Ok(ListAttachedPoliciesOutput {
+        holder,
+        policies: page,
+        marker: policies.next().map(|policy| policy.policy_name.to_lowercase()),
+    })
+}
+
+/// The number of users, groups, and roles the managed policy named by `policy_arn` is attached to.
+pub async fn attachment_count(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    policy_arn: &str,
+) -> Result<usize, AttachedPolicyError> {
+    let policy = attachable_policy(store, account_id, policy_arn).await?;
+    Ok(store.count_policy_attachments(&policy.managed_policy_id).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            attach_policy, attachment_count, detach_policy, list_attached_policies, AttachedPolicyError,
+            ListAttachedRolePoliciesInput,
+        },
+        crate::{
+            limits::Limits,
+            operation::FromParameters,
+            store::{ControlPlaneStore, ManagedPolicy, MemoryStore, PolicyHolder, Role},
+        },
+        chrono::Utc,
+        pretty_assertions::assert_eq,
+        std::collections::HashMap,
+    };
+
+    fn policy(name: &str, path: &str) -> ManagedPolicy {
+        ManagedPolicy {
+            managed_policy_id: format!("ANPA{:0>12}", name.to_uppercase()),
+            account_id: "123456789012".to_string(),
+            policy_name: name.to_string(),
+            path: path.to_string(),
+            default_version: None,
+            deprecated: false,
+            policy_type: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_attach_and_detach() {
+        let store = MemoryStore::new();
+        let limits = Limits::defaults();
+        let document = r#"{"Version": "2012-10-17", "Statement": []}"#;
+        let role = Role {
+            role_id: "AROAEXAMPLEROLE1".to_string(),
+            account_id: "123456789012".to_string(),
+            role_name: "Deployer".to_string(),
+            path: "/".to_string(),
+            permissions_boundary: None,
+            description: None,
+            assume_role_policy_document: document.to_string(),
+            max_session_duration: 3600,
+            created_at: Utc::now(),
+        };
+        store.create_role(&role, &[]).await.unwrap();
+        store.create_policy(&policy("Deploy", "/ci/"), document).await.unwrap();
+        store.create_policy(&policy("Audit", "/"), document).await.unwrap();
+
+        let deploy = "arn:aws:iam::123456789012:policy/ci/Deploy";
+        let audit = "arn:aws:iam::123456789012:policy/Audit";
+        for arn in [deploy, audit, deploy] {
+            attach_policy(&store, &limits, "123456789012", PolicyHolder::Role, "deployer", arn).await.unwrap();
+        }
+        assert_eq!(attachment_count(&store, "123456789012", deploy).await.unwrap(), 1);
+
+        // The path is part of the ARN, and policies in other accounts cannot be attached.
+        for arn in ["arn:aws:iam::123456789012:policy/Deploy", "arn:aws:iam::210987654321:policy/Audit"] {
+            let e =
+                attach_policy(&store, &limits, "123456789012", PolicyHolder::Role, "Deployer", arn).await.unwrap_err();
+            assert!(matches!(e, AttachedPolicyError::NotAttachable(_)), "{e:?}");
+            assert_eq!((e.code(), e.status().as_u16()), ("NoSuchEntity", 404));
+        }
+        let e = attach_policy(&store, &limits, "123456789012", PolicyHolder::Role, "Deployer", "arn:aws:s3:::bucket")
+            .await
+            .unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("InvalidInput", 400));
+        let e =
+            attach_policy(&store, &limits, "123456789012", PolicyHolder::User, "Deployer", audit).await.unwrap_err();
+        assert_eq!(e.to_string(), "The user with name Deployer cannot be found.");
+
+        let parameters: HashMap<String, String> =
+            [("RoleName", "Deployer"), ("MaxItems", "1")].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        let input = ListAttachedRolePoliciesInput::from_parameters(&parameters).unwrap();
+        let max_items = input.max_items;
+        let page =
+            list_attached_policies(&store, "123456789012", PolicyHolder::Role, "Deployer", None, None, max_items)
+                .await
+                .unwrap();
+        assert_eq!(page.marker.as_deref(), Some("deploy"));
+        let xml = page.to_xml("aws", "01234567-89ab-cdef-0123-456789abcdef");
+        assert!(
+            xml.contains(
+                "<ListAttachedRolePoliciesResult><AttachedPolicies><member><PolicyName>Audit</PolicyName>\
+                 <PolicyArn>arn:aws:iam::123456789012:policy/Audit</PolicyArn></member></AttachedPolicies>\
+                 <IsTruncated>true</IsTruncated><Marker>deploy</Marker>"
+            ),
+            "{xml}"
+        );
+        let page =
+            list_attached_policies(&store, "123456789012", PolicyHolder::Role, "Deployer", Some("/ci/"), None, None)
+                .await
+                .unwrap();
+        assert_eq!(page.policies.iter().map(|policy| policy.policy_name.as_str()).collect::<Vec<_>>(), vec!["Deploy"]);
+
+        detach_policy(&store, "123456789012", PolicyHolder::Role, "Deployer", deploy).await.unwrap();
+        let e = detach_policy(&store, "123456789012", PolicyHolder::Role, "Deployer", deploy).await.unwrap_err();
+        assert_eq!(e.to_string(), format!("Policy {deploy} was not found."));
+        assert_eq!(attachment_count(&store, "123456789012", deploy).await.unwrap(), 0);
+
+        for n in 0..10 {
+            let name = format!("Policy{n}");
+            store.create_policy(&policy(&name, "/"), document).await.unwrap();
+            let arn = format!("arn:aws:iam::123456789012:policy/{name}");
+            let result = attach_policy(&store, &limits, "123456789012", PolicyHolder::Role, "Deployer", &arn).await;
+            if n < 9 {
+                result.unwrap();
+            } else {
+                let e = result.unwrap_err();
+                assert_eq!((e.code(), e.status().as_u16()), ("LimitExceeded", 409));
+                assert_eq!(e.to_string(), "Cannot exceed quota for PoliciesPerRole: 10.");
+            }
+        }
+    }
+}
@@ -0,0 +1,531 @@
+//! Delivery of audit events to external sinks.
+//!
+//! Audit events are log records with the [AUDIT_TARGET] target. [init_logging] installs a logger that passes every
+//! record to `env_logger` as before and also hands audit records to the sinks started by [start]. Each sink has its
+//! own worker and queue, so a slow or unavailable sink does not hold up the others or the request path. Events are
+//! queued on disk when a queue directory is configured, and retried with exponential backoff until the sink accepts
+//! them; a full queue drops new events and reports how many were lost.
+mod queue;
+mod sink;
+
+pub use self::{
+    queue::AuditQueue,
+    sink::{AuditSink, FileSink, SyslogSink, WebhookSink},
+};
+
+#[cfg(feature = "kafka")]
+pub use self::sink::KafkaSink;
+
+use {
+    crate::{
+        authz::AUDIT_TARGET,
+        outbound::{OutboundClient, OutboundError},
+    },
+    chrono::{SecondsFormat, Utc},
+    log::{error, warn, Level, LevelFilter, Log, Metadata, Record},
+    serde::{Deserialize, Serialize},
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        io::Error as IOError,
+        path::PathBuf,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc, Mutex,
+        },
+        time::Duration,
+    },
+    tokio::{
+        sync::mpsc::{self, error::TrySendError, Receiver, Sender},
+        task::JoinHandle,
+        time::{sleep_until, timeout_at, Instant},
+    },
+};
+
+/// The first delay before a failed delivery is retried; it doubles on each failure up to [MAX_BACKOFF].
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// The most events a worker delivers at once.
+const MAX_BATCH: usize = 100;
+
+/// Senders to the running sink workers.
+static SINKS: Mutex<Vec<SinkHandle>> = Mutex::new(Vec::new());
+
+/// Audit delivery settings.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(default)]
+pub struct AuditConfig {
+    pub sinks: Vec<AuditSinkConfig>,
+
+    /// If present, undelivered events are kept here so they survive sink outages and restarts; otherwise they are
+    /// held in memory.
+    pub queue_directory: Option<PathBuf>,
+
+    /// The most bytes of undelivered events kept for each sink.
+    pub max_queue_bytes: u64,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            sinks: Vec::new(),
+            queue_directory: None,
+            max_queue_bytes: 64 << 20,
+        }
+    }
+}
+
+/// Where audit events are sent.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum AuditSinkConfig {
+    /// Append JSON lines to a file.
+    File {
+        path: PathBuf,
+    },
+
+    /// Send RFC 5424 messages over UDP.
+    Syslog {
+        address: String,
+
+        /// The syslog facility; the default is 13, log audit.
+        #[serde(default = "default_syslog_facility")]
+        facility: u8,
+    },
+
+    /// Produce JSON messages to a Kafka topic; requires the `kafka` feature.
+    Kafka {
+        brokers: String,
+        topic: String,
+    },
+
+    /// POST batches of events as a JSON array, signed with HMAC-SHA256 if a secret is given.
+    Webhook {
+        url: String,
+
+        /// File containing the HMAC secret.
+        secret_file: Option<PathBuf>,
+
+        #[serde(default = "default_webhook_timeout_ms")]
+        timeout_ms: u64,
+    },
+}
+
+fn default_syslog_facility() -> u8 {
+    13
+}
+
+fn default_webhook_timeout_ms() -> u64 {
+    5000
+}
+
+impl AuditSinkConfig {
+    /// A short name for the sink, used in logs and queue directory names.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::File {
+                ..
+            } => "file",
+            Self::Syslog {
+                ..
+            } => "syslog",
+            Self::Kafka {
+                ..
+            } => "kafka",
+            Self::Webhook {
+                ..
+            } => "webhook",
+        }
+    }
+
+    fn sink(&self, outbound: &OutboundClient) -> Result<Box<dyn AuditSink>, AuditError> {
+        match self {
+            Self::File {
+                path,
+            } => Ok(Box::new(FileSink::new(path.clone()))),
+            Self::Syslog {
+                address,
+                facility,
+            } => Ok(Box::new(SyslogSink::new(address, *facility)?)),
+            #[cfg(feature = "kafka")]
+            Self::Kafka {
+                brokers,
+                topic,
+            } => Ok(Box::new(KafkaSink::new(brokers, topic)?)),
+            #[cfg(not(feature = "kafka"))]
+            Self::Kafka {
+                ..
+            } => Err(AuditError::Unsupported("the kafka feature is not enabled".to_string())),
+            Self::Webhook {
+                url,
+                secret_file,
+                timeout_ms,
+            } => {
+                let secret = match secret_file {
+                    None => None,
+                    Some(secret_file) => Some(std::fs::read_to_string(secret_file)?.trim_end().as_bytes().to_vec()),
+                };
+                Ok(Box::new(WebhookSink::new(url, secret, Duration::from_millis(*timeout_ms), outbound)?))
+            }
+        }
+    }
+}
+
+/// An audit log record.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq, Serialize)]
+pub struct AuditEvent {
+    /// RFC 3339, in UTC.
+    pub timestamp: String,
+    pub level: String,
+
+    /// The service that logged the event.
+    pub service: String,
+    pub message: String,
+}
+
+impl AuditEvent {
+    pub fn new(service: &str, level: Level, message: String) -> Self {
+        Self {
+            timestamp: Utc::now().to_rfc3339_opts(SecondsFormat::Micros, true),
+            level: level.to_string(),
+            service: service.to_string(),
+            message,
+        }
+    }
+}
+
+struct SinkHandle {
+    service: String,
+    sender: Sender<AuditEvent>,
+    dropped: Arc<AtomicU64>,
+}
+
+/// A logger that passes records to another logger and sends [AUDIT_TARGET] records to the audit sinks.
+pub struct AuditLogger<L> {
+    inner: L,
+}
+
+impl<L: Log> AuditLogger<L> {
+    pub fn new(inner: L) -> Self {
+        Self {
+            inner,
+        }
+    }
+}
+
+impl<L: Log> Log for AuditLogger<L> {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.target() == AUDIT_TARGET || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if record.target() == AUDIT_TARGET {
+            dispatch(record.level(), record.args().to_string());
+        }
+
+        self.inner.log(record);
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Install `env_logger`, configured from `RUST_LOG`, behind an [AuditLogger]. Services call this in place of
+/// `env_logger::init`.
+pub fn init_logging() {
+    let inner = env_logger::Builder::from_default_env().build();
+    // Audit records are always captured, whatever RUST_LOG says.
+    let max_level = inner.filter().max(LevelFilter::Warn);
+    if log::set_boxed_logger(Box::new(AuditLogger::new(inner))).is_ok() {
+        log::set_max_level(max_level);
+    }
+}
+
+/// Start a worker for each configured sink. Audit events logged afterwards are delivered to every sink. Webhook
+/// sinks send through `outbound`.
+pub fn start(
+    config: &AuditConfig,
+    service: &str,
+    outbound: &OutboundClient,
+) -> Result<Vec<JoinHandle<()>>, AuditError> {
+    let mut handles = Vec::with_capacity(config.sinks.len());
+    let mut sinks = SINKS.lock().expect("audit sinks poisoned");
+
+    for (index, sink_config) in config.sinks.iter().enumerate() {
+        let sink = sink_config.sink(outbound)?;
+        let queue = match &config.queue_directory {
+            None => AuditQueue::memory(config.max_queue_bytes),
+            Some(directory) => AuditQueue::disk(
+                directory.join(format!("{service}-{index}-{}", sink_config.kind())),
+                config.max_queue_bytes,
+            )?,
+        };
+
+        let (sender, receiver) = mpsc::channel(MAX_BATCH * 10);
+        let dropped = Arc::new(AtomicU64::new(0));
+        handles.push(tokio::spawn(run_sink(sink, receiver, queue, dropped.clone())));
+        sinks.push(SinkHandle {
+            service: service.to_string(),
+            sender,
+            dropped,
+        });
+    }
+
+    Ok(handles)
+}
+
+/// Send an event to every running sink. This never blocks; if a sink's worker is behind, the event is dropped for
+/// that sink and counted.
+fn dispatch(level: Level, message: String) {
+    let sinks = match SINKS.try_lock() {
+        Ok(sinks) => sinks,
+        Err(_) => return,
+    };
+
+    for sink in sinks.iter() {
+        match sink.sender.try_send(AuditEvent::new(&sink.service, level, message.clone())) {
+            Ok(()) | Err(TrySendError::Closed(_)) => (),
+            Err(TrySendError::Full(_)) => {
+                sink.dropped.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    }
+}
+
+/// Deliver events from `receiver` to `sink`, queueing them until the sink accepts them.
+async fn run_sink(
+    sink: Box<dyn AuditSink>,
+    mut receiver: Receiver<AuditEvent>,
+    mut queue: AuditQueue,
+    dropped: Arc<AtomicU64>,
+) {
+    let mut backoff = MIN_BACKOFF;
+    let mut retry_at = Instant::now();
+    let mut closed = false;
+
+    loop {
+        // Wait for new events; while events are queued, wake up in time to retry them.
+        let first = if closed {
+            sleep_until(retry_at).await;
+            None
+        } else if queue.is_empty() {
+            let event = receiver.recv().await;
+            closed = event.is_none();
+            event
+        } else {
+            match timeout_at(retry_at, receiver.recv()).await {
+                Ok(event) => {
+                    closed = event.is_none();
+                    event
+                }
+                // Time to retry the queued events.
+                Err(_) => None,
+            }
+        };
+
+        let mut batch: Vec<AuditEvent> = first.into_iter().collect();
+        while batch.len() < MAX_BATCH {
+            match receiver.try_recv() {
+                Ok(event) => batch.push(event),
+                Err(_) => break,
+            }
+        }
+
+        if closed && batch.is_empty() && queue.is_empty() {
+            return;
+        }
+
+        if !batch.is_empty() {
+            match queue.push(&batch) {
+                Ok(true) => (),
+                Ok(false) => {
+                    dropped.fetch_add(batch.len() as u64, Ordering::Relaxed);
+                }
+                Err(e) => error!("Unable to queue {} audit events for the {} sink: {}", batch.len(), sink.name(), e),
+            }
+        }
+
+        let n_dropped = dropped.swap(0, Ordering::Relaxed);
+        if n_dropped > 0 {
+            error!("Dropped {} audit events for the {} sink because its queue is full", n_dropped, sink.name());
+        }
+
+        if Instant::now() < retry_at {
+            continue;
+        }
+
+        loop {
+            let (id, events) = match queue.peek() {
+                Ok(Some(entry)) => entry,
+                Ok(None) => break,
+                Err(e) => {
+                    error!("Unable to read queued audit events for the {} sink: {}", sink.name(), e);
+                    break;
+                }
+            };
+
+            match sink.deliver(&events).await {
+                Ok(()) => {
+                    if let Err(e) = queue.remove(id) {
+                        error!("Unable to remove delivered audit events for the {} sink: {}", sink.name(), e);
+                    }
+                    backoff = MIN_BACKOFF;
+                }
+                Err(e) => {
+                    warn!(
+                        "Unable to deliver audit events to the {} sink; retrying in {:?}: {}",
+                        sink.name(),
+                        backoff,
+                        e
+                    );
+                    retry_at = Instant::now() + backoff;
+                    backoff = (backoff * 2).min(MAX_BACKOFF);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AuditError {
+    Http(hyper::Error),
+    IO(IOError),
+
+    /// The sink answered with a status other than 2xx.
+    Status(http::StatusCode),
+    Serialization(serde_json::Error),
+
+    /// The sink did not answer in time.
+    Timeout,
+
+    /// The sink rejected the events for another reason.
+    Sink(String),
+
+    /// The sink is not available in this build.
+    Unsupported(String),
+}
+
+impl Error for AuditError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Http(e) => Some(e),
+            Self::IO(e) => Some(e),
+            Self::Serialization(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for AuditError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Http(e) => write!(f, "HTTP error: {e}"),
+            Self::IO(e) => write!(f, "IO error: {e}"),
+            Self::Status(status) => write!(f, "Sink returned {status}"),
+            Self::Serialization(e) => write!(f, "Serialization error: {e}"),
+            Self::Timeout => f.write_str("Sink timed out"),
+            Self::Sink(message) => write!(f, "Sink error: {message}"),
+            Self::Unsupported(message) => write!(f, "Unsupported audit sink: {message}"),
+        }
+    }
+}
+
+impl From<hyper::Error> for AuditError {
+    fn from(e: hyper::Error) -> Self {
+        Self::Http(e)
+    }
+}
+
+impl From<OutboundError> for AuditError {
+    fn from(e: OutboundError) -> Self {
+        match e {
+            OutboundError::Http(e) => Self::Http(e),
+            OutboundError::IO(e) => Self::IO(e),
+            OutboundError::Timeout => Self::Timeout,
+            e => Self::Sink(e.to_string()),
+        }
+    }
+}
+
+impl From<IOError> for AuditError {
+    fn from(e: IOError) -> Self {
+        Self::IO(e)
+    }
+}
+
+impl From<serde_json::Error> for AuditError {
+    fn from(e: serde_json::Error) -> Self {
+        Self::Serialization(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{run_sink, AuditError, AuditEvent, AuditQueue, AuditSink},
+        async_trait::async_trait,
+        log::Level,
+        pretty_assertions::assert_eq,
+        std::{
+            env, process,
+            sync::{
+                atomic::{AtomicU64, Ordering},
+                Arc, Mutex,
+            },
+        },
+        tokio::sync::mpsc,
+    };
+
+    /// A sink that fails a number of times before accepting events.
+    struct FlakySink {
+        failures: AtomicU64,
+        delivered: Arc<Mutex<Vec<AuditEvent>>>,
+    }
+
+    #[async_trait]
+    impl AuditSink for FlakySink {
+        fn name(&self) -> &str {
+            "flaky"
+        }
+
+        async fn deliver(&self, events: &[AuditEvent]) -> Result<(), AuditError> {
+            if self.failures.load(Ordering::Relaxed) > 0 {
+                self.failures.fetch_sub(1, Ordering::Relaxed);
+                return Err(AuditError::Timeout);
+            }
+
+            self.delivered.lock().unwrap().extend_from_slice(events);
+            Ok(())
+        }
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn test_delivery_survives_outage() {
+        let directory = env::temp_dir().join(format!("scratchstack-audit-{}", process::id()));
+        let delivered = Arc::new(Mutex::new(Vec::new()));
+        let sink = FlakySink {
+            failures: AtomicU64::new(2),
+            delivered: delivered.clone(),
+        };
+
+        // Events left in the queue by an earlier process are delivered too.
+        let earlier = AuditEvent::new("iam", Level::Warn, "earlier".to_string());
+        AuditQueue::disk(directory.clone(), 1 << 20).unwrap().push(&[earlier.clone()]).unwrap();
+
+        let queue = AuditQueue::disk(directory.clone(), 1 << 20).unwrap();
+        let (sender, receiver) = mpsc::channel(10);
+        let worker = tokio::spawn(run_sink(Box::new(sink), receiver, queue, Arc::new(AtomicU64::new(0))));
+
+        let later = AuditEvent::new("iam", Level::Warn, "later".to_string());
+        sender.send(later.clone()).await.unwrap();
+        drop(sender);
+        worker.await.unwrap();
+
+        assert_eq!(*delivered.lock().unwrap(), vec![earlier, later]);
+        assert!(AuditQueue::disk(directory.clone(), 1 << 20).unwrap().is_empty());
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
@@ -0,0 +1,140 @@
+use {
+    super::{AuditError, AuditEvent},
+    log::warn,
+    std::{
+        collections::VecDeque,
+        fs,
+        io::Error as IOError,
+        path::{Path, PathBuf},
+    },
+};
+
+/// Undelivered audit events for one sink, as batches in the order they were added.
+///
+/// A disk queue keeps each batch in its own file of JSON lines, named by its sequence number, so batches left by a
+/// previous process are delivered after a restart.
+#[derive(Debug)]
+pub struct AuditQueue {
+    directory: Option<PathBuf>,
+    max_bytes: u64,
+    bytes: u64,
+    next_id: u64,
+
+    /// Batch ids and sizes, with the contents for a memory queue.
+    batches: VecDeque<(u64, u64, Option<Vec<u8>>)>,
+}
+
+impl AuditQueue {
+    pub fn memory(max_bytes: u64) -> Self {
+        Self {
+            directory: None,
+            max_bytes,
+            bytes: 0,
+            next_id: 0,
+            batches: VecDeque::new(),
+        }
+    }
+
+    /// Open a queue in `directory`, creating it if needed and picking up batches already there.
+    pub fn disk(directory: PathBuf, max_bytes: u64) -> Result<Self, IOError> {
+        fs::create_dir_all(&directory)?;
+
+        let mut batches = Vec::new();
+        for entry in fs::read_dir(&directory)? {
+            let entry = entry?;
+            let name = entry.file_name();
+            let id = match name.to_str().and_then(|name| name.strip_suffix(".jsonl")).and_then(|id| id.parse().ok()) {
+                Some(id) => id,
+                None => continue,
+            };
+            batches.push((id, entry.metadata()?.len(), None));
+        }
+        batches.sort_by_key(|(id, _, _)| *id);
+
+        Ok(Self {
+            max_bytes,
+            bytes: batches.iter().map(|(_, size, _)| size).sum(),
+            next_id: batches.last().map(|(id, _, _)| id + 1).unwrap_or(0),
+            batches: batches.into(),
+            directory: Some(directory),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.batches.is_empty()
+    }
+
+    /// Add a batch of events. This returns `false`, leaving the queue unchanged, if the batch does not fit.
+    pub fn push(&mut self, events: &[AuditEvent]) -> Result<bool, AuditError> {
+        let mut data = Vec::new();
+        for event in events {
+            serde_json::to_writer(&mut data, event)?;
+            data.push(b'\n');
+        }
+
+        let size = data.len() as u64;
+        if self.bytes + size > self.max_bytes {
+            return Ok(false);
+        }
+
+        let id = self.next_id;
+        let contents = match &self.directory {
+            None => Some(data),
+            Some(directory) => {
+                // Write under a temporary name first so a crash never leaves a partial batch.
+                let temp = directory.join(format!("{id:020}.tmp"));
+                fs::write(&temp, &data)?;
+                fs::rename(&temp, batch_path(directory, id))?;
+                None
+            }
+        };
+
+        self.next_id += 1;
+        self.bytes += size;
+        self.batches.push_back((id, size, contents));
+        Ok(true)
+    }
+
+    /// The oldest batch and its id. Lines that cannot be parsed are skipped.
+    pub fn peek(&self) -> Result<Option<(u64, Vec<AuditEvent>)>, AuditError> {
+        let (id, _, contents) = match self.batches.front() {
+            None => return Ok(None),
+            Some(batch) => batch,
+        };
+
+        let data = match (contents, &self.directory) {
+            (Some(contents), _) => contents.clone(),
+            (None, Some(directory)) => fs::read(batch_path(directory, *id))?,
+            (None, None) => Vec::new(),
+        };
+
+        let mut events = Vec::new();
+        for line in data.split(|b| *b == b'\n').filter(|line| !line.is_empty()) {
+            match serde_json::from_slice(line) {
+                Ok(event) => events.push(event),
+                Err(e) => warn!("Skipping unreadable audit event in batch {}: {}", id, e),
+            }
+        }
+
+        Ok(Some((*id, events)))
+    }
+
+    /// Remove a delivered batch.
+    pub fn remove(&mut self, id: u64) -> Result<(), IOError> {
+        if let Some(position) = self.batches.iter().position(|(batch_id, _, _)| *batch_id == id) {
+            if let Some((_, size, _)) = self.batches.remove(position) {
+                self.bytes -= size;
+            }
+
+            if let Some(directory) = &self.directory {
+                fs::remove_file(batch_path(directory, id))?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn batch_path(directory: &Path, id: u64) -> PathBuf {
+    directory.join(format!("{id:020}.jsonl"))
+}
@@ -0,0 +1,250 @@
+use {
+    super::{AuditError, AuditEvent},
+    crate::outbound::{OutboundClient, RetryPolicy},
+    async_trait::async_trait,
+    http::{header::CONTENT_TYPE, Method, Request},
+    hyper::body::Bytes,
+    ring::hmac,
+    std::{
+        env,
+        fmt::{Debug, Formatter, Result as FmtResult},
+        fs::OpenOptions,
+        io::Write,
+        net::{SocketAddr, ToSocketAddrs},
+        path::PathBuf,
+        time::Duration,
+    },
+    tokio::net::UdpSocket,
+};
+
+/// The header carrying the webhook signature: `sha256=<hex HMAC of "<timestamp>.<body>">`.
+pub const WEBHOOK_SIGNATURE_HEADER: &str = "X-Scratchstack-Signature";
+
+/// The header carrying the time the webhook request was signed, in seconds since the Unix epoch.
+pub const WEBHOOK_TIMESTAMP_HEADER: &str = "X-Scratchstack-Timestamp";
+
+/// A destination for audit events.
+#[async_trait]
+pub trait AuditSink: Send + Sync {
+    fn name(&self) -> &str;
+
+    /// Deliver a batch of events. On error, the whole batch is retried later.
+    async fn deliver(&self, events: &[AuditEvent]) -> Result<(), AuditError>;
+}
+
+/// Appends events to a file as JSON lines.
+#[derive(Debug)]
+pub struct FileSink {
+    path: PathBuf,
+}
+
+impl FileSink {
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+        }
+    }
+}
+
+#[async_trait]
+impl AuditSink for FileSink {
+    fn name(&self) -> &str {
+        "file"
+    }
+
+    async fn deliver(&self, events: &[AuditEvent]) -> Result<(), AuditError> {
+        let mut data = Vec::new();
+        for event in events {
+            serde_json::to_writer(&mut data, event)?;
+            data.push(b'\n');
+        }
+
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        file.write_all(&data)?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Sends events to a syslog server over UDP, one RFC 5424 message per event.
+#[derive(Debug)]
+pub struct SyslogSink {
+    address: SocketAddr,
+    facility: u8,
+    hostname: String,
+}
+
+impl SyslogSink {
+    pub fn new(address: &str, facility: u8) -> Result<Self, AuditError> {
+        let address = address
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| AuditError::Sink(format!("syslog address {address} did not resolve")))?;
+        if facility > 23 {
+            return Err(AuditError::Sink(format!("invalid syslog facility {facility}")));
+        }
+
+        Ok(Self {
+            address,
+            facility,
+            hostname: env::var("HOSTNAME").unwrap_or_else(|_| "-".to_string()),
+        })
+    }
+
+    /// Format an event as an RFC 5424 message.
+    fn message(&self, event: &AuditEvent) -> String {
+        let severity = match event.level.as_str() {
+            "ERROR" => 3,
+            "WARN" => 4,
+            "INFO" => 6,
+            _ => 7,
+        };
+        let priority = u16::from(self.facility) * 8 + severity;
+        format!(
+            "<{priority}>1 {} {} scratchstack-{} {} - - {}",
+            event.timestamp,
+            self.hostname,
+            event.service,
+            std::process::id(),
+            event.message
+        )
+    }
+}
+
+#[async_trait]
+impl AuditSink for SyslogSink {
+    fn name(&self) -> &str {
+        "syslog"
+    }
+
+    async fn deliver(&self, events: &[AuditEvent]) -> Result<(), AuditError> {
+        let bind: SocketAddr = if self.address.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+        let socket = UdpSocket::bind(bind).await?;
+        for event in events {
+            socket.send_to(self.message(event).as_bytes(), self.address).await?;
+        }
+        Ok(())
+    }
+}
+
+/// POSTs batches of events to an HTTP or HTTPS endpoint as a JSON array, through the service's [OutboundClient].
+///
+/// If a secret is configured, each request carries [WEBHOOK_TIMESTAMP_HEADER] and [WEBHOOK_SIGNATURE_HEADER] so
+/// the receiver can check that it came from Scratchstack and is recent.
+pub struct WebhookSink {
+    url: String,
+    secret: Option<hmac::Key>,
+    timeout: Duration,
+    client: OutboundClient,
+}
+
+impl WebhookSink {
+    /// Requests are sent once each, with `timeout`; the audit queue retries failed batches itself.
+    pub fn new(
+        url: &str,
+        secret: Option<Vec<u8>>,
+        timeout: Duration,
+        client: &OutboundClient,
+    ) -> Result<Self, AuditError> {
+        Ok(Self {
+            url: url.to_string(),
+            secret: secret.map(|secret| hmac::Key::new(hmac::HMAC_SHA256, &secret)),
+            timeout,
+            client: client.clone().with_timeout(timeout).with_retry(RetryPolicy::NONE),
+        })
+    }
+}
+
+impl Debug for WebhookSink {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("WebhookSink")
+            .field("url", &self.url)
+            .field("signed", &self.secret.is_some())
+            .field("timeout", &self.timeout)
+            .finish()
+    }
+}
+
+/// The webhook signature for a request body sent at `timestamp`.
+pub fn webhook_signature(key: &hmac::Key, timestamp: i64, body: &[u8]) -> String {
+    let mut context = hmac::Context::with_key(key);
+    context.update(timestamp.to_string().as_bytes());
+    context.update(b".");
+    context.update(body);
+    let signature: String = context.sign().as_ref().iter().map(|b| format!("{b:02x}")).collect();
+    format!("sha256={signature}")
+}
+
+#[async_trait]
+impl AuditSink for WebhookSink {
+    fn name(&self) -> &str {
+        "webhook"
+    }
+
+    async fn deliver(&self, events: &[AuditEvent]) -> Result<(), AuditError> {
+        let body = serde_json::to_vec(events)?;
+        let mut builder =
+            Request::builder().method(Method::POST).uri(&self.url).header(CONTENT_TYPE, "application/json");
+        if let Some(key) = &self.secret {
+            let timestamp = chrono::Utc::now().timestamp();
+            builder = builder
+                .header(WEBHOOK_TIMESTAMP_HEADER, timestamp.to_string())
+                .header(WEBHOOK_SIGNATURE_HEADER, webhook_signature(key, timestamp, &body));
+        }
+        let request = builder.body(Bytes::from(body)).map_err(|e| AuditError::Sink(e.to_string()))?;
+
+        let response = self.client.send(request).await?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(AuditError::Status(response.status()))
+        }
+    }
+}
+
+/// Produces events to a Kafka topic as JSON messages.
+#[cfg(feature = "kafka")]
+pub struct KafkaSink {
+    topic: String,
+    producer: rdkafka::producer::FutureProducer,
+}
+
+#[cfg(feature = "kafka")]
+impl KafkaSink {
+    pub fn new(brokers: &str, topic: &str) -> Result<Self, AuditError> {
+        let producer = rdkafka::ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .set("message.timeout.ms", "5000")
+            .create()
+            .map_err(|e| AuditError::Sink(e.to_string()))?;
+
+        Ok(Self {
+            topic: topic.to_string(),
+            producer,
+        })
+    }
+}
+
+#[cfg(feature = "kafka")]
+#[async_trait]
+impl AuditSink for KafkaSink {
+    fn name(&self) -> &str {
+        "kafka"
+    }
+
+    async fn deliver(&self, events: &[AuditEvent]) -> Result<(), AuditError> {
+        for event in events {
+            let payload = serde_json::to_vec(event)?;
+            let record = rdkafka::producer::FutureRecord::<(), _>::to(&self.topic).payload(&payload);
+            self.producer
+                .send(record, Duration::from_secs(5))
+                .await
+                .map_err(|(e, _)| AuditError::Sink(e.to_string()))?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,698 @@
+//! Enforcement of identity policies on the request path.
+//!
+//! [AuthorizeRequests] wraps a service implementation after the signature and session token have been verified. It
+//! reads the request parameters, resolves the caller's effective policies (inline and attached, including those of
+//! a user's groups) with a [PolicyResolver], and evaluates them against the action and the resource chosen by the
+//! service's resource function. Denied requests get an `AccessDenied` error; allowed ones are passed on with the body
+//! unchanged. The parameters are read as the services read them, so a request cannot name one action here and
+//! another to the service.
+//!
+//! The caller is mapped to a policy holder by its ARN: `iam` user ARNs to the user, and `sts` assumed-role ARNs to
+//! the role, which must still have the role id the session was issued for. Other callers, and users or roles that
+//! no longer exist, have no policies, so only [DefaultDecision::Allow] lets them through. Root callers bypass
+//! evaluation; see [crate::authz::decide]. Role sessions with [session policies][crate::session_policy] are also
+//! limited by those.
+//! Service-specific condition keys that are plain request parameters, such as `iam:PolicyARN`, are read with
+//! [crate::actions::ActionDefinition::condition_keys_from_parameters]; the rest are supplied by handlers and are not
+//! available here.
+use {
+    crate::{
+        actions,
+        authz::{decide, AuthorizationMode, DefaultDecision},
+        context::RequestContext,
+        effective::{EffectivePolicy, PolicyResolver},
+        engine::{EvaluationRequest, PolicyEvaluator},
+        flags::FeatureFlags,
+        parameters::{is_query_only, request_parameters},
+        protocol::{AwsError, ErrorProtocol},
+        region::RegionRegistry,
+        session_keys::AWS_USERID,
+        session_policy::{session_decision, session_policies},
+        store::{ControlPlaneStore, PolicyHolder, StoreError},
+    },
+    http::StatusCode,
+    hyper::{body::to_bytes, service::Service, Body, Request, Response},
+    log::warn,
+    scratchstack_arn::Arn,
+    scratchstack_aws_signature::canonical::get_content_type_and_charset,
+    std::{
+        collections::HashMap,
+        future::Future,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+    },
+    tower::BoxError,
+};
+
+/// Content-Type string for HTML forms
+const APPLICATION_X_WWW_FORM_URLENCODED: &str = "application/x-www-form-urlencoded";
+
+/// Chooses the resource a request acts on from its context.
+pub type ResourceFn = fn(&RequestContext) -> RequestResource;
+
+/// The resource a request acts on, as chosen by a service's [ResourceFn].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RequestResource {
+    /// Any resource, `*`.
+    Any,
+
+    /// A resource ARN, used as is.
+    Arn(String),
+
+    /// A user, group or role in the caller's account, by name. Its ARN includes its path, which is looked up; an
+    /// entity that does not exist is given the root path.
+    Entity(PolicyHolder, String),
+
+    /// The caller itself, for actions that act on the calling user when no user is named.
+    Caller,
+}
+
+/// Wraps a service implementation, denying requests that the caller's identity policies do not allow.
+///
+/// Requests without a principal (the anonymous actions of [crate::anonymous]) are passed on for the service to
+/// handle. Requests with a principal are evaluated or denied: those whose parameters cannot be read, or whose
+/// `Action` is missing or not in the [action registry][crate::actions], get `AccessDenied`. Actions in the exempt
+/// list, such as STS `GetCallerIdentity`, are never evaluated.
+#[derive(Clone)]
+pub struct AuthorizeRequests<S> {
+    inner: S,
+    authorizer: Authorizer,
+}
+
+#[derive(Clone)]
+struct Authorizer {
+    store: Arc<dyn ControlPlaneStore>,
+    resolver: PolicyResolver,
+    service_name: &'static str,
+    resource: ResourceFn,
+    exempt_actions: &'static [&'static str],
+    mode: AuthorizationMode,
+    flags: Option<Arc<FeatureFlags>>,
+    default_decision: DefaultDecision,
+    protocol: ErrorProtocol,
+    regions: Option<Arc<RegionRegistry>>,
+}
+
+impl<S> AuthorizeRequests<S> {
+    /// Authorize requests for the actions of `service_name` (e.g. `iam`) against the policies in `store`. Every action
+    /// is evaluated against `*` until [AuthorizeRequests::with_resource] is used.
+    pub fn new(
+        inner: S,
+        store: Arc<dyn ControlPlaneStore>,
+        service_name: &'static str,
+        protocol: ErrorProtocol,
+    ) -> Self {
+        Self {
+            inner,
+            authorizer: Authorizer {
+                resolver: PolicyResolver::new(store.clone()),
+                store,
+                service_name,
+                resource: |_| RequestResource::Any,
+                exempt_actions: &[],
+                mode: AuthorizationMode::default(),
+                flags: None,
+                default_decision: DefaultDecision::default(),
+                protocol,
+                regions: None,
+            },
+        }
+    }
+
+    pub fn with_resource(mut self, resource: ResourceFn) -> Self {
+        self.authorizer.resource = resource;
+        self
+    }
+
+    /// Pass requests for `exempt_actions` through without evaluating policies.
+    pub fn with_exempt_actions(mut self, exempt_actions: &'static [&'static str]) -> Self {
+        self.authorizer.exempt_actions = exempt_actions;
+        self
+    }
+
+    /// Log denials instead of returning them in [AuthorizationMode::Permissive].
+    pub fn with_mode(mut self, mode: AuthorizationMode) -> Self {
+        self.authorizer.mode = mode;
+        self
+    }
+
+    /// Let the [ENFORCE_AUTHORIZATION][crate::flags::ENFORCE_AUTHORIZATION] flag in `flags` override the mode for
+    /// the caller's account.
+    pub fn with_feature_flags(mut self, flags: Arc<FeatureFlags>) -> Self {
+        self.authorizer.flags = Some(flags);
+        self
+    }
+
+    pub fn with_default_decision(mut self, default_decision: DefaultDecision) -> Self {
+        self.authorizer.default_decision = default_decision;
+        self
+    }
+
+    /// Add `regions` to requests, so their [RequestContext] only reports a region the service accepts, both here
+    /// and in the wrapped service.
+    pub fn with_regions(mut self, regions: Arc<RegionRegistry>) -> Self {
+        self.authorizer.regions = Some(regions);
+        self
+    }
+}
+
+impl<S> Service<Request<Body>> for AuthorizeRequests<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = BoxError> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let authorizer = self.authorizer.clone();
+        if let Some(regions) = &authorizer.regions {
+            req.extensions_mut().insert(regions.clone());
+        }
+        Box::pin(async move {
+            let (req, context) = request_context(req).await?;
+            let mut context = match context {
+                Some(context) => context,
+                None => return inner.call(req).await,
+            };
+
+            // Anything that cannot be evaluated is denied, so the authorizer fails closed.
+            let definition = match context.parameter("Action") {
+                Some(action) if authorizer.exempt_actions.contains(&action) => return inner.call(req).await,
+                Some(action) => match actions::lookup(authorizer.service_name, action) {
+                    Some(definition) => definition,
+                    None => {
+                        let action = format!("{}:{action}", authorizer.service_name);
+                        return authorizer.access_denied(&context, &action, "*");
+                    }
+                },
+                None => return authorizer.access_denied(&context, "an unknown action", "*"),
+            };
+            let action = definition.name();
+            let condition_keys = definition.condition_keys_from_parameters(|name| context.parameter(name));
+            context.add_condition_keys(condition_keys);
+
+            let result = match authorizer.resource(&context).await {
+                Ok(resource) => {
+                    authorizer.authorize(&context, &action, &resource).await.map(|allowed| (allowed, resource))
+                }
+                Err(e) => Err(e.into()),
+            };
+            match result {
+                Ok((true, _)) => inner.call(req).await,
+                Ok((false, resource)) => authorizer.access_denied(&context, &action, &resource),
+                Err(e) => {
+                    warn!("{} Unable to authorize {}: {}", context.request_id(), action, e);
+                    let error = AwsError::receiver(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "ServiceFailure",
+                        "The request processing has failed because of an unknown error, exception or failure.",
+                    );
+                    authorizer.protocol.response(&error, context.request_id())
+                }
+            }
+        })
+    }
+}
+
+impl Authorizer {
+    /// The `AccessDenied` error for the caller of `context` performing `action` on `resource`.
+    fn access_denied(
+        &self,
+        context: &RequestContext,
+        action: &str,
+        resource: &str,
+    ) -> Result<Response<Body>, BoxError> {
+        let caller = context.caller_arn().map(|arn| arn.to_string()).unwrap_or_else(|| "anonymous".to_string());
+        let error = AwsError::sender(
+            StatusCode::FORBIDDEN,
+            "AccessDenied",
+            format!("User: {caller} is not authorized to perform: {action} on resource: {resource}"),
+        );
+        self.protocol.response(&error, context.request_id())
+    }
+
+    /// The ARN of the resource the request acts on, or `*`.
+    async fn resource(&self, context: &RequestContext) -> Result<String, StoreError> {
+        let (holder, name) = match (self.resource)(context) {
+            RequestResource::Any => return Ok("*".to_string()),
+            RequestResource::Arn(arn) => return Ok(arn),
+            RequestResource::Caller => {
+                return Ok(context.caller_arn().map(|arn| arn.to_string()).unwrap_or_else(|| "*".to_string()))
+            }
+            RequestResource::Entity(holder, name) => (holder, name),
+        };
+
+        // Names match case-insensitively, so the stored spelling is used.
+        let account_id = context.account_id().unwrap_or_default();
+        let (kind, entity) = match holder {
+            PolicyHolder::User => {
+                ("user", self.store.get_user(&account_id, &name).await.map(|user| (user.path, user.user_name)))
+            }
+            PolicyHolder::Group => {
+                ("group", self.store.get_group(&account_id, &name).await.map(|group| (group.path, group.group_name)))
+            }
+            PolicyHolder::Role => {
+                ("role", self.store.get_role(&account_id, &name).await.map(|role| (role.path, role.role_name)))
+            }
+        };
+        let (path, name) = match entity {
+            Ok(entity) => entity,
+            Err(StoreError::NoSuchEntity {
+                ..
+            }) => ("/".to_string(), name),
+            Err(e) => return Err(e),
+        };
+        Ok(format!("arn:{}:iam::{account_id}:{kind}{path}{name}", context.partition()))
+    }
+
+    /// Returns whether the request may proceed, in the mode configured for the caller's account.
+    async fn authorize(&self, context: &RequestContext, action: &str, resource: &str) -> Result<bool, BoxError> {
+        let (policies, session) = if context.is_root() {
+            (Vec::new(), None)
+        } else {
+            (
+                self.caller_policies(context).await?,
+                session_policies(self.store.as_ref(), context.packed_claims()).await?,
+            )
+        };
+        let evaluator = PolicyEvaluator::from_effective(&policies)?;
+        let session_evaluator = session.as_deref().map(PolicyEvaluator::from_effective).transpose()?;
+        let decision = decide(context, self.default_decision, || {
+            let request = EvaluationRequest {
+                principal: context.principal(),
+                action,
+                resource,
+                context: context.session_data(),
+            };
+            let decision = evaluator.evaluate(&request);
+            match &session_evaluator {
+                Some(session_evaluator) => session_decision(decision, session_evaluator.evaluate(&request)),
+                None => decision,
+            }
+        });
+
+        let mode = match &self.flags {
+            Some(flags) => flags.authorization_mode(context.account_id().as_deref(), self.mode),
+            None => self.mode,
+        };
+        Ok(mode.permits(context, action, resource, &decision))
+    }
+
+    /// The effective policies of the caller, or none if it is not a user or role that exists.
+    async fn caller_policies(&self, context: &RequestContext) -> Result<Vec<EffectivePolicy>, StoreError> {
+        let arn = match context.caller_arn() {
+            Some(arn) => arn,
+            None => return Ok(Vec::new()),
+        };
+
+        let holder = match self.caller_holder(context, &arn).await {
+            Ok(Some(holder)) => holder,
+            Ok(None)
+            | Err(StoreError::NoSuchEntity {
+                ..
+            }) => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+
+        self.resolver.resolve(arn.account_id(), holder.0, &holder.1).await
+    }
+
+    /// The policy holder and id of the caller with ARN `arn`.
+    async fn caller_holder(
+        &self,
+        context: &RequestContext,
+        arn: &Arn,
+    ) -> Result<Option<(PolicyHolder, String)>, StoreError> {
+        match arn.service() {
+            // user/<path>/<name>
+            "iam" => match arn.resource().strip_prefix("user/").and_then(|user| user.rsplit('/').next()) {
+                Some(user_name) => {
+                    let user = self.store.get_user(arn.account_id(), user_name).await?;
+                    Ok(Some((PolicyHolder::User, user.user_id)))
+                }
+                None => Ok(None),
+            },
+            // assumed-role/<name>/<session>
+            "sts" => {
+                let role_name =
+                    match arn.resource().strip_prefix("assumed-role/").and_then(|role| role.split('/').next()) {
+                        Some(role_name) => role_name,
+                        None => return Ok(None),
+                    };
+
+                // The session is bound to the role it was issued for, identified by the role id in aws:userid
+                // (<role id>:<session name>). A role deleted and recreated under the same name has a new id, and its
+                // policies do not apply to sessions of the old role.
+                let role_id = match context.user_id().and_then(|user_id| user_id.split_once(':')) {
+                    Some((role_id, _)) => role_id,
+                    None => {
+                        warn!("Session of role {} has no role id in {}", role_name, AWS_USERID);
+                        return Ok(None);
+                    }
+                };
+                let role = self.store.get_role_by_id(arn.account_id(), role_id).await?;
+                if !role.role_name.eq_ignore_ascii_case(role_name) {
+                    warn!("Session of role {} was issued for role {} ({})", role_name, role.role_name, role_id);
+                    return Ok(None);
+                }
+                Ok(Some((PolicyHolder::Role, role.role_id)))
+            }
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Read the parameters of `req` as the services do, returning the request with its body restored and its context,
+/// if it has a principal. The context has no parameters if they cannot be read.
+async fn request_context(req: Request<Body>) -> Result<(Request<Body>, Option<RequestContext>), BoxError> {
+    let (parts, body) = req.into_parts();
+    let is_form = get_content_type_and_charset(&parts.headers)
+        .map(|ctc| ctc.content_type == APPLICATION_X_WWW_FORM_URLENCODED)
+        .unwrap_or(false);
+
+    // GET and HEAD bodies are read whatever their content type; other bodies only if they are forms.
+    let (body, form_body) = if is_form || is_query_only(&parts.method) {
+        let bytes = to_bytes(body).await?;
+        (Body::from(bytes.clone()), Some(bytes))
+    } else {
+        (body, None)
+    };
+
+    let parameters: HashMap<String, String> =
+        request_parameters(&parts.method, parts.uri.query().unwrap_or_default(), form_body.as_deref())
+            .unwrap_or_default();
+    let context = RequestContext::from_parts(&parts, parameters);
+    Ok((Request::from_parts(parts, body), context))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{AuthorizeRequests, RequestResource},
+        crate::{
+            authz::AuthorizationMode,
+            context::RequestContext,
+            flags::{FeatureFlagConfig, FeatureFlags, ENFORCE_AUTHORIZATION},
+            protocol::IAM,
+            region::RegionRegistry,
+            session_keys::{AWS_REQUESTED_REGION, AWS_USERID},
+            store::{ControlPlaneStore, InlinePolicy, MemoryStore, PolicyHolder, Role, User},
+            token::PackedClaims,
+        },
+        chrono::Utc,
+        hyper::{
+            body::to_bytes,
+            service::{service_fn, Service},
+            Body, Request, Response, StatusCode,
+        },
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{self as principal, Principal, PrincipalIdentity, SessionData, SessionValue},
+        std::{collections::HashMap, sync::Arc},
+        tower::BoxError,
+    };
+
+    const POLICY: &str = r#"{"Statement": [
+        {"Effect": "Allow", "Action": ["iam:Get*", "iam:AttachUserPolicy"], "Resource": "*"},
+        {"Effect": "Deny", "Action": "iam:AttachUserPolicy", "Resource": "*",
+         "Condition": {"StringEquals": {"iam:PolicyARN": "arn:aws:iam::aws:policy/AdministratorAccess"}}},
+        {"Effect": "Allow", "Action": "iam:DeleteUser", "Resource": "arn:aws:iam::123456789012:user/staff/*"}
+    ]}"#;
+
+    fn with_alice(mut req: Request<Body>) -> Request<Body> {
+        let alice = principal::User::new("aws", "123456789012", "/", "alice").unwrap();
+        req.extensions_mut().insert(Principal::from(vec![PrincipalIdentity::from(alice)]));
+        req
+    }
+
+    fn request(body: &str) -> Request<Body> {
+        with_alice(
+            Request::post("/")
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .body(Body::from(body.to_string()))
+                .unwrap(),
+        )
+    }
+
+    async fn store() -> Arc<MemoryStore> {
+        let store = Arc::new(MemoryStore::new());
+        for (user_id, user_name, path) in [("AIDAEXAMPLEALICE", "alice", "/"), ("AIDAEXAMPLECAROL", "carol", "/staff/")]
+        {
+            let user = User {
+                user_id: user_id.to_string(),
+                account_id: "123456789012".to_string(),
+                user_name: user_name.to_string(),
+                path: path.to_string(),
+                permissions_boundary: None,
+                created_at: Utc::now(),
+            };
+            store.create_user(&user, &[]).await.unwrap();
+        }
+        let policy = InlinePolicy {
+            policy_name: "Staff".to_string(),
+            policy_document: POLICY.to_string(),
+        };
+        store.put_inline_policy(PolicyHolder::User, "AIDAEXAMPLEALICE", &policy).await.unwrap();
+        store
+    }
+
+    fn service(
+        store: Arc<MemoryStore>,
+    ) -> AuthorizeRequests<
+        impl Service<Request<Body>, Response = Response<Body>, Error = BoxError, Future = impl Send> + Clone + Send,
+    > {
+        let inner = service_fn(|req: Request<Body>| async { Ok::<_, BoxError>(Response::new(req.into_body())) });
+        AuthorizeRequests::new(inner, store, "iam", IAM).with_resource(|context| match context.parameter("UserName") {
+            Some(user_name) => RequestResource::Entity(PolicyHolder::User, user_name.to_string()),
+            None => RequestResource::Caller,
+        })
+    }
+
+    async fn body(response: Response<Body>) -> String {
+        String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap()
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_authorize_requests() {
+        let mut service = service(store().await);
+
+        let response = service.call(request("Action=GetUser&UserName=bob")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(response).await, "Action=GetUser&UserName=bob");
+
+        let response = service.call(request("Action=DeleteUser&UserName=bob")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        let body = body(response).await;
+        assert!(body.contains("<Code>AccessDenied</Code>"), "{body}");
+        assert!(
+            body.contains(
+                "arn:aws:iam::123456789012:user/alice is not authorized to perform: iam:DeleteUser on resource: \
+                 arn:aws:iam::123456789012:user/bob"
+            ),
+            "{body}"
+        );
+
+        // The resource is the user's ARN with its path.
+        assert_eq!(service.call(request("Action=DeleteUser&UserName=carol")).await.unwrap().status(), StatusCode::OK);
+
+        let mut service = service.with_mode(AuthorizationMode::Permissive);
+        assert_eq!(service.call(request("Action=DeleteUser&UserName=bob")).await.unwrap().status(), StatusCode::OK);
+
+        // The enforce-authorization flag overrides the mode for an account on each request.
+        let flags = Arc::new(FeatureFlags::new(&FeatureFlagConfig::default()));
+        let mut service = service.with_feature_flags(flags.clone());
+        flags.set(ENFORCE_AUTHORIZATION, Some("123456789012"), Some(true), "127.0.0.1:5000");
+        assert_eq!(
+            service.call(request("Action=DeleteUser&UserName=bob")).await.unwrap().status(),
+            StatusCode::FORBIDDEN
+        );
+        flags.set(ENFORCE_AUTHORIZATION, Some("123456789012"), None, "127.0.0.1:5000");
+        assert_eq!(service.call(request("Action=DeleteUser&UserName=bob")).await.unwrap().status(), StatusCode::OK);
+
+        let mut service = service.with_mode(AuthorizationMode::Enforce).with_exempt_actions(&["DeleteUser"]);
+        assert_eq!(service.call(request("Action=DeleteUser&UserName=bob")).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_condition_keys() {
+        let mut service = service(store().await);
+        let attach = |policy_arn: &str| {
+            request(&format!("Action=AttachUserPolicy&UserName=bob&PolicyArn={}", policy_arn.replace(':', "%3A")))
+        };
+
+        let response = service.call(attach("arn:aws:iam::aws:policy/ReadOnlyAccess")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let response = service.call(attach("arn:aws:iam::aws:policy/AdministratorAccess")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    /// A request by the `build` session of the `Deployer` role, whose session policies are in `packed`.
+    fn session_request(body: &str, packed: PackedClaims) -> Request<Body> {
+        let session = principal::AssumedRole::new("aws", "123456789012", "Deployer", "build").unwrap();
+        let mut session_data = SessionData::new();
+        session_data.insert(AWS_USERID, SessionValue::String("AROAEXAMPLEROLE1:build".to_string()));
+        let mut req = Request::post("/")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        req.extensions_mut().insert(Principal::from(vec![PrincipalIdentity::from(session)]));
+        req.extensions_mut().insert(session_data);
+        req.extensions_mut().insert(packed);
+        req
+    }
+
+    /// A store with the `Deployer` role, with id `role_id`, allowed [POLICY].
+    async fn deployer_store(role_id: &str) -> Arc<MemoryStore> {
+        let store = store().await;
+        let role = Role {
+            role_id: role_id.to_string(),
+            account_id: "123456789012".to_string(),
+            role_name: "Deployer".to_string(),
+            path: "/".to_string(),
+            permissions_boundary: None,
+            description: None,
+            assume_role_policy_document: "{}".to_string(),
+            max_session_duration: 3600,
+            created_at: Utc::now(),
+        };
+        store.create_role(&role, &[]).await.unwrap();
+        let policy = InlinePolicy {
+            policy_name: "Staff".to_string(),
+            policy_document: POLICY.to_string(),
+        };
+        store.put_inline_policy(PolicyHolder::Role, role_id, &policy).await.unwrap();
+        store
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_session_policies() {
+        let mut service = service(deployer_store("AROAEXAMPLEROLE1").await);
+        let attach =
+            "Action=AttachUserPolicy&UserName=bob&PolicyArn=arn%3Aaws%3Aiam%3A%3Aaws%3Apolicy%2FReadOnlyAccess";
+
+        // Without session policies, the role's policies decide.
+        for body in ["Action=GetUser&UserName=bob", attach] {
+            let response = service.call(session_request(body, PackedClaims::default())).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK, "{body}");
+        }
+
+        // A session policy only allows what the role's policies also allow.
+        let packed = PackedClaims {
+            policy: Some(
+                r#"{"Statement": {"Effect": "Allow", "Action": ["iam:GetUser", "iam:DeleteUser"], "Resource": "*"}}"#
+                    .to_string(),
+            ),
+            ..Default::default()
+        };
+        for (body, status) in [
+            ("Action=GetUser&UserName=bob", StatusCode::OK),
+            ("Action=DeleteUser&UserName=bob", StatusCode::FORBIDDEN),
+            (attach, StatusCode::FORBIDDEN),
+        ] {
+            let response = service.call(session_request(body, packed.clone())).await.unwrap();
+            assert_eq!(response.status(), status, "{body}");
+        }
+
+        // A managed session policy that does not exist grants nothing.
+        let packed = PackedClaims {
+            policy_arns: vec!["arn:aws:iam::123456789012:policy/Missing".to_string()],
+            ..Default::default()
+        };
+        let response = service.call(session_request("Action=GetUser&UserName=bob", packed)).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_recreated_role() {
+        // The session was issued for AROAEXAMPLEROLE1; the Deployer role now has a different id.
+        let mut service = service(deployer_store("AROAEXAMPLEROLE2").await);
+        let response =
+            service.call(session_request("Action=GetUser&UserName=bob", PackedClaims::default())).await.unwrap();
+        assert_eq!(response.status(), StatusCode::FORBIDDEN);
+
+        // A session without a role id is denied too.
+        let mut service = service(deployer_store("AROAEXAMPLEROLE1").await);
+        let mut req = session_request("Action=GetUser&UserName=bob", PackedClaims::default());
+        req.extensions_mut().insert(SessionData::new());
+        assert_eq!(service.call(req).await.unwrap().status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_fails_closed() {
+        let mut service = service(store().await);
+
+        // The action is read wherever the service reads it: from the query when the body is not a form, and from
+        // forms whatever the spelling of their content type.
+        for req in [
+            Request::post("/?Action=DeleteUser&UserName=bob").body(Body::empty()).unwrap(),
+            Request::post("/?Action=DeleteUser&UserName=bob")
+                .header("Content-Type", "text/plain")
+                .body(Body::empty())
+                .unwrap(),
+            Request::post("/")
+                .header("Content-Type", "Application/X-WWW-Form-URLEncoded; charset=utf-8")
+                .body(Body::from("Action=DeleteUser&UserName=bob"))
+                .unwrap(),
+        ] {
+            let response = service.call(with_alice(req)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN);
+        }
+
+        // Requests whose parameters cannot be read, with no action, or with an unregistered action are denied.
+        for body in ["Action=GetUser&Action=DeleteUser", "UserName=bob", "Action=NoSuchAction"] {
+            let response = service.call(request(body)).await.unwrap();
+            assert_eq!(response.status(), StatusCode::FORBIDDEN, "{body}");
+        }
+        let req = Request::get("/?Action=GetUser&Action=DeleteUser").body(Body::empty()).unwrap();
+        assert_eq!(service.call(with_alice(req)).await.unwrap().status(), StatusCode::FORBIDDEN);
+
+        // Anonymous requests are left to the service.
+        let req = Request::post("/").body(Body::from("Action=DeleteUser&UserName=bob")).unwrap();
+        assert_eq!(service.call(req).await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_requested_region() {
+        const REGIONAL_POLICY: &str = r#"{"Statement": {"Effect": "Allow", "Action": "iam:ListUsers", "Resource": "*",
+            "Condition": {"StringEquals": {"aws:RequestedRegion": "us-west-2"}}}}"#;
+        let store = store().await;
+        let policy = InlinePolicy {
+            policy_name: "Regional".to_string(),
+            policy_document: REGIONAL_POLICY.to_string(),
+        };
+        store.put_inline_policy(PolicyHolder::User, "AIDAEXAMPLEALICE", &policy).await.unwrap();
+
+        // The wrapped service sees the registry, and only regions in it.
+        let inner = service_fn(|req: Request<Body>| async move {
+            let (parts, _) = req.into_parts();
+            let context = RequestContext::from_parts(&parts, HashMap::new()).unwrap();
+            assert!(context.regions().unwrap().contains("us-west-2"));
+            Ok::<_, BoxError>(Response::new(Body::from(context.region().unwrap_or_default().to_string())))
+        });
+        let regions = RegionRegistry::new(vec!["local".parse().unwrap(), "us-west-2".parse().unwrap()]);
+        let mut service = AuthorizeRequests::new(inner, store, "iam", IAM).with_regions(Arc::new(regions));
+        let in_region = |region: Option<&str>| {
+            let mut req = request("Action=ListUsers");
+            let mut session_data = SessionData::new();
+            if let Some(region) = region {
+                session_data.insert(AWS_REQUESTED_REGION, SessionValue::String(region.to_string()));
+            }
+            req.extensions_mut().insert(session_data);
+            req
+        };
+
+        let response = service.call(in_region(Some("us-west-2"))).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(body(response).await, "us-west-2");
+        assert_eq!(service.call(in_region(Some("local"))).await.unwrap().status(), StatusCode::FORBIDDEN);
+        assert_eq!(service.call(in_region(None)).await.unwrap().status(), StatusCode::FORBIDDEN);
+    }
+}
@@ -0,0 +1,188 @@
+//! Applying authorization decisions.
+//!
+//! In [AuthorizationMode::Permissive], policies are still evaluated but denials are logged to [AUDIT_TARGET]
+//! instead of being returned to the caller. This allows enforcement to be rolled out to an environment that
+//! previously had no authorization by first reviewing what would have been denied.
+//!
+//! When no statement applies to a request, [DefaultDecision] chooses between AWS's implicit deny and allowing the
+//! request, for setups that only want explicit `Deny` statements to take effect. Requests made with an account's root
+//! credentials bypass identity policies altogether, as AWS's root user does.
+use {
+    crate::context::RequestContext,
+    log::{debug, warn},
+    serde::Deserialize,
+    std::fmt::{Display, Formatter, Result as FmtResult},
+};
+
+/// The log target for would-be denials in permissive mode.
+pub const AUDIT_TARGET: &str = "scratchstack::audit";
+
+/// Whether authorization decisions are enforced.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum AuthorizationMode {
+    #[default]
+    Enforce,
+
+    /// Allow every request, logging the ones that would have been denied.
+    Permissive,
+}
+
+/// The decision for a request that no policy statement allows or denies.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DefaultDecision {
+    /// Deny the request, as AWS does.
+    #[default]
+    Deny,
+
+    /// Allow the request; only explicit denies take effect.
+    Allow,
+}
+
+impl DefaultDecision {
+    /// Apply the default to an evaluated decision. Explicit denies are never overridden.
+    pub fn apply(&self, decision: Decision) -> Decision {
+        match (self, decision) {
+            (Self::Allow, Decision::ImplicitDeny) => Decision::Allow,
+            (_, decision) => decision,
+        }
+    }
+}
+
+/// The result of evaluating the policies that apply to a request.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Decision {
+    Allow,
+
+    /// No statement allowed the request.
+    ImplicitDeny,
+
+    /// A `Deny` statement matched the request.
+    ExplicitDeny {
+        /// The ARN or name of the policy containing the statement.
+        policy: String,
+
+        /// The statement's `Sid`, or its index in the policy if it has none.
+        statement: String,
+    },
+}
+
+impl Decision {
+    pub fn is_allowed(&self) -> bool {
+        matches!(self, Self::Allow)
+    }
+}
+
+impl Display for Decision {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Allow => f.write_str("allow"),
+            Self::ImplicitDeny => f.write_str("implicit deny"),
+            Self::ExplicitDeny {
+                policy,
+                statement,
+            } => write!(f, "explicit deny by statement {statement} of {policy}"),
+        }
+    }
+}
+
+impl AuthorizationMode {
+    /// Returns whether the request described by `context`, `action` and `resource` may proceed given `decision`.
+    ///
+    /// In permissive mode this always returns `true`; denials are logged to [AUDIT_TARGET] with the statement
+    /// responsible.
+    pub fn permits(&self, context: &RequestContext, action: &str, resource: &str, decision: &Decision) -> bool {
+        if decision.is_allowed() {
+            return true;
+        }
+
+        let caller = context.caller_arn().map(|arn| arn.to_string()).unwrap_or_else(|| "anonymous".to_string());
+        match self {
+            Self::Enforce => {
+                debug!(
+                    "Denied {} on {} for {}: {} (request {})",
+                    action,
+                    resource,
+                    caller,
+                    decision,
+                    context.request_id()
+                );
+                false
+            }
+            Self::Permissive => {
+                warn!(
+                    target: AUDIT_TARGET,
+                    "Would deny {} on {} for {}: {} (request {})",
+                    action,
+                    resource,
+                    caller,
+                    decision,
+                    context.request_id()
+                );
+                true
+            }
+        }
+    }
+}
+
+/// Decide a request, bypassing policy evaluation for the account root user and otherwise applying
+/// `default_decision` to the result of `evaluate`.
+pub fn decide<F>(context: &RequestContext, default_decision: DefaultDecision, evaluate: F) -> Decision
+where
+    F: FnOnce() -> Decision,
+{
+    if context.is_root() {
+        debug!("Allowing request {} made with root credentials", context.request_id());
+        return Decision::Allow;
+    }
+
+    default_decision.apply(evaluate())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{decide, AuthorizationMode, Decision, DefaultDecision},
+        crate::context::RequestContext,
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{Principal, PrincipalIdentity, RootUser, User},
+    };
+
+    #[test_log::test]
+    fn test_permits() {
+        let user = User::new("aws", "123456789012", "/", "alice").unwrap();
+        let principal = Principal::from(vec![PrincipalIdentity::from(user)]);
+        let context = RequestContext::builder().principal(principal).build().unwrap();
+        let deny = Decision::ExplicitDeny {
+            policy: "arn:aws:iam::123456789012:policy/NoDelete".to_string(),
+            statement: "DenyDelete".to_string(),
+        };
+
+        assert!(AuthorizationMode::Enforce.permits(&context, "iam:GetUser", "*", &Decision::Allow));
+        assert!(!AuthorizationMode::Enforce.permits(&context, "iam:DeleteUser", "*", &deny));
+        assert!(!AuthorizationMode::Enforce.permits(&context, "iam:DeleteUser", "*", &Decision::ImplicitDeny));
+        assert!(AuthorizationMode::Permissive.permits(&context, "iam:DeleteUser", "*", &deny));
+    }
+
+    #[test_log::test]
+    fn test_default_decision_and_root() {
+        let user = User::new("aws", "123456789012", "/", "alice").unwrap();
+        let context =
+            RequestContext::builder().principal(Principal::from(vec![PrincipalIdentity::from(user)])).build().unwrap();
+        let deny = Decision::ExplicitDeny {
+            policy: "NoDelete".to_string(),
+            statement: "0".to_string(),
+        };
+
+        assert_eq!(decide(&context, DefaultDecision::Deny, || Decision::ImplicitDeny), Decision::ImplicitDeny);
+        assert_eq!(decide(&context, DefaultDecision::Allow, || Decision::ImplicitDeny), Decision::Allow);
+        assert_eq!(decide(&context, DefaultDecision::Allow, || deny.clone()), deny);
+
+        let root = RootUser::new("aws", "123456789012").unwrap();
+        let context =
+            RequestContext::builder().principal(Principal::from(vec![PrincipalIdentity::from(root)])).build().unwrap();
+        assert!(context.is_root());
+        assert_eq!(decide(&context, DefaultDecision::Deny, || deny.clone()), Decision::Allow);
+    }
+}
@@ -0,0 +1,166 @@
+//! Provisioning new accounts.
+//!
+//! AWS creates accounts outside of IAM, so there is no IAM operation for it. [create_account] fills the gap for
+//! administrators setting up a fresh database: it creates the account row, an administrator user with an inline
+//! policy allowing every action, and an access key for that user, so the first IAM requests can be signed without
+//! hand-written SQL.
+//!
+//! The steps are separate store calls. If one fails, the entities already created are left in place and the error
+//! is returned; delete them, or create the rest by hand, before trying again with the same email.
+use {
+    crate::{
+        access_key::{generate_secret_key, AccessKeyKind, AccessKeyPrefixes},
+        ids::{unique_id, USER_ID_PREFIX},
+        store::{AccessKey, Account, ControlPlaneStore, InlinePolicy, PolicyHolder, StoreError, User},
+    },
+    chrono::Utc,
+    ring::rand::{SecureRandom, SystemRandom},
+    serde::Serialize,
+    std::fmt::{Debug, Formatter, Result as FmtResult},
+};
+
+/// The name of the administrator user created when none is given.
+pub const DEFAULT_ADMIN_USER_NAME: &str = "admin";
+
+/// The name of the inline policy given to the administrator user.
+pub const ADMIN_POLICY_NAME: &str = "AdministratorAccess";
+
+/// The policy document of [ADMIN_POLICY_NAME].
+pub const ADMIN_POLICY_DOCUMENT: &str =
+    r#"{"Version":"2012-10-17","Statement":[{"Effect":"Allow","Action":"*","Resource":"*"}]}"#;
+
+/// A newly provisioned account and the credentials of its administrator, as printed by the command line tools.
+#[derive(Clone, Eq, PartialEq, Serialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct AccountBootstrap {
+    pub account_id: String,
+    pub email: String,
+    pub user_name: String,
+    pub user_id: String,
+
+    /// The access key id as callers use it, with the generated prefix.
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+impl Debug for AccountBootstrap {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("AccountBootstrap")
+            .field("account_id", &self.account_id)
+            .field("email", &self.email)
+            .field("user_name", &self.user_name)
+            .field("user_id", &self.user_id)
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_access_key", &"<redacted>")
+            .finish()
+    }
+}
+
+/// A new random 12-digit account id.
+pub fn generate_account_id() -> String {
+    let mut random = [0u8; 12];
+    SystemRandom::new().fill(&mut random).expect("Unable to generate account id");
+    // Skew toward low digits is harmless here; ids only need to be unlikely to collide.
+    random.iter().map(|b| char::from(b'0' + b % 10)).collect()
+}
+
+/// Create an active account for `email` with an administrator user named `admin_user_name` and an access key for
+/// it. The account id is generated unless `account_id` is given.
+pub async fn create_account(
+    store: &dyn ControlPlaneStore,
+    prefixes: &AccessKeyPrefixes,
+    email: &str,
+    account_id: Option<&str>,
+    admin_user_name: &str,
+) -> Result<AccountBootstrap, StoreError> {
+    let account = Account {
+        account_id: account_id.map(str::to_string).unwrap_or_else(generate_account_id),
+        email: email.to_string(),
+        active: true,
+        alias: None,
+    };
+    store.create_account(&account).await?;
+
+    let now = Utc::now();
+    let user = User {
+        user_id: unique_id(USER_ID_PREFIX),
+        account_id: account.account_id.clone(),
+        user_name: admin_user_name.to_string(),
+        path: "/".to_string(),
+        permissions_boundary: None,
+        created_at: now,
+    };
+    store.create_user(&user, &[]).await?;
+
+    let policy = InlinePolicy {
+        policy_name: ADMIN_POLICY_NAME.to_string(),
+        policy_document: ADMIN_POLICY_DOCUMENT.to_string(),
+    };
+    store.put_inline_policy(PolicyHolder::User, &user.user_id, &policy).await?;
+
+    let generated = prefixes.generate(AccessKeyKind::LongTerm);
+    let access_key = AccessKey {
+        user_id: user.user_id.clone(),
+        access_key_id: prefixes.stored_id(&generated).unwrap_or(&generated).to_string(),
+        secret_key: generate_secret_key(),
+        active: true,
+        created_at: now,
+    };
+    store.create_access_key(&access_key).await?;
+
+    Ok(AccountBootstrap {
+        account_id: account.account_id,
+        email: account.email,
+        user_name: user.user_name,
+        user_id: user.user_id,
+        access_key_id: generated,
+        secret_access_key: access_key.secret_key,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{create_account, ADMIN_POLICY_DOCUMENT, ADMIN_POLICY_NAME, DEFAULT_ADMIN_USER_NAME},
+        crate::{
+            access_key::AccessKeyPrefixes,
+            store::{ControlPlaneStore, MemoryStore, PolicyHolder},
+        },
+        pretty_assertions::assert_eq,
+    };
+
+    #[test_log::test(tokio::test)]
+    async fn test_create_account() {
+        let store = MemoryStore::new();
+        let prefixes = AccessKeyPrefixes::default();
+        let bootstrap =
+            create_account(&store, &prefixes, "ops@example.com", None, DEFAULT_ADMIN_USER_NAME).await.unwrap();
+        assert_eq!(bootstrap.account_id.len(), 12);
+        assert!(bootstrap.account_id.bytes().all(|b| b.is_ascii_digit()));
+        assert!(!format!("{bootstrap:?}").contains(&bootstrap.secret_access_key));
+
+        let account = store.get_account(&bootstrap.account_id).await.unwrap();
+        assert_eq!((account.email.as_str(), account.active, account.alias), ("ops@example.com", true, None));
+        let user = store.get_user(&bootstrap.account_id, "admin").await.unwrap();
+        assert_eq!(user.user_id, bootstrap.user_id);
+        let policy = store.get_inline_policy(PolicyHolder::User, &user.user_id, ADMIN_POLICY_NAME).await.unwrap();
+        assert_eq!(policy.policy_document, ADMIN_POLICY_DOCUMENT);
+
+        assert!(bootstrap.access_key_id.starts_with("AKIA"));
+        let stored_id = prefixes.stored_id(&bootstrap.access_key_id).unwrap();
+        let access_key = store.get_access_key(stored_id).await.unwrap();
+        assert_eq!((access_key.user_id.as_str(), access_key.active), (user.user_id.as_str(), true));
+        assert_eq!(access_key.secret_key, bootstrap.secret_access_key);
+
+        let json = serde_json::to_value(&bootstrap).unwrap();
+        assert_eq!(json["AccountId"], bootstrap.account_id.as_str());
+        assert_eq!(json["SecretAccessKey"], bootstrap.secret_access_key.as_str());
+
+        let e = create_account(&store, &prefixes, "ops@example.com", None, "admin").await.unwrap_err();
+        assert_eq!(e.code(), "EntityAlreadyExists");
+        let e = create_account(&store, &prefixes, "dev@example.com", Some(&bootstrap.account_id), "admin")
+            .await
+            .unwrap_err();
+        assert_eq!(e.code(), "EntityAlreadyExists");
+    }
+}
@@ -0,0 +1,248 @@
+use {
+    crate::{
+        context::RequestContext,
+        integrity::request_credential,
+        protocol::{AwsError, ErrorProtocol},
+    },
+    http::StatusCode,
+    hyper::{service::Service, Body, Request, Response},
+    log::debug,
+    scratchstack_aws_principal::Principal,
+    scratchstack_http_framework::RequestId,
+    serde::Deserialize,
+    std::{
+        collections::HashMap,
+        fmt::{Debug, Formatter, Result as FmtResult},
+        future::Future,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+        time::{Duration, Instant},
+    },
+    tokio::sync::{OwnedSemaphorePermit, Semaphore},
+    tower::BoxError,
+};
+
+/// What requests are grouped by when counting concurrent requests.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ConcurrencyKey {
+    /// The account of the caller.
+    #[default]
+    Account,
+
+    /// The access key the request was signed with.
+    AccessKey,
+}
+
+/// Limits on the number of requests from one principal that are processed at the same time.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ConcurrencyConfig {
+    pub max_in_flight: usize,
+
+    #[serde(default)]
+    pub key: ConcurrencyKey,
+
+    /// Seconds after which the counter for a principal with no requests in flight is discarded.
+    #[serde(default = "ConcurrencyConfig::default_idle_seconds")]
+    pub idle_seconds: u64,
+}
+
+impl ConcurrencyConfig {
+    fn default_idle_seconds() -> u64 {
+        300
+    }
+}
+
+/// In-flight request counters, one semaphore per principal.
+pub struct ConcurrencyLimits {
+    max_in_flight: usize,
+    idle: Duration,
+    semaphores: Mutex<LimitState>,
+}
+
+struct LimitState {
+    semaphores: HashMap<String, (Arc<Semaphore>, Instant)>,
+    last_eviction: Instant,
+}
+
+impl ConcurrencyLimits {
+    pub fn new(max_in_flight: usize, idle: Duration) -> Self {
+        Self {
+            max_in_flight,
+            idle,
+            semaphores: Mutex::new(LimitState {
+                semaphores: HashMap::new(),
+                last_eviction: Instant::now(),
+            }),
+        }
+    }
+
+    /// Take one of the slots for `key`. This returns `None` if all of them are in use. The slot is released when
+    /// the permit is dropped.
+    pub fn try_acquire(&self, key: &str) -> Option<OwnedSemaphorePermit> {
+        let mut state = self.semaphores.lock().expect("concurrency limits poisoned");
+        let now = Instant::now();
+
+        if now.duration_since(state.last_eviction) >= self.idle {
+            let idle = self.idle;
+            let max_in_flight = self.max_in_flight;
+            state.semaphores.retain(|_, (semaphore, last_used)| {
+                semaphore.available_permits() < max_in_flight || now.duration_since(*last_used) < idle
+            });
+            state.last_eviction = now;
+        }
+
+        let max_in_flight = self.max_in_flight;
+        let (semaphore, last_used) =
+            state.semaphores.entry(key.to_string()).or_insert_with(|| (Arc::new(Semaphore::new(max_in_flight)), now));
+        *last_used = now;
+        semaphore.clone().try_acquire_owned().ok()
+    }
+
+    /// The number of principals currently tracked.
+    pub fn len(&self) -> usize {
+        self.semaphores.lock().expect("concurrency limits poisoned").semaphores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Debug for ConcurrencyLimits {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("ConcurrencyLimits")
+            .field("max_in_flight", &self.max_in_flight)
+            .field("idle", &self.idle)
+            .field("len", &self.len())
+            .finish()
+    }
+}
+
+/// A service wrapper that rejects a request with a `Throttling` error when its principal already has the maximum
+/// number of requests in flight.
+///
+/// This wraps the service implementation, so requests are limited after signature verification, when the principal
+/// is known. Without a configuration, requests are passed through unchanged.
+#[derive(Clone, Debug)]
+pub struct ConcurrencyLimit<S> {
+    inner: S,
+    limits: Option<Arc<ConcurrencyLimits>>,
+    key: ConcurrencyKey,
+    xml_ns: &'static str,
+}
+
+impl<S> ConcurrencyLimit<S> {
+    /// Wrap `inner`. Throttling errors are rendered in the `xml_ns` namespace.
+    pub fn new(inner: S, config: Option<&ConcurrencyConfig>, xml_ns: &'static str) -> Self {
+        let (limits, key) = match config {
+            None => (None, ConcurrencyKey::default()),
+            Some(config) => (
+                Some(Arc::new(ConcurrencyLimits::new(config.max_in_flight, Duration::from_secs(config.idle_seconds)))),
+                config.key,
+            ),
+        };
+
+        Self {
+            inner,
+            limits,
+            key,
+            xml_ns,
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for ConcurrencyLimit<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = BoxError> + Send + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let limits = match &self.limits {
+            None => return Box::pin(self.inner.call(req)),
+            Some(limits) => limits,
+        };
+
+        let key = match principal_key(&req, self.key) {
+            Some(key) => key,
+            None => return Box::pin(self.inner.call(req)),
+        };
+
+        let permit = match limits.try_acquire(&key) {
+            Some(permit) => permit,
+            None => {
+                debug!("Too many requests in flight for {}", key);
+                let request_id = req.extensions().get::<RequestId>().copied().unwrap_or_else(RequestId::new);
+                let response = throttling_response(self.xml_ns, request_id);
+                return Box::pin(async move { response });
+            }
+        };
+
+        let future = self.inner.call(req);
+        Box::pin(async move {
+            let response = future.await;
+            drop(permit);
+            response
+        })
+    }
+}
+
+/// Returns the key `req` is counted under, or `None` if it cannot be attributed to a principal.
+fn principal_key<B>(req: &Request<B>, key: ConcurrencyKey) -> Option<String> {
+    match key {
+        ConcurrencyKey::Account => {
+            let principal = req.extensions().get::<Principal>()?.clone();
+            RequestContext::builder().principal(principal).build().ok()?.account_id()
+        }
+        ConcurrencyKey::AccessKey => {
+            let (credential, _) = request_credential(req)?;
+            credential.split('/').next().map(ToString::to_string)
+        }
+    }
+}
+
+fn throttling_response(xml_ns: &'static str, request_id: RequestId) -> Result<Response<Body>, BoxError> {
+    let error = AwsError::sender(StatusCode::BAD_REQUEST, "Throttling", "Rate exceeded");
+    ErrorProtocol::Query {
+        xml_ns,
+    }
+    .response(&error, request_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::ConcurrencyLimits,
+        pretty_assertions::assert_eq,
+        std::{thread::sleep, time::Duration},
+    };
+
+    #[test_log::test]
+    fn test_try_acquire() {
+        let limits = ConcurrencyLimits::new(2, Duration::from_millis(10));
+        let first = limits.try_acquire("123456789012").unwrap();
+        let _second = limits.try_acquire("123456789012").unwrap();
+        assert!(limits.try_acquire("123456789012").is_none());
+
+        // Other principals are not affected.
+        let other = limits.try_acquire("210987654321").unwrap();
+
+        drop(first);
+        assert!(limits.try_acquire("123456789012").is_some());
+
+        // Idle principals are evicted; busy ones are kept.
+        drop(other);
+        sleep(Duration::from_millis(20));
+        let _third = limits.try_acquire("111111111111").unwrap();
+        assert_eq!(limits.len(), 2);
+    }
+}
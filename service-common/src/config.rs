@@ -0,0 +1,632 @@
+use {
+    crate::{
+        access_key::AccessKeyPrefixes,
+        access_log::AccessLogConfig,
+        audit::AuditConfig,
+        authz::{AuthorizationMode, DefaultDecision},
+        backup::BackupConfig,
+        concurrency::ConcurrencyConfig,
+        consistency::ConsistencyConfig,
+        deprecation::DeprecationConfig,
+        encoding::RequestDecodingConfig,
+        flags::FeatureFlagConfig,
+        gsk::{CircuitBreakerConfig, RootCredentialConfig},
+        health::HealthConfig,
+        integrity::ResponseSigningConfig,
+        load_shed::LoadShedConfig,
+        mirror::MirrorConfig,
+        net::{IpFilter, TrustedProxies},
+        oidc::OidcProviderConfig,
+        outbound::OutboundConfig,
+        payload_hash::PayloadHashConfig,
+        region::{Region, RegionRegistry},
+        request_time::{RequestTimeConfig, SignatureConfig},
+        response_cache::ResponseCacheConfig,
+        revocation::RevocationConfig,
+        route::RoutingConfig,
+        signing::{SigningKeyProvider, SigningKeyProviderError},
+        store::QueryLogConfig,
+        telemetry::MetricsEndpointConfig,
+    },
+    ipnet::IpNet,
+    serde::Deserialize,
+    std::{
+        env,
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        fs::read_to_string,
+        io::{Error as IOError, ErrorKind},
+        path::{Path, PathBuf},
+        sync::Arc,
+    },
+    toml::{de::Error as TomlError, value::Table, Value},
+};
+
+#[cfg(feature = "pkcs11")]
+use crate::signing::Pkcs11SigningKeyProvider;
+
+/// Prefix of environment variables that override settings in the configuration file.
+pub const ENV_PREFIX: &str = "SCRATCHSTACK_";
+
+/// The environment variable naming the configuration profile to apply when none is given on the command line.
+pub const PROFILE_ENV: &str = "SCRATCHSTACK_PROFILE";
+
+/// The table of the configuration file holding named profiles, e.g. `[profile.ci]`.
+const PROFILES_KEY: &str = "profile";
+
+/// The key in a profile naming the profile it extends.
+const INHERITS_KEY: &str = "inherits";
+
+/// Service settings that are handled by Scratchstack itself rather than by `scratchstack-config`.
+///
+/// These are read from the same `[service.<name>]` section of the configuration file; keys that are not
+/// recognized here are ignored so the two parsers can share the section.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ServiceOptions {
+    pub listener: ListenerConfig,
+
+    /// Additional regions, beyond the service's own region, accepted in request credential scopes.
+    pub regions: Vec<Region>,
+
+    pub signing_key_provider: SigningKeyProviderConfig,
+
+    /// If present, a sample of verified requests is also sent to a secondary backend for comparison.
+    pub mirror: Option<MirrorConfig>,
+
+    /// If present, some requests are sent to an alternate implementation of the service.
+    pub routing: Option<RoutingConfig>,
+
+    pub response_signing: ResponseSigningConfig,
+
+    /// Certificate and private key for the endpoint, loaded by Scratchstack; see [TlsFiles].
+    pub tls_files: Option<TlsFiles>,
+
+    /// Start even if the database schema version does not match the one this service was written for.
+    pub skip_schema_check: bool,
+
+    /// If present, the database is backed up on a schedule.
+    pub backup: Option<BackupConfig>,
+
+    /// If present, a limits catalog applied to `limitstore.limit_definition` at startup; see [crate::limit_catalog].
+    pub limits_catalog: Option<PathBuf>,
+
+    /// If present, limits the number of requests from one principal that are processed at once.
+    pub concurrency: Option<ConcurrencyConfig>,
+
+    /// If present, requests are shed with `Throttling` while the process is saturated; see [crate::load_shed].
+    pub load_shedding: Option<LoadShedConfig>,
+
+    /// If present, requests are answered with a 503 while signing key lookups are failing or slow.
+    pub circuit_breaker: Option<CircuitBreakerConfig>,
+
+    /// The largest request header block accepted, in bytes. Session tokens for chained roles with many tags can
+    /// exceed Hyper's default. Values below 8192 are raised to 8192.
+    pub max_header_bytes: Option<usize>,
+
+    /// Whether authorization denials are enforced or only logged.
+    pub authorization: AuthorizationMode,
+
+    /// The decision for requests that no policy statement allows or denies.
+    pub default_decision: DefaultDecision,
+
+    /// Access keys for account root users, which bypass identity policies.
+    pub root_credentials: Vec<RootCredentialConfig>,
+
+    /// Readiness and graceful shutdown settings.
+    pub health: HealthConfig,
+
+    /// If present, request bodies compressed with gzip or zstd are decoded.
+    pub request_decoding: Option<RequestDecodingConfig>,
+
+    /// If present, newly created entities and access keys are hidden from reads for a short time.
+    pub eventual_consistency: Option<ConsistencyConfig>,
+
+    /// If present, responses to expensive read operations are reused for a few seconds; see [crate::response_cache].
+    pub response_cache: Option<ResponseCacheConfig>,
+
+    /// Initial feature flags and the admin endpoint token.
+    pub feature_flags: FeatureFlagConfig,
+
+    /// Where audit events are delivered in addition to the service log.
+    pub audit: AuditConfig,
+
+    /// If present, access keys can be deactivated across every replica at once; see [crate::revocation].
+    pub credential_revocation: Option<RevocationConfig>,
+
+    /// Slow query logging for the control plane store.
+    pub query_log: QueryLogConfig,
+
+    /// The per-request access log; see [crate::access_log].
+    pub access_log: AccessLogConfig,
+
+    /// If present, Prometheus metrics are served on this separate listener; see [crate::telemetry].
+    pub metrics_endpoint: Option<MetricsEndpointConfig>,
+
+    /// The access key prefixes this deployment generates and accepts; by default `AKIA` and `ASIA`.
+    pub access_key_prefixes: AccessKeyPrefixes,
+
+    /// Whether `UNSIGNED-PAYLOAD` is accepted, and how much of a body is read to check a declared digest; see
+    /// [crate::payload_hash].
+    pub payload_hash: PayloadHashConfig,
+
+    /// The clock skew and presigned request lifetime accepted; see [crate::request_time].
+    pub request_time: RequestTimeConfig,
+
+    /// Whether requests using deprecated operations or parameters are warned about or rejected; see
+    /// [crate::deprecation].
+    pub deprecations: DeprecationConfig,
+
+    /// Signature verification settings; `allowed_clock_skew` overrides `request_time.clock_skew_seconds`.
+    pub signature: SignatureConfig,
+
+    /// Proxy, trust roots, timeouts and retries for requests this service makes; see [crate::outbound].
+    pub outbound: OutboundConfig,
+
+    /// The OpenID Connect providers trusted for `AssumeRoleWithWebIdentity`; see [crate::oidc].
+    pub oidc_providers: Vec<OidcProviderConfig>,
+}
+
+impl ServiceOptions {
+    /// Read the options for `service` from the given configuration file.
+    pub fn read_file<P: AsRef<Path>>(filename: P, service: &str) -> Result<Self, ConfigError> {
+        let contents = read_to_string(filename)?;
+        Self::from_toml_str(&contents, service)
+    }
+
+    /// Parse the options for `service` from the contents of a configuration file.
+    pub fn from_toml_str(contents: &str, service: &str) -> Result<Self, ConfigError> {
+        let value: Value = toml::from_str(contents)?;
+        Self::from_value(&value, service)
+    }
+
+    /// Extract the options for `service` from an already parsed configuration file.
+    pub fn from_value(value: &Value, service: &str) -> Result<Self, ConfigError> {
+        match value.get("service").and_then(|s| s.get(service)) {
+            None => Ok(Self::default()),
+            Some(section) => Ok(section.clone().try_into()?),
+        }
+    }
+
+    /// The request time settings, with the clock skew from `signature.allowed_clock_skew` if it is set.
+    pub fn request_time(&self) -> RequestTimeConfig {
+        self.request_time.clone().with_allowed_clock_skew(self.signature.allowed_clock_skew)
+    }
+
+    /// Returns the registry of regions accepted by the service, which always includes `service_region`.
+    pub fn region_registry(&self, service_region: &Region) -> RegionRegistry {
+        let mut registry = RegionRegistry::new(self.regions.iter().cloned());
+        registry.insert(service_region.clone());
+        registry
+    }
+}
+
+/// Read a configuration file, apply the selected profile (see [apply_profile]), and then apply overrides from
+/// `SCRATCHSTACK_*` environment variables (see [apply_env_overrides]).
+///
+/// The profile is `profile` if given, usually from a `--profile` option, and otherwise the value of [PROFILE_ENV].
+///
+/// If `required` is false and the file does not exist, the configuration comes from the environment alone. This
+/// lets containers run without a mounted configuration file.
+pub fn read_layered_config<P: AsRef<Path>>(
+    filename: P,
+    required: bool,
+    profile: Option<&str>,
+) -> Result<Value, ConfigError> {
+    let mut value = match read_to_string(filename) {
+        Ok(contents) => toml::from_str(&contents)?,
+        Err(e) if e.kind() == ErrorKind::NotFound && !required => Value::Table(Table::new()),
+        Err(e) => return Err(e.into()),
+    };
+
+    let profile = profile.map(ToString::to_string).or_else(|| env::var(PROFILE_ENV).ok().filter(|p| !p.is_empty()));
+    apply_profile(&mut value, profile.as_deref())?;
+    apply_env_overrides(&mut value, env::vars())?;
+    Ok(value)
+}
+
+/// Remove the `[profile.*]` tables from a parsed configuration and merge the named profile, if any, over the rest.
+///
+/// A profile is written like the top level of the file, e.g. `[profile.ci.service.iam]`, and may name another
+/// profile to extend with `inherits = "<name>"`; the chain is applied starting from the profile that inherits from
+/// nothing. Tables are merged key by key; any other value, including an array, replaces the one beneath it.
+pub fn apply_profile(value: &mut Value, profile: Option<&str>) -> Result<(), ConfigError> {
+    let mut profiles = match value {
+        Value::Table(table) => match table.remove(PROFILES_KEY) {
+            None => Table::new(),
+            Some(Value::Table(profiles)) => profiles,
+            Some(_) => return Err(ConfigError::InvalidProfile(PROFILES_KEY.to_string())),
+        },
+        _ => Table::new(),
+    };
+
+    let mut name = match profile {
+        None => return Ok(()),
+        Some(name) => name.to_string(),
+    };
+
+    // Collect the chain from the selected profile up to its root.
+    let mut chain: Vec<(String, Table)> = Vec::new();
+    loop {
+        let mut overlay = match profiles.remove(&name) {
+            Some(Value::Table(overlay)) => overlay,
+            Some(_) => return Err(ConfigError::InvalidProfile(name)),
+            None if chain.iter().any(|(seen, _)| *seen == name) => return Err(ConfigError::ProfileCycle(name)),
+            None => return Err(ConfigError::UnknownProfile(name)),
+        };
+
+        let parent = match overlay.remove(INHERITS_KEY) {
+            None => None,
+            Some(Value::String(parent)) => Some(parent),
+            Some(_) => return Err(ConfigError::InvalidProfile(name)),
+        };
+        chain.push((name, overlay));
+
+        match parent {
+            None => break,
+            Some(parent) => name = parent,
+        }
+    }
+
+    for (_, overlay) in chain.into_iter().rev() {
+        merge(value, Value::Table(overlay));
+    }
+
+    Ok(())
+}
+
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Table(base), Value::Table(overlay)) => {
+            for (key, value) in overlay {
+                match base.get_mut(&key) {
+                    Some(existing) => merge(existing, value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+/// Apply overrides from environment variables to a parsed configuration.
+///
+/// The variable name after [ENV_PREFIX] is split on double underscores and lowercased to give the path of the
+/// setting, so `SCRATCHSTACK_SERVICE__IAM__REGION=us-west-2` sets `region` in `[service.iam]`. Values that parse as
+/// a TOML value (numbers, booleans, arrays) are used as such; anything else is a string.
+pub fn apply_env_overrides<I>(value: &mut Value, vars: I) -> Result<(), ConfigError>
+where
+    I: IntoIterator<Item = (String, String)>,
+{
+    for (name, setting) in vars {
+        if name == PROFILE_ENV {
+            continue;
+        }
+
+        let path = match name.strip_prefix(ENV_PREFIX) {
+            Some(path) if !path.is_empty() => path.to_lowercase(),
+            _ => continue,
+        };
+        let keys: Vec<&str> = path.split("__").collect();
+        if keys.iter().any(|key| key.is_empty()) {
+            return Err(ConfigError::InvalidOverride(name));
+        }
+
+        let mut table = match value {
+            Value::Table(table) => table,
+            _ => return Err(ConfigError::InvalidOverride(name)),
+        };
+
+        let (last, parents) = keys.split_last().expect("split always yields a key");
+        for key in parents {
+            let entry = table.entry(key.to_string()).or_insert(Value::Table(Table::new()));
+            table = match entry {
+                Value::Table(table) => table,
+                _ => return Err(ConfigError::InvalidOverride(name)),
+            };
+        }
+
+        table.insert(last.to_string(), parse_env_value(&setting));
+    }
+
+    Ok(())
+}
+
+fn parse_env_value(setting: &str) -> Value {
+    match toml::from_str::<Table>(&format!("value = {setting}")) {
+        Ok(mut table) => table.remove("value").unwrap_or_else(|| Value::String(setting.to_string())),
+        Err(_) => Value::String(setting.to_string()),
+    }
+}
+
+/// Network-level restrictions applied to incoming connections.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ListenerConfig {
+    /// If non-empty, only connections from these networks are accepted.
+    pub allow: Vec<IpNet>,
+
+    /// Connections from these networks are always rejected, even if they are also in `allow`.
+    pub deny: Vec<IpNet>,
+
+    /// Proxies whose `X-Forwarded-For` and `X-Forwarded-Proto` headers are believed, for load balancers that
+    /// terminate TLS.
+    pub trusted_proxies: Vec<IpNet>,
+}
+
+impl ListenerConfig {
+    pub fn ip_filter(&self) -> IpFilter {
+        IpFilter::new(self.allow.clone(), self.deny.clone())
+    }
+
+    pub fn trusted_proxies(&self) -> TrustedProxies {
+        TrustedProxies::new(self.trusted_proxies.clone())
+    }
+}
+
+/// Where secret keys are held when deriving signing keys.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum SigningKeyProviderConfig {
+    /// Secret keys are read from the database and all derivation is performed in software.
+    #[default]
+    Database,
+
+    /// Secret keys are held in a PKCS#11 token; requires the `pkcs11` feature.
+    Pkcs11(Pkcs11Config),
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct Pkcs11Config {
+    /// Path to the PKCS#11 module (e.g. `/usr/lib/softhsm/libsofthsm2.so`).
+    pub module: PathBuf,
+
+    pub slot: u64,
+
+    /// File containing the user PIN for the token.
+    pub pin_file: Option<PathBuf>,
+
+    /// Prefix prepended to the access key id to form the label of the key object on the token.
+    #[serde(default)]
+    pub label_prefix: String,
+}
+
+impl SigningKeyProviderConfig {
+    /// The provider that replaces the database key derivation, if any.
+    pub fn provider(&self) -> Result<Option<Arc<dyn SigningKeyProvider>>, SigningKeyProviderError> {
+        match self {
+            Self::Database => Ok(None),
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(pkcs11) => Ok(Some(Arc::new(pkcs11.provider()?))),
+            #[cfg(not(feature = "pkcs11"))]
+            Self::Pkcs11(_) => Err(SigningKeyProviderError::Backend(
+                "The PKCS#11 signing key provider requires the pkcs11 feature".into(),
+            )),
+        }
+    }
+}
+
+#[cfg(feature = "pkcs11")]
+impl Pkcs11Config {
+    pub fn provider(&self) -> Result<Pkcs11SigningKeyProvider, SigningKeyProviderError> {
+        let pin = match &self.pin_file {
+            None => None,
+            Some(pin_file) => Some(
+                read_to_string(pin_file)
+                    .map_err(|e| SigningKeyProviderError::Backend(Box::new(e)))?
+                    .trim_end()
+                    .to_string(),
+            ),
+        };
+
+        Pkcs11SigningKeyProvider::new(&self.module, self.slot, pin, self.label_prefix.clone())
+    }
+}
+
+#[derive(Debug)]
+pub enum ConfigError {
+    IO(IOError),
+
+    /// The named environment variable does not map to a setting, e.g. because a parent is not a table.
+    InvalidOverride(String),
+
+    /// The named profile is not a table, or its `inherits` setting is not a string.
+    InvalidProfile(String),
+
+    /// The named profile inherits from itself, directly or through other profiles.
+    ProfileCycle(String),
+    Toml(TomlError),
+
+    /// No profile with this name is defined.
+    UnknownProfile(String),
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::IO(e) => Some(e),
+            Self::Toml(e) => Some(e),
+            Self::InvalidOverride(_) | Self::InvalidProfile(_) | Self::ProfileCycle(_) | Self::UnknownProfile(_) => {
+                None
+            }
+        }
+    }
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::IO(e) => write!(f, "IO error: {e}"),
+            Self::InvalidOverride(name) => write!(f, "Environment variable {name} does not name a setting"),
+            Self::InvalidProfile(name) => write!(f, "Invalid configuration profile: {name}"),
+            Self::ProfileCycle(name) => write!(f, "Configuration profile {name} inherits from itself"),
+            Self::Toml(e) => write!(f, "TOML error: {e}"),
+            Self::UnknownProfile(name) => write!(f, "No configuration profile named {name}"),
+        }
+    }
+}
+
+impl From<IOError> for ConfigError {
+    fn from(e: IOError) -> Self {
+        Self::IO(e)
+    }
+}
+
+impl From<TomlError> for ConfigError {
+    fn from(e: TomlError) -> Self {
+        Self::Toml(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{apply_env_overrides, apply_profile, ConfigError, ServiceOptions, SigningKeyProviderConfig},
+        pretty_assertions::assert_eq,
+    };
+
+    #[test_log::test]
+    fn test_service_options() {
+        let contents = r#"
+[service.iam]
+region = "local"
+regions = ["us-west-2"]
+
+[service.iam.listener]
+allow = ["10.0.0.0/8", "::1/128"]
+deny = ["10.1.0.0/16"]
+
+[service.iam.signing_key_provider]
+type = "pkcs11"
+module = "/usr/lib/softhsm/libsofthsm2.so"
+slot = 0
+
+[service.iam.request_time]
+clock_skew_seconds = 900
+
+[service.iam.load_shedding]
+max_in_flight = 64
+
+[service.sts]
+region = "local"
+
+[service.sts.signature]
+allowed_clock_skew = "15m"
+"#;
+        let iam = ServiceOptions::from_toml_str(contents, "iam").unwrap();
+        let regions = iam.region_registry(&"local".parse().unwrap());
+        assert!(regions.contains("local"));
+        assert!(regions.contains("us-west-2"));
+        assert!(!regions.contains("us-east-1"));
+        assert_eq!(iam.listener.allow.len(), 2);
+        assert_eq!(iam.listener.deny[0].to_string(), "10.1.0.0/16");
+        match &iam.signing_key_provider {
+            SigningKeyProviderConfig::Pkcs11(pkcs11) => {
+                assert_eq!(pkcs11.slot, 0);
+                assert_eq!(pkcs11.label_prefix, "");
+            }
+            other => panic!("Unexpected signing key provider: {other:?}"),
+        }
+
+        let sts = ServiceOptions::from_toml_str(contents, "sts").unwrap();
+        assert!(sts.listener.allow.is_empty());
+        assert!(sts.listener.deny.is_empty());
+
+        assert!(matches!(sts.signing_key_provider, SigningKeyProviderConfig::Database));
+        assert!(sts.signing_key_provider.provider().unwrap().is_none());
+        #[cfg(not(feature = "pkcs11"))]
+        assert!(iam.signing_key_provider.provider().is_err());
+        assert_eq!((iam.request_time.clock_skew_seconds, sts.request_time.clock_skew_seconds), (900, 300));
+        assert_eq!((iam.request_time().clock_skew_seconds, sts.request_time().clock_skew_seconds), (900, 900));
+        assert_eq!(iam.request_time.max_expires_seconds, 604800);
+        let load_shedding = iam.load_shedding.unwrap();
+        assert_eq!((load_shedding.max_in_flight, load_shedding.max_scheduler_delay_ms), (64, 20));
+        assert!(sts.load_shedding.is_none());
+
+        let missing = ServiceOptions::from_toml_str(contents, "s3").unwrap();
+        assert!(missing.listener.allow.is_empty());
+    }
+
+    #[test_log::test]
+    fn test_env_overrides() {
+        let mut value = toml::from_str("[service.iam]\nregion = \"local\"\n").unwrap();
+        let vars = [
+            ("SCRATCHSTACK_SERVICE__IAM__REGION", "us-west-2"),
+            ("SCRATCHSTACK_SERVICE__IAM__PORT", "8180"),
+            ("SCRATCHSTACK_SERVICE__IAM__REGIONS", r#"["us-east-1"]"#),
+            ("SCRATCHSTACK_SERVICE__STS__LISTENER__ALLOW", r#"["10.0.0.0/8"]"#),
+            ("PATH", "/usr/bin"),
+        ];
+        apply_env_overrides(&mut value, vars.iter().map(|(k, v)| (k.to_string(), v.to_string()))).unwrap();
+
+        assert_eq!(value["service"]["iam"]["region"].as_str(), Some("us-west-2"));
+        assert_eq!(value["service"]["iam"]["port"].as_integer(), Some(8180));
+        assert!(ServiceOptions::from_value(&value, "iam")
+            .unwrap()
+            .region_registry(&"local".parse().unwrap())
+            .contains("us-east-1"));
+        assert_eq!(ServiceOptions::from_value(&value, "sts").unwrap().listener.allow.len(), 1);
+
+        let bad = [("SCRATCHSTACK_SERVICE__IAM__REGION__NAME".to_string(), "x".to_string())];
+        assert!(apply_env_overrides(&mut value, bad).is_err());
+    }
+
+    #[test_log::test]
+    fn test_profiles() {
+        let contents = r#"
+[service.iam]
+region = "local"
+regions = ["us-west-2"]
+skip_schema_check = false
+
+[service.iam.feature_flags.defaults]
+sigv4a = true
+
+[profile.ci.service.iam]
+skip_schema_check = true
+regions = ["us-east-1"]
+
+[profile.ci.service.iam.feature_flags.defaults]
+imds = false
+
+[profile.demo]
+inherits = "ci"
+
+[profile.demo.service.iam]
+region = "us-east-1"
+
+[profile.loop]
+inherits = "loop"
+"#;
+        let parsed: toml::Value = toml::from_str(contents).unwrap();
+
+        let mut value = parsed.clone();
+        apply_profile(&mut value, None).unwrap();
+        assert!(value.get("profile").is_none());
+        assert_eq!(value["service"]["iam"]["skip_schema_check"].as_bool(), Some(false));
+
+        let mut value = parsed.clone();
+        apply_profile(&mut value, Some("demo")).unwrap();
+        let iam = &value["service"]["iam"];
+        assert_eq!(iam["region"].as_str(), Some("us-east-1"));
+        assert_eq!(iam["skip_schema_check"].as_bool(), Some(true));
+        assert_eq!(iam["regions"].as_array().unwrap().len(), 1);
+        assert_eq!(iam["regions"][0].as_str(), Some("us-east-1"));
+
+        // Tables merge, so the base's flags are kept alongside the profile's.
+        assert_eq!(iam["feature_flags"]["defaults"]["sigv4a"].as_bool(), Some(true));
+        assert_eq!(iam["feature_flags"]["defaults"]["imds"].as_bool(), Some(false));
+        assert!(ServiceOptions::from_value(&value, "iam").unwrap().skip_schema_check);
+
+        let mut value = parsed.clone();
+        assert!(
+            matches!(apply_profile(&mut value, Some("prod")), Err(ConfigError::UnknownProfile(name)) if name == "prod")
+        );
+        let mut value = parsed;
+        assert!(
+            matches!(apply_profile(&mut value, Some("loop")), Err(ConfigError::ProfileCycle(name)) if name == "loop")
+        );
+    }
+}
@@ -0,0 +1,490 @@
+//! Simulated eventual consistency.
+//!
+//! IAM is eventually consistent: a user, role, policy or access key that was just created may not be visible to
+//! reads, or usable for signing, for a few seconds. Applications are expected to retry. With this mode enabled,
+//! Scratchstack hides each new entity from read paths until a delay after its creation time, so that retry logic can
+//! be tested.
+//!
+//! The delay for an entity is chosen between [ConsistencyConfig::min_delay_ms] and
+//! [ConsistencyConfig::max_delay_ms] from a hash of its id. The same entity therefore gets the same delay in every
+//! process, and reads never see an entity appear and then disappear again.
+use {
+    crate::store::{
+        AccessKey, Account, ControlPlaneStore, EntityKind, Group, InlinePolicy, ManagedPolicy, PolicyHolder, Role,
+        ServiceSpecificCredential, SshPublicKey, StoreError, Tag, User,
+    },
+    async_trait::async_trait,
+    chrono::{DateTime, Duration as ChronoDuration, Utc},
+    log::debug,
+    scratchstack_aws_signature::{GetSigningKeyRequest, GetSigningKeyResponse, SignatureError},
+    serde::Deserialize,
+    std::{
+        collections::hash_map::DefaultHasher,
+        future::Future,
+        hash::{Hash, Hasher},
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+    },
+    tower::{BoxError, Service},
+};
+
+/// Settings for simulated eventual consistency.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ConsistencyConfig {
+    /// The shortest time after creation before an entity is visible, in milliseconds.
+    #[serde(default)]
+    pub min_delay_ms: u64,
+
+    /// The longest time after creation before an entity is visible, in milliseconds.
+    #[serde(default = "ConsistencyConfig::default_max_delay_ms")]
+    pub max_delay_ms: u64,
+}
+
+impl ConsistencyConfig {
+    fn default_max_delay_ms() -> u64 {
+        2000
+    }
+
+    /// The visibility delay for the entity with the given id.
+    pub fn delay_for(&self, id: &str) -> ChronoDuration {
+        let span = self.max_delay_ms.saturating_sub(self.min_delay_ms);
+        let jitter = if span == 0 {
+            0
+        } else {
+            // DefaultHasher::new() uses fixed keys, so this is stable across processes.
+            let mut hasher = DefaultHasher::new();
+            id.hash(&mut hasher);
+            hasher.finish() % (span + 1)
+        };
+
+        ChronoDuration::milliseconds(i64::try_from(self.min_delay_ms + jitter).unwrap_or(i64::MAX))
+    }
+
+    /// Indicates whether an entity created at `created_at` is visible at `now`.
+    pub fn is_visible(&self, id: &str, created_at: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        now >= created_at + self.delay_for(id)
+    }
+}
+
+/// A [ControlPlaneStore] wrapper that hides newly created entities from reads until their delay has passed.
+///
+/// Writes go to the inner store immediately. Creating an entity with the name of one that is not yet visible fails
+/// with `EntityAlreadyExists`, as it would in AWS.
+#[derive(Debug)]
+pub struct EventuallyConsistentStore<S> {
+    inner: S,
+    config: ConsistencyConfig,
+}
+
+impl<S> EventuallyConsistentStore<S> {
+    pub fn new(inner: S, config: ConsistencyConfig) -> Self {
+        Self {
+            inner,
+            config,
+        }
+    }
+
+    fn visible(&self, id: &str, created_at: DateTime<Utc>) -> bool {
+        self.config.is_visible(id, created_at, Utc::now())
+    }
+
+    fn check<T>(
+        &self,
+        entity: T,
+        id: &str,
+        created_at: DateTime<Utc>,
+        kind: EntityKind,
+        name: &str,
+    ) -> Result<T, StoreError> {
+        if self.visible(id, created_at) {
+            Ok(entity)
+        } else {
+            debug!("Hiding {} {} until it is consistent", kind, name);
+            Err(StoreError::no_such_entity(kind, name))
+        }
+    }
+}
+
+#[async_trait]
+impl<S: ControlPlaneStore> ControlPlaneStore for EventuallyConsistentStore<S> {
+    async fn create_account(&self, account: &Account) -> Result<(), StoreError> {
+        self.inner.create_account(account).await
+    }
+
+    async fn get_account(&self, account_id: &str) -> Result<Account, StoreError> {
+        self.inner.get_account(account_id).await
+    }
+
+    async fn set_account_alias(&self, account_id: &str, alias: Option<&str>) -> Result<(), StoreError> {
+        self.inner.set_account_alias(account_id, alias).await
+    }
+
+    async fn create_user(&self, user: &User, tags: &[Tag]) -> Result<(), StoreError> {
+        self.inner.create_user(user, tags).await
+    }
+
+    async fn get_user(&self, account_id: &str, user_name: &str) -> Result<User, StoreError> {
+        let user = self.inner.get_user(account_id, user_name).await?;
+        let (id, created_at) = (user.user_id.clone(), user.created_at);
+        self.check(user, &id, created_at, EntityKind::User, user_name)
+    }
+
+    async fn list_users(&self, account_id: &str, path_prefix: &str) -> Result<Vec<User>, StoreError> {
+        let mut users = self.inner.list_users(account_id, path_prefix).await?;
+        users.retain(|user| self.visible(&user.user_id, user.created_at));
+        Ok(users)
+    }
+
+    async fn update_user(
+        &self,
+        account_id: &str,
+        user_name: &str,
+        new_user_name: Option<&str>,
+        new_path: Option<&str>,
+    ) -> Result<User, StoreError> {
+        self.get_user(account_id, user_name).await?;
+        self.inner.update_user(account_id, user_name, new_user_name, new_path).await
+    }
+
+    async fn delete_user(&self, account_id: &str, user_name: &str) -> Result<(), StoreError> {
+        self.get_user(account_id, user_name).await?;
+        self.inner.delete_user(account_id, user_name).await
+    }
+
+    async fn create_group(&self, group: &Group) -> Result<(), StoreError> {
+        self.inner.create_group(group).await
+    }
+
+    async fn get_group(&self, account_id: &str, group_name: &str) -> Result<Group, StoreError> {
+        let group = self.inner.get_group(account_id, group_name).await?;
+        let (id, created_at) = (group.group_id.clone(), group.created_at);
+        self.check(group, &id, created_at, EntityKind::Group, group_name)
+    }
+
+    async fn delete_group(&self, account_id: &str, group_name: &str) -> Result<(), StoreError> {
+        self.get_group(account_id, group_name).await?;
+        self.inner.delete_group(account_id, group_name).await
+    }
+
+    async fn add_user_to_group(&self, group_id: &str, user_id: &str) -> Result<(), StoreError> {
+        self.inner.add_user_to_group(group_id, user_id).await
+    }
+
+    async fn remove_user_from_group(&self, group_id: &str, user_id: &str) -> Result<(), StoreError> {
+        self.inner.remove_user_from_group(group_id, user_id).await
+    }
+
+    async fn list_groups_for_user(&self, user_id: &str) -> Result<Vec<Group>, StoreError> {
+        let mut groups = self.inner.list_groups_for_user(user_id).await?;
+        groups.retain(|group| self.visible(&group.group_id, group.created_at));
+        Ok(groups)
+    }
+
+    async fn create_role(&self, role: &Role, tags: &[Tag]) -> Result<(), StoreError> {
+        self.inner.create_role(role, tags).await
+    }
+
+    async fn get_role(&self, account_id: &str, role_name: &str) -> Result<Role, StoreError> {
+        let role = self.inner.get_role(account_id, role_name).await?;
+        let (id, created_at) = (role.role_id.clone(), role.created_at);
+        self.check(role, &id, created_at, EntityKind::Role, role_name)
+    }
+
+    async fn get_role_by_id(&self, account_id: &str, role_id: &str) -> Result<Role, StoreError> {
+        let role = self.inner.get_role_by_id(account_id, role_id).await?;
+        let (name, created_at) = (role.role_name.clone(), role.created_at);
+        self.check(role, role_id, created_at, EntityKind::Role, &name)
+    }
+
+    async fn list_roles(&self, account_id: &str, path_prefix: &str) -> Result<Vec<Role>, StoreError> {
+        let mut roles = self.inner.list_roles(account_id, path_prefix).await?;
+        roles.retain(|role| self.visible(&role.role_id, role.created_at));
+        Ok(roles)
+    }
+
+    async fn delete_role(&self, account_id: &str, role_name: &str) -> Result<(), StoreError> {
+        self.get_role(account_id, role_name).await?;
+        self.inner.delete_role(account_id, role_name).await
+    }
+
+    async fn create_policy(&self, policy: &ManagedPolicy, policy_document: &str) -> Result<(), StoreError> {
+        self.inner.create_policy(policy, policy_document).await
+    }
+
+    async fn get_policy(&self, account_id: &str, policy_name: &str) -> Result<ManagedPolicy, StoreError> {
+        let policy = self.inner.get_policy(account_id, policy_name).await?;
+        let (id, created_at) = (policy.managed_policy_id.clone(), policy.created_at);
+        self.check(policy, &id, created_at, EntityKind::Policy, policy_name)
+    }
+
+    async fn list_policies(&self, account_id: &str, path_prefix: &str) -> Result<Vec<ManagedPolicy>, StoreError> {
+        let mut policies = self.inner.list_policies(account_id, path_prefix).await?;
+        policies.retain(|policy| self.visible(&policy.managed_policy_id, policy.created_at));
+        Ok(policies)
+    }
+
+    async fn get_policy_version(&self, managed_policy_id: &str, version: i64) -> Result<String, StoreError> {
+        self.inner.get_policy_version(managed_policy_id, version).await
+    }
+
+    async fn create_policy_version(
+        &self,
+        managed_policy_id: &str,
+        policy_document: &str,
+        set_as_default: bool,
+        max_versions: usize,
+    ) -> Result<i64, StoreError> {
+        self.inner.create_policy_version(managed_policy_id, policy_document, set_as_default, max_versions).await
+    }
+
+    async fn delete_policy(&self, account_id: &str, policy_name: &str) -> Result<(), StoreError> {
+        self.get_policy(account_id, policy_name).await?;
+        self.inner.delete_policy(account_id, policy_name).await
+    }
+
+    async fn attach_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        managed_policy_id: &str,
+        max_attached: usize,
+    ) -> Result<(), StoreError> {
+        self.inner.attach_policy(holder, holder_id, managed_policy_id, max_attached).await
+    }
+
+    async fn detach_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        managed_policy_id: &str,
+    ) -> Result<(), StoreError> {
+        self.inner.detach_policy(holder, holder_id, managed_policy_id).await
+    }
+
+    async fn list_attached_policies(&self, holder: PolicyHolder, holder_id: &str) -> Result<Vec<String>, StoreError> {
+        self.inner.list_attached_policies(holder, holder_id).await
+    }
+
+    async fn count_policy_attachments(&self, managed_policy_id: &str) -> Result<usize, StoreError> {
+        self.inner.count_policy_attachments(managed_policy_id).await
+    }
+
+    async fn detach_policy_from_all(&self, managed_policy_id: &str) -> Result<usize, StoreError> {
+        self.inner.detach_policy_from_all(managed_policy_id).await
+    }
+
+    async fn put_inline_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        policy: &InlinePolicy,
+    ) -> Result<(), StoreError> {
+        self.inner.put_inline_policy(holder, holder_id, policy).await
+    }
+
+    async fn get_inline_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        policy_name: &str,
+    ) -> Result<InlinePolicy, StoreError> {
+        self.inner.get_inline_policy(holder, holder_id, policy_name).await
+    }
+
+    async fn list_inline_policies(&self, holder: PolicyHolder, holder_id: &str) -> Result<Vec<String>, StoreError> {
+        self.inner.list_inline_policies(holder, holder_id).await
+    }
+
+    async fn delete_inline_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        policy_name: &str,
+    ) -> Result<(), StoreError> {
+        self.inner.delete_inline_policy(holder, holder_id, policy_name).await
+    }
+
+    async fn list_tags(&self, kind: EntityKind, entity_id: &str) -> Result<Vec<Tag>, StoreError> {
+        self.inner.list_tags(kind, entity_id).await
+    }
+
+    async fn create_access_key(&self, access_key: &AccessKey) -> Result<(), StoreError> {
+        self.inner.create_access_key(access_key).await
+    }
+
+    async fn get_access_key(&self, access_key_id: &str) -> Result<AccessKey, StoreError> {
+        let access_key = self.inner.get_access_key(access_key_id).await?;
+        let created_at = access_key.created_at;
+        self.check(access_key, access_key_id, created_at, EntityKind::AccessKey, access_key_id)
+    }
+
+    async fn list_access_keys(&self, user_id: &str) -> Result<Vec<AccessKey>, StoreError> {
+        let mut access_keys = self.inner.list_access_keys(user_id).await?;
+        access_keys.retain(|access_key| self.visible(&access_key.access_key_id, access_key.created_at));
+        Ok(access_keys)
+    }
+
+    async fn set_access_key_active(&self, user_id: &str, access_key_id: &str, active: bool) -> Result<(), StoreError> {
+        self.inner.set_access_key_active(user_id, access_key_id, active).await
+    }
+
+    async fn delete_access_key(&self, user_id: &str, access_key_id: &str) -> Result<(), StoreError> {
+        self.inner.delete_access_key(user_id, access_key_id).await
+    }
+
+    async fn create_ssh_public_key(&self, ssh_public_key: &SshPublicKey) -> Result<(), StoreError> {
+        self.inner.create_ssh_public_key(ssh_public_key).await
+    }
+
+    async fn list_ssh_public_keys(&self, user_id: &str) -> Result<Vec<SshPublicKey>, StoreError> {
+        self.inner.list_ssh_public_keys(user_id).await
+    }
+
+    async fn set_ssh_public_key_active(
+        &self,
+        user_id: &str,
+        ssh_public_key_id: &str,
+        active: bool,
+    ) -> Result<(), StoreError> {
+        self.inner.set_ssh_public_key_active(user_id, ssh_public_key_id, active).await
+    }
+
+    async fn delete_ssh_public_key(&self, user_id: &str, ssh_public_key_id: &str) -> Result<(), StoreError> {
+        self.inner.delete_ssh_public_key(user_id, ssh_public_key_id).await
+    }
+
+    async fn create_service_specific_credential(
+        &self,
+        credential: &ServiceSpecificCredential,
+    ) -> Result<(), StoreError> {
+        self.inner.create_service_specific_credential(credential).await
+    }
+
+    async fn list_service_specific_credentials(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<ServiceSpecificCredential>, StoreError> {
+        self.inner.list_service_specific_credentials(user_id).await
+    }
+
+    async fn reset_service_specific_credential(
+        &self,
+        user_id: &str,
+        service_specific_credential_id: &str,
+        service_password: &str,
+    ) -> Result<ServiceSpecificCredential, StoreError> {
+        self.inner.reset_service_specific_credential(user_id, service_specific_credential_id, service_password).await
+    }
+
+    async fn delete_service_specific_credential(
+        &self,
+        user_id: &str,
+        service_specific_credential_id: &str,
+    ) -> Result<(), StoreError> {
+        self.inner.delete_service_specific_credential(user_id, service_specific_credential_id).await
+    }
+}
+
+/// Wraps a signing key service, rejecting requests signed with a long-term access key that was created too
+/// recently to be visible.
+///
+/// Without a configuration, requests are passed through unchanged.
+#[derive(Clone, Debug)]
+pub struct DelayNewCredentials<G> {
+    inner: G,
+    visibility: Option<(Arc<dyn ControlPlaneStore>, ConsistencyConfig)>,
+}
+
+impl<G> DelayNewCredentials<G> {
+    pub fn new(inner: G, store: Arc<dyn ControlPlaneStore>, config: Option<ConsistencyConfig>) -> Self {
+        Self {
+            inner,
+            visibility: config.map(|config| (store, config)),
+        }
+    }
+}
+
+impl<G> Service<GetSigningKeyRequest> for DelayNewCredentials<G>
+where
+    G: Service<GetSigningKeyRequest, Response = GetSigningKeyResponse> + Clone + Send + 'static,
+    G::Error: Into<BoxError>,
+    G::Future: Send + 'static,
+{
+    type Response = GetSigningKeyResponse;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: GetSigningKeyRequest) -> Self::Future {
+        let (store, config) = match &self.visibility {
+            Some((store, config)) if req.access_key().starts_with("AKIA") => (store.clone(), config.clone()),
+            _ => {
+                let future = self.inner.call(req);
+                return Box::pin(async move { future.await.map_err(Into::into) });
+            }
+        };
+
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        Box::pin(async move {
+            // The database stores access key ids without their AKIA prefix.
+            let access_key_id = req.access_key().trim_start_matches("AKIA").to_string();
+            if let Ok(access_key) = store.get_access_key(&access_key_id).await {
+                if !config.is_visible(&access_key_id, access_key.created_at, Utc::now()) {
+                    debug!("Access key {} is not yet consistent", req.access_key());
+                    return Err(SignatureError::InvalidClientTokenId(
+                        "The security token included in the request is invalid.".to_string(),
+                    )
+                    .into());
+                }
+            }
+
+            inner.call(req).await.map_err(Into::into)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{ConsistencyConfig, EventuallyConsistentStore},
+        crate::store::{ControlPlaneStore, MemoryStore, User},
+        chrono::{Duration, Utc},
+        pretty_assertions::assert_eq,
+    };
+
+    #[test_log::test(tokio::test)]
+    async fn test_visibility() {
+        let config = ConsistencyConfig {
+            min_delay_ms: 1000,
+            max_delay_ms: 3000,
+        };
+        let delay = config.delay_for("AIDAEXAMPLEUSER1");
+        assert!(delay >= Duration::seconds(1) && delay <= Duration::seconds(3));
+        assert_eq!(config.delay_for("AIDAEXAMPLEUSER1"), delay);
+
+        let store = EventuallyConsistentStore::new(MemoryStore::new(), config);
+        let user = |user_id: &str, user_name: &str, age: Duration| User {
+            user_id: user_id.to_string(),
+            account_id: "123456789012".to_string(),
+            user_name: user_name.to_string(),
+            path: "/".to_string(),
+            permissions_boundary: None,
+            created_at: Utc::now() - age,
+        };
+        store.create_user(&user("AIDAEXAMPLEUSER1", "Alice", Duration::zero()), &[]).await.unwrap();
+        store.create_user(&user("AIDAEXAMPLEUSER2", "Bob", Duration::minutes(1)), &[]).await.unwrap();
+
+        assert_eq!(store.get_user("123456789012", "alice").await.unwrap_err().code(), "NoSuchEntity");
+        assert_eq!(store.get_user("123456789012", "bob").await.unwrap().user_id, "AIDAEXAMPLEUSER2");
+        assert_eq!(store.list_users("123456789012", "/").await.unwrap().len(), 1);
+
+        // The name is taken even though the user is not yet visible.
+        let e = store.create_user(&user("AIDAEXAMPLEUSER3", "alice", Duration::zero()), &[]).await.unwrap_err();
+        assert_eq!(e.code(), "EntityAlreadyExists");
+    }
+}
@@ -0,0 +1,277 @@
+use {
+    crate::{
+        actions::ConditionKeys,
+        forward,
+        net::ConnectionInfo,
+        region::RegionRegistry,
+        session_keys::{get_string, AWS_REQUESTED_REGION, AWS_USERID},
+        token::PackedClaims,
+    },
+    derive_builder::Builder,
+    http::request::Parts,
+    log::warn,
+    scratchstack_arn::Arn,
+    scratchstack_aws_principal::{Principal, SessionData},
+    scratchstack_http_framework::RequestId,
+    std::{collections::HashMap, net::IpAddr, sync::Arc},
+};
+
+/// Everything an operation handler needs to know about an authenticated request.
+///
+/// This is assembled from the extensions added by the HTTP framework (principal, session data, request id) and by
+/// [WithConnectionInfo][crate::net::WithConnectionInfo], along with the request parameters parsed by the service.
+#[derive(Builder, Clone, Debug)]
+pub struct RequestContext {
+    principal: Principal,
+
+    #[builder(default)]
+    session_data: SessionData,
+
+    #[builder(default)]
+    packed_claims: PackedClaims,
+
+    #[builder(default = "RequestId::new()")]
+    request_id: RequestId,
+
+    #[builder(setter(into, strip_option), default)]
+    region: Option<String>,
+
+    #[builder(setter(strip_option), default)]
+    regions: Option<Arc<RegionRegistry>>,
+
+    #[builder(setter(strip_option), default)]
+    source_ip: Option<IpAddr>,
+
+    #[builder(default)]
+    secure_transport: bool,
+
+    #[builder(default)]
+    parameters: HashMap<String, String>,
+}
+
+impl RequestContext {
+    /// Create a builder for a request context. This is intended for tests; services should use
+    /// [RequestContext::from_parts].
+    pub fn builder() -> RequestContextBuilder {
+        RequestContextBuilder::default()
+    }
+
+    /// Assemble the context for a request. This returns `None` if the request has not been authenticated.
+    pub fn from_parts(parts: &Parts, parameters: HashMap<String, String>) -> Option<Self> {
+        let principal = parts.extensions.get::<Principal>()?.clone();
+        Some(Self::with_principal(parts, principal, parameters))
+    }
+
+    /// Assemble the context for a request to one of the [anonymous][crate::anonymous] actions, whose callers are
+    /// authenticated by the operation itself. The principal is empty, so [RequestContext::caller_arn] is `None`.
+    pub fn anonymous(parts: &Parts, parameters: HashMap<String, String>) -> Self {
+        Self::with_principal(parts, Principal::from(Vec::new()), parameters)
+    }
+
+    fn with_principal(parts: &Parts, principal: Principal, parameters: HashMap<String, String>) -> Self {
+        let session_data = parts.extensions.get::<SessionData>().cloned().unwrap_or_default();
+        let packed_claims = parts.extensions.get::<PackedClaims>().cloned().unwrap_or_default();
+        let request_id = parts.extensions.get::<RequestId>().copied().unwrap_or_else(RequestId::new);
+        let regions = parts.extensions.get::<Arc<RegionRegistry>>().cloned();
+        let region = match get_string(&session_data, AWS_REQUESTED_REGION) {
+            Ok(Some(region)) if regions.as_ref().map(|regions| !regions.contains(region)).unwrap_or(false) => {
+                warn!("{} Request region {} is not an accepted region", request_id, region);
+                None
+            }
+            Ok(region) => region.map(ToString::to_string),
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        };
+        let connection = parts.extensions.get::<ConnectionInfo>();
+
+        Self {
+            principal,
+            session_data,
+            packed_claims,
+            request_id,
+            region,
+            regions,
+            source_ip: connection.map(ConnectionInfo::source_ip),
+            secure_transport: connection.map(ConnectionInfo::is_secure).unwrap_or(false),
+            parameters,
+        }
+    }
+
+    pub fn principal(&self) -> &Principal {
+        &self.principal
+    }
+
+    pub fn session_data(&self) -> &SessionData {
+        &self.session_data
+    }
+
+    /// The session policies and tags of the caller's session, as added by
+    /// [ValidateSessionTokens][crate::token::ValidateSessionTokens]. These are empty unless the request was signed
+    /// with temporary credentials.
+    pub fn packed_claims(&self) -> &PackedClaims {
+        &self.packed_claims
+    }
+
+    /// Add the service-specific condition keys supplied by the handler to the session data policies are evaluated
+    /// against.
+    pub fn add_condition_keys(&mut self, keys: ConditionKeys) {
+        keys.add_to_session_data(&mut self.session_data);
+    }
+
+    pub fn request_id(&self) -> RequestId {
+        self.request_id
+    }
+
+    /// The region from the request's credential scope. This is `None` if the region is not in
+    /// [RequestContext::regions].
+    pub fn region(&self) -> Option<&str> {
+        self.region.as_deref()
+    }
+
+    /// The regions the service accepts requests for, as added by
+    /// [AuthorizeRequests::with_regions][crate::authorizer::AuthorizeRequests::with_regions].
+    pub fn regions(&self) -> Option<&RegionRegistry> {
+        self.regions.as_deref()
+    }
+
+    pub fn source_ip(&self) -> Option<IpAddr> {
+        self.source_ip
+    }
+
+    /// Indicates whether the request was received over TLS, either directly or by a trusted proxy.
+    pub fn secure_transport(&self) -> bool {
+        self.secure_transport
+    }
+
+    pub fn parameters(&self) -> &HashMap<String, String> {
+        &self.parameters
+    }
+
+    pub fn parameter(&self, name: &str) -> Option<&str> {
+        self.parameters.get(name).map(String::as_str)
+    }
+
+    /// The unique id of the caller (`aws:userid`), if the signing key service supplied one.
+    pub fn user_id(&self) -> Option<&str> {
+        match get_string(&self.session_data, AWS_USERID) {
+            Ok(user_id) => user_id,
+            Err(e) => {
+                warn!("{}", e);
+                None
+            }
+        }
+    }
+
+    /// Indicates whether this request was made by a service on behalf of the principal.
+    pub fn via_aws_service(&self) -> bool {
+        forward::via_aws_service(&self.session_data)
+    }
+
+    /// The service principals that made this request on behalf of the principal, in order.
+    pub fn called_via(&self) -> Vec<String> {
+        forward::called_via(&self.session_data)
+    }
+
+    /// The ARN of the first principal identity that has one.
+    pub fn caller_arn(&self) -> Option<Arn> {
+        for identity in &self.principal {
+            if identity.has_arn() {
+                if let Ok(arn) = Arn::try_from(identity) {
+                    return Some(arn);
+                }
+            }
+        }
+
+        None
+    }
+
+    /// Indicates whether the caller is an account root user (`arn:<partition>:iam::<account>:root`).
+    pub fn is_root(&self) -> bool {
+        self.caller_arn().map(|arn| arn.service() == "iam" && arn.resource() == "root").unwrap_or(false)
+    }
+
+    /// The account of the caller, taken from [RequestContext::caller_arn].
+    pub fn account_id(&self) -> Option<String> {
+        self.caller_arn().map(|arn| arn.account_id().to_string())
+    }
+
+    /// The partition of the caller, taken from [RequestContext::caller_arn], defaulting to `aws`.
+    pub fn partition(&self) -> String {
+        self.caller_arn().map(|arn| arn.partition().to_string()).unwrap_or_else(|| "aws".to_string())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::RequestContext,
+        crate::{region::RegionRegistry, session_keys::AWS_REQUESTED_REGION},
+        http::Request,
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{Principal, PrincipalIdentity, SessionData, SessionValue, User},
+        std::{collections::HashMap, sync::Arc},
+    };
+
+    #[test_log::test]
+    fn test_accessors() {
+        let user = User::new("aws", "123456789012", "/", "alice").unwrap();
+        let principal = Principal::from(vec![PrincipalIdentity::from(user)]);
+        let mut parameters = HashMap::new();
+        parameters.insert("Action".to_string(), "GetCallerIdentity".to_string());
+
+        let context = RequestContext::builder()
+            .principal(principal)
+            .region("us-west-2")
+            .source_ip("192.0.2.1".parse().unwrap())
+            .parameters(parameters)
+            .build()
+            .unwrap();
+
+        assert_eq!(context.caller_arn().unwrap().to_string(), "arn:aws:iam::123456789012:user/alice");
+        assert_eq!(context.account_id().as_deref(), Some("123456789012"));
+        assert_eq!(context.partition(), "aws");
+        assert_eq!(context.region(), Some("us-west-2"));
+        assert_eq!(context.parameter("Action"), Some("GetCallerIdentity"));
+        assert_eq!(context.parameter("Version"), None);
+        assert_eq!(context.user_id(), None);
+        assert!(!context.secure_transport());
+    }
+
+    #[test_log::test]
+    fn test_anonymous() {
+        let (parts, ()) = Request::builder().uri("/").body(()).unwrap().into_parts();
+        let mut parameters = HashMap::new();
+        parameters.insert("Action".to_string(), "AssumeRoleWithWebIdentity".to_string());
+
+        assert!(RequestContext::from_parts(&parts, parameters.clone()).is_none());
+        let context = RequestContext::anonymous(&parts, parameters);
+        assert!(context.caller_arn().is_none());
+        assert_eq!(context.parameter("Action"), Some("AssumeRoleWithWebIdentity"));
+    }
+
+    #[test_log::test]
+    fn test_regions() {
+        let user = User::new("aws", "123456789012", "/", "alice").unwrap();
+        let mut session_data = SessionData::new();
+        session_data.insert(AWS_REQUESTED_REGION, SessionValue::String("us-east-1".to_string()));
+        let (mut parts, ()) = Request::builder().uri("/").body(()).unwrap().into_parts();
+        parts.extensions.insert(Principal::from(vec![PrincipalIdentity::from(user)]));
+        parts.extensions.insert(session_data);
+
+        // Without a registry, the region is reported as given.
+        let context = RequestContext::from_parts(&parts, HashMap::new()).unwrap();
+        assert_eq!(context.region(), Some("us-east-1"));
+        assert!(context.regions().is_none());
+
+        parts.extensions.insert(Arc::new(RegionRegistry::new(vec!["us-west-2".parse().unwrap()])));
+        let context = RequestContext::from_parts(&parts, HashMap::new()).unwrap();
+        assert_eq!(context.region(), None);
+        assert_eq!(context.regions().map(RegionRegistry::len), Some(1));
+
+        parts.extensions.insert(Arc::new(RegionRegistry::new(vec!["us-east-1".parse().unwrap()])));
+        let context = RequestContext::from_parts(&parts, HashMap::new()).unwrap();
+        assert_eq!(context.region(), Some("us-east-1"));
+    }
+}
@@ -0,0 +1,92 @@
+//! The HMAC, SHA-256 and signature verification primitives used by the signature stack.
+//!
+//! Signing key derivation, response signing, mirrored request hashing and OIDC token verification all go through
+//! here, so the provider behind them is chosen in one place at build time: [ring] by default, or the FIPS-validated
+//! build of `aws-lc-rs` with the `fips` feature. Both expose the same API, and the tests below are known answers that
+//! every backend must reproduce; run them with `--features fips` to check the FIPS build.
+#[cfg(feature = "fips")]
+use aws_lc_rs as backend;
+#[cfg(not(feature = "fips"))]
+use ring as backend;
+
+use backend::{digest, hmac, signature};
+
+/// The name of the crypto provider this build uses.
+#[cfg(feature = "fips")]
+pub const BACKEND: &str = "aws-lc-rs (FIPS)";
+
+/// The name of the crypto provider this build uses.
+#[cfg(not(feature = "fips"))]
+pub const BACKEND: &str = "ring";
+
+/// HMAC-SHA256 of `data`, keyed with `key`.
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> [u8; 32] {
+    let key = hmac::Key::new(hmac::HMAC_SHA256, key);
+    let tag = hmac::sign(&key, data);
+    let mut result = [0u8; 32];
+    result.copy_from_slice(tag.as_ref());
+    result
+}
+
+/// The SHA-256 digest of `data`.
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    let digest = digest::digest(&digest::SHA256, data);
+    let mut result = [0u8; 32];
+    result.copy_from_slice(digest.as_ref());
+    result
+}
+
+/// Verify an RSASSA-PKCS1-v1_5 signature over the SHA-256 digest of `message` (JWS `RS256`) with the RSA public
+/// key whose modulus and exponent are `n` and `e`, as big-endian bytes.
+pub fn verify_rs256(n: &[u8], e: &[u8], message: &[u8], sig: &[u8]) -> bool {
+    let key = signature::RsaPublicKeyComponents {
+        n,
+        e,
+    };
+    key.verify(&signature::RSA_PKCS1_2048_8192_SHA256, message, sig).is_ok()
+}
+
+/// Verify an ECDSA P-256 signature over the SHA-256 digest of `message` in the fixed-length `r || s` form (JWS
+/// `ES256`) with the uncompressed public key point `public_key`.
+pub fn verify_es256(public_key: &[u8], message: &[u8], sig: &[u8]) -> bool {
+    signature::UnparsedPublicKey::new(&signature::ECDSA_P256_SHA256_FIXED, public_key).verify(message, sig).is_ok()
+}
+
+/// `bytes` as lowercase hex.
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{hex, hmac_sha256, sha256},
+        crate::signing::{derive_from_date_key, SigningKeyScope},
+        chrono::NaiveDate,
+        pretty_assertions::assert_eq,
+    };
+
+    #[test_log::test]
+    fn test_known_answers() {
+        assert_eq!(hex(&sha256(b"")), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        assert_eq!(hex(&sha256(b"abc")), "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad");
+
+        // RFC 4231 test cases 2 and 6.
+        assert_eq!(
+            hex(&hmac_sha256(b"Jefe", b"what do ya want for nothing?")),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+        assert_eq!(
+            hex(&hmac_sha256(&[0xaa; 131], b"Test Using Larger Than Block-Size Key - Hash Key First")),
+            "60e431591ee0b67f0d8a26aacbf5b77f8e0bc6213728c5140546040f0ee37f54"
+        );
+
+        // The signing key example from the AWS SigV4 documentation.
+        let k_date = hmac_sha256(b"AWS4wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY", b"20120215");
+        let scope = SigningKeyScope::new(NaiveDate::from_ymd_opt(2012, 2, 15).unwrap(), "us-east-1", "iam");
+        assert_eq!(
+            hex(&derive_from_date_key(&k_date, &scope)),
+            "f4780e2d9f65fa895f9c67b32ce1baf0b0d8a43505a000a1a9e090d414db404d"
+        );
+    }
+}
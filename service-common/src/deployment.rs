@@ -0,0 +1,251 @@
+//! What is running where: build metadata and uptime.
+//!
+//! `GET /version` answers with [DeploymentInfo] as JSON. Like the health endpoints, it is answered before signature
+//! verification. The git commit and build timestamp are set by each service's build script through `vergen`; they
+//! are omitted if the service was built outside of a git checkout.
+use {
+    crate::region::Region,
+    chrono::{DateTime, SecondsFormat, Utc},
+    http::{header::HeaderValue, Method, StatusCode},
+    hyper::{service::Service, Body, Request, Response},
+    serde::Serialize,
+    std::{
+        collections::BTreeMap,
+        fmt::{Display, Formatter, Result as FmtResult},
+        future::Future,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+        time::{Duration, Instant},
+    },
+    tower::BoxError,
+};
+
+/// Cargo features enabled in this crate.
+pub const ENABLED_FEATURES: &[&str] = &[
+    #[cfg(feature = "pkcs11")]
+    "pkcs11",
+];
+
+/// Build metadata for a service binary.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct BuildInfo {
+    pub service: &'static str,
+    pub version: &'static str,
+    pub git_sha: Option<&'static str>,
+    pub build_timestamp: Option<&'static str>,
+
+    /// Versions of the Scratchstack crates linked into the binary.
+    pub crates: BTreeMap<&'static str, &'static str>,
+    pub features: Vec<&'static str>,
+}
+
+impl BuildInfo {
+    /// Create the build metadata for `service`. Call this from the service's own crate with
+    /// `env!("CARGO_PKG_NAME")`, `env!("CARGO_PKG_VERSION")`, `option_env!("VERGEN_GIT_SHA")` and
+    /// `option_env!("VERGEN_BUILD_TIMESTAMP")` so the values describe the service rather than this crate.
+    pub fn new(
+        service: &'static str,
+        package: &'static str,
+        version: &'static str,
+        git_sha: Option<&'static str>,
+        build_timestamp: Option<&'static str>,
+    ) -> Self {
+        let mut crates = BTreeMap::new();
+        crates.insert(package, version);
+        crates.insert(env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+        Self {
+            service,
+            version,
+            git_sha,
+            build_timestamp,
+            crates,
+            features: ENABLED_FEATURES.to_vec(),
+        }
+    }
+}
+
+/// A running service instance.
+#[derive(Debug)]
+pub struct Deployment {
+    build: BuildInfo,
+    region: Region,
+    started_at: DateTime<Utc>,
+    started: Instant,
+}
+
+impl Deployment {
+    pub fn new(build: BuildInfo, region: &Region) -> Self {
+        Self {
+            build,
+            region: region.clone(),
+            started_at: Utc::now(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn build(&self) -> &BuildInfo {
+        &self.build
+    }
+
+    pub fn uptime(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// A snapshot of the deployment, for responses.
+    pub fn info(&self) -> DeploymentInfo {
+        DeploymentInfo {
+            service: self.build.service.to_string(),
+            version: self.build.version.to_string(),
+            git_sha: self.build.git_sha.map(ToString::to_string),
+            build_timestamp: self.build.build_timestamp.map(ToString::to_string),
+            crates: self.build.crates.iter().map(|(name, version)| (name.to_string(), version.to_string())).collect(),
+            features: self.build.features.iter().map(ToString::to_string).collect(),
+            region: self.region.to_string(),
+            started_at: self.started_at.to_rfc3339_opts(SecondsFormat::Secs, true),
+            uptime_seconds: self.uptime().as_secs(),
+        }
+    }
+}
+
+impl Display for Deployment {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(
+            f,
+            "{} {} (git {}, built {}) in {}",
+            self.build.service,
+            self.build.version,
+            self.build.git_sha.unwrap_or("unknown"),
+            self.build.build_timestamp.unwrap_or("unknown"),
+            self.region
+        )?;
+
+        for (name, version) in &self.build.crates {
+            write!(f, "; {name} {version}")?;
+        }
+
+        if self.build.features.is_empty() {
+            write!(f, "; no optional features")
+        } else {
+            write!(f, "; features: {}", self.build.features.join(", "))
+        }
+    }
+}
+
+/// The body of `GET /version`.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize)]
+pub struct DeploymentInfo {
+    pub service: String,
+    pub version: String,
+    pub git_sha: Option<String>,
+    pub build_timestamp: Option<String>,
+    pub crates: BTreeMap<String, String>,
+    pub features: Vec<String>,
+    pub region: String,
+    pub started_at: String,
+    pub uptime_seconds: u64,
+}
+
+/// Wraps a make-service (such as `SpawnService`) so each per-connection service answers `/version`.
+#[derive(Clone, Debug)]
+pub struct WithVersionEndpoint<M> {
+    inner: M,
+    deployment: Arc<Deployment>,
+}
+
+impl<M> WithVersionEndpoint<M> {
+    pub fn new(inner: M, deployment: Arc<Deployment>) -> Self {
+        Self {
+            inner,
+            deployment,
+        }
+    }
+}
+
+impl<T, M> Service<T> for WithVersionEndpoint<M>
+where
+    M: Service<T>,
+    M::Future: Send + 'static,
+{
+    type Response = VersionEndpoint<M::Response>;
+    type Error = M::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let deployment = self.deployment.clone();
+        let future = self.inner.call(target);
+        Box::pin(async move {
+            Ok(VersionEndpoint {
+                inner: future.await?,
+                deployment,
+            })
+        })
+    }
+}
+
+/// A per-connection service that answers `/version` and passes other requests through.
+#[derive(Clone, Debug)]
+pub struct VersionEndpoint<S> {
+    inner: S,
+    deployment: Arc<Deployment>,
+}
+
+impl<S> Service<Request<Body>> for VersionEndpoint<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if (req.method() == Method::GET || req.method() == Method::HEAD) && req.uri().path() == "/version" {
+            let info = self.deployment.info();
+            return Box::pin(async move {
+                Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", HeaderValue::from_static("application/json"))
+                    .header("Cache-Control", HeaderValue::from_static("no-store"))
+                    .body(Body::from(serde_json::to_string(&info)?))?)
+            });
+        }
+
+        let future = self.inner.call(req);
+        Box::pin(async move { future.await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{BuildInfo, Deployment},
+        pretty_assertions::assert_eq,
+    };
+
+    #[test_log::test]
+    fn test_deployment_info() {
+        let build = BuildInfo::new("sts", "scratchstack-service-sts", "1.2.3", Some("0123abcd"), None);
+        let deployment = Deployment::new(build, &"us-west-2".parse().unwrap());
+        let info = deployment.info();
+        assert_eq!(info.version, "1.2.3");
+        assert_eq!(info.git_sha.as_deref(), Some("0123abcd"));
+        assert_eq!(info.crates["scratchstack-service-sts"], "1.2.3");
+        assert!(info.crates.contains_key("scratchstack-service-common"));
+
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["region"], "us-west-2");
+        assert_eq!(json["build_timestamp"], serde_json::Value::Null);
+        assert!(deployment.to_string().starts_with("sts 1.2.3 (git 0123abcd, built unknown) in us-west-2"));
+    }
+}
@@ -0,0 +1,213 @@
+//! Deprecation and compatibility warnings.
+//!
+//! Each service lists the operations and parameters it is phasing out in a table of [Deprecation]s. A request that
+//! uses one is answered as usual with a `Warning` header per deprecation (RFC 7234 warn-code 299), counted in
+//! [Deprecations::render], and logged to [AUDIT_TARGET] with the caller, so operators can find the clients that still
+//! need to migrate. With [DeprecationMode::Strict], such requests are rejected with `ValidationError` instead, which
+//! lets a deployment check that nothing depends on deprecated behavior before upgrading to a version that removes it.
+use {
+    crate::{authz::AUDIT_TARGET, context::RequestContext, protocol::AwsError},
+    http::{
+        header::{HeaderValue, WARNING},
+        StatusCode,
+    },
+    hyper::{Body, Response},
+    log::warn,
+    serde::Deserialize,
+    std::{collections::BTreeMap, fmt::Write, sync::Mutex},
+};
+
+/// An operation, or a parameter of one, that will be removed in a later version.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct Deprecation {
+    /// A stable identifier, used as the metric label.
+    pub id: &'static str,
+    pub action: &'static str,
+
+    /// The deprecated parameter; if `None`, the whole operation is deprecated.
+    pub parameter: Option<&'static str>,
+
+    /// The Scratchstack version that deprecated it.
+    pub since: &'static str,
+
+    /// What to do instead.
+    pub guidance: &'static str,
+}
+
+impl Deprecation {
+    /// Indicates whether a request for `action` with `parameters` uses this.
+    pub fn applies(&self, action: &str, parameters: &BTreeMap<&str, &str>) -> bool {
+        self.action == action && self.parameter.map(|parameter| parameters.contains_key(parameter)).unwrap_or(true)
+    }
+
+    /// A sentence describing the deprecation, e.g. for the `Warning` header.
+    pub fn message(&self) -> String {
+        match self.parameter {
+            None => format!("{} is deprecated since Scratchstack {}: {}", self.action, self.since, self.guidance),
+            Some(parameter) => format!(
+                "The {} parameter of {} is deprecated since Scratchstack {}: {}",
+                parameter, self.action, self.since, self.guidance
+            ),
+        }
+    }
+}
+
+/// How requests using deprecated operations or parameters are treated.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeprecationMode {
+    /// Requests are served, with a warning.
+    #[default]
+    Warn,
+
+    /// Requests are rejected with `ValidationError`.
+    Strict,
+}
+
+/// Deprecation settings, per service.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct DeprecationConfig {
+    pub mode: DeprecationMode,
+}
+
+/// The deprecations of a service, and how often each was used.
+#[derive(Debug)]
+pub struct Deprecations {
+    table: &'static [Deprecation],
+    mode: DeprecationMode,
+    uses: Mutex<BTreeMap<&'static str, u64>>,
+}
+
+impl Deprecations {
+    pub fn new(table: &'static [Deprecation], config: &DeprecationConfig) -> Self {
+        Self {
+            table,
+            mode: config.mode,
+            uses: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn mode(&self) -> DeprecationMode {
+        self.mode
+    }
+
+    /// Check a request for deprecated usage, recording any found. Returns the deprecations to warn about, or the error
+    /// to reject the request with in strict mode.
+    pub fn check(&self, context: &RequestContext, action: &str) -> Result<Vec<&'static Deprecation>, AwsError> {
+        let parameters = context.parameters().iter().map(|(k, v)| (k.as_str(), v.as_str())).collect();
+        let used: Vec<&'static Deprecation> =
+            self.table.iter().filter(|deprecation| deprecation.applies(action, &parameters)).collect();
+        if used.is_empty() {
+            return Ok(used);
+        }
+
+        let caller = context.caller_arn().map(|arn| arn.to_string()).unwrap_or_else(|| "anonymous".to_string());
+        let mut uses = self.uses.lock().expect("deprecation counters poisoned");
+        for deprecation in &used {
+            *uses.entry(deprecation.id).or_default() += 1;
+            warn!(
+                target: AUDIT_TARGET,
+                "Deprecated usage {} by {} (request {}): {}",
+                deprecation.id,
+                caller,
+                context.request_id(),
+                deprecation.message()
+            );
+        }
+
+        match self.mode {
+            DeprecationMode::Warn => Ok(used),
+            DeprecationMode::Strict => Err(AwsError::sender(
+                StatusCode::BAD_REQUEST,
+                "ValidationError",
+                format!("{} Deprecated usage is rejected by this deployment.", used[0].message()),
+            )),
+        }
+    }
+
+    /// The number of requests that used the deprecation `id`.
+    pub fn count(&self, id: &str) -> u64 {
+        self.uses.lock().expect("deprecation counters poisoned").get(id).copied().unwrap_or_default()
+    }
+
+    /// Render the counters in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let uses = self.uses.lock().expect("deprecation counters poisoned");
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP scratchstack_deprecated_usage_total Requests that used a deprecation.");
+        let _ = writeln!(out, "# TYPE scratchstack_deprecated_usage_total counter");
+        for deprecation in self.table {
+            let count = uses.get(deprecation.id).copied().unwrap_or_default();
+            let _ = writeln!(out, "scratchstack_deprecated_usage_total{{id=\"{}\"}} {}", deprecation.id, count);
+        }
+        out
+    }
+}
+
+/// Add a `Warning` header to `response` for each of `used`.
+pub fn add_warnings(mut response: Response<Body>, used: &[&Deprecation]) -> Response<Body> {
+    for deprecation in used {
+        let warning = format!("299 scratchstack \"{}\"", deprecation.message().replace('"', "'"));
+        if let Ok(value) = HeaderValue::from_str(&warning) {
+            response.headers_mut().append(WARNING, value);
+        }
+    }
+    response
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{add_warnings, Deprecation, DeprecationConfig, DeprecationMode, Deprecations},
+        crate::context::RequestContext,
+        hyper::{Body, Response},
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{Principal, PrincipalIdentity, User},
+        std::collections::HashMap,
+    };
+
+    const TABLE: &[Deprecation] = &[Deprecation {
+        id: "list_users_marker",
+        action: "ListUsers",
+        parameter: Some("Marker"),
+        since: "0.2",
+        guidance: "use PaginationToken instead.",
+    }];
+
+    fn context(parameters: &[(&str, &str)]) -> RequestContext {
+        let user = User::new("aws", "123456789012", "/", "alice").unwrap();
+        RequestContext::builder()
+            .principal(Principal::from(vec![PrincipalIdentity::from(user)]))
+            .parameters(parameters.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<_, _>>())
+            .build()
+            .unwrap()
+    }
+
+    #[test_log::test]
+    fn test_deprecations() {
+        let deprecations = Deprecations::new(TABLE, &DeprecationConfig::default());
+        assert!(deprecations.check(&context(&[]), "ListUsers").unwrap().is_empty());
+        let used = deprecations.check(&context(&[("Marker", "abc")]), "ListUsers").unwrap();
+        assert_eq!(used, vec![&TABLE[0]]);
+        assert_eq!(deprecations.count("list_users_marker"), 1);
+        assert!(deprecations.render().contains("scratchstack_deprecated_usage_total{id=\"list_users_marker\"} 1\n"));
+
+        let response = add_warnings(Response::new(Body::empty()), &used);
+        assert_eq!(
+            response.headers()["Warning"],
+            "299 scratchstack \"The Marker parameter of ListUsers is deprecated since Scratchstack 0.2: use \
+             PaginationToken instead.\""
+        );
+
+        let strict = Deprecations::new(
+            TABLE,
+            &DeprecationConfig {
+                mode: DeprecationMode::Strict,
+            },
+        );
+        let error = strict.check(&context(&[("Marker", "abc")]), "ListUsers").unwrap_err();
+        assert_eq!(error.code, "ValidationError");
+        assert_eq!(strict.count("list_users_marker"), 1);
+    }
+}
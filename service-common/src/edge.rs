@@ -0,0 +1,192 @@
+//! A single port for all services, routed the way localstack's edge port is.
+//!
+//! Test suites written against localstack send every request to one endpoint (usually port 4566) and rely on the
+//! edge to find the service. [EdgeRouter] picks the service from, in order:
+//!
+//! 1. The first label of the `Host` header, e.g. `iam.amazonaws.com` or `sts.us-east-1.localhost.localstack.cloud`.
+//! 2. The first segment of the path, e.g. `http://localhost:4566/sts/`.
+//! 3. The service in the SigV4 credential scope, from the `Authorization` header or `X-Amz-Credential` parameter.
+//! 4. [EdgeConfig::default_service], if set.
+//!
+//! Requests are forwarded unchanged, including the path, so their signatures remain valid. IAM and STS ignore the
+//! path of query API requests.
+use {
+    crate::{
+        config::{ConfigError, ListenerConfig},
+        integrity::request_credential,
+        route::Proxy,
+    },
+    http::{header::HeaderValue, StatusCode},
+    hyper::{service::Service, Body, Request, Response},
+    log::debug,
+    serde::Deserialize,
+    std::{
+        collections::BTreeMap,
+        future::Future,
+        net::SocketAddr,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+    },
+    toml::Value,
+    tower::BoxError,
+};
+
+/// The `[edge]` section of the configuration file.
+#[derive(Clone, Debug, Deserialize)]
+pub struct EdgeConfig {
+    /// The address to listen on. This defaults to localstack's port, `127.0.0.1:4566`.
+    #[serde(default = "EdgeConfig::default_address")]
+    pub address: SocketAddr,
+
+    /// The base URL of each service, keyed by the service name used in credential scopes, e.g.
+    /// `iam = "http://127.0.0.1:8180"`. Only plain HTTP is supported.
+    pub services: BTreeMap<String, String>,
+
+    /// The service for requests that match no other rule.
+    pub default_service: Option<String>,
+
+    #[serde(default)]
+    pub listener: ListenerConfig,
+}
+
+impl EdgeConfig {
+    fn default_address() -> SocketAddr {
+        SocketAddr::from(([127, 0, 0, 1], 4566))
+    }
+
+    /// Read the `[edge]` section from a configuration file, if present.
+    pub fn from_value(value: &Value) -> Result<Option<Self>, ConfigError> {
+        match value.get("edge") {
+            None => Ok(None),
+            Some(section) => Ok(Some(section.clone().try_into()?)),
+        }
+    }
+}
+
+/// A service that forwards each request to the service it is addressed to.
+#[derive(Clone, Debug)]
+pub struct EdgeRouter {
+    services: Arc<BTreeMap<String, Proxy>>,
+    default_service: Option<String>,
+}
+
+impl EdgeRouter {
+    pub fn new(config: &EdgeConfig) -> Result<Self, BoxError> {
+        let mut services = BTreeMap::new();
+        for (name, url) in &config.services {
+            services.insert(name.to_ascii_lowercase(), Proxy::new(url)?);
+        }
+
+        if let Some(default_service) = &config.default_service {
+            if !services.contains_key(default_service) {
+                return Err(format!("default_service {default_service} is not in [edge.services]").into());
+            }
+        }
+
+        Ok(Self {
+            services: Arc::new(services),
+            default_service: config.default_service.clone(),
+        })
+    }
+
+    /// Returns the name of the service `req` is addressed to.
+    pub fn resolve<B>(&self, req: &Request<B>) -> Option<String> {
+        let known = |name: &str| self.services.contains_key(name).then(|| name.to_string());
+
+        let host = req.headers().get("Host").and_then(|v| v.to_str().ok()).or_else(|| req.uri().host());
+        if let Some(host) = host {
+            let label = host.split('.').next().unwrap_or_default();
+            let label = label.split(':').next().unwrap_or_default().to_ascii_lowercase();
+            if let Some(service) = known(&label) {
+                return Some(service);
+            }
+        }
+
+        let segment = req.uri().path().trim_start_matches('/').split('/').next().unwrap_or_default();
+        if let Some(service) = known(&segment.to_ascii_lowercase()) {
+            return Some(service);
+        }
+
+        // Credential=<access key>/<date>/<region>/<service>/aws4_request
+        if let Some((credential, _)) = request_credential(req) {
+            if let Some(service) = credential.split('/').nth(3).and_then(known) {
+                return Some(service);
+            }
+        }
+
+        self.default_service.clone()
+    }
+}
+
+impl Service<Request<Body>> for EdgeRouter {
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let mut proxy = match self.resolve(&req).and_then(|service| self.services.get(&service)) {
+            Some(proxy) => proxy.clone(),
+            None => {
+                debug!("No service found for {} {}", req.method(), req.uri());
+                return Box::pin(async {
+                    Ok(Response::builder()
+                        .status(StatusCode::NOT_FOUND)
+                        .header("Content-Type", HeaderValue::from_static("text/plain"))
+                        .body(Body::from("Unable to determine the service this request is addressed to\n"))?)
+                });
+            }
+        };
+
+        proxy.call(req)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{EdgeConfig, EdgeRouter},
+        hyper::Request,
+        pretty_assertions::assert_eq,
+    };
+
+    #[test_log::test]
+    fn test_resolve() {
+        let config: EdgeConfig = toml::from_str(
+            r#"
+            [services]
+            iam = "http://127.0.0.1:8180"
+            sts = "http://127.0.0.1:8190"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.address.port(), 4566);
+        let router = EdgeRouter::new(&config).unwrap();
+
+        let resolve = |host: &str, uri: &str, authorization: Option<&str>| {
+            let mut req = Request::builder().uri(uri).header("Host", host);
+            if let Some(authorization) = authorization {
+                req = req.header("Authorization", authorization);
+            }
+            router.resolve(&req.body(()).unwrap())
+        };
+
+        assert_eq!(resolve("sts.us-east-1.amazonaws.com", "/", None).as_deref(), Some("sts"));
+        assert_eq!(resolve("IAM.localhost.localstack.cloud:4566", "/", None).as_deref(), Some("iam"));
+        assert_eq!(resolve("localhost:4566", "/iam/?Action=ListUsers", None).as_deref(), Some("iam"));
+        assert_eq!(
+            resolve(
+                "localhost:4566",
+                "/",
+                Some("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20221014/us-east-1/sts/aws4_request, SignedHeaders=host, Signature=abcd")
+            )
+            .as_deref(),
+            Some("sts")
+        );
+        assert_eq!(resolve("localhost:4566", "/", None), None);
+    }
+}
@@ -0,0 +1,277 @@
+//! Effective-policy resolution: the identity policies that apply to a principal.
+//!
+//! A user's effective policies are its own inline and attached policies plus those of every group it belongs to.
+//! Roles and groups only have their own. [PolicyResolver] caches each holder's policy set separately, so the
+//! policies of a group shared by many users are read once, and each group is visited at most once per resolution.
+use {
+    crate::store::{ControlPlaneStore, PolicyHolder, StoreError},
+    log::debug,
+    std::{
+        collections::{HashMap, HashSet},
+        fmt::{Debug, Formatter, Result as FmtResult},
+        sync::{Arc, Mutex},
+        time::{Duration, Instant},
+    },
+};
+
+/// How long a holder's policy set is cached by default.
+const DEFAULT_TTL: Duration = Duration::from_secs(30);
+
+/// A policy that applies to a principal, with where it came from for audit logs.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EffectivePolicy {
+    /// The policy name for inline policies, or the managed policy id.
+    pub source: String,
+
+    /// The group the policy was inherited from, if any.
+    pub via_group: Option<String>,
+
+    /// Whether this is an attached managed policy rather than an inline one.
+    pub managed: bool,
+    pub policy_document: String,
+}
+
+/// Collect a holder's own identity policies in `account_id`: its inline policies and the default versions of its
+/// attached managed policies. Group policies are not included; see [effective_policies].
+pub async fn holder_policies(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    holder: PolicyHolder,
+    holder_id: &str,
+) -> Result<Vec<EffectivePolicy>, StoreError> {
+    let mut policies = Vec::new();
+    for policy_name in store.list_inline_policies(holder, holder_id).await? {
+        let policy = store.get_inline_policy(holder, holder_id, &policy_name).await?;
+        policies.push(EffectivePolicy {
+            source: policy.policy_name,
+            via_group: None,
+            managed: false,
+            policy_document: policy.policy_document,
+        });
+    }
+
+    let attached = store.list_attached_policies(holder, holder_id).await?;
+    if attached.is_empty() {
+        return Ok(policies);
+    }
+
+    for policy in store.list_policies(account_id, "/").await? {
+        if !attached.contains(&policy.managed_policy_id) {
+            continue;
+        }
+
+        // Attached policies without a default version grant nothing.
+        let version = match policy.default_version {
+            None => continue,
+            Some(version) => version,
+        };
+
+        policies.push(EffectivePolicy {
+            policy_document: store.get_policy_version(&policy.managed_policy_id, version).await?,
+            source: policy.managed_policy_id,
+            via_group: None,
+            managed: true,
+        });
+    }
+
+    Ok(policies)
+}
+
+/// Collect all identity policies that apply to a holder, including those inherited from a user's groups. This is
+/// the policy set the authorization middleware and policy simulation evaluate.
+pub async fn effective_policies(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    holder: PolicyHolder,
+    holder_id: &str,
+) -> Result<Vec<EffectivePolicy>, StoreError> {
+    let mut policies = holder_policies(store, account_id, holder, holder_id).await?;
+    if holder != PolicyHolder::User {
+        return Ok(policies);
+    }
+
+    let mut visited = HashSet::new();
+    for group in store.list_groups_for_user(holder_id).await? {
+        if !visited.insert(group.group_id.clone()) {
+            continue;
+        }
+
+        for policy in holder_policies(store, account_id, PolicyHolder::Group, &group.group_id).await? {
+            policies.push(EffectivePolicy {
+                via_group: Some(group.group_name.clone()),
+                ..policy
+            });
+        }
+    }
+
+    Ok(policies)
+}
+
+/// A holder's cached policy set and when it was read.
+type CacheEntry = (Instant, Arc<Vec<EffectivePolicy>>);
+
+/// Resolves effective policies, caching each holder's own policies for a short time.
+///
+/// Group memberships are read on every resolution. Changes to a holder's policies become visible once its cached
+/// entry expires, or at once after [PolicyResolver::invalidate].
+#[derive(Clone)]
+pub struct PolicyResolver {
+    store: Arc<dyn ControlPlaneStore>,
+    ttl: Duration,
+    cache: Arc<Mutex<HashMap<(PolicyHolder, String), CacheEntry>>>,
+}
+
+impl PolicyResolver {
+    pub fn new(store: Arc<dyn ControlPlaneStore>) -> Self {
+        Self::with_ttl(store, DEFAULT_TTL)
+    }
+
+    pub fn with_ttl(store: Arc<dyn ControlPlaneStore>, ttl: Duration) -> Self {
+        Self {
+            store,
+            ttl,
+            cache: Default::default(),
+        }
+    }
+
+    /// Resolve the effective policies of a holder in `account_id`.
+    pub async fn resolve(
+        &self,
+        account_id: &str,
+        holder: PolicyHolder,
+        holder_id: &str,
+    ) -> Result<Vec<EffectivePolicy>, StoreError> {
+        let mut policies = self.cached_policies(account_id, holder, holder_id).await?.as_ref().clone();
+        if holder != PolicyHolder::User {
+            return Ok(policies);
+        }
+
+        // Memberships are read every time; only the policy sets are cached.
+        let mut visited = HashSet::new();
+        for group in self.store.list_groups_for_user(holder_id).await? {
+            if !visited.insert(group.group_id.clone()) {
+                continue;
+            }
+
+            let group_policies = self.cached_policies(account_id, PolicyHolder::Group, &group.group_id).await?;
+            policies.extend(group_policies.iter().cloned().map(|policy| EffectivePolicy {
+                via_group: Some(group.group_name.clone()),
+                ..policy
+            }));
+        }
+
+        Ok(policies)
+    }
+
+    /// Drop the cached policies of a holder, e.g. after its policies change.
+    pub fn invalidate(&self, holder: PolicyHolder, holder_id: &str) {
+        self.cache.lock().expect("policy cache poisoned").remove(&(holder, holder_id.to_string()));
+    }
+
+    pub fn clear(&self) {
+        self.cache.lock().expect("policy cache poisoned").clear();
+    }
+
+    async fn cached_policies(
+        &self,
+        account_id: &str,
+        holder: PolicyHolder,
+        holder_id: &str,
+    ) -> Result<Arc<Vec<EffectivePolicy>>, StoreError> {
+        let key = (holder, holder_id.to_string());
+        {
+            let cache = self.cache.lock().expect("policy cache poisoned");
+            if let Some((read_at, policies)) = cache.get(&key) {
+                if read_at.elapsed() < self.ttl {
+                    return Ok(policies.clone());
+                }
+            }
+        }
+
+        let policies = Arc::new(holder_policies(self.store.as_ref(), account_id, holder, holder_id).await?);
+        debug!("Read {} policies for {} {}", policies.len(), holder.kind(), holder_id);
+
+        let mut cache = self.cache.lock().expect("policy cache poisoned");
+        cache.retain(|_, (read_at, _)| read_at.elapsed() < self.ttl);
+        cache.insert(key, (Instant::now(), policies.clone()));
+        Ok(policies)
+    }
+}
+
+impl Debug for PolicyResolver {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let len = self.cache.lock().map(|cache| cache.len()).unwrap_or_default();
+        f.debug_struct("PolicyResolver").field("ttl", &self.ttl).field("len", &len).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{effective_policies, PolicyResolver},
+        crate::store::{ControlPlaneStore, Group, InlinePolicy, MemoryStore, PolicyHolder, User},
+        chrono::Utc,
+        pretty_assertions::assert_eq,
+        std::sync::Arc,
+    };
+
+    const DOCUMENT: &str =
+        r#"{"Version":"2012-10-17","Statement":[{"Effect":"Allow","Action":"s3:GetObject","Resource":"*"}]}"#;
+
+    #[test_log::test(tokio::test)]
+    async fn test_group_policies() {
+        let store = MemoryStore::new();
+        store
+            .create_user(
+                &User {
+                    user_id: "AIDAEXAMPLEUSER1".to_string(),
+                    account_id: "123456789012".to_string(),
+                    user_name: "Alice".to_string(),
+                    path: "/".to_string(),
+                    permissions_boundary: None,
+                    created_at: Utc::now(),
+                },
+                &[],
+            )
+            .await
+            .unwrap();
+        store
+            .create_group(&Group {
+                group_id: "AGPAEXAMPLEGROUP".to_string(),
+                account_id: "123456789012".to_string(),
+                group_name: "Readers".to_string(),
+                path: "/".to_string(),
+                created_at: Utc::now(),
+            })
+            .await
+            .unwrap();
+        let policy = InlinePolicy {
+            policy_name: "ReadObjects".to_string(),
+            policy_document: DOCUMENT.to_string(),
+        };
+        store.put_inline_policy(PolicyHolder::Group, "AGPAEXAMPLEGROUP", &policy).await.unwrap();
+
+        let resolver = PolicyResolver::new(Arc::new(store.clone()));
+        assert!(resolver.resolve("123456789012", PolicyHolder::User, "AIDAEXAMPLEUSER1").await.unwrap().is_empty());
+
+        // Adding the user to the group twice still yields the group's policies once.
+        store.add_user_to_group("AGPAEXAMPLEGROUP", "AIDAEXAMPLEUSER1").await.unwrap();
+        store.add_user_to_group("AGPAEXAMPLEGROUP", "AIDAEXAMPLEUSER1").await.unwrap();
+        let policies = resolver.resolve("123456789012", PolicyHolder::User, "AIDAEXAMPLEUSER1").await.unwrap();
+        assert_eq!(policies.len(), 1);
+        assert_eq!((policies[0].source.as_str(), policies[0].via_group.as_deref()), ("ReadObjects", Some("Readers")));
+        assert_eq!(
+            effective_policies(&store, "123456789012", PolicyHolder::User, "AIDAEXAMPLEUSER1").await.unwrap(),
+            policies
+        );
+
+        // The group's policy set is cached until invalidated.
+        store.delete_inline_policy(PolicyHolder::Group, "AGPAEXAMPLEGROUP", "ReadObjects").await.unwrap();
+        assert_eq!(resolver.resolve("123456789012", PolicyHolder::User, "AIDAEXAMPLEUSER1").await.unwrap().len(), 1);
+        resolver.invalidate(PolicyHolder::Group, "AGPAEXAMPLEGROUP");
+        assert!(resolver.resolve("123456789012", PolicyHolder::User, "AIDAEXAMPLEUSER1").await.unwrap().is_empty());
+
+        assert_eq!(store.delete_group("123456789012", "Readers").await.unwrap_err().code(), "DeleteConflict");
+        assert_eq!(store.delete_user("123456789012", "Alice").await.unwrap_err().code(), "DeleteConflict");
+    }
+}
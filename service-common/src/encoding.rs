@@ -0,0 +1,376 @@
+//! Decoding of request bodies sent with a `Content-Encoding` of `gzip` or `zstd`.
+//!
+//! A SigV4 signature covers the body as it was sent, so a compressed body with a signed payload hash is decoded only
+//! after the signature has been verified, by [DecodeRequestBody] wrapping the service implementation. When the
+//! payload is unsigned (`X-Amz-Content-Sha256: UNSIGNED-PAYLOAD`), nothing depends on the compressed bytes, and
+//! [WithRequestDecoding] decodes the body before verification so the framework sees the form parameters.
+//!
+//! Decoded bodies are limited to [RequestDecodingConfig::max_decoded_bytes]; a body that expands past the limit is
+//! rejected without being decoded further.
+use {
+    crate::{
+        payload_hash::{UNSIGNED_PAYLOAD, X_AMZ_CONTENT_SHA256},
+        protocol::{AwsError, ErrorProtocol},
+    },
+    flate2::read::GzDecoder,
+    http::{
+        header::{HeaderMap, HeaderValue, CONTENT_ENCODING, CONTENT_LENGTH},
+        StatusCode,
+    },
+    hyper::{body::HttpBody, service::Service, Body, Request, Response},
+    log::debug,
+    scratchstack_http_framework::RequestId,
+    serde::Deserialize,
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        future::Future,
+        io::Read,
+        pin::Pin,
+        task::{Context, Poll},
+    },
+    tower::BoxError,
+};
+
+/// When a compressed body is decoded.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DecodeStage {
+    /// Before signature verification if the payload is unsigned, otherwise after.
+    #[default]
+    Auto,
+
+    /// Always after signature verification.
+    AfterVerification,
+}
+
+/// Settings for decoding compressed request bodies. Without this, compressed bodies are passed to the service as is.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RequestDecodingConfig {
+    /// The largest decoded body accepted, in bytes. The compressed body is subject to the same limit.
+    #[serde(default = "RequestDecodingConfig::default_max_decoded_bytes")]
+    pub max_decoded_bytes: usize,
+
+    #[serde(default)]
+    pub stage: DecodeStage,
+}
+
+impl RequestDecodingConfig {
+    fn default_max_decoded_bytes() -> usize {
+        10 << 20
+    }
+}
+
+impl Default for RequestDecodingConfig {
+    fn default() -> Self {
+        Self {
+            max_decoded_bytes: Self::default_max_decoded_bytes(),
+            stage: DecodeStage::default(),
+        }
+    }
+}
+
+/// Reasons a request body could not be decoded.
+#[derive(Debug)]
+pub enum DecodeError {
+    Unsupported(String),
+    TooLarge(usize),
+    Malformed(String),
+}
+
+impl DecodeError {
+    /// The AWS error code returned to the caller.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Unsupported(_) => "UnsupportedMediaType",
+            Self::TooLarge(_) => "RequestEntityTooLarge",
+            Self::Malformed(_) => "InvalidRequest",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Unsupported(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            Self::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            Self::Malformed(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Unsupported(encoding) => write!(f, "Unsupported Content-Encoding: {encoding}"),
+            Self::TooLarge(limit) => write!(f, "Request body exceeds {limit} bytes"),
+            Self::Malformed(e) => write!(f, "Unable to decode request body: {e}"),
+        }
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Returns the content codings applied to a request, in the order they were applied, or `None` if it is not encoded.
+fn content_codings(headers: &HeaderMap) -> Option<Vec<String>> {
+    let mut codings = Vec::new();
+    for value in headers.get_all(CONTENT_ENCODING) {
+        for coding in value.to_str().unwrap_or_default().split(',') {
+            let coding = coding.trim().to_ascii_lowercase();
+            if !coding.is_empty() && coding != "identity" {
+                codings.push(coding);
+            }
+        }
+    }
+
+    (!codings.is_empty()).then_some(codings)
+}
+
+fn is_unsigned_payload(headers: &HeaderMap) -> bool {
+    headers.get(X_AMZ_CONTENT_SHA256).and_then(|value| value.to_str().ok()) == Some(UNSIGNED_PAYLOAD)
+}
+
+/// Undo `codings`, which were applied in order, to `data`.
+pub fn decode(data: Vec<u8>, codings: &[String], limit: usize) -> Result<Vec<u8>, DecodeError> {
+    let mut data = data;
+    for coding in codings.iter().rev() {
+        data = match coding.as_str() {
+            "gzip" | "x-gzip" => read_limited(GzDecoder::new(data.as_slice()), limit)?,
+            "zstd" => {
+                let decoder = zstd::stream::read::Decoder::new(data.as_slice())
+                    .map_err(|e| DecodeError::Malformed(e.to_string()))?;
+                read_limited(decoder, limit)?
+            }
+            _ => return Err(DecodeError::Unsupported(coding.clone())),
+        };
+    }
+
+    Ok(data)
+}
+
+fn read_limited<R: Read>(reader: R, limit: usize) -> Result<Vec<u8>, DecodeError> {
+    let mut decoded = Vec::new();
+    reader.take(limit as u64 + 1).read_to_end(&mut decoded).map_err(|e| DecodeError::Malformed(e.to_string()))?;
+    if decoded.len() > limit {
+        return Err(DecodeError::TooLarge(limit));
+    }
+
+    Ok(decoded)
+}
+
+/// Read and decode the body of `req`, replacing it and removing `Content-Encoding`. Requests that are not encoded
+/// are returned unchanged.
+async fn decode_request(req: Request<Body>, limit: usize) -> Result<Request<Body>, DecodeError> {
+    let codings = match content_codings(req.headers()) {
+        Some(codings) => codings,
+        None => return Ok(req),
+    };
+
+    let (mut parts, mut body) = req.into_parts();
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk.map_err(|e| DecodeError::Malformed(e.to_string()))?;
+        if data.len() + chunk.len() > limit {
+            return Err(DecodeError::TooLarge(limit));
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    let decoded = decode(data, &codings, limit)?;
+    debug!("Decoded {} request body to {} bytes", codings.join(", "), decoded.len());
+    parts.headers.remove(CONTENT_ENCODING);
+    parts.headers.insert(CONTENT_LENGTH, HeaderValue::from(decoded.len()));
+    Ok(Request::from_parts(parts, Body::from(decoded)))
+}
+
+fn decode_error_response(
+    xml_ns: &'static str,
+    request_id: RequestId,
+    error: &DecodeError,
+) -> Result<Response<Body>, BoxError> {
+    ErrorProtocol::Query {
+        xml_ns,
+    }
+    .response(&AwsError::sender(error.status(), error.code(), error.to_string()), request_id)
+}
+
+/// Decode `req` and pass it to `inner`, or answer with the reason it could not be decoded.
+async fn call_decoded<S>(
+    mut inner: S,
+    req: Request<Body>,
+    limit: usize,
+    xml_ns: &'static str,
+) -> Result<Response<Body>, BoxError>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Error: Into<BoxError>,
+{
+    let request_id = req.extensions().get::<RequestId>().copied();
+    match decode_request(req, limit).await {
+        Ok(req) => inner.call(req).await.map_err(Into::into),
+        Err(e) => {
+            debug!("Rejecting request body: {}", e);
+            decode_error_response(xml_ns, request_id.unwrap_or_else(RequestId::new), &e)
+        }
+    }
+}
+
+/// Wraps a make-service (such as `SpawnService`) so each per-connection service decodes compressed bodies with
+/// unsigned payloads before the signature is verified.
+#[derive(Clone, Debug)]
+pub struct WithRequestDecoding<M> {
+    inner: M,
+    config: Option<RequestDecodingConfig>,
+    xml_ns: &'static str,
+}
+
+impl<M> WithRequestDecoding<M> {
+    /// Wrap `inner`. Errors are rendered in the `xml_ns` namespace.
+    pub fn new(inner: M, config: Option<RequestDecodingConfig>, xml_ns: &'static str) -> Self {
+        Self {
+            inner,
+            config,
+            xml_ns,
+        }
+    }
+}
+
+impl<T, M> Service<T> for WithRequestDecoding<M>
+where
+    M: Service<T>,
+    M::Future: Send + 'static,
+{
+    type Response = EarlyRequestDecoding<M::Response>;
+    type Error = M::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let limit = match &self.config {
+            Some(config) if config.stage == DecodeStage::Auto => Some(config.max_decoded_bytes),
+            _ => None,
+        };
+        let xml_ns = self.xml_ns;
+        let future = self.inner.call(target);
+        Box::pin(async move {
+            Ok(EarlyRequestDecoding {
+                inner: future.await?,
+                limit,
+                xml_ns,
+            })
+        })
+    }
+}
+
+/// A per-connection service that decodes compressed bodies with unsigned payloads before passing them on.
+#[derive(Clone, Debug)]
+pub struct EarlyRequestDecoding<S> {
+    inner: S,
+    limit: Option<usize>,
+    xml_ns: &'static str,
+}
+
+impl<S> Service<Request<Body>> for EarlyRequestDecoding<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        match self.limit {
+            Some(limit) if is_unsigned_payload(req.headers()) && content_codings(req.headers()).is_some() => {
+                let clone = self.inner.clone();
+                let inner = std::mem::replace(&mut self.inner, clone);
+                Box::pin(call_decoded(inner, req, limit, self.xml_ns))
+            }
+            _ => {
+                let future = self.inner.call(req);
+                Box::pin(async move { future.await.map_err(Into::into) })
+            }
+        }
+    }
+}
+
+/// A service wrapper that decodes compressed bodies that are still encoded after signature verification.
+///
+/// Without a configuration, requests are passed through unchanged.
+#[derive(Clone, Debug)]
+pub struct DecodeRequestBody<S> {
+    inner: S,
+    limit: Option<usize>,
+    xml_ns: &'static str,
+}
+
+impl<S> DecodeRequestBody<S> {
+    /// Wrap `inner`. Errors are rendered in the `xml_ns` namespace.
+    pub fn new(inner: S, config: Option<&RequestDecodingConfig>, xml_ns: &'static str) -> Self {
+        Self {
+            inner,
+            limit: config.map(|config| config.max_decoded_bytes),
+            xml_ns,
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for DecodeRequestBody<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = BoxError> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        match self.limit {
+            Some(limit) if content_codings(req.headers()).is_some() => {
+                let clone = self.inner.clone();
+                let inner = std::mem::replace(&mut self.inner, clone);
+                Box::pin(call_decoded(inner, req, limit, self.xml_ns))
+            }
+            _ => Box::pin(self.inner.call(req)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{decode, DecodeError},
+        flate2::{write::GzEncoder, Compression},
+        pretty_assertions::assert_eq,
+        std::io::Write,
+    };
+
+    #[test_log::test]
+    fn test_decode() {
+        let body = b"Action=GetCallerIdentity&Version=2011-06-15".repeat(10);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&body).unwrap();
+        let gzipped = encoder.finish().unwrap();
+        assert_eq!(decode(gzipped.clone(), &["gzip".to_string()], 1 << 20).unwrap(), body);
+
+        let both = zstd::bulk::compress(&gzipped, 3).unwrap();
+        assert_eq!(decode(both, &["gzip".to_string(), "zstd".to_string()], 1 << 20).unwrap(), body);
+
+        // A body that expands past the limit is rejected.
+        let bomb = zstd::bulk::compress(&vec![0u8; 1 << 20], 19).unwrap();
+        assert!(matches!(decode(bomb, &["zstd".to_string()], 1 << 16), Err(DecodeError::TooLarge(_))));
+
+        assert!(matches!(decode(body.clone(), &["br".to_string()], 1 << 20), Err(DecodeError::Unsupported(_))));
+        assert!(matches!(decode(body, &["gzip".to_string()], 1 << 20), Err(DecodeError::Malformed(_))));
+    }
+}
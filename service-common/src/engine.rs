@@ -0,0 +1,480 @@
+//! Evaluation of identity policies.
+//!
+//! [PolicyEvaluator] follows the IAM evaluation logic for the policies that apply to one principal: an explicit
+//! `Deny` in any policy overrides every `Allow`, and a request that no statement allows is implicitly denied.
+//! Statements may use `Action` or `NotAction`, `Resource` or `NotResource`, and the condition operators supported for
+//! trust policies (see [crate::trust]). Condition keys are looked up in the request's session data, which is where
+//! [RequestContext::add_condition_keys][crate::context::RequestContext::add_condition_keys] puts them, with
+//! `aws:PrincipalArn` and `aws:PrincipalAccount` filled in from the principal. `Action` and `Resource` patterns are
+//! matched with [action_matches] and [resource_matches], which services can also use directly.
+//!
+//! Aspen parses policies but does not evaluate them, so statements are read from the JSON here. A policy that cannot
+//! be evaluated is rejected when it is added rather than skipped, so that a policy using an unsupported feature
+//! cannot silently drop its `Deny` statements.
+use {
+    crate::{
+        authz::Decision,
+        effective::EffectivePolicy,
+        session_keys::{AWS_PRINCIPAL_ACCOUNT, AWS_PRINCIPAL_ARN},
+        trust::{condition_matches, string_values, wildcard_match, CONDITION_OPERATORS},
+    },
+    scratchstack_arn::Arn,
+    scratchstack_aws_principal::{Principal, SessionData, SessionValue},
+    serde_json::Value,
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// The identity policies of a principal, ready to be evaluated.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PolicyEvaluator {
+    policies: Vec<IdentityPolicy>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct IdentityPolicy {
+    /// The policy name or ARN, reported in explicit denies.
+    name: String,
+    statements: Vec<IdentityStatement>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct IdentityStatement {
+    /// The `Sid`, or the statement index if there is none.
+    id: String,
+    allow: bool,
+
+    /// The patterns, and whether they came from `NotAction`.
+    actions: (Vec<String>, bool),
+
+    /// The patterns, and whether they came from `NotResource`.
+    resources: (Vec<String>, bool),
+
+    /// (operator, key, values); keys are lowercased.
+    conditions: Vec<(String, String, Vec<String>)>,
+}
+
+/// A statement that matched a request; see [PolicyEvaluator::matched_statements].
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct MatchedStatement {
+    /// The name the policy was added with.
+    pub policy: String,
+
+    /// The statement's `Sid`, or its index in the policy if it has none.
+    pub statement: String,
+    pub allow: bool,
+}
+
+/// A request to be authorized: who is asking to perform which action on which resource.
+#[derive(Clone, Copy, Debug)]
+pub struct EvaluationRequest<'a> {
+    pub principal: &'a Principal,
+
+    /// The action as written in policies, e.g. `iam:GetUser`.
+    pub action: &'a str,
+
+    /// The ARN of the resource, or `*` for actions that do not act on one.
+    pub resource: &'a str,
+    pub context: &'a SessionData,
+}
+
+impl PolicyEvaluator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// An evaluator for the effective policies of a principal; see [crate::effective].
+    pub fn from_effective(policies: &[EffectivePolicy]) -> Result<Self, PolicyError> {
+        policies
+            .iter()
+            .try_fold(Self::new(), |evaluator, policy| evaluator.with_policy(&policy.source, &policy.policy_document))
+    }
+
+    /// Add the policy document `document`, reported as `name` when one of its statements denies a request.
+    pub fn with_policy(mut self, name: &str, document: &str) -> Result<Self, PolicyError> {
+        let malformed = |reason: String| PolicyError::MalformedPolicy {
+            policy: name.to_string(),
+            reason,
+        };
+
+        let value: Value = serde_json::from_str(document).map_err(|e| malformed(e.to_string()))?;
+        let statements = match value.get("Statement") {
+            Some(Value::Array(statements)) => statements.iter().collect(),
+            Some(statement @ Value::Object(_)) => vec![statement],
+            _ => return Err(malformed("Missing required field Statement".to_string())),
+        };
+
+        let mut parsed = Vec::with_capacity(statements.len());
+        for (index, statement) in statements.into_iter().enumerate() {
+            parsed.push(IdentityStatement::parse(statement, index).map_err(malformed)?);
+        }
+
+        self.policies.push(IdentityPolicy {
+            name: name.to_string(),
+            statements: parsed,
+        });
+        Ok(self)
+    }
+
+    /// The number of statements across every policy.
+    pub fn statement_count(&self) -> usize {
+        self.policies.iter().map(|policy| policy.statements.len()).sum()
+    }
+
+    /// Evaluate every policy against `request`. An explicit deny overrides any allow; if no statement applies, the
+    /// result is an implicit deny.
+    pub fn evaluate(&self, request: &EvaluationRequest) -> Decision {
+        self.evaluate_counted(request).0
+    }
+
+    /// As [PolicyEvaluator::evaluate], also returning the number of statements matched against the request.
+    /// Evaluation stops at the first explicit deny, so this can be less than [PolicyEvaluator::statement_count].
+    pub fn evaluate_counted(&self, request: &EvaluationRequest) -> (Decision, usize) {
+        let caller = principal_arn(request.principal);
+        let mut allowed = false;
+        let mut evaluated = 0;
+
+        for policy in &self.policies {
+            for statement in &policy.statements {
+                evaluated += 1;
+                if !statement.matches(request, caller.as_ref()) {
+                    continue;
+                }
+
+                if !statement.allow {
+                    let decision = Decision::ExplicitDeny {
+                        policy: policy.name.clone(),
+                        statement: statement.id.clone(),
+                    };
+                    return (decision, evaluated);
+                }
+
+                allowed = true;
+            }
+        }
+
+        if allowed {
+            (Decision::Allow, evaluated)
+        } else {
+            (Decision::ImplicitDeny, evaluated)
+        }
+    }
+
+    /// Every statement that matches `request`, in the order the policies were added. Unlike
+    /// [PolicyEvaluator::evaluate], this does not stop at the first explicit deny, so it can explain a decision.
+    pub fn matched_statements(&self, request: &EvaluationRequest) -> Vec<MatchedStatement> {
+        let caller = principal_arn(request.principal);
+        let mut matched = Vec::new();
+        for policy in &self.policies {
+            for statement in policy.statements.iter().filter(|statement| statement.matches(request, caller.as_ref())) {
+                matched.push(MatchedStatement {
+                    policy: policy.name.clone(),
+                    statement: statement.id.clone(),
+                    allow: statement.allow,
+                });
+            }
+        }
+
+        matched
+    }
+}
+
+impl IdentityStatement {
+    fn parse(statement: &Value, index: usize) -> Result<Self, String> {
+        let id =
+            statement.get("Sid").and_then(Value::as_str).map(ToString::to_string).unwrap_or_else(|| index.to_string());
+        let allow = match statement.get("Effect").and_then(Value::as_str) {
+            Some("Allow") => true,
+            Some("Deny") => false,
+            _ => return Err(format!("Invalid Effect in statement {id}")),
+        };
+
+        if statement.get("Principal").is_some() || statement.get("NotPrincipal").is_some() {
+            return Err(format!("Identity policies cannot name a Principal (statement {id})"));
+        }
+
+        let actions = either(statement, "Action", "NotAction", &id)?;
+        let resources = either(statement, "Resource", "NotResource", &id)?;
+
+        let mut conditions = Vec::new();
+        if let Some(condition) = statement.get("Condition") {
+            let operators = condition.as_object().ok_or_else(|| format!("Invalid Condition in statement {id}"))?;
+            for (operator, keys) in operators {
+                if !CONDITION_OPERATORS.contains(&operator.as_str()) {
+                    return Err(format!("Unsupported condition operator {operator}"));
+                }
+
+                let keys = keys.as_object().ok_or_else(|| format!("Invalid Condition in statement {id}"))?;
+                for (key, values) in keys {
+                    let values = string_values(values).ok_or_else(|| format!("Invalid Condition in statement {id}"))?;
+                    conditions.push((operator.clone(), key.to_lowercase(), values));
+                }
+            }
+        }
+
+        Ok(Self {
+            id,
+            allow,
+            actions,
+            resources,
+            conditions,
+        })
+    }
+
+    fn matches(&self, request: &EvaluationRequest, caller: Option<&Arn>) -> bool {
+        let (actions, not_action) = &self.actions;
+        let (resources, not_resource) = &self.resources;
+
+        actions.iter().any(|pattern| action_matches(pattern, request.action)) != *not_action
+            && resources.iter().any(|pattern| resource_matches(pattern, request.resource)) != *not_resource
+            && self.conditions.iter().all(|(operator, key, values)| {
+                condition_matches(operator, condition_value(key, caller, request.context).as_ref(), values)
+            })
+    }
+}
+
+/// Returns whether the `Action` pattern `pattern` matches `action`, e.g. `ec2:Describe*` and `ec2:DescribeInstances`.
+///
+/// Actions are compared case-insensitively. A wildcard matches within the service prefix or the action name but not
+/// across the colon between them, so `iam*` matches nothing; `*` alone matches every action.
+pub fn action_matches(pattern: &str, action: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+
+    let (pattern, action) = (pattern.to_lowercase(), action.to_lowercase());
+    match (pattern.split_once(':'), action.split_once(':')) {
+        (Some((pattern_service, pattern_name)), Some((service, name))) => {
+            wildcard_match(pattern_service, service) && wildcard_match(pattern_name, name)
+        }
+        _ => false,
+    }
+}
+
+/// Returns whether the `Resource` pattern `pattern` matches the resource ARN `resource`.
+///
+/// Both are split into the six ARN segments and compared segment by segment, case-sensitively as AWS does. A wildcard
+/// in the partition, service, region, or account matches within that segment only; in the resource segment it may
+/// span `/` and `:`, so `arn:aws:s3:::bucket/*` matches every object in the bucket. `*` alone matches every resource,
+/// including requests for actions that do not act on one.
+pub fn resource_matches(pattern: &str, resource: &str) -> bool {
+    if pattern == "*" {
+        return true;
+    }
+
+    let pattern: Vec<&str> = pattern.splitn(6, ':').collect();
+    let resource: Vec<&str> = resource.splitn(6, ':').collect();
+    pattern.len() == 6
+        && resource.len() == 6
+        && pattern[0] == "arn"
+        && resource[0] == "arn"
+        && pattern.iter().zip(&resource).skip(1).all(|(pattern, value)| wildcard_match(pattern, value))
+}
+
+/// The patterns of `positive` or `negative`, whichever the statement has, and whether it was `negative`.
+fn either(statement: &Value, positive: &str, negative: &str, id: &str) -> Result<(Vec<String>, bool), String> {
+    let invalid = || format!("Expected a string or list of strings in statement {id}");
+    match (statement.get(positive), statement.get(negative)) {
+        (Some(value), None) => Ok((string_values(value).ok_or_else(invalid)?, false)),
+        (None, Some(value)) => Ok((string_values(value).ok_or_else(invalid)?, true)),
+        (Some(_), Some(_)) => Err(format!("Statement {id} cannot have both {positive} and {negative}")),
+        (None, None) => Err(format!("Missing {positive} in statement {id}")),
+    }
+}
+
+/// The ARN of the first principal identity that has one, as in
+/// [RequestContext::caller_arn][crate::context::RequestContext::caller_arn].
+fn principal_arn(principal: &Principal) -> Option<Arn> {
+    principal.iter().filter(|identity| identity.has_arn()).find_map(|identity| Arn::try_from(identity).ok())
+}
+
+/// The value of the lowercased condition key `key`, if the request has one.
+fn condition_value(key: &str, caller: Option<&Arn>, context: &SessionData) -> Option<String> {
+    if let Some(caller) = caller {
+        if key.eq_ignore_ascii_case(AWS_PRINCIPAL_ARN) {
+            return Some(caller.to_string());
+        } else if key.eq_ignore_ascii_case(AWS_PRINCIPAL_ACCOUNT) {
+            return Some(caller.account_id().to_string());
+        }
+    }
+
+    match context.get(key)? {
+        SessionValue::String(value) => Some(value.clone()),
+        SessionValue::Bool(value) => Some(value.to_string()),
+        SessionValue::Timestamp(value) => Some(value.to_rfc3339()),
+        _ => None,
+    }
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PolicyError {
+    /// The policy is not valid JSON or uses a part of the policy language that cannot be evaluated.
+    MalformedPolicy {
+        policy: String,
+        reason: String,
+    },
+}
+
+impl PolicyError {
+    /// The AWS error code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::MalformedPolicy {
+                ..
+            } => "MalformedPolicyDocument",
+        }
+    }
+}
+
+impl Error for PolicyError {}
+
+impl Display for PolicyError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::MalformedPolicy {
+                policy,
+                reason,
+            } => write!(f, "Malformed policy {policy}: {reason}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{action_matches, resource_matches, EvaluationRequest, PolicyEvaluator},
+        crate::authz::Decision,
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{Principal, PrincipalIdentity, SessionData, SessionValue, User},
+    };
+
+    const READ_ONLY: &str = r#"{
+        "Version": "2012-10-17",
+        "Statement": [
+            {"Effect": "Allow", "Action": ["iam:Get*", "iam:List*"], "Resource": "*"},
+            {
+                "Sid": "Tagged",
+                "Effect": "Allow",
+                "Action": "iam:UpdateUser",
+                "Resource": "arn:aws:iam::123456789012:user/*",
+                "Condition": {"StringEquals": {"aws:RequestedRegion": "us-west-2"}}
+            }
+        ]
+    }"#;
+
+    const NO_ADMINS: &str = r#"{
+        "Version": "2012-10-17",
+        "Statement": {
+            "Sid": "ProtectAdmins",
+            "Effect": "Deny",
+            "NotAction": "iam:List*",
+            "Resource": "arn:aws:iam::123456789012:user/admins/*"
+        }
+    }"#;
+
+    fn request<'a>(
+        principal: &'a Principal,
+        action: &'a str,
+        resource: &'a str,
+        context: &'a SessionData,
+    ) -> EvaluationRequest<'a> {
+        EvaluationRequest {
+            principal,
+            action,
+            resource,
+            context,
+        }
+    }
+
+    #[test_log::test]
+    fn test_evaluate() {
+        let evaluator = PolicyEvaluator::new()
+            .with_policy("ReadOnly", READ_ONLY)
+            .unwrap()
+            .with_policy("NoAdmins", NO_ADMINS)
+            .unwrap();
+        let alice = User::new("aws", "123456789012", "/", "alice").unwrap();
+        let principal = Principal::from(vec![PrincipalIdentity::from(alice)]);
+        let mut context = SessionData::new();
+
+        let bob = "arn:aws:iam::123456789012:user/bob";
+        assert_eq!(evaluator.evaluate(&request(&principal, "iam:GetUser", bob, &context)), Decision::Allow);
+        assert_eq!(evaluator.evaluate(&request(&principal, "IAM:getuser", bob, &context)), Decision::Allow);
+        assert_eq!(evaluator.evaluate(&request(&principal, "iam:DeleteUser", bob, &context)), Decision::ImplicitDeny);
+        assert_eq!(evaluator.evaluate(&request(&principal, "iam:UpdateUser", bob, &context)), Decision::ImplicitDeny);
+
+        context.insert("aws:RequestedRegion", SessionValue::String("us-west-2".to_string()));
+        assert_eq!(evaluator.evaluate(&request(&principal, "iam:UpdateUser", bob, &context)), Decision::Allow);
+
+        let admin = "arn:aws:iam::123456789012:user/admins/root-ish";
+        assert_eq!(evaluator.evaluate(&request(&principal, "iam:ListUserPolicies", admin, &context)), Decision::Allow);
+        assert_eq!(
+            evaluator.evaluate(&request(&principal, "iam:GetUser", admin, &context)),
+            Decision::ExplicitDeny {
+                policy: "NoAdmins".to_string(),
+                statement: "ProtectAdmins".to_string(),
+            }
+        );
+
+        assert_eq!(
+            PolicyEvaluator::new().evaluate(&request(&principal, "iam:GetUser", bob, &context)),
+            Decision::ImplicitDeny
+        );
+
+        // An allowed request is matched against every statement; evaluation stops at an explicit deny.
+        assert_eq!(evaluator.statement_count(), 3);
+        assert_eq!(evaluator.evaluate_counted(&request(&principal, "iam:GetUser", bob, &context)).1, 3);
+        let deny_first = PolicyEvaluator::new()
+            .with_policy("NoAdmins", NO_ADMINS)
+            .unwrap()
+            .with_policy("ReadOnly", READ_ONLY)
+            .unwrap();
+        assert_eq!(deny_first.evaluate_counted(&request(&principal, "iam:GetUser", admin, &context)).1, 1);
+    }
+
+    #[test_log::test]
+    fn test_action_matches() {
+        assert!(action_matches("ec2:Describe*", "ec2:DescribeInstances"));
+        assert!(action_matches("EC2:describe*", "ec2:DescribeInstances"));
+        assert!(action_matches("iam:?etUser", "iam:GetUser"));
+        assert!(action_matches("*", "sts:AssumeRole"));
+        assert!(action_matches("*:Get*", "iam:GetRole"));
+        assert!(!action_matches("ec2:Describe*", "ec2:RunInstances"));
+        assert!(!action_matches("iam*", "iam:GetUser"));
+        assert!(!action_matches("iam:GetUser", "iam:GetUserPolicy"));
+    }
+
+    #[test_log::test]
+    fn test_resource_matches() {
+        assert!(resource_matches("arn:aws:s3:::bucket/*", "arn:aws:s3:::bucket/photos/2022/cat.jpg"));
+        assert!(resource_matches("arn:aws:iam::*:user/*", "arn:aws:iam::123456789012:user/ops/alice"));
+        assert!(resource_matches(
+            "arn:aws:logs:us-?est-2:123456789012:log-group:*",
+            "arn:aws:logs:us-west-2:123456789012:log-group:app:log-stream:1"
+        ));
+        assert!(resource_matches("*", "*"));
+        assert!(resource_matches("*", "arn:aws:iam::123456789012:role/Deployer"));
+
+        // Wildcards do not cross segments, and ARNs are case-sensitive.
+        assert!(!resource_matches("arn:aws:iam::*", "arn:aws:iam::123456789012:user/alice"));
+        assert!(!resource_matches("arn:*:user/alice", "arn:aws:iam::123456789012:user/alice"));
+        assert!(!resource_matches("arn:aws:iam::123456789012:user/Alice", "arn:aws:iam::123456789012:user/alice"));
+        assert!(!resource_matches("arn:aws:s3:::bucket/*", "*"));
+    }
+
+    #[test_log::test]
+    fn test_rejects_unsupported_policies() {
+        for document in [
+            "not json",
+            r#"{"Statement": [{"Effect": "Allow", "Resource": "*"}]}"#,
+            r#"{"Statement": [{"Effect": "Allow", "Action": "*", "NotAction": "iam:*", "Resource": "*"}]}"#,
+            r#"{"Statement": [{"Effect": "Allow", "Principal": "*", "Action": "*", "Resource": "*"}]}"#,
+            r#"{"Statement": [{"Effect": "Deny", "Action": "*", "Resource": "*", "Condition": {"IpAddress": {"aws:SourceIp": "192.0.2.0/24"}}}]}"#,
+        ] {
+            let e = PolicyEvaluator::new().with_policy("Bad", document).unwrap_err();
+            assert_eq!(e.code(), "MalformedPolicyDocument", "{document}");
+        }
+    }
+}
@@ -0,0 +1,497 @@
+//! Feature flags for operations and behaviors.
+//!
+//! Flags are named booleans with a default per service and optional overrides per account. They start from the
+//! `[service.<name>.feature_flags]` configuration and can be changed at runtime through the admin endpoint at
+//! [ADMIN_PATH] when an admin token is configured. Every change is logged to [AUDIT_TARGET] and kept in a short
+//! history returned by the endpoint.
+//!
+//! A flag named `operation:<Action>` that is disabled rejects that action. The flags in effect for the caller's
+//! account are added to the request's session data under [FLAG_SESSION_PREFIX], so handlers can branch on them.
+use {
+    crate::{
+        authz::{AuthorizationMode, AUDIT_TARGET},
+        context::RequestContext,
+        net::ConnectionInfo,
+        protocol::{AwsError, ErrorProtocol},
+        session_keys::get_bool,
+    },
+    chrono::Utc,
+    http::{header::HeaderValue, Method, StatusCode},
+    hyper::{body::to_bytes, Body, Request, Response},
+    log::{debug, info},
+    ring::constant_time::verify_slices_are_equal,
+    scratchstack_aws_principal::{Principal, SessionData, SessionValue},
+    scratchstack_http_framework::RequestId,
+    serde::{Deserialize, Serialize},
+    std::{
+        collections::{BTreeMap, VecDeque},
+        future::Future,
+        pin::Pin,
+        sync::{Arc, Mutex, RwLock},
+        task::{Context, Poll},
+    },
+    tower::{BoxError, Service},
+};
+
+/// Whether authorization denials are enforced; overrides [AuthorizationMode] for an account.
+pub const ENFORCE_AUTHORIZATION: &str = "enforce-authorization";
+
+/// Whether SigV4a signatures are accepted.
+pub const SIGV4A: &str = "sigv4a";
+
+/// Whether the instance metadata emulation is served.
+pub const IMDS: &str = "imds";
+
+/// Prefix of flags that enable or disable a single action, e.g. `operation:CreateUser`.
+pub const OPERATION_PREFIX: &str = "operation:";
+
+/// Prefix of the session data keys that flags are exposed under, e.g. `scratchstack:FeatureFlag/sigv4a`.
+pub const FLAG_SESSION_PREFIX: &str = "scratchstack:FeatureFlag/";
+
+/// The path of the admin endpoint.
+pub const ADMIN_PATH: &str = "/_scratchstack/flags";
+
+/// The number of flag changes kept for the admin endpoint.
+const MAX_CHANGES: usize = 100;
+
+/// Initial feature flag settings.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct FeatureFlagConfig {
+    /// Flag values that apply to every account.
+    pub defaults: BTreeMap<String, bool>,
+
+    /// Flag values for individual accounts, keyed by account id. These take precedence over the defaults.
+    pub accounts: BTreeMap<String, BTreeMap<String, bool>>,
+
+    /// The bearer token required by the admin endpoint. Without one, the endpoint is not served.
+    pub admin_token: Option<String>,
+}
+
+/// A change made to a flag through [FeatureFlags::set].
+#[derive(Clone, Debug, Serialize)]
+pub struct FlagChange {
+    pub at: String,
+    pub actor: String,
+    pub flag: String,
+    pub account_id: Option<String>,
+
+    /// The new value, or `None` if the setting was removed.
+    pub enabled: Option<bool>,
+}
+
+#[derive(Debug, Default)]
+struct FlagState {
+    defaults: BTreeMap<String, bool>,
+    accounts: BTreeMap<String, BTreeMap<String, bool>>,
+}
+
+/// The flag registry shared by a service's request handlers.
+#[derive(Debug)]
+pub struct FeatureFlags {
+    state: RwLock<FlagState>,
+    changes: Mutex<VecDeque<FlagChange>>,
+    admin_token: Option<String>,
+}
+
+impl FeatureFlags {
+    pub fn new(config: &FeatureFlagConfig) -> Self {
+        Self {
+            state: RwLock::new(FlagState {
+                defaults: config.defaults.clone(),
+                accounts: config.accounts.clone(),
+            }),
+            changes: Mutex::new(VecDeque::new()),
+            admin_token: config.admin_token.clone(),
+        }
+    }
+
+    /// The value of `flag` for `account_id`, or `None` if it is not set.
+    pub fn get(&self, flag: &str, account_id: Option<&str>) -> Option<bool> {
+        let state = self.state.read().expect("feature flags poisoned");
+        account_id
+            .and_then(|account_id| state.accounts.get(account_id))
+            .and_then(|flags| flags.get(flag))
+            .or_else(|| state.defaults.get(flag))
+            .copied()
+    }
+
+    /// The value of `flag` for `account_id`, or `default` if it is not set.
+    pub fn is_enabled(&self, flag: &str, account_id: Option<&str>, default: bool) -> bool {
+        self.get(flag, account_id).unwrap_or(default)
+    }
+
+    /// Indicates whether `action` may be called by `account_id`. Actions are enabled unless a flag disables them.
+    pub fn operation_enabled(&self, action: &str, account_id: Option<&str>) -> bool {
+        self.is_enabled(&format!("{OPERATION_PREFIX}{action}"), account_id, true)
+    }
+
+    /// The authorization mode for `account_id`, applying [ENFORCE_AUTHORIZATION] to the configured `mode`.
+    pub fn authorization_mode(&self, account_id: Option<&str>, mode: AuthorizationMode) -> AuthorizationMode {
+        match self.get(ENFORCE_AUTHORIZATION, account_id) {
+            None => mode,
+            Some(true) => AuthorizationMode::Enforce,
+            Some(false) => AuthorizationMode::Permissive,
+        }
+    }
+
+    /// All flags in effect for `account_id`.
+    pub fn effective(&self, account_id: Option<&str>) -> BTreeMap<String, bool> {
+        let state = self.state.read().expect("feature flags poisoned");
+        let mut flags = state.defaults.clone();
+        if let Some(overrides) = account_id.and_then(|account_id| state.accounts.get(account_id)) {
+            flags.extend(overrides.iter().map(|(flag, enabled)| (flag.clone(), *enabled)));
+        }
+        flags
+    }
+
+    /// Set `flag` for `account_id` (or the default, if `None`), or remove the setting if `enabled` is `None`.
+    /// `actor` is recorded in the audit log.
+    pub fn set(&self, flag: &str, account_id: Option<&str>, enabled: Option<bool>, actor: &str) {
+        {
+            let mut state = self.state.write().expect("feature flags poisoned");
+            let flags = match account_id {
+                None => &mut state.defaults,
+                Some(account_id) => state.accounts.entry(account_id.to_string()).or_default(),
+            };
+
+            match enabled {
+                Some(enabled) => flags.insert(flag.to_string(), enabled),
+                None => flags.remove(flag),
+            };
+        }
+
+        info!(
+            target: AUDIT_TARGET,
+            "Feature flag {} for {} set to {} by {}",
+            flag,
+            account_id.unwrap_or("all accounts"),
+            enabled.map(|e| e.to_string()).unwrap_or_else(|| "unset".to_string()),
+            actor
+        );
+
+        let mut changes = self.changes.lock().expect("feature flag changes poisoned");
+        if changes.len() == MAX_CHANGES {
+            changes.pop_front();
+        }
+        changes.push_back(FlagChange {
+            at: Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            actor: actor.to_string(),
+            flag: flag.to_string(),
+            account_id: account_id.map(ToString::to_string),
+            enabled,
+        });
+    }
+
+    /// Recent changes, oldest first.
+    pub fn changes(&self) -> Vec<FlagChange> {
+        self.changes.lock().expect("feature flag changes poisoned").iter().cloned().collect()
+    }
+
+    /// Add the flags in effect for `account_id` to `session_data`.
+    pub fn add_to_session_data(&self, account_id: Option<&str>, session_data: &mut SessionData) {
+        for (flag, enabled) in self.effective(account_id) {
+            session_data.insert(&format!("{FLAG_SESSION_PREFIX}{flag}"), SessionValue::Bool(enabled));
+        }
+    }
+
+    fn has_operation_flags(&self) -> bool {
+        let state = self.state.read().expect("feature flags poisoned");
+        let is_operation = |flag: &String| flag.starts_with(OPERATION_PREFIX);
+        state.defaults.keys().any(is_operation) || state.accounts.values().any(|flags| flags.keys().any(is_operation))
+    }
+
+    fn admin_authorized<B>(&self, req: &Request<B>) -> Option<bool> {
+        let token = self.admin_token.as_ref()?;
+        Some(bearer_token_matches(req, token))
+    }
+}
+
+/// Indicates whether the request's `Authorization: Bearer` header carries `token`, compared in constant time.
+pub(crate) fn bearer_token_matches<B>(req: &Request<B>, token: &str) -> bool {
+    let presented = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .unwrap_or("");
+    verify_slices_are_equal(presented.as_bytes(), token.as_bytes()).is_ok()
+}
+
+/// Returns the session data value for `flag`, as added by [FeatureFlags::add_to_session_data].
+pub fn session_flag(session_data: &SessionData, flag: &str) -> Option<bool> {
+    get_bool(session_data, &format!("{FLAG_SESSION_PREFIX}{flag}")).ok().flatten()
+}
+
+/// A service wrapper that adds the caller's flags to the session data and rejects actions disabled by a flag.
+///
+/// This wraps the service implementation, after signature verification, so the caller's account is known.
+#[derive(Clone, Debug)]
+pub struct ApplyFeatureFlags<S> {
+    inner: S,
+    flags: Arc<FeatureFlags>,
+    xml_ns: &'static str,
+}
+
+impl<S> ApplyFeatureFlags<S> {
+    /// Wrap `inner`. Errors for disabled actions are rendered in the `xml_ns` namespace.
+    pub fn new(inner: S, flags: Arc<FeatureFlags>, xml_ns: &'static str) -> Self {
+        Self {
+            inner,
+            flags,
+            xml_ns,
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for ApplyFeatureFlags<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = BoxError> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        let account_id = req
+            .extensions()
+            .get::<Principal>()
+            .and_then(|principal| RequestContext::builder().principal(principal.clone()).build().ok())
+            .and_then(|context| context.account_id());
+
+        let extensions = req.extensions_mut();
+        if extensions.get::<SessionData>().is_none() {
+            extensions.insert(SessionData::new());
+        }
+        if let Some(session_data) = extensions.get_mut::<SessionData>() {
+            self.flags.add_to_session_data(account_id.as_deref(), session_data);
+        }
+
+        if !self.flags.has_operation_flags() {
+            return Box::pin(self.inner.call(req));
+        }
+
+        // The action is usually in the form parameters, so the body is needed.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let flags = self.flags.clone();
+        let xml_ns = self.xml_ns;
+
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = to_bytes(body).await?;
+            let query = form_urlencoded::parse(parts.uri.query().unwrap_or("").as_bytes());
+            let action = query
+                .chain(form_urlencoded::parse(&body))
+                .find(|(key, _)| key == "Action")
+                .map(|(_, value)| value.into_owned());
+
+            if let Some(action) = action {
+                if !flags.operation_enabled(&action, account_id.as_deref()) {
+                    debug!("{} is disabled by a feature flag", action);
+                    let request_id = parts.extensions.get::<RequestId>().copied().unwrap_or_else(RequestId::new);
+                    return disabled_response(xml_ns, &action, request_id);
+                }
+            }
+
+            inner.call(Request::from_parts(parts, Body::from(body))).await
+        })
+    }
+}
+
+fn disabled_response(xml_ns: &'static str, action: &str, request_id: RequestId) -> Result<Response<Body>, BoxError> {
+    let error = AwsError::sender(StatusCode::BAD_REQUEST, "UnsupportedOperation", format!("{action} is disabled"));
+    ErrorProtocol::Query {
+        xml_ns,
+    }
+    .response(&error, request_id)
+}
+
+/// Wraps a make-service (such as `SpawnService`) so each per-connection service answers the admin endpoint.
+#[derive(Clone, Debug)]
+pub struct WithFeatureFlagAdmin<M> {
+    inner: M,
+    flags: Arc<FeatureFlags>,
+}
+
+impl<M> WithFeatureFlagAdmin<M> {
+    pub fn new(inner: M, flags: Arc<FeatureFlags>) -> Self {
+        Self {
+            inner,
+            flags,
+        }
+    }
+}
+
+impl<T, M> Service<T> for WithFeatureFlagAdmin<M>
+where
+    M: Service<T>,
+    M::Future: Send + 'static,
+{
+    type Response = FeatureFlagAdmin<M::Response>;
+    type Error = M::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let flags = self.flags.clone();
+        let future = self.inner.call(target);
+        Box::pin(async move {
+            Ok(FeatureFlagAdmin {
+                inner: future.await?,
+                flags,
+            })
+        })
+    }
+}
+
+/// A per-connection service that answers the admin endpoint and passes other requests through.
+///
+/// - `GET /_scratchstack/flags[?account=ID]` returns the flags in effect and recent changes as JSON.
+/// - `PUT /_scratchstack/flags/NAME?enabled=BOOL[&account=ID]` sets a flag.
+/// - `DELETE /_scratchstack/flags/NAME[?account=ID]` removes a setting.
+#[derive(Clone, Debug)]
+pub struct FeatureFlagAdmin<S> {
+    inner: S,
+    flags: Arc<FeatureFlags>,
+}
+
+#[derive(Serialize)]
+struct FlagsDocument {
+    flags: BTreeMap<String, bool>,
+    changes: Vec<FlagChange>,
+}
+
+impl<S> FeatureFlagAdmin<S> {
+    fn handle(&self, req: &Request<Body>) -> Result<Response<Body>, BoxError> {
+        let query: BTreeMap<String, String> =
+            form_urlencoded::parse(req.uri().query().unwrap_or("").as_bytes()).into_owned().collect();
+        let account_id = query.get("account").map(String::as_str);
+        let flag = req.uri().path().strip_prefix(ADMIN_PATH).unwrap_or("").trim_start_matches('/');
+        let actor = req
+            .extensions()
+            .get::<ConnectionInfo>()
+            .map(|info| info.remote_addr().to_string())
+            .unwrap_or_else(|| "unknown".to_string());
+
+        let status = match (req.method(), flag.is_empty()) {
+            (&Method::GET, true) => {
+                let document = FlagsDocument {
+                    flags: self.flags.effective(account_id),
+                    changes: self.flags.changes(),
+                };
+                return Ok(Response::builder()
+                    .status(StatusCode::OK)
+                    .header("Content-Type", HeaderValue::from_static("application/json"))
+                    .header("Cache-Control", HeaderValue::from_static("no-store"))
+                    .body(Body::from(serde_json::to_string(&document)?))?);
+            }
+            (&Method::PUT, false) => match query.get("enabled").map(|enabled| enabled.parse::<bool>()) {
+                Some(Ok(enabled)) => {
+                    self.flags.set(flag, account_id, Some(enabled), &actor);
+                    StatusCode::NO_CONTENT
+                }
+                _ => StatusCode::BAD_REQUEST,
+            },
+            (&Method::DELETE, false) => {
+                self.flags.set(flag, account_id, None, &actor);
+                StatusCode::NO_CONTENT
+            }
+            _ => StatusCode::METHOD_NOT_ALLOWED,
+        };
+
+        Ok(Response::builder().status(status).body(Body::empty())?)
+    }
+}
+
+impl<S> Service<Request<Body>> for FeatureFlagAdmin<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let path = req.uri().path();
+        let is_admin = path == ADMIN_PATH || path.starts_with(&format!("{ADMIN_PATH}/"));
+        match self.flags.admin_authorized(&req) {
+            Some(true) if is_admin => {
+                let response = self.handle(&req);
+                Box::pin(async move { response })
+            }
+            Some(false) if is_admin => {
+                Box::pin(async move { Ok(Response::builder().status(StatusCode::UNAUTHORIZED).body(Body::empty())?) })
+            }
+            _ => {
+                let future = self.inner.call(req);
+                Box::pin(async move { future.await.map_err(Into::into) })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{session_flag, FeatureFlagConfig, FeatureFlags, ENFORCE_AUTHORIZATION, SIGV4A},
+        crate::authz::AuthorizationMode,
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::SessionData,
+    };
+
+    #[test_log::test]
+    fn test_flags() {
+        let config: FeatureFlagConfig = toml::from_str(
+            r#"
+            [defaults]
+            sigv4a = false
+            "operation:DeleteUser" = false
+
+            [accounts.123456789012]
+            sigv4a = true
+            "#,
+        )
+        .unwrap();
+        let flags = FeatureFlags::new(&config);
+
+        assert_eq!(flags.get(SIGV4A, None), Some(false));
+        assert_eq!(flags.get(SIGV4A, Some("123456789012")), Some(true));
+        assert!(!flags.operation_enabled("DeleteUser", Some("123456789012")));
+        assert!(flags.operation_enabled("GetUser", Some("123456789012")));
+        assert!(flags.has_operation_flags());
+
+        let mut session_data = SessionData::new();
+        flags.add_to_session_data(Some("123456789012"), &mut session_data);
+        assert_eq!(session_flag(&session_data, SIGV4A), Some(true));
+
+        assert_eq!(flags.authorization_mode(None, AuthorizationMode::Enforce), AuthorizationMode::Enforce);
+        flags.set(ENFORCE_AUTHORIZATION, Some("210987654321"), Some(false), "127.0.0.1:5000");
+        assert_eq!(
+            flags.authorization_mode(Some("210987654321"), AuthorizationMode::Enforce),
+            AuthorizationMode::Permissive
+        );
+
+        flags.set(SIGV4A, Some("123456789012"), None, "127.0.0.1:5000");
+        assert_eq!(flags.get(SIGV4A, Some("123456789012")), Some(false));
+
+        let changes = flags.changes();
+        assert_eq!(changes.len(), 2);
+        assert_eq!(changes[1].flag, SIGV4A);
+        assert_eq!(changes[1].enabled, None);
+    }
+}
@@ -0,0 +1,82 @@
+use {
+    crate::{
+        context::RequestContext,
+        session_keys::{
+            get_bool, get_string, AWS_CALLED_VIA, AWS_CALLED_VIA_FIRST, AWS_CALLED_VIA_LAST, AWS_VIA_AWS_SERVICE,
+        },
+    },
+    http::Request,
+    scratchstack_aws_principal::{SessionData, SessionValue},
+};
+
+/// Returns the session data for a call made by `service_principal` (e.g. `iam.amazonaws.com`) on behalf of the
+/// principal that `session_data` belongs to.
+///
+/// `aws:ViaAWSService` is set and `service_principal` is appended to the `aws:CalledVia` chain. Because condition
+/// keys are evaluated against session data, policies using these keys apply to the forwarded call automatically.
+pub fn forward_session_data(session_data: &SessionData, service_principal: &str) -> SessionData {
+    let mut chain = called_via(session_data);
+    chain.push(service_principal.to_string());
+
+    let mut forwarded = session_data.clone();
+    forwarded.insert(AWS_VIA_AWS_SERVICE, SessionValue::Bool(true));
+    forwarded.insert(AWS_CALLED_VIA, SessionValue::String(chain.join(",")));
+    forwarded.insert(AWS_CALLED_VIA_FIRST, SessionValue::String(chain[0].clone()));
+    forwarded.insert(AWS_CALLED_VIA_LAST, SessionValue::String(service_principal.to_string()));
+    forwarded
+}
+
+/// Prepare a request for an in-process call to another service on behalf of the caller in `context`.
+///
+/// The caller's principal and the forwarded session data are attached as extensions, in the same way the HTTP
+/// framework attaches them after verifying a signature. The request must be sent directly to the target service's
+/// inner handler rather than through its signature-verifying front end.
+pub fn forward_request<B>(context: &RequestContext, service_principal: &str, mut request: Request<B>) -> Request<B> {
+    let extensions = request.extensions_mut();
+    extensions.insert(context.principal().clone());
+    extensions.insert(forward_session_data(context.session_data(), service_principal));
+    extensions.insert(context.request_id());
+    request
+}
+
+/// Returns the `aws:CalledVia` chain recorded in `session_data`.
+pub fn called_via(session_data: &SessionData) -> Vec<String> {
+    match get_string(session_data, AWS_CALLED_VIA) {
+        Ok(Some(chain)) if !chain.is_empty() => chain.split(',').map(ToString::to_string).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Indicates whether `session_data` belongs to a call made by a service on behalf of a principal.
+pub fn via_aws_service(session_data: &SessionData) -> bool {
+    matches!(get_bool(session_data, AWS_VIA_AWS_SERVICE), Ok(Some(true)))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{called_via, forward_session_data, via_aws_service, AWS_CALLED_VIA_FIRST, AWS_CALLED_VIA_LAST},
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{SessionData, SessionValue},
+    };
+
+    #[test_log::test]
+    fn test_forward_session_data() {
+        let original = SessionData::new();
+        assert!(!via_aws_service(&original));
+        assert!(called_via(&original).is_empty());
+
+        let first = forward_session_data(&original, "cloudformation.amazonaws.com");
+        let second = forward_session_data(&first, "iam.amazonaws.com");
+        assert!(via_aws_service(&second));
+        assert_eq!(called_via(&second), vec!["cloudformation.amazonaws.com", "iam.amazonaws.com"]);
+        assert_eq!(
+            second.get(AWS_CALLED_VIA_FIRST),
+            Some(&SessionValue::String("cloudformation.amazonaws.com".to_string()))
+        );
+        assert_eq!(second.get(AWS_CALLED_VIA_LAST), Some(&SessionValue::String("iam.amazonaws.com".to_string())));
+
+        // The original session is not modified.
+        assert!(!via_aws_service(&original));
+    }
+}
@@ -0,0 +1,421 @@
+//! A circuit breaker around the signing key service.
+//!
+//! Every request needs a signing key, so when the database is unhealthy every request waits on it. The breaker
+//! watches signing key lookups and opens when too many fail or are slow. While it is open, [FailFast] answers
+//! requests with a 503 before they are verified. After a cool-down period, a single lookup is let through as a
+//! probe; the breaker closes again if it succeeds.
+use {
+    crate::protocol::{AwsError, ErrorProtocol},
+    http::{header::HeaderValue, StatusCode},
+    hyper::{Body, Request, Response},
+    log::{info, warn},
+    scratchstack_aws_signature::{GetSigningKeyRequest, GetSigningKeyResponse, SignatureError},
+    scratchstack_http_framework::RequestId,
+    serde::Deserialize,
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        future::Future,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+        time::{Duration, Instant},
+    },
+    tower::{BoxError, Service},
+};
+
+/// Thresholds for the signing key circuit breaker.
+#[derive(Clone, Debug, Deserialize)]
+pub struct CircuitBreakerConfig {
+    /// The percentage of failed or slow lookups in a window at which the breaker opens.
+    #[serde(default = "CircuitBreakerConfig::default_error_percent")]
+    pub error_percent: u32,
+
+    /// Lookups taking longer than this many milliseconds count as failures.
+    #[serde(default = "CircuitBreakerConfig::default_latency_ms")]
+    pub latency_ms: u64,
+
+    /// The breaker does not open until a window has seen this many lookups.
+    #[serde(default = "CircuitBreakerConfig::default_min_requests")]
+    pub min_requests: u32,
+
+    #[serde(default = "CircuitBreakerConfig::default_window_seconds")]
+    pub window_seconds: u64,
+
+    /// Seconds the breaker stays open before probing the database.
+    #[serde(default = "CircuitBreakerConfig::default_open_seconds")]
+    pub open_seconds: u64,
+}
+
+impl CircuitBreakerConfig {
+    fn default_error_percent() -> u32 {
+        50
+    }
+
+    fn default_latency_ms() -> u64 {
+        1000
+    }
+
+    fn default_min_requests() -> u32 {
+        20
+    }
+
+    fn default_window_seconds() -> u64 {
+        30
+    }
+
+    fn default_open_seconds() -> u64 {
+        10
+    }
+}
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum BreakerState {
+    Closed,
+    Open {
+        until: Instant,
+    },
+
+    /// A probe was admitted at `since`.
+    HalfOpen {
+        since: Instant,
+    },
+}
+
+/// How a lookup was admitted by [CircuitBreaker::admit].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Admission {
+    Normal,
+    Probe,
+    Rejected,
+}
+
+#[derive(Debug)]
+struct BreakerInner {
+    state: BreakerState,
+    window_start: Instant,
+    calls: u32,
+    failures: u32,
+}
+
+/// Shared state of the circuit breaker. Clones share the same state.
+#[derive(Clone, Debug)]
+pub struct CircuitBreaker {
+    config: Arc<CircuitBreakerConfig>,
+    inner: Arc<Mutex<BreakerInner>>,
+}
+
+impl CircuitBreaker {
+    pub fn new(config: CircuitBreakerConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            inner: Arc::new(Mutex::new(BreakerInner {
+                state: BreakerState::Closed,
+                window_start: Instant::now(),
+                calls: 0,
+                failures: 0,
+            })),
+        }
+    }
+
+    pub fn config(&self) -> &CircuitBreakerConfig {
+        &self.config
+    }
+
+    fn open_duration(&self) -> Duration {
+        Duration::from_secs(self.config.open_seconds)
+    }
+
+    /// Returns true if requests are currently being rejected.
+    pub fn is_open(&self) -> bool {
+        let inner = self.inner.lock().expect("circuit breaker poisoned");
+        let now = Instant::now();
+        match inner.state {
+            BreakerState::Closed => false,
+            BreakerState::Open {
+                until,
+            } => now < until,
+            BreakerState::HalfOpen {
+                since,
+            } => now.duration_since(since) < self.open_duration(),
+        }
+    }
+
+    /// Decide whether a lookup may proceed.
+    pub fn admit(&self) -> Admission {
+        let mut inner = self.inner.lock().expect("circuit breaker poisoned");
+        let now = Instant::now();
+        match inner.state {
+            BreakerState::Closed => Admission::Normal,
+            BreakerState::Open {
+                until,
+            } if now < until => Admission::Rejected,
+
+            // A probe that never reported back (e.g. its connection was dropped) is replaced after the same delay.
+            BreakerState::HalfOpen {
+                since,
+            } if now.duration_since(since) < self.open_duration() => Admission::Rejected,
+            _ => {
+                inner.state = BreakerState::HalfOpen {
+                    since: now,
+                };
+                Admission::Probe
+            }
+        }
+    }
+
+    /// Record the result of an admitted lookup. `healthy` is false if the lookup failed for a reason other than the
+    /// key being unknown, or took longer than the latency threshold.
+    pub fn record(&self, admission: Admission, healthy: bool) {
+        let mut inner = self.inner.lock().expect("circuit breaker poisoned");
+        let now = Instant::now();
+        match admission {
+            Admission::Rejected => (),
+            Admission::Probe if healthy => {
+                info!("Signing key lookups recovered; closing circuit breaker");
+                inner.state = BreakerState::Closed;
+                inner.window_start = now;
+                inner.calls = 0;
+                inner.failures = 0;
+            }
+            Admission::Probe => {
+                warn!("Signing key probe failed; circuit breaker remains open");
+                inner.state = BreakerState::Open {
+                    until: now + self.open_duration(),
+                };
+            }
+            Admission::Normal => {
+                if now.duration_since(inner.window_start) >= Duration::from_secs(self.config.window_seconds) {
+                    inner.window_start = now;
+                    inner.calls = 0;
+                    inner.failures = 0;
+                }
+
+                inner.calls += 1;
+                if !healthy {
+                    inner.failures += 1;
+                }
+
+                if inner.state == BreakerState::Closed
+                    && inner.calls >= self.config.min_requests
+                    && inner.failures * 100 >= self.config.error_percent * inner.calls
+                {
+                    warn!(
+                        "{} of {} signing key lookups failed or were slow; opening circuit breaker for {} seconds",
+                        inner.failures, inner.calls, self.config.open_seconds
+                    );
+                    inner.state = BreakerState::Open {
+                        until: now + self.open_duration(),
+                    };
+                }
+            }
+        }
+    }
+}
+
+/// The error returned by [SigningKeyCircuitBreaker] while the breaker is open.
+#[derive(Debug)]
+pub struct CircuitOpen;
+
+impl Error for CircuitOpen {}
+
+impl Display for CircuitOpen {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str("Signing key service unavailable")
+    }
+}
+
+/// Wraps a signing key service, feeding the outcome of each lookup to a [CircuitBreaker]. Lookups are rejected
+/// with [CircuitOpen] while the breaker is open.
+///
+/// If no breaker is given, this passes requests through unchanged.
+#[derive(Clone, Debug)]
+pub struct SigningKeyCircuitBreaker<G> {
+    inner: G,
+    breaker: Option<CircuitBreaker>,
+}
+
+impl<G> SigningKeyCircuitBreaker<G> {
+    pub fn new(inner: G, breaker: Option<CircuitBreaker>) -> Self {
+        Self {
+            inner,
+            breaker,
+        }
+    }
+}
+
+impl<G> Service<GetSigningKeyRequest> for SigningKeyCircuitBreaker<G>
+where
+    G: Service<GetSigningKeyRequest, Response = GetSigningKeyResponse>,
+    G::Error: Into<BoxError>,
+    G::Future: Send + 'static,
+{
+    type Response = GetSigningKeyResponse;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: GetSigningKeyRequest) -> Self::Future {
+        let breaker = match &self.breaker {
+            None => {
+                let future = self.inner.call(req);
+                return Box::pin(async move { future.await.map_err(Into::into) });
+            }
+            Some(breaker) => breaker.clone(),
+        };
+
+        let admission = breaker.admit();
+        if admission == Admission::Rejected {
+            return Box::pin(async { Err(CircuitOpen.into()) });
+        }
+
+        let latency_threshold = Duration::from_millis(breaker.config().latency_ms);
+        let start = Instant::now();
+        let future = self.inner.call(req);
+        Box::pin(async move {
+            let result = future.await.map_err(Into::into);
+
+            // Unknown keys and other signature errors are answered by a healthy database.
+            let healthy = match &result {
+                Ok(_) => start.elapsed() <= latency_threshold,
+                Err(e) => e.is::<SignatureError>() && start.elapsed() <= latency_threshold,
+            };
+            breaker.record(admission, healthy);
+            result
+        })
+    }
+}
+
+/// Wraps a make-service (such as `SpawnService`) so each per-connection service answers with a 503 while the
+/// breaker is open, without waiting on signature verification.
+#[derive(Clone, Debug)]
+pub struct WithCircuitBreaker<M> {
+    inner: M,
+    breaker: Option<CircuitBreaker>,
+    xml_ns: &'static str,
+}
+
+impl<M> WithCircuitBreaker<M> {
+    /// Wrap `inner`. Errors are rendered in the `xml_ns` namespace.
+    pub fn new(inner: M, breaker: Option<CircuitBreaker>, xml_ns: &'static str) -> Self {
+        Self {
+            inner,
+            breaker,
+            xml_ns,
+        }
+    }
+}
+
+impl<T, M> Service<T> for WithCircuitBreaker<M>
+where
+    M: Service<T>,
+    M::Future: Send + 'static,
+{
+    type Response = FailFast<M::Response>;
+    type Error = M::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let breaker = self.breaker.clone();
+        let xml_ns = self.xml_ns;
+        let future = self.inner.call(target);
+        Box::pin(async move {
+            Ok(FailFast {
+                inner: future.await?,
+                breaker,
+                xml_ns,
+            })
+        })
+    }
+}
+
+/// A per-connection service that rejects requests with `ServiceUnavailable` while the breaker is open.
+#[derive(Clone, Debug)]
+pub struct FailFast<S> {
+    inner: S,
+    breaker: Option<CircuitBreaker>,
+    xml_ns: &'static str,
+}
+
+impl<S> Service<Request<Body>> for FailFast<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if self.breaker.as_ref().map(CircuitBreaker::is_open).unwrap_or(false) {
+            let response = unavailable_response(self.xml_ns, RequestId::new());
+            return Box::pin(async move { response });
+        }
+
+        let future = self.inner.call(req);
+        Box::pin(async move { future.await.map_err(Into::into) })
+    }
+}
+
+fn unavailable_response(xml_ns: &'static str, request_id: RequestId) -> Result<Response<Body>, BoxError> {
+    let error = AwsError::receiver(
+        StatusCode::SERVICE_UNAVAILABLE,
+        "ServiceUnavailable",
+        "Service is unable to handle request.",
+    );
+    let mut response = ErrorProtocol::Query {
+        xml_ns,
+    }
+    .response(&error, request_id)?;
+    response.headers_mut().insert("Retry-After", HeaderValue::from_static("1"));
+    Ok(response)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{Admission, CircuitBreaker, CircuitBreakerConfig},
+        pretty_assertions::assert_eq,
+        std::{thread::sleep, time::Duration},
+    };
+
+    #[test_log::test]
+    fn test_transitions() {
+        let breaker = CircuitBreaker::new(CircuitBreakerConfig {
+            error_percent: 50,
+            latency_ms: 1000,
+            min_requests: 4,
+            window_seconds: 60,
+            open_seconds: 0,
+        });
+
+        // Failures below the minimum number of requests do not open the breaker.
+        for _ in 0..3 {
+            assert_eq!(breaker.admit(), Admission::Normal);
+            breaker.record(Admission::Normal, false);
+        }
+        assert!(!breaker.is_open());
+
+        breaker.record(Admission::Normal, true);
+        assert_eq!(breaker.admit(), Admission::Probe);
+
+        // A failed probe keeps it open; a successful one closes it.
+        breaker.record(Admission::Probe, false);
+        sleep(Duration::from_millis(1));
+        assert_eq!(breaker.admit(), Admission::Probe);
+        breaker.record(Admission::Probe, true);
+        assert_eq!(breaker.admit(), Admission::Normal);
+    }
+}
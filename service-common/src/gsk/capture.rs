@@ -0,0 +1,115 @@
+use {
+    scratchstack_aws_signature::{GetSigningKeyRequest, GetSigningKeyResponse},
+    std::{
+        collections::HashMap,
+        fmt::{Debug, Formatter, Result as FmtResult},
+        future::Future,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+        time::{Duration, Instant},
+    },
+    tower::{BoxError, Service},
+};
+
+/// How long a captured signing key is kept. This only needs to cover the time between signature verification and
+/// the response being produced.
+const CAPTURE_TTL: Duration = Duration::from_secs(300);
+
+/// Signing keys recently returned by a signing key service, indexed by credential
+/// (`<access key>/<date>/<region>/<service>/aws4_request`).
+#[derive(Clone, Default)]
+pub struct SigningKeyCache {
+    keys: Arc<Mutex<HashMap<String, (Vec<u8>, Instant)>>>,
+}
+
+impl SigningKeyCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the signing key for `credential` if it was captured recently.
+    pub fn get(&self, credential: &str) -> Option<Vec<u8>> {
+        let keys = self.keys.lock().expect("signing key cache poisoned");
+        match keys.get(credential) {
+            Some((key, captured_at)) if captured_at.elapsed() < CAPTURE_TTL => Some(key.clone()),
+            _ => None,
+        }
+    }
+
+    pub fn insert(&self, credential: String, key: Vec<u8>) {
+        let mut keys = self.keys.lock().expect("signing key cache poisoned");
+        keys.retain(|_, (_, captured_at)| captured_at.elapsed() < CAPTURE_TTL);
+        keys.insert(credential, (key, Instant::now()));
+    }
+
+    /// Forget every signing key derived from `access_key`.
+    pub fn remove_access_key(&self, access_key: &str) {
+        let prefix = format!("{access_key}/");
+        let mut keys = self.keys.lock().expect("signing key cache poisoned");
+        keys.retain(|credential, _| !credential.starts_with(&prefix));
+    }
+}
+
+impl Debug for SigningKeyCache {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let len = self.keys.lock().map(|keys| keys.len()).unwrap_or_default();
+        f.debug_struct("SigningKeyCache").field("len", &len).finish()
+    }
+}
+
+/// Wraps a signing key service, recording each signing key it returns in a [SigningKeyCache] so the key can be
+/// used after verification, e.g. by [ResponseSigning][crate::integrity::ResponseSigning].
+///
+/// If no cache is given, this passes requests through unchanged.
+#[derive(Clone, Debug)]
+pub struct CaptureSigningKey<G> {
+    inner: G,
+    cache: Option<SigningKeyCache>,
+}
+
+impl<G> CaptureSigningKey<G> {
+    pub fn new(inner: G, cache: Option<SigningKeyCache>) -> Self {
+        Self {
+            inner,
+            cache,
+        }
+    }
+}
+
+impl<G> Service<GetSigningKeyRequest> for CaptureSigningKey<G>
+where
+    G: Service<GetSigningKeyRequest, Response = GetSigningKeyResponse>,
+    G::Error: Into<BoxError>,
+    G::Future: Send + 'static,
+{
+    type Response = GetSigningKeyResponse;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: GetSigningKeyRequest) -> Self::Future {
+        let capture = self.cache.clone().map(|cache| {
+            let credential = format!(
+                "{}/{}/{}/{}/aws4_request",
+                req.access_key(),
+                req.request_date().format("%Y%m%d"),
+                req.region(),
+                req.service()
+            );
+            (cache, credential)
+        });
+
+        let future = self.inner.call(req);
+        Box::pin(async move {
+            let response = future.await.map_err(Into::into)?;
+            if let Some((cache, credential)) = capture {
+                cache.insert(credential, response.signing_key().as_ref().to_vec());
+            }
+            Ok(response)
+        })
+    }
+}
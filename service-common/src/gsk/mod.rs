@@ -0,0 +1,26 @@
+mod breaker;
+mod capture;
+mod inactive;
+mod memory;
+mod prefix;
+mod provider;
+mod region;
+mod revoked;
+mod root;
+mod session;
+
+pub use self::{
+    breaker::{
+        Admission, CircuitBreaker, CircuitBreakerConfig, CircuitOpen, FailFast, SigningKeyCircuitBreaker,
+        WithCircuitBreaker,
+    },
+    capture::{CaptureSigningKey, SigningKeyCache},
+    inactive::{RejectInactiveCredentials, INACTIVE_ACCESS_KEY_MESSAGE},
+    memory::MemorySigningKeys,
+    prefix::CanonicalAccessKeys,
+    provider::ProviderSigningKeys,
+    region::RegionValidation,
+    revoked::{RejectRevokedCredentials, RevokedCredentials},
+    root::{RootCredentialConfig, RootCredentials},
+    session::SessionCredentials,
+};
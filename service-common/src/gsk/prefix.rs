@@ -0,0 +1,84 @@
+use {
+    crate::access_key::AccessKeyPrefixes,
+    scratchstack_aws_signature::{GetSigningKeyRequest, GetSigningKeyResponse, SignatureError},
+    std::{
+        future::Future,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+    },
+    tower::{BoxError, Service},
+};
+
+/// Wraps a signing key service, rewriting access keys to their canonical `AKIA` or `ASIA` spelling before they are
+/// looked up, and rejecting keys whose prefix is not in the [AccessKeyPrefixes].
+///
+/// This belongs just inside [CaptureSigningKey][super::CaptureSigningKey]: the captured signing key is looked up
+/// again with the access key as the client sent it, while everything further in only sees canonical keys.
+#[derive(Clone, Debug)]
+pub struct CanonicalAccessKeys<G> {
+    inner: G,
+    prefixes: Arc<AccessKeyPrefixes>,
+}
+
+impl<G> CanonicalAccessKeys<G> {
+    pub fn new(inner: G, prefixes: AccessKeyPrefixes) -> Self {
+        Self {
+            inner,
+            prefixes: Arc::new(prefixes),
+        }
+    }
+
+    pub fn prefixes(&self) -> &AccessKeyPrefixes {
+        &self.prefixes
+    }
+}
+
+impl<G> Service<GetSigningKeyRequest> for CanonicalAccessKeys<G>
+where
+    G: Service<GetSigningKeyRequest, Response = GetSigningKeyResponse>,
+    G::Error: Into<BoxError>,
+    G::Future: Send + 'static,
+{
+    type Response = GetSigningKeyResponse;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: GetSigningKeyRequest) -> Self::Future {
+        let canonical = match self.prefixes.canonical(req.access_key()) {
+            Some(canonical) if self.prefixes.is_valid(req.access_key()) => canonical,
+            _ => {
+                return Box::pin(async {
+                    Err(SignatureError::InvalidClientTokenId(
+                        "The security token included in the request is invalid.".to_string(),
+                    )
+                    .into())
+                });
+            }
+        };
+
+        let req = if canonical == req.access_key() {
+            req
+        } else {
+            let rebuilt = GetSigningKeyRequest::builder()
+                .access_key(canonical)
+                .session_token(req.session_token().map(ToString::to_string))
+                .request_date(req.request_date())
+                .region(req.region())
+                .service(req.service())
+                .build();
+
+            match rebuilt {
+                Ok(rebuilt) => rebuilt,
+                Err(e) => return Box::pin(async move { Err(e.into()) }),
+            }
+        };
+
+        let future = self.inner.call(req);
+        Box::pin(async move { future.await.map_err(Into::into) })
+    }
+}
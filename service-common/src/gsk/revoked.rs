@@ -0,0 +1,94 @@
+use {
+    scratchstack_aws_signature::{GetSigningKeyRequest, GetSigningKeyResponse, SignatureError},
+    std::{
+        collections::HashMap,
+        fmt::{Debug, Formatter, Result as FmtResult},
+        future::Future,
+        pin::Pin,
+        sync::{Arc, RwLock},
+        task::{Context, Poll},
+        time::{Duration, Instant},
+    },
+    tower::{BoxError, Service},
+};
+
+/// Access keys deactivated recently, which are rejected without consulting the database or any cache.
+#[derive(Clone, Default)]
+pub struct RevokedCredentials {
+    keys: Arc<RwLock<HashMap<String, Instant>>>,
+}
+
+impl RevokedCredentials {
+    /// How long a revoked access key is rejected here. This outlasts every signing key cache; afterwards the key's
+    /// inactive status in the database keeps it out (see [RejectInactiveCredentials][super::RejectInactiveCredentials]),
+    /// so a key reactivated later works again.
+    pub const TTL: Duration = Duration::from_secs(900);
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Start rejecting `access_key` (including its `AKIA` prefix).
+    pub fn insert(&self, access_key: &str) {
+        let mut keys = self.keys.write().expect("revoked credentials poisoned");
+        keys.retain(|_, revoked_at| revoked_at.elapsed() < Self::TTL);
+        keys.insert(access_key.to_string(), Instant::now());
+    }
+
+    pub fn contains(&self, access_key: &str) -> bool {
+        let keys = self.keys.read().expect("revoked credentials poisoned");
+        matches!(keys.get(access_key), Some(revoked_at) if revoked_at.elapsed() < Self::TTL)
+    }
+}
+
+impl Debug for RevokedCredentials {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        let len = self.keys.read().map(|keys| keys.len()).unwrap_or_default();
+        f.debug_struct("RevokedCredentials").field("len", &len).finish()
+    }
+}
+
+/// Wraps a signing key service, rejecting access keys in [RevokedCredentials] as AWS rejects inactive keys.
+#[derive(Clone, Debug)]
+pub struct RejectRevokedCredentials<G> {
+    inner: G,
+    revoked: RevokedCredentials,
+}
+
+impl<G> RejectRevokedCredentials<G> {
+    pub fn new(inner: G, revoked: RevokedCredentials) -> Self {
+        Self {
+            inner,
+            revoked,
+        }
+    }
+}
+
+impl<G> Service<GetSigningKeyRequest> for RejectRevokedCredentials<G>
+where
+    G: Service<GetSigningKeyRequest, Response = GetSigningKeyResponse>,
+    G::Error: Into<BoxError>,
+    G::Future: Send + 'static,
+{
+    type Response = GetSigningKeyResponse;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: GetSigningKeyRequest) -> Self::Future {
+        if self.revoked.contains(req.access_key()) {
+            return Box::pin(async {
+                Err(SignatureError::InvalidClientTokenId(
+                    "The security token included in the request is invalid.".to_string(),
+                )
+                .into())
+            });
+        }
+
+        let future = self.inner.call(req);
+        Box::pin(async move { future.await.map_err(Into::into) })
+    }
+}
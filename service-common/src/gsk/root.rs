@@ -0,0 +1,134 @@
+use {
+    crate::{access_key::AccessKeyPrefixes, region::Partition, session_keys::AWS_USERID},
+    log::debug,
+    scratchstack_aws_principal::{Principal, PrincipalIdentity, RootUser, SessionData, SessionValue},
+    scratchstack_aws_signature::{GetSigningKeyRequest, GetSigningKeyResponse, KSecretKey},
+    serde::Deserialize,
+    std::{
+        collections::HashMap,
+        fmt::{Debug, Formatter, Result as FmtResult},
+        future::Future,
+        pin::Pin,
+        str::FromStr,
+        sync::Arc,
+        task::{Context, Poll},
+    },
+    tower::{BoxError, Service},
+};
+
+/// An access key for an account's root user.
+#[derive(Clone, Deserialize)]
+pub struct RootCredentialConfig {
+    pub account_id: String,
+    pub access_key_id: String,
+    pub secret_access_key: String,
+}
+
+impl Debug for RootCredentialConfig {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("RootCredentialConfig")
+            .field("account_id", &self.account_id)
+            .field("access_key_id", &self.access_key_id)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Wraps a signing key service, answering for the configured root access keys itself.
+///
+/// Requests signed with a root access key are authenticated as the account's root user, which bypasses identity
+/// policies; see [decide][crate::authz::decide]. Other requests are passed to the wrapped service.
+///
+/// The service maker clones this for every connection, so everything besides the wrapped service is shared.
+#[derive(Clone)]
+pub struct RootCredentials<G> {
+    inner: G,
+    partition: Arc<Partition>,
+
+    /// Account id and secret key, indexed by access key id.
+    keys: Arc<HashMap<String, (String, String)>>,
+}
+
+impl<G> RootCredentials<G> {
+    pub fn new(inner: G, partition: &Partition, credentials: &[RootCredentialConfig]) -> Self {
+        let keys = credentials
+            .iter()
+            .map(|c| (c.access_key_id.clone(), (c.account_id.clone(), c.secret_access_key.clone())))
+            .collect();
+
+        Self {
+            inner,
+            partition: Arc::new(partition.clone()),
+            keys: Arc::new(keys),
+        }
+    }
+
+    /// Match requests with the configured access key ids in their canonical spelling, as they are seen inside
+    /// [CanonicalAccessKeys][super::CanonicalAccessKeys]. Ids without an accepted prefix are kept as configured.
+    pub fn with_access_key_prefixes(mut self, prefixes: &AccessKeyPrefixes) -> Self {
+        let keys = self
+            .keys
+            .iter()
+            .map(|(access_key, value)| {
+                (prefixes.canonical(access_key).unwrap_or_else(|| access_key.clone()), value.clone())
+            })
+            .collect();
+        self.keys = Arc::new(keys);
+        self
+    }
+
+    /// Indicates whether any root access keys are configured.
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+}
+
+impl<G> Debug for RootCredentials<G> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("RootCredentials").field("partition", &self.partition).field("len", &self.keys.len()).finish()
+    }
+}
+
+impl<G> Service<GetSigningKeyRequest> for RootCredentials<G>
+where
+    G: Service<GetSigningKeyRequest, Response = GetSigningKeyResponse>,
+    G::Error: Into<BoxError>,
+    G::Future: Send + 'static,
+{
+    type Response = GetSigningKeyResponse;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: GetSigningKeyRequest) -> Self::Future {
+        let (account_id, secret_key) = match self.keys.get(req.access_key()) {
+            Some(key) => key.clone(),
+            None => {
+                let future = self.inner.call(req);
+                return Box::pin(async move { future.await.map_err(Into::into) });
+            }
+        };
+
+        let partition = self.partition.clone();
+        Box::pin(async move {
+            debug!("Access key {} belongs to the root user of {}", req.access_key(), account_id);
+            let root = RootUser::new(partition.as_str(), &account_id)?;
+            let signing_key = KSecretKey::from_str(&secret_key)?
+                .to_kdate(req.request_date())
+                .to_kregion(req.region())
+                .to_kservice(req.service())
+                .to_ksigning();
+            // As in AWS, the unique id of the root user is the account id.
+            let mut session_data = SessionData::new();
+            session_data.insert(AWS_USERID, SessionValue::String(account_id));
+            let response = GetSigningKeyResponse::builder()
+                .principal(Principal::from(vec![PrincipalIdentity::from(root)]))
+                .session_data(session_data)
+                .signing_key(signing_key)
+                .build()?;
+            Ok(response)
+        })
+    }
+}
@@ -0,0 +1,429 @@
+//! Liveness and readiness endpoints and graceful shutdown.
+//!
+//! `GET /healthz` answers 200 while the process is serving requests at all. `GET /readyz` answers 200 only if the
+//! database is reachable, its schema is the expected version, and the service is not draining. Both are answered
+//! before signature verification, so they need no credentials. The paths can be changed, or the endpoints turned off,
+//! with [HealthConfig].
+//!
+//! Shutdown is requested by cancelling a [CancellationToken]; the binaries cancel theirs on SIGTERM with
+//! [cancel_on_termination], and programs embedding a service cancel it themselves. [shutdown_signal] then marks the
+//! service as draining, so readiness fails immediately, but keeps accepting connections for
+//! [HealthConfig::drain_seconds] while load balancers notice. Hyper then stops accepting and waits for in-flight
+//! requests to finish; [serve_until_shutdown] abandons them after [HealthConfig::shutdown_timeout_seconds], so the
+//! process exits before its supervisor kills it.
+use {
+    crate::schema::{check_schema_version, ExpectedSchema},
+    futures::future::Either,
+    http::{header::HeaderValue, Method, StatusCode},
+    hyper::{service::Service, Body, Request, Response},
+    log::{info, warn},
+    serde::Deserialize,
+    sqlx::AnyPool,
+    std::{
+        future::Future,
+        pin::Pin,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        task::{Context, Poll},
+        time::Duration,
+    },
+    tokio::{signal::ctrl_c, time::timeout},
+    tower::BoxError,
+};
+
+pub use tokio_util::sync::CancellationToken;
+
+/// How long a readiness check waits for the database before reporting it unreachable.
+const READINESS_TIMEOUT: Duration = Duration::from_secs(2);
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct HealthConfig {
+    /// Whether the liveness and readiness endpoints are answered. If not, requests for them are passed to the service
+    /// like any other and need a signature.
+    #[serde(default = "HealthConfig::default_enabled")]
+    pub enabled: bool,
+
+    #[serde(default = "HealthConfig::default_liveness_path")]
+    pub liveness_path: String,
+
+    #[serde(default = "HealthConfig::default_readiness_path")]
+    pub readiness_path: String,
+
+    /// Seconds to keep accepting connections after SIGTERM, while readiness reports false. This should cover the
+    /// time a load balancer takes to stop sending new requests.
+    #[serde(default = "HealthConfig::default_drain_seconds")]
+    pub drain_seconds: u64,
+
+    /// Seconds to wait for in-flight requests to finish once connections are no longer accepted. Together with
+    /// `drain_seconds` this should be less than the supervisor's kill timeout, such as Kubernetes'
+    /// `terminationGracePeriodSeconds`.
+    #[serde(default = "HealthConfig::default_shutdown_timeout_seconds")]
+    pub shutdown_timeout_seconds: u64,
+}
+
+impl HealthConfig {
+    fn default_enabled() -> bool {
+        true
+    }
+
+    fn default_liveness_path() -> String {
+        "/healthz".to_string()
+    }
+
+    fn default_readiness_path() -> String {
+        "/readyz".to_string()
+    }
+
+    /// The paths answered without a signature, if the endpoints are enabled.
+    pub fn paths(&self) -> Vec<String> {
+        if self.enabled {
+            vec![self.liveness_path.clone(), self.readiness_path.clone()]
+        } else {
+            Vec::new()
+        }
+    }
+
+    fn default_drain_seconds() -> u64 {
+        5
+    }
+
+    fn default_shutdown_timeout_seconds() -> u64 {
+        20
+    }
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        Self {
+            enabled: Self::default_enabled(),
+            liveness_path: Self::default_liveness_path(),
+            readiness_path: Self::default_readiness_path(),
+            drain_seconds: Self::default_drain_seconds(),
+            shutdown_timeout_seconds: Self::default_shutdown_timeout_seconds(),
+        }
+    }
+}
+
+/// Shared health state of a service.
+#[derive(Debug)]
+pub struct Health {
+    pool: Arc<AnyPool>,
+
+    /// If `None`, the schema version is not checked.
+    schema: Option<ExpectedSchema>,
+    draining: AtomicBool,
+}
+
+impl Health {
+    pub fn new(pool: Arc<AnyPool>, schema: Option<ExpectedSchema>) -> Self {
+        Self {
+            pool,
+            schema,
+            draining: AtomicBool::new(false),
+        }
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Acquire)
+    }
+
+    pub fn set_draining(&self) {
+        self.draining.store(true, Ordering::Release);
+    }
+
+    /// Returns `Err` with the reason if the service should not receive new requests.
+    pub async fn readiness(&self) -> Result<(), String> {
+        if self.is_draining() {
+            return Err("draining".to_string());
+        }
+
+        match timeout(READINESS_TIMEOUT, sqlx::query("SELECT 1").execute(self.pool.as_ref())).await {
+            Err(_) => return Err("database did not respond".to_string()),
+            Ok(Err(e)) => return Err(format!("database unreachable: {e}")),
+            Ok(Ok(_)) => (),
+        }
+
+        if let Some(schema) = self.schema {
+            check_schema_version(&self.pool, schema).await.map_err(|e| e.to_string())?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Wait for `shutdown` to be cancelled, then mark `health` as draining and wait `drain` before returning. Pass this
+/// to Hyper's `with_graceful_shutdown`.
+pub async fn shutdown_signal(health: Arc<Health>, drain: Duration, shutdown: CancellationToken) {
+    shutdown.cancelled().await;
+    info!("Shutdown requested; reporting not ready and draining for {} seconds", drain.as_secs());
+    health.set_draining();
+    tokio::time::sleep(drain).await;
+    info!("No longer accepting connections; waiting for in-flight requests to finish");
+}
+
+/// Run `server`, a Hyper server using [shutdown_signal], until it stops. Once `shutdown` is cancelled, the server is
+/// given `drain` plus `timeout` to finish; if in-flight requests are still running then, they are abandoned and this
+/// returns `None`.
+pub async fn serve_until_shutdown<F: Future>(
+    server: F,
+    shutdown: CancellationToken,
+    drain: Duration,
+    timeout_after_drain: Duration,
+) -> Option<F::Output> {
+    let mut server = Box::pin(server);
+    match futures::future::select(&mut server, Box::pin(shutdown.cancelled())).await {
+        Either::Left((output, _)) => return Some(output),
+        Either::Right(((), _)) => (),
+    }
+
+    match timeout(drain + timeout_after_drain, server).await {
+        Ok(output) => Some(output),
+        Err(_) => {
+            warn!(
+                "In-flight requests did not finish within {} seconds; abandoning them",
+                timeout_after_drain.as_secs()
+            );
+            None
+        }
+    }
+}
+
+/// Cancel `shutdown` on SIGTERM or Ctrl-C.
+pub async fn cancel_on_termination(shutdown: CancellationToken) {
+    wait_for_termination().await;
+    shutdown.cancel();
+}
+
+#[cfg(unix)]
+async fn wait_for_termination() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    match signal(SignalKind::terminate()) {
+        Ok(mut terminate) => {
+            futures::future::select(Box::pin(terminate.recv()), Box::pin(ctrl_c())).await;
+        }
+        Err(e) => {
+            warn!("Unable to listen for SIGTERM: {}", e);
+            let _ = ctrl_c().await;
+        }
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_termination() {
+    let _ = ctrl_c().await;
+}
+
+/// Wraps a make-service (such as `SpawnService`) so each per-connection service answers the liveness and readiness
+/// endpoints set in `config`.
+#[derive(Clone, Debug)]
+pub struct WithHealthChecks<M> {
+    inner: M,
+    health: Arc<Health>,
+    config: Arc<HealthConfig>,
+}
+
+impl<M> WithHealthChecks<M> {
+    pub fn new(inner: M, health: Arc<Health>, config: &HealthConfig) -> Self {
+        Self {
+            inner,
+            health,
+            config: Arc::new(config.clone()),
+        }
+    }
+}
+
+impl<T, M> Service<T> for WithHealthChecks<M>
+where
+    M: Service<T>,
+    M::Future: Send + 'static,
+{
+    type Response = HealthChecks<M::Response>;
+    type Error = M::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let health = self.health.clone();
+        let config = self.config.clone();
+        let future = self.inner.call(target);
+        Box::pin(async move {
+            Ok(HealthChecks {
+                inner: future.await?,
+                health,
+                config,
+            })
+        })
+    }
+}
+
+/// A per-connection service that answers health checks and passes other requests through.
+#[derive(Clone, Debug)]
+pub struct HealthChecks<S> {
+    inner: S,
+    health: Arc<Health>,
+    config: Arc<HealthConfig>,
+}
+
+impl<S> Service<Request<Body>> for HealthChecks<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        if self.config.enabled && (req.method() == Method::GET || req.method() == Method::HEAD) {
+            let path = req.uri().path();
+            if path == self.config.liveness_path {
+                return Box::pin(async { health_response(StatusCode::OK, "ok") });
+            }
+            if path == self.config.readiness_path {
+                let health = self.health.clone();
+                return Box::pin(async move {
+                    match health.readiness().await {
+                        Ok(()) => health_response(StatusCode::OK, "ready"),
+                        Err(reason) => health_response(StatusCode::SERVICE_UNAVAILABLE, &reason),
+                    }
+                });
+            }
+        }
+
+        let future = self.inner.call(req);
+        Box::pin(async move { future.await.map_err(Into::into) })
+    }
+}
+
+fn health_response(status: StatusCode, body: &str) -> Result<Response<Body>, BoxError> {
+    Ok(Response::builder()
+        .status(status)
+        .header("Content-Type", HeaderValue::from_static("text/plain"))
+        .header("Cache-Control", HeaderValue::from_static("no-store"))
+        .body(Body::from(format!("{body}\n")))?)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{serve_until_shutdown, shutdown_signal, CancellationToken, Health, HealthChecks, HealthConfig},
+        hyper::{
+            service::{service_fn, Service},
+            Body, Request, Response, StatusCode,
+        },
+        pretty_assertions::assert_eq,
+        sqlx::any::AnyPoolOptions,
+        std::{convert::Infallible, sync::Arc, time::Duration},
+    };
+
+    fn get(path: &str) -> Request<Body> {
+        Request::get(path).body(Body::empty()).unwrap()
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_readiness() {
+        let pool = AnyPoolOptions::new().connect("sqlite::memory:").await.unwrap();
+        let health = Health::new(Arc::new(pool), None);
+        assert_eq!(health.readiness().await, Ok(()));
+
+        health.set_draining();
+        assert_eq!(health.readiness().await, Err("draining".to_string()));
+        assert_eq!(HealthConfig::default().drain_seconds, 5);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_health_checks() {
+        let pool = AnyPoolOptions::new().connect("sqlite::memory:").await.unwrap();
+        let health = Arc::new(Health::new(Arc::new(pool), None));
+        let config = HealthConfig {
+            liveness_path: "/live".to_string(),
+            ..HealthConfig::default()
+        };
+        let mut service = HealthChecks {
+            inner: service_fn(|_: Request<Body>| async {
+                Ok::<_, Infallible>(Response::builder().status(StatusCode::FORBIDDEN).body(Body::empty()).unwrap())
+            }),
+            health: health.clone(),
+            config: Arc::new(config.clone()),
+        };
+
+        assert_eq!(service.call(get("/live")).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(service.call(get("/readyz")).await.unwrap().status(), StatusCode::OK);
+        assert_eq!(service.call(get("/healthz")).await.unwrap().status(), StatusCode::FORBIDDEN);
+        health.set_draining();
+        assert_eq!(service.call(get("/readyz")).await.unwrap().status(), StatusCode::SERVICE_UNAVAILABLE);
+
+        // Disabled, the endpoints need a signature like everything else.
+        let config = HealthConfig {
+            enabled: false,
+            ..config
+        };
+        assert!(config.paths().is_empty());
+        service.config = Arc::new(config);
+        assert_eq!(service.call(get("/live")).await.unwrap().status(), StatusCode::FORBIDDEN);
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn test_shutdown_signal() {
+        let pool = AnyPoolOptions::new().connect_lazy("sqlite::memory:").unwrap();
+        let health = Arc::new(Health::new(Arc::new(pool), None));
+        let shutdown = CancellationToken::new();
+        let signal = tokio::spawn(shutdown_signal(health.clone(), Duration::from_secs(5), shutdown.clone()));
+
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(!signal.is_finished());
+        assert!(!health.is_draining());
+
+        shutdown.cancel();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(health.is_draining());
+        assert!(!signal.is_finished());
+
+        tokio::time::sleep(Duration::from_secs(5)).await;
+        assert!(signal.is_finished());
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn test_serve_until_shutdown() {
+        let drain = Duration::from_secs(5);
+        let timeout = Duration::from_secs(20);
+
+        // A server that stops on its own is not cut short.
+        let shutdown = CancellationToken::new();
+        assert_eq!(serve_until_shutdown(async { 1 }, shutdown, drain, timeout).await, Some(1));
+
+        // One that finishes its requests in time returns normally.
+        let shutdown = CancellationToken::new();
+        let stopping = shutdown.clone();
+        let server = async move {
+            stopping.cancelled().await;
+            tokio::time::sleep(Duration::from_secs(10)).await;
+            2
+        };
+        let serving = tokio::spawn(serve_until_shutdown(server, shutdown.clone(), drain, timeout));
+        shutdown.cancel();
+        assert_eq!(serving.await.unwrap(), Some(2));
+
+        // One that never finishes is abandoned once the drain and the timeout have passed.
+        let shutdown = CancellationToken::new();
+        let serving =
+            tokio::spawn(serve_until_shutdown(futures::future::pending::<()>(), shutdown.clone(), drain, timeout));
+        tokio::time::sleep(Duration::from_secs(60)).await;
+        assert!(!serving.is_finished());
+        shutdown.cancel();
+        tokio::time::sleep(Duration::from_secs(24)).await;
+        assert!(!serving.is_finished());
+        assert_eq!(serving.await.unwrap(), None);
+    }
+}
@@ -0,0 +1,95 @@
+//! Fixed-bucket histograms for the Prometheus metrics the services keep.
+//!
+//! Each metrics type holds one [Histogram] per series, behind its own lock, and writes it out with
+//! [Histogram::render] among its other metrics. Bucket bounds are fixed when the histogram is created, so series of
+//! the same metric always share them.
+use std::fmt::Write;
+
+/// A histogram with fixed bucket bounds. Bucket counts are not cumulative; they are summed when rendered.
+#[derive(Clone, Debug)]
+pub struct Histogram {
+    bounds: &'static [f64],
+
+    /// One count per bound, and one for values above the last bound.
+    buckets: Vec<u64>,
+    count: u64,
+    sum: f64,
+}
+
+impl Histogram {
+    /// An empty histogram with buckets up to each of `bounds`, which must be in increasing order.
+    pub fn new(bounds: &'static [f64]) -> Self {
+        Self {
+            bounds,
+            buckets: vec![0; bounds.len() + 1],
+            count: 0,
+            sum: 0.0,
+        }
+    }
+
+    pub fn observe(&mut self, value: f64) {
+        let bucket = self.bounds.iter().position(|bound| value <= *bound).unwrap_or(self.bounds.len());
+        self.buckets[bucket] += 1;
+        self.count += 1;
+        self.sum += value;
+    }
+
+    /// The number of values observed.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    pub fn sum(&self) -> f64 {
+        self.sum
+    }
+
+    /// Append the `_bucket`, `_sum` and `_count` samples of the metric `name` to `out`. `labels`, such as
+    /// `action="GetUser"`, are added to each sample; it is empty for a metric with a single series.
+    pub fn render(&self, out: &mut String, name: &str, labels: &str) {
+        let (bucket_labels, labels) = if labels.is_empty() {
+            (String::new(), String::new())
+        } else {
+            (format!("{labels},"), format!("{{{labels}}}"))
+        };
+
+        let mut cumulative = 0;
+        for (bound, count) in self.bounds.iter().zip(&self.buckets) {
+            cumulative += count;
+            let _ = writeln!(out, "{name}_bucket{{{bucket_labels}le=\"{bound}\"}} {cumulative}");
+        }
+        let _ = writeln!(out, "{name}_bucket{{{bucket_labels}le=\"+Inf\"}} {}", self.count);
+        let _ = writeln!(out, "{name}_sum{labels} {}", self.sum);
+        let _ = writeln!(out, "{name}_count{labels} {}", self.count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::Histogram, pretty_assertions::assert_eq};
+
+    #[test_log::test]
+    fn test_render() {
+        let mut histogram = Histogram::new(&[1.0, 5.0]);
+        for value in [0.5, 1.0, 3.0, 7.5] {
+            histogram.observe(value);
+        }
+        assert_eq!((histogram.count(), histogram.sum()), (4, 12.0));
+
+        let mut out = String::new();
+        histogram.render(&mut out, "test_seconds", "");
+        assert_eq!(
+            out,
+            "test_seconds_bucket{le=\"1\"} 2\ntest_seconds_bucket{le=\"5\"} 3\ntest_seconds_bucket{le=\"+Inf\"} 4\n\
+             test_seconds_sum 12\ntest_seconds_count 4\n"
+        );
+
+        let mut out = String::new();
+        Histogram::new(&[1.0]).render(&mut out, "test_seconds", "action=\"GetUser\"");
+        assert_eq!(
+            out,
+            "test_seconds_bucket{action=\"GetUser\",le=\"1\"} 0\n\
+             test_seconds_bucket{action=\"GetUser\",le=\"+Inf\"} 0\n\
+             test_seconds_sum{action=\"GetUser\"} 0\ntest_seconds_count{action=\"GetUser\"} 0\n"
+        );
+    }
+}
@@ -0,0 +1,51 @@
+//! Generation of the unique ids IAM gives to users, roles, and other entities.
+//!
+//! Unlike names, ids are never reused: a user that is deleted and recreated with the same name gets a new id, which
+//! is how policies that name the old user by id stop applying to the new one.
+use {
+    crate::access_key::ALPHABET,
+    ring::rand::{SecureRandom, SystemRandom},
+};
+
+/// The prefix of user ids.
+pub const USER_ID_PREFIX: &str = "AIDA";
+
+/// The prefix of role ids.
+pub const ROLE_ID_PREFIX: &str = "AROA";
+
+/// The prefix of SSH public key ids.
+pub const SSH_PUBLIC_KEY_ID_PREFIX: &str = "APKA";
+
+/// The prefix of service specific credential ids.
+pub const SERVICE_SPECIFIC_CREDENTIAL_ID_PREFIX: &str = "ACCA";
+
+/// The length of generated ids, including the prefix. This is the width of the id columns in the `iam` schema.
+pub const ID_LENGTH: usize = 16;
+
+/// Generate a new id starting with `prefix`, filled out to [ID_LENGTH] with random characters.
+pub fn unique_id(prefix: &str) -> String {
+    let mut random = vec![0u8; ID_LENGTH.saturating_sub(prefix.len())];
+    SystemRandom::new().fill(&mut random).expect("Unable to generate id");
+
+    let mut id = prefix.to_string();
+    // 256 is a multiple of the alphabet size, so every character is equally likely.
+    id.extend(random.iter().map(|b| ALPHABET[(*b as usize) % ALPHABET.len()] as char));
+    id
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{unique_id, ID_LENGTH, USER_ID_PREFIX},
+        pretty_assertions::assert_eq,
+    };
+
+    #[test_log::test]
+    fn test_unique_id() {
+        let id = unique_id(USER_ID_PREFIX);
+        assert_eq!(id.len(), ID_LENGTH);
+        assert!(id.starts_with("AIDA"));
+        assert!(id.bytes().all(|b| b.is_ascii_uppercase() || (b'2'..=b'7').contains(&b)));
+        assert_ne!(id, unique_id(USER_ID_PREFIX));
+    }
+}
@@ -0,0 +1,365 @@
+//! Inline policy operations for users, groups, and roles: `Put*Policy`, `Get*Policy`, `Delete*Policy`, and
+//! `List*Policies`.
+//!
+//! Policy documents are validated with Aspen before they are stored. Some clients URL-encode documents a second
+//! time before sending them, as IAM does when returning them, so a document that is not JSON as given is
+//! percent-decoded first.
+use {
+    crate::{
+        attached_policies::holder_element,
+        operation::{
+            output::{query_response, write_element, ToXml},
+            ValidationError,
+        },
+        operation_input, operation_output,
+        protocol::IAM_XML_NS,
+        store::{ControlPlaneStore, InlinePolicy, PolicyHolder, StoreError},
+    },
+    http::StatusCode,
+    percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC},
+    scratchstack_aspen::Policy,
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+/// Characters left unencoded in returned policy documents: the RFC 3986 unreserved set.
+const UNRESERVED: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+operation_input! {
+    /// Input for the PutUserPolicy operation.
+    pub struct PutUserPolicyInput {
+        "UserName" => pub user_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+        "PolicyName" => pub policy_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+        "PolicyDocument" => pub policy_document: String where length(1, 131072),
+    }
+}
+
+operation_input! {
+    /// Input for the GetUserPolicy and DeleteUserPolicy operations.
+    pub struct UserPolicyInput {
+        "UserName" => pub user_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+        "PolicyName" => pub policy_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+    }
+}
+
+operation_input! {
+    /// Input for the ListUserPolicies operation.
+    pub struct ListUserPoliciesInput {
+        "UserName" => pub user_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+    }
+}
+
+operation_input! {
+    /// Input for the PutGroupPolicy operation.
+    pub struct PutGroupPolicyInput {
+        "GroupName" => pub group_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+        "PolicyName" => pub policy_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+        "PolicyDocument" => pub policy_document: String where length(1, 131072),
+    }
+}
+
+operation_input! {
+    /// Input for the GetGroupPolicy and DeleteGroupPolicy operations.
+    pub struct GroupPolicyInput {
+        "GroupName" => pub group_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+        "PolicyName" => pub policy_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+    }
+}
+
+operation_input! {
+    /// Input for the ListGroupPolicies operation.
+    pub struct ListGroupPoliciesInput {
+        "GroupName" => pub group_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+    }
+}
+
+operation_input! {
+    /// Input for the PutRolePolicy operation.
+    pub struct PutRolePolicyInput {
+        "RoleName" => pub role_name: String where length(1, 64), pattern(r"[\w+=,.@-]+"),
+        "PolicyName" => pub policy_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+        "PolicyDocument" => pub policy_document: String where length(1, 131072),
+    }
+}
+
+operation_input! {
+    /// Input for the GetRolePolicy and DeleteRolePolicy operations.
+    pub struct RolePolicyInput {
+        "RoleName" => pub role_name: String where length(1, 64), pattern(r"[\w+=,.@-]+"),
+        "PolicyName" => pub policy_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+    }
+}
+
+operation_input! {
+    /// Input for the ListRolePolicies operation.
+    pub struct ListRolePoliciesInput {
+        "RoleName" => pub role_name: String where length(1, 64), pattern(r"[\w+=,.@-]+"),
+    }
+}
+
+/// Errors from inline policy operations.
+#[derive(Debug)]
+pub enum InlinePolicyError {
+    Validation(ValidationError),
+
+    /// The policy document is not a valid policy.
+    MalformedPolicyDocument(String),
+    Store(StoreError),
+}
+
+impl InlinePolicyError {
+    /// The IAM error code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Validation(e) => e.code(),
+            Self::MalformedPolicyDocument(_) => "MalformedPolicyDocument",
+            Self::Store(e) => e.code(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Validation(_) | Self::MalformedPolicyDocument(_) => StatusCode::BAD_REQUEST,
+            Self::Store(e) => e.status(),
+        }
+    }
+}
+
+impl Error for InlinePolicyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Validation(e) => Some(e),
+            Self::MalformedPolicyDocument(_) => None,
+            Self::Store(e) => Some(e),
+        }
+    }
+}
+
+impl Display for InlinePolicyError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Validation(e) => write!(f, "{e}"),
+            Self::MalformedPolicyDocument(message) => write!(f, "Syntax errors in policy: {message}"),
+            Self::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<ValidationError> for InlinePolicyError {
+    fn from(e: ValidationError) -> Self {
+        Self::Validation(e)
+    }
+}
+
+impl From<StoreError> for InlinePolicyError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+/// An inline policy, as returned by Get*Policy.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GetInlinePolicyOutput {
+    pub holder: PolicyHolder,
+    pub holder_name: String,
+    pub policy: InlinePolicy,
+}
+
+impl GetInlinePolicyOutput {
+    /// The `Get{User,Group,Role}PolicyResponse` body, with the document URL-encoded.
+    pub fn to_xml(&self, request_id: &str) -> String {
+        let operation = format!("Get{}Policy", holder_element(self.holder));
+        query_response(IAM_XML_NS, &operation, self, request_id)
+    }
+}
+
+/// The holder's name is written as its `UserName`, `GroupName`, or `RoleName`.
+impl ToXml for GetInlinePolicyOutput {
+    fn write_xml(&self, xml: &mut String) {
+        write_element(xml, &format!("{}Name", holder_element(self.holder)), &self.holder_name);
+        write_element(xml, "PolicyName", &self.policy.policy_name);
+        write_element(xml, "PolicyDocument", &encode_policy_document(&self.policy.policy_document));
+    }
+}
+
+/// The names of a holder's inline policies, as returned by List*Policies. The list is never truncated.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ListInlinePoliciesOutput {
+    pub holder: PolicyHolder,
+    pub policy_names: Vec<String>,
+}
+
+impl ListInlinePoliciesOutput {
+    /// The `List{User,Group,Role}PoliciesResponse` body.
+    pub fn to_xml(&self, request_id: &str) -> String {
+        let result = ListInlinePoliciesResult {
+            policy_names: &self.policy_names,
+            is_truncated: false,
+        };
+        let operation = format!("List{}Policies", holder_element(self.holder));
+        query_response(IAM_XML_NS, &operation, &result, request_id)
+    }
+}
+
+operation_output! {
+    struct ListInlinePoliciesResult<'a> {
+        "PolicyNames" => policy_names: &'a [String],
+        "IsTruncated" => is_truncated: bool,
+    }
+}
+
+/// Returns the policy document as JSON, percent-decoding it if it was URL-encoded.
+pub fn decode_policy_document(document: &str) -> String {
+    let trimmed = document.trim_start();
+    if trimmed.starts_with('{') {
+        return document.to_string();
+    }
+
+    match percent_decode_str(document).decode_utf8() {
+        Ok(decoded) if decoded.trim_start().starts_with('{') => decoded.into_owned(),
+        _ => document.to_string(),
+    }
+}
+
+/// URL-encode a policy document for a response, as IAM does.
+pub fn encode_policy_document(document: &str) -> String {
+    utf8_percent_encode(document, UNRESERVED).to_string()
+}
+
+/// Decode and validate a policy document, returning the JSON to store.
+pub fn validate_policy_document(document: &str) -> Result<String, InlinePolicyError> {
+    let document = decode_policy_document(document);
+    Policy::from_str(&document).map_err(|e| InlinePolicyError::MalformedPolicyDocument(e.to_string()))?;
+    Ok(document)
+}
+
+/// Look up the id of a user, group, or role by name.
+pub async fn resolve_holder(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    holder: PolicyHolder,
+    holder_name: &str,
+) -> Result<String, StoreError> {
+    match holder {
+        PolicyHolder::User => Ok(store.get_user(account_id, holder_name).await?.user_id),
+        PolicyHolder::Group => Ok(store.get_group(account_id, holder_name).await?.group_id),
+        PolicyHolder::Role => Ok(store.get_role(account_id, holder_name).await?.role_id),
+    }
+}
+
+/// Create or replace an inline policy on the named holder.
+pub async fn put_inline_policy(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    holder: PolicyHolder,
+    holder_name: &str,
+    policy_name: &str,
+    policy_document: &str,
+) -> Result<(), InlinePolicyError> {
+    let policy_document = validate_policy_document(policy_document)?;
+    let holder_id = resolve_holder(store, account_id, holder, holder_name).await?;
+    let policy = InlinePolicy {
+        policy_name: policy_name.to_string(),
+        policy_document,
+    };
+    Ok(store.put_inline_policy(holder, &holder_id, &policy).await?)
+}
+
+/// Fetch an inline policy from the named holder. The document is returned as stored; responses should encode it
+/// with [encode_policy_document].
+pub async fn get_inline_policy(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    holder: PolicyHolder,
+    holder_name: &str,
+    policy_name: &str,
+) -> Result<InlinePolicy, InlinePolicyError> {
+    let holder_id = resolve_holder(store, account_id, holder, holder_name).await?;
+    Ok(store.get_inline_policy(holder, &holder_id, policy_name).await?)
+}
+
+pub async fn delete_inline_policy(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    holder: PolicyHolder,
+    holder_name: &str,
+    policy_name: &str,
+) -> Result<(), InlinePolicyError> {
+    let holder_id = resolve_holder(store, account_id, holder, holder_name).await?;
+    Ok(store.delete_inline_policy(holder, &holder_id, policy_name).await?)
+}
+
+/// List the names of the named holder's inline policies.
+pub async fn list_inline_policies(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    holder: PolicyHolder,
+    holder_name: &str,
+) -> Result<Vec<String>, InlinePolicyError> {
+    let holder_id = resolve_holder(store, account_id, holder, holder_name).await?;
+    Ok(store.list_inline_policies(holder, &holder_id).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            decode_policy_document, encode_policy_document, get_inline_policy, list_inline_policies, put_inline_policy,
+        },
+        crate::{
+            effective::effective_policies,
+            store::{ControlPlaneStore, MemoryStore, PolicyHolder, User},
+        },
+        chrono::Utc,
+        pretty_assertions::assert_eq,
+    };
+
+    const DOCUMENT: &str =
+        r#"{"Version":"2012-10-17","Statement":[{"Effect":"Allow","Action":"s3:GetObject","Resource":"*"}]}"#;
+
+    #[test_log::test(tokio::test)]
+    async fn test_inline_policies() {
+        let store = MemoryStore::new();
+        store
+            .create_user(
+                &User {
+                    user_id: "AIDAEXAMPLEUSER1".to_string(),
+                    account_id: "123456789012".to_string(),
+                    user_name: "Alice".to_string(),
+                    path: "/".to_string(),
+                    permissions_boundary: None,
+                    created_at: Utc::now(),
+                },
+                &[],
+            )
+            .await
+            .unwrap();
+
+        let encoded = encode_policy_document(DOCUMENT);
+        assert!(encoded.starts_with("%7B%22Version%22"));
+        assert_eq!(decode_policy_document(&encoded), DOCUMENT);
+
+        put_inline_policy(&store, "123456789012", PolicyHolder::User, "alice", "ReadObjects", &encoded).await.unwrap();
+        let policy =
+            get_inline_policy(&store, "123456789012", PolicyHolder::User, "Alice", "readobjects").await.unwrap();
+        assert_eq!((policy.policy_name.as_str(), policy.policy_document.as_str()), ("ReadObjects", DOCUMENT));
+
+        let e = put_inline_policy(&store, "123456789012", PolicyHolder::User, "Alice", "Bad", "{\"Statement\": 1}")
+            .await
+            .unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("MalformedPolicyDocument", 400));
+
+        let e = list_inline_policies(&store, "123456789012", PolicyHolder::Role, "Alice").await.unwrap_err();
+        assert_eq!(e.code(), "NoSuchEntity");
+
+        assert_eq!(store.delete_user("123456789012", "Alice").await.unwrap_err().code(), "DeleteConflict");
+
+        let effective =
+            effective_policies(&store, "123456789012", PolicyHolder::User, "AIDAEXAMPLEUSER1").await.unwrap();
+        assert_eq!(effective.len(), 1);
+        assert_eq!(effective[0].source, "ReadObjects");
+    }
+}
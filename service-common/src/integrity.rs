@@ -0,0 +1,181 @@
+use {
+    crate::{
+        crypto::{hex, hmac_sha256, sha256},
+        gsk::SigningKeyCache,
+    },
+    hyper::{body::to_bytes, header::HeaderValue, service::Service, Body, Request, Response},
+    log::debug,
+    serde::Deserialize,
+    std::{
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+    },
+    tower::BoxError,
+};
+
+/// The header carrying the response signature.
+pub const RESPONSE_SIGNATURE_HEADER: &str = "X-Scratchstack-Response-Signature";
+
+/// The algorithm identifier at the start of the string to sign and the header value.
+pub const RESPONSE_SIGNATURE_ALGORITHM: &str = "SCRATCHSTACK-HMAC-SHA256";
+
+/// Settings for signing responses.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ResponseSigningConfig {
+    pub enabled: bool,
+}
+
+/// Computes the response signature: the hex-encoded HMAC-SHA256, keyed with the request's signing key, of
+///
+/// ```text
+/// SCRATCHSTACK-HMAC-SHA256\n<request signature>\n<hex SHA-256 of the response body>
+/// ```
+///
+/// Including the request signature binds the response to the request it answers.
+pub fn response_signature(signing_key: &[u8], request_signature: &str, body: &[u8]) -> String {
+    let string_to_sign = format!("{RESPONSE_SIGNATURE_ALGORITHM}\n{request_signature}\n{}", hex(&sha256(body)));
+    hex(&hmac_sha256(signing_key, string_to_sign.as_bytes()))
+}
+
+/// A service wrapper that adds an [X-Scratchstack-Response-Signature][RESPONSE_SIGNATURE_HEADER] header to each
+/// response so clients in test environments can detect responses altered by a proxy.
+///
+/// The signature uses the signing key of the request, which is found in the [SigningKeyCache] filled by
+/// [CaptureSigningKey][crate::gsk::CaptureSigningKey]. The header value has the form
+/// `SCRATCHSTACK-HMAC-SHA256 Credential=<credential>, Signature=<signature>`; see [response_signature]. If no cache
+/// is given, responses are passed through unchanged.
+#[derive(Clone, Debug)]
+pub struct ResponseSigning<S> {
+    inner: S,
+    cache: Option<SigningKeyCache>,
+}
+
+impl<S> ResponseSigning<S> {
+    pub fn new(inner: S, cache: Option<SigningKeyCache>) -> Self {
+        Self {
+            inner,
+            cache,
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for ResponseSigning<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = BoxError> + Send + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let signing = self.cache.as_ref().and_then(|cache| {
+            let (credential, signature) = request_credential(&req)?;
+            match cache.get(&credential) {
+                Some(key) => Some((credential, signature, key)),
+                None => {
+                    debug!("No signing key captured for credential {}; response will not be signed", credential);
+                    None
+                }
+            }
+        });
+
+        let future = self.inner.call(req);
+
+        Box::pin(async move {
+            let response = future.await?;
+            let (credential, request_signature, key) = match signing {
+                None => return Ok(response),
+                Some(signing) => signing,
+            };
+
+            let (mut parts, body) = response.into_parts();
+            let body = to_bytes(body).await?;
+            let signature = response_signature(&key, &request_signature, &body);
+            let value = format!("{RESPONSE_SIGNATURE_ALGORITHM} Credential={credential}, Signature={signature}");
+            parts.headers.insert(RESPONSE_SIGNATURE_HEADER, HeaderValue::try_from(value)?);
+            Ok(Response::from_parts(parts, Body::from(body)))
+        })
+    }
+}
+
+/// Returns the credential and signature of a SigV4 request, from either the `Authorization` header or the
+/// `X-Amz-Credential` and `X-Amz-Signature` query parameters.
+pub(crate) fn request_credential<B>(req: &Request<B>) -> Option<(String, String)> {
+    if let Some(authorization) = req.headers().get("Authorization").and_then(|v| v.to_str().ok()) {
+        let mut credential = None;
+        let mut signature = None;
+        let (_, parameters) = authorization.split_once(' ')?;
+        for parameter in parameters.split(',') {
+            match parameter.trim().split_once('=') {
+                Some(("Credential", value)) => credential = Some(value.to_string()),
+                Some(("Signature", value)) => signature = Some(value.to_string()),
+                _ => (),
+            }
+        }
+        return Some((credential?, signature?));
+    }
+
+    let query = req.uri().query()?;
+    let mut credential = None;
+    let mut signature = None;
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        match key.as_ref() {
+            "X-Amz-Credential" => credential = Some(value.into_owned()),
+            "X-Amz-Signature" => signature = Some(value.into_owned()),
+            _ => (),
+        }
+    }
+    Some((credential?, signature?))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{request_credential, response_signature},
+        hyper::Request,
+        pretty_assertions::assert_eq,
+    };
+
+    #[test_log::test]
+    fn test_request_credential() {
+        let req = Request::builder()
+            .header(
+                "Authorization",
+                "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request, \
+                 SignedHeaders=content-type;host;x-amz-date, Signature=5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7",
+            )
+            .body(())
+            .unwrap();
+        let (credential, signature) = request_credential(&req).unwrap();
+        assert_eq!(credential, "AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request");
+        assert_eq!(signature, "5d672d79c15b13162d9279b0855cfba6789a8edb4c82c400e06b5924a6f2b5d7");
+
+        let req = Request::builder()
+            .uri("/?X-Amz-Credential=AKIDEXAMPLE%2F20150830%2Fus-east-1%2Fiam%2Faws4_request&X-Amz-Signature=abcd")
+            .body(())
+            .unwrap();
+        assert_eq!(
+            request_credential(&req),
+            Some(("AKIDEXAMPLE/20150830/us-east-1/iam/aws4_request".to_string(), "abcd".to_string()))
+        );
+
+        assert_eq!(request_credential(&Request::builder().body(()).unwrap()), None);
+    }
+
+    #[test_log::test]
+    fn test_response_signature_binds_request_and_body() {
+        let key = [7u8; 32];
+        let signature = response_signature(&key, "abcd", b"<Response/>");
+        assert_eq!(signature.len(), 64);
+        assert_eq!(signature, response_signature(&key, "abcd", b"<Response/>"));
+        assert_ne!(signature, response_signature(&key, "abce", b"<Response/>"));
+        assert_ne!(signature, response_signature(&key, "abcd", b"<Response />"));
+    }
+}
@@ -0,0 +1,73 @@
+//! Support code shared by the Scratchstack service binaries.
+pub mod access_key;
+pub mod access_keys;
+pub mod access_log;
+pub mod account_aliases;
+pub mod actions;
+pub mod anonymous;
+pub mod api_docs;
+pub mod attached_policies;
+pub mod audit;
+pub mod authorizer;
+pub mod authz;
+pub mod backup;
+pub mod bootstrap;
+pub mod concurrency;
+pub mod config;
+pub mod consistency;
+pub mod context;
+pub mod crypto;
+pub mod deployment;
+pub mod deprecation;
+pub mod edge;
+pub mod effective;
+pub mod encoding;
+pub mod engine;
+pub mod flags;
+pub mod forward;
+pub mod gsk;
+pub mod health;
+pub mod histogram;
+pub mod ids;
+pub mod inline_policies;
+pub mod integrity;
+pub mod limit_catalog;
+pub mod limits;
+pub mod load_shed;
+pub mod lock;
+pub mod managed_policies;
+pub mod metrics;
+pub mod mirror;
+pub mod net;
+pub mod oidc;
+pub mod operation;
+pub mod outbound;
+pub mod parameters;
+pub mod payload_hash;
+pub mod policies;
+pub mod policy_metrics;
+pub mod protocol;
+pub mod region;
+pub mod request_id;
+pub mod request_time;
+pub mod response_cache;
+pub mod revocation;
+pub mod roles;
+pub mod route;
+pub mod schema;
+pub mod service_specific_credentials;
+pub mod session;
+pub mod session_keys;
+pub mod session_policy;
+pub mod signing;
+pub mod sigv2;
+pub mod simulation;
+pub mod ssh_public_keys;
+pub mod startup;
+pub mod store;
+pub mod tags;
+pub mod telemetry;
+pub mod tls;
+pub mod token;
+pub mod trust;
+pub mod users;
@@ -0,0 +1,392 @@
+//! Declarative seeding of `limitstore.limit_definition`.
+//!
+//! A limits catalog is a TOML file listing the quotas of a deployment:
+//!
+//! ```toml
+//! [[limit]]
+//! service = "iam"
+//! name = "AttachedPoliciesPerUser"
+//! description = "Managed policies that can be attached to one user"
+//! default = 10
+//! min = 0
+//! max = 20
+//!
+//! [[limit]]
+//! service = "iam"
+//! name = "PasswordPolicyName"
+//! type = "STRING"
+//! default = "standard"
+//! ```
+//!
+//! [seed_limits] inserts a definition for each limit that does not exist yet and updates the ones that do, so the
+//! same catalog can be applied at every startup. Definitions not in the catalog, and account overrides in
+//! `limitstore.account_limit`, are left alone.
+use {
+    log::{debug, info},
+    serde::Deserialize,
+    sqlx::{
+        any::{AnyKind, AnyPool},
+        Error as SqlxError, Row,
+    },
+    std::{
+        collections::HashSet,
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        fs::read_to_string,
+        io::Error as IOError,
+        path::Path,
+    },
+    toml::de::Error as TomlError,
+};
+
+/// The values of `limitstore.value_type`.
+#[derive(Clone, Copy, Debug, Default, Deserialize, Eq, PartialEq)]
+pub enum LimitValueType {
+    #[default]
+    #[serde(rename = "INTEGER")]
+    Integer,
+
+    #[serde(rename = "STRING")]
+    String,
+}
+
+impl LimitValueType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Integer => "INTEGER",
+            Self::String => "STRING",
+        }
+    }
+}
+
+/// The default value of a limit; an integer or a string, according to its [LimitValueType].
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(untagged)]
+pub enum LimitValue {
+    Integer(i32),
+    String(String),
+}
+
+/// One entry of a limits catalog.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CatalogLimit {
+    pub service: String,
+    pub name: String,
+
+    #[serde(default)]
+    pub description: Option<String>,
+
+    #[serde(default, rename = "type")]
+    pub value_type: LimitValueType,
+
+    #[serde(default)]
+    pub default: Option<LimitValue>,
+
+    /// The smallest value an account override may have; integer limits only.
+    #[serde(default)]
+    pub min: Option<i32>,
+
+    /// The largest value an account override may have; integer limits only.
+    #[serde(default)]
+    pub max: Option<i32>,
+}
+
+impl CatalogLimit {
+    fn validate(&self) -> Result<(), LimitCatalogError> {
+        let invalid =
+            |reason: &str| Err(LimitCatalogError::Invalid(format!("{}:{} {reason}", self.service, self.name)));
+
+        if self.service.is_empty() || self.service.len() > 64 || self.name.is_empty() || self.name.len() > 64 {
+            return invalid("must have a service and name of 1 to 64 characters");
+        }
+
+        match (self.value_type, &self.default) {
+            (LimitValueType::Integer, Some(LimitValue::String(_))) => return invalid("has a string default"),
+            (LimitValueType::String, Some(LimitValue::Integer(_))) => return invalid("has an integer default"),
+            (LimitValueType::String, Some(LimitValue::String(s))) if s.len() > 1024 => {
+                return invalid("has a default longer than 1024 characters")
+            }
+            (LimitValueType::String, _) if self.min.is_some() || self.max.is_some() => {
+                return invalid("is a string limit with a minimum or maximum")
+            }
+            _ => (),
+        }
+
+        if let (Some(min), Some(max)) = (self.min, self.max) {
+            if min > max {
+                return invalid("has a minimum greater than its maximum");
+            }
+        }
+
+        if let Some(LimitValue::Integer(default)) = self.default {
+            if self.min.map(|min| default < min).unwrap_or(false) || self.max.map(|max| default > max).unwrap_or(false)
+            {
+                return invalid("has a default outside its minimum and maximum");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn default_int_value(&self) -> Option<i32> {
+        match &self.default {
+            Some(LimitValue::Integer(value)) => Some(*value),
+            _ => None,
+        }
+    }
+
+    fn default_string_value(&self) -> Option<&str> {
+        match &self.default {
+            Some(LimitValue::String(value)) => Some(value.as_str()),
+            _ => None,
+        }
+    }
+}
+
+/// A parsed and validated limits catalog.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LimitCatalog {
+    #[serde(default, rename = "limit")]
+    pub limits: Vec<CatalogLimit>,
+}
+
+impl LimitCatalog {
+    pub fn read_file<P: AsRef<Path>>(filename: P) -> Result<Self, LimitCatalogError> {
+        let contents = read_to_string(filename)?;
+        Self::from_toml_str(&contents)
+    }
+
+    pub fn from_toml_str(contents: &str) -> Result<Self, LimitCatalogError> {
+        let catalog: Self = toml::from_str(contents)?;
+        catalog.validate()?;
+        Ok(catalog)
+    }
+
+    /// Check each limit, and that no limit is declared twice.
+    pub fn validate(&self) -> Result<(), LimitCatalogError> {
+        let mut seen = HashSet::new();
+        for limit in &self.limits {
+            limit.validate()?;
+            if !seen.insert((limit.service.as_str(), limit.name.as_str())) {
+                return Err(LimitCatalogError::Invalid(format!("{}:{} is declared twice", limit.service, limit.name)));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// What [seed_limits] changed.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct SeedSummary {
+    pub inserted: usize,
+    pub updated: usize,
+}
+
+/// Insert or update a `limitstore.limit_definition` row for each limit in `catalog`, in one transaction.
+pub async fn seed_limits(pool: &AnyPool, catalog: &LimitCatalog) -> Result<SeedSummary, LimitCatalogError> {
+    let next_limit_id = match pool.any_kind() {
+        AnyKind::Postgres => "nextval('limitstore.seq_limit_id')",
+        _ => "(SELECT COALESCE(MAX(limit_id), 0) + 1 FROM limitstore.limit_definition)",
+    };
+    let insert = format!(
+        "INSERT INTO limitstore.limit_definition(limit_id, service_name, limit_name, description, value_type, \
+         default_int_value, default_string_value, min_value, max_value) \
+         VALUES({next_limit_id}, $1, $2, $3, $4, $5, $6, $7, $8)"
+    );
+    let update = "UPDATE limitstore.limit_definition SET description = $1, value_type = $2, default_int_value = $3, \
+                  default_string_value = $4, min_value = $5, max_value = $6 WHERE limit_id = $7";
+
+    let mut summary = SeedSummary::default();
+    let mut tx = pool.begin().await?;
+    for limit in &catalog.limits {
+        let existing =
+            sqlx::query("SELECT limit_id FROM limitstore.limit_definition WHERE service_name = $1 AND limit_name = $2")
+                .bind(&limit.service)
+                .bind(&limit.name)
+                .fetch_optional(&mut tx)
+                .await?;
+
+        match existing {
+            Some(row) => {
+                let limit_id: i64 = row.try_get("limit_id")?;
+                sqlx::query(update)
+                    .bind(limit.description.as_deref())
+                    .bind(limit.value_type.as_str())
+                    .bind(limit.default_int_value())
+                    .bind(limit.default_string_value())
+                    .bind(limit.min)
+                    .bind(limit.max)
+                    .bind(limit_id)
+                    .execute(&mut tx)
+                    .await?;
+                debug!("Updated limit {}:{}", limit.service, limit.name);
+                summary.updated += 1;
+            }
+            None => {
+                sqlx::query(&insert)
+                    .bind(&limit.service)
+                    .bind(&limit.name)
+                    .bind(limit.description.as_deref())
+                    .bind(limit.value_type.as_str())
+                    .bind(limit.default_int_value())
+                    .bind(limit.default_string_value())
+                    .bind(limit.min)
+                    .bind(limit.max)
+                    .execute(&mut tx)
+                    .await?;
+                debug!("Inserted limit {}:{}", limit.service, limit.name);
+                summary.inserted += 1;
+            }
+        }
+    }
+    tx.commit().await?;
+
+    info!("Seeded limits catalog: {} inserted, {} updated", summary.inserted, summary.updated);
+    Ok(summary)
+}
+
+#[derive(Debug)]
+pub enum LimitCatalogError {
+    Invalid(String),
+    IO(IOError),
+    Parse(TomlError),
+    Sqlx(SqlxError),
+}
+
+impl Error for LimitCatalogError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::IO(e) => Some(e),
+            Self::Parse(e) => Some(e),
+            Self::Sqlx(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for LimitCatalogError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Invalid(msg) => write!(f, "Invalid limits catalog: {msg}"),
+            Self::IO(e) => write!(f, "IO error: {e}"),
+            Self::Parse(e) => write!(f, "Unable to parse limits catalog: {e}"),
+            Self::Sqlx(e) => write!(f, "Sqlx error: {e}"),
+        }
+    }
+}
+
+impl From<IOError> for LimitCatalogError {
+    fn from(e: IOError) -> Self {
+        Self::IO(e)
+    }
+}
+
+impl From<TomlError> for LimitCatalogError {
+    fn from(e: TomlError) -> Self {
+        Self::Parse(e)
+    }
+}
+
+impl From<SqlxError> for LimitCatalogError {
+    fn from(e: SqlxError) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{seed_limits, LimitCatalog, LimitCatalogError, SeedSummary},
+        crate::limits::{Limits, ATTACHED_POLICIES_PER_USER},
+        pretty_assertions::assert_eq,
+        sqlx::any::AnyPoolOptions,
+        std::sync::Arc,
+    };
+
+    const CATALOG: &str = r#"
+        [[limit]]
+        service = "iam"
+        name = "AttachedPoliciesPerUser"
+        description = "Managed policies that can be attached to one user"
+        default = 20
+        min = 0
+        max = 20
+
+        [[limit]]
+        service = "iam"
+        name = "PasswordPolicyName"
+        type = "STRING"
+        default = "standard"
+    "#;
+
+    #[test_log::test]
+    fn test_parse() {
+        let catalog = LimitCatalog::from_toml_str(CATALOG).unwrap();
+        assert_eq!(catalog.limits.len(), 2);
+        assert_eq!(catalog.limits[0].max, Some(20));
+
+        let invalid = |contents: &str| match LimitCatalog::from_toml_str(contents) {
+            Err(LimitCatalogError::Invalid(msg)) => msg,
+            other => panic!("expected Invalid, got {other:?}"),
+        };
+        assert_eq!(
+            invalid("[[limit]]\nservice = \"iam\"\nname = \"X\"\ndefault = 30\nmax = 20"),
+            "iam:X has a default outside its minimum and maximum"
+        );
+        assert_eq!(
+            invalid("[[limit]]\nservice = \"iam\"\nname = \"X\"\ndefault = \"a\""),
+            "iam:X has a string default"
+        );
+        assert_eq!(
+            invalid("[[limit]]\nservice = \"iam\"\nname = \"X\"\n[[limit]]\nservice = \"iam\"\nname = \"X\""),
+            "iam:X is declared twice"
+        );
+        assert!(matches!(
+            LimitCatalog::from_toml_str("[[limit]]\nservice = \"iam\""),
+            Err(LimitCatalogError::Parse(_))
+        ));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_seed() {
+        // A single connection, since each SQLite in-memory connection is a separate database.
+        let pool = AnyPoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+        for statement in [
+            "ATTACH DATABASE ':memory:' AS limitstore",
+            "CREATE TABLE limitstore.limit_definition(limit_id BIGINT NOT NULL PRIMARY KEY,
+             service_name VARCHAR(64) NOT NULL, limit_name VARCHAR(64) NOT NULL, description TEXT,
+             value_type VARCHAR(16) NOT NULL, default_int_value INTEGER, default_string_value VARCHAR(1024),
+             min_value INTEGER, max_value INTEGER, UNIQUE (service_name, limit_name))",
+            "CREATE TABLE limitstore.account_limit(account_id CHAR(12) NOT NULL, limit_id BIGINT NOT NULL,
+             region VARCHAR(64) NOT NULL, int_value INTEGER, string_value VARCHAR(1024))",
+        ] {
+            sqlx::query(statement).execute(&pool).await.unwrap();
+        }
+
+        let catalog = LimitCatalog::from_toml_str(CATALOG).unwrap();
+        let summary = seed_limits(&pool, &catalog).await.unwrap();
+        assert_eq!(
+            summary,
+            SeedSummary {
+                inserted: 2,
+                updated: 0,
+            }
+        );
+
+        // Seeding again updates the rows in place.
+        let summary = seed_limits(&pool, &catalog).await.unwrap();
+        assert_eq!(
+            summary,
+            SeedSummary {
+                inserted: 0,
+                updated: 2,
+            }
+        );
+
+        let limits = Limits::new(Arc::new(pool));
+        assert_eq!(limits.get(&ATTACHED_POLICIES_PER_USER, "123456789012").await.unwrap(), 20);
+    }
+}
@@ -0,0 +1,109 @@
+//! Service quotas from the `limitstore` schema.
+//!
+//! Each quota has a definition in `limitstore.limit_definition` with a default value, which an account can override
+//! with a row in `limitstore.account_limit`. When neither is present — or the database has no `limitstore` schema,
+//! as with SQLite — the default compiled in here applies.
+use {
+    log::debug,
+    sqlx::{any::AnyPool, Error as SqlxError, Row},
+    std::sync::Arc,
+};
+
+/// A quota and the value used when the database does not define it.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct LimitDefinition {
+    pub service_name: &'static str,
+    pub limit_name: &'static str,
+    pub default_value: i64,
+}
+
+/// Managed policies that can be attached to one user.
+pub const ATTACHED_POLICIES_PER_USER: LimitDefinition = LimitDefinition {
+    service_name: "iam",
+    limit_name: "AttachedPoliciesPerUser",
+    default_value: 10,
+};
+
+/// Managed policies that can be attached to one group.
+pub const ATTACHED_POLICIES_PER_GROUP: LimitDefinition = LimitDefinition {
+    service_name: "iam",
+    limit_name: "AttachedPoliciesPerGroup",
+    default_value: 10,
+};
+
+/// Managed policies that can be attached to one role.
+pub const ATTACHED_POLICIES_PER_ROLE: LimitDefinition = LimitDefinition {
+    service_name: "iam",
+    limit_name: "AttachedPoliciesPerRole",
+    default_value: 10,
+};
+
+/// Versions kept for one managed policy.
+pub const VERSIONS_PER_POLICY: LimitDefinition = LimitDefinition {
+    service_name: "iam",
+    limit_name: "VersionsPerPolicy",
+    default_value: 5,
+};
+
+/// Reads quotas for accounts.
+#[derive(Clone, Debug)]
+pub struct Limits {
+    pool: Option<Arc<AnyPool>>,
+}
+
+impl Limits {
+    pub fn new(pool: Arc<AnyPool>) -> Self {
+        Self {
+            pool: Some(pool),
+        }
+    }
+
+    /// Quotas that always have their default values. This is intended for tests.
+    pub fn defaults() -> Self {
+        Self {
+            pool: None,
+        }
+    }
+
+    /// The value of `limit` for `account_id`.
+    pub async fn get(&self, limit: &LimitDefinition, account_id: &str) -> Result<i64, SqlxError> {
+        let pool = match &self.pool {
+            None => return Ok(limit.default_value),
+            Some(pool) => pool,
+        };
+
+        let query = "SELECT ld.default_int_value AS default_value, al.int_value AS account_value \
+                     FROM limitstore.limit_definition ld \
+                     LEFT OUTER JOIN limitstore.account_limit al \
+                     ON al.limit_id = ld.limit_id AND al.account_id = $1 AND al.region = 'global' \
+                     WHERE ld.service_name = $2 AND ld.limit_name = $3";
+        let row = match sqlx::query(query)
+            .bind(account_id)
+            .bind(limit.service_name)
+            .bind(limit.limit_name)
+            .fetch_optional(pool.as_ref())
+            .await
+        {
+            Ok(row) => row,
+            Err(SqlxError::Database(e)) => {
+                debug!("Using the default for {}:{}: {}", limit.service_name, limit.limit_name, e);
+                return Ok(limit.default_value);
+            }
+            Err(e) => return Err(e),
+        };
+
+        let row = match row {
+            None => return Ok(limit.default_value),
+            Some(row) => row,
+        };
+
+        let account_value: Option<i32> = row.try_get("account_value")?;
+        let default_value: Option<i32> = row.try_get("default_value")?;
+        Ok(account_value.or(default_value).map(i64::from).unwrap_or(limit.default_value))
+    }
+
+    /// The value of `limit` for `account_id` as a count. Negative values are treated as zero.
+    pub async fn get_count(&self, limit: &LimitDefinition, account_id: &str) -> Result<usize, SqlxError> {
+        Ok(usize::try_from(self.get(limit, account_id).await?).unwrap_or(0))
+    }
+}
@@ -0,0 +1,502 @@
+//! Adaptive load shedding before signature verification.
+//!
+//! A [LoadMonitor] measures how saturated the process is in two ways: the scheduler delay, how much later than
+//! asked a task that sleeps for [LoadShedConfig::probe_interval_ms] is woken, which is how long ready tasks are
+//! waiting for a worker thread; and the number of requests in flight. The saturation is the larger of the two as a
+//! fraction of its limit, so 1.0 means one of them has reached its limit.
+//!
+//! [WithLoadShedding] wraps the make-service outside the signature verifier. While the saturation is below 1.0,
+//! requests are only counted. From 1.0, low priority requests, those without a signature and List operations, are
+//! answered with `Throttling`; from [LoadShedConfig::critical_saturation], every request is, except health checks.
+//! Shedding before verification keeps the work the verifier does, and so its latency, bounded by the requests that
+//! will actually be served. SDKs retry `Throttling` with backoff.
+//!
+//! Classifying a request posted as a form means reading its body, which is only done while the process is
+//! saturated and only for bodies up to [MAX_CLASSIFIED_BODY_BYTES].
+use {
+    crate::protocol::{AwsError, ErrorProtocol},
+    http::{
+        header::{AUTHORIZATION, CONTENT_LENGTH, CONTENT_TYPE},
+        request::Parts,
+        Method, StatusCode,
+    },
+    hyper::{body::to_bytes, service::Service, Body, Request, Response},
+    log::{debug, warn},
+    scratchstack_http_framework::RequestId,
+    serde::Deserialize,
+    std::{
+        fmt::Write,
+        future::Future,
+        pin::Pin,
+        sync::{
+            atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+            Arc,
+        },
+        task::{Context, Poll},
+        time::{Duration, Instant},
+    },
+    tokio::{task::JoinHandle, time::sleep},
+    tower::BoxError,
+};
+
+/// The largest form body read to find the action of a request while saturated.
+pub const MAX_CLASSIFIED_BODY_BYTES: usize = 64 << 10;
+
+/// Paths that are never shed, so orchestrators do not restart a process for being busy, unless changed with
+/// [WithLoadShedding::with_exempt_paths].
+const HEALTH_PATHS: &[&str] = &["/healthz", "/readyz"];
+
+/// Settings for load shedding, per service.
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+#[serde(default)]
+pub struct LoadShedConfig {
+    /// The scheduler delay, in milliseconds, at which the process is saturated.
+    pub max_scheduler_delay_ms: u64,
+
+    /// The number of requests in flight at which the process is saturated.
+    pub max_in_flight: usize,
+
+    /// The saturation from which every request is shed, not only low priority ones.
+    pub critical_saturation: f64,
+
+    /// How often the scheduler delay is measured, in milliseconds.
+    pub probe_interval_ms: u64,
+}
+
+impl Default for LoadShedConfig {
+    fn default() -> Self {
+        Self {
+            max_scheduler_delay_ms: 20,
+            max_in_flight: 512,
+            critical_saturation: 2.0,
+            probe_interval_ms: 50,
+        }
+    }
+}
+
+/// How readily a request is shed.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Priority {
+    /// Unsigned requests and List operations, shed as soon as the process is saturated.
+    Low,
+
+    /// Everything else, shed only at the critical saturation.
+    Normal,
+}
+
+impl Priority {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Low => "low",
+            Self::Normal => "normal",
+        }
+    }
+}
+
+/// The priority of a request from its headers and query string, and its form body if it has been read.
+pub fn request_priority(parts: &Parts, body: Option<&[u8]>) -> Priority {
+    let query = parts.uri.query().unwrap_or_default();
+    let parameters = || form_urlencoded::parse(query.as_bytes()).chain(form_urlencoded::parse(body.unwrap_or(&[])));
+
+    let signed = parts.headers.contains_key(AUTHORIZATION) || parameters().any(|(key, _)| key == "X-Amz-Signature");
+    let listing = parameters().any(|(key, value)| key == "Action" && value.starts_with("List"));
+    if !signed || listing {
+        Priority::Low
+    } else {
+        Priority::Normal
+    }
+}
+
+/// Whether the body of a request should be read to classify it: a form small enough to buffer.
+fn is_classifiable_form(parts: &Parts) -> bool {
+    let is_form = parts
+        .headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(|value| value.starts_with("application/x-www-form-urlencoded"))
+        .unwrap_or(false);
+    let length = parts
+        .headers
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.parse::<usize>().ok());
+
+    parts.method == Method::POST && is_form && matches!(length, Some(length) if length <= MAX_CLASSIFIED_BODY_BYTES)
+}
+
+/// Saturation measurements and shedding counters for one process.
+#[derive(Debug)]
+pub struct LoadMonitor {
+    config: LoadShedConfig,
+
+    /// The smoothed scheduler delay, in microseconds.
+    scheduler_delay_us: AtomicU64,
+    in_flight: AtomicUsize,
+    saturated: AtomicBool,
+    shed_low: AtomicU64,
+    shed_normal: AtomicU64,
+}
+
+impl LoadMonitor {
+    pub fn new(config: LoadShedConfig) -> Self {
+        Self {
+            config,
+            scheduler_delay_us: AtomicU64::new(0),
+            in_flight: AtomicUsize::new(0),
+            saturated: AtomicBool::new(false),
+            shed_low: AtomicU64::new(0),
+            shed_normal: AtomicU64::new(0),
+        }
+    }
+
+    pub fn config(&self) -> &LoadShedConfig {
+        &self.config
+    }
+
+    /// Measure the scheduler delay until the task is aborted.
+    pub fn spawn_probe(self: &Arc<Self>) -> JoinHandle<()> {
+        let monitor = self.clone();
+        tokio::spawn(async move {
+            let interval = Duration::from_millis(monitor.config.probe_interval_ms.max(1));
+            loop {
+                let start = Instant::now();
+                sleep(interval).await;
+                monitor.record_scheduler_delay(start.elapsed().saturating_sub(interval));
+            }
+        })
+    }
+
+    /// Fold one measurement into the scheduler delay. Each measurement counts for half, so a single slow wakeup
+    /// does not shed requests on its own but a sustained backlog is noticed within a few probes.
+    pub fn record_scheduler_delay(&self, delay: Duration) {
+        let sample = u64::try_from(delay.as_micros()).unwrap_or(u64::MAX);
+        let previous = self.scheduler_delay_us.load(Ordering::Relaxed);
+        self.scheduler_delay_us.store(previous / 2 + sample / 2, Ordering::Relaxed);
+    }
+
+    pub fn scheduler_delay(&self) -> Duration {
+        Duration::from_micros(self.scheduler_delay_us.load(Ordering::Relaxed))
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Relaxed)
+    }
+
+    /// The larger of the scheduler delay and the requests in flight as a fraction of their limits.
+    pub fn saturation(&self) -> f64 {
+        let delay = self.scheduler_delay_us.load(Ordering::Relaxed) as f64
+            / (self.config.max_scheduler_delay_ms.max(1) * 1000) as f64;
+        let in_flight = self.in_flight() as f64 / self.config.max_in_flight.max(1) as f64;
+        delay.max(in_flight)
+    }
+
+    /// Whether a request of `priority` should be shed at `saturation`.
+    pub fn should_shed(&self, priority: Priority, saturation: f64) -> bool {
+        match priority {
+            Priority::Low => saturation >= 1.0,
+            Priority::Normal => saturation >= self.config.critical_saturation,
+        }
+    }
+
+    /// The number of requests of `priority` shed so far.
+    pub fn shed_count(&self, priority: Priority) -> u64 {
+        match priority {
+            Priority::Low => self.shed_low.load(Ordering::Relaxed),
+            Priority::Normal => self.shed_normal.load(Ordering::Relaxed),
+        }
+    }
+
+    fn record_shed(&self, priority: Priority) {
+        match priority {
+            Priority::Low => self.shed_low.fetch_add(1, Ordering::Relaxed),
+            Priority::Normal => self.shed_normal.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    /// Log when the process becomes saturated or recovers, once per change.
+    fn note_saturation(&self, saturation: f64) {
+        let saturated = saturation >= 1.0;
+        if self.saturated.swap(saturated, Ordering::Relaxed) != saturated {
+            if saturated {
+                warn!(
+                    "Shedding load: saturation {:.2} (scheduler delay {:?}, {} requests in flight)",
+                    saturation,
+                    self.scheduler_delay(),
+                    self.in_flight()
+                );
+            } else {
+                warn!("No longer shedding load: saturation {:.2}", saturation);
+            }
+        }
+    }
+
+    /// Count a request in flight until the returned guard is dropped.
+    fn start_request(self: &Arc<Self>) -> InFlight {
+        self.in_flight.fetch_add(1, Ordering::Relaxed);
+        InFlight(self.clone())
+    }
+
+    /// Render the measurements and counters in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        let _ = writeln!(out, "# HELP scratchstack_load_shed_total Requests shed because the process was saturated.");
+        let _ = writeln!(out, "# TYPE scratchstack_load_shed_total counter");
+        for priority in [Priority::Low, Priority::Normal] {
+            let _ = writeln!(
+                out,
+                "scratchstack_load_shed_total{{priority=\"{}\"}} {}",
+                priority.as_str(),
+                self.shed_count(priority)
+            );
+        }
+
+        let _ = writeln!(out, "# HELP scratchstack_load_saturation Saturation of the process; 1 is fully saturated.");
+        let _ = writeln!(out, "# TYPE scratchstack_load_saturation gauge");
+        let _ = writeln!(out, "scratchstack_load_saturation {}", self.saturation());
+        let _ = writeln!(out, "# HELP scratchstack_scheduler_delay_seconds Smoothed tokio scheduler delay.");
+        let _ = writeln!(out, "# TYPE scratchstack_scheduler_delay_seconds gauge");
+        let _ = writeln!(out, "scratchstack_scheduler_delay_seconds {}", self.scheduler_delay().as_secs_f64());
+        let _ = writeln!(out, "# HELP scratchstack_requests_in_flight Requests being processed.");
+        let _ = writeln!(out, "# TYPE scratchstack_requests_in_flight gauge");
+        let _ = writeln!(out, "scratchstack_requests_in_flight {}", self.in_flight());
+        out
+    }
+}
+
+/// A request counted by [LoadMonitor::in_flight].
+struct InFlight(Arc<LoadMonitor>);
+
+impl Drop for InFlight {
+    fn drop(&mut self) {
+        self.0.in_flight.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// Wraps a make-service (such as `SpawnService`) so each per-connection service sheds load before the signature is
+/// verified. Without a monitor, requests are passed through unchanged.
+#[derive(Clone, Debug)]
+pub struct WithLoadShedding<M> {
+    inner: M,
+    monitor: Option<Arc<LoadMonitor>>,
+    protocol: ErrorProtocol,
+    exempt_paths: Arc<Vec<String>>,
+}
+
+impl<M> WithLoadShedding<M> {
+    /// Wrap `inner`. Throttling errors are rendered in the shape `protocol` uses.
+    pub fn new(inner: M, monitor: Option<Arc<LoadMonitor>>, protocol: ErrorProtocol) -> Self {
+        Self {
+            inner,
+            monitor,
+            protocol,
+            exempt_paths: Arc::new(HEALTH_PATHS.iter().map(ToString::to_string).collect()),
+        }
+    }
+
+    /// Never shed requests for `paths`, such as the health check paths in use.
+    pub fn with_exempt_paths(mut self, paths: Vec<String>) -> Self {
+        self.exempt_paths = Arc::new(paths);
+        self
+    }
+}
+
+impl<T, M> Service<T> for WithLoadShedding<M>
+where
+    M: Service<T>,
+    M::Future: Send + 'static,
+{
+    type Response = LoadShedding<M::Response>;
+    type Error = M::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let monitor = self.monitor.clone();
+        let protocol = self.protocol;
+        let exempt_paths = self.exempt_paths.clone();
+        let future = self.inner.call(target);
+        Box::pin(async move {
+            Ok(LoadShedding {
+                inner: future.await?,
+                monitor,
+                protocol,
+                exempt_paths,
+            })
+        })
+    }
+}
+
+/// A per-connection service that counts requests in flight and sheds them while the process is saturated.
+#[derive(Clone, Debug)]
+pub struct LoadShedding<S> {
+    inner: S,
+    monitor: Option<Arc<LoadMonitor>>,
+    protocol: ErrorProtocol,
+    exempt_paths: Arc<Vec<String>>,
+}
+
+impl<S> Service<Request<Body>> for LoadShedding<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let monitor = match &self.monitor {
+            Some(monitor) if !self.exempt_paths.iter().any(|path| path == req.uri().path()) => monitor.clone(),
+            _ => {
+                let future = self.inner.call(req);
+                return Box::pin(async move { future.await.map_err(Into::into) });
+            }
+        };
+
+        let saturation = monitor.saturation();
+        monitor.note_saturation(saturation);
+        if saturation < 1.0 {
+            let in_flight = monitor.start_request();
+            let future = self.inner.call(req);
+            return Box::pin(async move {
+                let response = future.await.map_err(Into::into);
+                drop(in_flight);
+                response
+            });
+        }
+
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let protocol = self.protocol;
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let (priority, body) = if is_classifiable_form(&parts) {
+                let bytes = to_bytes(body).await?;
+                (request_priority(&parts, Some(&bytes)), Body::from(bytes))
+            } else {
+                (request_priority(&parts, None), body)
+            };
+
+            if monitor.should_shed(priority, saturation) {
+                debug!("Shedding {} priority request at saturation {:.2}", priority.as_str(), saturation);
+                monitor.record_shed(priority);
+                let request_id = parts.extensions.get::<RequestId>().copied().unwrap_or_else(RequestId::new);
+                let error = AwsError::sender(StatusCode::BAD_REQUEST, "Throttling", "Rate exceeded");
+                return protocol.response(&error, request_id);
+            }
+
+            let in_flight = monitor.start_request();
+            let response = inner.call(Request::from_parts(parts, body)).await.map_err(Into::into);
+            drop(in_flight);
+            response
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{request_priority, LoadMonitor, LoadShedConfig, LoadShedding, Priority},
+        crate::protocol::STS,
+        hyper::{
+            body::to_bytes,
+            service::{service_fn, Service},
+            Body, Request, Response,
+        },
+        pretty_assertions::assert_eq,
+        std::{convert::Infallible, sync::Arc, time::Duration},
+    };
+
+    fn signed(uri: &str) -> Request<Body> {
+        Request::post(uri)
+            .header("Authorization", "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/...")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Content-Length", "39")
+            .body(Body::from("Action=ListRoles&Version=2010-05-08&x=1"))
+            .unwrap()
+    }
+
+    #[test_log::test]
+    fn test_request_priority() {
+        let priority = |req: Request<()>, body: Option<&[u8]>| request_priority(&req.into_parts().0, body);
+        let get = |uri| Request::get(uri).header("Authorization", "AWS4-HMAC-SHA256 ...").body(()).unwrap();
+
+        assert_eq!(priority(get("/?Action=GetCallerIdentity"), None), Priority::Normal);
+        assert_eq!(priority(get("/?Action=ListUsers"), None), Priority::Low);
+        assert_eq!(priority(get("/"), Some(&b"Action=ListAccessKeys&Version=2010-05-08"[..])), Priority::Low);
+        assert_eq!(priority(Request::get("/version").body(()).unwrap(), None), Priority::Low);
+        assert_eq!(
+            priority(Request::get("/?Action=GetUser&X-Amz-Signature=abcd").body(()).unwrap(), None),
+            Priority::Normal
+        );
+    }
+
+    #[test_log::test]
+    fn test_saturation() {
+        let monitor = LoadMonitor::new(LoadShedConfig {
+            max_scheduler_delay_ms: 10,
+            ..LoadShedConfig::default()
+        });
+        assert_eq!(monitor.saturation(), 0.0);
+
+        // Measurements are smoothed, so one slow wakeup does not saturate the process.
+        monitor.record_scheduler_delay(Duration::from_millis(10));
+        assert_eq!(monitor.scheduler_delay(), Duration::from_millis(5));
+        assert!(!monitor.should_shed(Priority::Low, monitor.saturation()));
+        monitor.record_scheduler_delay(Duration::from_millis(40));
+        assert_eq!(monitor.saturation(), 2.25);
+        assert!(monitor.should_shed(Priority::Low, monitor.saturation()));
+        assert!(monitor.should_shed(Priority::Normal, monitor.saturation()));
+        assert!(!monitor.should_shed(Priority::Normal, 1.5));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_load_shedding() {
+        let monitor = Arc::new(LoadMonitor::new(LoadShedConfig {
+            max_scheduler_delay_ms: 10,
+            ..LoadShedConfig::default()
+        }));
+        let mut service = LoadShedding {
+            inner: service_fn(|req: Request<Body>| async move {
+                let body = to_bytes(req.into_body()).await.unwrap();
+                Ok::<_, Infallible>(Response::new(Body::from(body)))
+            }),
+            monitor: Some(monitor.clone()),
+            protocol: STS,
+            exempt_paths: Arc::new(vec!["/healthz".to_string()]),
+        };
+
+        let response = service.call(signed("/")).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert_eq!(monitor.in_flight(), 0);
+
+        // Saturated: List operations are shed, but other requests and health checks still pass, bodies intact.
+        monitor.record_scheduler_delay(Duration::from_millis(30));
+        let response = service.call(signed("/")).await.unwrap();
+        assert_eq!(response.status().as_u16(), 400);
+        let body = to_bytes(response.into_body()).await.unwrap();
+        assert!(std::str::from_utf8(&body).unwrap().contains("<Code>Throttling</Code>"));
+        assert_eq!(monitor.shed_count(Priority::Low), 1);
+
+        let req = Request::post("/")
+            .header("Authorization", "AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/...")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .header("Content-Length", "24")
+            .body(Body::from("Action=GetCallerIdentity"))
+            .unwrap();
+        let response = service.call(req).await.unwrap();
+        assert_eq!(to_bytes(response.into_body()).await.unwrap(), "Action=GetCallerIdentity");
+
+        let response = service.call(Request::get("/healthz").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status().as_u16(), 200);
+        assert!(monitor.render().contains("scratchstack_load_shed_total{priority=\"low\"} 1\n"));
+    }
+}
@@ -0,0 +1,259 @@
+//! Leases for coordinating replicas.
+//!
+//! When several replicas share a database, work that must happen once — a scheduled backup, a migration, seeding
+//! an empty database — is guarded by a named lease in the `lease` table. A lease is held by one replica until it
+//! expires; the holder renews it while working and releases it when done. If the holder dies, the lease expires and
+//! another replica can take it over. Leases work the same way on PostgreSQL and SQLite.
+use {
+    crate::store::is_unique_violation,
+    chrono::{DateTime, Duration as ChronoDuration, Utc},
+    log::debug,
+    ring::rand::{SecureRandom, SystemRandom},
+    sqlx::{
+        any::{AnyKind, AnyPool},
+        Error as SqlxError,
+    },
+    std::{
+        env,
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        process,
+        sync::Arc,
+        time::Duration,
+    },
+};
+
+/// The format used to pass timestamps to the database, as in [SqlStore][crate::store::SqlStore].
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f";
+
+/// Acquires named leases on behalf of one replica.
+#[derive(Clone, Debug)]
+pub struct Leases {
+    pool: Arc<AnyPool>,
+    holder: String,
+    prefix: &'static str,
+    cast_timestamps: bool,
+}
+
+/// A lease held by this replica. Dropping it does not release it; the lease expires on its own unless
+/// [Lease::release] is called.
+#[derive(Debug)]
+pub struct Lease {
+    leases: Leases,
+    name: String,
+    expires_at: DateTime<Utc>,
+}
+
+impl Leases {
+    /// Create a lease manager with a new [replica_id] as the holder.
+    pub fn new(pool: Arc<AnyPool>) -> Self {
+        Self::with_holder(pool, replica_id())
+    }
+
+    /// Create a lease manager with the given holder id.
+    pub fn with_holder(pool: Arc<AnyPool>, holder: String) -> Self {
+        let (prefix, cast_timestamps) = match pool.any_kind() {
+            AnyKind::Postgres => ("iam.", true),
+            _ => ("", false),
+        };
+
+        Self {
+            pool,
+            holder,
+            prefix,
+            cast_timestamps,
+        }
+    }
+
+    pub fn holder(&self) -> &str {
+        &self.holder
+    }
+
+    fn timestamp_param(&self, n: usize) -> String {
+        if self.cast_timestamps {
+            format!("CAST(${n} AS TIMESTAMP)")
+        } else {
+            format!("${n}")
+        }
+    }
+
+    /// Take the lease `name` for `ttl` if it is free, expired, or already held by this replica.
+    pub async fn try_acquire(&self, name: &str, ttl: Duration) -> Result<Option<Lease>, LockError> {
+        let now = Utc::now();
+        let expires_at = now + ChronoDuration::from_std(ttl).map_err(|_| LockError::InvalidTtl)?;
+
+        // Take over an existing row first. The condition is rechecked under the row lock, so only one replica can
+        // take over an expired lease.
+        let update = format!(
+            "UPDATE {}lease SET holder = $1, acquired_at = {}, expires_at = {} WHERE lease_name = $2 AND (holder = $1 OR expires_at < {})",
+            self.prefix,
+            self.timestamp_param(3),
+            self.timestamp_param(4),
+            self.timestamp_param(3),
+        );
+        let result = sqlx::query(&update)
+            .bind(&self.holder)
+            .bind(name)
+            .bind(now.format(TIMESTAMP_FORMAT).to_string())
+            .bind(expires_at.format(TIMESTAMP_FORMAT).to_string())
+            .execute(self.pool.as_ref())
+            .await?;
+
+        if result.rows_affected() == 0 {
+            let insert = format!(
+                "INSERT INTO {}lease(lease_name, holder, acquired_at, expires_at) VALUES($1, $2, {}, {})",
+                self.prefix,
+                self.timestamp_param(3),
+                self.timestamp_param(4),
+            );
+            let result = sqlx::query(&insert)
+                .bind(name)
+                .bind(&self.holder)
+                .bind(now.format(TIMESTAMP_FORMAT).to_string())
+                .bind(expires_at.format(TIMESTAMP_FORMAT).to_string())
+                .execute(self.pool.as_ref())
+                .await;
+
+            match result {
+                Ok(_) => (),
+                Err(e) if is_unique_violation(&e) => {
+                    debug!("Lease {} is held by another replica", name);
+                    return Ok(None);
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        debug!("Acquired lease {} as {} until {}", name, self.holder, expires_at);
+        Ok(Some(Lease {
+            leases: self.clone(),
+            name: name.to_string(),
+            expires_at,
+        }))
+    }
+
+    /// Wait until the lease `name` can be taken, checking every `retry`.
+    pub async fn acquire(&self, name: &str, ttl: Duration, retry: Duration) -> Result<Lease, LockError> {
+        loop {
+            if let Some(lease) = self.try_acquire(name, ttl).await? {
+                return Ok(lease);
+            }
+            tokio::time::sleep(retry).await;
+        }
+    }
+}
+
+impl Lease {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+    }
+
+    /// Extend the lease to `ttl` from now. This fails with [LockError::Lost] if another replica has taken it over.
+    pub async fn renew(&mut self, ttl: Duration) -> Result<(), LockError> {
+        match self.leases.try_acquire(&self.name, ttl).await? {
+            Some(lease) => {
+                self.expires_at = lease.expires_at;
+                Ok(())
+            }
+            None => Err(LockError::Lost(self.name.clone())),
+        }
+    }
+
+    /// Release the lease so another replica can take it immediately.
+    pub async fn release(self) -> Result<(), LockError> {
+        let delete = format!("DELETE FROM {}lease WHERE lease_name = $1 AND holder = $2", self.leases.prefix);
+        sqlx::query(&delete).bind(&self.name).bind(&self.leases.holder).execute(self.leases.pool.as_ref()).await?;
+        debug!("Released lease {}", self.name);
+        Ok(())
+    }
+}
+
+/// A new id for this process, made from the hostname, process id, and a random suffix.
+pub fn replica_id() -> String {
+    let mut suffix = [0u8; 4];
+    // An all-zero suffix still leaves the hostname and process id to tell replicas apart.
+    let _ = SystemRandom::new().fill(&mut suffix);
+    let host = env::var("HOSTNAME").unwrap_or_else(|_| "localhost".to_string());
+    let suffix: String = suffix.iter().map(|b| format!("{b:02x}")).collect();
+    format!("{host}:{}:{suffix}", process::id())
+}
+
+#[derive(Debug)]
+pub enum LockError {
+    /// The lease duration is too large to represent.
+    InvalidTtl,
+
+    /// The lease expired and was taken by another replica.
+    Lost(String),
+    Sqlx(SqlxError),
+}
+
+impl Error for LockError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Sqlx(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for LockError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::InvalidTtl => f.write_str("Lease duration is out of range"),
+            Self::Lost(name) => write!(f, "Lease {name} was taken over by another replica"),
+            Self::Sqlx(e) => write!(f, "Sqlx error: {e}"),
+        }
+    }
+}
+
+impl From<SqlxError> for LockError {
+    fn from(e: SqlxError) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{Leases, LockError},
+        pretty_assertions::assert_eq,
+        sqlx::any::AnyPoolOptions,
+        std::{sync::Arc, time::Duration},
+    };
+
+    #[test_log::test(tokio::test)]
+    async fn test_leases() {
+        // A single connection, since each SQLite in-memory connection is a separate database.
+        let pool = AnyPoolOptions::new().max_connections(1).connect("sqlite::memory:").await.unwrap();
+        sqlx::query(
+            "CREATE TABLE lease(lease_name VARCHAR(256) NOT NULL PRIMARY KEY, holder VARCHAR(256) NOT NULL,
+             acquired_at TIMESTAMP(6) NOT NULL, expires_at TIMESTAMP(6) NOT NULL)",
+        )
+        .execute(&pool)
+        .await
+        .unwrap();
+        let pool = Arc::new(pool);
+
+        let first = Leases::with_holder(pool.clone(), "first".to_string());
+        let second = Leases::with_holder(pool, "second".to_string());
+
+        let mut lease = first.try_acquire("backup:iam", Duration::from_secs(60)).await.unwrap().unwrap();
+        assert!(second.try_acquire("backup:iam", Duration::from_secs(60)).await.unwrap().is_none());
+        lease.renew(Duration::from_secs(60)).await.unwrap();
+
+        // Once released, the other replica can take it.
+        lease.release().await.unwrap();
+        let mut lease = second.try_acquire("backup:iam", Duration::from_millis(1)).await.unwrap().unwrap();
+        assert_eq!(lease.name(), "backup:iam");
+
+        // An expired lease can be taken over, after which the old holder cannot renew it.
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        let _taken = first.try_acquire("backup:iam", Duration::from_secs(60)).await.unwrap().unwrap();
+        assert!(matches!(lease.renew(Duration::from_secs(60)).await, Err(LockError::Lost(_))));
+    }
+}
@@ -0,0 +1,271 @@
+//! Customer managed policy operations: `CreatePolicyVersion` and `DeletePolicy`.
+//!
+//! Policies are named by ARN, as for [attachment][crate::attached_policies]; only policies in the caller's account
+//! can be changed. New versions are validated with Aspen, and the number of versions is limited by the account's
+//! `VersionsPerPolicy` quota; see [crate::policies]. As in AWS, a policy cannot be deleted while it is attached.
+use {
+    crate::{
+        attached_policies::parse_policy_arn,
+        inline_policies::{validate_policy_document, InlinePolicyError},
+        limits::Limits,
+        operation::{output::query_response, ValidationError},
+        operation_input, operation_output, policies,
+        protocol::IAM_XML_NS,
+        store::{ControlPlaneStore, ManagedPolicy, StoreError},
+    },
+    chrono::{DateTime, Utc},
+    http::StatusCode,
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+operation_input! {
+    /// Input for the CreatePolicyVersion operation.
+    pub struct CreatePolicyVersionInput {
+        "PolicyArn" => pub policy_arn: String where length(20, 2048),
+        "PolicyDocument" => pub policy_document: String where length(1, 131072),
+        "SetAsDefault" => pub set_as_default: Option<bool>,
+    }
+}
+
+operation_input! {
+    /// Input for the DeletePolicy operation.
+    pub struct DeletePolicyInput {
+        "PolicyArn" => pub policy_arn: String where length(20, 2048),
+    }
+}
+
+/// Errors from managed policy operations.
+#[derive(Debug)]
+pub enum ManagedPolicyError {
+    Validation(ValidationError),
+
+    /// The policy ARN could not be parsed, or does not name an IAM policy.
+    InvalidPolicyArn(String),
+
+    /// The policy does not exist in the caller's account.
+    NoSuchPolicy(String),
+
+    /// The policy document is not a valid policy.
+    MalformedPolicyDocument(String),
+    Store(StoreError),
+}
+
+impl ManagedPolicyError {
+    /// The IAM error code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Validation(e) => e.code(),
+            Self::InvalidPolicyArn(_) => "InvalidInput",
+            Self::NoSuchPolicy(_) => "NoSuchEntity",
+            Self::MalformedPolicyDocument(_) => "MalformedPolicyDocument",
+            Self::Store(e) => e.code(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Validation(_) | Self::InvalidPolicyArn(_) | Self::MalformedPolicyDocument(_) => {
+                StatusCode::BAD_REQUEST
+            }
+            Self::NoSuchPolicy(_) => StatusCode::NOT_FOUND,
+            Self::Store(e) => e.status(),
+        }
+    }
+}
+
+impl Error for ManagedPolicyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Validation(e) => Some(e),
+            Self::Store(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for ManagedPolicyError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Validation(e) => write!(f, "{e}"),
+            Self::InvalidPolicyArn(arn) => write!(f, "ARN {arn} is not valid."),
+            Self::NoSuchPolicy(arn) => write!(f, "Policy {arn} was not found."),
+            Self::MalformedPolicyDocument(message) => write!(f, "Syntax errors in policy: {message}"),
+            Self::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<ValidationError> for ManagedPolicyError {
+    fn from(e: ValidationError) -> Self {
+        Self::Validation(e)
+    }
+}
+
+impl From<StoreError> for ManagedPolicyError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+impl From<InlinePolicyError> for ManagedPolicyError {
+    fn from(e: InlinePolicyError) -> Self {
+        match e {
+            InlinePolicyError::Validation(e) => Self::Validation(e),
+            InlinePolicyError::MalformedPolicyDocument(message) => Self::MalformedPolicyDocument(message),
+            InlinePolicyError::Store(e) => Self::Store(e),
+        }
+    }
+}
+
+/// A newly created policy version, as returned by CreatePolicyVersion.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CreatePolicyVersionOutput {
+    pub version: i64,
+    pub is_default_version: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl CreatePolicyVersionOutput {
+    /// The `CreatePolicyVersionResponse` body. Versions are named `v1`, `v2`, and so on, as in AWS.
+    pub fn to_xml(&self, request_id: &str) -> String {
+        let result = CreatePolicyVersionResult {
+            policy_version: PolicyVersionElement {
+                version_id: format!("v{}", self.version),
+                is_default_version: self.is_default_version,
+                create_date: self.created_at,
+            },
+        };
+        query_response(IAM_XML_NS, "CreatePolicyVersion", &result, request_id)
+    }
+}
+
+operation_output! {
+    struct PolicyVersionElement {
+        "VersionId" => version_id: String,
+        "IsDefaultVersion" => is_default_version: bool,
+        "CreateDate" => create_date: DateTime<Utc>,
+    }
+}
+
+operation_output! {
+    struct CreatePolicyVersionResult {
+        "PolicyVersion" => policy_version: PolicyVersionElement,
+    }
+}
+
+/// Look up the customer managed policy in `account_id` named by `policy_arn`.
+pub async fn customer_policy(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    policy_arn: &str,
+) -> Result<ManagedPolicy, ManagedPolicyError> {
+    let (policy_account_id, path, policy_name) =
+        parse_policy_arn(policy_arn).ok_or_else(|| ManagedPolicyError::InvalidPolicyArn(policy_arn.to_string()))?;
+    if policy_account_id != account_id {
+        return Err(ManagedPolicyError::NoSuchPolicy(policy_arn.to_string()));
+    }
+
+    match store.get_policy(account_id, &policy_name).await {
+        Ok(policy) if policy.path == path => Ok(policy),
+        Ok(_)
+        | Err(StoreError::NoSuchEntity {
+            ..
+        }) => Err(ManagedPolicyError::NoSuchPolicy(policy_arn.to_string())),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Add a version to the managed policy named by `policy_arn`, making it the default if `set_as_default` is true.
+pub async fn create_policy_version(
+    store: &dyn ControlPlaneStore,
+    limits: &Limits,
+    account_id: &str,
+    policy_arn: &str,
+    policy_document: &str,
+    set_as_default: bool,
+) -> Result<CreatePolicyVersionOutput, ManagedPolicyError> {
+    let policy_document = validate_policy_document(policy_document)?;
+    let policy = customer_policy(store, account_id, policy_arn).await?;
+    let version = policies::create_policy_version(
+        store,
+        limits,
+        account_id,
+        &policy.managed_policy_id,
+        &policy_document,
+        set_as_default,
+    )
+    .await?;
+
+    Ok(CreatePolicyVersionOutput {
+        version,
+        is_default_version: set_as_default,
+        created_at: Utc::now(),
+    })
+}
+
+/// Delete the managed policy named by `policy_arn`. This fails with `DeleteConflict` while the policy is attached.
+pub async fn delete_policy(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    policy_arn: &str,
+) -> Result<(), ManagedPolicyError> {
+    let policy = customer_policy(store, account_id, policy_arn).await?;
+    Ok(policies::delete_policy(store, account_id, &policy.policy_name, false).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{create_policy_version, delete_policy},
+        crate::{
+            limits::Limits,
+            store::{ControlPlaneStore, ManagedPolicy, MemoryStore},
+        },
+        chrono::Utc,
+        pretty_assertions::assert_eq,
+    };
+
+    const DOCUMENT: &str =
+        r#"{"Version":"2012-10-17","Statement":[{"Effect":"Allow","Action":"s3:GetObject","Resource":"*"}]}"#;
+
+    #[test_log::test(tokio::test)]
+    async fn test_versions_and_delete() {
+        let store = MemoryStore::new();
+        let limits = Limits::defaults();
+        let policy = ManagedPolicy {
+            managed_policy_id: "ANPAEXAMPLEPOLICY1".to_string(),
+            account_id: "123456789012".to_string(),
+            policy_name: "Deploy".to_string(),
+            path: "/ci/".to_string(),
+            default_version: None,
+            deprecated: false,
+            policy_type: None,
+            created_at: Utc::now(),
+        };
+        store.create_policy(&policy, DOCUMENT).await.unwrap();
+        let arn = "arn:aws:iam::123456789012:policy/ci/Deploy";
+
+        let output = create_policy_version(&store, &limits, "123456789012", arn, DOCUMENT, true).await.unwrap();
+        assert_eq!((output.version, output.is_default_version), (2, true));
+        let xml = output.to_xml("01234567-89ab-cdef-0123-456789abcdef");
+        assert!(xml.contains("<VersionId>v2</VersionId><IsDefaultVersion>true</IsDefaultVersion>"), "{xml}");
+
+        let e =
+            create_policy_version(&store, &limits, "123456789012", arn, "{\"Statement\": 1}", false).await.unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("MalformedPolicyDocument", 400));
+
+        // The path is part of the ARN, and policies in other accounts cannot be changed.
+        for other in ["arn:aws:iam::123456789012:policy/Deploy", "arn:aws:iam::210987654321:policy/ci/Deploy"] {
+            let e = delete_policy(&store, "123456789012", other).await.unwrap_err();
+            assert_eq!(e.to_string(), format!("Policy {other} was not found."));
+        }
+        let e = delete_policy(&store, "123456789012", "arn:aws:s3:::bucket").await.unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("InvalidInput", 400));
+
+        delete_policy(&store, "123456789012", arn).await.unwrap();
+        assert_eq!(store.get_policy("123456789012", "Deploy").await.unwrap_err().code(), "NoSuchEntity");
+    }
+}
@@ -0,0 +1,389 @@
+//! Metrics for signature verification.
+//!
+//! Verification happens inside the HTTP framework, so it is measured from the outside: [WithVerificationMetrics]
+//! starts a timer when a request arrives and [MarkVerified], which wraps the service implementation, stops it when a
+//! verified request reaches the implementation. Requests that never reach the implementation were rejected; the
+//! reason is taken from the error code in the response.
+use {
+    crate::{
+        access_key::{AccessKeyKind, AccessKeyPrefixes},
+        histogram::Histogram,
+        integrity::request_credential,
+    },
+    hyper::{body::to_bytes, service::Service, Body, Request, Response},
+    log::debug,
+    std::{
+        collections::BTreeMap,
+        fmt::{Display, Formatter, Result as FmtResult, Write},
+        future::Future,
+        pin::Pin,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc, Mutex,
+        },
+        task::{Context, Poll},
+        time::{Duration, Instant},
+    },
+    tower::BoxError,
+};
+
+/// Upper bounds of the verification time histogram buckets, in seconds.
+pub const VERIFICATION_BUCKETS: &[f64] = &[0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0];
+
+/// The kind of access key a request was signed with, by prefix. Labels use the canonical prefixes whatever prefixes
+/// the deployment issues.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum AccessKeyClass {
+    /// A long-term key (`AKIA`).
+    LongTerm,
+
+    /// A temporary key issued by STS (`ASIA`).
+    Temporary,
+
+    /// A prefix the deployment does not accept.
+    Other,
+
+    /// The request was not signed.
+    Anonymous,
+}
+
+impl AccessKeyClass {
+    pub fn of<B>(req: &Request<B>, prefixes: &AccessKeyPrefixes) -> Self {
+        match request_credential(req) {
+            None => Self::Anonymous,
+            Some((credential, _)) => match prefixes.kind(&credential) {
+                Some(AccessKeyKind::LongTerm) => Self::LongTerm,
+                Some(AccessKeyKind::Temporary) => Self::Temporary,
+                None => Self::Other,
+            },
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::LongTerm => "AKIA",
+            Self::Temporary => "ASIA",
+            Self::Other => "other",
+            Self::Anonymous => "anonymous",
+        }
+    }
+}
+
+impl Display for AccessKeyClass {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(self.as_str())
+    }
+}
+
+/// The outcome of verifying a request's signature.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum VerificationOutcome {
+    Success,
+
+    /// The request timestamp was outside the allowed window.
+    SkewedClock,
+    BadSignature,
+
+    /// The access key or session token was not recognized.
+    UnknownKey,
+
+    /// The request was rejected for some other reason, such as a missing or malformed signature.
+    Other,
+}
+
+impl VerificationOutcome {
+    /// Classify a rejection from the error code and message in the response.
+    pub fn from_error(code: &str, message: &str) -> Self {
+        match code {
+            "RequestExpired" => Self::SkewedClock,
+            "SignatureDoesNotMatch" if message.contains("expired") || message.contains("not yet current") => {
+                Self::SkewedClock
+            }
+            "SignatureDoesNotMatch" => Self::BadSignature,
+            "InvalidClientTokenId" | "UnrecognizedClientException" | "ExpiredToken" => Self::UnknownKey,
+            _ => Self::Other,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::SkewedClock => "skewed_clock",
+            Self::BadSignature => "bad_signature",
+            Self::UnknownKey => "unknown_key",
+            Self::Other => "other",
+        }
+    }
+}
+
+impl Display for VerificationOutcome {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Signature verification counters and timings.
+#[derive(Debug)]
+pub struct VerificationMetrics {
+    state: Mutex<MetricsState>,
+}
+
+#[derive(Debug)]
+struct MetricsState {
+    seconds: Histogram,
+    outcomes: BTreeMap<(VerificationOutcome, AccessKeyClass), u64>,
+}
+
+impl Default for VerificationMetrics {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(MetricsState {
+                seconds: Histogram::new(VERIFICATION_BUCKETS),
+                outcomes: BTreeMap::new(),
+            }),
+        }
+    }
+}
+
+impl VerificationMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, outcome: VerificationOutcome, class: AccessKeyClass, elapsed: Duration) {
+        let mut state = self.state.lock().expect("verification metrics poisoned");
+        state.seconds.observe(elapsed.as_secs_f64());
+        *state.outcomes.entry((outcome, class)).or_default() += 1;
+    }
+
+    /// The number of requests recorded with the given outcome and access key class.
+    pub fn count(&self, outcome: VerificationOutcome, class: AccessKeyClass) -> u64 {
+        let state = self.state.lock().expect("verification metrics poisoned");
+        state.outcomes.get(&(outcome, class)).copied().unwrap_or_default()
+    }
+
+    /// Verification counts by outcome and key class, and the verification time histogram.
+    pub fn render(&self) -> String {
+        let state = self.state.lock().expect("verification metrics poisoned");
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# HELP scratchstack_signature_verifications_total Signature verifications by outcome.");
+        let _ = writeln!(out, "# TYPE scratchstack_signature_verifications_total counter");
+        for ((outcome, class), count) in &state.outcomes {
+            let _ = writeln!(
+                out,
+                "scratchstack_signature_verifications_total{{outcome=\"{outcome}\",key_class=\"{class}\"}} {count}"
+            );
+        }
+
+        let _ = writeln!(out, "# HELP scratchstack_signature_verification_seconds Time spent verifying signatures.");
+        let _ = writeln!(out, "# TYPE scratchstack_signature_verification_seconds histogram");
+        state.seconds.render(&mut out, "scratchstack_signature_verification_seconds", "");
+        out
+    }
+}
+
+/// Per-request state shared by [RecordVerification] and [MarkVerified].
+#[derive(Clone, Debug)]
+struct VerificationTimer {
+    start: Instant,
+    class: AccessKeyClass,
+    verified: Arc<AtomicBool>,
+}
+
+/// Wraps a make-service (such as `SpawnService`) so each per-connection service records signature verification
+/// metrics. The service implementation must be wrapped in [MarkVerified].
+#[derive(Clone, Debug)]
+pub struct WithVerificationMetrics<M> {
+    inner: M,
+    metrics: Arc<VerificationMetrics>,
+    prefixes: Arc<AccessKeyPrefixes>,
+}
+
+impl<M> WithVerificationMetrics<M> {
+    pub fn new(inner: M, metrics: Arc<VerificationMetrics>) -> Self {
+        Self {
+            inner,
+            metrics,
+            prefixes: Arc::new(AccessKeyPrefixes::default()),
+        }
+    }
+
+    /// Classify access keys by this deployment's prefixes rather than AWS's.
+    pub fn with_access_key_prefixes(mut self, prefixes: AccessKeyPrefixes) -> Self {
+        self.prefixes = Arc::new(prefixes);
+        self
+    }
+}
+
+impl<T, M> Service<T> for WithVerificationMetrics<M>
+where
+    M: Service<T>,
+    M::Future: Send + 'static,
+{
+    type Response = RecordVerification<M::Response>;
+    type Error = M::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let metrics = self.metrics.clone();
+        let prefixes = self.prefixes.clone();
+        let future = self.inner.call(target);
+        Box::pin(async move {
+            Ok(RecordVerification {
+                inner: future.await?,
+                metrics,
+                prefixes,
+            })
+        })
+    }
+}
+
+/// A per-connection service that starts the verification timer and records rejected requests.
+#[derive(Clone, Debug)]
+pub struct RecordVerification<S> {
+    inner: S,
+    metrics: Arc<VerificationMetrics>,
+    prefixes: Arc<AccessKeyPrefixes>,
+}
+
+impl<S> Service<Request<Body>> for RecordVerification<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        let timer = VerificationTimer {
+            start: Instant::now(),
+            class: AccessKeyClass::of(&req, &self.prefixes),
+            verified: Arc::new(AtomicBool::new(false)),
+        };
+        req.extensions_mut().insert(timer.clone());
+        let metrics = self.metrics.clone();
+        let future = self.inner.call(req);
+
+        Box::pin(async move {
+            let response = future.await.map_err(Into::into)?;
+            if timer.verified.load(Ordering::Acquire) || !response.status().is_client_error() {
+                return Ok(response);
+            }
+
+            let elapsed = timer.start.elapsed();
+            let (parts, body) = response.into_parts();
+            let body = to_bytes(body).await?;
+            let text = String::from_utf8_lossy(&body);
+            let code = xml_element(&text, "Code").unwrap_or_default();
+            let outcome = VerificationOutcome::from_error(code, xml_element(&text, "Message").unwrap_or_default());
+            debug!("Signature verification failed: code={} outcome={} key_class={}", code, outcome, timer.class);
+            metrics.record(outcome, timer.class, elapsed);
+            Ok(Response::from_parts(parts, Body::from(body)))
+        })
+    }
+}
+
+/// A service wrapper for the service implementation that records successful verifications.
+#[derive(Clone, Debug)]
+pub struct MarkVerified<S> {
+    inner: S,
+    metrics: Arc<VerificationMetrics>,
+}
+
+impl<S> MarkVerified<S> {
+    pub fn new(inner: S, metrics: Arc<VerificationMetrics>) -> Self {
+        Self {
+            inner,
+            metrics,
+        }
+    }
+}
+
+impl<S, B> Service<Request<B>> for MarkVerified<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        if let Some(timer) = req.extensions().get::<VerificationTimer>() {
+            timer.verified.store(true, Ordering::Release);
+            self.metrics.record(VerificationOutcome::Success, timer.class, timer.start.elapsed());
+        }
+        self.inner.call(req)
+    }
+}
+
+/// Returns the text of the first `<name>` element in `xml`.
+fn xml_element<'a>(xml: &'a str, name: &str) -> Option<&'a str> {
+    let open = format!("<{name}>");
+    let start = xml.find(&open)? + open.len();
+    let end = xml[start..].find(&format!("</{name}>"))?;
+    Some(&xml[start..start + end])
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{xml_element, AccessKeyClass, VerificationMetrics, VerificationOutcome},
+        crate::access_key::AccessKeyPrefixes,
+        hyper::Request,
+        pretty_assertions::assert_eq,
+        std::time::Duration,
+    };
+
+    #[test_log::test]
+    fn test_classification() {
+        let req = Request::builder()
+            .header(
+                "Authorization",
+                "AWS4-HMAC-SHA256 Credential=ASIAEXAMPLE/20150830/us-east-1/sts/aws4_request, Signature=00",
+            )
+            .body(())
+            .unwrap();
+        let prefixes = AccessKeyPrefixes::default();
+        assert_eq!(AccessKeyClass::of(&req, &prefixes), AccessKeyClass::Temporary);
+        assert_eq!(AccessKeyClass::of(&Request::builder().body(()).unwrap(), &prefixes), AccessKeyClass::Anonymous);
+
+        let prefixes = AccessKeyPrefixes::new("SKIA", "SSIA").unwrap();
+        assert_eq!(AccessKeyClass::of(&req, &prefixes), AccessKeyClass::Other);
+
+        let body = "<ErrorResponse><Error><Type>Sender</Type><Code>SignatureDoesNotMatch</Code><Message>Signature expired: 20150830T123600Z is now earlier than 20150830T124100Z</Message></Error></ErrorResponse>";
+        let code = xml_element(body, "Code").unwrap();
+        let message = xml_element(body, "Message").unwrap();
+        assert_eq!(VerificationOutcome::from_error(code, message), VerificationOutcome::SkewedClock);
+        assert_eq!(VerificationOutcome::from_error(code, "bad"), VerificationOutcome::BadSignature);
+        assert_eq!(VerificationOutcome::from_error("InvalidClientTokenId", ""), VerificationOutcome::UnknownKey);
+    }
+
+    #[test_log::test]
+    fn test_render() {
+        let metrics = VerificationMetrics::new();
+        metrics.record(VerificationOutcome::Success, AccessKeyClass::LongTerm, Duration::from_micros(800));
+        metrics.record(VerificationOutcome::UnknownKey, AccessKeyClass::LongTerm, Duration::from_secs(2));
+        assert_eq!(metrics.count(VerificationOutcome::UnknownKey, AccessKeyClass::LongTerm), 1);
+
+        let rendered = metrics.render();
+        assert!(rendered
+            .contains("scratchstack_signature_verifications_total{outcome=\"unknown_key\",key_class=\"AKIA\"} 1"));
+        assert!(rendered.contains("scratchstack_signature_verification_seconds_bucket{le=\"0.001\"} 1"));
+        assert!(rendered.contains("scratchstack_signature_verification_seconds_bucket{le=\"+Inf\"} 2"));
+    }
+}
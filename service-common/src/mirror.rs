@@ -0,0 +1,267 @@
+use {
+    crate::crypto::{hex, sha256},
+    hyper::{
+        body::{to_bytes, Bytes},
+        client::HttpConnector,
+        http::uri::{InvalidUri, Parts as UriParts},
+        service::Service,
+        Body, Client, HeaderMap, Request, Response, StatusCode, Uri,
+    },
+    log::{debug, info, warn},
+    serde::Deserialize,
+    std::{
+        fmt::{Debug, Formatter, Result as FmtResult},
+        future::Future,
+        pin::Pin,
+        sync::{
+            atomic::{AtomicU64, Ordering},
+            Arc,
+        },
+        task::{Context, Poll},
+    },
+    tower::BoxError,
+};
+
+/// Settings for mirroring requests to a secondary backend.
+#[derive(Clone, Debug, Deserialize)]
+pub struct MirrorConfig {
+    /// The base URL of the secondary backend, e.g. `http://10.0.0.5:8080`. Only plain HTTP is supported.
+    pub url: String,
+
+    /// The percentage of requests to mirror, from 0 to 100.
+    #[serde(default = "MirrorConfig::default_percent")]
+    pub percent: u32,
+}
+
+impl MirrorConfig {
+    fn default_percent() -> u32 {
+        100
+    }
+}
+
+/// A service wrapper that copies a sample of requests to a secondary backend and logs when its response differs
+/// from the one produced by the wrapped service.
+///
+/// This wraps the service implementation, so only requests that have passed signature verification are mirrored.
+/// The request is forwarded with its original headers, so the secondary backend sees the same signature. Mirrored
+/// requests are sent after the primary response has been produced and never delay it.
+#[derive(Clone)]
+pub struct Mirror<S> {
+    inner: S,
+    target: Option<Arc<MirrorTarget>>,
+}
+
+impl<S> Mirror<S> {
+    /// Wrap `inner`; requests are only mirrored if `config` is present.
+    pub fn new(inner: S, config: Option<&MirrorConfig>) -> Result<Self, InvalidUri> {
+        let target = match config {
+            None => None,
+            Some(config) => Some(Arc::new(MirrorTarget::new(config)?)),
+        };
+
+        Ok(Self {
+            inner,
+            target,
+        })
+    }
+}
+
+impl<S: Debug> Debug for Mirror<S> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Mirror")
+            .field("inner", &self.inner)
+            .field("target", &self.target.as_ref().map(|t| t.base.to_string()))
+            .finish()
+    }
+}
+
+impl<S> Service<Request<Body>> for Mirror<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = BoxError> + Clone + Send + 'static,
+    S::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let target = match &self.target {
+            Some(target) if target.sample() => target.clone(),
+            _ => return Box::pin(self.inner.call(req)),
+        };
+
+        // The clone is not guaranteed to be ready; use the instance that was polled and leave the clone behind.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = to_bytes(body).await?;
+
+            let mut mirrored = Request::builder().method(parts.method.clone()).uri(target.uri_for(&parts.uri)?);
+            if let Some(headers) = mirrored.headers_mut() {
+                *headers = parts.headers.clone();
+            }
+            let mirrored = mirrored.body(Body::from(body.clone()))?;
+
+            let response = inner.call(Request::from_parts(parts, Body::from(body))).await?;
+            let (parts, body) = response.into_parts();
+            let body = to_bytes(body).await?;
+            let primary = ResponseSummary::new(parts.status, &parts.headers, &body);
+
+            tokio::spawn(async move {
+                let uri = mirrored.uri().clone();
+                match target.client.request(mirrored).await {
+                    Ok(response) => {
+                        let (parts, body) = response.into_parts();
+                        match to_bytes(body).await {
+                            Ok(body) => {
+                                let secondary = ResponseSummary::new(parts.status, &parts.headers, &body);
+                                if primary == secondary {
+                                    debug!("Mirrored request to {} matched: {:?}", uri, primary);
+                                } else {
+                                    info!(
+                                        "Mirrored request to {} differs: primary={:?} secondary={:?}",
+                                        uri, primary, secondary
+                                    );
+                                }
+                            }
+                            Err(e) => warn!("Unable to read mirrored response from {}: {}", uri, e),
+                        }
+                    }
+                    Err(e) => warn!("Unable to send mirrored request to {}: {}", uri, e),
+                }
+            });
+
+            Ok(Response::from_parts(parts, Body::from(body)))
+        })
+    }
+}
+
+struct MirrorTarget {
+    base: Uri,
+    sampler: PercentSampler,
+    client: Client<HttpConnector>,
+}
+
+impl MirrorTarget {
+    fn new(config: &MirrorConfig) -> Result<Self, InvalidUri> {
+        Ok(Self {
+            base: config.url.parse()?,
+            sampler: PercentSampler::new(config.percent),
+            client: Client::new(),
+        })
+    }
+
+    fn sample(&self) -> bool {
+        self.sampler.sample()
+    }
+
+    fn uri_for(&self, original: &Uri) -> Result<Uri, BoxError> {
+        rebase_uri(&self.base, original)
+    }
+}
+
+/// Selects a fixed percentage of requests at evenly spaced intervals.
+#[derive(Debug)]
+pub(crate) struct PercentSampler {
+    percent: u64,
+    counter: AtomicU64,
+}
+
+impl PercentSampler {
+    pub(crate) fn new(percent: u32) -> Self {
+        Self {
+            percent: percent.min(100) as u64,
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    /// Returns true if the next request is selected.
+    pub(crate) fn sample(&self) -> bool {
+        let n = self.counter.fetch_add(1, Ordering::Relaxed);
+        (n + 1) * self.percent / 100 > n * self.percent / 100
+    }
+}
+
+/// Returns `original` with its scheme and authority replaced by those of `base`.
+pub(crate) fn rebase_uri(base: &Uri, original: &Uri) -> Result<Uri, BoxError> {
+    let mut parts = UriParts::from(base.clone());
+    parts.path_and_query = original.path_and_query().cloned();
+    Ok(Uri::from_parts(parts)?)
+}
+
+/// The parts of a response that are compared between the primary and secondary backends.
+#[derive(Eq, PartialEq)]
+struct ResponseSummary {
+    status: StatusCode,
+    body_sha256: String,
+}
+
+impl ResponseSummary {
+    /// Summarize a response. Each backend generates its own request id, so the value of `X-Amzn-RequestId` is
+    /// removed from the body before it is hashed.
+    fn new(status: StatusCode, headers: &HeaderMap, body: &Bytes) -> Self {
+        let body = String::from_utf8_lossy(body);
+        let body = match headers.get("X-Amzn-RequestId").and_then(|v| v.to_str().ok()) {
+            Some(request_id) if !request_id.is_empty() => body.replace(request_id, ""),
+            _ => body.into_owned(),
+        };
+
+        let body_sha256 = hex(&sha256(body.as_bytes()));
+        Self {
+            status,
+            body_sha256,
+        }
+    }
+}
+
+impl Debug for ResponseSummary {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "{} sha256={}", self.status.as_u16(), self.body_sha256)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{MirrorConfig, MirrorTarget, ResponseSummary},
+        hyper::{body::Bytes, HeaderMap, StatusCode},
+        pretty_assertions::assert_eq,
+    };
+
+    #[test_log::test]
+    fn test_sample() {
+        let target = MirrorTarget::new(&MirrorConfig {
+            url: "http://127.0.0.1:8080".to_string(),
+            percent: 25,
+        })
+        .unwrap();
+
+        let sampled = (0..100).filter(|_| target.sample()).count();
+        assert_eq!(sampled, 25);
+        assert_eq!(
+            target.uri_for(&"/?Action=GetCallerIdentity".parse().unwrap()).unwrap().to_string(),
+            "http://127.0.0.1:8080/?Action=GetCallerIdentity"
+        );
+    }
+
+    #[test_log::test]
+    fn test_response_summary_ignores_request_id() {
+        let mut h1 = HeaderMap::new();
+        h1.insert("X-Amzn-RequestId", "11111111-1111-1111-1111-111111111111".parse().unwrap());
+        let mut h2 = HeaderMap::new();
+        h2.insert("X-Amzn-RequestId", "22222222-2222-2222-2222-222222222222".parse().unwrap());
+
+        let b1 = Bytes::from("<RequestId>11111111-1111-1111-1111-111111111111</RequestId>");
+        let b2 = Bytes::from("<RequestId>22222222-2222-2222-2222-222222222222</RequestId>");
+        assert!(ResponseSummary::new(StatusCode::OK, &h1, &b1) == ResponseSummary::new(StatusCode::OK, &h2, &b2));
+        assert!(
+            ResponseSummary::new(StatusCode::OK, &h1, &b1) != ResponseSummary::new(StatusCode::FORBIDDEN, &h2, &b2)
+        );
+    }
+}
@@ -0,0 +1,46 @@
+use {
+    crate::tls::{subject_alt_names, SubjectAltName, TlsError},
+    rustls::Certificate,
+    x509_parser::{certificate::X509Certificate, prelude::FromDer},
+};
+
+/// The identity a client proved with its TLS certificate.
+///
+/// When the endpoint requires client certificates (see [TlsFiles::client_ca_file][crate::tls::TlsFiles]), this is
+/// inserted into the extensions of every request on the connection by
+/// [WithConnectionInfo][crate::net::WithConnectionInfo]. It identifies the peer, such as a service mesh sidecar, and
+/// is separate from the principal the request was signed as.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ClientIdentity {
+    subject: String,
+    subject_alt_names: Vec<SubjectAltName>,
+    certificate: Certificate,
+}
+
+impl ClientIdentity {
+    /// The identity in a verified client certificate.
+    pub fn from_certificate(certificate: &Certificate) -> Result<Self, TlsError> {
+        let (_, x509) =
+            X509Certificate::from_der(&certificate.0).map_err(|e| TlsError::InvalidCertificate(e.to_string()))?;
+
+        Ok(Self {
+            subject: x509.subject().to_string(),
+            subject_alt_names: subject_alt_names(certificate)?,
+            certificate: certificate.clone(),
+        })
+    }
+
+    /// The subject distinguished name, e.g. `CN=iam-client`.
+    pub fn subject(&self) -> &str {
+        &self.subject
+    }
+
+    pub fn subject_alt_names(&self) -> &[SubjectAltName] {
+        &self.subject_alt_names
+    }
+
+    /// The DER-encoded client certificate.
+    pub fn certificate(&self) -> &Certificate {
+        &self.certificate
+    }
+}
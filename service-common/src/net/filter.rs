@@ -0,0 +1,94 @@
+use {ipnet::IpNet, std::net::IpAddr};
+
+/// CIDR-based allow and deny lists for incoming connections.
+///
+/// Deny entries always take precedence. If the allow list is empty, any address not explicitly denied is
+/// accepted; otherwise, the address must also fall within one of the allowed networks.
+#[derive(Clone, Debug, Default)]
+pub struct IpFilter {
+    allow: Vec<IpNet>,
+    deny: Vec<IpNet>,
+}
+
+impl IpFilter {
+    pub fn new(allow: Vec<IpNet>, deny: Vec<IpNet>) -> Self {
+        Self {
+            allow,
+            deny,
+        }
+    }
+
+    /// Indicates whether this filter performs any filtering at all.
+    pub fn is_empty(&self) -> bool {
+        self.allow.is_empty() && self.deny.is_empty()
+    }
+
+    pub fn is_allowed(&self, addr: IpAddr) -> bool {
+        let addr = unmap_ipv4(addr);
+
+        if self.deny.iter().any(|net| net.contains(&addr)) {
+            return false;
+        }
+
+        self.allow.is_empty() || self.allow.iter().any(|net| net.contains(&addr))
+    }
+}
+
+/// Dual-stack listeners report IPv4 clients as IPv4-mapped IPv6 addresses (::ffff:a.b.c.d); convert these back so
+/// they match IPv4 rules.
+pub(crate) fn unmap_ipv4(addr: IpAddr) -> IpAddr {
+    match addr {
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped() {
+            Some(v4) => IpAddr::V4(v4),
+            None => addr,
+        },
+        IpAddr::V4(_) => addr,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::IpFilter, std::net::IpAddr};
+
+    fn filter(allow: &[&str], deny: &[&str]) -> IpFilter {
+        IpFilter::new(
+            allow.iter().map(|s| s.parse().unwrap()).collect(),
+            deny.iter().map(|s| s.parse().unwrap()).collect(),
+        )
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test_log::test]
+    fn test_empty_filter_allows_all() {
+        let f = filter(&[], &[]);
+        assert!(f.is_empty());
+        assert!(f.is_allowed(ip("192.0.2.1")));
+        assert!(f.is_allowed(ip("2001:db8::1")));
+    }
+
+    #[test_log::test]
+    fn test_deny_wins() {
+        let f = filter(&["10.0.0.0/8"], &["10.1.0.0/16"]);
+        assert!(f.is_allowed(ip("10.2.3.4")));
+        assert!(!f.is_allowed(ip("10.1.3.4")));
+        assert!(!f.is_allowed(ip("192.168.0.1")));
+    }
+
+    #[test_log::test]
+    fn test_deny_only() {
+        let f = filter(&[], &["192.0.2.0/24", "2001:db8::/32"]);
+        assert!(!f.is_allowed(ip("192.0.2.77")));
+        assert!(!f.is_allowed(ip("2001:db8::5")));
+        assert!(f.is_allowed(ip("198.51.100.1")));
+    }
+
+    #[test_log::test]
+    fn test_ipv4_mapped() {
+        let f = filter(&["127.0.0.0/8"], &[]);
+        assert!(f.is_allowed(ip("::ffff:127.0.0.1")));
+        assert!(!f.is_allowed(ip("::ffff:192.0.2.1")));
+    }
+}
@@ -0,0 +1,137 @@
+use {
+    crate::net::{filter::unmap_ipv4, ConnectionInfo},
+    http::HeaderMap,
+    ipnet::IpNet,
+    std::{
+        net::{IpAddr, SocketAddr},
+        sync::Arc,
+    },
+};
+
+/// The client address chain appended to by each proxy, leftmost first.
+pub const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// The protocol the client used to connect to the first proxy.
+pub const X_FORWARDED_PROTO: &str = "x-forwarded-proto";
+
+/// Proxies, such as load balancers that terminate TLS, whose `X-Forwarded-For` and `X-Forwarded-Proto` headers are
+/// believed.
+///
+/// Headers are only honored on connections from a trusted proxy. `X-Forwarded-For` is read from the right, skipping
+/// further trusted proxies, so a client cannot choose its own source address by sending the header itself. The
+/// rightmost `X-Forwarded-Proto` value, set by the nearest proxy, decides whether the request counts as secure for
+/// `aws:SecureTransport`.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies {
+    networks: Arc<Vec<IpNet>>,
+}
+
+impl TrustedProxies {
+    pub fn new(networks: Vec<IpNet>) -> Self {
+        Self {
+            networks: Arc::new(networks),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.networks.is_empty()
+    }
+
+    pub fn contains(&self, addr: IpAddr) -> bool {
+        let addr = unmap_ipv4(addr);
+        self.networks.iter().any(|net| net.contains(&addr))
+    }
+
+    /// The connection details for a request with these headers that arrived on `info`.
+    pub fn resolve(&self, info: ConnectionInfo, headers: &HeaderMap) -> ConnectionInfo {
+        let peer = info.remote_addr().ip();
+        if !self.contains(peer) {
+            return info;
+        }
+
+        let mut source_ip = peer;
+        let hops: Vec<&str> = headers
+            .get_all(X_FORWARDED_FOR)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .collect();
+        for hop in hops.iter().rev() {
+            match parse_hop(hop) {
+                Some(addr) => {
+                    source_ip = addr;
+                    if !self.contains(addr) {
+                        break;
+                    }
+                }
+                // Anything left of an unreadable entry cannot be attributed to a trusted proxy.
+                None => break,
+            }
+        }
+
+        let proto = headers
+            .get_all(X_FORWARDED_PROTO)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim)
+            .last();
+        let secure = match proto {
+            Some(proto) => proto.eq_ignore_ascii_case("https"),
+            None => info.is_tls(),
+        };
+
+        info.with_forwarded(source_ip, secure)
+    }
+}
+
+/// Parse an `X-Forwarded-For` entry, which some proxies write with a port.
+fn parse_hop(hop: &str) -> Option<IpAddr> {
+    hop.parse::<IpAddr>().ok().or_else(|| hop.parse::<SocketAddr>().ok().map(|addr| addr.ip()))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::TrustedProxies, crate::net::ConnectionInfo, http::HeaderMap, pretty_assertions::assert_eq,
+        std::net::IpAddr,
+    };
+
+    fn resolve(peer: &str, headers: &[(&'static str, &str)]) -> (IpAddr, bool) {
+        let proxies = TrustedProxies::new(vec!["10.0.0.0/8".parse().unwrap()]);
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            map.append(*name, value.parse().unwrap());
+        }
+
+        let info = proxies.resolve(ConnectionInfo::new(peer.parse().unwrap(), false), &map);
+        (info.source_ip(), info.is_secure())
+    }
+
+    fn ip(s: &str) -> IpAddr {
+        s.parse().unwrap()
+    }
+
+    #[test_log::test]
+    fn test_resolve() {
+        // Headers from untrusted peers are ignored.
+        let spoofed = [("x-forwarded-for", "192.0.2.1"), ("x-forwarded-proto", "https")];
+        assert_eq!(resolve("198.51.100.7:4000", &spoofed), (ip("198.51.100.7"), false));
+
+        // A trusted proxy terminating TLS.
+        assert_eq!(resolve("10.0.0.5:4000", &spoofed), (ip("192.0.2.1"), true));
+        assert_eq!(resolve("[::ffff:10.0.0.5]:4000", &spoofed), (ip("192.0.2.1"), true));
+
+        // A client-supplied entry to the left of the real client address is not believed.
+        let chained = [("x-forwarded-for", "203.0.113.9, 192.0.2.1"), ("x-forwarded-for", "10.1.2.3:80")];
+        assert_eq!(resolve("10.0.0.5:4000", &chained), (ip("192.0.2.1"), false));
+
+        // An unreadable entry stops the walk at the last address that was vouched for.
+        assert_eq!(resolve("10.0.0.5:4000", &[("x-forwarded-for", "192.0.2.1, garbage")]), (ip("10.0.0.5"), false));
+
+        // The nearest proxy's protocol wins.
+        let protos = [("x-forwarded-proto", "https, http")];
+        assert_eq!(resolve("10.0.0.5:4000", &protos), (ip("10.0.0.5"), false));
+    }
+}
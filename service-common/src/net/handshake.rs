@@ -0,0 +1,199 @@
+use {
+    crate::histogram::Histogram,
+    rustls::{ProtocolVersion, ServerConnection},
+    std::{collections::BTreeMap, fmt::Write, sync::Mutex, time::Duration},
+};
+
+/// Upper bounds of the TLS handshake time histogram buckets, in seconds.
+pub const HANDSHAKE_BUCKETS: &[f64] = &[0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+/// What was negotiated in the TLS handshake of a connection.
+///
+/// Available from [Connection::tls_session][crate::net::Connection::tls_session], and inserted into the extensions of
+/// every request on the connection by [WithConnectionInfo][crate::net::WithConnectionInfo] so the access log can
+/// report it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TlsSession {
+    protocol_version: String,
+    cipher_suite: String,
+    server_name: Option<String>,
+    client_auth: bool,
+    handshake_time: Duration,
+}
+
+impl TlsSession {
+    /// The session of an established server connection whose handshake took `handshake_time`.
+    pub fn new(conn: &ServerConnection, handshake_time: Duration) -> Self {
+        let protocol_version = match conn.protocol_version() {
+            None => "unknown".to_string(),
+            Some(ProtocolVersion::TLSv1_2) => "TLSv1.2".to_string(),
+            Some(ProtocolVersion::TLSv1_3) => "TLSv1.3".to_string(),
+            Some(version) => format!("{version:?}"),
+        };
+        let cipher_suite = match conn.negotiated_cipher_suite() {
+            None => "unknown".to_string(),
+            Some(suite) => format!("{:?}", suite.suite()),
+        };
+
+        Self {
+            protocol_version,
+            cipher_suite,
+            server_name: conn.sni_hostname().map(ToString::to_string),
+            client_auth: conn.peer_certificates().map(|chain| !chain.is_empty()).unwrap_or(false),
+            handshake_time,
+        }
+    }
+
+    /// The negotiated protocol version, e.g. `TLSv1.3`.
+    pub fn protocol_version(&self) -> &str {
+        &self.protocol_version
+    }
+
+    /// The negotiated cipher suite, e.g. `TLS13_AES_128_GCM_SHA256`.
+    pub fn cipher_suite(&self) -> &str {
+        &self.cipher_suite
+    }
+
+    /// The server name the client asked for with SNI, if any.
+    pub fn server_name(&self) -> Option<&str> {
+        self.server_name.as_deref()
+    }
+
+    /// Indicates whether the client presented a certificate.
+    pub fn client_auth(&self) -> bool {
+        self.client_auth
+    }
+
+    pub fn handshake_time(&self) -> Duration {
+        self.handshake_time
+    }
+}
+
+/// TLS handshake counters and timings for an [Incoming][crate::net::Incoming] listener.
+///
+/// Handshakes are counted by protocol version, cipher suite and whether the client presented a certificate, so
+/// operators can find clients still on an old protocol version before tightening the rustls configuration.
+#[derive(Debug)]
+pub struct TlsMetrics {
+    state: Mutex<MetricsState>,
+}
+
+#[derive(Debug)]
+struct MetricsState {
+    seconds: Histogram,
+    handshakes: BTreeMap<(String, String, bool), u64>,
+    failures: u64,
+}
+
+impl Default for TlsMetrics {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(MetricsState {
+                seconds: Histogram::new(HANDSHAKE_BUCKETS),
+                handshakes: BTreeMap::new(),
+                failures: 0,
+            }),
+        }
+    }
+}
+
+impl TlsMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record a successful handshake.
+    pub fn record(&self, session: &TlsSession) {
+        let mut state = self.state.lock().expect("TLS metrics poisoned");
+        state.seconds.observe(session.handshake_time.as_secs_f64());
+        let key = (session.protocol_version.clone(), session.cipher_suite.clone(), session.client_auth);
+        *state.handshakes.entry(key).or_default() += 1;
+    }
+
+    /// Record a handshake that failed, e.g. because the client offered no protocol version in common.
+    pub fn record_failure(&self) {
+        self.state.lock().expect("TLS metrics poisoned").failures += 1;
+    }
+
+    /// The number of successful handshakes that negotiated `protocol_version`.
+    pub fn count(&self, protocol_version: &str) -> u64 {
+        let state = self.state.lock().expect("TLS metrics poisoned");
+        state.handshakes.iter().filter(|((version, _, _), _)| version == protocol_version).map(|(_, count)| count).sum()
+    }
+
+    pub fn failures(&self) -> u64 {
+        self.state.lock().expect("TLS metrics poisoned").failures
+    }
+
+    /// Handshake counts by protocol version, cipher suite and client authentication, failures, and the handshake
+    /// time histogram.
+    pub fn render(&self) -> String {
+        let state = self.state.lock().expect("TLS metrics poisoned");
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# HELP scratchstack_tls_handshakes_total Successful TLS handshakes.");
+        let _ = writeln!(out, "# TYPE scratchstack_tls_handshakes_total counter");
+        for ((version, cipher, client_auth), count) in &state.handshakes {
+            let _ = writeln!(
+                out,
+                "scratchstack_tls_handshakes_total{{version=\"{}\",cipher=\"{}\",client_auth=\"{}\"}} {}",
+                version, cipher, client_auth, count
+            );
+        }
+
+        let _ = writeln!(out, "# HELP scratchstack_tls_handshake_failures_total Failed TLS handshakes.");
+        let _ = writeln!(out, "# TYPE scratchstack_tls_handshake_failures_total counter");
+        let _ = writeln!(out, "scratchstack_tls_handshake_failures_total {}", state.failures);
+
+        let _ = writeln!(out, "# HELP scratchstack_tls_handshake_seconds Time spent on successful TLS handshakes.");
+        let _ = writeln!(out, "# TYPE scratchstack_tls_handshake_seconds histogram");
+        state.seconds.render(&mut out, "scratchstack_tls_handshake_seconds", "");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{TlsMetrics, TlsSession},
+        pretty_assertions::assert_eq,
+        std::time::Duration,
+    };
+
+    fn session(protocol_version: &str, client_auth: bool, handshake_time: Duration) -> TlsSession {
+        TlsSession {
+            protocol_version: protocol_version.to_string(),
+            cipher_suite: "AES128".to_string(),
+            server_name: None,
+            client_auth,
+            handshake_time,
+        }
+    }
+
+    #[test_log::test]
+    fn test_record_and_render() {
+        let metrics = TlsMetrics::new();
+        metrics.record(&session("TLSv1.3", false, Duration::from_millis(2)));
+        metrics.record(&session("TLSv1.3", true, Duration::from_millis(40)));
+        metrics.record(&session("TLSv1.2", false, Duration::from_secs(3)));
+        metrics.record_failure();
+
+        assert_eq!((metrics.count("TLSv1.3"), metrics.count("TLSv1.2"), metrics.count("TLSv1.1")), (2, 1, 0));
+        assert_eq!(metrics.failures(), 1);
+
+        let rendered = metrics.render();
+        for line in [
+            "scratchstack_tls_handshakes_total{version=\"TLSv1.2\",cipher=\"AES128\",client_auth=\"false\"} 1",
+            "scratchstack_tls_handshakes_total{version=\"TLSv1.3\",cipher=\"AES128\",client_auth=\"true\"} 1",
+            "scratchstack_tls_handshake_failures_total 1",
+            "scratchstack_tls_handshake_seconds_bucket{le=\"0.001\"} 0",
+            "scratchstack_tls_handshake_seconds_bucket{le=\"0.0025\"} 1",
+            "scratchstack_tls_handshake_seconds_bucket{le=\"0.05\"} 2",
+            "scratchstack_tls_handshake_seconds_bucket{le=\"2.5\"} 2",
+            "scratchstack_tls_handshake_seconds_bucket{le=\"+Inf\"} 3",
+            "scratchstack_tls_handshake_seconds_count 3",
+        ] {
+            assert!(rendered.lines().any(|rendered| rendered == line), "{line} missing from:\n{rendered}");
+        }
+    }
+}
@@ -0,0 +1,429 @@
+use {
+    crate::net::{ClientIdentity, IpFilter, TlsMetrics, TlsSession},
+    futures::stream::{FuturesUnordered, StreamExt},
+    hyper::server::accept::Accept,
+    log::{debug, warn},
+    rustls::ServerConfig,
+    std::{
+        future::Future,
+        io::{Error as IOError, ErrorKind, IoSlice},
+        net::SocketAddr,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+        time::{Duration, Instant},
+    },
+    tokio::{
+        io::{AsyncRead, AsyncWrite, ReadBuf},
+        net::{TcpListener, TcpStream},
+        time::timeout,
+    },
+    tokio_rustls::{server::TlsStream, TlsAcceptor},
+};
+
+/// How long a client has to complete the TLS handshake before the connection is dropped.
+pub const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How many TLS handshakes may be in progress at once. Further connections wait in the listen backlog.
+pub const DEFAULT_MAX_HANDSHAKES: usize = 256;
+
+type Handshake = Pin<Box<dyn Future<Output = (SocketAddr, Duration, Result<TlsStream<TcpStream>, IOError>)> + Send>>;
+
+/// A listener for service endpoints that applies an [IpFilter] to each connection as it is accepted, before any TLS
+/// handshake (if configured) is performed.
+///
+/// Handshakes run concurrently so that a slow client does not hold up the others, but each is limited to the
+/// handshake timeout and only a bounded number are in progress at once; clients that connect and never finish the
+/// handshake cannot exhaust the service's memory or file descriptors.
+pub struct Incoming {
+    listener: TcpListener,
+    acceptor: Option<TlsAcceptor>,
+    filter: IpFilter,
+    handshakes: FuturesUnordered<Handshake>,
+    handshake_timeout: Duration,
+    max_handshakes: usize,
+    tls_metrics: Option<Arc<TlsMetrics>>,
+}
+
+impl Incoming {
+    pub async fn bind(address: &SocketAddr, tls: Option<ServerConfig>, filter: IpFilter) -> Result<Self, IOError> {
+        let listener = TcpListener::bind(address).await?;
+        Ok(Self::new(listener, tls, filter))
+    }
+
+    pub fn new(listener: TcpListener, tls: Option<ServerConfig>, filter: IpFilter) -> Self {
+        Self {
+            listener,
+            acceptor: tls.map(|tls| TlsAcceptor::from(Arc::new(tls))),
+            filter,
+            handshakes: FuturesUnordered::new(),
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            max_handshakes: DEFAULT_MAX_HANDSHAKES,
+            tls_metrics: None,
+        }
+    }
+
+    /// Drop connections that have not completed the TLS handshake within `handshake_timeout`.
+    pub fn with_handshake_timeout(mut self, handshake_timeout: Duration) -> Self {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    /// Stop accepting connections while `max_handshakes` TLS handshakes are in progress.
+    pub fn with_max_handshakes(mut self, max_handshakes: usize) -> Self {
+        self.max_handshakes = max_handshakes.max(1);
+        self
+    }
+
+    /// Record the outcome, duration and negotiated parameters of each TLS handshake in `metrics`.
+    pub fn with_tls_metrics(mut self, metrics: Arc<TlsMetrics>) -> Self {
+        self.tls_metrics = Some(metrics);
+        self
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, IOError> {
+        self.listener.local_addr()
+    }
+}
+
+impl Accept for Incoming {
+    type Conn = Connection;
+    type Error = IOError;
+
+    fn poll_accept(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Result<Self::Conn, Self::Error>>> {
+        let this = self.get_mut();
+
+        loop {
+            // Finish handshakes first so that completed ones make room for new connections.
+            while let Poll::Ready(Some((remote_addr, elapsed, result))) = this.handshakes.poll_next_unpin(cx) {
+                match result {
+                    Ok(stream) => {
+                        let conn = Connection::tls(stream, remote_addr, elapsed);
+                        if let Some(session) = conn.tls_session() {
+                            debug!(
+                                "TLS handshake with {} took {:?}: version={} cipher={} sni={} client_auth={}",
+                                remote_addr,
+                                elapsed,
+                                session.protocol_version(),
+                                session.cipher_suite(),
+                                session.server_name().unwrap_or("-"),
+                                session.client_auth()
+                            );
+                            if let Some(metrics) = &this.tls_metrics {
+                                metrics.record(session);
+                            }
+                        }
+                        return Poll::Ready(Some(Ok(conn)));
+                    }
+                    Err(e) => {
+                        warn!("TLS handshake with {} failed: {}", remote_addr, e);
+                        if let Some(metrics) = &this.tls_metrics {
+                            metrics.record_failure();
+                        }
+                    }
+                }
+            }
+
+            // The task is woken when a handshake finishes, so the listener is polled again then.
+            if this.handshakes.len() >= this.max_handshakes {
+                return Poll::Pending;
+            }
+
+            match this.listener.poll_accept(cx) {
+                Poll::Ready(Ok((stream, remote_addr))) => {
+                    if !this.filter.is_allowed(remote_addr.ip()) {
+                        debug!("Rejecting connection from {}: address not permitted by listener filter", remote_addr);
+                        continue;
+                    }
+
+                    match &this.acceptor {
+                        None => return Poll::Ready(Some(Ok(Connection::plain(stream, remote_addr)))),
+                        Some(acceptor) => {
+                            let accept = timeout(this.handshake_timeout, acceptor.accept(stream));
+                            this.handshakes.push(Box::pin(async move {
+                                let start = Instant::now();
+                                let result = match accept.await {
+                                    Ok(result) => result,
+                                    Err(_) => Err(IOError::new(ErrorKind::TimedOut, "handshake timed out")),
+                                };
+                                (remote_addr, start.elapsed(), result)
+                            }));
+                        }
+                    }
+                }
+                Poll::Ready(Err(e)) => match e.kind() {
+                    // These refer to a single failed connection; keep listening.
+                    ErrorKind::ConnectionAborted | ErrorKind::ConnectionRefused | ErrorKind::ConnectionReset => {
+                        debug!("Error accepting connection: {}", e);
+                    }
+                    _ => return Poll::Ready(Some(Err(e))),
+                },
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// An accepted connection from [Incoming].
+pub struct Connection {
+    remote_addr: SocketAddr,
+    stream: Stream,
+    client_identity: Option<ClientIdentity>,
+    tls_session: Option<TlsSession>,
+}
+
+enum Stream {
+    Plain(TcpStream),
+    Tls(Box<TlsStream<TcpStream>>),
+}
+
+impl Connection {
+    fn plain(stream: TcpStream, remote_addr: SocketAddr) -> Self {
+        Self {
+            remote_addr,
+            stream: Stream::Plain(stream),
+            client_identity: None,
+            tls_session: None,
+        }
+    }
+
+    fn tls(stream: TlsStream<TcpStream>, remote_addr: SocketAddr, handshake_time: Duration) -> Self {
+        // rustls has already verified the certificate chain if the endpoint asked for one.
+        let client_identity = match stream.get_ref().1.peer_certificates().and_then(|chain| chain.first()) {
+            None => None,
+            Some(certificate) => match ClientIdentity::from_certificate(certificate) {
+                Ok(identity) => Some(identity),
+                Err(e) => {
+                    warn!("Unable to read client certificate from {}: {}", remote_addr, e);
+                    None
+                }
+            },
+        };
+
+        let tls_session = TlsSession::new(stream.get_ref().1, handshake_time);
+
+        Self {
+            remote_addr,
+            stream: Stream::Tls(Box::new(stream)),
+            client_identity,
+            tls_session: Some(tls_session),
+        }
+    }
+
+    /// The address of the remote peer.
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+
+    /// Indicates whether this connection is secured by TLS.
+    pub fn is_tls(&self) -> bool {
+        matches!(self.stream, Stream::Tls(_))
+    }
+
+    /// The identity in the client's TLS certificate, if the endpoint requires one.
+    pub fn client_identity(&self) -> Option<&ClientIdentity> {
+        self.client_identity.as_ref()
+    }
+
+    /// What was negotiated in the TLS handshake, if this connection is secured by TLS.
+    pub fn tls_session(&self) -> Option<&TlsSession> {
+        self.tls_session.as_ref()
+    }
+}
+
+impl AsyncRead for Connection {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &mut ReadBuf<'_>) -> Poll<Result<(), IOError>> {
+        match &mut self.get_mut().stream {
+            Stream::Plain(s) => Pin::new(s).poll_read(cx, buf),
+            Stream::Tls(s) => Pin::new(s).poll_read(cx, buf),
+        }
+    }
+}
+
+impl AsyncWrite for Connection {
+    fn poll_write(self: Pin<&mut Self>, cx: &mut Context<'_>, buf: &[u8]) -> Poll<Result<usize, IOError>> {
+        match &mut self.get_mut().stream {
+            Stream::Plain(s) => Pin::new(s).poll_write(cx, buf),
+            Stream::Tls(s) => Pin::new(s).poll_write(cx, buf),
+        }
+    }
+
+    fn poll_write_vectored(
+        self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        bufs: &[IoSlice<'_>],
+    ) -> Poll<Result<usize, IOError>> {
+        match &mut self.get_mut().stream {
+            Stream::Plain(s) => Pin::new(s).poll_write_vectored(cx, bufs),
+            Stream::Tls(s) => Pin::new(s).poll_write_vectored(cx, bufs),
+        }
+    }
+
+    fn is_write_vectored(&self) -> bool {
+        match &self.stream {
+            Stream::Plain(s) => s.is_write_vectored(),
+            Stream::Tls(s) => s.is_write_vectored(),
+        }
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IOError>> {
+        match &mut self.get_mut().stream {
+            Stream::Plain(s) => Pin::new(s).poll_flush(cx),
+            Stream::Tls(s) => Pin::new(s).poll_flush(cx),
+        }
+    }
+
+    fn poll_shutdown(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), IOError>> {
+        match &mut self.get_mut().stream {
+            Stream::Plain(s) => Pin::new(s).poll_shutdown(cx),
+            Stream::Tls(s) => Pin::new(s).poll_shutdown(cx),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::Incoming,
+        crate::net::{IpFilter, TlsMetrics},
+        futures::future::poll_fn,
+        hyper::server::accept::Accept,
+        pretty_assertions::assert_eq,
+        rcgen::{BasicConstraints, Certificate as RcgenCertificate, CertificateParams, DnType, IsCa},
+        rustls::{
+            server::AllowAnyAuthenticatedClient, Certificate, ClientConfig, PrivateKey, RootCertStore, ServerConfig,
+            ServerName,
+        },
+        std::{pin::Pin, sync::Arc, time::Duration},
+        tokio::{
+            io::AsyncReadExt,
+            net::{TcpListener, TcpStream},
+            time::timeout,
+        },
+        tokio_rustls::TlsConnector,
+    };
+
+    fn issue(common_name: &str, ca: Option<&RcgenCertificate>) -> (RcgenCertificate, Certificate, PrivateKey) {
+        let mut params = CertificateParams::new(vec![common_name.to_string()]);
+        params.distinguished_name.push(DnType::CommonName, common_name);
+        if ca.is_none() {
+            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        }
+        let cert = RcgenCertificate::from_params(params).unwrap();
+        let der = match ca {
+            None => cert.serialize_der().unwrap(),
+            Some(ca) => cert.serialize_der_with_signer(ca).unwrap(),
+        };
+        let key = PrivateKey(cert.serialize_private_key_der());
+        (cert, Certificate(der), key)
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_client_identity() {
+        let (ca, ca_cert, _) = issue("Scratchstack Test CA", None);
+        let (_, server_cert, server_key) = issue("localhost", Some(&ca));
+        let (_, client_cert, client_key) = issue("iam-client", Some(&ca));
+        let mut roots = RootCertStore::empty();
+        roots.add(&ca_cert).unwrap();
+
+        let server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots.clone()))
+            .with_single_cert(vec![server_cert], server_key)
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let metrics = Arc::new(TlsMetrics::new());
+        let mut incoming =
+            Incoming::new(listener, Some(server_config), IpFilter::default()).with_tls_metrics(metrics.clone());
+        let address = incoming.local_addr().unwrap();
+
+        let client_config = ClientConfig::builder()
+            .with_safe_defaults()
+            .with_root_certificates(roots)
+            .with_single_cert(vec![client_cert], client_key)
+            .unwrap();
+        let client = tokio::spawn(async move {
+            let stream = TcpStream::connect(address).await.unwrap();
+            let server_name = ServerName::try_from("localhost").unwrap();
+            TlsConnector::from(Arc::new(client_config)).connect(server_name, stream).await.unwrap()
+        });
+
+        let conn = poll_fn(|cx| Pin::new(&mut incoming).poll_accept(cx)).await.unwrap().unwrap();
+        let _client = client.await.unwrap();
+        assert!(conn.is_tls());
+        let identity = conn.client_identity().unwrap();
+        assert_eq!(identity.subject(), "CN=iam-client");
+        assert_eq!(identity.subject_alt_names()[0].to_string(), "DNS:iam-client");
+
+        let session = conn.tls_session().unwrap();
+        assert_eq!(session.protocol_version(), "TLSv1.3");
+        assert_eq!(session.server_name(), Some("localhost"));
+        assert!(session.client_auth());
+        assert_eq!(metrics.count("TLSv1.3"), 1);
+        assert_eq!(metrics.failures(), 0);
+        assert!(metrics.render().contains("scratchstack_tls_handshake_seconds_count 1\n"));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_ip_filter() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut incoming = Incoming::new(listener, None, IpFilter::default());
+        let stream = TcpStream::connect(incoming.local_addr().unwrap()).await.unwrap();
+        let conn = poll_fn(|cx| Pin::new(&mut incoming).poll_accept(cx)).await.unwrap().unwrap();
+        assert!(!conn.is_tls());
+        assert_eq!(conn.remote_addr(), stream.local_addr().unwrap());
+
+        // Denied connections are closed without being returned.
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let filter = IpFilter::new(vec![], vec!["127.0.0.0/8".parse().unwrap()]);
+        let mut incoming = Incoming::new(listener, None, filter);
+        let mut stream = TcpStream::connect(incoming.local_addr().unwrap()).await.unwrap();
+        let accept = poll_fn(|cx| Pin::new(&mut incoming).poll_accept(cx));
+        assert!(timeout(Duration::from_millis(200), accept).await.is_err());
+        assert_eq!(stream.read(&mut [0; 1]).await.unwrap_or(0), 0);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_handshake_limits() {
+        let (ca, ca_cert, _) = issue("Scratchstack Test CA", None);
+        let (_, server_cert, server_key) = issue("localhost", Some(&ca));
+        let mut roots = RootCertStore::empty();
+        roots.add(&ca_cert).unwrap();
+
+        let server_config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_no_client_auth()
+            .with_single_cert(vec![server_cert], server_key)
+            .unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let metrics = Arc::new(TlsMetrics::new());
+        let mut incoming = Incoming::new(listener, Some(server_config), IpFilter::default())
+            .with_handshake_timeout(Duration::from_millis(500))
+            .with_max_handshakes(1)
+            .with_tls_metrics(metrics.clone());
+        let address = incoming.local_addr().unwrap();
+
+        // A client that never sends a ClientHello takes the only handshake slot.
+        let mut silent = TcpStream::connect(address).await.unwrap();
+        let accept = poll_fn(|cx| Pin::new(&mut incoming).poll_accept(cx));
+        assert!(timeout(Duration::from_millis(100), accept).await.is_err());
+
+        let client_config =
+            ClientConfig::builder().with_safe_defaults().with_root_certificates(roots).with_no_client_auth();
+        let client = tokio::spawn(async move {
+            let stream = TcpStream::connect(address).await.unwrap();
+            let server_name = ServerName::try_from("localhost").unwrap();
+            TlsConnector::from(Arc::new(client_config)).connect(server_name, stream).await.unwrap()
+        });
+
+        // The second client waits in the backlog until the first handshake times out.
+        let conn = poll_fn(|cx| Pin::new(&mut incoming).poll_accept(cx)).await.unwrap().unwrap();
+        let _client = client.await.unwrap();
+        assert!(conn.is_tls());
+        assert_eq!(metrics.failures(), 1);
+        assert_eq!(metrics.count("TLSv1.3"), 1);
+
+        // The timed out connection was closed.
+        assert_eq!(silent.read(&mut [0; 1]).await.unwrap_or(0), 0);
+    }
+}
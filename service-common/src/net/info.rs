@@ -0,0 +1,161 @@
+use {
+    crate::net::{ClientIdentity, Connection, TlsSession, TrustedProxies},
+    http::Request,
+    std::{
+        future::Future,
+        net::{IpAddr, SocketAddr},
+        pin::Pin,
+        task::{Context, Poll},
+    },
+    tower::Service,
+};
+
+/// Details about the connection a request arrived on, inserted into the request extensions by
+/// [WithConnectionInfo].
+///
+/// For requests relayed by a [trusted proxy][TrustedProxies], the source address and security of the request are
+/// those reported by the proxy rather than those of the connection itself.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ConnectionInfo {
+    remote_addr: SocketAddr,
+    tls: bool,
+    source_ip: IpAddr,
+    secure: bool,
+}
+
+impl ConnectionInfo {
+    pub fn new(remote_addr: SocketAddr, tls: bool) -> Self {
+        Self {
+            remote_addr,
+            tls,
+            source_ip: remote_addr.ip(),
+            secure: tls,
+        }
+    }
+
+    /// The same connection, with the client address and protocol reported by a proxy.
+    pub fn with_forwarded(self, source_ip: IpAddr, secure: bool) -> Self {
+        Self {
+            source_ip,
+            secure,
+            ..self
+        }
+    }
+
+    /// The address of the peer, which may be a proxy.
+    pub fn remote_addr(&self) -> SocketAddr {
+        self.remote_addr
+    }
+
+    /// Indicates whether the connection from the peer uses TLS.
+    pub fn is_tls(&self) -> bool {
+        self.tls
+    }
+
+    /// The address of the client that made the request (`aws:SourceIp`).
+    pub fn source_ip(&self) -> IpAddr {
+        self.source_ip
+    }
+
+    /// Indicates whether the client used TLS (`aws:SecureTransport`), either to this endpoint or to a trusted proxy.
+    pub fn is_secure(&self) -> bool {
+        self.secure
+    }
+}
+
+impl From<&Connection> for ConnectionInfo {
+    fn from(conn: &Connection) -> Self {
+        Self::new(conn.remote_addr(), conn.is_tls())
+    }
+}
+
+/// Wraps a make-service (such as `SpawnService`) so each per-connection service adds a [ConnectionInfo] extension to
+/// the requests it handles, along with a [TlsSession] extension for TLS connections and a [ClientIdentity] extension
+/// if the client presented a certificate.
+#[derive(Clone, Debug)]
+pub struct WithConnectionInfo<M> {
+    inner: M,
+    proxies: TrustedProxies,
+}
+
+impl<M> WithConnectionInfo<M> {
+    pub fn new(inner: M) -> Self {
+        Self {
+            inner,
+            proxies: TrustedProxies::default(),
+        }
+    }
+
+    /// Believe the forwarding headers on requests from these proxies.
+    pub fn with_trusted_proxies(mut self, proxies: TrustedProxies) -> Self {
+        self.proxies = proxies;
+        self
+    }
+}
+
+impl<'a, M> Service<&'a Connection> for WithConnectionInfo<M>
+where
+    M: Service<&'a Connection>,
+    M::Future: Send + 'static,
+{
+    type Response = AddConnectionInfo<M::Response>;
+    type Error = M::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, conn: &'a Connection) -> Self::Future {
+        let info = ConnectionInfo::from(conn);
+        let client_identity = conn.client_identity().cloned();
+        let tls_session = conn.tls_session().cloned();
+        let proxies = self.proxies.clone();
+        let future = self.inner.call(conn);
+        Box::pin(async move {
+            Ok(AddConnectionInfo {
+                inner: future.await?,
+                info,
+                client_identity,
+                tls_session,
+                proxies,
+            })
+        })
+    }
+}
+
+/// A per-connection service that adds a [ConnectionInfo] extension, and [TlsSession] and [ClientIdentity] extensions
+/// if there are any, to each request.
+#[derive(Clone, Debug)]
+pub struct AddConnectionInfo<S> {
+    inner: S,
+    info: ConnectionInfo,
+    client_identity: Option<ClientIdentity>,
+    tls_session: Option<TlsSession>,
+    proxies: TrustedProxies,
+}
+
+impl<S, B> Service<Request<B>> for AddConnectionInfo<S>
+where
+    S: Service<Request<B>>,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = S::Future;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<B>) -> Self::Future {
+        let info = self.proxies.resolve(self.info, req.headers());
+        req.extensions_mut().insert(info);
+        if let Some(client_identity) = &self.client_identity {
+            req.extensions_mut().insert(client_identity.clone());
+        }
+        if let Some(tls_session) = &self.tls_session {
+            req.extensions_mut().insert(tls_session.clone());
+        }
+        self.inner.call(req)
+    }
+}
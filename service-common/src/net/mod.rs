@@ -0,0 +1,15 @@
+mod client;
+mod filter;
+mod forwarded;
+mod handshake;
+mod incoming;
+mod info;
+
+pub use self::{
+    client::ClientIdentity,
+    filter::IpFilter,
+    forwarded::{TrustedProxies, X_FORWARDED_FOR, X_FORWARDED_PROTO},
+    handshake::{TlsMetrics, TlsSession, HANDSHAKE_BUCKETS},
+    incoming::{Connection, Incoming, DEFAULT_HANDSHAKE_TIMEOUT, DEFAULT_MAX_HANDSHAKES},
+    info::{AddConnectionInfo, ConnectionInfo, WithConnectionInfo},
+};
@@ -0,0 +1,521 @@
+//! Verification of OpenID Connect identity tokens for `AssumeRoleWithWebIdentity`.
+//!
+//! Each service lists the OIDC providers it trusts in `[[service.<name>.oidc_providers]]`: the issuer, the audiences
+//! (client ids) accepted from it, and optionally where its JSON Web Key Set is published. If no JWKS URL is given,
+//! it is discovered from `<issuer>/.well-known/openid-configuration`. [OidcProviders::spawn_refresh] fetches the
+//! keys of every provider through the service's [OutboundClient] and refetches them periodically, so providers can
+//! rotate keys; until the first fetch succeeds, tokens from that provider fail with `IDPCommunicationError`.
+//!
+//! Tokens must be signed with `RS256` or `ES256`, and carry `iss`, `sub`, `aud` and `exp` claims. A configured
+//! provider is trusted by every account: trust policies name it as `arn:aws:iam::<account>:oidc-provider/<issuer>`,
+//! where `<issuer>` is the issuer URL without `https://`, as [OidcProviderConfig::name] returns.
+use {
+    crate::{
+        crypto::{verify_es256, verify_rs256},
+        outbound::OutboundClient,
+    },
+    chrono::{DateTime, TimeZone, Utc},
+    http::{Request, StatusCode},
+    hyper::body::{to_bytes, Bytes},
+    log::{debug, info, warn},
+    serde::Deserialize,
+    serde_json::Value,
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        sync::{Arc, RwLock},
+        time::Duration,
+    },
+    tokio::task::JoinHandle,
+};
+
+/// How soon a failed key fetch is retried.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The path, relative to the issuer, of the OpenID Connect discovery document.
+const DISCOVERY_PATH: &str = "/.well-known/openid-configuration";
+
+/// An OpenID Connect provider trusted for `AssumeRoleWithWebIdentity`.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct OidcProviderConfig {
+    /// The `iss` claim of tokens from this provider, e.g. `https://token.actions.githubusercontent.com`.
+    pub issuer: String,
+
+    /// The `aud` values accepted; a token must name at least one of them.
+    pub audiences: Vec<String>,
+
+    /// Where the provider publishes its signing keys. If absent, the `jwks_uri` of its discovery document is used.
+    #[serde(default)]
+    pub jwks_url: Option<String>,
+
+    /// How often the signing keys are refetched, in seconds.
+    #[serde(default = "OidcProviderConfig::default_refresh_seconds")]
+    pub refresh_seconds: u64,
+}
+
+impl OidcProviderConfig {
+    fn default_refresh_seconds() -> u64 {
+        3600
+    }
+
+    /// The issuer without its scheme or a trailing `/`, as it appears in the provider ARN and condition keys.
+    pub fn name(&self) -> &str {
+        self.issuer.strip_prefix("https://").unwrap_or(&self.issuer).trim_end_matches('/')
+    }
+}
+
+/// The caller of a verified identity token.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct WebIdentity {
+    /// The provider's [name][OidcProviderConfig::name].
+    pub provider: String,
+
+    /// The `sub` claim.
+    pub subject: String,
+
+    /// The configured audience the token was issued for.
+    pub audience: String,
+    pub expires_at: DateTime<Utc>,
+}
+
+impl WebIdentity {
+    /// The ARN of the provider in `account_id`, the caller that trust policies name in `Federated` principals.
+    pub fn provider_arn(&self, partition: &str, account_id: &str) -> String {
+        format!("arn:{partition}:iam::{account_id}:oidc-provider/{}", self.provider)
+    }
+
+    /// The condition keys trust policies can test, e.g. `token.actions.githubusercontent.com:sub`.
+    pub fn condition_keys(&self) -> Vec<(String, String)> {
+        vec![
+            (format!("{}:sub", self.provider), self.subject.clone()),
+            (format!("{}:aud", self.provider), self.audience.clone()),
+        ]
+    }
+}
+
+/// A public key from a JWKS document.
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct Jwk {
+    kid: Option<String>,
+    key: JwkKey,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+enum JwkKey {
+    Rsa {
+        n: Vec<u8>,
+        e: Vec<u8>,
+    },
+
+    /// A P-256 key, as the uncompressed point `0x04 || x || y`.
+    EcP256(Vec<u8>),
+}
+
+impl Jwk {
+    /// Parse a key, returning `None` for key types and curves that tokens are not verified with.
+    fn parse(value: &Value) -> Option<Self> {
+        if value.get("use").and_then(Value::as_str).unwrap_or("sig") != "sig" {
+            return None;
+        }
+
+        let field = |name: &str| value.get(name).and_then(Value::as_str).and_then(|v| decode_base64url(v).ok());
+        let key = match (value.get("kty").and_then(Value::as_str), value.get("crv").and_then(Value::as_str)) {
+            (Some("RSA"), _) => JwkKey::Rsa {
+                n: field("n")?,
+                e: field("e")?,
+            },
+            (Some("EC"), Some("P-256")) => {
+                let (x, y) = (field("x")?, field("y")?);
+                if x.len() != 32 || y.len() != 32 {
+                    return None;
+                }
+                JwkKey::EcP256([&[4u8][..], &x, &y].concat())
+            }
+            _ => return None,
+        };
+
+        Some(Self {
+            kid: value.get("kid").and_then(Value::as_str).map(ToString::to_string),
+            key,
+        })
+    }
+
+    fn verify(&self, alg: &str, message: &[u8], signature: &[u8]) -> bool {
+        match (&self.key, alg) {
+            (
+                JwkKey::Rsa {
+                    n,
+                    e,
+                },
+                "RS256",
+            ) => verify_rs256(n, e, message, signature),
+            (JwkKey::EcP256(point), "ES256") => verify_es256(point, message, signature),
+            _ => false,
+        }
+    }
+}
+
+/// Parse a JWKS document, keeping the keys tokens can be verified with.
+fn parse_jwks(document: &[u8]) -> Result<Vec<Jwk>, OidcError> {
+    let value: Value = serde_json::from_slice(document).map_err(|e| OidcError::Fetch(e.to_string()))?;
+    match value.get("keys") {
+        Some(Value::Array(keys)) => Ok(keys.iter().filter_map(Jwk::parse).collect()),
+        _ => Err(OidcError::Fetch("JWKS document has no keys".to_string())),
+    }
+}
+
+#[derive(Debug)]
+struct OidcProvider {
+    config: OidcProviderConfig,
+    keys: RwLock<Vec<Jwk>>,
+}
+
+/// The OIDC providers a service trusts, and their current signing keys.
+#[derive(Debug, Default)]
+pub struct OidcProviders {
+    providers: Vec<OidcProvider>,
+}
+
+impl OidcProviders {
+    pub fn new(configs: &[OidcProviderConfig]) -> Self {
+        Self {
+            providers: configs
+                .iter()
+                .map(|config| OidcProvider {
+                    config: config.clone(),
+                    keys: RwLock::new(Vec::new()),
+                })
+                .collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.providers.is_empty()
+    }
+
+    /// Replace the signing keys of the provider for `issuer` with those in the JWKS `document`, returning how many
+    /// usable keys it has.
+    pub fn set_keys(&self, issuer: &str, document: &[u8]) -> Result<usize, OidcError> {
+        let provider = self.provider(issuer).ok_or_else(|| OidcError::UnknownIssuer(issuer.to_string()))?;
+        let keys = parse_jwks(document)?;
+        let count = keys.len();
+        *provider.keys.write().expect("OIDC keys poisoned") = keys;
+        Ok(count)
+    }
+
+    fn provider(&self, issuer: &str) -> Option<&OidcProvider> {
+        self.providers.iter().find(|provider| provider.config.issuer == issuer)
+    }
+
+    /// Verify the signature and claims of the identity token `token` at `now`.
+    pub fn verify(&self, token: &str, now: DateTime<Utc>) -> Result<WebIdentity, OidcError> {
+        let mut segments = token.split('.');
+        let (header, payload, signature) = match (segments.next(), segments.next(), segments.next(), segments.next()) {
+            (Some(header), Some(payload), Some(signature), None) => (header, payload, signature),
+            _ => return Err(OidcError::Malformed("not a JSON Web Token".to_string())),
+        };
+        let signature = decode_base64url(signature)?;
+        let header = decode_json(header)?;
+        let claims = decode_json(payload)?;
+
+        let issuer = claims.get("iss").and_then(Value::as_str).ok_or(OidcError::MissingClaim("iss"))?;
+        let provider = self.provider(issuer).ok_or_else(|| OidcError::UnknownIssuer(issuer.to_string()))?;
+        let alg = header.get("alg").and_then(Value::as_str).unwrap_or_default();
+        if alg != "RS256" && alg != "ES256" {
+            return Err(OidcError::UnsupportedAlgorithm(alg.to_string()));
+        }
+
+        let signed = &token[..header_and_payload_len(token)];
+        let kid = header.get("kid").and_then(Value::as_str);
+        {
+            let keys = provider.keys.read().expect("OIDC keys poisoned");
+            if keys.is_empty() {
+                return Err(OidcError::NoKeys(issuer.to_string()));
+            }
+            let verified = keys
+                .iter()
+                .filter(|key| kid.is_none() || key.kid.as_deref() == kid)
+                .any(|key| key.verify(alg, signed.as_bytes(), &signature));
+            if !verified {
+                return Err(OidcError::SignatureMismatch);
+            }
+        }
+
+        let expires_at = timestamp_claim(&claims, "exp")?.ok_or(OidcError::MissingClaim("exp"))?;
+        if expires_at <= now {
+            return Err(OidcError::Expired);
+        }
+        if let Some(not_before) = timestamp_claim(&claims, "nbf")? {
+            if not_before > now {
+                return Err(OidcError::NotYetValid);
+            }
+        }
+
+        let subject = claims.get("sub").and_then(Value::as_str).ok_or(OidcError::MissingClaim("sub"))?;
+        let audiences = match claims.get("aud") {
+            Some(Value::String(audience)) => vec![audience.as_str()],
+            Some(Value::Array(audiences)) => audiences.iter().filter_map(Value::as_str).collect(),
+            _ => return Err(OidcError::MissingClaim("aud")),
+        };
+        let audience = audiences
+            .into_iter()
+            .find(|audience| provider.config.audiences.iter().any(|accepted| accepted == audience))
+            .ok_or(OidcError::IncorrectAudience)?;
+
+        Ok(WebIdentity {
+            provider: provider.config.name().to_string(),
+            subject: subject.to_string(),
+            audience: audience.to_string(),
+            expires_at,
+        })
+    }
+
+    /// Start fetching the signing keys of each provider with `client`, refetching them every
+    /// [refresh_seconds][OidcProviderConfig::refresh_seconds].
+    pub fn spawn_refresh(self: &Arc<Self>, client: OutboundClient) -> Vec<JoinHandle<()>> {
+        (0..self.providers.len())
+            .map(|index| {
+                let providers = self.clone();
+                let client = client.clone();
+                tokio::spawn(async move {
+                    let provider = &providers.providers[index];
+                    let refresh = Duration::from_secs(provider.config.refresh_seconds.max(1));
+                    loop {
+                        let delay = match fetch_keys(&client, &provider.config).await {
+                            Ok(keys) => {
+                                info!(
+                                    "Loaded {} signing key(s) for OIDC provider {}",
+                                    keys.len(),
+                                    provider.config.issuer
+                                );
+                                *provider.keys.write().expect("OIDC keys poisoned") = keys;
+                                refresh
+                            }
+                            Err(e) => {
+                                warn!(
+                                    "Unable to fetch signing keys for OIDC provider {}: {}",
+                                    provider.config.issuer, e
+                                );
+                                RETRY_INTERVAL.min(refresh)
+                            }
+                        };
+                        tokio::time::sleep(delay).await;
+                    }
+                })
+            })
+            .collect()
+    }
+}
+
+async fn fetch_keys(client: &OutboundClient, config: &OidcProviderConfig) -> Result<Vec<Jwk>, OidcError> {
+    let jwks_url = match &config.jwks_url {
+        Some(jwks_url) => jwks_url.clone(),
+        None => {
+            let discovery_url = format!("{}{DISCOVERY_PATH}", config.issuer.trim_end_matches('/'));
+            let document: Value = serde_json::from_slice(&get(client, &discovery_url).await?)
+                .map_err(|e| OidcError::Fetch(e.to_string()))?;
+            match document.get("jwks_uri").and_then(Value::as_str) {
+                Some(jwks_uri) => jwks_uri.to_string(),
+                None => return Err(OidcError::Fetch(format!("{discovery_url} has no jwks_uri"))),
+            }
+        }
+    };
+
+    debug!("Fetching OIDC signing keys from {}", jwks_url);
+    parse_jwks(&get(client, &jwks_url).await?)
+}
+
+async fn get(client: &OutboundClient, url: &str) -> Result<Bytes, OidcError> {
+    let request = Request::get(url).body(Bytes::new()).map_err(|e| OidcError::Fetch(e.to_string()))?;
+    let response = client.send(request).await.map_err(|e| OidcError::Fetch(e.to_string()))?;
+    if !response.status().is_success() {
+        return Err(OidcError::Fetch(format!("{url} returned {}", response.status())));
+    }
+    to_bytes(response.into_body()).await.map_err(|e| OidcError::Fetch(e.to_string()))
+}
+
+fn header_and_payload_len(token: &str) -> usize {
+    token.rfind('.').unwrap_or(token.len())
+}
+
+fn decode_base64url(value: &str) -> Result<Vec<u8>, OidcError> {
+    base64::decode_config(value, base64::URL_SAFE_NO_PAD)
+        .map_err(|_| OidcError::Malformed("invalid base64url".to_string()))
+}
+
+fn decode_json(segment: &str) -> Result<Value, OidcError> {
+    match serde_json::from_slice(&decode_base64url(segment)?) {
+        Ok(value @ Value::Object(_)) => Ok(value),
+        _ => Err(OidcError::Malformed("invalid JSON".to_string())),
+    }
+}
+
+fn timestamp_claim(claims: &Value, name: &'static str) -> Result<Option<DateTime<Utc>>, OidcError> {
+    match claims.get(name) {
+        None => Ok(None),
+        Some(value) => {
+            let seconds = value.as_i64().ok_or_else(|| OidcError::Malformed(format!("invalid {name} claim")))?;
+            match Utc.timestamp_opt(seconds, 0).single() {
+                Some(timestamp) => Ok(Some(timestamp)),
+                None => Err(OidcError::Malformed(format!("invalid {name} claim"))),
+            }
+        }
+    }
+}
+
+/// Why an identity token was not accepted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum OidcError {
+    Malformed(String),
+    MissingClaim(&'static str),
+
+    /// No provider is configured for the token's `iss`.
+    UnknownIssuer(String),
+    UnsupportedAlgorithm(String),
+
+    /// The provider's signing keys have not been fetched.
+    NoKeys(String),
+    SignatureMismatch,
+    Expired,
+    NotYetValid,
+
+    /// None of the token's audiences is accepted for its provider.
+    IncorrectAudience,
+
+    /// Fetching or parsing a discovery or JWKS document failed.
+    Fetch(String),
+}
+
+impl OidcError {
+    /// The AWS error code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Expired => "ExpiredTokenException",
+            Self::NoKeys(_) | Self::Fetch(_) => "IDPCommunicationError",
+            _ => "InvalidIdentityToken",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        StatusCode::BAD_REQUEST
+    }
+}
+
+impl Error for OidcError {}
+
+impl Display for OidcError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Malformed(e) => write!(f, "The web identity token is malformed: {e}"),
+            Self::MissingClaim(claim) => write!(f, "The web identity token has no {claim} claim"),
+            Self::UnknownIssuer(issuer) => write!(f, "No OpenIDConnect provider found in your account for {issuer}"),
+            Self::UnsupportedAlgorithm(alg) => write!(f, "The web identity token algorithm {alg} is not supported"),
+            Self::NoKeys(issuer) => write!(f, "The signing keys of {issuer} are not available"),
+            Self::SignatureMismatch => f.write_str("The web identity token signature could not be verified"),
+            Self::Expired => f.write_str("Token is expired"),
+            Self::NotYetValid => f.write_str("The web identity token is not yet valid"),
+            Self::IncorrectAudience => f.write_str("Incorrect token audience"),
+            Self::Fetch(e) => write!(f, "Unable to fetch OIDC signing keys: {e}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{OidcError, OidcProviderConfig, OidcProviders},
+        chrono::{DateTime, Duration, Utc},
+        pretty_assertions::assert_eq,
+        ring::{
+            rand::SystemRandom,
+            signature::{EcdsaKeyPair, KeyPair, ECDSA_P256_SHA256_FIXED_SIGNING},
+        },
+        serde_json::json,
+    };
+
+    const ISSUER: &str = "https://oidc.example.com";
+
+    /// An ES256 key pair and the JWKS document publishing its public key.
+    fn signing_key() -> (EcdsaKeyPair, String) {
+        let rng = SystemRandom::new();
+        let pkcs8 = EcdsaKeyPair::generate_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, &rng).unwrap();
+        let key = EcdsaKeyPair::from_pkcs8(&ECDSA_P256_SHA256_FIXED_SIGNING, pkcs8.as_ref()).unwrap();
+        let point = key.public_key().as_ref();
+        let jwks = json!({"keys": [{
+            "kty": "EC",
+            "crv": "P-256",
+            "kid": "key-1",
+            "x": base64::encode_config(&point[1..33], base64::URL_SAFE_NO_PAD),
+            "y": base64::encode_config(&point[33..], base64::URL_SAFE_NO_PAD),
+        }]});
+        (key, jwks.to_string())
+    }
+
+    /// A token with `claims`, signed with `key`.
+    fn sign_token(key: &EcdsaKeyPair, claims: serde_json::Value) -> String {
+        let encode = |value: serde_json::Value| base64::encode_config(value.to_string(), base64::URL_SAFE_NO_PAD);
+        let signed = format!("{}.{}", encode(json!({"alg": "ES256", "kid": "key-1"})), encode(claims));
+        let signature = key.sign(&SystemRandom::new(), signed.as_bytes()).unwrap();
+        format!("{signed}.{}", base64::encode_config(signature.as_ref(), base64::URL_SAFE_NO_PAD))
+    }
+
+    fn providers(jwks: &str) -> OidcProviders {
+        let providers = OidcProviders::new(&[OidcProviderConfig {
+            issuer: ISSUER.to_string(),
+            audiences: vec!["sts.amazonaws.com".to_string()],
+            jwks_url: None,
+            refresh_seconds: 3600,
+        }]);
+        providers.set_keys(ISSUER, jwks.as_bytes()).unwrap();
+        providers
+    }
+
+    fn now() -> DateTime<Utc> {
+        "2022-10-14T12:00:00Z".parse().unwrap()
+    }
+
+    #[test_log::test]
+    fn test_verify() {
+        let (key, jwks) = signing_key();
+        let providers = providers(&jwks);
+        let claims = |aud: serde_json::Value, exp: DateTime<Utc>| {
+            let subject = "system:serviceaccount:ci:deploy";
+            json!({"iss": ISSUER, "sub": subject, "aud": aud, "exp": exp.timestamp()})
+        };
+
+        let identity = providers
+            .verify(&sign_token(&key, claims(json!(["other", "sts.amazonaws.com"]), now() + Duration::hours(1))), now())
+            .unwrap();
+        assert_eq!(identity.provider, "oidc.example.com");
+        assert_eq!(identity.subject, "system:serviceaccount:ci:deploy");
+        assert_eq!(identity.audience, "sts.amazonaws.com");
+        assert_eq!(
+            identity.provider_arn("aws", "123456789012"),
+            "arn:aws:iam::123456789012:oidc-provider/oidc.example.com"
+        );
+
+        let expired = sign_token(&key, claims(json!("sts.amazonaws.com"), now() - Duration::minutes(1)));
+        assert_eq!(providers.verify(&expired, now()).unwrap_err(), OidcError::Expired);
+        let other_audience = sign_token(&key, claims(json!("other"), now() + Duration::hours(1)));
+        assert_eq!(providers.verify(&other_audience, now()).unwrap_err(), OidcError::IncorrectAudience);
+
+        // A token signed by another key, or with its claims changed, is rejected.
+        let (other_key, _) = signing_key();
+        let forged = sign_token(&other_key, claims(json!("sts.amazonaws.com"), now() + Duration::hours(1)));
+        assert_eq!(providers.verify(&forged, now()).unwrap_err(), OidcError::SignatureMismatch);
+        let mut segments: Vec<String> =
+            sign_token(&key, claims(json!("sts.amazonaws.com"), now() + Duration::hours(1)))
+                .split('.')
+                .map(ToString::to_string)
+                .collect();
+        segments[1] = base64::encode_config(
+            claims(json!("sts.amazonaws.com"), now() + Duration::days(365)).to_string(),
+            base64::URL_SAFE_NO_PAD,
+        );
+        assert_eq!(providers.verify(&segments.join("."), now()).unwrap_err(), OidcError::SignatureMismatch);
+
+        let unknown = OidcProviders::new(&[]);
+        assert_eq!(unknown.verify(&forged, now()).unwrap_err().code(), "InvalidIdentityToken");
+        assert!(matches!(providers.verify("not.a-token", now()), Err(OidcError::Malformed(_))));
+    }
+}
@@ -0,0 +1,186 @@
+//! Constraints that may be applied to fields in [operation_input!][crate::operation_input].
+//!
+//! Each constraint is checked only when the parameter is present; a missing required parameter is reported on its
+//! own.
+use {
+    super::ParameterViolation,
+    regex::Regex,
+    serde::Serialize,
+    std::{collections::HashMap, sync::Mutex},
+};
+
+/// A constraint as documented; see [crate::api_docs].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum ConstraintSpec {
+    Length {
+        min: usize,
+        max: usize,
+    },
+    Pattern {
+        pattern: &'static str,
+    },
+    OneOf {
+        values: &'static [&'static str],
+    },
+    Range {
+        min: i64,
+        max: i64,
+    },
+}
+
+/// The [ConstraintSpec] of each constraint, taking the same arguments, so inputs can describe their constraints.
+pub mod spec {
+    use super::ConstraintSpec;
+
+    pub const fn length(min: usize, max: usize) -> ConstraintSpec {
+        ConstraintSpec::Length {
+            min,
+            max,
+        }
+    }
+
+    pub const fn pattern(pattern: &'static str) -> ConstraintSpec {
+        ConstraintSpec::Pattern {
+            pattern,
+        }
+    }
+
+    pub const fn one_of(values: &'static [&'static str]) -> ConstraintSpec {
+        ConstraintSpec::OneOf {
+            values,
+        }
+    }
+
+    pub const fn range(min: i64, max: i64) -> ConstraintSpec {
+        ConstraintSpec::Range {
+            min,
+            max,
+        }
+    }
+}
+
+/// A field value that constraints can be checked against.
+pub trait ConstraintValue {
+    /// The value as a string, if present.
+    fn constraint_str(&self) -> Option<String>;
+
+    /// The value as an integer, if present and integral.
+    fn constraint_int(&self) -> Option<i64> {
+        None
+    }
+}
+
+impl ConstraintValue for String {
+    fn constraint_str(&self) -> Option<String> {
+        Some(self.clone())
+    }
+}
+
+impl<T: ConstraintValue> ConstraintValue for Option<T> {
+    fn constraint_str(&self) -> Option<String> {
+        self.as_ref().and_then(ConstraintValue::constraint_str)
+    }
+
+    fn constraint_int(&self) -> Option<i64> {
+        self.as_ref().and_then(ConstraintValue::constraint_int)
+    }
+}
+
+macro_rules! constraint_value_int {
+    ($($ty:ty),*) => {
+        $(
+            impl ConstraintValue for $ty {
+                fn constraint_str(&self) -> Option<String> {
+                    Some(self.to_string())
+                }
+
+                fn constraint_int(&self) -> Option<i64> {
+                    i64::try_from(*self).ok()
+                }
+            }
+        )*
+    };
+}
+
+constraint_value_int!(i32, i64, u32, u64);
+
+/// The value must be between `min` and `max` characters long, inclusive.
+pub fn length<T: ConstraintValue>(name: &str, value: &T, min: usize, max: usize) -> Result<(), ParameterViolation> {
+    let s = match value.constraint_str() {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+
+    let len = s.chars().count();
+    if len < min {
+        Err(ParameterViolation::new(name, Some(s), format!("have length greater than or equal to {min}")))
+    } else if len > max {
+        Err(ParameterViolation::new(name, Some(s), format!("have length less than or equal to {max}")))
+    } else {
+        Ok(())
+    }
+}
+
+/// The entire value must match the regular expression `pattern`.
+pub fn pattern<T: ConstraintValue>(name: &str, value: &T, pattern: &'static str) -> Result<(), ParameterViolation> {
+    let s = match value.constraint_str() {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+
+    if compiled(pattern).is_match(&s) {
+        Ok(())
+    } else {
+        Err(ParameterViolation::new(name, Some(s), format!("satisfy regular expression pattern: {pattern}")))
+    }
+}
+
+/// The value must be one of `allowed`.
+pub fn one_of<T: ConstraintValue>(
+    name: &str,
+    value: &T,
+    allowed: &'static [&'static str],
+) -> Result<(), ParameterViolation> {
+    let s = match value.constraint_str() {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+
+    if allowed.contains(&s.as_str()) {
+        Ok(())
+    } else {
+        Err(ParameterViolation::new(name, Some(s), format!("satisfy enum value set: [{}]", allowed.join(", "))))
+    }
+}
+
+/// The value must be an integer between `min` and `max`, inclusive.
+pub fn range<T: ConstraintValue>(name: &str, value: &T, min: i64, max: i64) -> Result<(), ParameterViolation> {
+    let s = match value.constraint_str() {
+        Some(s) => s,
+        None => return Ok(()),
+    };
+
+    match value.constraint_int() {
+        Some(n) if n < min => {
+            Err(ParameterViolation::new(name, Some(s), format!("have value greater than or equal to {min}")))
+        }
+        Some(n) if n > max => {
+            Err(ParameterViolation::new(name, Some(s), format!("have value less than or equal to {max}")))
+        }
+        Some(_) => Ok(()),
+        None => Err(ParameterViolation::new(name, Some(s), "be an integer")),
+    }
+}
+
+/// Compile `pattern`, anchored to match the entire value, caching the result since the same patterns are used on
+/// every request.
+fn compiled(pattern: &'static str) -> Regex {
+    static CACHE: Mutex<Option<HashMap<&'static str, Regex>>> = Mutex::new(None);
+    let mut cache = CACHE.lock().expect("regex cache poisoned");
+    cache
+        .get_or_insert_with(HashMap::new)
+        .entry(pattern)
+        .or_insert_with(|| Regex::new(&format!("^(?:{pattern})$")).expect("invalid constraint pattern"))
+        .clone()
+}
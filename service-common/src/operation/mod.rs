@@ -0,0 +1,542 @@
+use {
+    self::constraint::ConstraintSpec,
+    serde::Serialize,
+    std::{
+        collections::HashMap,
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+pub mod constraint;
+pub mod output;
+
+/// A typed operation input that can be decoded from query protocol parameters.
+///
+/// This is normally implemented with the [operation_input!][crate::operation_input] macro.
+pub trait FromParameters: Sized {
+    /// The names of the parameters recognized by this input.
+    const PARAMETER_NAMES: &'static [&'static str];
+
+    /// The parameters recognized by this input with their types and constraints, in the same order.
+    const PARAMETERS: &'static [ParameterSpec];
+
+    /// Decode the input, reporting every parameter that is missing or violates a constraint.
+    fn from_parameters(parameters: &HashMap<String, String>) -> Result<Self, ValidationError>;
+}
+
+/// A single input field that can be decoded from the named query protocol parameter.
+pub trait FromParameter: Sized {
+    /// The type of the parameter as documented: `string`, `integer`, `boolean` or `list`.
+    const KIND: &'static str = "string";
+
+    /// Whether requests must include the parameter.
+    const REQUIRED: bool = true;
+
+    fn from_parameter(parameters: &HashMap<String, String>, name: &str) -> Result<Self, ParameterError>;
+}
+
+/// Error returned when a query protocol parameter is missing or malformed.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ParameterError {
+    Missing(String),
+    Invalid {
+        name: String,
+        value: String,
+        expected: &'static str,
+    },
+}
+
+impl ParameterError {
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Missing(name) => name,
+            Self::Invalid {
+                name,
+                ..
+            } => name,
+        }
+    }
+}
+
+impl Error for ParameterError {}
+
+impl Display for ParameterError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Missing(name) => write!(f, "Missing required parameter: {name}"),
+            Self::Invalid {
+                name,
+                value,
+                expected,
+            } => write!(f, "Invalid value for parameter {name}: expected {expected}, got {value:?}"),
+        }
+    }
+}
+
+/// The documented shape of one parameter of an operation input; see [crate::api_docs].
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub struct ParameterSpec {
+    pub name: &'static str,
+
+    /// One of the [FromParameter::KIND] names.
+    #[serde(rename = "type")]
+    pub kind: &'static str,
+    pub required: bool,
+    pub constraints: &'static [ConstraintSpec],
+}
+
+/// A single constraint violated by a parameter value.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ParameterViolation {
+    name: String,
+    value: Option<String>,
+    constraint: String,
+}
+
+impl ParameterViolation {
+    /// Create a violation. `constraint` completes the sentence "Member must ...".
+    pub fn new<N: Into<String>, C: Into<String>>(name: N, value: Option<String>, constraint: C) -> Self {
+        Self {
+            name: name.into(),
+            value,
+            constraint: constraint.into(),
+        }
+    }
+
+    /// The name of the query parameter, e.g. `UserName`.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn value(&self) -> Option<&str> {
+        self.value.as_deref()
+    }
+
+    /// The field path AWS reports for the parameter: each component of the name in lower camel case, e.g.
+    /// `userName` or `tags.1.key`.
+    pub fn field_path(&self) -> String {
+        self.name
+            .split('.')
+            .map(|part| {
+                let mut chars = part.chars();
+                match chars.next() {
+                    Some(first) => first.to_lowercase().chain(chars).collect(),
+                    None => String::new(),
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(".")
+    }
+}
+
+impl Display for ParameterViolation {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match &self.value {
+            None => write!(
+                f,
+                "Value null at '{}' failed to satisfy constraint: Member must {}",
+                self.field_path(),
+                self.constraint
+            ),
+            Some(value) => write!(
+                f,
+                "Value '{value}' at '{}' failed to satisfy constraint: Member must {}",
+                self.field_path(),
+                self.constraint
+            ),
+        }
+    }
+}
+
+impl From<ParameterError> for ParameterViolation {
+    fn from(e: ParameterError) -> Self {
+        match e {
+            ParameterError::Missing(name) => Self::new(name, None, "not be null"),
+            ParameterError::Invalid {
+                name,
+                value,
+                expected,
+            } => Self::new(name, Some(value), format!("be {expected}")),
+        }
+    }
+}
+
+/// All of the parameter violations found while decoding an operation input. This is reported to the caller as a
+/// `ValidationError`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ValidationError {
+    violations: Vec<ParameterViolation>,
+}
+
+impl ValidationError {
+    pub fn new(violations: Vec<ParameterViolation>) -> Self {
+        Self {
+            violations,
+        }
+    }
+
+    /// The AWS error code for this error.
+    pub fn code(&self) -> &'static str {
+        "ValidationError"
+    }
+
+    pub fn violations(&self) -> &[ParameterViolation] {
+        &self.violations
+    }
+}
+
+impl Error for ValidationError {}
+
+impl Display for ValidationError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self.violations.len() {
+            1 => f.write_str("1 validation error detected: ")?,
+            n => write!(f, "{n} validation errors detected: ")?,
+        }
+
+        for (i, violation) in self.violations.iter().enumerate() {
+            if i > 0 {
+                f.write_str("; ")?;
+            }
+            write!(f, "{violation}")?;
+        }
+
+        Ok(())
+    }
+}
+
+impl From<ParameterViolation> for ValidationError {
+    fn from(violation: ParameterViolation) -> Self {
+        Self::new(vec![violation])
+    }
+}
+
+impl FromParameter for String {
+    fn from_parameter(parameters: &HashMap<String, String>, name: &str) -> Result<Self, ParameterError> {
+        parameters.get(name).cloned().ok_or_else(|| ParameterError::Missing(name.to_string()))
+    }
+}
+
+impl FromParameter for Option<String> {
+    const REQUIRED: bool = false;
+
+    fn from_parameter(parameters: &HashMap<String, String>, name: &str) -> Result<Self, ParameterError> {
+        Ok(parameters.get(name).cloned())
+    }
+}
+
+/// A query protocol list of strings, `Name.member.N`, numbered from 1. Omitting it gives an empty list.
+impl FromParameter for Vec<String> {
+    const KIND: &'static str = "list";
+    const REQUIRED: bool = false;
+
+    fn from_parameter(parameters: &HashMap<String, String>, name: &str) -> Result<Self, ParameterError> {
+        Ok((1..).map_while(|n| parameters.get(&format!("{name}.member.{n}")).cloned()).collect())
+    }
+}
+
+impl FromParameter for bool {
+    const KIND: &'static str = "boolean";
+
+    fn from_parameter(parameters: &HashMap<String, String>, name: &str) -> Result<Self, ParameterError> {
+        match <Option<bool>>::from_parameter(parameters, name)? {
+            Some(value) => Ok(value),
+            None => Err(ParameterError::Missing(name.to_string())),
+        }
+    }
+}
+
+impl FromParameter for Option<bool> {
+    const KIND: &'static str = "boolean";
+    const REQUIRED: bool = false;
+
+    fn from_parameter(parameters: &HashMap<String, String>, name: &str) -> Result<Self, ParameterError> {
+        match parameters.get(name).map(String::as_str) {
+            None => Ok(None),
+            Some("true") => Ok(Some(true)),
+            Some("false") => Ok(Some(false)),
+            Some(value) => Err(ParameterError::Invalid {
+                name: name.to_string(),
+                value: value.to_string(),
+                expected: "true or false",
+            }),
+        }
+    }
+}
+
+macro_rules! from_parameter_int {
+    ($($ty:ty),*) => {
+        $(
+            impl FromParameter for $ty {
+                const KIND: &'static str = "integer";
+
+                fn from_parameter(parameters: &HashMap<String, String>, name: &str) -> Result<Self, ParameterError> {
+                    match <Option<$ty>>::from_parameter(parameters, name)? {
+                        Some(value) => Ok(value),
+                        None => Err(ParameterError::Missing(name.to_string())),
+                    }
+                }
+            }
+
+            impl FromParameter for Option<$ty> {
+                const KIND: &'static str = "integer";
+                const REQUIRED: bool = false;
+
+                fn from_parameter(parameters: &HashMap<String, String>, name: &str) -> Result<Self, ParameterError> {
+                    match parameters.get(name) {
+                        None => Ok(None),
+                        Some(value) => match value.parse::<$ty>() {
+                            Ok(value) => Ok(Some(value)),
+                            Err(_) => Err(ParameterError::Invalid {
+                                name: name.to_string(),
+                                value: value.to_string(),
+                                expected: "an integer",
+                            }),
+                        },
+                    }
+                }
+            }
+        )*
+    };
+}
+
+from_parameter_int!(i32, i64, u32, u64);
+
+/// Define an operation input structure along with its [FromParameters] implementation.
+///
+/// Each field is declared with the name of the query parameter it is read from. Fields of type `Option<T>` are
+/// optional; all others are required. Constraints from the [constraint] module may follow a `where`; they are only
+/// checked when a value is present. All violations are collected into a single [ValidationError]. The fields and
+/// their constraints are also described in [FromParameters::PARAMETERS].
+///
+/// ```
+/// scratchstack_service_common::operation_input! {
+///     /// Input for the CreateUser operation.
+///     pub struct CreateUserInput {
+///         "UserName" => pub user_name: String where length(1, 64), pattern(r"[\w+=,.@-]+"),
+///         "Path" => pub path: Option<String> where length(1, 512),
+///     }
+/// }
+/// ```
+#[macro_export]
+macro_rules! operation_input {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident {
+            $(
+                $(#[$field_meta:meta])*
+                $param:literal => $field_vis:vis $field:ident: $ty:ty $(where $($constraint:ident($($arg:expr),*)),+)?
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Debug)]
+        $vis struct $name {
+            $(
+                $(#[$field_meta])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        impl $crate::operation::FromParameters for $name {
+            const PARAMETER_NAMES: &'static [&'static str] = &[$($param),*];
+            const PARAMETERS: &'static [$crate::operation::ParameterSpec] = &[$(
+                $crate::operation::ParameterSpec {
+                    name: $param,
+                    kind: <$ty as $crate::operation::FromParameter>::KIND,
+                    required: <$ty as $crate::operation::FromParameter>::REQUIRED,
+                    constraints: &[$($($crate::operation::constraint::spec::$constraint($($arg),*)),+)?],
+                }
+            ),*];
+
+            #[allow(unused_variables)]
+            fn from_parameters(
+                parameters: &::std::collections::HashMap<::std::string::String, ::std::string::String>,
+            ) -> ::std::result::Result<Self, $crate::operation::ValidationError> {
+                let mut violations: ::std::vec::Vec<$crate::operation::ParameterViolation> = ::std::vec::Vec::new();
+
+                $(
+                    let $field = match <$ty as $crate::operation::FromParameter>::from_parameter(parameters, $param) {
+                        Ok(value) => {
+                            $($(
+                                if let Err(e) = $crate::operation::constraint::$constraint($param, &value, $($arg),*) {
+                                    violations.push(e);
+                                }
+                            )+)?
+                            Some(value)
+                        }
+                        Err(e) => {
+                            violations.push(e.into());
+                            None
+                        }
+                    };
+                )*
+
+                if !violations.is_empty() {
+                    return Err($crate::operation::ValidationError::new(violations));
+                }
+
+                Ok(Self {
+                    $(
+                        $field: $field.expect("value is present when there are no violations"),
+                    )*
+                })
+            }
+        }
+    };
+}
+
+/// Define a service's dispatcher and its [ApiDocs][crate::api_docs::ApiDocs] from one table of operations.
+///
+/// Each entry names the [action][crate::actions] it implements, the [operation_input!][crate::operation_input]
+/// type its parameters are decoded into, if it takes any, and the call that runs it. Calls may use the arguments of
+/// the dispatcher. The dispatcher returns `None` for actions not in the table, leaving the service to report
+/// `InvalidAction`; the API docs list the entries in the order given.
+///
+/// ```
+/// use scratchstack_service_common::{actions, users::DeleteUserInput};
+///
+/// async fn delete_user(user_name: &str) -> String {
+///     format!("Deleted {user_name}")
+/// }
+///
+/// scratchstack_service_common::operations! {
+///     service: "iam", version: "2010-05-08";
+///
+///     /// Run the operation for `action`.
+///     async fn dispatch(user_name: &str) -> String;
+///
+///     /// The operations dispatched by [dispatch].
+///     fn api_docs();
+///
+///     actions::DELETE_USER: DeleteUserInput => delete_user(user_name),
+///     actions::GET_IAM_API_DOCS => async { api_docs().to_json() },
+/// }
+///
+/// let response = futures::executor::block_on(dispatch("DeleteUser", "Alice"));
+/// assert_eq!(response.as_deref(), Some("Deleted Alice"));
+/// assert_eq!(futures::executor::block_on(dispatch("DeleteRole", "Alice")), None);
+/// assert_eq!(api_docs().operations.len(), 2);
+/// ```
+#[macro_export]
+macro_rules! operations {
+    (@doc $action:path, $input:ty) => {
+        $crate::api_docs::OperationDoc::new::<$input>(&$action)
+    };
+
+    (@doc $action:path) => {
+        $crate::api_docs::OperationDoc::without_input(&$action)
+    };
+
+    (
+        service: $service:literal, version: $version:expr;
+
+        $(#[$dispatch_meta:meta])*
+        $dispatch_vis:vis async fn $dispatch:ident($($arg:ident: $arg_ty:ty),* $(,)?) -> $ret:ty;
+
+        $(#[$docs_meta:meta])*
+        $docs_vis:vis fn $docs:ident();
+
+        $(
+            $action:path $(: $input:ty)? => $call:expr
+        ),* $(,)?
+    ) => {
+        $(#[$dispatch_meta])*
+        $dispatch_vis async fn $dispatch(action: &str, $($arg: $arg_ty),*) -> ::std::option::Option<$ret> {
+            $(
+                if action == $action.action_name {
+                    return ::std::option::Option::Some($call.await);
+                }
+            )*
+            ::std::option::Option::None
+        }
+
+        $(#[$docs_meta])*
+        $docs_vis fn $docs() -> $crate::api_docs::ApiDocs {
+            $crate::api_docs::ApiDocs::new($service, $version)
+                $(.with_operation($crate::operations!(@doc $action $(, $input)?)))*
+        }
+    };
+}
+
+/// Query protocol parameters from `(name, value)` pairs, for tests that decode operation inputs.
+#[cfg(test)]
+pub(crate) fn parameters(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+    pairs.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{constraint::ConstraintSpec, parameters, FromParameters, ParameterViolation},
+        pretty_assertions::assert_eq,
+    };
+
+    crate::operation_input! {
+        struct TestInput {
+            "UserName" => user_name: String where length(1, 8), pattern(r"[a-z]+"),
+            "Path" => path: Option<String>,
+            "MaxItems" => max_items: Option<u32> where range(1, 1000),
+            "Status" => status: Option<String> where one_of(&["Active", "Inactive"]),
+        }
+    }
+
+    #[test_log::test]
+    fn test_operation_input() {
+        assert_eq!(TestInput::PARAMETER_NAMES, &["UserName", "Path", "MaxItems", "Status"]);
+        let user_name = &TestInput::PARAMETERS[0];
+        assert_eq!((user_name.name, user_name.kind, user_name.required), ("UserName", "string", true));
+        assert_eq!(
+            user_name.constraints,
+            &[
+                ConstraintSpec::Length {
+                    min: 1,
+                    max: 8
+                },
+                ConstraintSpec::Pattern {
+                    pattern: "[a-z]+"
+                }
+            ]
+        );
+        let max_items = &TestInput::PARAMETERS[2];
+        assert_eq!((max_items.kind, max_items.required), ("integer", false));
+
+        let input = TestInput::from_parameters(&parameters(&[("UserName", "alice"), ("MaxItems", "10")])).unwrap();
+        assert_eq!(input.user_name, "alice");
+        assert_eq!(input.path, None);
+        assert_eq!(input.max_items, Some(10));
+
+        let e = TestInput::from_parameters(&parameters(&[("Path", "/")])).unwrap_err();
+        assert_eq!(e.violations(), &[ParameterViolation::new("UserName", None, "not be null")]);
+        assert_eq!(
+            e.to_string(),
+            "1 validation error detected: Value null at 'userName' failed to satisfy constraint: Member must not be null"
+        );
+
+        let e = TestInput::from_parameters(&parameters(&[("UserName", "alice"), ("MaxItems", "ten")])).unwrap_err();
+        assert_eq!(e.violations()[0].name(), "MaxItems");
+    }
+
+    #[test_log::test]
+    fn test_multiple_violations() {
+        let e = TestInput::from_parameters(&parameters(&[
+            ("UserName", "Alice-Liddell"),
+            ("MaxItems", "0"),
+            ("Status", "Deleted"),
+        ]))
+        .unwrap_err();
+
+        assert_eq!(e.code(), "ValidationError");
+        assert_eq!(
+            e.to_string(),
+            "4 validation errors detected: \
+             Value 'Alice-Liddell' at 'userName' failed to satisfy constraint: Member must have length less than or equal to 8; \
+             Value 'Alice-Liddell' at 'userName' failed to satisfy constraint: Member must satisfy regular expression pattern: [a-z]+; \
+             Value '0' at 'maxItems' failed to satisfy constraint: Member must have value greater than or equal to 1; \
+             Value 'Deleted' at 'status' failed to satisfy constraint: Member must satisfy enum value set: [Active, Inactive]"
+        );
+
+        assert_eq!(ParameterViolation::new("Tags.1.Key", None, "not be null").field_path(), "tags.1.key");
+    }
+}
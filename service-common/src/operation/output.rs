@@ -0,0 +1,253 @@
+//! Query protocol results: the response side of [operation_input!][crate::operation_input].
+//!
+//! A successful response is a `<{Operation}Response>` element holding the operation's `<{Operation}Result>`, if it
+//! returns anything, and a `<ResponseMetadata>` with the request id. [query_response] and [empty_query_response]
+//! write that envelope, so operations only describe their result. Results and the elements nested in them are
+//! declared with [operation_output!][crate::operation_output], which writes each field as a child element in the
+//! order given; the order must match AWS, as some SDKs depend on it.
+use {
+    crate::protocol::escape_xml,
+    chrono::{DateTime, SecondsFormat, Utc},
+};
+
+/// A value written as the content of an XML element.
+pub trait ToXml {
+    /// Append the content of the element, escaped text or child elements, to `xml`.
+    fn write_xml(&self, xml: &mut String);
+
+    /// Whether the element is written at all. Absent optional values are omitted.
+    fn is_present(&self) -> bool {
+        true
+    }
+}
+
+impl ToXml for str {
+    fn write_xml(&self, xml: &mut String) {
+        xml.push_str(&escape_xml(self));
+    }
+}
+
+impl ToXml for String {
+    fn write_xml(&self, xml: &mut String) {
+        self.as_str().write_xml(xml);
+    }
+}
+
+impl<T: ToXml + ?Sized> ToXml for &T {
+    fn write_xml(&self, xml: &mut String) {
+        (**self).write_xml(xml);
+    }
+
+    fn is_present(&self) -> bool {
+        (**self).is_present()
+    }
+}
+
+impl ToXml for bool {
+    fn write_xml(&self, xml: &mut String) {
+        xml.push_str(if *self {
+            "true"
+        } else {
+            "false"
+        });
+    }
+}
+
+macro_rules! to_xml_int {
+    ($($ty:ty),*) => {
+        $(
+            impl ToXml for $ty {
+                fn write_xml(&self, xml: &mut String) {
+                    xml.push_str(&self.to_string());
+                }
+            }
+        )*
+    };
+}
+
+to_xml_int!(i32, i64, u32, u64, usize);
+
+/// Timestamps are written in ISO 8601 format to the second, as AWS does.
+impl ToXml for DateTime<Utc> {
+    fn write_xml(&self, xml: &mut String) {
+        xml.push_str(&self.to_rfc3339_opts(SecondsFormat::Secs, true));
+    }
+}
+
+impl<T: ToXml> ToXml for Option<T> {
+    fn write_xml(&self, xml: &mut String) {
+        if let Some(value) = self {
+            value.write_xml(xml);
+        }
+    }
+
+    fn is_present(&self) -> bool {
+        self.as_ref().map(ToXml::is_present).unwrap_or(false)
+    }
+}
+
+/// Lists are written as one `<member>` element per item.
+impl<T: ToXml> ToXml for [T] {
+    fn write_xml(&self, xml: &mut String) {
+        for item in self {
+            write_element(xml, "member", item);
+        }
+    }
+}
+
+impl<T: ToXml> ToXml for Vec<T> {
+    fn write_xml(&self, xml: &mut String) {
+        self.as_slice().write_xml(xml);
+    }
+}
+
+/// Append `value` to `xml` as the element `name`, unless it is absent.
+pub fn write_element<T: ToXml + ?Sized>(xml: &mut String, name: &str, value: &T) {
+    if !value.is_present() {
+        return;
+    }
+
+    xml.push('<');
+    xml.push_str(name);
+    xml.push('>');
+    value.write_xml(xml);
+    xml.push_str("</");
+    xml.push_str(name);
+    xml.push('>');
+}
+
+/// The body of a successful response to `operation`, e.g. `CreateUser`, in the namespace `xml_ns`.
+pub fn query_response<T: ToXml + ?Sized>(xml_ns: &str, operation: &str, result: &T, request_id: &str) -> String {
+    let mut xml = format!("<{operation}Response xmlns=\"{xml_ns}\">");
+    write_element(&mut xml, &format!("{operation}Result"), result);
+    write_response_metadata(&mut xml, operation, request_id);
+    xml
+}
+
+/// The body of a successful response to `operation` that returns nothing, such as `DeleteUser`.
+pub fn empty_query_response(xml_ns: &str, operation: &str, request_id: &str) -> String {
+    let mut xml = format!("<{operation}Response xmlns=\"{xml_ns}\">");
+    write_response_metadata(&mut xml, operation, request_id);
+    xml
+}
+
+/// The `<ResponseMetadata>` and the end of the `<{operation}Response>` element.
+fn write_response_metadata(xml: &mut String, operation: &str, request_id: &str) {
+    xml.push_str("<ResponseMetadata>");
+    write_element(xml, "RequestId", request_id);
+    xml.push_str("</ResponseMetadata></");
+    xml.push_str(operation);
+    xml.push_str("Response>");
+}
+
+/// Define an operation result, or an element nested in one, along with its [ToXml] implementation.
+///
+/// Each field is declared with the name of the child element it is written as. Fields are written in the order
+/// given. `Option` fields are omitted when they are `None`, and `Vec` fields are written as a list of `<member>`
+/// elements. A lifetime parameter lets a result borrow from the values it describes. Unlike
+/// [operation_input!][crate::operation_input], no traits are derived; results holding secrets should not derive
+/// `Debug`.
+///
+/// ```
+/// use scratchstack_service_common::operation::output::query_response;
+///
+/// scratchstack_service_common::operation_output! {
+///     /// The result of the GetCallerIdentity operation.
+///     pub struct GetCallerIdentityOutput {
+///         "Arn" => pub arn: String,
+///         "UserId" => pub user_id: String,
+///         "Account" => pub account: String,
+///     }
+/// }
+///
+/// let output = GetCallerIdentityOutput {
+///     arn: "arn:aws:iam::123456789012:user/Alice".to_string(),
+///     user_id: "AIDAEXAMPLEUSER1".to_string(),
+///     account: "123456789012".to_string(),
+/// };
+/// let xml = query_response("https://sts.amazonaws.com/doc/2011-06-15/", "GetCallerIdentity", &output, "1234");
+/// assert!(xml.contains("<GetCallerIdentityResult><Arn>arn:aws:iam::123456789012:user/Alice</Arn><UserId>"));
+/// ```
+#[macro_export]
+macro_rules! operation_output {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident $(<$lt:lifetime>)? {
+            $(
+                $(#[$field_meta:meta])*
+                $element:literal => $field_vis:vis $field:ident: $ty:ty
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        $vis struct $name $(<$lt>)? {
+            $(
+                $(#[$field_meta])*
+                $field_vis $field: $ty,
+            )*
+        }
+
+        impl $(<$lt>)? $crate::operation::output::ToXml for $name $(<$lt>)? {
+            #[allow(unused_variables)]
+            fn write_xml(&self, xml: &mut ::std::string::String) {
+                $(
+                    $crate::operation::output::write_element(xml, $element, &self.$field);
+                )*
+            }
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{empty_query_response, query_response},
+        chrono::{DateTime, TimeZone, Utc},
+        pretty_assertions::assert_eq,
+    };
+
+    crate::operation_output! {
+        struct TestTag {
+            "Key" => key: String,
+            "Value" => value: String,
+        }
+    }
+
+    crate::operation_output! {
+        struct TestOutput {
+            "UserName" => user_name: String,
+            "Enabled" => enabled: bool,
+            "CreateDate" => create_date: DateTime<Utc>,
+            "Description" => description: Option<String>,
+            "Tags" => tags: Vec<TestTag>,
+            "Marker" => marker: Option<&'static str>,
+        }
+    }
+
+    #[test_log::test]
+    fn test_query_response() {
+        let output = TestOutput {
+            user_name: "Alice & Bob".to_string(),
+            enabled: true,
+            create_date: Utc.with_ymd_and_hms(2022, 10, 14, 1, 2, 3).unwrap(),
+            description: None,
+            tags: vec![TestTag {
+                key: "team".to_string(),
+                value: "<core>".to_string(),
+            }],
+            marker: Some("bob"),
+        };
+        assert_eq!(
+            query_response("urn:test", "Test", &output, "1234"),
+            "<TestResponse xmlns=\"urn:test\"><TestResult><UserName>Alice &amp; Bob</UserName><Enabled>true</Enabled>\
+             <CreateDate>2022-10-14T01:02:03Z</CreateDate><Tags><member><Key>team</Key><Value>&lt;core&gt;</Value>\
+             </member></Tags><Marker>bob</Marker></TestResult><ResponseMetadata><RequestId>1234</RequestId>\
+             </ResponseMetadata></TestResponse>"
+        );
+        assert_eq!(
+            empty_query_response("urn:test", "DeleteTest", "1234"),
+            "<DeleteTestResponse xmlns=\"urn:test\"><ResponseMetadata><RequestId>1234</RequestId></ResponseMetadata>\
+             </DeleteTestResponse>"
+        );
+    }
+}
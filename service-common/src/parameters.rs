@@ -0,0 +1,181 @@
+//! Query protocol request parameters.
+//!
+//! GET and HEAD requests carry their parameters in the query string only and must have an empty body; AWS CLIs
+//! use GET for some read-only actions. Other requests may also carry parameters in an
+//! `application/x-www-form-urlencoded` body, whose values take precedence over the query string. In either location,
+//! a parameter given more than once is rejected rather than silently resolved to one of its values. Names are
+//! case-sensitive, so `Action` and `action` are different parameters.
+//!
+//! As in SigV4 canonicalization, `+` decodes to a space only in form bodies; in a query string it is a literal `+`,
+//! and a space must be sent as `%20`.
+use {
+    http::Method,
+    percent_encoding::percent_decode,
+    std::{
+        collections::HashMap,
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum ParameterError {
+    /// A GET or HEAD request had a body.
+    BodyNotAllowed(Method),
+
+    /// The named parameter appeared more than once in the query string.
+    DuplicateQueryParameter(String),
+
+    /// The named parameter appeared more than once in the request body.
+    DuplicateBodyParameter(String),
+}
+
+impl Error for ParameterError {}
+
+impl Display for ParameterError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::BodyNotAllowed(method) => write!(f, "{method} requests must not have a body"),
+            Self::DuplicateQueryParameter(name) => {
+                write!(f, "Parameter {name} is specified more than once in the query string")
+            }
+            Self::DuplicateBodyParameter(name) => {
+                write!(f, "Parameter {name} is specified more than once in the request body")
+            }
+        }
+    }
+}
+
+/// Whether requests with this method take parameters from the query string only.
+pub fn is_query_only(method: &Method) -> bool {
+    method == Method::GET || method == Method::HEAD
+}
+
+/// Collect the parameters of a request. `form_body` is the request body if it is form-encoded; for GET and HEAD
+/// requests it is the body whatever its content type, and must be empty.
+pub fn request_parameters(
+    method: &Method,
+    query: &str,
+    form_body: Option<&[u8]>,
+) -> Result<HashMap<String, String>, ParameterError> {
+    let mut parameters = parse_query(query).map_err(ParameterError::DuplicateQueryParameter)?;
+
+    match form_body {
+        Some(body) if is_query_only(method) => {
+            if !body.is_empty() {
+                return Err(ParameterError::BodyNotAllowed(method.clone()));
+            }
+        }
+        Some(body) => {
+            let body_parameters = parse(body).map_err(ParameterError::DuplicateBodyParameter)?;
+            parameters.extend(body_parameters);
+        }
+        None => (),
+    }
+
+    Ok(parameters)
+}
+
+/// Parse form-encoded parameters, returning the name of the first one that is repeated.
+fn parse(input: &[u8]) -> Result<HashMap<String, String>, String> {
+    insert_all(form_urlencoded::parse(input).map(|(key, value)| (key.into_owned(), value.into_owned())))
+}
+
+/// Parse a query string. This differs from [parse] only in leaving `+` undecoded.
+fn parse_query(query: &str) -> Result<HashMap<String, String>, String> {
+    let decode = |s: &str| percent_decode(s.as_bytes()).decode_utf8_lossy().into_owned();
+    insert_all(query.split('&').filter(|pair| !pair.is_empty()).map(|pair| match pair.split_once('=') {
+        Some((key, value)) => (decode(key), decode(value)),
+        None => (decode(pair), String::new()),
+    }))
+}
+
+fn insert_all<I: Iterator<Item = (String, String)>>(pairs: I) -> Result<HashMap<String, String>, String> {
+    let mut parameters = HashMap::new();
+    for (key, value) in pairs {
+        if parameters.contains_key(&key) {
+            return Err(key);
+        }
+        parameters.insert(key, value);
+    }
+
+    Ok(parameters)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{request_parameters, ParameterError},
+        http::Method,
+        pretty_assertions::assert_eq,
+    };
+
+    #[test_log::test]
+    fn test_query_parameters() {
+        let parameters =
+            request_parameters(&Method::GET, "Action=GetCallerIdentity&Version=2011-06-15", Some(b"")).unwrap();
+        assert_eq!(parameters.get("Action").unwrap(), "GetCallerIdentity");
+        assert_eq!(parameters.get("Version").unwrap(), "2011-06-15");
+
+        assert_eq!(
+            request_parameters(&Method::GET, "Action=GetCallerIdentity", Some(b"Version=2011-06-15")).unwrap_err(),
+            ParameterError::BodyNotAllowed(Method::GET)
+        );
+        assert_eq!(
+            request_parameters(&Method::GET, "Action=GetCallerIdentity&Action=GetDeploymentInfo", None).unwrap_err(),
+            ParameterError::DuplicateQueryParameter("Action".to_string())
+        );
+    }
+
+    #[test_log::test]
+    fn test_body_parameters() {
+        let parameters = request_parameters(
+            &Method::POST,
+            "Action=GetDeploymentInfo&Version=2011-06-15",
+            Some(b"Action=GetCallerIdentity"),
+        )
+        .unwrap();
+        assert_eq!(parameters.get("Action").unwrap(), "GetCallerIdentity");
+        assert_eq!(parameters.get("Version").unwrap(), "2011-06-15");
+
+        assert_eq!(
+            request_parameters(&Method::POST, "", Some(b"Action=GetCallerIdentity&Action=GetCallerIdentity"))
+                .unwrap_err(),
+            ParameterError::DuplicateBodyParameter("Action".to_string())
+        );
+    }
+
+    #[test_log::test]
+    fn test_plus_and_case() {
+        // (method, query, body, parameter, expected value)
+        let vectors: &[(Method, &str, &[u8], &str, &str)] = &[
+            (Method::GET, "RoleSessionName=a+b", b"", "RoleSessionName", "a+b"),
+            (Method::GET, "RoleSessionName=a%2Bb", b"", "RoleSessionName", "a+b"),
+            (Method::GET, "RoleSessionName=a%20b", b"", "RoleSessionName", "a b"),
+            (Method::GET, "Role+Name=x", b"", "Role+Name", "x"),
+            (Method::GET, "Flag", b"", "Flag", ""),
+            (Method::POST, "", b"RoleSessionName=a+b", "RoleSessionName", "a b"),
+            (Method::POST, "", b"RoleSessionName=a%2Bb", "RoleSessionName", "a+b"),
+            (Method::POST, "", b"RoleSessionName=a%20b", "RoleSessionName", "a b"),
+            (Method::POST, "RoleSessionName=a+b", b"", "RoleSessionName", "a+b"),
+        ];
+
+        for (method, query, body, name, expected) in vectors {
+            let parameters = request_parameters(method, query, Some(*body)).unwrap();
+            assert_eq!(parameters.get(*name).map(String::as_str), Some(*expected), "{method} {query:?} {body:?}");
+        }
+
+        // Names that differ only in case are distinct, in either location.
+        let parameters = request_parameters(&Method::GET, "Action=GetCallerIdentity&action=Other", None).unwrap();
+        assert_eq!(parameters.get("Action").unwrap(), "GetCallerIdentity");
+        assert_eq!(parameters.get("action").unwrap(), "Other");
+        let parameters = request_parameters(&Method::POST, "", Some(b"Version=2011-06-15&VERSION=x")).unwrap();
+        assert_eq!(parameters.len(), 2);
+
+        // Percent-encoded names are decoded before duplicates are detected.
+        assert_eq!(
+            request_parameters(&Method::GET, "Action=A&%41ction=B", None).unwrap_err(),
+            ParameterError::DuplicateQueryParameter("Action".to_string())
+        );
+    }
+}
@@ -0,0 +1,367 @@
+//! Validation of `X-Amz-Content-Sha256` before signature verification.
+//!
+//! A SigV4 request may declare the hash of its payload in `X-Amz-Content-Sha256`: either the hex SHA-256 digest of
+//! the body, or `UNSIGNED-PAYLOAD`, which clients such as the S3 SDKs send over TLS to avoid hashing large bodies.
+//! [WithPayloadHashValidation] checks a literal digest against the body as it was sent, so a body altered after
+//! signing is rejected with `XAmzContentSHA256Mismatch` even if the signature itself only covers the header, and
+//! rejects `UNSIGNED-PAYLOAD` for services that turn off [PayloadHashConfig::allow_unsigned]. Any other value is
+//! rejected with `InvalidArgument`. Requests without the header are passed through unchanged.
+use {
+    crate::{
+        crypto::{hex, sha256},
+        protocol::{AwsError, ErrorProtocol},
+    },
+    http::{header::HeaderMap, StatusCode},
+    hyper::{body::HttpBody, service::Service, Body, Request, Response},
+    log::debug,
+    scratchstack_http_framework::RequestId,
+    serde::Deserialize,
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+    },
+    tower::BoxError,
+};
+
+/// The header giving the hash of the payload in a SigV4 request.
+pub const X_AMZ_CONTENT_SHA256: &str = "x-amz-content-sha256";
+
+/// The `X-Amz-Content-Sha256` value of a request whose payload is not signed.
+pub const UNSIGNED_PAYLOAD: &str = "UNSIGNED-PAYLOAD";
+
+/// Settings for payload hash validation, per service.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct PayloadHashConfig {
+    /// Whether requests may declare `UNSIGNED-PAYLOAD`.
+    #[serde(default = "PayloadHashConfig::default_allow_unsigned")]
+    pub allow_unsigned: bool,
+
+    /// The largest body read to check a declared digest, in bytes.
+    #[serde(default = "PayloadHashConfig::default_max_body_bytes")]
+    pub max_body_bytes: usize,
+}
+
+impl PayloadHashConfig {
+    fn default_allow_unsigned() -> bool {
+        true
+    }
+
+    fn default_max_body_bytes() -> usize {
+        10 << 20
+    }
+}
+
+impl Default for PayloadHashConfig {
+    fn default() -> Self {
+        Self {
+            allow_unsigned: Self::default_allow_unsigned(),
+            max_body_bytes: Self::default_max_body_bytes(),
+        }
+    }
+}
+
+/// What a request declares about its payload.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PayloadHash {
+    /// No `X-Amz-Content-Sha256` header.
+    Absent,
+    Unsigned,
+
+    /// The lowercase hex SHA-256 digest of the body.
+    Digest(String),
+}
+
+/// Why a request's payload hash was not accepted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PayloadHashError {
+    /// The header is neither a SHA-256 digest nor `UNSIGNED-PAYLOAD`.
+    Invalid(String),
+
+    /// `UNSIGNED-PAYLOAD` was sent to a service that does not accept it.
+    UnsignedNotAllowed,
+
+    /// The body does not have the declared digest.
+    Mismatch {
+        declared: String,
+        computed: String,
+    },
+
+    /// The body is larger than [PayloadHashConfig::max_body_bytes].
+    TooLarge(usize),
+}
+
+impl PayloadHashError {
+    /// The AWS error code returned to the caller.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Invalid(_) | Self::UnsignedNotAllowed => "InvalidArgument",
+            Self::Mismatch {
+                ..
+            } => "XAmzContentSHA256Mismatch",
+            Self::TooLarge(_) => "RequestEntityTooLarge",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::TooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl Display for PayloadHashError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Invalid(value) => {
+                write!(f, "x-amz-content-sha256 must be {UNSIGNED_PAYLOAD} or a valid sha256 value, not {value}")
+            }
+            Self::UnsignedNotAllowed => write!(f, "{UNSIGNED_PAYLOAD} is not accepted by this service"),
+            Self::Mismatch {
+                declared,
+                computed,
+            } => write!(
+                f,
+                "The provided 'x-amz-content-sha256' header does not match what was computed. Client: {declared}, \
+                 server: {computed}"
+            ),
+            Self::TooLarge(limit) => write!(f, "Request body exceeds {limit} bytes"),
+        }
+    }
+}
+
+impl Error for PayloadHashError {}
+
+/// The payload hash declared by a request with `headers`, checked against `config` but not yet against the body.
+pub fn payload_hash(headers: &HeaderMap, config: &PayloadHashConfig) -> Result<PayloadHash, PayloadHashError> {
+    let value = match headers.get(X_AMZ_CONTENT_SHA256) {
+        None => return Ok(PayloadHash::Absent),
+        Some(value) => String::from_utf8_lossy(value.as_bytes()).into_owned(),
+    };
+
+    if value == UNSIGNED_PAYLOAD {
+        return if config.allow_unsigned {
+            Ok(PayloadHash::Unsigned)
+        } else {
+            Err(PayloadHashError::UnsignedNotAllowed)
+        };
+    }
+
+    if value.len() == 64 && value.bytes().all(|b| b.is_ascii_hexdigit()) {
+        Ok(PayloadHash::Digest(value.to_ascii_lowercase()))
+    } else {
+        Err(PayloadHashError::Invalid(value))
+    }
+}
+
+/// Check `body` against the declared digest `declared`.
+pub fn check_digest(declared: &str, body: &[u8]) -> Result<(), PayloadHashError> {
+    let computed = hex(&sha256(body));
+    if computed == declared {
+        Ok(())
+    } else {
+        Err(PayloadHashError::Mismatch {
+            declared: declared.to_string(),
+            computed,
+        })
+    }
+}
+
+/// Read the body of `req` and check it against `declared`, returning the request with its body restored.
+async fn check_request(req: Request<Body>, declared: &str, limit: usize) -> Result<Request<Body>, PayloadHashError> {
+    let (parts, mut body) = req.into_parts();
+    let mut data = Vec::new();
+    while let Some(chunk) = body.data().await {
+        // A body that cannot be read in full does not match the declared digest.
+        let chunk = match chunk {
+            Ok(chunk) => chunk,
+            Err(_) => break,
+        };
+        if data.len() + chunk.len() > limit {
+            return Err(PayloadHashError::TooLarge(limit));
+        }
+        data.extend_from_slice(&chunk);
+    }
+
+    check_digest(declared, &data)?;
+    Ok(Request::from_parts(parts, Body::from(data)))
+}
+
+/// Wraps a make-service (such as `SpawnService`) so each per-connection service validates `X-Amz-Content-Sha256`
+/// before the signature is verified.
+///
+/// Wrap this outside `WithRequestDecoding`, so digests are checked against the body as it was sent.
+#[derive(Clone, Debug)]
+pub struct WithPayloadHashValidation<M> {
+    inner: M,
+    config: PayloadHashConfig,
+    protocol: ErrorProtocol,
+}
+
+impl<M> WithPayloadHashValidation<M> {
+    /// Wrap `inner`. Errors are rendered in the shape `protocol` uses.
+    pub fn new(inner: M, config: PayloadHashConfig, protocol: ErrorProtocol) -> Self {
+        Self {
+            inner,
+            config,
+            protocol,
+        }
+    }
+}
+
+impl<T, M> Service<T> for WithPayloadHashValidation<M>
+where
+    M: Service<T>,
+    M::Future: Send + 'static,
+{
+    type Response = PayloadHashValidation<M::Response>;
+    type Error = M::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let config = self.config.clone();
+        let protocol = self.protocol;
+        let future = self.inner.call(target);
+        Box::pin(async move {
+            Ok(PayloadHashValidation {
+                inner: future.await?,
+                config,
+                protocol,
+            })
+        })
+    }
+}
+
+/// A per-connection service that rejects requests whose payload hash is not accepted or does not match the body.
+#[derive(Clone, Debug)]
+pub struct PayloadHashValidation<S> {
+    inner: S,
+    config: PayloadHashConfig,
+    protocol: ErrorProtocol,
+}
+
+impl<S> Service<Request<Body>> for PayloadHashValidation<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let request_id = req.extensions().get::<RequestId>().copied().unwrap_or_else(RequestId::new);
+        let protocol = self.protocol;
+        let reject = move |e: PayloadHashError| {
+            debug!("Rejecting request payload hash: {}", e);
+            protocol.response(&AwsError::sender(e.status(), e.code(), e.to_string()), request_id)
+        };
+
+        let declared = match payload_hash(req.headers(), &self.config) {
+            Ok(PayloadHash::Digest(declared)) => declared,
+            Ok(_) => {
+                let future = self.inner.call(req);
+                return Box::pin(async move { future.await.map_err(Into::into) });
+            }
+            Err(e) => {
+                let response = reject(e);
+                return Box::pin(async move { response });
+            }
+        };
+
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let limit = self.config.max_body_bytes;
+        Box::pin(async move {
+            match check_request(req, &declared, limit).await {
+                Ok(req) => inner.call(req).await.map_err(Into::into),
+                Err(e) => reject(e),
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{payload_hash, PayloadHash, PayloadHashConfig, PayloadHashError, PayloadHashValidation},
+        crate::protocol::STS,
+        http::header::{HeaderMap, HeaderValue},
+        hyper::{
+            body::to_bytes,
+            service::{service_fn, Service},
+            Body, Request, Response,
+        },
+        pretty_assertions::assert_eq,
+        std::convert::Infallible,
+    };
+
+    /// The SHA-256 digest of `Action=GetCallerIdentity`.
+    const DIGEST: &str = "b4b2eab8379f56ae8a4197079d53323c1523a50007a3c9b1cb4dccb185d6e267";
+
+    fn headers(value: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("X-Amz-Content-Sha256", HeaderValue::from_str(value).unwrap());
+        headers
+    }
+
+    #[test_log::test]
+    fn test_payload_hash() {
+        let config = PayloadHashConfig::default();
+        assert_eq!(payload_hash(&HeaderMap::new(), &config), Ok(PayloadHash::Absent));
+        assert_eq!(payload_hash(&headers("UNSIGNED-PAYLOAD"), &config), Ok(PayloadHash::Unsigned));
+        assert_eq!(
+            payload_hash(&headers(&DIGEST.to_ascii_uppercase()), &config),
+            Ok(PayloadHash::Digest(DIGEST.to_string()))
+        );
+        assert!(matches!(
+            payload_hash(&headers("STREAMING-AWS4-HMAC-SHA256-PAYLOAD"), &config),
+            Err(PayloadHashError::Invalid(_))
+        ));
+
+        let config = PayloadHashConfig {
+            allow_unsigned: false,
+            ..config
+        };
+        let e = payload_hash(&headers("UNSIGNED-PAYLOAD"), &config).unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("InvalidArgument", 400));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_payload_hash_validation() {
+        let mut service = PayloadHashValidation {
+            inner: service_fn(|req: Request<Body>| async move {
+                let body = to_bytes(req.into_body()).await.unwrap();
+                Ok::<_, Infallible>(Response::new(Body::from(body)))
+            }),
+            config: PayloadHashConfig::default(),
+            protocol: STS,
+        };
+        let request = |digest: &str, body: &'static str| {
+            Request::post("/").header("X-Amz-Content-Sha256", digest).body(Body::from(body)).unwrap()
+        };
+
+        let response = service.call(request(DIGEST, "Action=GetCallerIdentity")).await.unwrap();
+        assert_eq!(to_bytes(response.into_body()).await.unwrap(), "Action=GetCallerIdentity");
+
+        let response = service.call(request(DIGEST, "Action=AssumeRole")).await.unwrap();
+        assert_eq!(response.status().as_u16(), 400);
+        let body = to_bytes(response.into_body()).await.unwrap();
+        assert!(std::str::from_utf8(&body).unwrap().contains("<Code>XAmzContentSHA256Mismatch</Code>"));
+
+        let response = service.call(request("UNSIGNED-PAYLOAD", "Action=AssumeRole")).await.unwrap();
+        assert_eq!(to_bytes(response.into_body()).await.unwrap(), "Action=AssumeRole");
+    }
+}
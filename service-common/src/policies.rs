@@ -0,0 +1,149 @@
+//! Managed policy attachments and versions, with the quotas AWS enforces.
+//!
+//! Quotas come from [Limits], so they can be raised per account. A policy cannot be deleted while it is attached;
+//! callers detach it from every user, group, and role first, or pass `force` from administrative tools.
+use {
+    crate::{
+        limits::{
+            LimitDefinition, Limits, ATTACHED_POLICIES_PER_GROUP, ATTACHED_POLICIES_PER_ROLE,
+            ATTACHED_POLICIES_PER_USER, VERSIONS_PER_POLICY,
+        },
+        store::{ControlPlaneStore, PolicyHolder, StoreError},
+    },
+    log::warn,
+};
+
+/// The quota on attached policies for a kind of holder.
+pub fn attachment_limit(holder: PolicyHolder) -> &'static LimitDefinition {
+    match holder {
+        PolicyHolder::User => &ATTACHED_POLICIES_PER_USER,
+        PolicyHolder::Group => &ATTACHED_POLICIES_PER_GROUP,
+        PolicyHolder::Role => &ATTACHED_POLICIES_PER_ROLE,
+    }
+}
+
+/// Attach a managed policy to a holder in `account_id`, enforcing the account's attachment quota.
+pub async fn attach_policy(
+    store: &dyn ControlPlaneStore,
+    limits: &Limits,
+    account_id: &str,
+    holder: PolicyHolder,
+    holder_id: &str,
+    managed_policy_id: &str,
+) -> Result<(), StoreError> {
+    let max_attached = limits.get_count(attachment_limit(holder), account_id).await?;
+    store.attach_policy(holder, holder_id, managed_policy_id, max_attached).await
+}
+
+/// Add a version to a managed policy in `account_id`, enforcing the account's version quota.
+pub async fn create_policy_version(
+    store: &dyn ControlPlaneStore,
+    limits: &Limits,
+    account_id: &str,
+    managed_policy_id: &str,
+    policy_document: &str,
+    set_as_default: bool,
+) -> Result<i64, StoreError> {
+    let max_versions = limits.get_count(&VERSIONS_PER_POLICY, account_id).await?;
+    store.create_policy_version(managed_policy_id, policy_document, set_as_default, max_versions).await
+}
+
+/// Delete a managed policy. Without `force`, this fails with `DeleteConflict` while the policy is attached, as in
+/// AWS; with it, the policy is detached from everything first.
+pub async fn delete_policy(
+    store: &dyn ControlPlaneStore,
+    account_id: &str,
+    policy_name: &str,
+    force: bool,
+) -> Result<(), StoreError> {
+    if force {
+        let policy = store.get_policy(account_id, policy_name).await?;
+        let n_detached = store.detach_policy_from_all(&policy.managed_policy_id).await?;
+        if n_detached > 0 {
+            warn!("Detached policy {} from {} entities to delete it", policy_name, n_detached);
+        }
+    }
+
+    store.delete_policy(account_id, policy_name).await
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{attach_policy, create_policy_version, delete_policy},
+        crate::{
+            limits::Limits,
+            store::{ControlPlaneStore, ManagedPolicy, MemoryStore, PolicyHolder, User},
+        },
+        chrono::Utc,
+        pretty_assertions::assert_eq,
+    };
+
+    fn policy(n: usize) -> ManagedPolicy {
+        ManagedPolicy {
+            managed_policy_id: format!("ANPA{n:0>12}"),
+            account_id: "123456789012".to_string(),
+            policy_name: format!("Policy{n}"),
+            path: "/".to_string(),
+            default_version: None,
+            deprecated: false,
+            policy_type: None,
+            created_at: Utc::now(),
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_limits_and_delete_conflict() {
+        let store = MemoryStore::new();
+        let limits = Limits::defaults();
+        let document = r#"{"Version": "2012-10-17", "Statement": []}"#;
+        store
+            .create_user(
+                &User {
+                    user_id: "AIDAEXAMPLEUSER1".to_string(),
+                    account_id: "123456789012".to_string(),
+                    user_name: "Alice".to_string(),
+                    path: "/".to_string(),
+                    permissions_boundary: None,
+                    created_at: Utc::now(),
+                },
+                &[],
+            )
+            .await
+            .unwrap();
+
+        for n in 0..11 {
+            store.create_policy(&policy(n), document).await.unwrap();
+        }
+
+        for n in 0..10 {
+            let id = policy(n).managed_policy_id;
+            attach_policy(&store, &limits, "123456789012", PolicyHolder::User, "AIDAEXAMPLEUSER1", &id).await.unwrap();
+        }
+
+        // Attaching again is not an error, but an eleventh policy is.
+        let id = policy(0).managed_policy_id;
+        attach_policy(&store, &limits, "123456789012", PolicyHolder::User, "AIDAEXAMPLEUSER1", &id).await.unwrap();
+        let id = policy(10).managed_policy_id;
+        let e = attach_policy(&store, &limits, "123456789012", PolicyHolder::User, "AIDAEXAMPLEUSER1", &id)
+            .await
+            .unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("LimitExceeded", 409));
+
+        let id = policy(0).managed_policy_id;
+        for version in 2..=5 {
+            let created = create_policy_version(&store, &limits, "123456789012", &id, document, true).await.unwrap();
+            assert_eq!(created, version);
+        }
+        let e = create_policy_version(&store, &limits, "123456789012", &id, document, true).await.unwrap_err();
+        assert_eq!(e.code(), "LimitExceeded");
+        assert_eq!(store.get_policy("123456789012", "Policy0").await.unwrap().default_version, Some(5));
+
+        let e = delete_policy(&store, "123456789012", "Policy0", false).await.unwrap_err();
+        assert_eq!(e.code(), "DeleteConflict");
+        assert_eq!(store.delete_user("123456789012", "Alice").await.unwrap_err().code(), "DeleteConflict");
+
+        delete_policy(&store, "123456789012", "Policy0", true).await.unwrap();
+        assert_eq!(store.list_attached_policies(PolicyHolder::User, "AIDAEXAMPLEUSER1").await.unwrap().len(), 9);
+    }
+}
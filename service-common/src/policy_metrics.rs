@@ -0,0 +1,203 @@
+//! Metrics for identity policy evaluation.
+//!
+//! [PolicyMetrics::evaluate] wraps [PolicyEvaluator::evaluate], counting decisions by service, action and outcome and
+//! recording how long evaluation took and how many statements were matched against the request. An action whose
+//! requests consistently match many statements, or take long to evaluate, is a candidate for precompiling its
+//! policies.
+use {
+    crate::{
+        authz::Decision,
+        engine::{EvaluationRequest, PolicyEvaluator},
+        histogram::Histogram,
+    },
+    std::{
+        collections::BTreeMap,
+        fmt::{Display, Formatter, Result as FmtResult, Write},
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+};
+
+/// Upper bounds of the evaluation time histogram buckets, in seconds.
+pub const EVALUATION_BUCKETS: &[f64] = &[0.00001, 0.000025, 0.00005, 0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.01];
+
+/// Upper bounds of the statements evaluated histogram buckets.
+pub const STATEMENT_BUCKETS: &[f64] = &[1.0, 2.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0];
+
+/// The outcome of evaluating the policies that apply to a request.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub enum PolicyOutcome {
+    Allow,
+    ExplicitDeny,
+    ImplicitDeny,
+}
+
+impl PolicyOutcome {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Allow => "allow",
+            Self::ExplicitDeny => "explicit_deny",
+            Self::ImplicitDeny => "implicit_deny",
+        }
+    }
+}
+
+impl From<&Decision> for PolicyOutcome {
+    fn from(decision: &Decision) -> Self {
+        match decision {
+            Decision::Allow => Self::Allow,
+            Decision::ExplicitDeny {
+                ..
+            } => Self::ExplicitDeny,
+            Decision::ImplicitDeny => Self::ImplicitDeny,
+        }
+    }
+}
+
+impl Display for PolicyOutcome {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Debug)]
+struct MetricsState {
+    /// Keyed by (service, action, outcome); the service is lowercased, as actions are case-insensitive.
+    outcomes: BTreeMap<(String, String, PolicyOutcome), u64>,
+    seconds: Histogram,
+    statements: Histogram,
+}
+
+/// Policy evaluation counters and timings.
+#[derive(Debug)]
+pub struct PolicyMetrics {
+    state: Mutex<MetricsState>,
+}
+
+impl Default for PolicyMetrics {
+    fn default() -> Self {
+        Self {
+            state: Mutex::new(MetricsState {
+                outcomes: BTreeMap::new(),
+                seconds: Histogram::new(EVALUATION_BUCKETS),
+                statements: Histogram::new(STATEMENT_BUCKETS),
+            }),
+        }
+    }
+}
+
+impl PolicyMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Evaluate `request` with `evaluator`, recording the outcome.
+    pub fn evaluate(&self, evaluator: &PolicyEvaluator, request: &EvaluationRequest) -> Decision {
+        let start = Instant::now();
+        let (decision, statements) = evaluator.evaluate_counted(request);
+        self.record(request.action, &decision, statements, start.elapsed());
+        decision
+    }
+
+    /// Record a decision for `action` (e.g. `iam:GetUser`) that matched `statements` statements in `elapsed`.
+    pub fn record(&self, action: &str, decision: &Decision, statements: usize, elapsed: Duration) {
+        let (service, name) = split_action(action);
+        let mut state = self.state.lock().expect("policy metrics poisoned");
+        *state.outcomes.entry((service, name, PolicyOutcome::from(decision))).or_default() += 1;
+        state.seconds.observe(elapsed.as_secs_f64());
+        state.statements.observe(statements as f64);
+    }
+
+    /// The number of decisions recorded for `action` with the given outcome.
+    pub fn count(&self, action: &str, outcome: PolicyOutcome) -> u64 {
+        let (service, name) = split_action(action);
+        let state = self.state.lock().expect("policy metrics poisoned");
+        state.outcomes.get(&(service, name, outcome)).copied().unwrap_or_default()
+    }
+
+    /// Decision counts by action and outcome, and the evaluation time and statement count histograms.
+    pub fn render(&self) -> String {
+        let state = self.state.lock().expect("policy metrics poisoned");
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# HELP scratchstack_policy_decisions_total Policy evaluation decisions by outcome.");
+        let _ = writeln!(out, "# TYPE scratchstack_policy_decisions_total counter");
+        for ((service, action, outcome), count) in &state.outcomes {
+            let _ = writeln!(
+                out,
+                "scratchstack_policy_decisions_total{{service=\"{service}\",action=\"{action}\",outcome=\"{outcome}\"}} {count}"
+            );
+        }
+
+        let _ = writeln!(out, "# HELP scratchstack_policy_evaluation_seconds Time spent evaluating policies.");
+        let _ = writeln!(out, "# TYPE scratchstack_policy_evaluation_seconds histogram");
+        state.seconds.render(&mut out, "scratchstack_policy_evaluation_seconds", "");
+
+        let _ =
+            writeln!(out, "# HELP scratchstack_policy_statements_evaluated Statements matched against each request.");
+        let _ = writeln!(out, "# TYPE scratchstack_policy_statements_evaluated histogram");
+        state.statements.render(&mut out, "scratchstack_policy_statements_evaluated", "");
+        out
+    }
+}
+
+/// Split `service:Action` into its parts, escaping quotes and backslashes for use as label values.
+fn split_action(action: &str) -> (String, String) {
+    let escape = |s: &str| s.replace('\\', "\\\\").replace('"', "\\\"");
+    match action.split_once(':') {
+        Some((service, name)) => (escape(&service.to_ascii_lowercase()), escape(name)),
+        None => (String::new(), escape(action)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{PolicyMetrics, PolicyOutcome},
+        crate::{
+            authz::Decision,
+            engine::{EvaluationRequest, PolicyEvaluator},
+        },
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{Principal, PrincipalIdentity, SessionData, User},
+        std::time::Duration,
+    };
+
+    #[test_log::test]
+    fn test_policy_metrics() {
+        let evaluator = PolicyEvaluator::new()
+            .with_policy("ReadOnly", r#"{"Statement": {"Effect": "Allow", "Action": "iam:Get*", "Resource": "*"}}"#)
+            .unwrap();
+        let alice = User::new("aws", "123456789012", "/", "alice").unwrap();
+        let principal = Principal::from(vec![PrincipalIdentity::from(alice)]);
+        let context = SessionData::new();
+        let request = |action: &'static str| EvaluationRequest {
+            principal: &principal,
+            action,
+            resource: "*",
+            context: &context,
+        };
+
+        let metrics = PolicyMetrics::new();
+        assert_eq!(metrics.evaluate(&evaluator, &request("iam:GetUser")), Decision::Allow);
+        assert_eq!(metrics.evaluate(&evaluator, &request("IAM:GetUser")), Decision::Allow);
+        assert_eq!(metrics.evaluate(&evaluator, &request("iam:DeleteUser")), Decision::ImplicitDeny);
+        let deny = Decision::ExplicitDeny {
+            policy: "NoDelete".to_string(),
+            statement: "0".to_string(),
+        };
+        metrics.record("iam:DeleteUser", &deny, 40, Duration::from_millis(5));
+
+        assert_eq!(metrics.count("iam:GetUser", PolicyOutcome::Allow), 2);
+        assert_eq!(metrics.count("iam:DeleteUser", PolicyOutcome::ImplicitDeny), 1);
+        assert_eq!(metrics.count("iam:DeleteUser", PolicyOutcome::ExplicitDeny), 1);
+
+        let rendered = metrics.render();
+        assert!(rendered
+            .contains("scratchstack_policy_decisions_total{service=\"iam\",action=\"GetUser\",outcome=\"allow\"} 2\n"));
+        assert!(rendered.contains("scratchstack_policy_statements_evaluated_bucket{le=\"1\"} 3\n"), "{rendered}");
+        assert!(rendered.contains("scratchstack_policy_statements_evaluated_bucket{le=\"25\"} 3\n"), "{rendered}");
+        assert!(rendered.contains("scratchstack_policy_statements_evaluated_bucket{le=\"50\"} 4\n"), "{rendered}");
+        assert!(rendered.contains("scratchstack_policy_evaluation_seconds_count 4\n"), "{rendered}");
+    }
+}
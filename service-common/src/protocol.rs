@@ -0,0 +1,444 @@
+//! Error responses in the shape each AWS protocol uses.
+//!
+//! The query protocol services (IAM, STS) wrap errors in `<ErrorResponse>`, but the other protocols differ in the
+//! wrapper element, namespace, field names, and whether the request id and error code travel in the body or in
+//! headers. An [ErrorProtocol] captures one such shape, and the constants here give the template for each service or
+//! protocol family Scratchstack answers for. Middleware that rejects requests before they reach a service renders
+//! its errors through [ErrorProtocol::response] so they match what the service itself would return.
+use {
+    http::{header::HeaderValue, StatusCode},
+    hyper::{Body, Response},
+    scratchstack_http_framework::RequestId,
+    serde_json::Value,
+    std::fmt::{Display, Formatter, Result as FmtResult},
+    tower::BoxError,
+};
+
+/// The XML declaration some rest-xml and EC2 responses start with.
+const XML_DECLARATION: &str = r#"<?xml version="1.0" encoding="UTF-8"?>"#;
+
+/// The namespace of IAM responses.
+pub const IAM_XML_NS: &str = "https://iam.amazonaws.com/doc/2010-05-08/";
+
+/// IAM: query protocol.
+pub const IAM: ErrorProtocol = ErrorProtocol::Query {
+    xml_ns: IAM_XML_NS,
+};
+
+/// STS: query protocol.
+pub const STS: ErrorProtocol = ErrorProtocol::Query {
+    xml_ns: "https://sts.amazonaws.com/doc/2011-06-15/",
+};
+
+/// Query protocol errors raised before an action is identified, such as `InvalidAction` or a missing `Action`.
+pub const AWS_FAULT: ErrorProtocol = ErrorProtocol::Query {
+    xml_ns: "http://webservices.amazon.com/AWSFault/2005-15-09",
+};
+
+/// EC2: its own variant of the query protocol.
+pub const EC2: ErrorProtocol = ErrorProtocol::Ec2;
+
+/// S3: rest-xml with a bare `<Error>` element.
+pub const S3: ErrorProtocol = ErrorProtocol::RestXml;
+
+/// Route 53: rest-xml with the query protocol's wrapper.
+pub const ROUTE53: ErrorProtocol = ErrorProtocol::RestXmlWrapped {
+    xml_ns: "https://route53.amazonaws.com/doc/2013-04-01/",
+};
+
+/// DynamoDB: awsJson1_0 with a qualified `__type`.
+pub const DYNAMODB: ErrorProtocol = ErrorProtocol::AwsJson {
+    version: "1.0",
+    type_prefix: Some("com.amazonaws.dynamodb.v20120810"),
+};
+
+/// CloudWatch Logs: awsJson1_1 with a bare `__type`.
+pub const CLOUDWATCH_LOGS: ErrorProtocol = ErrorProtocol::AwsJson {
+    version: "1.1",
+    type_prefix: None,
+};
+
+/// Lambda: restJson1 with capitalized `Message` and `Type` fields.
+pub const LAMBDA: ErrorProtocol = ErrorProtocol::RestJson {
+    message_field: "Message",
+    type_field: Some("Type"),
+};
+
+/// Whether an error is the caller's fault or the service's.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Fault {
+    Sender,
+    Receiver,
+}
+
+impl Display for Fault {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Sender => f.write_str("Sender"),
+            Self::Receiver => f.write_str("Receiver"),
+        }
+    }
+}
+
+/// An error to report to the caller.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct AwsError {
+    pub status: StatusCode,
+    pub fault: Fault,
+    pub code: String,
+    pub message: String,
+
+    /// The resource the error concerns. Only rest-xml responses report this.
+    pub resource: Option<String>,
+
+    /// The server's clock, for errors about request timestamps. Only rest-xml responses report this; other protocols
+    /// include it in the message.
+    pub server_time: Option<String>,
+}
+
+impl AwsError {
+    /// An error caused by the request.
+    pub fn sender<C: Into<String>, M: Into<String>>(status: StatusCode, code: C, message: M) -> Self {
+        Self {
+            status,
+            fault: Fault::Sender,
+            code: code.into(),
+            message: message.into(),
+            resource: None,
+            server_time: None,
+        }
+    }
+
+    /// An error on the service's side.
+    pub fn receiver<C: Into<String>, M: Into<String>>(status: StatusCode, code: C, message: M) -> Self {
+        Self {
+            fault: Fault::Receiver,
+            ..Self::sender(status, code, message)
+        }
+    }
+
+    pub fn with_resource<R: Into<String>>(mut self, resource: R) -> Self {
+        self.resource = Some(resource.into());
+        self
+    }
+
+    pub fn with_server_time<T: Into<String>>(mut self, server_time: T) -> Self {
+        self.server_time = Some(server_time.into());
+        self
+    }
+}
+
+/// The shape of error responses for one protocol or service.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ErrorProtocol {
+    /// `<ErrorResponse xmlns><Error><Type/><Code/><Message/></Error><RequestId/></ErrorResponse>`.
+    Query {
+        xml_ns: &'static str,
+    },
+
+    /// `<Response><Errors><Error><Code/><Message/></Error></Errors><RequestID/></Response>`.
+    Ec2,
+
+    /// `<Error><Code/><Message/><Resource/><RequestId/></Error>`, as S3 returns.
+    RestXml,
+
+    /// The query protocol's wrapper with an XML declaration, as Route 53 and CloudFront return.
+    RestXmlWrapped {
+        xml_ns: &'static str,
+    },
+
+    /// `{"__type": "<type_prefix>#<code>", "message": ...}`; the request id is only in a header.
+    AwsJson {
+        /// `1.0` or `1.1`, for the `application/x-amz-json-*` content type.
+        version: &'static str,
+        type_prefix: Option<&'static str>,
+    },
+
+    /// A JSON body with the message; the code is in the `X-Amzn-ErrorType` header. If `type_field` is set, the body
+    /// also carries the fault under that name, as `User` or `Service`.
+    RestJson {
+        message_field: &'static str,
+        type_field: Option<&'static str>,
+    },
+}
+
+/// A rendered error body and the headers that go with it.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct RenderedError {
+    pub content_type: &'static str,
+    pub headers: Vec<(&'static str, String)>,
+    pub body: String,
+}
+
+impl ErrorProtocol {
+    /// The header the request id is returned in.
+    pub fn request_id_header(&self) -> &'static str {
+        match self {
+            Self::RestXml => "x-amz-request-id",
+            _ => "X-Amzn-RequestId",
+        }
+    }
+
+    pub fn render(&self, error: &AwsError, request_id: &str) -> RenderedError {
+        let code = escape_xml(&error.code);
+        let message = escape_xml(&error.message);
+        let request_id_header = (self.request_id_header(), request_id.to_string());
+
+        match self {
+            Self::Query {
+                xml_ns,
+            } => RenderedError {
+                content_type: "text/xml",
+                headers: vec![request_id_header],
+                body: format!(
+                    r#"<ErrorResponse xmlns="{xml_ns}"><Error><Type>{}</Type><Code>{code}</Code><Message>{message}</Message></Error><RequestId>{}</RequestId></ErrorResponse>"#,
+                    error.fault,
+                    escape_xml(request_id)
+                ),
+            },
+            Self::Ec2 => RenderedError {
+                content_type: "text/xml",
+                headers: vec![request_id_header],
+                body: format!(
+                    "{XML_DECLARATION}\n<Response><Errors><Error><Code>{code}</Code><Message>{message}</Message></Error></Errors><RequestID>{}</RequestID></Response>",
+                    escape_xml(request_id)
+                ),
+            },
+            Self::RestXml => {
+                let resource = match &error.resource {
+                    Some(resource) => format!("<Resource>{}</Resource>", escape_xml(resource)),
+                    None => String::new(),
+                };
+                let server_time = match &error.server_time {
+                    Some(server_time) => format!("<ServerTime>{}</ServerTime>", escape_xml(server_time)),
+                    None => String::new(),
+                };
+                RenderedError {
+                    content_type: "application/xml",
+                    headers: vec![request_id_header],
+                    body: format!(
+                        "{XML_DECLARATION}\n<Error><Code>{code}</Code><Message>{message}</Message>{resource}{server_time}<RequestId>{}</RequestId></Error>",
+                        escape_xml(request_id)
+                    ),
+                }
+            }
+            Self::RestXmlWrapped {
+                xml_ns,
+            } => RenderedError {
+                content_type: "text/xml",
+                headers: vec![request_id_header],
+                body: format!(
+                    r#"{XML_DECLARATION}
+<ErrorResponse xmlns="{xml_ns}"><Error><Type>{}</Type><Code>{code}</Code><Message>{message}</Message></Error><RequestId>{}</RequestId></ErrorResponse>"#,
+                    error.fault,
+                    escape_xml(request_id)
+                ),
+            },
+            Self::AwsJson {
+                version,
+                type_prefix,
+            } => {
+                let error_type = match type_prefix {
+                    Some(prefix) => format!("{prefix}#{}", error.code),
+                    None => error.code.clone(),
+                };
+                RenderedError {
+                    content_type: if *version == "1.0" {
+                        "application/x-amz-json-1.0"
+                    } else {
+                        "application/x-amz-json-1.1"
+                    },
+                    headers: vec![request_id_header],
+                    body: json_object(&[("__type", &error_type), ("message", &error.message)]),
+                }
+            }
+            Self::RestJson {
+                message_field,
+                type_field,
+            } => {
+                let mut fields = Vec::new();
+                if let Some(type_field) = type_field {
+                    let fault = match error.fault {
+                        Fault::Sender => "User",
+                        Fault::Receiver => "Service",
+                    };
+                    fields.push((*type_field, fault));
+                }
+                fields.push((*message_field, error.message.as_str()));
+                RenderedError {
+                    content_type: "application/json",
+                    headers: vec![request_id_header, ("X-Amzn-ErrorType", error.code.clone())],
+                    body: json_object(&fields),
+                }
+            }
+        }
+    }
+
+    /// Render `error` as a complete response.
+    pub fn response(&self, error: &AwsError, request_id: RequestId) -> Result<Response<Body>, BoxError> {
+        let rendered = self.render(error, &request_id.to_string());
+        let mut builder = Response::builder()
+            .status(error.status)
+            .header("Content-Type", HeaderValue::from_static(rendered.content_type));
+        for (name, value) in rendered.headers {
+            builder = builder.header(name, value);
+        }
+
+        Ok(builder.body(Body::from(rendered.body))?)
+    }
+}
+
+/// A JSON object with `fields` in the order given, as AWS writes them. A serde_json [Map][serde_json::Map] would sort
+/// them instead, or keep insertion order if another crate in the build enables its `preserve_order` feature.
+fn json_object(fields: &[(&str, &str)]) -> String {
+    let fields = fields.iter().map(|(name, value)| format!("{}:{}", Value::from(*name), Value::from(*value)));
+    format!("{{{}}}", fields.collect::<Vec<_>>().join(","))
+}
+
+/// Escape the characters that cannot appear literally in XML text.
+pub fn escape_xml(s: &str) -> String {
+    s.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{AwsError, ErrorProtocol, CLOUDWATCH_LOGS, DYNAMODB, EC2, IAM, LAMBDA, ROUTE53, S3, STS},
+        http::StatusCode,
+        pretty_assertions::assert_eq,
+    };
+
+    /// Drop the whitespace between elements that AWS pretty-prints with.
+    fn compact_xml(xml: &str) -> String {
+        xml.lines().map(str::trim).collect::<Vec<_>>().join("").replace("?><", "?>\n<")
+    }
+
+    fn assert_xml(protocol: ErrorProtocol, error: AwsError, request_id: &str, recorded: &str) {
+        assert_eq!(protocol.render(&error, request_id).body, compact_xml(recorded));
+    }
+
+    fn assert_json(protocol: ErrorProtocol, error: AwsError, recorded: &str) -> Vec<(&'static str, String)> {
+        // Field order is compared too; some SDKs are sensitive to it.
+        let rendered = protocol.render(&error, "f2a5b4a0-0000-4000-8000-000000000000");
+        assert_eq!(rendered.body, recorded);
+        rendered.headers
+    }
+
+    #[test_log::test]
+    fn test_recorded_xml_errors() {
+        assert_xml(
+            IAM,
+            AwsError::sender(StatusCode::NOT_FOUND, "NoSuchEntity", "The user with name alice cannot be found."),
+            "0b8b9b41-1c77-4ac5-a6b5-1e4a1de0d8f1",
+            r#"<ErrorResponse xmlns="https://iam.amazonaws.com/doc/2010-05-08/">
+                 <Error>
+                   <Type>Sender</Type>
+                   <Code>NoSuchEntity</Code>
+                   <Message>The user with name alice cannot be found.</Message>
+                 </Error>
+                 <RequestId>0b8b9b41-1c77-4ac5-a6b5-1e4a1de0d8f1</RequestId>
+               </ErrorResponse>"#,
+        );
+
+        assert_xml(
+            STS,
+            AwsError::sender(
+                StatusCode::FORBIDDEN,
+                "InvalidClientTokenId",
+                "The security token included in the request is invalid.",
+            ),
+            "4b1f0a4e-9d91-4b1b-9c52-77a3a4c7a1de",
+            r#"<ErrorResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/">
+                 <Error>
+                   <Type>Sender</Type>
+                   <Code>InvalidClientTokenId</Code>
+                   <Message>The security token included in the request is invalid.</Message>
+                 </Error>
+                 <RequestId>4b1f0a4e-9d91-4b1b-9c52-77a3a4c7a1de</RequestId>
+               </ErrorResponse>"#,
+        );
+
+        assert_xml(
+            EC2,
+            AwsError::sender(
+                StatusCode::BAD_REQUEST,
+                "InvalidInstanceID.NotFound",
+                "The instance ID 'i-1a2b3c4d' does not exist",
+            ),
+            "ea966190-f9aa-478e-9ede-example",
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+               <Response><Errors><Error><Code>InvalidInstanceID.NotFound</Code><Message>The instance ID 'i-1a2b3c4d' does not exist</Message></Error></Errors><RequestID>ea966190-f9aa-478e-9ede-example</RequestID></Response>"#,
+        );
+
+        assert_xml(
+            S3,
+            AwsError::sender(StatusCode::NOT_FOUND, "NoSuchKey", "The resource you requested does not exist")
+                .with_resource("/mybucket/myfoto.jpg"),
+            "4442587FB7D0A2F9",
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+               <Error>
+                 <Code>NoSuchKey</Code>
+                 <Message>The resource you requested does not exist</Message>
+                 <Resource>/mybucket/myfoto.jpg</Resource>
+                 <RequestId>4442587FB7D0A2F9</RequestId>
+               </Error>"#,
+        );
+
+        assert_xml(
+            ROUTE53,
+            AwsError::sender(StatusCode::NOT_FOUND, "NoSuchHostedZone", "No hosted zone found with ID: Z1D633PJN98FT9"),
+            "8b2a63d4-1f5b-11e6-9a4e-0b9e4c7eEXAMPLE",
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+               <ErrorResponse xmlns="https://route53.amazonaws.com/doc/2013-04-01/">
+                 <Error>
+                   <Type>Sender</Type>
+                   <Code>NoSuchHostedZone</Code>
+                   <Message>No hosted zone found with ID: Z1D633PJN98FT9</Message>
+                 </Error>
+                 <RequestId>8b2a63d4-1f5b-11e6-9a4e-0b9e4c7eEXAMPLE</RequestId>
+               </ErrorResponse>"#,
+        );
+
+        // Messages are escaped.
+        let rendered =
+            IAM.render(&AwsError::sender(StatusCode::BAD_REQUEST, "MalformedPolicyDocument", "a < b & c"), "1");
+        assert!(rendered.body.contains("<Message>a &lt; b &amp; c</Message>"));
+        assert_eq!(rendered.content_type, "text/xml");
+    }
+
+    #[test_log::test]
+    fn test_recorded_json_errors() {
+        assert_json(
+            DYNAMODB,
+            AwsError::sender(StatusCode::BAD_REQUEST, "ResourceNotFoundException", "Requested resource not found"),
+            r#"{"__type":"com.amazonaws.dynamodb.v20120810#ResourceNotFoundException","message":"Requested resource not found"}"#,
+        );
+
+        assert_json(
+            CLOUDWATCH_LOGS,
+            AwsError::sender(
+                StatusCode::BAD_REQUEST,
+                "ResourceNotFoundException",
+                "The specified log group does not exist.",
+            ),
+            r#"{"__type":"ResourceNotFoundException","message":"The specified log group does not exist."}"#,
+        );
+
+        let headers = assert_json(
+            LAMBDA,
+            AwsError::sender(
+                StatusCode::NOT_FOUND,
+                "ResourceNotFoundException",
+                "Function not found: arn:aws:lambda:us-west-2:123456789012:function:my-function",
+            ),
+            r#"{"Type":"User","Message":"Function not found: arn:aws:lambda:us-west-2:123456789012:function:my-function"}"#,
+        );
+        assert!(headers.contains(&("X-Amzn-ErrorType", "ResourceNotFoundException".to_string())));
+        assert_eq!(
+            DYNAMODB
+                .render(&AwsError::receiver(StatusCode::INTERNAL_SERVER_ERROR, "InternalServerError", ""), "1")
+                .content_type,
+            "application/x-amz-json-1.0"
+        );
+    }
+}
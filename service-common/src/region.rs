@@ -0,0 +1,222 @@
+use {
+    serde::{Deserialize, Serialize},
+    std::{
+        borrow::Borrow,
+        collections::BTreeSet,
+        error::Error,
+        fmt::{Debug, Display, Formatter, Result as FmtResult},
+        ops::Deref,
+        str::FromStr,
+    },
+};
+
+/// The longest region or partition name accepted.
+const MAX_NAME_LENGTH: usize = 63;
+
+/// A region name, such as `us-west-2` or `local`.
+///
+/// Names are lowercase ASCII letters, digits, and single hyphens, starting with a letter and not ending with a
+/// hyphen.
+#[derive(Clone, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Region(String);
+
+/// A partition name, such as `aws` or `aws-cn`. The rules for names are the same as for [Region].
+#[derive(Clone, Deserialize, Eq, Hash, Ord, PartialEq, PartialOrd, Serialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Partition(String);
+
+macro_rules! name_type {
+    ($name:ident, $error:ident) => {
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Debug for $name {
+            fn fmt(&self, f: &mut Formatter) -> FmtResult {
+                Debug::fmt(&self.0, f)
+            }
+        }
+
+        impl Deref for $name {
+            type Target = str;
+
+            fn deref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut Formatter) -> FmtResult {
+                f.write_str(&self.0)
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(value: $name) -> String {
+                value.0
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = RegionError;
+
+            fn from_str(s: &str) -> Result<Self, RegionError> {
+                Self::try_from(s.to_string())
+            }
+        }
+
+        impl PartialEq<str> for $name {
+            fn eq(&self, other: &str) -> bool {
+                self.0 == other
+            }
+        }
+
+        impl PartialEq<&str> for $name {
+            fn eq(&self, other: &&str) -> bool {
+                self.0 == *other
+            }
+        }
+
+        impl TryFrom<String> for $name {
+            type Error = RegionError;
+
+            fn try_from(value: String) -> Result<Self, RegionError> {
+                if is_valid_name(&value) {
+                    Ok(Self(value))
+                } else {
+                    Err(RegionError::$error(value))
+                }
+            }
+        }
+    };
+}
+
+name_type!(Region, InvalidRegion);
+name_type!(Partition, InvalidPartition);
+
+fn is_valid_name(name: &str) -> bool {
+    let bytes = name.as_bytes();
+    match (bytes.first(), bytes.last()) {
+        (Some(first), Some(last)) => {
+            bytes.len() <= MAX_NAME_LENGTH
+                && first.is_ascii_lowercase()
+                && *last != b'-'
+                && bytes.iter().all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || *b == b'-')
+                && !name.contains("--")
+        }
+        _ => false,
+    }
+}
+
+#[derive(Debug, Eq, PartialEq)]
+pub enum RegionError {
+    InvalidPartition(String),
+    InvalidRegion(String),
+}
+
+impl Error for RegionError {}
+
+impl Display for RegionError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::InvalidPartition(name) => write!(f, "Invalid partition name: {name:?}"),
+            Self::InvalidRegion(name) => write!(f, "Invalid region name: {name:?}"),
+        }
+    }
+}
+
+/// The set of regions a service endpoint accepts in the credential scope of signed requests.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct RegionRegistry {
+    regions: BTreeSet<Region>,
+}
+
+impl RegionRegistry {
+    pub fn new<I: IntoIterator<Item = Region>>(regions: I) -> Self {
+        Self {
+            regions: regions.into_iter().collect(),
+        }
+    }
+
+    /// Whether `region`, as given in a request, is accepted.
+    pub fn contains(&self, region: &str) -> bool {
+        self.regions.contains(region)
+    }
+
+    pub fn insert(&mut self, region: Region) -> bool {
+        self.regions.insert(region)
+    }
+
+    pub fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Region> {
+        self.regions.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{Partition, Region, RegionError, RegionRegistry},
+        pretty_assertions::assert_eq,
+    };
+
+    fn region(name: &str) -> Region {
+        name.parse().unwrap()
+    }
+
+    #[test_log::test]
+    fn test_registry() {
+        let mut registry = RegionRegistry::new([region("local"), region("us-west-2")]);
+        assert!(registry.contains("local"));
+        assert!(registry.contains("us-west-2"));
+        assert!(!registry.contains("us-east-1"));
+        assert!(!registry.contains("US-WEST-2"));
+
+        assert!(registry.insert(region("us-east-1")));
+        assert!(!registry.insert(region("local")));
+        assert_eq!(registry.iter().map(Region::as_str).collect::<Vec<_>>(), vec!["local", "us-east-1", "us-west-2"]);
+    }
+
+    #[test_log::test]
+    fn test_names() {
+        for name in ["local", "us-west-2", "cn-northwest-1", "us-gov-east-1"] {
+            assert_eq!(region(name).to_string(), name);
+        }
+        for name in ["", "US-WEST-2", "us_west_2", "-local", "local-", "us--west-2", "2local", "us west 2"] {
+            assert_eq!(name.parse::<Region>().unwrap_err(), RegionError::InvalidRegion(name.to_string()));
+        }
+        assert!("a".repeat(64).parse::<Region>().is_err());
+
+        let partition: Partition = "aws-us-gov".parse().unwrap();
+        assert_eq!(partition, "aws-us-gov");
+        assert_eq!("aws cn".parse::<Partition>().unwrap_err(), RegionError::InvalidPartition("aws cn".to_string()));
+
+        // Regions and partitions are validated when read from configuration, too.
+        let regions: Vec<Region> = serde_json::from_str(r#"["local", "us-east-1"]"#).unwrap();
+        assert_eq!(regions, vec![region("local"), region("us-east-1")]);
+        assert!(serde_json::from_str::<Region>(r#""Local""#).is_err());
+        assert_eq!(serde_json::to_string(&region("local")).unwrap(), r#""local""#);
+    }
+}
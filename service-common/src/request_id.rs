@@ -0,0 +1,296 @@
+//! Request ids on every response.
+//!
+//! AWS returns the request id of every response in a header, and query protocol bodies repeat it: in
+//! `<ResponseMetadata><RequestId>` for results and in `<RequestId>` for errors. The service implementations add it
+//! to the responses they build, but rejections from the signature verifier, middleware that builds its own error
+//! responses, and panics did not reliably carry one.
+//!
+//! [WithRequestIds] wraps the per-connection service outside the signature verifier, so every response passes
+//! through it. It assigns each request a [RequestId] in the request extensions, where the inner layers find it, and
+//! on the way out adds the header if it is missing and fills in the body of query protocol XML responses that have
+//! no `<RequestId>`. When a
+//! layer inside already set the header, its value is used in the body too, so the two always agree. A handler that
+//! panics is answered with `InternalFailure` instead of the connection being dropped.
+use {
+    crate::protocol::{escape_xml, AwsError, ErrorProtocol},
+    futures::FutureExt,
+    http::{header::HeaderValue, StatusCode},
+    hyper::{body::to_bytes, service::Service, Body, Request, Response},
+    log::error,
+    scratchstack_http_framework::RequestId,
+    std::{
+        future::Future,
+        panic::AssertUnwindSafe,
+        pin::Pin,
+        task::{Context, Poll},
+    },
+    tower::BoxError,
+};
+
+/// Wraps a make-service so that each connection's service is a [RequestIds].
+#[derive(Clone, Debug)]
+pub struct WithRequestIds<M> {
+    inner: M,
+    protocol: ErrorProtocol,
+}
+
+impl<M> WithRequestIds<M> {
+    /// `protocol` is the error shape of the service, used for panics and to decide which bodies to fill in.
+    pub fn new(inner: M, protocol: ErrorProtocol) -> Self {
+        Self {
+            inner,
+            protocol,
+        }
+    }
+}
+
+impl<T, M> Service<T> for WithRequestIds<M>
+where
+    M: Service<T>,
+    M::Future: Send + 'static,
+{
+    type Response = RequestIds<M::Response>;
+    type Error = M::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let protocol = self.protocol;
+        let future = self.inner.call(target);
+        Box::pin(async move {
+            Ok(RequestIds {
+                inner: future.await?,
+                protocol,
+            })
+        })
+    }
+}
+
+/// A per-connection service that gives every response a request id.
+#[derive(Clone, Debug)]
+pub struct RequestIds<S> {
+    inner: S,
+    protocol: ErrorProtocol,
+}
+
+impl<S> Service<Request<Body>> for RequestIds<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, mut req: Request<Body>) -> Self::Future {
+        let request_id = match req.extensions().get::<RequestId>() {
+            Some(request_id) => *request_id,
+            None => {
+                let request_id = RequestId::new();
+                req.extensions_mut().insert(request_id);
+                request_id
+            }
+        };
+
+        let protocol = self.protocol;
+        let future = AssertUnwindSafe(self.inner.call(req)).catch_unwind();
+        Box::pin(async move {
+            let response = match future.await {
+                Ok(result) => result.map_err(Into::into)?,
+                Err(_) => {
+                    error!("Request {} panicked", request_id);
+                    let error = AwsError::receiver(
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        "InternalFailure",
+                        "The request processing has failed because of an unknown error, exception or failure.",
+                    );
+                    return protocol.response(&error, request_id);
+                }
+            };
+
+            with_request_id(response, protocol, request_id).await
+        })
+    }
+}
+
+/// Add the request id to `response` wherever `protocol` expects it and it is missing.
+async fn with_request_id(
+    response: Response<Body>,
+    protocol: ErrorProtocol,
+    request_id: RequestId,
+) -> Result<Response<Body>, BoxError> {
+    let (mut parts, body) = response.into_parts();
+    let header = protocol.request_id_header();
+    let request_id = match parts.headers.get(header).and_then(|value| value.to_str().ok()) {
+        Some(existing) => existing.to_string(),
+        None => {
+            let request_id = request_id.to_string();
+            parts.headers.insert(header, HeaderValue::from_str(&request_id)?);
+            request_id
+        }
+    };
+
+    let is_xml = parts
+        .headers
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .map(|content_type| content_type.starts_with("text/xml"))
+        .unwrap_or(false);
+    if !is_xml || !matches!(protocol, ErrorProtocol::Query { .. }) {
+        return Ok(Response::from_parts(parts, body));
+    }
+
+    let bytes = to_bytes(body).await?;
+    let body = match std::str::from_utf8(&bytes).ok().and_then(|xml| add_query_request_id(xml, &request_id)) {
+        Some(xml) => {
+            parts.headers.remove("Content-Length");
+            Body::from(xml)
+        }
+        None => Body::from(bytes),
+    };
+
+    Ok(Response::from_parts(parts, body))
+}
+
+/// `xml` with `request_id` added, if it is a query protocol response without a `<RequestId>`: to the
+/// `<ResponseMetadata>` of a result, or after the `<Error>` of an `<ErrorResponse>`. Returns `None` if nothing needs
+/// to be added.
+pub fn add_query_request_id(xml: &str, request_id: &str) -> Option<String> {
+    if xml.contains("<RequestId>") {
+        return None;
+    }
+
+    let element = format!("<RequestId>{}</RequestId>", escape_xml(request_id));
+    for empty in ["<ResponseMetadata/>", "<ResponseMetadata></ResponseMetadata>"] {
+        if xml.contains(empty) {
+            return Some(xml.replacen(empty, &format!("<ResponseMetadata>{element}</ResponseMetadata>"), 1));
+        }
+    }
+
+    // The root element, skipping any XML declaration.
+    let start = xml.find(|c: char| !c.is_whitespace())?;
+    let root = if xml[start..].starts_with("<?") {
+        let declaration_end = start + xml[start..].find("?>")? + 2;
+        declaration_end + xml[declaration_end..].find('<')?
+    } else {
+        start
+    };
+    let name_end = root + 1 + xml[root + 1..].find(|c: char| c.is_whitespace() || c == '>' || c == '/')?;
+    let name = &xml[root + 1..name_end];
+    if !name.ends_with("Response") {
+        return None;
+    }
+
+    let close = format!("</{name}>");
+    let close_at = xml.rfind(&close)?;
+    let addition = if name == "ErrorResponse" {
+        element
+    } else {
+        format!("<ResponseMetadata>{element}</ResponseMetadata>")
+    };
+
+    Some(format!("{}{addition}{}", &xml[..close_at], &xml[close_at..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{add_query_request_id, RequestIds},
+        crate::protocol::STS,
+        hyper::{
+            body::to_bytes,
+            service::{service_fn, Service},
+            Body, Request, Response,
+        },
+        pretty_assertions::assert_eq,
+        scratchstack_http_framework::RequestId,
+        std::convert::Infallible,
+    };
+
+    const ID: &str = "01234567-89ab-cdef-0123-456789abcdef";
+
+    #[test_log::test]
+    fn test_add_query_request_id() {
+        assert_eq!(
+            add_query_request_id(
+                r#"<GetCallerIdentityResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/"><GetCallerIdentityResult/><ResponseMetadata/></GetCallerIdentityResponse>"#,
+                ID
+            )
+            .unwrap(),
+            r#"<GetCallerIdentityResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/"><GetCallerIdentityResult/><ResponseMetadata><RequestId>01234567-89ab-cdef-0123-456789abcdef</RequestId></ResponseMetadata></GetCallerIdentityResponse>"#
+        );
+        assert_eq!(
+            add_query_request_id(
+                "<?xml version=\"1.0\"?>\n<ListUsersResponse><ListUsersResult/></ListUsersResponse>",
+                ID
+            )
+            .unwrap(),
+            "<?xml version=\"1.0\"?>\n<ListUsersResponse><ListUsersResult/><ResponseMetadata><RequestId>01234567-89ab-cdef-0123-456789abcdef</RequestId></ResponseMetadata></ListUsersResponse>"
+        );
+        assert_eq!(
+            add_query_request_id("<ErrorResponse><Error><Code>X</Code></Error></ErrorResponse>", ID).unwrap(),
+            "<ErrorResponse><Error><Code>X</Code></Error><RequestId>01234567-89ab-cdef-0123-456789abcdef</RequestId></ErrorResponse>"
+        );
+
+        assert_eq!(add_query_request_id("<ErrorResponse><RequestId>other</RequestId></ErrorResponse>", ID), None);
+        assert_eq!(add_query_request_id("<html><body>Bad request</body></html>", ID), None);
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_request_ids() {
+        let mut service = RequestIds {
+            inner: service_fn(|req: Request<Body>| async move {
+                match req.uri().path() {
+                    "/panic" => panic!("handler failed"),
+                    "/error" => Ok::<_, Infallible>(
+                        Response::builder()
+                            .header("Content-Type", "text/xml")
+                            .body(Body::from("<ErrorResponse><Error><Code>Throttling</Code></Error></ErrorResponse>"))
+                            .unwrap(),
+                    ),
+                    _ => {
+                        let request_id = req.extensions().get::<RequestId>().unwrap().to_string();
+                        Ok(Response::builder()
+                            .header("X-Amzn-RequestId", request_id)
+                            .body(Body::from("plain text"))
+                            .unwrap())
+                    }
+                }
+            }),
+            protocol: STS,
+        };
+
+        let request_id = RequestId::new();
+        let mut req = Request::new(Body::empty());
+        req.extensions_mut().insert(request_id);
+        let response = service.call(req).await.unwrap();
+        assert_eq!(response.headers()["X-Amzn-RequestId"], request_id.to_string().as_str());
+        assert_eq!(to_bytes(response.into_body()).await.unwrap(), "plain text");
+
+        let response = service.call(Request::get("/error").body(Body::empty()).unwrap()).await.unwrap();
+        let header = response.headers()["X-Amzn-RequestId"].to_str().unwrap().to_string();
+        let body = to_bytes(response.into_body()).await.unwrap();
+        assert_eq!(
+            std::str::from_utf8(&body).unwrap(),
+            format!(
+                "<ErrorResponse><Error><Code>Throttling</Code></Error><RequestId>{header}</RequestId></ErrorResponse>"
+            )
+        );
+
+        let response = service.call(Request::get("/panic").body(Body::empty()).unwrap()).await.unwrap();
+        assert_eq!(response.status().as_u16(), 500);
+        let header = response.headers()["X-Amzn-RequestId"].to_str().unwrap().to_string();
+        let body = to_bytes(response.into_body()).await.unwrap();
+        let body = std::str::from_utf8(&body).unwrap();
+        assert!(body.contains("<Code>InternalFailure</Code>"), "{body}");
+        assert!(body.contains(&format!("<RequestId>{header}</RequestId>")), "{body}");
+    }
+}
@@ -0,0 +1,508 @@
+//! Validation of request timestamps before signature verification.
+//!
+//! A SigV4 request is signed at the time in `X-Amz-Date` (or `Date`). Header-signed requests are accepted while that
+//! time is within [RequestTimeConfig::clock_skew_seconds] of the server's clock. Presigned requests carry the time in
+//! the query string along with `X-Amz-Expires`, and are accepted from the signing time, less the clock skew, until
+//! `X-Amz-Expires` seconds after it. [WithRequestTimeValidation] applies these rules to every request before the
+//! framework verifies the signature, so header-signed and presigned requests are judged by one policy and rejected
+//! with the same errors whichever path they take.
+//!
+//! The clock skew can also be given as `allowed_clock_skew` in the `[service.<name>.signature]` table, in humantime
+//! format such as `"15m"`; see [SignatureConfig]. It takes precedence over `clock_skew_seconds`.
+//!
+//! Errors report the server's clock: in the message for the query and JSON protocols, and also in `<ServerTime>` for
+//! rest-xml. Requests without a parseable signing time are passed through for the verifier to reject.
+use {
+    crate::protocol::{AwsError, ErrorProtocol},
+    chrono::{DateTime, Duration, NaiveDateTime, Utc},
+    http::{
+        header::{HeaderMap, AUTHORIZATION, DATE},
+        StatusCode,
+    },
+    hyper::{service::Service, Body, Request, Response},
+    log::debug,
+    percent_encoding::percent_decode_str,
+    scratchstack_http_framework::RequestId,
+    serde::{de::Error as _, Deserialize, Deserializer},
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        future::Future,
+        pin::Pin,
+        task::{Context, Poll},
+        time::Duration as StdDuration,
+    },
+    tower::BoxError,
+};
+
+/// The compact ISO 8601 format of `X-Amz-Date`.
+const AMZ_DATE_FORMAT: &str = "%Y%m%dT%H%M%SZ";
+
+const X_AMZ_DATE: &str = "X-Amz-Date";
+const X_AMZ_EXPIRES: &str = "X-Amz-Expires";
+const X_AMZ_SIGNATURE: &str = "X-Amz-Signature";
+
+/// Settings for request timestamp validation, per service.
+#[derive(Clone, Debug, Deserialize, Eq, PartialEq)]
+pub struct RequestTimeConfig {
+    /// How far the signing time of a request may be from the server's clock, in seconds.
+    #[serde(default = "RequestTimeConfig::default_clock_skew_seconds")]
+    pub clock_skew_seconds: i64,
+
+    /// The largest `X-Amz-Expires` accepted on a presigned request, in seconds.
+    #[serde(default = "RequestTimeConfig::default_max_expires_seconds")]
+    pub max_expires_seconds: i64,
+}
+
+impl RequestTimeConfig {
+    fn default_clock_skew_seconds() -> i64 {
+        300
+    }
+
+    /// One week, as in AWS.
+    fn default_max_expires_seconds() -> i64 {
+        604800
+    }
+}
+
+impl RequestTimeConfig {
+    /// The config with the clock skew replaced by `allowed_clock_skew`, if given.
+    pub fn with_allowed_clock_skew(self, allowed_clock_skew: Option<StdDuration>) -> Self {
+        match allowed_clock_skew {
+            None => self,
+            Some(skew) => Self {
+                clock_skew_seconds: i64::try_from(skew.as_secs()).unwrap_or(i64::MAX),
+                ..self
+            },
+        }
+    }
+}
+
+impl Default for RequestTimeConfig {
+    fn default() -> Self {
+        Self {
+            clock_skew_seconds: Self::default_clock_skew_seconds(),
+            max_expires_seconds: Self::default_max_expires_seconds(),
+        }
+    }
+}
+
+/// Signature verification settings, per service.
+#[derive(Clone, Debug, Default, Deserialize, Eq, PartialEq)]
+#[serde(default)]
+pub struct SignatureConfig {
+    /// How far the signing time of a request may be from the server's clock, e.g. `"15m"` or `"90s"`. If unset,
+    /// [RequestTimeConfig::clock_skew_seconds] applies.
+    #[serde(deserialize_with = "deserialize_humantime")]
+    pub allowed_clock_skew: Option<StdDuration>,
+
+    /// Whether Signature Version 2 requests are accepted; see [crate::sigv2].
+    pub allow_sigv2: bool,
+}
+
+fn deserialize_humantime<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<StdDuration>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(value) => humantime::parse_duration(&value).map(Some).map_err(D::Error::custom),
+    }
+}
+
+/// How a request was signed, and the times its validity depends on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum SignedTime {
+    /// Signed in the `Authorization` header at this time.
+    Header(DateTime<Utc>),
+
+    /// Presigned in the query string at this time, valid for the given number of seconds.
+    Query(DateTime<Utc>, i64),
+}
+
+/// Why a request's signing time was not accepted.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum RequestTimeError {
+    /// A header-signed request was signed too long ago.
+    Expired {
+        signed_at: DateTime<Utc>,
+        server_time: DateTime<Utc>,
+        clock_skew_seconds: i64,
+    },
+
+    /// A request was signed too far in the future.
+    NotYetCurrent {
+        signed_at: DateTime<Utc>,
+        server_time: DateTime<Utc>,
+        clock_skew_seconds: i64,
+    },
+
+    /// A presigned request was used after `X-Amz-Expires` had passed.
+    PresignedExpired {
+        signed_at: DateTime<Utc>,
+        expires_seconds: i64,
+        server_time: DateTime<Utc>,
+    },
+
+    /// `X-Amz-Expires` is not a number between 1 and the configured maximum.
+    InvalidExpires(String, i64),
+}
+
+impl RequestTimeError {
+    /// The AWS error code returned to the caller.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Expired {
+                ..
+            }
+            | Self::NotYetCurrent {
+                ..
+            } => "SignatureDoesNotMatch",
+            Self::PresignedExpired {
+                ..
+            } => "RequestExpired",
+            Self::InvalidExpires(..) => "AuthorizationQueryParametersError",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::InvalidExpires(..) => StatusCode::BAD_REQUEST,
+            _ => StatusCode::FORBIDDEN,
+        }
+    }
+
+    /// The server's clock when the request was rejected, if the error concerns it.
+    pub fn server_time(&self) -> Option<DateTime<Utc>> {
+        match self {
+            Self::Expired {
+                server_time,
+                ..
+            }
+            | Self::NotYetCurrent {
+                server_time,
+                ..
+            }
+            | Self::PresignedExpired {
+                server_time,
+                ..
+            } => Some(*server_time),
+            Self::InvalidExpires(..) => None,
+        }
+    }
+
+    /// The error as it is returned to the caller.
+    pub fn to_aws_error(&self) -> AwsError {
+        let error = AwsError::sender(self.status(), self.code(), self.to_string());
+        match self.server_time() {
+            Some(server_time) => error.with_server_time(amz_date(server_time)),
+            None => error,
+        }
+    }
+}
+
+impl Display for RequestTimeError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Expired {
+                signed_at,
+                server_time,
+                clock_skew_seconds,
+            } => write!(
+                f,
+                "Signature expired: {} is now earlier than {} ({} - {} min.)",
+                amz_date(*signed_at),
+                amz_date(*server_time - Duration::seconds(*clock_skew_seconds)),
+                amz_date(*server_time),
+                clock_skew_seconds / 60
+            ),
+            Self::NotYetCurrent {
+                signed_at,
+                server_time,
+                clock_skew_seconds,
+            } => write!(
+                f,
+                "Signature not yet current: {} is still later than {} ({} + {} min.)",
+                amz_date(*signed_at),
+                amz_date(*server_time + Duration::seconds(*clock_skew_seconds)),
+                amz_date(*server_time),
+                clock_skew_seconds / 60
+            ),
+            Self::PresignedExpired {
+                signed_at,
+                expires_seconds,
+                server_time,
+            } => write!(
+                f,
+                "Request has expired: signed at {} for {} seconds; server time is {}",
+                amz_date(*signed_at),
+                expires_seconds,
+                amz_date(*server_time)
+            ),
+            Self::InvalidExpires(value, max) => {
+                write!(f, "X-Amz-Expires must be between 1 and {max} seconds, not {value}")
+            }
+        }
+    }
+}
+
+impl Error for RequestTimeError {}
+
+fn amz_date(time: DateTime<Utc>) -> String {
+    time.format(AMZ_DATE_FORMAT).to_string()
+}
+
+fn parse_amz_date(value: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(value, AMZ_DATE_FORMAT).ok().map(|time| DateTime::from_utc(time, Utc))
+}
+
+/// The decoded value of the query parameter `name`.
+fn query_parameter(query: &str, name: &str) -> Option<String> {
+    query.split('&').find_map(|pair| {
+        let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+        (percent_decode_str(key).decode_utf8_lossy() == name)
+            .then(|| percent_decode_str(value).decode_utf8_lossy().into_owned())
+    })
+}
+
+/// How the request was signed and when, or `None` if it carries no signature or no parseable time. A malformed
+/// `X-Amz-Expires` is an error, as AWS rejects it before checking the signature.
+pub fn signed_time(
+    headers: &HeaderMap,
+    query: &str,
+    config: &RequestTimeConfig,
+) -> Result<Option<SignedTime>, RequestTimeError> {
+    if query_parameter(query, X_AMZ_SIGNATURE).is_some() {
+        let signed_at = match query_parameter(query, X_AMZ_DATE).as_deref().and_then(parse_amz_date) {
+            Some(signed_at) => signed_at,
+            None => return Ok(None),
+        };
+        let expires = query_parameter(query, X_AMZ_EXPIRES).unwrap_or_default();
+        return match expires.parse::<i64>() {
+            Ok(seconds) if seconds >= 1 && seconds <= config.max_expires_seconds => {
+                Ok(Some(SignedTime::Query(signed_at, seconds)))
+            }
+            _ => Err(RequestTimeError::InvalidExpires(expires, config.max_expires_seconds)),
+        };
+    }
+
+    if !headers.contains_key(AUTHORIZATION) {
+        return Ok(None);
+    }
+
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    let signed_at = match header(X_AMZ_DATE) {
+        Some(value) => parse_amz_date(value),
+        None => header(DATE.as_str())
+            .and_then(|value| DateTime::parse_from_rfc2822(value).ok())
+            .map(|time| time.with_timezone(&Utc)),
+    };
+    Ok(signed_at.map(SignedTime::Header))
+}
+
+/// Check the signing time of a request against the server's clock `now`.
+pub fn check_request_time(
+    headers: &HeaderMap,
+    query: &str,
+    now: DateTime<Utc>,
+    config: &RequestTimeConfig,
+) -> Result<(), RequestTimeError> {
+    let skew = Duration::seconds(config.clock_skew_seconds);
+    let (signed_at, not_after) = match signed_time(headers, query, config)? {
+        None => return Ok(()),
+        Some(SignedTime::Header(signed_at)) => (signed_at, None),
+        Some(SignedTime::Query(signed_at, seconds)) => (signed_at, Some(seconds)),
+    };
+
+    if signed_at > now + skew {
+        return Err(RequestTimeError::NotYetCurrent {
+            signed_at,
+            server_time: now,
+            clock_skew_seconds: config.clock_skew_seconds,
+        });
+    }
+
+    match not_after {
+        None if signed_at < now - skew => Err(RequestTimeError::Expired {
+            signed_at,
+            server_time: now,
+            clock_skew_seconds: config.clock_skew_seconds,
+        }),
+        Some(expires_seconds) if now > signed_at + Duration::seconds(expires_seconds) => {
+            Err(RequestTimeError::PresignedExpired {
+                signed_at,
+                expires_seconds,
+                server_time: now,
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Wraps a make-service (such as `SpawnService`) so each per-connection service checks request timestamps before
+/// the signature is verified.
+#[derive(Clone, Debug)]
+pub struct WithRequestTimeValidation<M> {
+    inner: M,
+    config: RequestTimeConfig,
+    protocol: ErrorProtocol,
+}
+
+impl<M> WithRequestTimeValidation<M> {
+    /// Wrap `inner`. Errors are rendered in the shape `protocol` uses.
+    pub fn new(inner: M, config: RequestTimeConfig, protocol: ErrorProtocol) -> Self {
+        Self {
+            inner,
+            config,
+            protocol,
+        }
+    }
+}
+
+impl<T, M> Service<T> for WithRequestTimeValidation<M>
+where
+    M: Service<T>,
+    M::Future: Send + 'static,
+{
+    type Response = RequestTimeValidation<M::Response>;
+    type Error = M::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, target: T) -> Self::Future {
+        let config = self.config.clone();
+        let protocol = self.protocol;
+        let future = self.inner.call(target);
+        Box::pin(async move {
+            Ok(RequestTimeValidation {
+                inner: future.await?,
+                config,
+                protocol,
+            })
+        })
+    }
+}
+
+/// A per-connection service that rejects requests signed outside the accepted time window.
+#[derive(Clone, Debug)]
+pub struct RequestTimeValidation<S> {
+    inner: S,
+    config: RequestTimeConfig,
+    protocol: ErrorProtocol,
+}
+
+impl<S> Service<Request<Body>> for RequestTimeValidation<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>>,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let query = req.uri().query().unwrap_or_default();
+        if let Err(e) = check_request_time(req.headers(), query, Utc::now(), &self.config) {
+            debug!("Rejecting request signed outside the accepted time window: {}", e);
+            let request_id = req.extensions().get::<RequestId>().copied().unwrap_or_else(RequestId::new);
+            let response = self.protocol.response(&e.to_aws_error(), request_id);
+            return Box::pin(async move { response });
+        }
+
+        let future = self.inner.call(req);
+        Box::pin(async move { future.await.map_err(Into::into) })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{check_request_time, RequestTimeConfig, RequestTimeError},
+        crate::protocol::S3,
+        chrono::{DateTime, Utc},
+        http::header::{HeaderMap, HeaderValue, AUTHORIZATION},
+        pretty_assertions::assert_eq,
+        std::time::Duration,
+    };
+
+    fn now() -> DateTime<Utc> {
+        "2015-08-30T12:41:00Z".parse().unwrap()
+    }
+
+    fn signed_headers(x_amz_date: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(AUTHORIZATION, HeaderValue::from_static("AWS4-HMAC-SHA256 Credential=AKIDEXAMPLE/..."));
+        headers.insert("X-Amz-Date", HeaderValue::from_str(x_amz_date).unwrap());
+        headers
+    }
+
+    #[test_log::test]
+    fn test_header_signed() {
+        let config = RequestTimeConfig::default();
+        check_request_time(&signed_headers("20150830T123600Z"), "", now(), &config).unwrap();
+        check_request_time(&signed_headers("20150830T124600Z"), "", now(), &config).unwrap();
+
+        let e = check_request_time(&signed_headers("20150830T123559Z"), "", now(), &config).unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("SignatureDoesNotMatch", 403));
+        assert_eq!(
+            e.to_string(),
+            "Signature expired: 20150830T123559Z is now earlier than 20150830T123600Z (20150830T124100Z - 5 min.)"
+        );
+
+        let e = check_request_time(&signed_headers("20150830T124601Z"), "", now(), &config).unwrap_err();
+        assert!(matches!(e, RequestTimeError::NotYetCurrent { .. }));
+
+        // Unsigned requests and unparseable dates are left to the verifier.
+        check_request_time(&HeaderMap::new(), "", now(), &config).unwrap();
+        check_request_time(&signed_headers("yesterday"), "", now(), &config).unwrap();
+
+        let config = RequestTimeConfig {
+            clock_skew_seconds: 900,
+            ..RequestTimeConfig::default()
+        };
+        check_request_time(&signed_headers("20150830T123000Z"), "", now(), &config).unwrap();
+
+        let config = config.with_allowed_clock_skew(Some(Duration::from_secs(60)));
+        let e = check_request_time(&signed_headers("20150830T123900Z"), "", now(), &config).unwrap_err();
+        assert!(e.to_string().ends_with("(20150830T124100Z - 1 min.)"));
+    }
+
+    #[test_log::test]
+    fn test_presigned() {
+        let config = RequestTimeConfig::default();
+        let query = |date: &str, expires: &str| {
+            format!("Action=GetCallerIdentity&X-Amz-Date={date}&X-Amz-Expires={expires}&X-Amz-Signature=abcd")
+        };
+
+        // Presigned requests ignore the clock skew once signed, but not before.
+        check_request_time(&HeaderMap::new(), &query("20150830T120000Z", "3600"), now(), &config).unwrap();
+        let e = check_request_time(&HeaderMap::new(), &query("20150830T113000Z", "3600"), now(), &config).unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("RequestExpired", 403));
+        assert_eq!(e.server_time(), Some(now()));
+        let e = check_request_time(&HeaderMap::new(), &query("20150830T130000Z", "3600"), now(), &config).unwrap_err();
+        assert!(matches!(e, RequestTimeError::NotYetCurrent { .. }));
+
+        for expires in ["0", "604801", "soon", ""] {
+            let e =
+                check_request_time(&HeaderMap::new(), &query("20150830T124000Z", expires), now(), &config).unwrap_err();
+            assert_eq!((e.code(), e.status().as_u16()), ("AuthorizationQueryParametersError", 400), "{expires}");
+        }
+
+        // The query takes precedence over any Authorization header, as in the verifier.
+        let headers = signed_headers("20150830T000000Z");
+        check_request_time(&headers, &query("20150830T124000Z", "60"), now(), &config).unwrap();
+    }
+
+    #[test_log::test]
+    fn test_server_time_in_rest_xml() {
+        let config = RequestTimeConfig::default();
+        let e = check_request_time(&signed_headers("20150830T120000Z"), "", now(), &config).unwrap_err();
+        let rendered = S3.render(&e.to_aws_error(), "4442587FB7D0A2F9");
+        assert!(rendered.body.contains("<ServerTime>20150830T124100Z</ServerTime><RequestId>"), "{}", rendered.body);
+    }
+}
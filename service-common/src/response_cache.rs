@@ -0,0 +1,269 @@
+//! Short-lived caching of read operation responses.
+//!
+//! Audit tooling tends to call expensive read operations such as `GetAccountAuthorizationDetails` over and over. With
+//! a [ResponseCache], the response to a successful call of one of [ResponseCacheConfig::operations] is kept for
+//! [ResponseCacheConfig::ttl_seconds], and an identical request from the same caller is answered from it without
+//! touching the database.
+//!
+//! Requests are identical if every parameter, including `Action` and `Version`, matches. A successful operation that
+//! is not a read (its name does not start with `Get` or `List`) invalidates every entry for the caller's account, so
+//! a caller sees its own changes immediately. Changes made by other replicas are only seen once entries expire, which
+//! is why the TTL should stay short.
+use {
+    crate::context::RequestContext,
+    http::header::{HeaderValue, CONTENT_TYPE},
+    hyper::{body::to_bytes, Body, Response},
+    log::debug,
+    serde::Deserialize,
+    std::{
+        collections::{BTreeMap, HashMap},
+        sync::Mutex,
+        time::Duration,
+    },
+    tokio::time::Instant,
+    tower::BoxError,
+};
+
+/// Settings for caching read operation responses.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ResponseCacheConfig {
+    /// How long a response is reused, in seconds.
+    #[serde(default = "ResponseCacheConfig::default_ttl_seconds")]
+    pub ttl_seconds: u64,
+
+    /// The most responses kept at once. Responses are not cached while the cache is full of unexpired entries.
+    #[serde(default = "ResponseCacheConfig::default_max_entries")]
+    pub max_entries: usize,
+
+    /// The operations whose responses are cached; by default [DEFAULT_CACHED_OPERATIONS].
+    #[serde(default = "ResponseCacheConfig::default_operations")]
+    pub operations: Vec<String>,
+}
+
+/// Read operations that are expensive to answer and are called repeatedly by audit tooling.
+pub const DEFAULT_CACHED_OPERATIONS: &[&str] =
+    &["GetAccountAuthorizationDetails", "ListAccessKeys", "ListEntitiesForPolicy", "ListUsers"];
+
+impl ResponseCacheConfig {
+    fn default_ttl_seconds() -> u64 {
+        5
+    }
+
+    fn default_max_entries() -> usize {
+        1000
+    }
+
+    fn default_operations() -> Vec<String> {
+        DEFAULT_CACHED_OPERATIONS.iter().map(|operation| operation.to_string()).collect()
+    }
+}
+
+impl Default for ResponseCacheConfig {
+    fn default() -> Self {
+        Self {
+            ttl_seconds: Self::default_ttl_seconds(),
+            max_entries: Self::default_max_entries(),
+            operations: Self::default_operations(),
+        }
+    }
+}
+
+/// Whether `action` only reads state, and so does not invalidate cached responses.
+pub fn is_read_only(action: &str) -> bool {
+    action.starts_with("Get") || action.starts_with("List")
+}
+
+/// The caller and the sorted request parameters.
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
+struct CacheKey {
+    caller: String,
+    parameters: Vec<(String, String)>,
+}
+
+impl CacheKey {
+    /// The key for `context`, or `None` if the caller cannot be identified.
+    fn new(context: &RequestContext) -> Option<Self> {
+        let caller = match context.caller_arn() {
+            Some(arn) => arn.to_string(),
+            None => context.user_id()?.to_string(),
+        };
+        let parameters = context.parameters().iter().map(|(k, v)| (k.clone(), v.clone())).collect::<BTreeMap<_, _>>();
+
+        Some(Self {
+            caller,
+            parameters: parameters.into_iter().collect(),
+        })
+    }
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    account_id: Option<String>,
+    content_type: Option<HeaderValue>,
+    body: String,
+
+    /// The request id embedded in `body`, replaced with the current request's id when the response is reused.
+    request_id: String,
+    expires_at: Instant,
+}
+
+/// A cache of read operation responses; see the [module documentation][self].
+#[derive(Debug)]
+pub struct ResponseCache {
+    config: ResponseCacheConfig,
+    entries: Mutex<HashMap<CacheKey, CacheEntry>>,
+}
+
+impl ResponseCache {
+    pub fn new(config: ResponseCacheConfig) -> Self {
+        Self {
+            config,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Whether responses to `action` are cached.
+    pub fn is_cacheable(&self, action: &str) -> bool {
+        self.config.operations.iter().any(|operation| operation == action)
+    }
+
+    /// The cached response to the request in `context`, if there is an unexpired one.
+    pub fn get(&self, context: &RequestContext) -> Option<Response<Body>> {
+        let key = CacheKey::new(context)?;
+        let mut entries = self.entries.lock().expect("response cache poisoned");
+        let entry = entries.get(&key)?;
+        if entry.expires_at <= Instant::now() {
+            entries.remove(&key);
+            return None;
+        }
+
+        let request_id = context.request_id().to_string();
+        debug!("{} Answering {} from the response cache", request_id, context.parameter("Action").unwrap_or_default());
+        let body = entry.body.replace(&entry.request_id, &request_id);
+        let mut response = Response::builder().header("X-Amzn-RequestId", request_id.as_str());
+        if let Some(content_type) = &entry.content_type {
+            response = response.header(CONTENT_TYPE, content_type.clone());
+        }
+        response.body(Body::from(body)).ok()
+    }
+
+    /// Cache `response`, a successful response to the request in `context`, and return it.
+    pub async fn insert(&self, context: &RequestContext, response: Response<Body>) -> Result<Response<Body>, BoxError> {
+        let key = match CacheKey::new(context) {
+            Some(key) => key,
+            None => return Ok(response),
+        };
+
+        let (parts, body) = response.into_parts();
+        let body = to_bytes(body).await?;
+        let text = match std::str::from_utf8(&body) {
+            Ok(text) => text.to_string(),
+            Err(_) => return Ok(Response::from_parts(parts, Body::from(body))),
+        };
+
+        let now = Instant::now();
+        let mut entries = self.entries.lock().expect("response cache poisoned");
+        if entries.len() >= self.config.max_entries {
+            entries.retain(|_, entry| entry.expires_at > now);
+        }
+        if entries.len() < self.config.max_entries {
+            entries.insert(
+                key,
+                CacheEntry {
+                    account_id: context.account_id(),
+                    content_type: parts.headers.get(CONTENT_TYPE).cloned(),
+                    body: text,
+                    request_id: context.request_id().to_string(),
+                    expires_at: now + Duration::from_secs(self.config.ttl_seconds),
+                },
+            );
+        }
+
+        Ok(Response::from_parts(parts, Body::from(body)))
+    }
+
+    /// Drop every cached response to callers in `account_id`.
+    pub fn invalidate_account(&self, account_id: &str) {
+        let mut entries = self.entries.lock().expect("response cache poisoned");
+        entries.retain(|_, entry| entry.account_id.as_deref() != Some(account_id));
+    }
+
+    /// Drop every cached response.
+    pub fn invalidate_all(&self) {
+        self.entries.lock().expect("response cache poisoned").clear();
+    }
+
+    /// The number of cached responses, including expired ones that have not been dropped yet.
+    pub fn len(&self) -> usize {
+        self.entries.lock().expect("response cache poisoned").len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{is_read_only, ResponseCache, ResponseCacheConfig},
+        crate::context::RequestContext,
+        hyper::{body::to_bytes, Body, Response},
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{Principal, PrincipalIdentity, User},
+        std::{collections::HashMap, time::Duration},
+    };
+
+    fn context(user_name: &str, parameters: &[(&str, &str)]) -> RequestContext {
+        let user = User::new("aws", "123456789012", "/", user_name).unwrap();
+        let parameters =
+            parameters.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<HashMap<String, String>>();
+        RequestContext::builder()
+            .principal(Principal::from(vec![PrincipalIdentity::from(user)]))
+            .parameters(parameters)
+            .build()
+            .unwrap()
+    }
+
+    #[test_log::test(tokio::test(start_paused = true))]
+    async fn test_response_cache() {
+        assert!(is_read_only("ListUsers"));
+        assert!(!is_read_only("CreateUser"));
+
+        let cache = ResponseCache::new(ResponseCacheConfig::default());
+        assert!(cache.is_cacheable("ListUsers"));
+        assert!(!cache.is_cacheable("GetApiDocs"));
+
+        let first = context("Alice", &[("Action", "ListUsers"), ("Version", "2010-05-08")]);
+        assert!(cache.get(&first).is_none());
+        let body = format!("<ListUsersResponse><RequestId>{}</RequestId></ListUsersResponse>", first.request_id());
+        let response = cache.insert(&first, Response::new(Body::from(body.clone()))).await.unwrap();
+        assert_eq!(to_bytes(response.into_body()).await.unwrap(), body.as_bytes());
+
+        // The same request gets the cached body, with its own request id.
+        let second = context("Alice", &[("Version", "2010-05-08"), ("Action", "ListUsers")]);
+        let response = cache.get(&second).unwrap();
+        let body = String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap();
+        assert_eq!(
+            body,
+            format!("<ListUsersResponse><RequestId>{}</RequestId></ListUsersResponse>", second.request_id())
+        );
+
+        // Other callers and other parameters miss.
+        assert!(cache.get(&context("Bob", &[("Action", "ListUsers"), ("Version", "2010-05-08")])).is_none());
+        assert!(cache.get(&context("Alice", &[("Action", "ListUsers"), ("PathPrefix", "/a/")])).is_none());
+
+        // Entries expire.
+        tokio::time::advance(Duration::from_secs(6)).await;
+        assert!(cache.get(&second).is_none());
+        assert!(cache.is_empty());
+
+        // And are dropped when the account changes.
+        cache.insert(&first, Response::new(Body::from("<ListUsersResponse/>"))).await.unwrap();
+        assert_eq!(cache.len(), 1);
+        cache.invalidate_account("999999999999");
+        assert_eq!(cache.len(), 1);
+        cache.invalidate_account("123456789012");
+        assert!(cache.is_empty());
+    }
+}
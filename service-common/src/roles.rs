@@ -0,0 +1,260 @@
+//! IAM role operations, written against [ControlPlaneStore].
+//!
+//! A role's trust policy is checked with [TrustPolicy::parse] when the role is created, so a role that could never
+//! be assumed is rejected with `MalformedPolicyDocument` up front rather than failing every AssumeRole later.
+use {
+    crate::{
+        context::RequestContext,
+        ids::{unique_id, ROLE_ID_PREFIX},
+        inline_policies::{decode_policy_document, encode_policy_document},
+        operation::{output::query_response, ValidationError},
+        operation_input, operation_output,
+        protocol::IAM_XML_NS,
+        store::{ControlPlaneStore, Role, StoreError, Tag},
+        tags::{response_tags, validate_tags, TagError},
+        trust::{TrustError, TrustPolicy},
+    },
+    chrono::{DateTime, Utc},
+    http::StatusCode,
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// The maximum session duration of a role when none is given, in seconds.
+pub const DEFAULT_MAX_SESSION_DURATION: i64 = 3600;
+
+operation_input! {
+    /// Input for the CreateRole operation.
+    pub struct CreateRoleInput {
+        "RoleName" => pub role_name: String where length(1, 64), pattern(r"[\w+=,.@-]+"),
+        "Path" => pub path: Option<String> where length(1, 512), pattern(r"(/)|(/[\x21-\x7e]+/)"),
+        "AssumeRolePolicyDocument" => pub assume_role_policy_document: String where length(1, 131072),
+        "Description" => pub description: Option<String>
+            where length(0, 1000), pattern(r"[\x09\x0a\x0d\x20-\x7e\xa1-\xff]*"),
+        "MaxSessionDuration" => pub max_session_duration: Option<i64> where range(3600, 43200),
+        "Tags" => pub tags: Vec<Tag>,
+    }
+}
+
+/// Errors from role operations.
+#[derive(Debug)]
+pub enum RoleError {
+    Validation(ValidationError),
+
+    /// The trust policy could not be parsed.
+    Trust(TrustError),
+    Tag(TagError),
+    Store(StoreError),
+}
+
+impl RoleError {
+    /// The IAM error code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Validation(e) => e.code(),
+            Self::Trust(e) => e.code(),
+            Self::Tag(e) => e.code(),
+            Self::Store(e) => e.code(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Validation(_) | Self::Trust(_) => StatusCode::BAD_REQUEST,
+            Self::Tag(e) => e.status(),
+            Self::Store(e) => e.status(),
+        }
+    }
+}
+
+impl Error for RoleError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Validation(e) => Some(e),
+            Self::Trust(e) => Some(e),
+            Self::Tag(e) => Some(e),
+            Self::Store(e) => Some(e),
+        }
+    }
+}
+
+impl Display for RoleError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Validation(e) => write!(f, "{e}"),
+            Self::Trust(e) => write!(f, "{e}"),
+            Self::Tag(e) => write!(f, "{e}"),
+            Self::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<ValidationError> for RoleError {
+    fn from(e: ValidationError) -> Self {
+        Self::Validation(e)
+    }
+}
+
+impl From<TrustError> for RoleError {
+    fn from(e: TrustError) -> Self {
+        Self::Trust(e)
+    }
+}
+
+impl From<TagError> for RoleError {
+    fn from(e: TagError) -> Self {
+        Self::Tag(e)
+    }
+}
+
+impl From<StoreError> for RoleError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+/// A newly created role and the tags it was created with.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CreateRoleOutput {
+    pub role: Role,
+    pub tags: Vec<Tag>,
+}
+
+impl CreateRoleOutput {
+    /// The `CreateRoleResponse` body. The trust policy is URL-encoded, as IAM returns it.
+    pub fn to_xml(&self, partition: &str, request_id: &str) -> String {
+        let role = &self.role;
+        let result = CreateRoleResult {
+            role: RoleElement {
+                path: &role.path,
+                role_name: &role.role_name,
+                role_id: &role.role_id,
+                arn: format!("arn:{partition}:iam::{}:role{}{}", role.account_id, role.path, role.role_name),
+                create_date: role.created_at,
+                assume_role_policy_document: encode_policy_document(&role.assume_role_policy_document),
+                description: role.description.as_deref(),
+                max_session_duration: role.max_session_duration,
+                tags: response_tags(&self.tags),
+            },
+        };
+        query_response(IAM_XML_NS, "CreateRole", &result, request_id)
+    }
+}
+
+operation_output! {
+    /// A role, with the children in the order AWS writes them.
+    struct RoleElement<'a> {
+        "Path" => path: &'a str,
+        "RoleName" => role_name: &'a str,
+        "RoleId" => role_id: &'a str,
+        "Arn" => arn: String,
+        "CreateDate" => create_date: DateTime<Utc>,
+        "AssumeRolePolicyDocument" => assume_role_policy_document: String,
+        "Description" => description: Option<&'a str>,
+        "MaxSessionDuration" => max_session_duration: i64,
+        "Tags" => tags: Option<&'a [Tag]>,
+    }
+}
+
+operation_output! {
+    struct CreateRoleResult<'a> {
+        "Role" => role: RoleElement<'a>,
+    }
+}
+
+/// Create a role in the caller's account. The trust policy and tags are validated before anything is stored.
+pub async fn create_role(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    input: &CreateRoleInput,
+) -> Result<CreateRoleOutput, RoleError> {
+    let assume_role_policy_document = decode_policy_document(&input.assume_role_policy_document);
+    TrustPolicy::parse(&assume_role_policy_document)?;
+    validate_tags(&input.tags)?;
+
+    let role = Role {
+        role_id: unique_id(ROLE_ID_PREFIX),
+        account_id: context.account_id().unwrap_or_default(),
+        role_name: input.role_name.clone(),
+        path: input.path.clone().unwrap_or_else(|| "/".to_string()),
+        permissions_boundary: None,
+        description: input.description.clone(),
+        assume_role_policy_document,
+        max_session_duration: input.max_session_duration.unwrap_or(DEFAULT_MAX_SESSION_DURATION),
+        created_at: Utc::now(),
+    };
+    store.create_role(&role, &input.tags).await?;
+
+    Ok(CreateRoleOutput {
+        role,
+        tags: input.tags.clone(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{create_role, CreateRoleInput, RoleError, DEFAULT_MAX_SESSION_DURATION},
+        crate::{
+            context::RequestContext,
+            operation::{parameters, FromParameters},
+            store::{ControlPlaneStore, EntityKind, MemoryStore},
+        },
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{Principal, PrincipalIdentity, User as PrincipalUser},
+    };
+
+    const TRUST_POLICY: &str = r#"{"Version":"2012-10-17","Statement":[{"Effect":"Allow","Principal":{"AWS":"123456789012"},"Action":"sts:AssumeRole"}]}"#;
+
+    #[test_log::test(tokio::test)]
+    async fn test_create_role_with_tags() {
+        let store = MemoryStore::new();
+        let admin = PrincipalUser::new("aws", "123456789012", "/", "Admin").unwrap();
+        let context =
+            RequestContext::builder().principal(Principal::from(vec![PrincipalIdentity::from(admin)])).build().unwrap();
+
+        let input = CreateRoleInput::from_parameters(&parameters(&[
+            ("RoleName", "Deployer"),
+            ("AssumeRolePolicyDocument", TRUST_POLICY),
+            ("Tags.member.1.Key", "Team"),
+            ("Tags.member.1.Value", "Platform"),
+        ]))
+        .unwrap();
+        let output = create_role(&store, &context, &input).await.unwrap();
+        assert!(output.role.role_id.starts_with("AROA"));
+        assert_eq!(output.role.max_session_duration, DEFAULT_MAX_SESSION_DURATION);
+        assert_eq!(store.list_tags(EntityKind::Role, &output.role.role_id).await.unwrap(), output.tags);
+
+        let xml = output.to_xml("aws", "01234567-89ab-cdef-0123-456789abcdef");
+        assert!(xml.contains("<Arn>arn:aws:iam::123456789012:role/Deployer</Arn>"), "{xml}");
+        assert!(xml.contains("<AssumeRolePolicyDocument>%7B%22Version%22%3A%222012-10-17%22"), "{xml}");
+        assert!(
+            xml.contains(
+                "<MaxSessionDuration>3600</MaxSessionDuration>\
+                 <Tags><member><Key>Team</Key><Value>Platform</Value></member></Tags></Role>"
+            ),
+            "{xml}"
+        );
+
+        let input = CreateRoleInput::from_parameters(&parameters(&[
+            ("RoleName", "Broken"),
+            ("AssumeRolePolicyDocument", r#"{"Version":"2012-10-17"}"#),
+        ]))
+        .unwrap();
+        let e = create_role(&store, &context, &input).await.unwrap_err();
+        assert!(matches!(e, RoleError::Trust(_)));
+        assert_eq!((e.code(), e.status().as_u16()), ("MalformedPolicyDocument", 400));
+
+        let e = CreateRoleInput::from_parameters(&parameters(&[
+            ("RoleName", "Deployer"),
+            ("AssumeRolePolicyDocument", TRUST_POLICY),
+            ("MaxSessionDuration", "60"),
+            ("Tags.member.1.Key", "Team"),
+        ]))
+        .unwrap_err();
+        let names: Vec<_> = e.violations().iter().map(|v| v.name().to_string()).collect();
+        assert_eq!(names, vec!["MaxSessionDuration", "Tags.1.member.Value"]);
+    }
+}
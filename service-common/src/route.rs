@@ -0,0 +1,283 @@
+use {
+    crate::mirror::{rebase_uri, PercentSampler},
+    hyper::{
+        body::{to_bytes, Bytes},
+        client::HttpConnector,
+        http::uri::InvalidUri,
+        service::Service,
+        Body, Client, Request, Response, Uri,
+    },
+    serde::Deserialize,
+    std::{
+        collections::HashSet,
+        fmt::{Debug, Formatter, Result as FmtResult},
+        future::Future,
+        pin::Pin,
+        sync::Arc,
+        task::{Context, Poll},
+    },
+    tower::BoxError,
+};
+
+/// Settings for sending some requests to an alternate implementation of a service.
+#[derive(Clone, Debug, Deserialize)]
+pub struct RoutingConfig {
+    /// The base URL of the alternate implementation, e.g. `http://127.0.0.1:9191`. Only plain HTTP is supported.
+    pub alternate_url: String,
+
+    /// Actions that are always sent to the alternate implementation.
+    #[serde(default)]
+    pub actions: Vec<String>,
+
+    /// If set, a request with this header set to `primary` or `alternate` is sent to that implementation, overriding
+    /// the other rules.
+    pub header: Option<String>,
+
+    /// The percentage of remaining requests sent to the alternate implementation, from 0 to 100.
+    #[serde(default)]
+    pub percent: u32,
+}
+
+/// The implementation chosen for a request by [Split].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Route {
+    Primary,
+    Alternate,
+}
+
+/// A service that sends each request to one of two implementations, so operations can be moved from one
+/// implementation to another individually and moved back by changing the configuration.
+///
+/// Rules are applied in order: the override header, then the action list, then the percentage. Requests that match
+/// no rule go to the primary implementation.
+#[derive(Clone)]
+pub struct Split<P, A> {
+    primary: P,
+    alternate: Option<A>,
+    rules: Arc<SplitRules>,
+}
+
+impl<P, A> Split<P, A> {
+    /// Create a split that sends all requests to `primary`.
+    pub fn new(primary: P) -> Self {
+        Self {
+            primary,
+            alternate: None,
+            rules: Arc::new(SplitRules::default()),
+        }
+    }
+
+    /// Send requests matching the rules in `config` to `alternate`.
+    pub fn with_alternate(self, alternate: A, config: &RoutingConfig) -> Self {
+        Self {
+            primary: self.primary,
+            alternate: Some(alternate),
+            rules: Arc::new(SplitRules::new(config)),
+        }
+    }
+}
+
+impl<P: Debug, A: Debug> Debug for Split<P, A> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Split")
+            .field("primary", &self.primary)
+            .field("alternate", &self.alternate)
+            .field("rules", &self.rules)
+            .finish()
+    }
+}
+
+type BoxFuture = Pin<Box<dyn Future<Output = Result<Response<Body>, BoxError>> + Send>>;
+
+impl<P, A> Service<Request<Body>> for Split<P, A>
+where
+    P: Service<Request<Body>, Response = Response<Body>, Error = BoxError> + Clone + Send + 'static,
+    P::Future: Send,
+    A: Service<Request<Body>, Response = Response<Body>, Error = BoxError> + Clone + Send + 'static,
+    A::Future: Send,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.primary.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let alternate = match &self.alternate {
+            None => return Box::pin(self.primary.call(req)),
+            Some(alternate) => alternate.clone(),
+        };
+
+        if let Some(route) = self.rules.route_by_header(&req) {
+            return self.dispatch(route, alternate, req);
+        }
+
+        if self.rules.actions.is_empty() {
+            let route = self.rules.route_by_percent();
+            return self.dispatch(route, alternate, req);
+        }
+
+        // Routing by action requires the body, since the action is usually in the form parameters.
+        let clone = self.primary.clone();
+        let mut primary = std::mem::replace(&mut self.primary, clone);
+        let rules = self.rules.clone();
+
+        Box::pin(async move {
+            let (parts, body) = req.into_parts();
+            let body = to_bytes(body).await?;
+            let route = match action(parts.uri.query(), &body) {
+                Some(action) if rules.actions.contains(&action) => Route::Alternate,
+                _ => rules.route_by_percent(),
+            };
+
+            let req = Request::from_parts(parts, Body::from(body));
+            match route {
+                Route::Primary => primary.call(req).await,
+                Route::Alternate => call_ready(alternate, req).await,
+            }
+        })
+    }
+}
+
+impl<P, A> Split<P, A>
+where
+    P: Service<Request<Body>, Response = Response<Body>, Error = BoxError> + Clone + Send + 'static,
+    P::Future: Send,
+    A: Service<Request<Body>, Response = Response<Body>, Error = BoxError> + Clone + Send + 'static,
+    A::Future: Send,
+{
+    fn dispatch(&mut self, route: Route, alternate: A, req: Request<Body>) -> BoxFuture {
+        match route {
+            Route::Primary => Box::pin(self.primary.call(req)),
+            Route::Alternate => Box::pin(call_ready(alternate, req)),
+        }
+    }
+}
+
+/// Wait for `service` to become ready, then call it. The primary implementation is polled by [Split::poll_ready];
+/// the alternate is only polled when it is chosen.
+async fn call_ready<S>(mut service: S, req: Request<Body>) -> Result<Response<Body>, BoxError>
+where
+    S: Service<Request<Body>, Response = Response<Body>, Error = BoxError>,
+{
+    futures::future::poll_fn(|cx| service.poll_ready(cx)).await?;
+    service.call(req).await
+}
+
+/// Returns the first `Action` parameter in the query string or form body.
+fn action(query: Option<&str>, body: &Bytes) -> Option<String> {
+    let query = form_urlencoded::parse(query.unwrap_or("").as_bytes());
+    let body = form_urlencoded::parse(body);
+    query.chain(body).find(|(key, _)| key == "Action").map(|(_, value)| value.into_owned())
+}
+
+#[derive(Debug, Default)]
+struct SplitRules {
+    actions: HashSet<String>,
+    header: Option<String>,
+    sampler: Option<PercentSampler>,
+}
+
+impl SplitRules {
+    fn new(config: &RoutingConfig) -> Self {
+        Self {
+            actions: config.actions.iter().cloned().collect(),
+            header: config.header.clone(),
+            sampler: Some(PercentSampler::new(config.percent)),
+        }
+    }
+
+    fn route_by_header<B>(&self, req: &Request<B>) -> Option<Route> {
+        let header = self.header.as_ref()?;
+        match req.headers().get(header.as_str()).and_then(|v| v.to_str().ok()) {
+            Some(v) if v.eq_ignore_ascii_case("primary") => Some(Route::Primary),
+            Some(v) if v.eq_ignore_ascii_case("alternate") => Some(Route::Alternate),
+            _ => None,
+        }
+    }
+
+    fn route_by_percent(&self) -> Route {
+        match &self.sampler {
+            Some(sampler) if sampler.sample() => Route::Alternate,
+            _ => Route::Primary,
+        }
+    }
+}
+
+/// A service that forwards requests, with their original headers, to another HTTP endpoint.
+///
+/// Because the `Host` header and path are unchanged, the request signature remains valid for the receiving endpoint.
+#[derive(Clone)]
+pub struct Proxy {
+    base: Uri,
+    client: Client<HttpConnector>,
+}
+
+impl Proxy {
+    pub fn new(base: &str) -> Result<Self, InvalidUri> {
+        Ok(Self {
+            base: base.parse()?,
+            client: Client::new(),
+        })
+    }
+}
+
+impl Debug for Proxy {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Proxy").field("base", &self.base.to_string()).finish()
+    }
+}
+
+impl Service<Request<Body>> for Proxy {
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = BoxFuture;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let client = self.client.clone();
+        let base = self.base.clone();
+
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+            parts.uri = rebase_uri(&base, &parts.uri)?;
+            // Extensions (principal, session data) are local to this process and are not forwarded.
+            parts.extensions.clear();
+            Ok(client.request(Request::from_parts(parts, body)).await?)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{action, Route, RoutingConfig, SplitRules},
+        hyper::{body::Bytes, Request},
+        pretty_assertions::assert_eq,
+    };
+
+    #[test_log::test]
+    fn test_rules() {
+        let rules = SplitRules::new(&RoutingConfig {
+            alternate_url: "http://127.0.0.1:9191".to_string(),
+            actions: vec!["CreateUser".to_string()],
+            header: Some("X-Scratchstack-Implementation".to_string()),
+            percent: 0,
+        });
+
+        let req = Request::builder().header("X-Scratchstack-Implementation", "Alternate").body(()).unwrap();
+        assert_eq!(rules.route_by_header(&req), Some(Route::Alternate));
+        let req = Request::builder().body(()).unwrap();
+        assert_eq!(rules.route_by_header(&req), None);
+        assert_eq!(rules.route_by_percent(), Route::Primary);
+
+        assert_eq!(action(Some("Action=GetUser"), &Bytes::from("Action=CreateUser")).as_deref(), Some("GetUser"));
+        assert_eq!(action(None, &Bytes::from("Version=2010-05-08&Action=CreateUser")).as_deref(), Some("CreateUser"));
+        assert_eq!(action(None, &Bytes::new()), None);
+    }
+}
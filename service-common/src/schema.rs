@@ -0,0 +1,183 @@
+use {
+    log::info,
+    sqlx::{
+        any::{AnyKind, AnyPool},
+        Error as SqlxError,
+    },
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// The version of the `iam` schema this code was written against. This must be updated whenever a migration
+/// changes the `iam` schema and the code starts relying on the change.
+pub const IAM_SCHEMA_VERSION: i64 = 7;
+
+/// A database schema and the version the running code expects.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ExpectedSchema {
+    pub name: &'static str,
+    pub version: i64,
+}
+
+impl ExpectedSchema {
+    pub const IAM: Self = Self {
+        name: "iam",
+        version: IAM_SCHEMA_VERSION,
+    };
+
+    /// Compare the version recorded in the database against the expected version.
+    ///
+    /// The database must have at least the expected version applied, and the expected version must not be older
+    /// than the database's `min_compatible_version`.
+    pub fn check(&self, version: i64, min_compatible_version: i64) -> Result<(), SchemaError> {
+        if version < self.version {
+            Err(SchemaError::TooOld {
+                schema: self.name,
+                found: version,
+                expected: self.version,
+            })
+        } else if self.version < min_compatible_version {
+            Err(SchemaError::TooNew {
+                schema: self.name,
+                found: version,
+                expected: self.version,
+                min_compatible_version,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Verify that the database schema is compatible with this code before serving requests. Running against a
+/// partially upgraded database can otherwise corrupt data in ways that are hard to trace.
+pub async fn check_schema_version(pool: &AnyPool, schema: ExpectedSchema) -> Result<(), SchemaError> {
+    // SQLite does not have schemas; each Scratchstack schema is a separate database file.
+    let table = match pool.any_kind() {
+        AnyKind::Postgres => format!("{}.schema_version", schema.name),
+        _ => "schema_version".to_string(),
+    };
+
+    let query = format!("SELECT version, min_compatible_version FROM {table} WHERE schema_version_id = 1");
+    let row: Option<(i64, i64)> = match sqlx::query_as(&query).fetch_optional(pool).await {
+        Ok(row) => row,
+        Err(SqlxError::Database(e)) => {
+            return Err(SchemaError::Missing {
+                schema: schema.name,
+                message: e.to_string(),
+            })
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let (version, min_compatible_version) = row.ok_or_else(|| SchemaError::Missing {
+        schema: schema.name,
+        message: format!("{table} is empty"),
+    })?;
+
+    schema.check(version, min_compatible_version)?;
+    info!("Database schema {} is at version {} (expected {})", schema.name, version, schema.version);
+    Ok(())
+}
+
+#[derive(Debug)]
+pub enum SchemaError {
+    Missing {
+        schema: &'static str,
+        message: String,
+    },
+    TooOld {
+        schema: &'static str,
+        found: i64,
+        expected: i64,
+    },
+    TooNew {
+        schema: &'static str,
+        found: i64,
+        expected: i64,
+        min_compatible_version: i64,
+    },
+    Sqlx(SqlxError),
+}
+
+impl Error for SchemaError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Sqlx(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for SchemaError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Missing {
+                schema,
+                message,
+            } => write!(
+                f,
+                "Unable to read the version of database schema {schema} ({message}); apply the migrations in migrations/{schema} before starting the service"
+            ),
+            Self::TooOld {
+                schema,
+                found,
+                expected,
+            } => write!(
+                f,
+                "Database schema {schema} is at version {found} but this service requires version {expected}; apply the pending migrations in migrations/{schema}"
+            ),
+            Self::TooNew {
+                schema,
+                found,
+                expected,
+                min_compatible_version,
+            } => write!(
+                f,
+                "Database schema {schema} is at version {found}, which requires services written for version {min_compatible_version} or later; this service was written for version {expected} and must be upgraded"
+            ),
+            Self::Sqlx(e) => write!(f, "Sqlx error: {e}"),
+        }
+    }
+}
+
+impl From<SqlxError> for SchemaError {
+    fn from(e: SqlxError) -> Self {
+        Self::Sqlx(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{ExpectedSchema, SchemaError},
+        pretty_assertions::assert_eq,
+    };
+
+    #[test_log::test]
+    fn test_check() {
+        let schema = ExpectedSchema {
+            name: "iam",
+            version: 4,
+        };
+
+        assert!(schema.check(4, 1).is_ok());
+        // Additive migrations after this code was written are fine.
+        assert!(schema.check(5, 4).is_ok());
+        assert!(matches!(
+            schema.check(3, 1),
+            Err(SchemaError::TooOld {
+                found: 3,
+                ..
+            })
+        ));
+
+        let e = schema.check(6, 5).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "Database schema iam is at version 6, which requires services written for version 5 or later; this service was written for version 4 and must be upgraded"
+        );
+    }
+}
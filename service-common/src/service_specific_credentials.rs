@@ -0,0 +1,381 @@
+//! IAM service specific credential operations: `CreateServiceSpecificCredential`, `ResetServiceSpecificCredential`,
+//! and `DeleteServiceSpecificCredential`, written against [ControlPlaneStore].
+//!
+//! A service specific credential is a user name and password a user presents to one of the
+//! [SUPPORTED_SERVICES] instead of signing requests. As in AWS, the user name is derived from the IAM user name and
+//! account, the password is generated and shown only when the credential is created or reset, and a user has at most
+//! [MAX_SERVICE_SPECIFIC_CREDENTIALS_PER_SERVICE] credentials for each service.
+use {
+    crate::{
+        context::RequestContext,
+        ids::{unique_id, SERVICE_SPECIFIC_CREDENTIAL_ID_PREFIX},
+        operation::{output::query_response, ValidationError},
+        operation_input, operation_output,
+        protocol::IAM_XML_NS,
+        store::{ControlPlaneStore, EntityKind, ServiceSpecificCredential, StoreError, User},
+    },
+    chrono::{DateTime, Utc},
+    http::StatusCode,
+    ring::rand::{SecureRandom, SystemRandom},
+    std::{
+        error::Error,
+        fmt::{Debug, Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// The services that accept service specific credentials.
+pub const SUPPORTED_SERVICES: &[&str] = &["cassandra.amazonaws.com", "codecommit.amazonaws.com"];
+
+/// The number of credentials a user may have for each service.
+pub const MAX_SERVICE_SPECIFIC_CREDENTIALS_PER_SERVICE: usize = 2;
+
+/// The number of random bytes in a generated password, before base64 encoding.
+const SERVICE_PASSWORD_BYTES: usize = 32;
+
+operation_input! {
+    /// Input for the CreateServiceSpecificCredential operation.
+    pub struct CreateServiceSpecificCredentialInput {
+        "UserName" => pub user_name: String where length(1, 64), pattern(r"[\w+=,.@-]+"),
+        "ServiceName" => pub service_name: String where length(1, 128),
+    }
+}
+
+operation_input! {
+    /// Input for the ResetServiceSpecificCredential operation.
+    pub struct ResetServiceSpecificCredentialInput {
+        "UserName" => pub user_name: String where length(1, 64), pattern(r"[\w+=,.@-]+"),
+        "ServiceSpecificCredentialId" => pub service_specific_credential_id: String
+            where length(16, 128), pattern(r"\w+"),
+    }
+}
+
+operation_input! {
+    /// Input for the DeleteServiceSpecificCredential operation.
+    pub struct DeleteServiceSpecificCredentialInput {
+        "UserName" => pub user_name: String where length(1, 64), pattern(r"[\w+=,.@-]+"),
+        "ServiceSpecificCredentialId" => pub service_specific_credential_id: String
+            where length(16, 128), pattern(r"\w+"),
+    }
+}
+
+/// Errors from service specific credential operations.
+#[derive(Debug)]
+pub enum ServiceSpecificCredentialError {
+    Validation(ValidationError),
+
+    /// The service does not accept service specific credentials.
+    NotSupportedService(String),
+    Store(StoreError),
+}
+
+impl ServiceSpecificCredentialError {
+    /// The IAM error code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Validation(e) => e.code(),
+            Self::NotSupportedService(_) => "NotSupportedService",
+            Self::Store(e) => e.code(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Validation(_) => StatusCode::BAD_REQUEST,
+            Self::NotSupportedService(_) => StatusCode::NOT_FOUND,
+            Self::Store(e) => e.status(),
+        }
+    }
+}
+
+impl Error for ServiceSpecificCredentialError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Validation(e) => Some(e),
+            Self::NotSupportedService(_) => None,
+            Self::Store(e) => Some(e),
+        }
+    }
+}
+
+impl Display for ServiceSpecificCredentialError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Validation(e) => write!(f, "{e}"),
+            Self::NotSupportedService(service_name) => {
+                write!(f, "Service {service_name} does not support service specific credentials.")
+            }
+            Self::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<ValidationError> for ServiceSpecificCredentialError {
+    fn from(e: ValidationError) -> Self {
+        Self::Validation(e)
+    }
+}
+
+impl From<StoreError> for ServiceSpecificCredentialError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+/// A service specific credential with its password, as returned by CreateServiceSpecificCredential and
+/// ResetServiceSpecificCredential.
+#[derive(Clone, Eq, PartialEq)]
+pub struct ServiceSpecificCredentialOutput {
+    pub user_name: String,
+
+    /// The user name to present to the service.
+    pub service_user_name: String,
+    pub credential: ServiceSpecificCredential,
+
+    /// The element the result is wrapped in: the operation name.
+    operation: &'static str,
+}
+
+impl Debug for ServiceSpecificCredentialOutput {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("ServiceSpecificCredentialOutput")
+            .field("user_name", &self.user_name)
+            .field("service_user_name", &self.service_user_name)
+            .field("credential", &self.credential)
+            .field("operation", &self.operation)
+            .finish()
+    }
+}
+
+impl ServiceSpecificCredentialOutput {
+    /// The `Status` IAM reports for the credential.
+    pub fn status(&self) -> &'static str {
+        if self.credential.active {
+            "Active"
+        } else {
+            "Inactive"
+        }
+    }
+
+    /// The `CreateServiceSpecificCredentialResponse` or `ResetServiceSpecificCredentialResponse` body.
+    pub fn to_xml(&self, request_id: &str) -> String {
+        let credential = &self.credential;
+        let result = ServiceSpecificCredentialResult {
+            service_specific_credential: ServiceSpecificCredentialElement {
+                create_date: credential.created_at,
+                service_name: &credential.service_name,
+                service_user_name: &self.service_user_name,
+                service_password: &credential.service_password,
+                service_specific_credential_id: &credential.service_specific_credential_id,
+                user_name: &self.user_name,
+                status: self.status(),
+            },
+        };
+        query_response(IAM_XML_NS, self.operation, &result, request_id)
+    }
+}
+
+operation_output! {
+    /// A service specific credential, with the children in the order AWS writes them.
+    struct ServiceSpecificCredentialElement<'a> {
+        "CreateDate" => create_date: DateTime<Utc>,
+        "ServiceName" => service_name: &'a str,
+        "ServiceUserName" => service_user_name: &'a str,
+        "ServicePassword" => service_password: &'a str,
+        "ServiceSpecificCredentialId" => service_specific_credential_id: &'a str,
+        "UserName" => user_name: &'a str,
+        "Status" => status: &'a str,
+    }
+}
+
+operation_output! {
+    struct ServiceSpecificCredentialResult<'a> {
+        "ServiceSpecificCredential" => service_specific_credential: ServiceSpecificCredentialElement<'a>,
+    }
+}
+
+/// A new random service password.
+pub fn generate_service_password() -> String {
+    let mut random = [0u8; SERVICE_PASSWORD_BYTES];
+    SystemRandom::new().fill(&mut random).expect("Unable to generate service password");
+    base64::encode(random)
+}
+
+/// The user name a user presents to services, unique across accounts.
+fn service_user_name(user: &User) -> String {
+    format!("{}-at-{}", user.user_name, user.account_id)
+}
+
+async fn target_user(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    user_name: &str,
+) -> Result<User, ServiceSpecificCredentialError> {
+    let account_id = context.account_id().unwrap_or_default();
+    Ok(store.get_user(&account_id, user_name).await?)
+}
+
+/// Create an active credential for a user and service. The quota is checked before the credential is stored, so two
+/// concurrent requests may both succeed.
+pub async fn create_service_specific_credential(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    input: &CreateServiceSpecificCredentialInput,
+) -> Result<ServiceSpecificCredentialOutput, ServiceSpecificCredentialError> {
+    if !SUPPORTED_SERVICES.contains(&input.service_name.as_str()) {
+        return Err(ServiceSpecificCredentialError::NotSupportedService(input.service_name.clone()));
+    }
+
+    let user = target_user(store, context, &input.user_name).await?;
+    let existing = store
+        .list_service_specific_credentials(&user.user_id)
+        .await?
+        .into_iter()
+        .filter(|credential| credential.service_name == input.service_name)
+        .count();
+    if existing >= MAX_SERVICE_SPECIFIC_CREDENTIALS_PER_SERVICE {
+        return Err(StoreError::limit_exceeded(
+            EntityKind::User,
+            &user.user_name,
+            MAX_SERVICE_SPECIFIC_CREDENTIALS_PER_SERVICE,
+        )
+        .into());
+    }
+
+    let credential = ServiceSpecificCredential {
+        user_id: user.user_id.clone(),
+        service_specific_credential_id: unique_id(SERVICE_SPECIFIC_CREDENTIAL_ID_PREFIX),
+        service_name: input.service_name.clone(),
+        service_password: generate_service_password(),
+        active: true,
+        created_at: Utc::now(),
+    };
+    store.create_service_specific_credential(&credential).await?;
+
+    Ok(ServiceSpecificCredentialOutput {
+        service_user_name: service_user_name(&user),
+        user_name: user.user_name,
+        credential,
+        operation: "CreateServiceSpecificCredential",
+    })
+}
+
+/// Replace the password of one of a user's credentials. The old password stops working immediately.
+pub async fn reset_service_specific_credential(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    input: &ResetServiceSpecificCredentialInput,
+) -> Result<ServiceSpecificCredentialOutput, ServiceSpecificCredentialError> {
+    let user = target_user(store, context, &input.user_name).await?;
+    let credential = store
+        .reset_service_specific_credential(
+            &user.user_id,
+            &input.service_specific_credential_id,
+            &generate_service_password(),
+        )
+        .await?;
+
+    Ok(ServiceSpecificCredentialOutput {
+        service_user_name: service_user_name(&user),
+        user_name: user.user_name,
+        credential,
+        operation: "ResetServiceSpecificCredential",
+    })
+}
+
+pub async fn delete_service_specific_credential(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    input: &DeleteServiceSpecificCredentialInput,
+) -> Result<(), ServiceSpecificCredentialError> {
+    let user = target_user(store, context, &input.user_name).await?;
+    Ok(store.delete_service_specific_credential(&user.user_id, &input.service_specific_credential_id).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            create_service_specific_credential, delete_service_specific_credential, reset_service_specific_credential,
+            CreateServiceSpecificCredentialInput, DeleteServiceSpecificCredentialInput,
+            ResetServiceSpecificCredentialInput,
+        },
+        crate::{
+            context::RequestContext,
+            operation::{parameters, FromParameters},
+            store::{ControlPlaneStore, MemoryStore, User},
+        },
+        chrono::Utc,
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{Principal, PrincipalIdentity, User as PrincipalUser},
+    };
+
+    #[test_log::test(tokio::test)]
+    async fn test_service_specific_credential_lifecycle() {
+        let store = MemoryStore::new();
+        store
+            .create_user(
+                &User {
+                    user_id: "AIDAEXAMPLEUSER1".to_string(),
+                    account_id: "123456789012".to_string(),
+                    user_name: "Alice".to_string(),
+                    path: "/".to_string(),
+                    permissions_boundary: None,
+                    created_at: Utc::now(),
+                },
+                &[],
+            )
+            .await
+            .unwrap();
+        let alice = PrincipalUser::new("aws", "123456789012", "/", "Alice").unwrap();
+        let context =
+            RequestContext::builder().principal(Principal::from(vec![PrincipalIdentity::from(alice)])).build().unwrap();
+
+        let create = |service_name: &'static str| {
+            CreateServiceSpecificCredentialInput::from_parameters(&parameters(&[
+                ("UserName", "Alice"),
+                ("ServiceName", service_name),
+            ]))
+            .unwrap()
+        };
+        let e = create_service_specific_credential(&store, &context, &create("s3.amazonaws.com")).await.unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("NotSupportedService", 404));
+
+        let first =
+            create_service_specific_credential(&store, &context, &create("codecommit.amazonaws.com")).await.unwrap();
+        assert!(first.credential.service_specific_credential_id.starts_with("ACCA"));
+        assert_eq!(first.service_user_name, "Alice-at-123456789012");
+        assert_eq!(first.credential.service_password.len(), 44);
+        assert!(!format!("{first:?}").contains(&first.credential.service_password));
+        let xml = first.to_xml("01234567-89ab-cdef-0123-456789abcdef");
+        assert!(xml.starts_with("<CreateServiceSpecificCredentialResponse "), "{xml}");
+        assert!(xml.contains("<ServiceUserName>Alice-at-123456789012</ServiceUserName>"), "{xml}");
+
+        // The limit is per service.
+        create_service_specific_credential(&store, &context, &create("codecommit.amazonaws.com")).await.unwrap();
+        create_service_specific_credential(&store, &context, &create("cassandra.amazonaws.com")).await.unwrap();
+        let e = create_service_specific_credential(&store, &context, &create("codecommit.amazonaws.com"))
+            .await
+            .unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("LimitExceeded", 409));
+
+        let credential_id = first.credential.service_specific_credential_id.as_str();
+        let input = ResetServiceSpecificCredentialInput::from_parameters(&parameters(&[
+            ("UserName", "Alice"),
+            ("ServiceSpecificCredentialId", credential_id),
+        ]))
+        .unwrap();
+        let reset = reset_service_specific_credential(&store, &context, &input).await.unwrap();
+        assert_eq!(reset.credential.service_specific_credential_id, credential_id);
+        assert_ne!(reset.credential.service_password, first.credential.service_password);
+        assert!(reset.to_xml("r").starts_with("<ResetServiceSpecificCredentialResponse "));
+
+        let input = DeleteServiceSpecificCredentialInput::from_parameters(&parameters(&[
+            ("UserName", "Alice"),
+            ("ServiceSpecificCredentialId", credential_id),
+        ]))
+        .unwrap();
+        delete_service_specific_credential(&store, &context, &input).await.unwrap();
+        let e = delete_service_specific_credential(&store, &context, &input).await.unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("NoSuchEntity", 404));
+    }
+}
@@ -0,0 +1,281 @@
+use {
+    crate::session_keys::{get_string, AWS_TOKEN_ISSUE_TIME, AWS_USERID, SCRATCHSTACK_ROLE_CHAIN},
+    chrono::{DateTime, Duration, Utc},
+    scratchstack_arn::Arn,
+    scratchstack_aws_principal::{SessionData, SessionValue},
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// The shortest session that may be requested from STS, in seconds.
+pub const MIN_SESSION_DURATION: i64 = 900;
+
+/// The session duration used when `DurationSeconds` is not specified, in seconds.
+pub const DEFAULT_SESSION_DURATION: i64 = 3600;
+
+/// The smallest `MaxSessionDuration` that may be set on a role, in seconds.
+pub const MIN_ROLE_MAX_SESSION_DURATION: i64 = 3600;
+
+/// The largest `MaxSessionDuration` that may be set on a role, in seconds.
+pub const MAX_ROLE_MAX_SESSION_DURATION: i64 = 43200;
+
+/// The longest session that may be requested by role chaining, whatever the role's `MaxSessionDuration`, in seconds.
+pub const MAX_ROLE_CHAINING_DURATION: i64 = 3600;
+
+/// Validate the `MaxSessionDuration` parameter of CreateRole or UpdateRole.
+pub fn validate_max_session_duration(max_session_duration: i64) -> Result<i64, SessionDurationError> {
+    if max_session_duration < MIN_ROLE_MAX_SESSION_DURATION {
+        Err(SessionDurationError::BelowMinimum {
+            parameter: "maxSessionDuration",
+            value: max_session_duration,
+            minimum: MIN_ROLE_MAX_SESSION_DURATION,
+        })
+    } else if max_session_duration > MAX_ROLE_MAX_SESSION_DURATION {
+        Err(SessionDurationError::AboveMaximum {
+            parameter: "maxSessionDuration",
+            value: max_session_duration,
+            maximum: MAX_ROLE_MAX_SESSION_DURATION,
+        })
+    } else {
+        Ok(max_session_duration)
+    }
+}
+
+/// Resolve the `DurationSeconds` parameter of AssumeRole against the role's `MaxSessionDuration`.
+pub fn role_session_duration(
+    duration_seconds: Option<i64>,
+    max_session_duration: i64,
+) -> Result<Duration, SessionDurationError> {
+    let duration_seconds = duration_seconds.unwrap_or(DEFAULT_SESSION_DURATION);
+
+    if duration_seconds < MIN_SESSION_DURATION {
+        return Err(SessionDurationError::BelowMinimum {
+            parameter: "durationSeconds",
+            value: duration_seconds,
+            minimum: MIN_SESSION_DURATION,
+        });
+    }
+
+    if duration_seconds > MAX_ROLE_MAX_SESSION_DURATION {
+        return Err(SessionDurationError::AboveMaximum {
+            parameter: "durationSeconds",
+            value: duration_seconds,
+            maximum: MAX_ROLE_MAX_SESSION_DURATION,
+        });
+    }
+
+    if duration_seconds > max_session_duration {
+        return Err(SessionDurationError::ExceedsRoleMaximum);
+    }
+
+    Ok(Duration::seconds(duration_seconds))
+}
+
+/// Resolve the `DurationSeconds` parameter of AssumeRole for a caller that is itself a role session. Role chaining
+/// limits the session to [MAX_ROLE_CHAINING_DURATION] regardless of the role's `MaxSessionDuration`.
+pub fn role_chaining_session_duration(
+    duration_seconds: Option<i64>,
+    max_session_duration: i64,
+) -> Result<Duration, SessionDurationError> {
+    match duration_seconds {
+        Some(duration_seconds)
+            if duration_seconds > MAX_ROLE_CHAINING_DURATION && duration_seconds <= MAX_ROLE_MAX_SESSION_DURATION =>
+        {
+            Err(SessionDurationError::ExceedsRoleChainingLimit)
+        }
+        _ => role_session_duration(duration_seconds, max_session_duration),
+    }
+}
+
+/// Indicates whether `caller` is a role session (`arn:<partition>:sts::<account>:assumed-role/<role>/<session>`), so
+/// that assuming a role with its credentials is role chaining.
+pub fn is_role_session(caller: &Arn) -> bool {
+    caller.service() == "sts" && caller.resource().starts_with("assumed-role/")
+}
+
+/// Returns the role chain recorded in `session_data`; see [SCRATCHSTACK_ROLE_CHAIN].
+pub fn role_chain(session_data: &SessionData) -> Vec<String> {
+    match get_string(session_data, SCRATCHSTACK_ROLE_CHAIN) {
+        Ok(Some(chain)) if !chain.is_empty() => chain.split(',').map(ToString::to_string).collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// The role chain of a session assumed by the caller whose session data is `session_data`: the caller's own chain
+/// followed by its `aws:userid`, so that the original caller is not lost.
+pub fn next_role_chain(session_data: &SessionData) -> Vec<String> {
+    let mut chain = role_chain(session_data);
+    if let Ok(Some(user_id)) = get_string(session_data, AWS_USERID) {
+        chain.push(user_id.to_string());
+    }
+    chain
+}
+
+/// Record `chain` in `session_data` as [SCRATCHSTACK_ROLE_CHAIN]. An empty chain is not recorded.
+pub fn add_role_chain_to_session_data(chain: &[String], session_data: &mut SessionData) {
+    if !chain.is_empty() {
+        session_data.insert(SCRATCHSTACK_ROLE_CHAIN, SessionValue::String(chain.join(",")));
+    }
+}
+
+/// The validity period of an issued session.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SessionValidity {
+    issued_at: DateTime<Utc>,
+    expires_at: DateTime<Utc>,
+}
+
+impl SessionValidity {
+    pub fn new(issued_at: DateTime<Utc>, duration: Duration) -> Self {
+        Self {
+            issued_at,
+            expires_at: issued_at + duration,
+        }
+    }
+
+    pub fn issued_at(&self) -> DateTime<Utc> {
+        self.issued_at
+    }
+
+    pub fn expires_at(&self) -> DateTime<Utc> {
+        self.expires_at
+    }
+
+    pub fn is_expired_at(&self, now: DateTime<Utc>) -> bool {
+        now >= self.expires_at
+    }
+
+    /// Record the issue time of the session's token in `session_data` as `aws:TokenIssueTime`.
+    pub fn add_to_session_data(&self, session_data: &mut SessionData) {
+        session_data.insert(AWS_TOKEN_ISSUE_TIME, SessionValue::Timestamp(self.issued_at));
+    }
+}
+
+/// A requested or configured session duration is out of range. These are reported to the caller as a
+/// `ValidationError`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SessionDurationError {
+    BelowMinimum {
+        parameter: &'static str,
+        value: i64,
+        minimum: i64,
+    },
+    AboveMaximum {
+        parameter: &'static str,
+        value: i64,
+        maximum: i64,
+    },
+    ExceedsRoleMaximum,
+    ExceedsRoleChainingLimit,
+}
+
+impl SessionDurationError {
+    /// The AWS error code for this error.
+    pub fn code(&self) -> &'static str {
+        "ValidationError"
+    }
+}
+
+impl Error for SessionDurationError {}
+
+impl Display for SessionDurationError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::BelowMinimum {
+                parameter,
+                value,
+                minimum,
+            } => write!(
+                f,
+                "1 validation error detected: Value '{value}' at '{parameter}' failed to satisfy constraint: Member must have value greater than or equal to {minimum}"
+            ),
+            Self::AboveMaximum {
+                parameter,
+                value,
+                maximum,
+            } => write!(
+                f,
+                "1 validation error detected: Value '{value}' at '{parameter}' failed to satisfy constraint: Member must have value less than or equal to {maximum}"
+            ),
+            Self::ExceedsRoleMaximum => {
+                f.write_str("The requested DurationSeconds exceeds the MaxSessionDuration set for this role.")
+            }
+            Self::ExceedsRoleChainingLimit => f.write_str(
+                "The requested DurationSeconds exceeds the 1 hour session limit for roles assumed by role chaining.",
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            add_role_chain_to_session_data, is_role_session, role_chain, role_chaining_session_duration,
+            role_session_duration, validate_max_session_duration, SessionDurationError, SessionValidity,
+        },
+        chrono::{DateTime, Duration, Utc},
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{SessionData, SessionValue},
+    };
+
+    #[test_log::test]
+    fn test_role_session_duration() {
+        assert_eq!(role_session_duration(None, 3600).unwrap(), Duration::hours(1));
+        assert_eq!(role_session_duration(Some(900), 3600).unwrap(), Duration::minutes(15));
+        assert_eq!(role_session_duration(Some(7200), 7200).unwrap(), Duration::hours(2));
+        assert_eq!(role_session_duration(Some(7200), 3600).unwrap_err(), SessionDurationError::ExceedsRoleMaximum);
+
+        let e = role_session_duration(Some(60), 3600).unwrap_err();
+        assert_eq!(e.code(), "ValidationError");
+        assert_eq!(
+            e.to_string(),
+            "1 validation error detected: Value '60' at 'durationSeconds' failed to satisfy constraint: Member must have value greater than or equal to 900"
+        );
+
+        assert!(validate_max_session_duration(3600).is_ok());
+        assert!(validate_max_session_duration(43200).is_ok());
+        assert!(validate_max_session_duration(900).is_err());
+        assert!(validate_max_session_duration(43201).is_err());
+    }
+
+    #[test_log::test]
+    fn test_role_chaining() {
+        assert_eq!(role_chaining_session_duration(None, 43200).unwrap(), Duration::hours(1));
+        assert_eq!(role_chaining_session_duration(Some(3600), 43200).unwrap(), Duration::hours(1));
+        assert_eq!(
+            role_chaining_session_duration(Some(3601), 43200).unwrap_err(),
+            SessionDurationError::ExceedsRoleChainingLimit
+        );
+        assert!(matches!(
+            role_chaining_session_duration(Some(43201), 43200),
+            Err(SessionDurationError::AboveMaximum { .. })
+        ));
+        assert!(role_chaining_session_duration(Some(60), 3600).is_err());
+
+        assert!(is_role_session(&"arn:aws:sts::123456789012:assumed-role/deploy/ci".parse().unwrap()));
+        assert!(!is_role_session(&"arn:aws:iam::123456789012:user/alice".parse().unwrap()));
+
+        let mut session_data = SessionData::new();
+        add_role_chain_to_session_data(&[], &mut session_data);
+        assert!(role_chain(&session_data).is_empty());
+        let chain = vec!["AIDAEXAMPLEUSER1".to_string(), "AROAEXAMPLEROLE1:ci".to_string()];
+        add_role_chain_to_session_data(&chain, &mut session_data);
+        assert_eq!(role_chain(&session_data), chain);
+    }
+
+    #[test_log::test]
+    fn test_session_validity() {
+        let issued_at = "2022-10-14T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let validity = SessionValidity::new(issued_at, Duration::hours(1));
+        assert_eq!(validity.expires_at(), "2022-10-14T13:00:00Z".parse::<DateTime<Utc>>().unwrap());
+        assert!(!validity.is_expired_at(issued_at));
+        assert!(validity.is_expired_at(validity.expires_at()));
+
+        let mut session_data = SessionData::new();
+        validity.add_to_session_data(&mut session_data);
+        assert_eq!(session_data.get("aws:TokenIssueTime"), Some(&SessionValue::Timestamp(issued_at)));
+    }
+}
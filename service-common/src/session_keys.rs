@@ -0,0 +1,132 @@
+//! Keys of the session data attached to authenticated requests, and typed accessors for their values.
+//!
+//! Session data is keyed by strings, and a misspelled key silently reads as absent, which for a key like
+//! `aws:SourceIdentity` is an authorization bug. Name keys with the constants here instead of literals, and read
+//! them with [get_string], [get_bool] and [get_datetime], which report a value of the wrong type as a
+//! [SessionKeyError] rather than treating it as absent.
+use {
+    chrono::{DateTime, Utc},
+    scratchstack_aws_principal::{SessionData, SessionValue},
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// The unique id of the caller: the user or role id, `<role id>:<session name>` for a role session, or the account
+/// id for the root user.
+pub const AWS_USERID: &str = "aws:userid";
+
+/// The region of the request's credential scope.
+pub const AWS_REQUESTED_REGION: &str = "aws:RequestedRegion";
+
+/// When the session token of a temporary credential was issued.
+pub const AWS_TOKEN_ISSUE_TIME: &str = "aws:TokenIssueTime";
+
+/// The ARN of the caller. This is filled in from the principal when policies are evaluated.
+pub const AWS_PRINCIPAL_ARN: &str = "aws:PrincipalArn";
+
+/// The account of the caller. This is filled in from the principal when policies are evaluated.
+pub const AWS_PRINCIPAL_ACCOUNT: &str = "aws:PrincipalAccount";
+
+/// The ARN of the identity provider that authenticated a web identity session.
+pub const AWS_FEDERATED_PROVIDER: &str = "aws:FederatedProvider";
+
+/// The source identity of a role session, carried through role chaining.
+pub const AWS_SOURCE_IDENTITY: &str = "aws:SourceIdentity";
+
+/// Set to `true` when a request was made by a service on behalf of the principal.
+pub const AWS_VIA_AWS_SERVICE: &str = "aws:ViaAWSService";
+
+/// The service principals that made calls on behalf of the principal, in order, separated by commas.
+pub const AWS_CALLED_VIA: &str = "aws:CalledVia";
+
+/// The first service principal in [AWS_CALLED_VIA].
+pub const AWS_CALLED_VIA_FIRST: &str = "aws:CalledViaFirst";
+
+/// The last service principal in [AWS_CALLED_VIA].
+pub const AWS_CALLED_VIA_LAST: &str = "aws:CalledViaLast";
+
+/// The `aws:userid` of each caller that led to a role session, the original caller first, separated by commas. This
+/// is a Scratchstack extension; AWS does not expose the chain.
+pub const SCRATCHSTACK_ROLE_CHAIN: &str = "scratchstack:RoleChain";
+
+/// The value of `key` if it is a string.
+pub fn get_string<'a>(session_data: &'a SessionData, key: &str) -> Result<Option<&'a str>, SessionKeyError> {
+    match session_data.get(key) {
+        None => Ok(None),
+        Some(SessionValue::String(value)) => Ok(Some(value.as_str())),
+        Some(_) => Err(SessionKeyError::wrong_type(key, "string")),
+    }
+}
+
+/// The value of `key` if it is a boolean.
+pub fn get_bool(session_data: &SessionData, key: &str) -> Result<Option<bool>, SessionKeyError> {
+    match session_data.get(key) {
+        None => Ok(None),
+        Some(SessionValue::Bool(value)) => Ok(Some(*value)),
+        Some(_) => Err(SessionKeyError::wrong_type(key, "boolean")),
+    }
+}
+
+/// The value of `key` if it is a timestamp.
+pub fn get_datetime(session_data: &SessionData, key: &str) -> Result<Option<DateTime<Utc>>, SessionKeyError> {
+    match session_data.get(key) {
+        None => Ok(None),
+        Some(SessionValue::Timestamp(value)) => Ok(Some(*value)),
+        Some(_) => Err(SessionKeyError::wrong_type(key, "timestamp")),
+    }
+}
+
+/// A session data value does not have the type its key calls for.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SessionKeyError {
+    pub key: String,
+    pub expected: &'static str,
+}
+
+impl SessionKeyError {
+    fn wrong_type(key: &str, expected: &'static str) -> Self {
+        Self {
+            key: key.to_string(),
+            expected,
+        }
+    }
+}
+
+impl Error for SessionKeyError {}
+
+impl Display for SessionKeyError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        write!(f, "Session data key {} is not a {}", self.key, self.expected)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{get_bool, get_datetime, get_string, AWS_TOKEN_ISSUE_TIME, AWS_USERID, AWS_VIA_AWS_SERVICE},
+        chrono::{DateTime, Utc},
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{SessionData, SessionValue},
+    };
+
+    #[test_log::test]
+    fn test_accessors() {
+        let issued_at = "2022-10-14T12:00:00Z".parse::<DateTime<Utc>>().unwrap();
+        let mut session_data = SessionData::new();
+        session_data.insert(AWS_USERID, SessionValue::String("AIDAEXAMPLEUSER1".to_string()));
+        session_data.insert(AWS_VIA_AWS_SERVICE, SessionValue::Bool(true));
+        session_data.insert(AWS_TOKEN_ISSUE_TIME, SessionValue::Timestamp(issued_at));
+
+        assert_eq!(get_string(&session_data, AWS_USERID).unwrap(), Some("AIDAEXAMPLEUSER1"));
+        assert_eq!(get_bool(&session_data, AWS_VIA_AWS_SERVICE).unwrap(), Some(true));
+        assert_eq!(get_datetime(&session_data, AWS_TOKEN_ISSUE_TIME).unwrap(), Some(issued_at));
+        assert_eq!(get_string(&session_data, "aws:SourceIdentity").unwrap(), None);
+
+        let e = get_string(&session_data, AWS_VIA_AWS_SERVICE).unwrap_err();
+        assert_eq!(e.to_string(), "Session data key aws:ViaAWSService is not a string");
+        assert!(get_bool(&session_data, AWS_USERID).is_err());
+        assert!(get_datetime(&session_data, AWS_USERID).is_err());
+    }
+}
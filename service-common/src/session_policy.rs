@@ -0,0 +1,343 @@
+//! Session policies and session tags, given to AssumeRole and carried in the packed part of the session token.
+//!
+//! A session policy limits what a role session can do: a request made with the session is allowed only if the
+//! role's identity policies allow it and so does the inline `Policy` or one of the managed `PolicyArns` the session
+//! was created with. As in AWS, a session policy cannot grant more than the role has. Session tags are recorded with
+//! the session, and those named in `TransitiveTagKeys` are passed on to sessions assumed by role chaining, which
+//! cannot change them. All of this counts against the [packed allotment][crate::token::PACKED_POLICY_LIMIT], which
+//! is checked when the token is sealed.
+use {
+    crate::{
+        attached_policies::parse_policy_arn,
+        authz::Decision,
+        effective::EffectivePolicy,
+        inline_policies::{validate_policy_document, InlinePolicyError},
+        operation::{FromParameter, ParameterError},
+        store::{ControlPlaneStore, StoreError, Tag},
+        tags::{validate_tags, TagError},
+        token::PackedClaims,
+    },
+    http::StatusCode,
+    std::{
+        collections::HashMap,
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// The most managed session policies a session may have.
+pub const MAX_POLICY_ARNS: usize = 10;
+
+/// The managed session policies of a request: the query protocol list `PolicyArns.member.N.arn`, numbered from 1.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct PolicyArns(pub Vec<String>);
+
+impl FromParameter for PolicyArns {
+    const KIND: &'static str = "list";
+    const REQUIRED: bool = false;
+
+    fn from_parameter(parameters: &HashMap<String, String>, name: &str) -> Result<Self, ParameterError> {
+        Ok(Self((1..).map_while(|n| parameters.get(&format!("{name}.member.{n}.arn")).cloned()).collect()))
+    }
+}
+
+/// Why the session policies or tags of a request were not accepted.
+#[derive(Debug)]
+pub enum SessionPolicyError {
+    /// The inline session policy is not a valid policy.
+    MalformedPolicyDocument(String),
+
+    /// A managed session policy ARN does not name an IAM policy.
+    InvalidPolicyArn(String),
+
+    /// More than [MAX_POLICY_ARNS] managed session policies were given; holds the number given.
+    TooManyPolicyArns(usize),
+    Tag(TagError),
+
+    /// A key in `TransitiveTagKeys` is not the key of a tag in `Tags`.
+    TransitiveTagKey(String),
+
+    /// A tag has the key of a transitive tag inherited from the caller's session.
+    InheritedTag(String),
+}
+
+impl SessionPolicyError {
+    /// The STS error code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::MalformedPolicyDocument(_) => "MalformedPolicyDocument",
+            Self::TooManyPolicyArns(_) => "ValidationError",
+            Self::Tag(e) => e.code(),
+            Self::InvalidPolicyArn(_) | Self::TransitiveTagKey(_) | Self::InheritedTag(_) => "InvalidParameterValue",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Tag(e) => e.status(),
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl Error for SessionPolicyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Tag(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for SessionPolicyError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::MalformedPolicyDocument(message) => write!(f, "Syntax errors in policy: {message}"),
+            Self::InvalidPolicyArn(arn) => write!(f, "ARN {arn} is not valid."),
+            Self::TooManyPolicyArns(n) => {
+                write!(f, "The number of session policy ARNs ({n}) cannot exceed {MAX_POLICY_ARNS}.")
+            }
+            Self::Tag(e) => write!(f, "{e}"),
+            Self::TransitiveTagKey(key) => write!(f, "Transitive tag key {key} is not the key of a session tag."),
+            Self::InheritedTag(key) => {
+                write!(f, "Tag {key} cannot be set because it is a transitive tag of the calling session.")
+            }
+        }
+    }
+}
+
+impl From<TagError> for SessionPolicyError {
+    fn from(e: TagError) -> Self {
+        Self::Tag(e)
+    }
+}
+
+/// The packed claims of a new session with the given session policies and tags, which are validated here.
+///
+/// `inherited` holds the packed claims of the caller's session, or is empty if the caller is not a role session;
+/// its transitive tags are carried into the new session. Its session policies are not.
+pub fn pack_session_claims(
+    inherited: &PackedClaims,
+    policy: Option<&str>,
+    policy_arns: &[String],
+    tags: &[Tag],
+    transitive_tag_keys: &[String],
+) -> Result<PackedClaims, SessionPolicyError> {
+    let policy = match policy {
+        Some(policy) => Some(validate_policy_document(policy).map_err(|e| match e {
+            InlinePolicyError::MalformedPolicyDocument(message) => SessionPolicyError::MalformedPolicyDocument(message),
+            e => SessionPolicyError::MalformedPolicyDocument(e.to_string()),
+        })?),
+        None => None,
+    };
+
+    if policy_arns.len() > MAX_POLICY_ARNS {
+        return Err(SessionPolicyError::TooManyPolicyArns(policy_arns.len()));
+    }
+    if let Some(arn) = policy_arns.iter().find(|arn| parse_policy_arn(arn).is_none()) {
+        return Err(SessionPolicyError::InvalidPolicyArn(arn.clone()));
+    }
+
+    validate_tags(tags)?;
+    let is_inherited = |key: &str| inherited.transitive_tag_keys.iter().any(|k| k.eq_ignore_ascii_case(key));
+    if let Some(tag) = tags.iter().find(|tag| is_inherited(&tag.key)) {
+        return Err(SessionPolicyError::InheritedTag(tag.key.clone()));
+    }
+    if let Some(key) = transitive_tag_keys.iter().find(|key| !tags.iter().any(|tag| tag.key.eq_ignore_ascii_case(key)))
+    {
+        return Err(SessionPolicyError::TransitiveTagKey(key.clone()));
+    }
+
+    let mut packed = PackedClaims {
+        policy,
+        policy_arns: policy_arns.to_vec(),
+        tags: inherited.tags.iter().filter(|(key, _)| is_inherited(key)).map(|(k, v)| (k.clone(), v.clone())).collect(),
+        transitive_tag_keys: inherited.transitive_tag_keys.clone(),
+    };
+    packed.tags.extend(tags.iter().map(|tag| (tag.key.clone(), tag.value.clone())));
+    packed.transitive_tag_keys.extend(transitive_tag_keys.iter().cloned());
+    Ok(packed)
+}
+
+/// The session policies in `packed`, or `None` if the session has none and is limited only by its identity
+/// policies. Managed session policies are read at their default version; those that do not exist grant nothing.
+pub async fn session_policies(
+    store: &dyn ControlPlaneStore,
+    packed: &PackedClaims,
+) -> Result<Option<Vec<EffectivePolicy>>, StoreError> {
+    if packed.policy.is_none() && packed.policy_arns.is_empty() {
+        return Ok(None);
+    }
+
+    let mut policies = Vec::new();
+    if let Some(policy) = &packed.policy {
+        policies.push(EffectivePolicy {
+            source: "session policy".to_string(),
+            via_group: None,
+            managed: false,
+            policy_document: policy.clone(),
+        });
+    }
+
+    for arn in &packed.policy_arns {
+        let (account_id, path, policy_name) = match parse_policy_arn(arn) {
+            Some(parts) => parts,
+            None => continue,
+        };
+        let policy = match store.get_policy(&account_id, &policy_name).await {
+            Ok(policy) if policy.path == path => policy,
+            Ok(_)
+            | Err(StoreError::NoSuchEntity {
+                ..
+            }) => continue,
+            Err(e) => return Err(e),
+        };
+        if let Some(version) = policy.default_version {
+            policies.push(EffectivePolicy {
+                policy_document: store.get_policy_version(&policy.managed_policy_id, version).await?,
+                source: arn.clone(),
+                via_group: None,
+                managed: true,
+            });
+        }
+    }
+
+    Ok(Some(policies))
+}
+
+/// Combine the decision of a session's identity policies with that of its session policies. Either one's explicit
+/// deny stands; otherwise both must allow the request.
+pub fn session_decision(identity: Decision, session: Decision) -> Decision {
+    match (identity, session) {
+        (
+            deny @ Decision::ExplicitDeny {
+                ..
+            },
+            _,
+        )
+        | (
+            _,
+            deny @ Decision::ExplicitDeny {
+                ..
+            },
+        ) => deny,
+        (Decision::Allow, Decision::Allow) => Decision::Allow,
+        _ => Decision::ImplicitDeny,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{pack_session_claims, session_decision, session_policies, PolicyArns, SessionPolicyError},
+        crate::{
+            authz::Decision,
+            operation::FromParameter,
+            store::{ControlPlaneStore, ManagedPolicy, MemoryStore, Tag},
+            token::PackedClaims,
+        },
+        chrono::Utc,
+        pretty_assertions::assert_eq,
+        std::collections::HashMap,
+    };
+
+    const POLICY: &str =
+        r#"{"Version": "2012-10-17", "Statement": {"Effect": "Allow", "Action": "s3:*", "Resource": "*"}}"#;
+
+    fn tag(key: &str, value: &str) -> Tag {
+        Tag {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test_log::test]
+    fn test_policy_arns() {
+        let mut parameters = HashMap::new();
+        parameters.insert("PolicyArns.member.1.arn".to_string(), "arn:aws:iam::aws:policy/ReadOnlyAccess".to_string());
+        parameters.insert("PolicyArns.member.3.arn".to_string(), "arn:aws:iam::aws:policy/Unreachable".to_string());
+        let policy_arns = PolicyArns::from_parameter(&parameters, "PolicyArns").unwrap();
+        assert_eq!(policy_arns.0, vec!["arn:aws:iam::aws:policy/ReadOnlyAccess".to_string()]);
+    }
+
+    #[test_log::test]
+    fn test_pack_session_claims() {
+        let packed = pack_session_claims(
+            &PackedClaims::default(),
+            Some(POLICY),
+            &["arn:aws:iam::123456789012:policy/ci/Deploy".to_string()],
+            &[tag("Project", "scratchstack"), tag("Team", "build")],
+            &["Project".to_string()],
+        )
+        .unwrap();
+        assert_eq!(packed.policy.as_deref(), Some(POLICY));
+        assert_eq!(packed.tags.len(), 2);
+
+        // Transitive tags are inherited and cannot be changed; other tags and session policies are not inherited.
+        let chained = pack_session_claims(&packed, None, &[], &[tag("Stage", "prod")], &[]).unwrap();
+        assert_eq!(chained.policy, None);
+        assert_eq!(chained.policy_arns, Vec::<String>::new());
+        assert_eq!(
+            chained.tags.into_iter().collect::<Vec<_>>(),
+            vec![("Project".to_string(), "scratchstack".to_string()), ("Stage".to_string(), "prod".to_string())]
+        );
+        assert_eq!(chained.transitive_tag_keys, vec!["Project".to_string()]);
+
+        let e = pack_session_claims(&packed, None, &[], &[tag("project", "other")], &[]).unwrap_err();
+        assert!(matches!(e, SessionPolicyError::InheritedTag(_)), "{e}");
+        let e = pack_session_claims(&PackedClaims::default(), None, &[], &[], &["Project".to_string()]).unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("InvalidParameterValue", 400));
+        let e = pack_session_claims(&PackedClaims::default(), Some("{\"Statement\": 1}"), &[], &[], &[]).unwrap_err();
+        assert_eq!(e.code(), "MalformedPolicyDocument");
+        let e = pack_session_claims(&PackedClaims::default(), None, &["arn:aws:s3:::bucket".to_string()], &[], &[])
+            .unwrap_err();
+        assert_eq!(e.to_string(), "ARN arn:aws:s3:::bucket is not valid.");
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_session_policies() {
+        let store = MemoryStore::new();
+        assert_eq!(session_policies(&store, &PackedClaims::default()).await.unwrap(), None);
+
+        let policy = ManagedPolicy {
+            managed_policy_id: "ANPAEXAMPLEPOLICY1".to_string(),
+            account_id: "123456789012".to_string(),
+            policy_name: "Deploy".to_string(),
+            path: "/ci/".to_string(),
+            default_version: None,
+            deprecated: false,
+            policy_type: None,
+            created_at: Utc::now(),
+        };
+        store.create_policy(&policy, POLICY).await.unwrap();
+
+        // Policies that do not exist, including one named with the wrong path, grant nothing.
+        let packed = PackedClaims {
+            policy_arns: vec![
+                "arn:aws:iam::123456789012:policy/ci/Deploy".to_string(),
+                "arn:aws:iam::123456789012:policy/Deploy".to_string(),
+                "arn:aws:iam::123456789012:policy/Missing".to_string(),
+            ],
+            ..Default::default()
+        };
+        let policies = session_policies(&store, &packed).await.unwrap().unwrap();
+        assert_eq!(
+            policies.iter().map(|p| p.source.as_str()).collect::<Vec<_>>(),
+            vec![packed.policy_arns[0].as_str()]
+        );
+        assert_eq!(policies[0].policy_document, POLICY);
+    }
+
+    #[test_log::test]
+    fn test_session_decision() {
+        let deny = Decision::ExplicitDeny {
+            policy: "session policy".to_string(),
+            statement: "0".to_string(),
+        };
+        assert_eq!(session_decision(Decision::Allow, Decision::Allow), Decision::Allow);
+        assert_eq!(session_decision(Decision::Allow, Decision::ImplicitDeny), Decision::ImplicitDeny);
+        assert_eq!(session_decision(Decision::ImplicitDeny, Decision::Allow), Decision::ImplicitDeny);
+        assert_eq!(session_decision(Decision::Allow, deny.clone()), deny);
+        assert_eq!(session_decision(deny.clone(), Decision::ImplicitDeny), deny);
+    }
+}
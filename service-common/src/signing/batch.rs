@@ -0,0 +1,88 @@
+use {
+    chrono::NaiveDate,
+    scratchstack_aws_signature::{KDateKey, KRegionKey, KSecretKey, KSigningKey},
+    std::collections::HashMap,
+};
+
+/// The credential scope a signing key is derived for.
+#[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+pub struct SigningKeyScope {
+    pub date: NaiveDate,
+    pub region: String,
+    pub service: String,
+}
+
+impl SigningKeyScope {
+    pub fn new<R: Into<String>, S: Into<String>>(date: NaiveDate, region: R, service: S) -> Self {
+        Self {
+            date,
+            region: region.into(),
+            service: service.into(),
+        }
+    }
+}
+
+/// Derive signing keys for many credential scopes at once.
+///
+/// Deriving a signing key is a chain of four HMAC operations (date, region, service, `aws4_request`). When keys are
+/// needed for several scopes -- e.g. when pre-warming a cache or verifying requests for multiple regions -- the date
+/// and region stages are usually shared; this computes each intermediate key only once.
+pub trait DeriveSigningKeys {
+    /// Returns the signing key for each scope, in the order given.
+    fn to_ksigning_batch<'a, I>(&self, scopes: I) -> Vec<(SigningKeyScope, KSigningKey)>
+    where
+        I: IntoIterator<Item = &'a SigningKeyScope>;
+}
+
+impl DeriveSigningKeys for KSecretKey {
+    fn to_ksigning_batch<'a, I>(&self, scopes: I) -> Vec<(SigningKeyScope, KSigningKey)>
+    where
+        I: IntoIterator<Item = &'a SigningKeyScope>,
+    {
+        let mut date_keys: HashMap<NaiveDate, KDateKey> = HashMap::new();
+        let mut region_keys: HashMap<(NaiveDate, &str), KRegionKey> = HashMap::new();
+        let mut result = Vec::new();
+
+        for scope in scopes {
+            let region_key = region_keys.entry((scope.date, scope.region.as_str())).or_insert_with(|| {
+                date_keys.entry(scope.date).or_insert_with(|| self.to_kdate(scope.date)).to_kregion(&scope.region)
+            });
+            let signing_key = region_key.to_kservice(&scope.service).to_ksigning();
+            result.push((scope.clone(), signing_key));
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{DeriveSigningKeys, SigningKeyScope},
+        chrono::NaiveDate,
+        pretty_assertions::assert_eq,
+        scratchstack_aws_signature::KSecretKey,
+        std::str::FromStr,
+    };
+
+    #[test_log::test]
+    fn test_batch_matches_individual() {
+        let secret = KSecretKey::from_str("wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY").unwrap();
+        let d1 = NaiveDate::from_ymd_opt(2015, 8, 30).unwrap();
+        let d2 = NaiveDate::from_ymd_opt(2015, 8, 31).unwrap();
+        let scopes = vec![
+            SigningKeyScope::new(d1, "us-east-1", "iam"),
+            SigningKeyScope::new(d1, "us-east-1", "sts"),
+            SigningKeyScope::new(d1, "us-west-2", "sts"),
+            SigningKeyScope::new(d2, "us-east-1", "iam"),
+        ];
+
+        let batch = secret.to_ksigning_batch(&scopes);
+        assert_eq!(batch.len(), scopes.len());
+
+        for (scope, (batch_scope, key)) in scopes.iter().zip(batch.iter()) {
+            assert_eq!(scope, batch_scope);
+            assert_eq!(key, &secret.to_ksigning(scope.date, &scope.region, &scope.service));
+        }
+    }
+}
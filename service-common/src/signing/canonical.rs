@@ -0,0 +1,110 @@
+use percent_encoding::{percent_decode_str, percent_encode, utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+
+/// Characters percent-encoded in canonical strings: everything except RFC 3986 unreserved characters.
+const URI_ENCODE: &AsciiSet = &NON_ALPHANUMERIC.remove(b'-').remove(b'.').remove(b'_').remove(b'~');
+
+/// As [URI_ENCODE], but leaving the `/` between path segments.
+const URI_ENCODE_PATH: &AsciiSet = &URI_ENCODE.remove(b'/');
+
+/// How the request path is turned into the canonical URI of a SigV4 canonical request.
+///
+/// Every service except S3 normalizes the path and encodes it as sent, so escapes are encoded again. S3 object keys
+/// may contain `//`, `.` and `..`, so S3 leaves the path as it is and encodes it once.
+///
+/// These options only apply to canonical requests built here, when [SigV2Verifier][crate::sigv2::SigV2Verifier]
+/// re-signs a SigV2 request with SigV4. SigV4 signatures themselves are verified by `scratchstack-aws-signature`,
+/// which canonicalizes the path on its own; the options chosen must match it, or re-signed requests will not verify.
+/// The SigV4 test vectors in `scratchstack-integration-tests` are checked against
+/// [canonical_uri_path][Self::canonical_uri_path].
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct SignatureOptions {
+    /// Remove `.` and `..` segments and empty segments from the path.
+    pub uri_path_normalization: bool,
+
+    /// Percent-encode the path as sent. Otherwise escapes are decoded first and the path is encoded once.
+    pub double_uri_encode: bool,
+}
+
+impl SignatureOptions {
+    /// Most services, including IAM and STS.
+    pub const STANDARD: Self = Self {
+        uri_path_normalization: true,
+        double_uri_encode: true,
+    };
+
+    /// S3 and S3-compatible services.
+    pub const S3: Self = Self {
+        uri_path_normalization: false,
+        double_uri_encode: false,
+    };
+
+    /// The canonical URI for `path`, the path of the request as sent.
+    pub fn canonical_uri_path(&self, path: &str) -> String {
+        let path = if self.uri_path_normalization {
+            normalize_path(path)
+        } else if path.is_empty() {
+            "/".to_string()
+        } else {
+            path.to_string()
+        };
+
+        if self.double_uri_encode {
+            utf8_percent_encode(&path, URI_ENCODE_PATH).to_string()
+        } else {
+            let decoded = percent_decode_str(&path).collect::<Vec<u8>>();
+            percent_encode(&decoded, URI_ENCODE_PATH).to_string()
+        }
+    }
+}
+
+impl Default for SignatureOptions {
+    fn default() -> Self {
+        Self::STANDARD
+    }
+}
+
+/// Percent-encode `value` for a canonical query string.
+pub fn uri_encode(value: &str) -> String {
+    utf8_percent_encode(value, URI_ENCODE).to_string()
+}
+
+fn normalize_path(path: &str) -> String {
+    let mut segments: Vec<&str> = Vec::new();
+    for segment in path.split('/') {
+        match segment {
+            "" | "." => (),
+            ".." => {
+                segments.pop();
+            }
+            _ => segments.push(segment),
+        }
+    }
+
+    let mut normalized = format!("/{}", segments.join("/"));
+    if !segments.is_empty() && (path.ends_with('/') || path.ends_with("/.") || path.ends_with("/..")) {
+        normalized.push('/');
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::SignatureOptions, pretty_assertions::assert_eq};
+
+    #[test_log::test]
+    fn test_canonical_uri_path() {
+        let cases = [
+            ("", "/", "/"),
+            ("/", "/", "/"),
+            ("/example/..", "/", "/example/.."),
+            ("/./example1/../example2/", "/example2/", "/./example1/../example2/"),
+            ("//example//key", "/example/key", "//example//key"),
+            ("/my%20key", "/my%2520key", "/my%20key"),
+            ("/-._~ ü", "/-._~%20%C3%BC", "/-._~%20%C3%BC"),
+        ];
+        for (path, standard, s3) in cases {
+            assert_eq!(SignatureOptions::STANDARD.canonical_uri_path(path), standard, "standard: {path}");
+            assert_eq!(SignatureOptions::S3.canonical_uri_path(path), s3, "s3: {path}");
+        }
+    }
+}
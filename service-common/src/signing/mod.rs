@@ -0,0 +1,13 @@
+mod batch;
+mod canonical;
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
+mod provider;
+
+#[cfg(feature = "pkcs11")]
+pub use self::pkcs11::Pkcs11SigningKeyProvider;
+pub use self::{
+    batch::{DeriveSigningKeys, SigningKeyScope},
+    canonical::{uri_encode, SignatureOptions},
+    provider::{derive_from_date_key, SecretKeySigningKeyProvider, SigningKeyProvider, SigningKeyProviderError},
+};
@@ -0,0 +1,78 @@
+use {
+    crate::signing::{derive_from_date_key, SigningKeyProvider, SigningKeyProviderError, SigningKeyScope},
+    cryptoki::{
+        context::{CInitializeArgs, Pkcs11},
+        mechanism::Mechanism,
+        object::{Attribute, ObjectClass},
+        session::{Session, UserType},
+        slot::Slot,
+        types::AuthPin,
+    },
+    std::{
+        fmt::{Debug, Formatter, Result as FmtResult},
+        path::Path,
+        sync::Mutex,
+    },
+};
+
+/// A [SigningKeyProvider] backed by a PKCS#11 token (an HSM or SoftHSM).
+///
+/// For each access key, the token holds a generic secret key object labeled `<label_prefix><access key id>` whose
+/// value is `"AWS4" + secret key`. The first stage of the derivation (`kDate`) is computed on the token with
+/// `CKM_SHA256_HMAC`; the remaining stages use only derived keys and are computed in software.
+pub struct Pkcs11SigningKeyProvider {
+    // Sessions must be dropped before the library context is finalized.
+    session: Mutex<Session>,
+    _context: Pkcs11,
+    label_prefix: String,
+}
+
+impl Pkcs11SigningKeyProvider {
+    pub fn new<P: AsRef<Path>>(
+        module: P,
+        slot: u64,
+        pin: Option<String>,
+        label_prefix: String,
+    ) -> Result<Self, SigningKeyProviderError> {
+        let context = Pkcs11::new(module).map_err(backend)?;
+        context.initialize(CInitializeArgs::OsThreads).map_err(backend)?;
+        let slot = Slot::try_from(slot).map_err(backend)?;
+        let session = context.open_ro_session(slot).map_err(backend)?;
+
+        if let Some(pin) = pin {
+            session.login(UserType::User, Some(&AuthPin::new(pin))).map_err(backend)?;
+        }
+
+        Ok(Self {
+            session: Mutex::new(session),
+            _context: context,
+            label_prefix,
+        })
+    }
+}
+
+impl Debug for Pkcs11SigningKeyProvider {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("Pkcs11SigningKeyProvider").field("label_prefix", &self.label_prefix).finish()
+    }
+}
+
+impl SigningKeyProvider for Pkcs11SigningKeyProvider {
+    fn signing_key(&self, access_key_id: &str, scope: &SigningKeyScope) -> Result<[u8; 32], SigningKeyProviderError> {
+        let label = format!("{}{}", self.label_prefix, access_key_id);
+        let session = self.session.lock().expect("PKCS#11 session mutex poisoned");
+        let template = [Attribute::Class(ObjectClass::SECRET_KEY), Attribute::Label(label.into_bytes())];
+        let key = match session.find_objects(&template).map_err(backend)?.first() {
+            Some(key) => *key,
+            None => return Err(SigningKeyProviderError::UnknownAccessKey(access_key_id.to_string())),
+        };
+
+        let date = scope.date.format("%Y%m%d").to_string();
+        let k_date = session.sign(&Mechanism::Sha256Hmac, key, date.as_bytes()).map_err(backend)?;
+        Ok(derive_from_date_key(&k_date, scope))
+    }
+}
+
+fn backend<E: std::error::Error + Send + Sync + 'static>(e: E) -> SigningKeyProviderError {
+    SigningKeyProviderError::Backend(Box::new(e))
+}
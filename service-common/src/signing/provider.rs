@@ -0,0 +1,119 @@
+use {
+    crate::{crypto::hmac_sha256, signing::SigningKeyScope},
+    std::{
+        collections::HashMap,
+        error::Error,
+        fmt::{Debug, Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// A source of AWS SigV4 signing keys.
+///
+/// Implementations may compute the HMAC chain in software or delegate the first stage (the only one that uses the
+/// secret key itself) to a hardware security module so the secret never leaves the device.
+pub trait SigningKeyProvider: Debug + Send + Sync {
+    /// Returns the raw signing key (`kSigning`) for the given access key and credential scope.
+    fn signing_key(&self, access_key_id: &str, scope: &SigningKeyScope) -> Result<[u8; 32], SigningKeyProviderError>;
+}
+
+#[derive(Debug)]
+pub enum SigningKeyProviderError {
+    UnknownAccessKey(String),
+    Backend(Box<dyn Error + Send + Sync>),
+}
+
+impl Error for SigningKeyProviderError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::UnknownAccessKey(_) => None,
+            Self::Backend(e) => Some(e.as_ref()),
+        }
+    }
+}
+
+impl Display for SigningKeyProviderError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::UnknownAccessKey(access_key_id) => write!(f, "Unknown access key: {access_key_id}"),
+            Self::Backend(e) => write!(f, "Signing key provider error: {e}"),
+        }
+    }
+}
+
+/// Completes the signing key derivation from a `kDate` key: `kSigning = HMAC(HMAC(HMAC(kDate, region), service),
+/// "aws4_request")`.
+pub fn derive_from_date_key(k_date: &[u8], scope: &SigningKeyScope) -> [u8; 32] {
+    let k_region = hmac_sha256(k_date, scope.region.as_bytes());
+    let k_service = hmac_sha256(&k_region, scope.service.as_bytes());
+    hmac_sha256(&k_service, b"aws4_request")
+}
+
+/// A [SigningKeyProvider] that holds secret keys in memory and computes the whole chain in software.
+#[derive(Clone, Default)]
+pub struct SecretKeySigningKeyProvider {
+    secret_keys: HashMap<String, String>,
+}
+
+impl SecretKeySigningKeyProvider {
+    pub fn new(secret_keys: HashMap<String, String>) -> Self {
+        Self {
+            secret_keys,
+        }
+    }
+
+    pub fn insert<A: Into<String>, S: Into<String>>(&mut self, access_key_id: A, secret_key: S) {
+        self.secret_keys.insert(access_key_id.into(), secret_key.into());
+    }
+}
+
+impl Debug for SecretKeySigningKeyProvider {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        // Never print the secret keys themselves.
+        f.debug_struct("SecretKeySigningKeyProvider").field("access_key_ids", &self.secret_keys.keys()).finish()
+    }
+}
+
+impl SigningKeyProvider for SecretKeySigningKeyProvider {
+    fn signing_key(&self, access_key_id: &str, scope: &SigningKeyScope) -> Result<[u8; 32], SigningKeyProviderError> {
+        let secret_key = self
+            .secret_keys
+            .get(access_key_id)
+            .ok_or_else(|| SigningKeyProviderError::UnknownAccessKey(access_key_id.to_string()))?;
+        let k_secret = format!("AWS4{secret_key}");
+        let k_date = hmac_sha256(k_secret.as_bytes(), scope.date.format("%Y%m%d").to_string().as_bytes());
+        Ok(derive_from_date_key(&k_date, scope))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{SecretKeySigningKeyProvider, SigningKeyProvider, SigningKeyProviderError},
+        crate::signing::SigningKeyScope,
+        chrono::NaiveDate,
+        pretty_assertions::assert_eq,
+    };
+
+    #[test_log::test]
+    fn test_secret_key_provider() {
+        let mut provider = SecretKeySigningKeyProvider::default();
+        provider.insert("AKIDEXAMPLE", "wJalrXUtnFEMI/K7MDENG+bPxRfiCYEXAMPLEKEY");
+        let scope = SigningKeyScope::new(NaiveDate::from_ymd_opt(2015, 8, 30).unwrap(), "us-east-1", "iam");
+
+        // Example from the AWS General Reference.
+        let key = provider.signing_key("AKIDEXAMPLE", &scope).unwrap();
+        assert_eq!(
+            key,
+            [
+                0xc4, 0xaf, 0xb1, 0xcc, 0x57, 0x71, 0xd8, 0x71, 0x76, 0x3a, 0x39, 0x3e, 0x44, 0xb7, 0x03, 0x57, 0x1b,
+                0x55, 0xcc, 0x28, 0x42, 0x4d, 0x1a, 0x5e, 0x86, 0xda, 0x6e, 0xd3, 0xc1, 0x54, 0xa4, 0xb9
+            ]
+        );
+
+        assert!(matches!(
+            provider.signing_key("AKIDUNKNOWN", &scope),
+            Err(SigningKeyProviderError::UnknownAccessKey(_))
+        ));
+        assert!(!format!("{provider:?}").contains("EXAMPLEKEY"));
+    }
+}
@@ -0,0 +1,594 @@
+//! The IAM policy simulator: `SimulatePrincipalPolicy` and `SimulateCustomPolicy`, written against
+//! [ControlPlaneStore].
+//!
+//! Each action is evaluated against each resource with [PolicyEvaluator], the engine that authorizes requests, so a
+//! simulation agrees with what the services would decide. `SimulatePrincipalPolicy` uses the effective policies of a
+//! user, group, or role (see [crate::effective]) plus any policies in `PolicyInputList`; `SimulateCustomPolicy` uses
+//! only the policies given. Context entries become the condition keys of the simulated request; for list types, only
+//! the first value is compared, as the engine compares single values.
+use {
+    crate::{
+        authz::Decision,
+        context::RequestContext,
+        effective::{effective_policies, EffectivePolicy},
+        engine::{EvaluationRequest, PolicyError, PolicyEvaluator},
+        inline_policies::{decode_policy_document, resolve_holder},
+        operation::{output::query_response, FromParameter, ParameterError, ParameterViolation, ValidationError},
+        operation_input, operation_output,
+        protocol::IAM_XML_NS,
+        store::{ControlPlaneStore, PolicyHolder, StoreError},
+    },
+    chrono::{DateTime, Utc},
+    http::StatusCode,
+    scratchstack_arn::Arn,
+    scratchstack_aws_principal::{Principal, PrincipalIdentity, SessionData, SessionValue, User},
+    std::{
+        collections::HashMap,
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        str::FromStr,
+    },
+};
+
+/// The most evaluation results returned at once when `MaxItems` is not given.
+const DEFAULT_MAX_ITEMS: usize = 100;
+
+/// The `ContextKeyType` values AWS accepts.
+const CONTEXT_KEY_TYPES: &[&str] = &[
+    "string",
+    "stringList",
+    "numeric",
+    "numericList",
+    "boolean",
+    "booleanList",
+    "ip",
+    "ipList",
+    "binary",
+    "binaryList",
+    "date",
+    "dateList",
+];
+
+/// A condition key value for a simulated request, from the query protocol list `ContextEntries.member.N`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ContextEntry {
+    pub context_key_name: String,
+    pub context_key_values: Vec<String>,
+    pub context_key_type: String,
+}
+
+impl FromParameter for Vec<ContextEntry> {
+    const KIND: &'static str = "list";
+    const REQUIRED: bool = false;
+
+    fn from_parameter(parameters: &HashMap<String, String>, name: &str) -> Result<Self, ParameterError> {
+        let mut entries = Vec::new();
+        for n in 1.. {
+            let prefix = format!("{name}.member.{n}");
+            let key_name = parameters.get(&format!("{prefix}.ContextKeyName"));
+            let key_type = parameters.get(&format!("{prefix}.ContextKeyType"));
+            let values = <Vec<String>>::from_parameter(parameters, &format!("{prefix}.ContextKeyValues"))?;
+            match (key_name, key_type) {
+                (None, None) if values.is_empty() => break,
+                (Some(key_name), Some(key_type)) => entries.push(ContextEntry {
+                    context_key_name: key_name.clone(),
+                    context_key_values: values,
+                    context_key_type: key_type.clone(),
+                }),
+                (None, _) => return Err(ParameterError::Missing(format!("{prefix}.ContextKeyName"))),
+                (_, None) => return Err(ParameterError::Missing(format!("{prefix}.ContextKeyType"))),
+            }
+        }
+
+        Ok(entries)
+    }
+}
+
+operation_input! {
+    /// Input for the SimulatePrincipalPolicy operation.
+    pub struct SimulatePrincipalPolicyInput {
+        "PolicySourceArn" => pub policy_source_arn: String where length(20, 2048),
+        "PolicyInputList" => pub policy_input_list: Vec<String>,
+        "ActionNames" => pub action_names: Vec<String>,
+        "ResourceArns" => pub resource_arns: Vec<String>,
+        "CallerArn" => pub caller_arn: Option<String> where length(1, 2048),
+        "ContextEntries" => pub context_entries: Vec<ContextEntry>,
+        "MaxItems" => pub max_items: Option<i64> where range(1, 1000),
+        "Marker" => pub marker: Option<String> where length(1, 320),
+    }
+}
+
+operation_input! {
+    /// Input for the SimulateCustomPolicy operation.
+    pub struct SimulateCustomPolicyInput {
+        "PolicyInputList" => pub policy_input_list: Vec<String>,
+        "ActionNames" => pub action_names: Vec<String>,
+        "ResourceArns" => pub resource_arns: Vec<String>,
+        "CallerArn" => pub caller_arn: Option<String> where length(1, 2048),
+        "ContextEntries" => pub context_entries: Vec<ContextEntry>,
+        "MaxItems" => pub max_items: Option<i64> where range(1, 1000),
+        "Marker" => pub marker: Option<String> where length(1, 320),
+    }
+}
+
+/// Errors from policy simulation.
+#[derive(Debug)]
+pub enum SimulationError {
+    Validation(ValidationError),
+
+    /// A policy in `PolicyInputList`, or one of the principal's, cannot be evaluated.
+    Policy(PolicyError),
+
+    /// `PolicySourceArn` or `CallerArn` does not name an entity the simulator accepts; holds the reason.
+    InvalidInput(String),
+    Store(StoreError),
+}
+
+impl SimulationError {
+    /// The IAM error code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Validation(e) => e.code(),
+            Self::Policy(e) => e.code(),
+            Self::InvalidInput(_) => "InvalidInput",
+            Self::Store(e) => e.code(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Validation(_) | Self::Policy(_) | Self::InvalidInput(_) => StatusCode::BAD_REQUEST,
+            Self::Store(e) => e.status(),
+        }
+    }
+}
+
+impl Error for SimulationError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Validation(e) => Some(e),
+            Self::Policy(e) => Some(e),
+            Self::InvalidInput(_) => None,
+            Self::Store(e) => Some(e),
+        }
+    }
+}
+
+impl Display for SimulationError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Validation(e) => write!(f, "{e}"),
+            Self::Policy(e) => write!(f, "{e}"),
+            Self::InvalidInput(reason) => f.write_str(reason),
+            Self::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<ValidationError> for SimulationError {
+    fn from(e: ValidationError) -> Self {
+        Self::Validation(e)
+    }
+}
+
+impl From<PolicyError> for SimulationError {
+    fn from(e: PolicyError) -> Self {
+        Self::Policy(e)
+    }
+}
+
+impl From<StoreError> for SimulationError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+operation_output! {
+    /// A statement that decided an evaluation result.
+    #[derive(Clone, Debug, Eq, PartialEq)]
+    pub struct SimulatedStatement {
+        /// The inline policy name, managed policy id, or `PolicyInputList.N` for given policies.
+        "SourcePolicyId" => pub source_policy_id: String,
+
+        /// `user`, `group`, or `role` for inline policies, `user-managed` for managed ones, and `none` for given
+        /// ones.
+        "SourcePolicyType" => pub source_policy_type: &'static str,
+    }
+}
+
+/// The decision for one action on one resource.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct EvaluationResult {
+    pub eval_action_name: String,
+    pub eval_resource_name: String,
+
+    /// `allowed`, `explicitDeny`, or `implicitDeny`.
+    pub eval_decision: &'static str,
+
+    /// The statements that allowed or explicitly denied the request; empty for an implicit deny.
+    pub matched_statements: Vec<SimulatedStatement>,
+}
+
+/// A page of evaluation results, as returned by both simulation operations.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SimulatePolicyOutput {
+    pub evaluation_results: Vec<EvaluationResult>,
+
+    /// The marker for the next page, if the results were truncated.
+    pub marker: Option<String>,
+}
+
+impl SimulatePolicyOutput {
+    /// The response body for `operation`, e.g. `SimulatePrincipalPolicy`.
+    pub fn to_xml(&self, operation: &str, request_id: &str) -> String {
+        let result = SimulatePolicyResult {
+            evaluation_results: self
+                .evaluation_results
+                .iter()
+                .map(|result| EvaluationResultElement {
+                    eval_action_name: &result.eval_action_name,
+                    eval_resource_name: &result.eval_resource_name,
+                    eval_decision: result.eval_decision,
+                    matched_statements: &result.matched_statements,
+                    missing_context_values: &[],
+                })
+                .collect(),
+            is_truncated: self.marker.is_some(),
+            marker: self.marker.as_deref(),
+        };
+        query_response(IAM_XML_NS, operation, &result, request_id)
+    }
+}
+
+operation_output! {
+    /// An evaluation result. Context keys are not simulated, so none are ever missing.
+    struct EvaluationResultElement<'a> {
+        "EvalActionName" => eval_action_name: &'a str,
+        "EvalResourceName" => eval_resource_name: &'a str,
+        "EvalDecision" => eval_decision: &'a str,
+        "MatchedStatements" => matched_statements: &'a [SimulatedStatement],
+        "MissingContextValues" => missing_context_values: &'a [String],
+    }
+}
+
+operation_output! {
+    struct SimulatePolicyResult<'a> {
+        "EvaluationResults" => evaluation_results: Vec<EvaluationResultElement<'a>>,
+        "IsTruncated" => is_truncated: bool,
+        "Marker" => marker: Option<&'a str>,
+    }
+}
+
+/// Simulate the effective policies of the user, group, or role named by `PolicySourceArn` in the caller's account,
+/// together with any policies in `PolicyInputList`.
+pub async fn simulate_principal_policy(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    input: &SimulatePrincipalPolicyInput,
+) -> Result<SimulatePolicyOutput, SimulationError> {
+    require_actions(&input.action_names)?;
+    let arn = Arn::from_str(&input.policy_source_arn)
+        .map_err(|_| SimulationError::InvalidInput(format!("Invalid ARN: {}", input.policy_source_arn)))?;
+    let (holder, holder_name) = match arn.resource().split_once('/') {
+        Some(("user", rest)) => (PolicyHolder::User, rest.rsplit('/').next().unwrap_or(rest)),
+        Some(("group", rest)) => (PolicyHolder::Group, rest.rsplit('/').next().unwrap_or(rest)),
+        Some(("role", rest)) => (PolicyHolder::Role, rest.rsplit('/').next().unwrap_or(rest)),
+        _ => {
+            return Err(SimulationError::InvalidInput(format!(
+                "PolicySourceArn must be a user, group, or role: {}",
+                input.policy_source_arn
+            )))
+        }
+    };
+
+    let account_id = context.account_id().unwrap_or_default();
+    if arn.service() != "iam" || arn.account_id() != account_id {
+        return Err(StoreError::no_such_entity(holder.kind(), holder_name).into());
+    }
+
+    let holder_id = resolve_holder(store, &account_id, holder, holder_name).await?;
+    let policies = effective_policies(store, &account_id, holder, &holder_id).await?;
+    let principal = match (&input.caller_arn, holder) {
+        (Some(caller_arn), _) => user_principal(caller_arn)?,
+        (None, PolicyHolder::User) => user_principal(&input.policy_source_arn)?,
+        (None, _) => context.principal().clone(),
+    };
+
+    let results = simulate(
+        &principal,
+        Some(holder),
+        &policies,
+        &input.policy_input_list,
+        &input.action_names,
+        &input.resource_arns,
+        &input.context_entries,
+    )?;
+    paginate(results, input.marker.as_deref(), input.max_items)
+}
+
+/// Simulate only the policies in `PolicyInputList`, as if the caller, or the user named by `CallerArn`, made the
+/// requests.
+pub async fn simulate_custom_policy(
+    context: &RequestContext,
+    input: &SimulateCustomPolicyInput,
+) -> Result<SimulatePolicyOutput, SimulationError> {
+    require_actions(&input.action_names)?;
+    if input.policy_input_list.is_empty() {
+        return Err(ValidationError::from(ParameterViolation::from(ParameterError::Missing(
+            "PolicyInputList".to_string(),
+        )))
+        .into());
+    }
+
+    let principal = match &input.caller_arn {
+        Some(caller_arn) => user_principal(caller_arn)?,
+        None => context.principal().clone(),
+    };
+
+    let results = simulate(
+        &principal,
+        None,
+        &[],
+        &input.policy_input_list,
+        &input.action_names,
+        &input.resource_arns,
+        &input.context_entries,
+    )?;
+    paginate(results, input.marker.as_deref(), input.max_items)
+}
+
+fn require_actions(action_names: &[String]) -> Result<(), ValidationError> {
+    if action_names.is_empty() {
+        return Err(ParameterViolation::from(ParameterError::Missing("ActionNames".to_string())).into());
+    }
+
+    Ok(())
+}
+
+/// The principal of the IAM user with ARN `arn`, which `CallerArn` must be.
+fn user_principal(arn: &str) -> Result<Principal, SimulationError> {
+    let invalid = || SimulationError::InvalidInput(format!("CallerArn must be the ARN of an IAM user: {arn}"));
+    let parsed = Arn::from_str(arn).map_err(|_| invalid())?;
+
+    // user/<path>/<name>
+    let resource = match (parsed.service(), parsed.resource().strip_prefix("user/")) {
+        ("iam", Some(resource)) => resource,
+        _ => return Err(invalid()),
+    };
+    let (path, user_name) = match resource.rsplit_once('/') {
+        Some((path, user_name)) => (format!("/{path}/"), user_name),
+        None => ("/".to_string(), resource),
+    };
+
+    let user = User::new(parsed.partition(), parsed.account_id(), &path, user_name).map_err(|_| invalid())?;
+    Ok(Principal::from(vec![PrincipalIdentity::from(user)]))
+}
+
+/// Evaluate every action against every resource (`*` if none are given).
+fn simulate(
+    principal: &Principal,
+    holder: Option<PolicyHolder>,
+    policies: &[EffectivePolicy],
+    policy_input_list: &[String],
+    action_names: &[String],
+    resource_arns: &[String],
+    context_entries: &[ContextEntry],
+) -> Result<Vec<EvaluationResult>, SimulationError> {
+    let mut evaluator = PolicyEvaluator::from_effective(policies)?;
+    let mut source_types = HashMap::new();
+    for policy in policies {
+        let source_type = match (policy.managed, &policy.via_group, holder) {
+            (true, _, _) => "user-managed",
+            (false, Some(_), _) => "group",
+            (false, None, Some(PolicyHolder::User)) => "user",
+            (false, None, Some(PolicyHolder::Group)) => "group",
+            (false, None, Some(PolicyHolder::Role)) => "role",
+            (false, None, None) => "none",
+        };
+        source_types.insert(policy.source.clone(), source_type);
+    }
+    for (i, document) in policy_input_list.iter().enumerate() {
+        let name = format!("PolicyInputList.{}", i + 1);
+        evaluator = evaluator.with_policy(&name, &decode_policy_document(document))?;
+        source_types.insert(name, "none");
+    }
+
+    let session_data = context_session_data(context_entries)?;
+    let star = ["*".to_string()];
+    let resources = if resource_arns.is_empty() {
+        &star[..]
+    } else {
+        resource_arns
+    };
+
+    let mut results = Vec::with_capacity(action_names.len() * resources.len());
+    for action in action_names {
+        for resource in resources {
+            let request = EvaluationRequest {
+                principal,
+                action,
+                resource,
+                context: &session_data,
+            };
+            let decision = evaluator.evaluate(&request);
+            let eval_decision = match &decision {
+                Decision::Allow => "allowed",
+                Decision::ImplicitDeny => "implicitDeny",
+                Decision::ExplicitDeny {
+                    ..
+                } => "explicitDeny",
+            };
+            let matched_statements = match decision {
+                Decision::ImplicitDeny => Vec::new(),
+                _ => evaluator
+                    .matched_statements(&request)
+                    .into_iter()
+                    .filter(|statement| statement.allow == decision.is_allowed())
+                    .map(|statement| SimulatedStatement {
+                        source_policy_type: source_types.get(&statement.policy).copied().unwrap_or("none"),
+                        source_policy_id: statement.policy,
+                    })
+                    .collect(),
+            };
+
+            results.push(EvaluationResult {
+                eval_action_name: action.clone(),
+                eval_resource_name: resource.clone(),
+                eval_decision,
+                matched_statements,
+            });
+        }
+    }
+
+    Ok(results)
+}
+
+/// The condition keys of the simulated requests.
+fn context_session_data(context_entries: &[ContextEntry]) -> Result<SessionData, SimulationError> {
+    let mut session_data = SessionData::new();
+    for (i, entry) in context_entries.iter().enumerate() {
+        let type_name = format!("ContextEntries.member.{}.ContextKeyType", i + 1);
+        if !CONTEXT_KEY_TYPES.contains(&entry.context_key_type.as_str()) {
+            return Err(ValidationError::from(ParameterViolation::new(
+                &type_name,
+                Some(entry.context_key_type.clone()),
+                format!("satisfy enum value set: [{}]", CONTEXT_KEY_TYPES.join(", ")),
+            ))
+            .into());
+        }
+
+        let value = match entry.context_key_values.first() {
+            Some(value) => value,
+            None => continue,
+        };
+        let invalid = || {
+            SimulationError::InvalidInput(format!(
+                "Invalid {} value for context key {}: {value}",
+                entry.context_key_type, entry.context_key_name
+            ))
+        };
+        let value = match entry.context_key_type.trim_end_matches("List") {
+            "boolean" => SessionValue::Bool(value.parse().map_err(|_| invalid())?),
+            "date" => SessionValue::Timestamp(value.parse::<DateTime<Utc>>().map_err(|_| invalid())?),
+            _ => SessionValue::String(value.clone()),
+        };
+        session_data.insert(&entry.context_key_name, value);
+    }
+
+    Ok(session_data)
+}
+
+/// The page of `results` starting at `marker`, an index into the results from a previous page.
+fn paginate(
+    results: Vec<EvaluationResult>,
+    marker: Option<&str>,
+    max_items: Option<i64>,
+) -> Result<SimulatePolicyOutput, SimulationError> {
+    let start = match marker {
+        None => 0,
+        Some(marker) => match marker.parse::<usize>() {
+            Ok(start) if start <= results.len() => start,
+            _ => return Err(SimulationError::InvalidInput(format!("Invalid Marker: {marker}"))),
+        },
+    };
+    let max_items = max_items.and_then(|max_items| usize::try_from(max_items).ok()).unwrap_or(DEFAULT_MAX_ITEMS);
+    let end = results.len().min(start + max_items);
+    let marker = (end < results.len()).then(|| end.to_string());
+
+    Ok(SimulatePolicyOutput {
+        evaluation_results: results.into_iter().skip(start).take(end - start).collect(),
+        marker,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            simulate_custom_policy, simulate_principal_policy, SimulateCustomPolicyInput, SimulatePrincipalPolicyInput,
+        },
+        crate::{
+            context::RequestContext,
+            operation::{parameters, FromParameters},
+            store::{ControlPlaneStore, InlinePolicy, MemoryStore, PolicyHolder, User},
+        },
+        chrono::Utc,
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{self as principal, Principal, PrincipalIdentity},
+    };
+
+    const DENY_WITHOUT_MFA: &str = r#"{"Statement":{"Effect":"Deny","Action":"iam:GetUser","Resource":"*",
+        "Condition":{"StringEquals":{"aws:MultiFactorAuthPresent":"false"}}}}"#;
+
+    #[test_log::test(tokio::test)]
+    async fn test_simulate_policies() {
+        let store = MemoryStore::new();
+        let bob = User {
+            user_id: "AIDAEXAMPLEBOB01".to_string(),
+            account_id: "123456789012".to_string(),
+            user_name: "bob".to_string(),
+            path: "/".to_string(),
+            permissions_boundary: None,
+            created_at: Utc::now(),
+        };
+        store.create_user(&bob, &[]).await.unwrap();
+        let policy = InlinePolicy {
+            policy_name: "ReadOnly".to_string(),
+            policy_document: r#"{"Statement":[{"Sid":"Read","Effect":"Allow","Action":"iam:Get*","Resource":"*"}]}"#
+                .to_string(),
+        };
+        store.put_inline_policy(PolicyHolder::User, &bob.user_id, &policy).await.unwrap();
+
+        let admin = principal::User::new("aws", "123456789012", "/", "admin").unwrap();
+        let context =
+            RequestContext::builder().principal(Principal::from(vec![PrincipalIdentity::from(admin)])).build().unwrap();
+
+        let input = SimulatePrincipalPolicyInput::from_parameters(&parameters(&[
+            ("PolicySourceArn", "arn:aws:iam::123456789012:user/bob"),
+            ("ActionNames.member.1", "iam:GetUser"),
+            ("ActionNames.member.2", "iam:DeleteUser"),
+            ("PolicyInputList.member.1", DENY_WITHOUT_MFA),
+            ("ContextEntries.member.1.ContextKeyName", "aws:MultiFactorAuthPresent"),
+            ("ContextEntries.member.1.ContextKeyValues.member.1", "true"),
+            ("ContextEntries.member.1.ContextKeyType", "boolean"),
+        ]))
+        .unwrap();
+        let output = simulate_principal_policy(&store, &context, &input).await.unwrap();
+        let decisions: Vec<_> = output
+            .evaluation_results
+            .iter()
+            .map(|result| (result.eval_action_name.as_str(), result.eval_decision, result.matched_statements.len()))
+            .collect();
+        assert_eq!(decisions, vec![("iam:GetUser", "allowed", 1), ("iam:DeleteUser", "implicitDeny", 0)]);
+        assert_eq!(output.evaluation_results[0].matched_statements[0].source_policy_id, "ReadOnly");
+        assert_eq!(output.evaluation_results[0].matched_statements[0].source_policy_type, "user");
+        let xml = output.to_xml("SimulatePrincipalPolicy", "01234567-89ab-cdef-0123-456789abcdef");
+        assert!(xml.contains("<EvalDecision>allowed</EvalDecision>"), "{xml}");
+        assert!(xml.contains("<IsTruncated>false</IsTruncated>"), "{xml}");
+
+        let input = SimulateCustomPolicyInput::from_parameters(&parameters(&[
+            ("ActionNames.member.1", "iam:DeleteUser"),
+            ("ResourceArns.member.1", "arn:aws:iam::123456789012:user/bob"),
+            ("ResourceArns.member.2", "arn:aws:iam::123456789012:user/carol"),
+            ("PolicyInputList.member.1", r#"{"Statement":{"Effect":"Allow","Action":"iam:*","Resource":"*"}}"#),
+            (
+                "PolicyInputList.member.2",
+                r#"{"Statement":{"Effect":"Deny","Action":"iam:Delete*","Resource":"arn:aws:iam::*:user/bob"}}"#,
+            ),
+            ("MaxItems", "1"),
+        ]))
+        .unwrap();
+        let output = simulate_custom_policy(&context, &input).await.unwrap();
+        assert_eq!(output.evaluation_results[0].eval_decision, "explicitDeny");
+        assert_eq!(output.evaluation_results[0].matched_statements[0].source_policy_id, "PolicyInputList.2");
+        assert_eq!(output.marker.as_deref(), Some("1"));
+
+        let input = SimulatePrincipalPolicyInput::from_parameters(&parameters(&[
+            ("PolicySourceArn", "arn:aws:iam::123456789012:user/nobody"),
+            ("ActionNames.member.1", "iam:GetUser"),
+        ]))
+        .unwrap();
+        let e = simulate_principal_policy(&store, &context, &input).await.unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("NoSuchEntity", 404));
+    }
+}
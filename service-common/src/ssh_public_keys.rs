@@ -0,0 +1,582 @@
+//! IAM SSH public key operations: `UploadSSHPublicKey`, `ListSSHPublicKeys`, `UpdateSSHPublicKey`, and
+//! `DeleteSSHPublicKey`, written against [ControlPlaneStore].
+//!
+//! These are the keys AWS CodeCommit accepts for Git over SSH. As in AWS, keys are RSA keys of at least
+//! [MIN_SSH_PUBLIC_KEY_BITS] bits, given in OpenSSH (`ssh-rsa AAAA...`) or PEM (`BEGIN PUBLIC KEY`) encoding, and a
+//! user has at most [MAX_SSH_PUBLIC_KEYS_PER_USER] of them. Each key is identified by the MD5 fingerprint of its
+//! OpenSSH wire encoding, which is what `ssh-keygen -l -E md5` prints, so uploading the same key again in either
+//! encoding is rejected as a duplicate.
+use {
+    crate::{
+        context::RequestContext,
+        ids::{unique_id, SSH_PUBLIC_KEY_ID_PREFIX},
+        operation::{output::query_response, ValidationError},
+        operation_input, operation_output,
+        protocol::IAM_XML_NS,
+        store::{ControlPlaneStore, EntityKind, SshPublicKey, StoreError, User},
+        users::caller_user_name,
+    },
+    chrono::{DateTime, Utc},
+    http::StatusCode,
+    md5::{Digest, Md5},
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+    x509_parser::{prelude::FromDer, public_key::PublicKey, x509::SubjectPublicKeyInfo},
+};
+
+/// The number of SSH public keys a user may have.
+pub const MAX_SSH_PUBLIC_KEYS_PER_USER: usize = 5;
+
+/// The smallest RSA modulus accepted, in bits.
+pub const MIN_SSH_PUBLIC_KEY_BITS: usize = 2048;
+
+/// The number of keys returned by ListSSHPublicKeys when `MaxItems` is not given.
+pub const DEFAULT_LIST_SSH_PUBLIC_KEYS_MAX_ITEMS: usize = 100;
+
+/// The OpenSSH name of RSA keys.
+const SSH_RSA: &str = "ssh-rsa";
+
+operation_input! {
+    /// Input for the UploadSSHPublicKey operation.
+    pub struct UploadSshPublicKeyInput {
+        "UserName" => pub user_name: String where length(1, 64), pattern(r"[\w+=,.@-]+"),
+        "SSHPublicKeyBody" => pub ssh_public_key_body: String where length(1, 16384), pattern(r"[\t\n\r\x20-\xff]+"),
+    }
+}
+
+operation_input! {
+    /// Input for the ListSSHPublicKeys operation. Without a user name, the caller's keys are listed.
+    pub struct ListSshPublicKeysInput {
+        "UserName" => pub user_name: Option<String> where length(1, 64), pattern(r"[\w+=,.@-]+"),
+        "Marker" => pub marker: Option<String> where length(1, 320),
+        "MaxItems" => pub max_items: Option<i64> where range(1, 1000),
+    }
+}
+
+operation_input! {
+    /// Input for the UpdateSSHPublicKey operation.
+    pub struct UpdateSshPublicKeyInput {
+        "UserName" => pub user_name: String where length(1, 64), pattern(r"[\w+=,.@-]+"),
+        "SSHPublicKeyId" => pub ssh_public_key_id: String where length(16, 128), pattern(r"\w+"),
+        "Status" => pub status: String where pattern(r"Active|Inactive"),
+    }
+}
+
+operation_input! {
+    /// Input for the DeleteSSHPublicKey operation.
+    pub struct DeleteSshPublicKeyInput {
+        "UserName" => pub user_name: String where length(1, 64), pattern(r"[\w+=,.@-]+"),
+        "SSHPublicKeyId" => pub ssh_public_key_id: String where length(16, 128), pattern(r"\w+"),
+    }
+}
+
+/// Errors from SSH public key operations.
+#[derive(Debug)]
+pub enum SshPublicKeyError {
+    Validation(ValidationError),
+
+    /// No user name was given and the caller is not an IAM user.
+    UserNameRequired,
+
+    /// The key is neither an OpenSSH nor a PEM public key, or is not an RSA key.
+    UnrecognizedEncoding,
+
+    /// The key could not be decoded, or is too short.
+    InvalidPublicKey,
+
+    /// The user already has a key with the same fingerprint.
+    Duplicate,
+    Store(StoreError),
+}
+
+impl SshPublicKeyError {
+    /// The IAM error code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Validation(e) => e.code(),
+            Self::UserNameRequired => "ValidationError",
+            Self::UnrecognizedEncoding => "UnrecognizedPublicKeyEncoding",
+            Self::InvalidPublicKey => "InvalidPublicKey",
+            Self::Duplicate => "DuplicateSSHPublicKey",
+            Self::Store(e) => e.code(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Store(e) => e.status(),
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl Error for SshPublicKeyError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Validation(e) => Some(e),
+            Self::Store(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for SshPublicKeyError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Validation(e) => write!(f, "{e}"),
+            Self::UserNameRequired => f.write_str("Must specify userName when calling with non-User credentials"),
+            Self::UnrecognizedEncoding => f.write_str(
+                "The request was rejected because the public key encoding format is unsupported or unrecognized.",
+            ),
+            Self::InvalidPublicKey => {
+                f.write_str("The request was rejected because the public key is malformed or otherwise invalid.")
+            }
+            Self::Duplicate => f.write_str(
+                "The request was rejected because the SSH public key is already associated with the specified IAM \
+                 user.",
+            ),
+            Self::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<ValidationError> for SshPublicKeyError {
+    fn from(e: ValidationError) -> Self {
+        Self::Validation(e)
+    }
+}
+
+impl From<StoreError> for SshPublicKeyError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+/// An SSH public key as shown to callers.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SshPublicKeyMetadata {
+    pub user_name: String,
+    pub ssh_public_key: SshPublicKey,
+}
+
+impl SshPublicKeyMetadata {
+    /// The `Status` IAM reports for the key.
+    pub fn status(&self) -> &'static str {
+        if self.ssh_public_key.active {
+            "Active"
+        } else {
+            "Inactive"
+        }
+    }
+}
+
+/// A newly uploaded SSH public key.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct UploadSshPublicKeyOutput {
+    pub ssh_public_key: SshPublicKeyMetadata,
+}
+
+impl UploadSshPublicKeyOutput {
+    /// The `UploadSSHPublicKeyResponse` body.
+    pub fn to_xml(&self, request_id: &str) -> String {
+        let result = UploadSshPublicKeyResult {
+            ssh_public_key: SshPublicKeyElement::new(&self.ssh_public_key, true),
+        };
+        query_response(IAM_XML_NS, "UploadSSHPublicKey", &result, request_id)
+    }
+}
+
+/// One page of a user's SSH public keys, as returned by ListSSHPublicKeys.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ListSshPublicKeysOutput {
+    pub ssh_public_keys: Vec<SshPublicKeyMetadata>,
+
+    /// If the list is truncated, the marker to pass for the next page.
+    pub marker: Option<String>,
+}
+
+impl ListSshPublicKeysOutput {
+    /// The `ListSSHPublicKeysResponse` body. As in AWS, the key bodies are not included.
+    pub fn to_xml(&self, request_id: &str) -> String {
+        let result = ListSshPublicKeysResult {
+            ssh_public_keys: self
+                .ssh_public_keys
+                .iter()
+                .map(|metadata| SshPublicKeyElement::new(metadata, false))
+                .collect(),
+            is_truncated: self.marker.is_some(),
+            marker: self.marker.as_deref(),
+        };
+        query_response(IAM_XML_NS, "ListSSHPublicKeys", &result, request_id)
+    }
+}
+
+operation_output! {
+    /// An SSH public key, with the children in the order AWS writes them. Only uploads include the fingerprint
+    /// and body.
+    struct SshPublicKeyElement<'a> {
+        "UserName" => user_name: &'a str,
+        "SSHPublicKeyId" => ssh_public_key_id: &'a str,
+        "Fingerprint" => fingerprint: Option<&'a str>,
+        "SSHPublicKeyBody" => ssh_public_key_body: Option<&'a str>,
+        "Status" => status: &'a str,
+        "UploadDate" => upload_date: DateTime<Utc>,
+    }
+}
+
+impl<'a> SshPublicKeyElement<'a> {
+    fn new(metadata: &'a SshPublicKeyMetadata, with_body: bool) -> Self {
+        let key = &metadata.ssh_public_key;
+        Self {
+            user_name: &metadata.user_name,
+            ssh_public_key_id: &key.ssh_public_key_id,
+            fingerprint: with_body.then_some(key.fingerprint.as_str()),
+            ssh_public_key_body: with_body.then_some(key.ssh_public_key_body.as_str()),
+            status: metadata.status(),
+            upload_date: key.created_at,
+        }
+    }
+}
+
+operation_output! {
+    struct UploadSshPublicKeyResult<'a> {
+        "SSHPublicKey" => ssh_public_key: SshPublicKeyElement<'a>,
+    }
+}
+
+operation_output! {
+    struct ListSshPublicKeysResult<'a> {
+        "SSHPublicKeys" => ssh_public_keys: Vec<SshPublicKeyElement<'a>>,
+        "IsTruncated" => is_truncated: bool,
+        "Marker" => marker: Option<&'a str>,
+    }
+}
+
+/// The MD5 fingerprint of an OpenSSH or PEM encoded RSA public key, as colon-separated lowercase hex.
+pub fn fingerprint(ssh_public_key_body: &str) -> Result<String, SshPublicKeyError> {
+    let body = ssh_public_key_body.trim();
+    let (exponent, modulus) = if body.starts_with("-----BEGIN ") {
+        pem_rsa_key(body)?
+    } else {
+        openssh_rsa_key(body)?
+    };
+
+    if bit_length(&modulus) < MIN_SSH_PUBLIC_KEY_BITS {
+        return Err(SshPublicKeyError::InvalidPublicKey);
+    }
+
+    let mut blob = Vec::new();
+    put_string(&mut blob, SSH_RSA.as_bytes());
+    put_mpint(&mut blob, &exponent);
+    put_mpint(&mut blob, &modulus);
+    let digest = Md5::digest(&blob);
+    Ok(digest.iter().map(|b| format!("{b:02x}")).collect::<Vec<_>>().join(":"))
+}
+
+/// The exponent and modulus of an `ssh-rsa <base64> [comment]` key.
+fn openssh_rsa_key(body: &str) -> Result<(Vec<u8>, Vec<u8>), SshPublicKeyError> {
+    let mut fields = body.split_ascii_whitespace();
+    if fields.next() != Some(SSH_RSA) {
+        return Err(SshPublicKeyError::UnrecognizedEncoding);
+    }
+
+    let blob =
+        fields.next().and_then(|encoded| base64::decode(encoded).ok()).ok_or(SshPublicKeyError::InvalidPublicKey)?;
+    let mut blob = blob.as_slice();
+    let mut next = || take_string(&mut blob).ok_or(SshPublicKeyError::InvalidPublicKey);
+    if next()? != SSH_RSA.as_bytes() {
+        return Err(SshPublicKeyError::InvalidPublicKey);
+    }
+    let exponent = next()?.to_vec();
+    let modulus = next()?.to_vec();
+    Ok((exponent, modulus))
+}
+
+/// The exponent and modulus of a PEM `PUBLIC KEY` (SubjectPublicKeyInfo) RSA key.
+fn pem_rsa_key(body: &str) -> Result<(Vec<u8>, Vec<u8>), SshPublicKeyError> {
+    let (label, der) = pem_rfc7468::decode_vec(body.as_bytes()).map_err(|_| SshPublicKeyError::InvalidPublicKey)?;
+    if label != "PUBLIC KEY" {
+        return Err(SshPublicKeyError::UnrecognizedEncoding);
+    }
+
+    let (_, spki) = SubjectPublicKeyInfo::from_der(&der).map_err(|_| SshPublicKeyError::InvalidPublicKey)?;
+    match spki.parsed() {
+        Ok(PublicKey::RSA(rsa)) => Ok((rsa.exponent.to_vec(), rsa.modulus.to_vec())),
+        Ok(_) => Err(SshPublicKeyError::UnrecognizedEncoding),
+        Err(_) => Err(SshPublicKeyError::InvalidPublicKey),
+    }
+}
+
+/// Remove a length-prefixed string from the front of an OpenSSH wire encoding.
+fn take_string<'a>(data: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let length = u32::from_be_bytes(data.get(..4)?.try_into().ok()?) as usize;
+    let value = data.get(4..4 + length)?;
+    *data = &data[4 + length..];
+    Some(value)
+}
+
+fn put_string(data: &mut Vec<u8>, value: &[u8]) {
+    data.extend_from_slice(&(value.len() as u32).to_be_bytes());
+    data.extend_from_slice(value);
+}
+
+/// Append a non-negative big-endian integer in the minimal `mpint` form: no leading zero bytes, except one to keep
+/// the high bit clear.
+fn put_mpint(data: &mut Vec<u8>, value: &[u8]) {
+    let start = value.iter().position(|b| *b != 0).unwrap_or(value.len());
+    let value = &value[start..];
+    if value.first().map(|b| b & 0x80 != 0).unwrap_or(false) {
+        let mut padded = vec![0];
+        padded.extend_from_slice(value);
+        put_string(data, &padded);
+    } else {
+        put_string(data, value);
+    }
+}
+
+/// The number of significant bits in a big-endian integer.
+fn bit_length(value: &[u8]) -> usize {
+    match value.iter().position(|b| *b != 0) {
+        Some(start) => (value.len() - start) * 8 - value[start].leading_zeros() as usize,
+        None => 0,
+    }
+}
+
+/// The user named in a request, or the caller if none is named.
+async fn target_user(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    user_name: Option<&str>,
+) -> Result<User, SshPublicKeyError> {
+    let user_name = match user_name {
+        Some(user_name) => user_name.to_string(),
+        None => caller_user_name(context).ok_or(SshPublicKeyError::UserNameRequired)?,
+    };
+
+    let account_id = context.account_id().unwrap_or_default();
+    Ok(store.get_user(&account_id, &user_name).await?)
+}
+
+/// Upload an active SSH public key for a user. The key is validated and fingerprinted before anything is stored.
+pub async fn upload_ssh_public_key(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    input: &UploadSshPublicKeyInput,
+) -> Result<UploadSshPublicKeyOutput, SshPublicKeyError> {
+    let fingerprint = fingerprint(&input.ssh_public_key_body)?;
+    let user = target_user(store, context, Some(&input.user_name)).await?;
+    let existing = store.list_ssh_public_keys(&user.user_id).await?;
+    if existing.iter().any(|key| key.fingerprint == fingerprint) {
+        return Err(SshPublicKeyError::Duplicate);
+    }
+    if existing.len() >= MAX_SSH_PUBLIC_KEYS_PER_USER {
+        return Err(StoreError::limit_exceeded(EntityKind::User, &user.user_name, MAX_SSH_PUBLIC_KEYS_PER_USER).into());
+    }
+
+    let ssh_public_key = SshPublicKey {
+        user_id: user.user_id.clone(),
+        ssh_public_key_id: unique_id(SSH_PUBLIC_KEY_ID_PREFIX),
+        fingerprint,
+        ssh_public_key_body: input.ssh_public_key_body.trim().to_string(),
+        active: true,
+        created_at: Utc::now(),
+    };
+    store.create_ssh_public_key(&ssh_public_key).await?;
+
+    Ok(UploadSshPublicKeyOutput {
+        ssh_public_key: SshPublicKeyMetadata {
+            user_name: user.user_name,
+            ssh_public_key,
+        },
+    })
+}
+
+/// List one page of a user's SSH public keys, oldest first. The marker is the id of the first key of the next page.
+pub async fn list_ssh_public_keys(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    input: &ListSshPublicKeysInput,
+) -> Result<ListSshPublicKeysOutput, SshPublicKeyError> {
+    let user = target_user(store, context, input.user_name.as_deref()).await?;
+    let ssh_public_keys = store.list_ssh_public_keys(&user.user_id).await?;
+    let start = match &input.marker {
+        Some(marker) => {
+            ssh_public_keys.iter().position(|key| key.ssh_public_key_id == *marker).unwrap_or(ssh_public_keys.len())
+        }
+        None => 0,
+    };
+    let max_items =
+        input.max_items.map(|max_items| max_items as usize).unwrap_or(DEFAULT_LIST_SSH_PUBLIC_KEYS_MAX_ITEMS);
+
+    let mut ssh_public_keys = ssh_public_keys.into_iter().skip(start);
+    let page: Vec<SshPublicKeyMetadata> = ssh_public_keys
+        .by_ref()
+        .take(max_items)
+        .map(|ssh_public_key| SshPublicKeyMetadata {
+            user_name: user.user_name.clone(),
+            ssh_public_key,
+        })
+        .collect();
+    Ok(ListSshPublicKeysOutput {
+        ssh_public_keys: page,
+        marker: ssh_public_keys.next().map(|key| key.ssh_public_key_id),
+    })
+}
+
+/// Activate or deactivate one of a user's SSH public keys.
+pub async fn update_ssh_public_key(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    input: &UpdateSshPublicKeyInput,
+) -> Result<(), SshPublicKeyError> {
+    let user = target_user(store, context, Some(&input.user_name)).await?;
+    let active = input.status == "Active";
+    Ok(store.set_ssh_public_key_active(&user.user_id, &input.ssh_public_key_id, active).await?)
+}
+
+pub async fn delete_ssh_public_key(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    input: &DeleteSshPublicKeyInput,
+) -> Result<(), SshPublicKeyError> {
+    let user = target_user(store, context, Some(&input.user_name)).await?;
+    Ok(store.delete_ssh_public_key(&user.user_id, &input.ssh_public_key_id).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            delete_ssh_public_key, fingerprint, list_ssh_public_keys, update_ssh_public_key, upload_ssh_public_key,
+            DeleteSshPublicKeyInput, ListSshPublicKeysInput, SshPublicKeyError, UpdateSshPublicKeyInput,
+            UploadSshPublicKeyInput,
+        },
+        crate::{
+            context::RequestContext,
+            operation::{parameters, FromParameters},
+            store::{ControlPlaneStore, MemoryStore, User},
+        },
+        chrono::Utc,
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{Principal, PrincipalIdentity, User as PrincipalUser},
+        std::collections::HashMap,
+    };
+
+    const OPENSSH_KEY: &str =
+        "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQCQ+YDDZkydRpQoibFEqK7AY92ON3zJtafaYHLqqJ5s/GoMIznEWJ58\
+                               SkXXeYJ/gBgGR/FqG16i9Q/VTASS3ef2/KwauWMT2etkaHE+qa9EGNmB3Czh0lte7JAyFcu2LTtfOiEwDFaE59UL\
+                               WOnMNBW+SdDVLxMYSMNtl+Z/UCaKK8G2qP5yGDuP50xl/0khSRi/g+Lo8QoXu6M9sZLboxUv6rqm7UHCdJY7kNJf\
+                               WZUxThOTZb90pOCoRk6Kzds+Z2uS1QpmS8EMNOxrotInfS8VODtcxfKcFxFtf7rPs5slE6xXG+vfTyAFEfA7jJWN\
+                               ifXKRVUEe0RQ3q5pQttRSOAz alice@example.com";
+
+    /// The same key, as printed by `ssh-keygen -e -m PKCS8`.
+    const PEM_KEY: &str = "-----BEGIN PUBLIC KEY-----
+MIIBIjANBgkqhkiG9w0BAQEFAAOCAQ8AMIIBCgKCAQEAkPmAw2ZMnUaUKImxRKiu
+wGPdjjd8ybWn2mBy6qiebPxqDCM5xFiefEpF13mCf4AYBkfxahteovUP1UwEkt3n
+9vysGrljE9nrZGhxPqmvRBjZgdws4dJbXuyQMhXLti07XzohMAxWhOfVC1jpzDQV
+vknQ1S8TGEjDbZfmf1AmiivBtqj+chg7j+dMZf9JIUkYv4Pi6PEKF7ujPbGS26MV
+L+q6pu1BwnSWO5DSX1mVMU4Tk2W/dKTgqEZOis3bPmdrktUKZkvBDDTsa6LSJ30v
+FTg7XMXynBcRbX+6z7ObJROsVxvr308gBRHwO4yVjYn1ykVVBHtEUN6uaULbUUjg
+MwIDAQAB
+-----END PUBLIC KEY-----
+";
+
+    /// A 1024-bit key, which is too short.
+    const SHORT_KEY: &str =
+        "ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAAAgQCrMfMF5R/v2zUUVi7iout9q9nhbj4djdECHpQTWSJJG6qFeAFLhA8Y\
+                             E31f0X/UY1BQhw7DNjS4nQTq8h2lLsSu+WwnZ+kgxuJ/tnzCbEVtzYvSthvVNb7CeyQQXVcaKWerho6DGHqzRxCj\
+                             Ag4LdOMt49Swrwr6IoolrDxX9BN+Zw==";
+
+    #[test_log::test]
+    fn test_fingerprint() {
+        // As printed by `ssh-keygen -l -E md5`.
+        let expected = "df:e8:b9:5a:4c:c2:b4:3d:72:41:6b:45:c8:e6:01:2e";
+        assert_eq!(fingerprint(OPENSSH_KEY).unwrap(), expected);
+        assert_eq!(fingerprint(PEM_KEY).unwrap(), expected);
+
+        assert!(matches!(fingerprint(SHORT_KEY), Err(SshPublicKeyError::InvalidPublicKey)));
+        assert!(matches!(
+            fingerprint("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5"),
+            Err(SshPublicKeyError::UnrecognizedEncoding)
+        ));
+        assert!(matches!(fingerprint("ssh-rsa AAAA!"), Err(SshPublicKeyError::InvalidPublicKey)));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_ssh_public_key_lifecycle() {
+        let store = MemoryStore::new();
+        store
+            .create_user(
+                &User {
+                    user_id: "AIDAEXAMPLEUSER1".to_string(),
+                    account_id: "123456789012".to_string(),
+                    user_name: "Alice".to_string(),
+                    path: "/".to_string(),
+                    permissions_boundary: None,
+                    created_at: Utc::now(),
+                },
+                &[],
+            )
+            .await
+            .unwrap();
+        let alice = PrincipalUser::new("aws", "123456789012", "/", "Alice").unwrap();
+        let context =
+            RequestContext::builder().principal(Principal::from(vec![PrincipalIdentity::from(alice)])).build().unwrap();
+
+        let input = UploadSshPublicKeyInput::from_parameters(&parameters(&[
+            ("UserName", "alice"),
+            ("SSHPublicKeyBody", PEM_KEY),
+        ]))
+        .unwrap();
+        let uploaded = upload_ssh_public_key(&store, &context, &input).await.unwrap();
+        let key = &uploaded.ssh_public_key.ssh_public_key;
+        assert!(key.ssh_public_key_id.starts_with("APKA"));
+        let xml = uploaded.to_xml("01234567-89ab-cdef-0123-456789abcdef");
+        assert!(xml.contains("<Fingerprint>df:e8:b9:5a:4c:c2:b4:3d:72:41:6b:45:c8:e6:01:2e</Fingerprint>"), "{xml}");
+        assert!(xml.contains("<Status>Active</Status>"), "{xml}");
+
+        // The same key in OpenSSH encoding is a duplicate.
+        let input = UploadSshPublicKeyInput::from_parameters(&parameters(&[
+            ("UserName", "Alice"),
+            ("SSHPublicKeyBody", OPENSSH_KEY),
+        ]))
+        .unwrap();
+        let e = upload_ssh_public_key(&store, &context, &input).await.unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("DuplicateSSHPublicKey", 400));
+
+        let ssh_public_key_id = key.ssh_public_key_id.as_str();
+        let input = UpdateSshPublicKeyInput::from_parameters(&parameters(&[
+            ("UserName", "Alice"),
+            ("SSHPublicKeyId", ssh_public_key_id),
+            ("Status", "Inactive"),
+        ]))
+        .unwrap();
+        update_ssh_public_key(&store, &context, &input).await.unwrap();
+        let input = ListSshPublicKeysInput::from_parameters(&HashMap::new()).unwrap();
+        let page = list_ssh_public_keys(&store, &context, &input).await.unwrap();
+        assert_eq!(page.ssh_public_keys.len(), 1);
+        let xml = page.to_xml("01234567-89ab-cdef-0123-456789abcdef");
+        assert!(xml.contains("<Status>Inactive</Status>"), "{xml}");
+        assert!(!xml.contains("SSHPublicKeyBody"), "{xml}");
+
+        let e = UpdateSshPublicKeyInput::from_parameters(&parameters(&[
+            ("UserName", "Alice"),
+            ("SSHPublicKeyId", ssh_public_key_id),
+            ("Status", "Disabled"),
+        ]))
+        .unwrap_err();
+        assert_eq!(e.violations()[0].name(), "Status");
+
+        assert_eq!(store.delete_user("123456789012", "Alice").await.unwrap_err().code(), "DeleteConflict");
+        let input = DeleteSshPublicKeyInput::from_parameters(&parameters(&[
+            ("UserName", "Alice"),
+            ("SSHPublicKeyId", ssh_public_key_id),
+        ]))
+        .unwrap();
+        delete_ssh_public_key(&store, &context, &input).await.unwrap();
+        let e = delete_ssh_public_key(&store, &context, &input).await.unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("NoSuchEntity", 404));
+    }
+}
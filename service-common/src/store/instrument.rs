@@ -0,0 +1,624 @@
+//! Timing for control plane store operations.
+//!
+//! [InstrumentedStore] wraps a [ControlPlaneStore] and times each call. Every call is added to a latency histogram
+//! for its operation name in [QueryMetrics]; calls slower than [QueryLogConfig::slow_query_ms] are also logged with
+//! their parameters. Parameters that hold secrets or policy documents are redacted from the log.
+//!
+//! To correlate slow queries with access logs, the service implementation is wrapped in [ScopeRequestId], which makes
+//! the request id available to store calls made while the request is handled. Calls made outside a request, such as
+//! signing key lookups during signature verification, are logged without one.
+use {
+    super::{
+        AccessKey, Account, ControlPlaneStore, EntityKind, Group, InlinePolicy, ManagedPolicy, PolicyHolder, Role,
+        ServiceSpecificCredential, SshPublicKey, StoreError, Tag, User,
+    },
+    crate::histogram::Histogram,
+    async_trait::async_trait,
+    hyper::{service::Service, Request},
+    log::warn,
+    scratchstack_http_framework::RequestId,
+    serde::Deserialize,
+    std::{
+        collections::BTreeMap,
+        fmt::Write,
+        future::Future,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+        time::{Duration, Instant},
+    },
+};
+
+/// Upper bounds of the query time histogram buckets, in seconds.
+pub const QUERY_BUCKETS: &[f64] = &[0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5];
+
+tokio::task_local! {
+    static REQUEST_ID: RequestId;
+}
+
+/// Settings for slow query logging.
+#[derive(Clone, Debug, Deserialize)]
+pub struct QueryLogConfig {
+    /// Store calls taking longer than this, in milliseconds, are logged. Zero disables the log.
+    #[serde(default = "QueryLogConfig::default_slow_query_ms")]
+    pub slow_query_ms: u64,
+}
+
+impl QueryLogConfig {
+    fn default_slow_query_ms() -> u64 {
+        500
+    }
+}
+
+impl Default for QueryLogConfig {
+    fn default() -> Self {
+        Self {
+            slow_query_ms: Self::default_slow_query_ms(),
+        }
+    }
+}
+
+/// Latency histograms for store calls, by operation name.
+#[derive(Debug, Default)]
+pub struct QueryMetrics {
+    state: Mutex<BTreeMap<&'static str, Histogram>>,
+}
+
+impl QueryMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, query: &'static str, elapsed: Duration) {
+        let mut state = self.state.lock().expect("query metrics poisoned");
+        state.entry(query).or_insert_with(|| Histogram::new(QUERY_BUCKETS)).observe(elapsed.as_secs_f64());
+    }
+
+    /// The number of calls recorded for `query`.
+    pub fn count(&self, query: &str) -> u64 {
+        let state = self.state.lock().expect("query metrics poisoned");
+        state.get(query).map(Histogram::count).unwrap_or_default()
+    }
+
+    /// One store call time histogram per operation, labelled `query`.
+    pub fn render(&self) -> String {
+        let state = self.state.lock().expect("query metrics poisoned");
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# HELP scratchstack_store_query_seconds Time spent in control plane store calls.");
+        let _ = writeln!(out, "# TYPE scratchstack_store_query_seconds histogram");
+        for (query, histogram) in state.iter() {
+            histogram.render(&mut out, "scratchstack_store_query_seconds", &format!("query=\"{query}\""));
+        }
+        out
+    }
+}
+
+/// The id of the request being handled by the current task, if it runs inside [ScopeRequestId].
+pub fn current_request_id() -> Option<RequestId> {
+    REQUEST_ID.try_with(|request_id| *request_id).ok()
+}
+
+/// Stand-in for a parameter value that must not be logged, such as a secret key or a policy document.
+fn redacted(value: &str) -> String {
+    format!("<redacted {} bytes>", value.len())
+}
+
+/// A [ControlPlaneStore] wrapper that records the latency of each call and logs slow ones.
+#[derive(Debug)]
+pub struct InstrumentedStore<S> {
+    inner: S,
+    metrics: Arc<QueryMetrics>,
+    slow_query: Option<Duration>,
+}
+
+impl<S> InstrumentedStore<S> {
+    pub fn new(inner: S, metrics: Arc<QueryMetrics>, config: &QueryLogConfig) -> Self {
+        Self {
+            inner,
+            metrics,
+            slow_query: (config.slow_query_ms > 0).then(|| Duration::from_millis(config.slow_query_ms)),
+        }
+    }
+
+    /// Run `call`, recording it as `query`. The parameters are only formatted if the call is slow.
+    async fn timed<T, F, P>(&self, query: &'static str, params: P, call: F) -> Result<T, StoreError>
+    where
+        F: Future<Output = Result<T, StoreError>>,
+        P: FnOnce() -> String,
+    {
+        let start = Instant::now();
+        let result = call.await;
+        let elapsed = start.elapsed();
+        self.metrics.record(query, elapsed);
+
+        if let Some(threshold) = self.slow_query {
+            if elapsed > threshold {
+                let request_id = current_request_id().map(|id| id.to_string()).unwrap_or_else(|| "-".to_string());
+                let outcome = match &result {
+                    Ok(_) => "ok",
+                    Err(e) => e.code(),
+                };
+                warn!(
+                    "Slow query: {} took {}ms request_id={} outcome={} params=[{}]",
+                    query,
+                    elapsed.as_millis(),
+                    request_id,
+                    outcome,
+                    params()
+                );
+            }
+        }
+
+        result
+    }
+}
+
+#[async_trait]
+impl<S: ControlPlaneStore> ControlPlaneStore for InstrumentedStore<S> {
+    async fn create_account(&self, account: &Account) -> Result<(), StoreError> {
+        let params = || format!("account_id={} alias={:?}", account.account_id, account.alias);
+        self.timed("create_account", params, self.inner.create_account(account)).await
+    }
+
+    async fn get_account(&self, account_id: &str) -> Result<Account, StoreError> {
+        let params = || format!("account_id={account_id}");
+        self.timed("get_account", params, self.inner.get_account(account_id)).await
+    }
+
+    async fn set_account_alias(&self, account_id: &str, alias: Option<&str>) -> Result<(), StoreError> {
+        let params = || format!("account_id={account_id} alias={alias:?}");
+        self.timed("set_account_alias", params, self.inner.set_account_alias(account_id, alias)).await
+    }
+
+    async fn create_user(&self, user: &User, tags: &[Tag]) -> Result<(), StoreError> {
+        let params = || format!("account_id={} user_name={} tags={}", user.account_id, user.user_name, tags.len());
+        self.timed("create_user", params, self.inner.create_user(user, tags)).await
+    }
+
+    async fn get_user(&self, account_id: &str, user_name: &str) -> Result<User, StoreError> {
+        let params = || format!("account_id={account_id} user_name={user_name}");
+        self.timed("get_user", params, self.inner.get_user(account_id, user_name)).await
+    }
+
+    async fn list_users(&self, account_id: &str, path_prefix: &str) -> Result<Vec<User>, StoreError> {
+        let params = || format!("account_id={account_id} path_prefix={path_prefix}");
+        self.timed("list_users", params, self.inner.list_users(account_id, path_prefix)).await
+    }
+
+    async fn update_user(
+        &self,
+        account_id: &str,
+        user_name: &str,
+        new_user_name: Option<&str>,
+        new_path: Option<&str>,
+    ) -> Result<User, StoreError> {
+        let params = || {
+            format!(
+                "account_id={account_id} user_name={user_name} new_user_name={new_user_name:?} new_path={new_path:?}"
+            )
+        };
+        self.timed("update_user", params, self.inner.update_user(account_id, user_name, new_user_name, new_path)).await
+    }
+
+    async fn delete_user(&self, account_id: &str, user_name: &str) -> Result<(), StoreError> {
+        let params = || format!("account_id={account_id} user_name={user_name}");
+        self.timed("delete_user", params, self.inner.delete_user(account_id, user_name)).await
+    }
+
+    async fn create_group(&self, group: &Group) -> Result<(), StoreError> {
+        let params = || format!("account_id={} group_name={}", group.account_id, group.group_name);
+        self.timed("create_group", params, self.inner.create_group(group)).await
+    }
+
+    async fn get_group(&self, account_id: &str, group_name: &str) -> Result<Group, StoreError> {
+        let params = || format!("account_id={account_id} group_name={group_name}");
+        self.timed("get_group", params, self.inner.get_group(account_id, group_name)).await
+    }
+
+    async fn delete_group(&self, account_id: &str, group_name: &str) -> Result<(), StoreError> {
+        let params = || format!("account_id={account_id} group_name={group_name}");
+        self.timed("delete_group", params, self.inner.delete_group(account_id, group_name)).await
+    }
+
+    async fn add_user_to_group(&self, group_id: &str, user_id: &str) -> Result<(), StoreError> {
+        let params = || format!("group_id={group_id} user_id={user_id}");
+        self.timed("add_user_to_group", params, self.inner.add_user_to_group(group_id, user_id)).await
+    }
+
+    async fn remove_user_from_group(&self, group_id: &str, user_id: &str) -> Result<(), StoreError> {
+        let params = || format!("group_id={group_id} user_id={user_id}");
+        self.timed("remove_user_from_group", params, self.inner.remove_user_from_group(group_id, user_id)).await
+    }
+
+    async fn list_groups_for_user(&self, user_id: &str) -> Result<Vec<Group>, StoreError> {
+        let params = || format!("user_id={user_id}");
+        self.timed("list_groups_for_user", params, self.inner.list_groups_for_user(user_id)).await
+    }
+
+    async fn create_role(&self, role: &Role, tags: &[Tag]) -> Result<(), StoreError> {
+        let params = || {
+            format!(
+                "account_id={} role_name={} assume_role_policy_document={} tags={}",
+                role.account_id,
+                role.role_name,
+                redacted(&role.assume_role_policy_document),
+                tags.len()
+            )
+        };
+        self.timed("create_role", params, self.inner.create_role(role, tags)).await
+    }
+
+    async fn get_role(&self, account_id: &str, role_name: &str) -> Result<Role, StoreError> {
+        let params = || format!("account_id={account_id} role_name={role_name}");
+        self.timed("get_role", params, self.inner.get_role(account_id, role_name)).await
+    }
+
+    async fn get_role_by_id(&self, account_id: &str, role_id: &str) -> Result<Role, StoreError> {
+        let params = || format!("account_id={account_id} role_id={role_id}");
+        self.timed("get_role_by_id", params, self.inner.get_role_by_id(account_id, role_id)).await
+    }
+
+    async fn list_roles(&self, account_id: &str, path_prefix: &str) -> Result<Vec<Role>, StoreError> {
+        let params = || format!("account_id={account_id} path_prefix={path_prefix}");
+        self.timed("list_roles", params, self.inner.list_roles(account_id, path_prefix)).await
+    }
+
+    async fn delete_role(&self, account_id: &str, role_name: &str) -> Result<(), StoreError> {
+        let params = || format!("account_id={account_id} role_name={role_name}");
+        self.timed("delete_role", params, self.inner.delete_role(account_id, role_name)).await
+    }
+
+    async fn create_policy(&self, policy: &ManagedPolicy, policy_document: &str) -> Result<(), StoreError> {
+        let params = || {
+            format!(
+                "account_id={} policy_name={} policy_document={}",
+                policy.account_id,
+                policy.policy_name,
+                redacted(policy_document)
+            )
+        };
+        self.timed("create_policy", params, self.inner.create_policy(policy, policy_document)).await
+    }
+
+    async fn get_policy(&self, account_id: &str, policy_name: &str) -> Result<ManagedPolicy, StoreError> {
+        let params = || format!("account_id={account_id} policy_name={policy_name}");
+        self.timed("get_policy", params, self.inner.get_policy(account_id, policy_name)).await
+    }
+
+    async fn list_policies(&self, account_id: &str, path_prefix: &str) -> Result<Vec<ManagedPolicy>, StoreError> {
+        let params = || format!("account_id={account_id} path_prefix={path_prefix}");
+        self.timed("list_policies", params, self.inner.list_policies(account_id, path_prefix)).await
+    }
+
+    async fn get_policy_version(&self, managed_policy_id: &str, version: i64) -> Result<String, StoreError> {
+        let params = || format!("managed_policy_id={managed_policy_id} version={version}");
+        self.timed("get_policy_version", params, self.inner.get_policy_version(managed_policy_id, version)).await
+    }
+
+    async fn create_policy_version(
+        &self,
+        managed_policy_id: &str,
+        policy_document: &str,
+        set_as_default: bool,
+        max_versions: usize,
+    ) -> Result<i64, StoreError> {
+        let params = || {
+            format!(
+                "managed_policy_id={managed_policy_id} policy_document={} set_as_default={set_as_default} \
+                 max_versions={max_versions}",
+                redacted(policy_document)
+            )
+        };
+        let call = self.inner.create_policy_version(managed_policy_id, policy_document, set_as_default, max_versions);
+        self.timed("create_policy_version", params, call).await
+    }
+
+    async fn delete_policy(&self, account_id: &str, policy_name: &str) -> Result<(), StoreError> {
+        let params = || format!("account_id={account_id} policy_name={policy_name}");
+        self.timed("delete_policy", params, self.inner.delete_policy(account_id, policy_name)).await
+    }
+
+    async fn attach_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        managed_policy_id: &str,
+        max_attached: usize,
+    ) -> Result<(), StoreError> {
+        let params = || {
+            format!(
+                "holder={holder:?} holder_id={holder_id} managed_policy_id={managed_policy_id} \
+                 max_attached={max_attached}"
+            )
+        };
+        let call = self.inner.attach_policy(holder, holder_id, managed_policy_id, max_attached);
+        self.timed("attach_policy", params, call).await
+    }
+
+    async fn detach_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        managed_policy_id: &str,
+    ) -> Result<(), StoreError> {
+        let params = || format!("holder={holder:?} holder_id={holder_id} managed_policy_id={managed_policy_id}");
+        self.timed("detach_policy", params, self.inner.detach_policy(holder, holder_id, managed_policy_id)).await
+    }
+
+    async fn list_attached_policies(&self, holder: PolicyHolder, holder_id: &str) -> Result<Vec<String>, StoreError> {
+        let params = || format!("holder={holder:?} holder_id={holder_id}");
+        self.timed("list_attached_policies", params, self.inner.list_attached_policies(holder, holder_id)).await
+    }
+
+    async fn count_policy_attachments(&self, managed_policy_id: &str) -> Result<usize, StoreError> {
+        let params = || format!("managed_policy_id={managed_policy_id}");
+        self.timed("count_policy_attachments", params, self.inner.count_policy_attachments(managed_policy_id)).await
+    }
+
+    async fn detach_policy_from_all(&self, managed_policy_id: &str) -> Result<usize, StoreError> {
+        let params = || format!("managed_policy_id={managed_policy_id}");
+        self.timed("detach_policy_from_all", params, self.inner.detach_policy_from_all(managed_policy_id)).await
+    }
+
+    async fn put_inline_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        policy: &InlinePolicy,
+    ) -> Result<(), StoreError> {
+        let params = || {
+            format!(
+                "holder={holder:?} holder_id={holder_id} policy_name={} policy_document={}",
+                policy.policy_name,
+                redacted(&policy.policy_document)
+            )
+        };
+        self.timed("put_inline_policy", params, self.inner.put_inline_policy(holder, holder_id, policy)).await
+    }
+
+    async fn get_inline_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        policy_name: &str,
+    ) -> Result<InlinePolicy, StoreError> {
+        let params = || format!("holder={holder:?} holder_id={holder_id} policy_name={policy_name}");
+        self.timed("get_inline_policy", params, self.inner.get_inline_policy(holder, holder_id, policy_name)).await
+    }
+
+    async fn list_inline_policies(&self, holder: PolicyHolder, holder_id: &str) -> Result<Vec<String>, StoreError> {
+        let params = || format!("holder={holder:?} holder_id={holder_id}");
+        self.timed("list_inline_policies", params, self.inner.list_inline_policies(holder, holder_id)).await
+    }
+
+    async fn delete_inline_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        policy_name: &str,
+    ) -> Result<(), StoreError> {
+        let params = || format!("holder={holder:?} holder_id={holder_id} policy_name={policy_name}");
+        let call = self.inner.delete_inline_policy(holder, holder_id, policy_name);
+        self.timed("delete_inline_policy", params, call).await
+    }
+
+    async fn list_tags(&self, kind: EntityKind, entity_id: &str) -> Result<Vec<Tag>, StoreError> {
+        let params = || format!("kind={kind} entity_id={entity_id}");
+        self.timed("list_tags", params, self.inner.list_tags(kind, entity_id)).await
+    }
+
+    async fn create_access_key(&self, access_key: &AccessKey) -> Result<(), StoreError> {
+        let params = || {
+            format!(
+                "user_id={} access_key_id={} secret_key={}",
+                access_key.user_id,
+                access_key.access_key_id,
+                redacted(&access_key.secret_key)
+            )
+        };
+        self.timed("create_access_key", params, self.inner.create_access_key(access_key)).await
+    }
+
+    async fn get_access_key(&self, access_key_id: &str) -> Result<AccessKey, StoreError> {
+        let params = || format!("access_key_id={access_key_id}");
+        self.timed("get_access_key", params, self.inner.get_access_key(access_key_id)).await
+    }
+
+    async fn list_access_keys(&self, user_id: &str) -> Result<Vec<AccessKey>, StoreError> {
+        let params = || format!("user_id={user_id}");
+        self.timed("list_access_keys", params, self.inner.list_access_keys(user_id)).await
+    }
+
+    async fn set_access_key_active(&self, user_id: &str, access_key_id: &str, active: bool) -> Result<(), StoreError> {
+        let params = || format!("user_id={user_id} access_key_id={access_key_id} active={active}");
+        let call = self.inner.set_access_key_active(user_id, access_key_id, active);
+        self.timed("set_access_key_active", params, call).await
+    }
+
+    async fn delete_access_key(&self, user_id: &str, access_key_id: &str) -> Result<(), StoreError> {
+        let params = || format!("user_id={user_id} access_key_id={access_key_id}");
+        self.timed("delete_access_key", params, self.inner.delete_access_key(user_id, access_key_id)).await
+    }
+
+    async fn create_ssh_public_key(&self, ssh_public_key: &SshPublicKey) -> Result<(), StoreError> {
+        let params = || {
+            format!(
+                "user_id={} ssh_public_key_id={} fingerprint={}",
+                ssh_public_key.user_id, ssh_public_key.ssh_public_key_id, ssh_public_key.fingerprint
+            )
+        };
+        self.timed("create_ssh_public_key", params, self.inner.create_ssh_public_key(ssh_public_key)).await
+    }
+
+    async fn list_ssh_public_keys(&self, user_id: &str) -> Result<Vec<SshPublicKey>, StoreError> {
+        let params = || format!("user_id={user_id}");
+        self.timed("list_ssh_public_keys", params, self.inner.list_ssh_public_keys(user_id)).await
+    }
+
+    async fn set_ssh_public_key_active(
+        &self,
+        user_id: &str,
+        ssh_public_key_id: &str,
+        active: bool,
+    ) -> Result<(), StoreError> {
+        let params = || format!("user_id={user_id} ssh_public_key_id={ssh_public_key_id} active={active}");
+        let call = self.inner.set_ssh_public_key_active(user_id, ssh_public_key_id, active);
+        self.timed("set_ssh_public_key_active", params, call).await
+    }
+
+    async fn delete_ssh_public_key(&self, user_id: &str, ssh_public_key_id: &str) -> Result<(), StoreError> {
+        let params = || format!("user_id={user_id} ssh_public_key_id={ssh_public_key_id}");
+        self.timed("delete_ssh_public_key", params, self.inner.delete_ssh_public_key(user_id, ssh_public_key_id)).await
+    }
+
+    async fn create_service_specific_credential(
+        &self,
+        credential: &ServiceSpecificCredential,
+    ) -> Result<(), StoreError> {
+        let params = || {
+            format!(
+                "user_id={} service_specific_credential_id={} service_name={} service_password={}",
+                credential.user_id,
+                credential.service_specific_credential_id,
+                credential.service_name,
+                redacted(&credential.service_password)
+            )
+        };
+        let call = self.inner.create_service_specific_credential(credential);
+        self.timed("create_service_specific_credential", params, call).await
+    }
+
+    async fn list_service_specific_credentials(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<ServiceSpecificCredential>, StoreError> {
+        let params = || format!("user_id={user_id}");
+        let call = self.inner.list_service_specific_credentials(user_id);
+        self.timed("list_service_specific_credentials", params, call).await
+    }
+
+    async fn reset_service_specific_credential(
+        &self,
+        user_id: &str,
+        service_specific_credential_id: &str,
+        service_password: &str,
+    ) -> Result<ServiceSpecificCredential, StoreError> {
+        let params = || {
+            format!(
+                "user_id={user_id} service_specific_credential_id={service_specific_credential_id} \
+                 service_password={}",
+                redacted(service_password)
+            )
+        };
+        let call =
+            self.inner.reset_service_specific_credential(user_id, service_specific_credential_id, service_password);
+        self.timed("reset_service_specific_credential", params, call).await
+    }
+
+    async fn delete_service_specific_credential(
+        &self,
+        user_id: &str,
+        service_specific_credential_id: &str,
+    ) -> Result<(), StoreError> {
+        let params = || format!("user_id={user_id} service_specific_credential_id={service_specific_credential_id}");
+        let call = self.inner.delete_service_specific_credential(user_id, service_specific_credential_id);
+        self.timed("delete_service_specific_credential", params, call).await
+    }
+}
+
+/// A service wrapper for the service implementation that makes the request id available to store calls through
+/// [current_request_id].
+#[derive(Clone, Debug)]
+pub struct ScopeRequestId<S> {
+    inner: S,
+}
+
+impl<S> ScopeRequestId<S> {
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+        }
+    }
+}
+
+impl<S, B> Service<Request<B>> for ScopeRequestId<S>
+where
+    S: Service<Request<B>>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        let request_id = req.extensions().get::<RequestId>().copied();
+        let future = self.inner.call(req);
+        match request_id {
+            Some(request_id) => Box::pin(REQUEST_ID.scope(request_id, future)),
+            None => Box::pin(future),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{current_request_id, InstrumentedStore, QueryLogConfig, QueryMetrics, ScopeRequestId},
+        crate::store::{ControlPlaneStore, MemoryStore, User},
+        chrono::Utc,
+        hyper::{
+            service::{service_fn, Service},
+            Request,
+        },
+        pretty_assertions::assert_eq,
+        scratchstack_http_framework::RequestId,
+        std::{convert::Infallible, sync::Arc},
+    };
+
+    #[test_log::test(tokio::test)]
+    async fn test_metrics() {
+        let metrics = Arc::new(QueryMetrics::new());
+        let config = QueryLogConfig {
+            slow_query_ms: 0,
+        };
+        let store = InstrumentedStore::new(MemoryStore::new(), metrics.clone(), &config);
+        let user = User {
+            user_id: "AIDAEXAMPLE".to_string(),
+            account_id: "123456789012".to_string(),
+            user_name: "alice".to_string(),
+            path: "/".to_string(),
+            permissions_boundary: None,
+            created_at: Utc::now(),
+        };
+        store.create_user(&user, &[]).await.unwrap();
+        assert_eq!(store.get_user("123456789012", "ALICE").await.unwrap(), user);
+        assert!(store.get_user("123456789012", "bob").await.is_err());
+
+        assert_eq!(metrics.count("create_user"), 1);
+        assert_eq!(metrics.count("get_user"), 2);
+        assert_eq!(metrics.count("delete_user"), 0);
+
+        let rendered = metrics.render();
+        assert!(rendered.contains("scratchstack_store_query_seconds_count{query=\"get_user\"} 2"));
+        assert!(rendered.contains("scratchstack_store_query_seconds_bucket{query=\"create_user\",le=\"+Inf\"} 1"));
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_scope_request_id() {
+        assert!(current_request_id().is_none());
+
+        let mut service = ScopeRequestId::new(service_fn(|_: Request<()>| async {
+            Ok::<_, Infallible>(current_request_id().map(|id| id.to_string()))
+        }));
+        let request_id = RequestId::new();
+        let mut req = Request::new(());
+        req.extensions_mut().insert(request_id);
+        assert_eq!(service.call(req).await.unwrap(), Some(request_id.to_string()));
+        assert_eq!(service.call(Request::new(())).await.unwrap(), None);
+    }
+}
@@ -0,0 +1,532 @@
+//! Storage for the IAM control plane: accounts, users, groups, roles, managed policies, and user credentials.
+//!
+//! Operations are written against the [ControlPlaneStore] trait rather than a particular database. [SqlStore] is
+//! the default, backed by the `iam` schema created by the migrations; [MemoryStore] keeps everything in memory for
+//! tests. Either can be wrapped in an [InstrumentedStore] to time calls and log slow ones.
+//!
+//! Names are compared case-insensitively, as in AWS, but returned with the case they were created with.
+use {
+    async_trait::async_trait,
+    chrono::{DateTime, Utc},
+    http::StatusCode,
+    sqlx::Error as SqlxError,
+    std::{
+        error::Error,
+        fmt::{Debug, Display, Formatter, Result as FmtResult},
+    },
+    tower::BoxError,
+};
+
+mod instrument;
+mod memory;
+mod sql;
+
+pub use {
+    instrument::{current_request_id, InstrumentedStore, QueryLogConfig, QueryMetrics, ScopeRequestId, QUERY_BUCKETS},
+    memory::MemoryStore,
+    sql::SqlStore,
+};
+
+pub(crate) use sql::is_unique_violation;
+
+/// An account. Every other entity belongs to one.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Account {
+    pub account_id: String,
+    pub email: String,
+    pub active: bool,
+
+    /// The alias used in place of the account id in sign-in URLs. Aliases are unique across accounts.
+    pub alias: Option<String>,
+}
+
+/// An IAM user.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct User {
+    pub user_id: String,
+    pub account_id: String,
+    pub user_name: String,
+    pub path: String,
+    pub permissions_boundary: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An IAM group.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Group {
+    pub group_id: String,
+    pub account_id: String,
+    pub group_name: String,
+    pub path: String,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An IAM role.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Role {
+    pub role_id: String,
+    pub account_id: String,
+    pub role_name: String,
+    pub path: String,
+    pub permissions_boundary: Option<String>,
+    pub description: Option<String>,
+    pub assume_role_policy_document: String,
+    pub max_session_duration: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A customer managed policy. The policy documents are stored separately as versions.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ManagedPolicy {
+    pub managed_policy_id: String,
+    pub account_id: String,
+    pub policy_name: String,
+    pub path: String,
+    pub default_version: Option<i64>,
+    pub deprecated: bool,
+    pub policy_type: Option<String>,
+    pub created_at: DateTime<Utc>,
+}
+
+/// An access key belonging to a user.
+#[derive(Clone, Eq, PartialEq)]
+pub struct AccessKey {
+    pub user_id: String,
+    pub access_key_id: String,
+    pub secret_key: String,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Debug for AccessKey {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("AccessKey")
+            .field("user_id", &self.user_id)
+            .field("access_key_id", &self.access_key_id)
+            .field("secret_key", &"<redacted>")
+            .field("active", &self.active)
+            .field("created_at", &self.created_at)
+            .finish()
+    }
+}
+
+/// An SSH public key belonging to a user, as used to authenticate to AWS CodeCommit.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct SshPublicKey {
+    pub user_id: String,
+    pub ssh_public_key_id: String,
+
+    /// The MD5 fingerprint of the key, as colon-separated hex.
+    pub fingerprint: String,
+    pub ssh_public_key_body: String,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+/// A user name and password belonging to a user for one AWS service, such as AWS CodeCommit.
+#[derive(Clone, Eq, PartialEq)]
+pub struct ServiceSpecificCredential {
+    pub user_id: String,
+    pub service_specific_credential_id: String,
+    pub service_name: String,
+    pub service_password: String,
+    pub active: bool,
+    pub created_at: DateTime<Utc>,
+}
+
+impl Debug for ServiceSpecificCredential {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        f.debug_struct("ServiceSpecificCredential")
+            .field("user_id", &self.user_id)
+            .field("service_specific_credential_id", &self.service_specific_credential_id)
+            .field("service_name", &self.service_name)
+            .field("service_password", &"<redacted>")
+            .field("active", &self.active)
+            .field("created_at", &self.created_at)
+            .finish()
+    }
+}
+
+/// The kind of entity a [StoreError] refers to.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum EntityKind {
+    User,
+    Group,
+    Role,
+    Policy,
+    PolicyVersion,
+    AccessKey,
+    SshPublicKey,
+    ServiceSpecificCredential,
+    Account,
+    AccountAlias,
+}
+
+impl Display for EntityKind {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::User => f.write_str("user"),
+            Self::Group => f.write_str("group"),
+            Self::Role => f.write_str("role"),
+            Self::Policy => f.write_str("policy"),
+            Self::PolicyVersion => f.write_str("policy version"),
+            Self::AccessKey => f.write_str("access key"),
+            Self::SshPublicKey => f.write_str("SSH public key"),
+            Self::ServiceSpecificCredential => f.write_str("service specific credential"),
+            Self::Account => f.write_str("account"),
+            Self::AccountAlias => f.write_str("account alias"),
+        }
+    }
+}
+
+/// A tag on a user or role. Keys are compared case-insensitively but returned with the case they were given with.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Tag {
+    pub key: String,
+    pub value: String,
+}
+
+/// A policy embedded in a user, group, or role.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct InlinePolicy {
+    pub policy_name: String,
+    pub policy_document: String,
+}
+
+/// The kinds of entity that managed policies can be attached to, and that can have inline policies.
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub enum PolicyHolder {
+    User,
+    Group,
+    Role,
+}
+
+impl PolicyHolder {
+    /// The [EntityKind] reported when the holder does not exist.
+    pub fn kind(&self) -> EntityKind {
+        match self {
+            Self::User => EntityKind::User,
+            Self::Group => EntityKind::Group,
+            Self::Role => EntityKind::Role,
+        }
+    }
+}
+
+/// Storage operations needed by the IAM control plane.
+///
+/// Entities are looked up by account and name, except for access keys which are global. Implementations must make
+/// each method atomic; callers do not hold transactions across calls.
+#[async_trait]
+pub trait ControlPlaneStore: Debug + Send + Sync {
+    /// Create an account. This fails with `EntityAlreadyExists` if its id, email, or alias is already taken.
+    async fn create_account(&self, account: &Account) -> Result<(), StoreError>;
+    async fn get_account(&self, account_id: &str) -> Result<Account, StoreError>;
+
+    /// Set or clear the alias of an account. This fails with `EntityAlreadyExists` if another account has the alias.
+    async fn set_account_alias(&self, account_id: &str, alias: Option<&str>) -> Result<(), StoreError>;
+
+    /// Create a user with the given tags.
+    async fn create_user(&self, user: &User, tags: &[Tag]) -> Result<(), StoreError>;
+    async fn get_user(&self, account_id: &str, user_name: &str) -> Result<User, StoreError>;
+
+    /// List the users in the account whose path starts with `path_prefix`, ordered by name.
+    async fn list_users(&self, account_id: &str, path_prefix: &str) -> Result<Vec<User>, StoreError>;
+
+    /// Rename a user or move it to a new path, returning the updated user. The user id does not change.
+    async fn update_user(
+        &self,
+        account_id: &str,
+        user_name: &str,
+        new_user_name: Option<&str>,
+        new_path: Option<&str>,
+    ) -> Result<User, StoreError>;
+
+    /// Delete a user and its tags.
+    async fn delete_user(&self, account_id: &str, user_name: &str) -> Result<(), StoreError>;
+
+    async fn create_group(&self, group: &Group) -> Result<(), StoreError>;
+    async fn get_group(&self, account_id: &str, group_name: &str) -> Result<Group, StoreError>;
+
+    /// Delete a group. This fails with `DeleteConflict` while it has members or policies.
+    async fn delete_group(&self, account_id: &str, group_name: &str) -> Result<(), StoreError>;
+
+    /// Add a user to a group, both identified by id. Adding an existing member does nothing.
+    async fn add_user_to_group(&self, group_id: &str, user_id: &str) -> Result<(), StoreError>;
+    async fn remove_user_from_group(&self, group_id: &str, user_id: &str) -> Result<(), StoreError>;
+
+    /// List the groups a user belongs to, ordered by name.
+    async fn list_groups_for_user(&self, user_id: &str) -> Result<Vec<Group>, StoreError>;
+
+    /// Create a role with the given tags.
+    async fn create_role(&self, role: &Role, tags: &[Tag]) -> Result<(), StoreError>;
+    async fn get_role(&self, account_id: &str, role_name: &str) -> Result<Role, StoreError>;
+
+    /// Get a role by its id rather than its name. A role that is deleted and recreated has a new id.
+    async fn get_role_by_id(&self, account_id: &str, role_id: &str) -> Result<Role, StoreError>;
+
+    /// List the roles in the account whose path starts with `path_prefix`, ordered by name.
+    async fn list_roles(&self, account_id: &str, path_prefix: &str) -> Result<Vec<Role>, StoreError>;
+
+    /// Delete a role and its tags.
+    async fn delete_role(&self, account_id: &str, role_name: &str) -> Result<(), StoreError>;
+
+    /// Create a managed policy with `policy_document` as version 1, which becomes the default version.
+    async fn create_policy(&self, policy: &ManagedPolicy, policy_document: &str) -> Result<(), StoreError>;
+    async fn get_policy(&self, account_id: &str, policy_name: &str) -> Result<ManagedPolicy, StoreError>;
+
+    /// List the managed policies in the account whose path starts with `path_prefix`, ordered by name.
+    async fn list_policies(&self, account_id: &str, path_prefix: &str) -> Result<Vec<ManagedPolicy>, StoreError>;
+    async fn get_policy_version(&self, managed_policy_id: &str, version: i64) -> Result<String, StoreError>;
+
+    /// Add a version to a managed policy, returning its version number. This fails with `LimitExceeded` if the
+    /// policy already has `max_versions` versions.
+    async fn create_policy_version(
+        &self,
+        managed_policy_id: &str,
+        policy_document: &str,
+        set_as_default: bool,
+        max_versions: usize,
+    ) -> Result<i64, StoreError>;
+
+    /// Delete a managed policy and all of its versions. This fails with `DeleteConflict` while the policy is
+    /// attached to any user, group, or role.
+    async fn delete_policy(&self, account_id: &str, policy_name: &str) -> Result<(), StoreError>;
+
+    /// Attach a managed policy to a user, group, or role, identified by its id. Attaching a policy that is already
+    /// attached does nothing. This fails with `LimitExceeded` if the holder already has `max_attached` policies.
+    async fn attach_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        managed_policy_id: &str,
+        max_attached: usize,
+    ) -> Result<(), StoreError>;
+    async fn detach_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        managed_policy_id: &str,
+    ) -> Result<(), StoreError>;
+
+    /// List the ids of the managed policies attached to a holder, in order.
+    async fn list_attached_policies(&self, holder: PolicyHolder, holder_id: &str) -> Result<Vec<String>, StoreError>;
+
+    /// The number of users, groups, and roles a managed policy is attached to.
+    async fn count_policy_attachments(&self, managed_policy_id: &str) -> Result<usize, StoreError>;
+
+    /// Detach a managed policy from everything it is attached to, returning the number of attachments removed.
+    /// This is for administrative tools that delete a policy by force.
+    async fn detach_policy_from_all(&self, managed_policy_id: &str) -> Result<usize, StoreError>;
+
+    /// Create or replace an inline policy on a holder, identified by its id.
+    async fn put_inline_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        policy: &InlinePolicy,
+    ) -> Result<(), StoreError>;
+    async fn get_inline_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        policy_name: &str,
+    ) -> Result<InlinePolicy, StoreError>;
+
+    /// List the names of a holder's inline policies, ordered by name.
+    async fn list_inline_policies(&self, holder: PolicyHolder, holder_id: &str) -> Result<Vec<String>, StoreError>;
+    async fn delete_inline_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        policy_name: &str,
+    ) -> Result<(), StoreError>;
+
+    /// List the tags of a user or role, identified by its id, ordered by lowercase key. Other kinds of entity have
+    /// no tags.
+    async fn list_tags(&self, kind: EntityKind, entity_id: &str) -> Result<Vec<Tag>, StoreError>;
+
+    async fn create_access_key(&self, access_key: &AccessKey) -> Result<(), StoreError>;
+    async fn get_access_key(&self, access_key_id: &str) -> Result<AccessKey, StoreError>;
+
+    /// List the access keys of a user, ordered by creation time.
+    async fn list_access_keys(&self, user_id: &str) -> Result<Vec<AccessKey>, StoreError>;
+    async fn set_access_key_active(&self, user_id: &str, access_key_id: &str, active: bool) -> Result<(), StoreError>;
+    async fn delete_access_key(&self, user_id: &str, access_key_id: &str) -> Result<(), StoreError>;
+
+    async fn create_ssh_public_key(&self, ssh_public_key: &SshPublicKey) -> Result<(), StoreError>;
+
+    /// List a user's SSH public keys, oldest first.
+    async fn list_ssh_public_keys(&self, user_id: &str) -> Result<Vec<SshPublicKey>, StoreError>;
+    async fn set_ssh_public_key_active(
+        &self,
+        user_id: &str,
+        ssh_public_key_id: &str,
+        active: bool,
+    ) -> Result<(), StoreError>;
+    async fn delete_ssh_public_key(&self, user_id: &str, ssh_public_key_id: &str) -> Result<(), StoreError>;
+
+    async fn create_service_specific_credential(
+        &self,
+        credential: &ServiceSpecificCredential,
+    ) -> Result<(), StoreError>;
+
+    /// List a user's service specific credentials for every service, oldest first.
+    async fn list_service_specific_credentials(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<ServiceSpecificCredential>, StoreError>;
+
+    /// Replace the password of a service specific credential, returning the updated credential.
+    async fn reset_service_specific_credential(
+        &self,
+        user_id: &str,
+        service_specific_credential_id: &str,
+        service_password: &str,
+    ) -> Result<ServiceSpecificCredential, StoreError>;
+    async fn delete_service_specific_credential(
+        &self,
+        user_id: &str,
+        service_specific_credential_id: &str,
+    ) -> Result<(), StoreError>;
+}
+
+#[derive(Debug)]
+pub enum StoreError {
+    NoSuchEntity {
+        kind: EntityKind,
+        name: String,
+    },
+    EntityAlreadyExists {
+        kind: EntityKind,
+        name: String,
+    },
+
+    /// The entity cannot be deleted while other entities refer to it.
+    DeleteConflict {
+        kind: EntityKind,
+        name: String,
+    },
+
+    /// The entity would exceed a quota, such as the number of policies attached to a user.
+    LimitExceeded {
+        kind: EntityKind,
+        name: String,
+        limit: usize,
+    },
+    Sqlx(SqlxError),
+
+    /// An error from a backend other than SQL.
+    Backend(BoxError),
+}
+
+impl StoreError {
+    pub fn no_such_entity<N: Into<String>>(kind: EntityKind, name: N) -> Self {
+        Self::NoSuchEntity {
+            kind,
+            name: name.into(),
+        }
+    }
+
+    pub fn already_exists<N: Into<String>>(kind: EntityKind, name: N) -> Self {
+        Self::EntityAlreadyExists {
+            kind,
+            name: name.into(),
+        }
+    }
+
+    pub fn delete_conflict<N: Into<String>>(kind: EntityKind, name: N) -> Self {
+        Self::DeleteConflict {
+            kind,
+            name: name.into(),
+        }
+    }
+
+    pub fn limit_exceeded<N: Into<String>>(kind: EntityKind, name: N, limit: usize) -> Self {
+        Self::LimitExceeded {
+            kind,
+            name: name.into(),
+            limit,
+        }
+    }
+
+    /// The IAM error code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::NoSuchEntity {
+                ..
+            } => "NoSuchEntity",
+            Self::EntityAlreadyExists {
+                ..
+            } => "EntityAlreadyExists",
+            Self::DeleteConflict {
+                ..
+            } => "DeleteConflict",
+            Self::LimitExceeded {
+                ..
+            } => "LimitExceeded",
+            Self::Sqlx(_) | Self::Backend(_) => "ServiceFailure",
+        }
+    }
+
+    /// The HTTP status IAM returns with this error. SDKs decide whether to retry from the status, so this must
+    /// match AWS.
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::NoSuchEntity {
+                ..
+            } => StatusCode::NOT_FOUND,
+            Self::EntityAlreadyExists {
+                ..
+            }
+            | Self::DeleteConflict {
+                ..
+            }
+            | Self::LimitExceeded {
+                ..
+            } => StatusCode::CONFLICT,
+            Self::Sqlx(_) | Self::Backend(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+}
+
+impl Error for StoreError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Sqlx(e) => Some(e),
+            Self::Backend(e) => Some(e.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl Display for StoreError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::NoSuchEntity {
+                kind,
+                name,
+            } => write!(f, "The {kind} with name {name} cannot be found."),
+            Self::EntityAlreadyExists {
+                kind,
+                name,
+            } => write!(f, "The {kind} with name {name} already exists."),
+            Self::DeleteConflict {
+                kind,
+                name,
+            } => write!(f, "Cannot delete the {kind} with name {name} while other entities refer to it."),
+            Self::LimitExceeded {
+                kind,
+                name,
+                limit,
+            } => write!(f, "Cannot exceed quota of {limit} for {kind} {name}."),
+            Self::Sqlx(e) => write!(f, "Sqlx error: {e}"),
+            Self::Backend(e) => write!(f, "Storage backend error: {e}"),
+        }
+    }
+}
+
+impl From<SqlxError> for StoreError {
+    fn from(e: SqlxError) -> Self {
+        Self::Sqlx(e)
+    }
+}
@@ -0,0 +1,1310 @@
+use {
+    super::{
+        AccessKey, Account, ControlPlaneStore, EntityKind, Group, InlinePolicy, ManagedPolicy, PolicyHolder, Role,
+        ServiceSpecificCredential, SshPublicKey, StoreError, Tag, User,
+    },
+    async_trait::async_trait,
+    chrono::{DateTime, NaiveDateTime, Utc},
+    sqlx::{
+        any::{Any, AnyKind, AnyPool, AnyRow},
+        Error as SqlxError, Row, Transaction,
+    },
+    std::sync::Arc,
+};
+
+/// The format used to pass timestamps to and from the database. The `Any` driver cannot encode chrono types, so
+/// timestamps are exchanged as text.
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f";
+
+/// A [ControlPlaneStore] backed by the `iam` tables in a PostgreSQL or SQLite database.
+#[derive(Clone, Debug)]
+pub struct SqlStore {
+    pool: Arc<AnyPool>,
+
+    /// Prefix for table names: `iam.` on PostgreSQL, where the tables are in the `iam` schema; empty on SQLite.
+    prefix: &'static str,
+
+    /// PostgreSQL needs explicit casts between text and timestamps; SQLite stores timestamps as text.
+    cast_timestamps: bool,
+}
+
+impl SqlStore {
+    pub fn new(pool: Arc<AnyPool>) -> Self {
+        let (prefix, cast_timestamps) = match pool.any_kind() {
+            AnyKind::Postgres => ("iam.", true),
+            _ => ("", false),
+        };
+
+        Self {
+            pool,
+            prefix,
+            cast_timestamps,
+        }
+    }
+
+    /// Select expression for the timestamp column `created_at`.
+    fn created_at(&self) -> &'static str {
+        if self.cast_timestamps {
+            "CAST(created_at AS TEXT) AS created_at"
+        } else {
+            "created_at"
+        }
+    }
+
+    /// Placeholder for a timestamp parameter.
+    fn timestamp_param(&self, n: usize) -> String {
+        if self.cast_timestamps {
+            format!("CAST(${n} AS TIMESTAMP)")
+        } else {
+            format!("${n}")
+        }
+    }
+
+    /// Insert the tags of a newly created user or role as part of its creation. Tag keys must already be unique
+    /// ignoring case.
+    async fn insert_tags(
+        &self,
+        tx: &mut Transaction<'_, Any>,
+        kind: EntityKind,
+        entity_id: &str,
+        tags: &[Tag],
+    ) -> Result<(), StoreError> {
+        let (table, id_column) = match tag_table(kind) {
+            Some(table) => table,
+            None => return Ok(()),
+        };
+        let query = format!(
+            "INSERT INTO {}{table}({id_column}, tag_key_lower, tag_key_cased, tag_value) VALUES($1, $2, $3, $4)",
+            self.prefix
+        );
+        for tag in tags {
+            sqlx::query(&query)
+                .bind(entity_id)
+                .bind(tag.key.to_lowercase())
+                .bind(&tag.key)
+                .bind(&tag.value)
+                .execute(&mut *tx)
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+fn format_timestamp(timestamp: &DateTime<Utc>) -> String {
+    timestamp.format(TIMESTAMP_FORMAT).to_string()
+}
+
+fn created_at_from_row(row: &AnyRow) -> Result<DateTime<Utc>, SqlxError> {
+    let created_at: String = row.try_get("created_at")?;
+    let created_at =
+        NaiveDateTime::parse_from_str(&created_at, TIMESTAMP_FORMAT).map_err(|e| SqlxError::ColumnDecode {
+            index: "created_at".to_string(),
+            source: Box::new(e),
+        })?;
+    Ok(DateTime::from_utc(created_at, Utc))
+}
+
+/// Returns true if the error is a unique or primary key constraint violation.
+pub(crate) fn is_unique_violation(e: &SqlxError) -> bool {
+    // PostgreSQL unique_violation; SQLite SQLITE_CONSTRAINT_UNIQUE and SQLITE_CONSTRAINT_PRIMARYKEY.
+    matches!(e.as_database_error().and_then(|e| e.code()).as_deref(), Some("23505") | Some("2067") | Some("1555"))
+}
+
+/// Returns true if the error is a foreign key constraint violation.
+fn is_foreign_key_violation(e: &SqlxError) -> bool {
+    // PostgreSQL foreign_key_violation; SQLite SQLITE_CONSTRAINT_FOREIGNKEY.
+    matches!(e.as_database_error().and_then(|e| e.code()).as_deref(), Some("23503") | Some("787"))
+}
+
+/// The attachment table for a policy holder and its id column.
+fn attached_policy_table(holder: PolicyHolder) -> (&'static str, &'static str) {
+    match holder {
+        PolicyHolder::User => ("iam_user_attached_policy", "user_id"),
+        PolicyHolder::Group => ("iam_group_attached_policy", "group_id"),
+        PolicyHolder::Role => ("iam_role_attached_policy", "role_id"),
+    }
+}
+
+/// The inline policy table for a policy holder and its id column.
+fn inline_policy_table(holder: PolicyHolder) -> (&'static str, &'static str) {
+    match holder {
+        PolicyHolder::User => ("iam_user_inline_policy", "user_id"),
+        PolicyHolder::Group => ("iam_group_inline_policy", "group_id"),
+        PolicyHolder::Role => ("iam_role_inline_policy", "role_id"),
+    }
+}
+
+/// The tag table for an entity kind and its id column, if that kind can be tagged.
+fn tag_table(kind: EntityKind) -> Option<(&'static str, &'static str)> {
+    match kind {
+        EntityKind::User => Some(("iam_user_tag", "user_id")),
+        EntityKind::Role => Some(("iam_role_tag", "role_id")),
+        _ => None,
+    }
+}
+
+const ALL_HOLDERS: [PolicyHolder; 3] = [PolicyHolder::User, PolicyHolder::Group, PolicyHolder::Role];
+
+fn account_from_row(row: &AnyRow) -> Result<Account, SqlxError> {
+    Ok(Account {
+        account_id: row.try_get("account_id")?,
+        email: row.try_get("email")?,
+        active: row.try_get("active")?,
+        alias: row.try_get("alias")?,
+    })
+}
+
+fn user_from_row(row: &AnyRow) -> Result<User, SqlxError> {
+    Ok(User {
+        user_id: row.try_get("user_id")?,
+        account_id: row.try_get("account_id")?,
+        user_name: row.try_get("user_name_cased")?,
+        path: row.try_get("path")?,
+        permissions_boundary: row.try_get("permissions_boundary_managed_policy_id")?,
+        created_at: created_at_from_row(row)?,
+    })
+}
+
+fn group_from_row(row: &AnyRow) -> Result<Group, SqlxError> {
+    Ok(Group {
+        group_id: row.try_get("group_id")?,
+        account_id: row.try_get("account_id")?,
+        group_name: row.try_get("group_name_cased")?,
+        path: row.try_get("path")?,
+        created_at: created_at_from_row(row)?,
+    })
+}
+
+fn role_from_row(row: &AnyRow) -> Result<Role, SqlxError> {
+    Ok(Role {
+        role_id: row.try_get("role_id")?,
+        account_id: row.try_get("account_id")?,
+        role_name: row.try_get("role_name_cased")?,
+        path: row.try_get("path")?,
+        permissions_boundary: row.try_get("permissions_boundary_managed_policy_id")?,
+        description: row.try_get("description")?,
+        assume_role_policy_document: row.try_get("assume_role_policy_document")?,
+        max_session_duration: row.try_get::<i32, _>("max_session_duration")?.into(),
+        created_at: created_at_from_row(row)?,
+    })
+}
+
+fn policy_from_row(row: &AnyRow) -> Result<ManagedPolicy, SqlxError> {
+    Ok(ManagedPolicy {
+        managed_policy_id: row.try_get("managed_policy_id")?,
+        account_id: row.try_get("account_id")?,
+        policy_name: row.try_get("managed_policy_name_cased")?,
+        path: row.try_get("path")?,
+        default_version: row.try_get("default_version")?,
+        deprecated: row.try_get("deprecated")?,
+        policy_type: row.try_get("policy_type")?,
+        created_at: created_at_from_row(row)?,
+    })
+}
+
+fn access_key_from_row(row: &AnyRow) -> Result<AccessKey, SqlxError> {
+    Ok(AccessKey {
+        user_id: row.try_get("user_id")?,
+        access_key_id: row.try_get("access_key_id")?,
+        secret_key: row.try_get("secret_key")?,
+        active: row.try_get("active")?,
+        created_at: created_at_from_row(row)?,
+    })
+}
+
+fn ssh_public_key_from_row(row: &AnyRow) -> Result<SshPublicKey, SqlxError> {
+    Ok(SshPublicKey {
+        user_id: row.try_get("user_id")?,
+        ssh_public_key_id: row.try_get("public_key_id")?,
+        fingerprint: row.try_get("fingerprint")?,
+        ssh_public_key_body: row.try_get("ssh_public_key_body")?,
+        active: row.try_get("active")?,
+        created_at: created_at_from_row(row)?,
+    })
+}
+
+fn service_specific_credential_from_row(row: &AnyRow) -> Result<ServiceSpecificCredential, SqlxError> {
+    Ok(ServiceSpecificCredential {
+        user_id: row.try_get("user_id")?,
+        service_specific_credential_id: row.try_get("service_specific_credential_id")?,
+        service_name: row.try_get("service_name")?,
+        service_password: row.try_get("service_password")?,
+        active: row.try_get("active")?,
+        created_at: created_at_from_row(row)?,
+    })
+}
+
+const ACCOUNT_COLUMNS: &str = "account_id, email, active, alias";
+const USER_COLUMNS: &str = "user_id, account_id, user_name_cased, path, permissions_boundary_managed_policy_id";
+const GROUP_COLUMNS: &str = "group_id, account_id, group_name_cased, path";
+const ROLE_COLUMNS: &str = "role_id, account_id, role_name_cased, path, permissions_boundary_managed_policy_id, \
+                            description, assume_role_policy_document, max_session_duration";
+const POLICY_COLUMNS: &str = "managed_policy_id, account_id, managed_policy_name_cased, path, default_version, \
+                              deprecated, policy_type";
+const ACCESS_KEY_COLUMNS: &str = "user_id, access_key_id, secret_key, active";
+const SSH_PUBLIC_KEY_COLUMNS: &str = "user_id, public_key_id, fingerprint, ssh_public_key_body, active";
+const SERVICE_SPECIFIC_CREDENTIAL_COLUMNS: &str =
+    "user_id, service_specific_credential_id, service_name, service_password, active";
+
+#[async_trait]
+impl ControlPlaneStore for SqlStore {
+    async fn create_account(&self, account: &Account) -> Result<(), StoreError> {
+        let query =
+            format!("INSERT INTO {}account(account_id, email, active, alias) VALUES($1, $2, $3, $4)", self.prefix);
+        sqlx::query(&query)
+            .bind(&account.account_id)
+            .bind(&account.email)
+            .bind(account.active)
+            .bind(&account.alias)
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| {
+                // The id, email, and alias are all unique; the violated constraint is not reported portably.
+                if is_unique_violation(&e) {
+                    StoreError::already_exists(EntityKind::Account, &account.account_id)
+                } else {
+                    e.into()
+                }
+            })?;
+        Ok(())
+    }
+
+    async fn get_account(&self, account_id: &str) -> Result<Account, StoreError> {
+        let query = format!("SELECT {ACCOUNT_COLUMNS} FROM {}account WHERE account_id = $1", self.prefix);
+        let row = sqlx::query(&query)
+            .bind(account_id)
+            .fetch_optional(self.pool.as_ref())
+            .await?
+            .ok_or_else(|| StoreError::no_such_entity(EntityKind::Account, account_id))?;
+        Ok(account_from_row(&row)?)
+    }
+
+    async fn set_account_alias(&self, account_id: &str, alias: Option<&str>) -> Result<(), StoreError> {
+        let query = format!("UPDATE {}account SET alias = $1 WHERE account_id = $2", self.prefix);
+        let result =
+            sqlx::query(&query).bind(alias).bind(account_id).execute(self.pool.as_ref()).await.map_err(|e| {
+                if is_unique_violation(&e) {
+                    StoreError::already_exists(EntityKind::AccountAlias, alias.unwrap_or_default())
+                } else {
+                    e.into()
+                }
+            })?;
+        if result.rows_affected() == 0 {
+            Err(StoreError::no_such_entity(EntityKind::Account, account_id))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn create_user(&self, user: &User, tags: &[Tag]) -> Result<(), StoreError> {
+        let mut tx = self.pool.begin().await?;
+        let query = format!(
+            "INSERT INTO {}iam_user(user_id, account_id, user_name_lower, user_name_cased, path, \
+             permissions_boundary_managed_policy_id, created_at) VALUES($1, $2, $3, $4, $5, $6, {})",
+            self.prefix,
+            self.timestamp_param(7)
+        );
+        sqlx::query(&query)
+            .bind(&user.user_id)
+            .bind(&user.account_id)
+            .bind(user.user_name.to_lowercase())
+            .bind(&user.user_name)
+            .bind(&user.path)
+            .bind(&user.permissions_boundary)
+            .bind(format_timestamp(&user.created_at))
+            .execute(&mut tx)
+            .await
+            .map_err(|e| {
+                if is_unique_violation(&e) {
+                    StoreError::already_exists(EntityKind::User, &user.user_name)
+                } else {
+                    e.into()
+                }
+            })?;
+
+        self.insert_tags(&mut tx, EntityKind::User, &user.user_id, tags).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_user(&self, account_id: &str, user_name: &str) -> Result<User, StoreError> {
+        let query = format!(
+            "SELECT {USER_COLUMNS}, {} FROM {}iam_user WHERE account_id = $1 AND user_name_lower = $2",
+            self.created_at(),
+            self.prefix
+        );
+        let row = sqlx::query(&query)
+            .bind(account_id)
+            .bind(user_name.to_lowercase())
+            .fetch_optional(self.pool.as_ref())
+            .await?
+            .ok_or_else(|| StoreError::no_such_entity(EntityKind::User, user_name))?;
+        Ok(user_from_row(&row)?)
+    }
+
+    async fn list_users(&self, account_id: &str, path_prefix: &str) -> Result<Vec<User>, StoreError> {
+        let query = format!(
+            "SELECT {USER_COLUMNS}, {} FROM {}iam_user WHERE account_id = $1 ORDER BY user_name_lower",
+            self.created_at(),
+            self.prefix
+        );
+        let rows = sqlx::query(&query).bind(account_id).fetch_all(self.pool.as_ref()).await?;
+        let mut users = Vec::with_capacity(rows.len());
+        for row in rows {
+            let user = user_from_row(&row)?;
+            // Paths may contain LIKE wildcards, so the prefix is matched here.
+            if user.path.starts_with(path_prefix) {
+                users.push(user);
+            }
+        }
+        Ok(users)
+    }
+
+    async fn update_user(
+        &self,
+        account_id: &str,
+        user_name: &str,
+        new_user_name: Option<&str>,
+        new_path: Option<&str>,
+    ) -> Result<User, StoreError> {
+        let query = format!(
+            "UPDATE {}iam_user SET user_name_lower = COALESCE($3, user_name_lower), \
+             user_name_cased = COALESCE($4, user_name_cased), path = COALESCE($5, path) \
+             WHERE account_id = $1 AND user_name_lower = $2",
+            self.prefix
+        );
+        let result = sqlx::query(&query)
+            .bind(account_id)
+            .bind(user_name.to_lowercase())
+            .bind(new_user_name.map(str::to_lowercase))
+            .bind(new_user_name.map(ToString::to_string))
+            .bind(new_path.map(ToString::to_string))
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| {
+                if is_unique_violation(&e) {
+                    StoreError::already_exists(EntityKind::User, new_user_name.unwrap_or(user_name))
+                } else {
+                    e.into()
+                }
+            })?;
+
+        if result.rows_affected() == 0 {
+            return Err(StoreError::no_such_entity(EntityKind::User, user_name));
+        }
+
+        self.get_user(account_id, new_user_name.unwrap_or(user_name)).await
+    }
+
+    async fn delete_user(&self, account_id: &str, user_name: &str) -> Result<(), StoreError> {
+        let mut tx = self.pool.begin().await?;
+        let query = format!(
+            "DELETE FROM {0}iam_user_tag WHERE user_id IN \
+             (SELECT user_id FROM {0}iam_user WHERE account_id = $1 AND user_name_lower = $2)",
+            self.prefix
+        );
+        sqlx::query(&query).bind(account_id).bind(user_name.to_lowercase()).execute(&mut tx).await?;
+
+        let query = format!("DELETE FROM {}iam_user WHERE account_id = $1 AND user_name_lower = $2", self.prefix);
+        let result =
+            sqlx::query(&query).bind(account_id).bind(user_name.to_lowercase()).execute(&mut tx).await.map_err(
+                |e| {
+                    if is_foreign_key_violation(&e) {
+                        StoreError::delete_conflict(EntityKind::User, user_name)
+                    } else {
+                        e.into()
+                    }
+                },
+            )?;
+
+        if result.rows_affected() == 0 {
+            Err(StoreError::no_such_entity(EntityKind::User, user_name))
+        } else {
+            tx.commit().await?;
+            Ok(())
+        }
+    }
+
+    async fn create_group(&self, group: &Group) -> Result<(), StoreError> {
+        let query = format!(
+            "INSERT INTO {}iam_group(group_id, account_id, group_name_lower, group_name_cased, path, created_at) \
+             VALUES($1, $2, $3, $4, $5, {})",
+            self.prefix,
+            self.timestamp_param(6)
+        );
+        sqlx::query(&query)
+            .bind(&group.group_id)
+            .bind(&group.account_id)
+            .bind(group.group_name.to_lowercase())
+            .bind(&group.group_name)
+            .bind(&group.path)
+            .bind(format_timestamp(&group.created_at))
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| {
+                if is_unique_violation(&e) {
+                    StoreError::already_exists(EntityKind::Group, &group.group_name)
+                } else {
+                    e.into()
+                }
+            })?;
+        Ok(())
+    }
+
+    async fn get_group(&self, account_id: &str, group_name: &str) -> Result<Group, StoreError> {
+        let query = format!(
+            "SELECT {GROUP_COLUMNS}, {} FROM {}iam_group WHERE account_id = $1 AND group_name_lower = $2",
+            self.created_at(),
+            self.prefix
+        );
+        let row = sqlx::query(&query)
+            .bind(account_id)
+            .bind(group_name.to_lowercase())
+            .fetch_optional(self.pool.as_ref())
+            .await?
+            .ok_or_else(|| StoreError::no_such_entity(EntityKind::Group, group_name))?;
+        Ok(group_from_row(&row)?)
+    }
+
+    async fn delete_group(&self, account_id: &str, group_name: &str) -> Result<(), StoreError> {
+        let query = format!("DELETE FROM {}iam_group WHERE account_id = $1 AND group_name_lower = $2", self.prefix);
+        let result = sqlx::query(&query)
+            .bind(account_id)
+            .bind(group_name.to_lowercase())
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| {
+                if is_foreign_key_violation(&e) {
+                    StoreError::delete_conflict(EntityKind::Group, group_name)
+                } else {
+                    e.into()
+                }
+            })?;
+
+        if result.rows_affected() == 0 {
+            Err(StoreError::no_such_entity(EntityKind::Group, group_name))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn add_user_to_group(&self, group_id: &str, user_id: &str) -> Result<(), StoreError> {
+        let query = format!("INSERT INTO {}iam_group_member(group_id, user_id) VALUES($1, $2)", self.prefix);
+        match sqlx::query(&query).bind(group_id).bind(user_id).execute(self.pool.as_ref()).await {
+            Ok(_) => Ok(()),
+            Err(e) if is_unique_violation(&e) => Ok(()),
+            Err(e) if is_foreign_key_violation(&e) => {
+                let query = format!("SELECT group_id FROM {}iam_group WHERE group_id = $1", self.prefix);
+                if sqlx::query(&query).bind(group_id).fetch_optional(self.pool.as_ref()).await?.is_none() {
+                    Err(StoreError::no_such_entity(EntityKind::Group, group_id))
+                } else {
+                    Err(StoreError::no_such_entity(EntityKind::User, user_id))
+                }
+            }
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn remove_user_from_group(&self, group_id: &str, user_id: &str) -> Result<(), StoreError> {
+        let query = format!("DELETE FROM {}iam_group_member WHERE group_id = $1 AND user_id = $2", self.prefix);
+        let result = sqlx::query(&query).bind(group_id).bind(user_id).execute(self.pool.as_ref()).await?;
+        if result.rows_affected() == 0 {
+            Err(StoreError::no_such_entity(EntityKind::User, user_id))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn list_groups_for_user(&self, user_id: &str) -> Result<Vec<Group>, StoreError> {
+        let created_at = if self.cast_timestamps {
+            "CAST(g.created_at AS TEXT) AS created_at"
+        } else {
+            "g.created_at"
+        };
+        let query = format!(
+            "SELECT g.group_id, g.account_id, g.group_name_cased, g.path, {created_at} FROM {0}iam_group g \
+             INNER JOIN {0}iam_group_member m ON m.group_id = g.group_id WHERE m.user_id = $1 \
+             ORDER BY g.group_name_lower",
+            self.prefix
+        );
+        let rows = sqlx::query(&query).bind(user_id).fetch_all(self.pool.as_ref()).await?;
+        let mut groups = Vec::with_capacity(rows.len());
+        for row in rows {
+            groups.push(group_from_row(&row)?);
+        }
+        Ok(groups)
+    }
+
+    async fn create_role(&self, role: &Role, tags: &[Tag]) -> Result<(), StoreError> {
+        let mut tx = self.pool.begin().await?;
+        let query = format!(
+            "INSERT INTO {}iam_role(role_id, account_id, role_name_lower, role_name_cased, path, \
+             permissions_boundary_managed_policy_id, description, assume_role_policy_document, \
+             max_session_duration, created_at) VALUES($1, $2, $3, $4, $5, $6, $7, $8, $9, {})",
+            self.prefix,
+            self.timestamp_param(10)
+        );
+        sqlx::query(&query)
+            .bind(&role.role_id)
+            .bind(&role.account_id)
+            .bind(role.role_name.to_lowercase())
+            .bind(&role.role_name)
+            .bind(&role.path)
+            .bind(&role.permissions_boundary)
+            .bind(&role.description)
+            .bind(&role.assume_role_policy_document)
+            .bind(i32::try_from(role.max_session_duration).unwrap_or(i32::MAX))
+            .bind(format_timestamp(&role.created_at))
+            .execute(&mut tx)
+            .await
+            .map_err(|e| {
+                if is_unique_violation(&e) {
+                    StoreError::already_exists(EntityKind::Role, &role.role_name)
+                } else {
+                    e.into()
+                }
+            })?;
+
+        self.insert_tags(&mut tx, EntityKind::Role, &role.role_id, tags).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_role(&self, account_id: &str, role_name: &str) -> Result<Role, StoreError> {
+        let query = format!(
+            "SELECT {ROLE_COLUMNS}, {} FROM {}iam_role WHERE account_id = $1 AND role_name_lower = $2",
+            self.created_at(),
+            self.prefix
+        );
+        let row = sqlx::query(&query)
+            .bind(account_id)
+            .bind(role_name.to_lowercase())
+            .fetch_optional(self.pool.as_ref())
+            .await?
+            .ok_or_else(|| StoreError::no_such_entity(EntityKind::Role, role_name))?;
+        Ok(role_from_row(&row)?)
+    }
+
+    async fn get_role_by_id(&self, account_id: &str, role_id: &str) -> Result<Role, StoreError> {
+        let query = format!(
+            "SELECT {ROLE_COLUMNS}, {} FROM {}iam_role WHERE account_id = $1 AND role_id = $2",
+            self.created_at(),
+            self.prefix
+        );
+        let row = sqlx::query(&query)
+            .bind(account_id)
+            .bind(role_id)
+            .fetch_optional(self.pool.as_ref())
+            .await?
+            .ok_or_else(|| StoreError::no_such_entity(EntityKind::Role, role_id))?;
+        Ok(role_from_row(&row)?)
+    }
+
+    async fn list_roles(&self, account_id: &str, path_prefix: &str) -> Result<Vec<Role>, StoreError> {
+        let query = format!(
+            "SELECT {ROLE_COLUMNS}, {} FROM {}iam_role WHERE account_id = $1 ORDER BY role_name_lower",
+            self.created_at(),
+            self.prefix
+        );
+        let rows = sqlx::query(&query).bind(account_id).fetch_all(self.pool.as_ref()).await?;
+        let mut roles = Vec::with_capacity(rows.len());
+        for row in rows {
+            let role = role_from_row(&row)?;
+            if role.path.starts_with(path_prefix) {
+                roles.push(role);
+            }
+        }
+        Ok(roles)
+    }
+
+    async fn delete_role(&self, account_id: &str, role_name: &str) -> Result<(), StoreError> {
+        let mut tx = self.pool.begin().await?;
+        let query = format!(
+            "DELETE FROM {0}iam_role_tag WHERE role_id IN \
+             (SELECT role_id FROM {0}iam_role WHERE account_id = $1 AND role_name_lower = $2)",
+            self.prefix
+        );
+        sqlx::query(&query).bind(account_id).bind(role_name.to_lowercase()).execute(&mut tx).await?;
+
+        let query = format!("DELETE FROM {}iam_role WHERE account_id = $1 AND role_name_lower = $2", self.prefix);
+        let result =
+            sqlx::query(&query).bind(account_id).bind(role_name.to_lowercase()).execute(&mut tx).await.map_err(
+                |e| {
+                    if is_foreign_key_violation(&e) {
+                        StoreError::delete_conflict(EntityKind::Role, role_name)
+                    } else {
+                        e.into()
+                    }
+                },
+            )?;
+
+        if result.rows_affected() == 0 {
+            Err(StoreError::no_such_entity(EntityKind::Role, role_name))
+        } else {
+            tx.commit().await?;
+            Ok(())
+        }
+    }
+
+    async fn create_policy(&self, policy: &ManagedPolicy, policy_document: &str) -> Result<(), StoreError> {
+        let mut tx = self.pool.begin().await?;
+        let query = format!(
+            "INSERT INTO {}managed_policy(managed_policy_id, account_id, managed_policy_name_lower, \
+             managed_policy_name_cased, path, default_version, deprecated, policy_type, created_at) \
+             VALUES($1, $2, $3, $4, $5, 1, $6, $7, {})",
+            self.prefix,
+            self.timestamp_param(8)
+        );
+        sqlx::query(&query)
+            .bind(&policy.managed_policy_id)
+            .bind(&policy.account_id)
+            .bind(policy.policy_name.to_lowercase())
+            .bind(&policy.policy_name)
+            .bind(&policy.path)
+            .bind(policy.deprecated)
+            .bind(&policy.policy_type)
+            .bind(format_timestamp(&policy.created_at))
+            .execute(&mut tx)
+            .await
+            .map_err(|e| {
+                if is_unique_violation(&e) {
+                    StoreError::already_exists(EntityKind::Policy, &policy.policy_name)
+                } else {
+                    e.into()
+                }
+            })?;
+
+        let query = format!(
+            "INSERT INTO {}managed_policy_version(managed_policy_id, managed_policy_version, policy_document, \
+             created_at) VALUES($1, 1, $2, {})",
+            self.prefix,
+            self.timestamp_param(3)
+        );
+        sqlx::query(&query)
+            .bind(&policy.managed_policy_id)
+            .bind(policy_document)
+            .bind(format_timestamp(&policy.created_at))
+            .execute(&mut tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_policy(&self, account_id: &str, policy_name: &str) -> Result<ManagedPolicy, StoreError> {
+        let query = format!(
+            "SELECT {POLICY_COLUMNS}, {} FROM {}managed_policy \
+             WHERE account_id = $1 AND managed_policy_name_lower = $2",
+            self.created_at(),
+            self.prefix
+        );
+        let row = sqlx::query(&query)
+            .bind(account_id)
+            .bind(policy_name.to_lowercase())
+            .fetch_optional(self.pool.as_ref())
+            .await?
+            .ok_or_else(|| StoreError::no_such_entity(EntityKind::Policy, policy_name))?;
+        Ok(policy_from_row(&row)?)
+    }
+
+    async fn list_policies(&self, account_id: &str, path_prefix: &str) -> Result<Vec<ManagedPolicy>, StoreError> {
+        let query = format!(
+            "SELECT {POLICY_COLUMNS}, {} FROM {}managed_policy WHERE account_id = $1 \
+             ORDER BY managed_policy_name_lower",
+            self.created_at(),
+            self.prefix
+        );
+        let rows = sqlx::query(&query).bind(account_id).fetch_all(self.pool.as_ref()).await?;
+        let mut policies = Vec::with_capacity(rows.len());
+        for row in rows {
+            let policy = policy_from_row(&row)?;
+            if policy.path.starts_with(path_prefix) {
+                policies.push(policy);
+            }
+        }
+        Ok(policies)
+    }
+
+    async fn get_policy_version(&self, managed_policy_id: &str, version: i64) -> Result<String, StoreError> {
+        let query = format!(
+            "SELECT policy_document FROM {}managed_policy_version \
+             WHERE managed_policy_id = $1 AND managed_policy_version = $2",
+            self.prefix
+        );
+        let row = sqlx::query(&query)
+            .bind(managed_policy_id)
+            .bind(version)
+            .fetch_optional(self.pool.as_ref())
+            .await?
+            .ok_or_else(|| {
+            StoreError::no_such_entity(EntityKind::PolicyVersion, format!("{managed_policy_id}:v{version}"))
+        })?;
+        Ok(row.try_get("policy_document")?)
+    }
+
+    async fn create_policy_version(
+        &self,
+        managed_policy_id: &str,
+        policy_document: &str,
+        set_as_default: bool,
+        max_versions: usize,
+    ) -> Result<i64, StoreError> {
+        let mut tx = self.pool.begin().await?;
+        let query = format!(
+            "SELECT COUNT(*) AS n_versions, MAX(managed_policy_version) AS latest_version \
+             FROM {}managed_policy_version WHERE managed_policy_id = $1",
+            self.prefix
+        );
+        let row = sqlx::query(&query).bind(managed_policy_id).fetch_one(&mut tx).await?;
+        let n_versions: i64 = row.try_get("n_versions")?;
+        let latest_version: Option<i64> = row.try_get("latest_version")?;
+
+        // Every policy has at least one version, so no versions means no policy.
+        let latest_version = match latest_version {
+            Some(latest_version) if n_versions > 0 => latest_version,
+            _ => return Err(StoreError::no_such_entity(EntityKind::Policy, managed_policy_id)),
+        };
+        if usize::try_from(n_versions).unwrap_or(usize::MAX) >= max_versions {
+            return Err(StoreError::limit_exceeded(EntityKind::Policy, managed_policy_id, max_versions));
+        }
+
+        let version = latest_version + 1;
+        let query = format!(
+            "INSERT INTO {}managed_policy_version(managed_policy_id, managed_policy_version, policy_document, \
+             created_at) VALUES($1, $2, $3, {})",
+            self.prefix,
+            self.timestamp_param(4)
+        );
+        sqlx::query(&query)
+            .bind(managed_policy_id)
+            .bind(version)
+            .bind(policy_document)
+            .bind(format_timestamp(&Utc::now()))
+            .execute(&mut tx)
+            .await?;
+
+        if set_as_default {
+            let query =
+                format!("UPDATE {}managed_policy SET default_version = $1 WHERE managed_policy_id = $2", self.prefix);
+            sqlx::query(&query).bind(version).bind(managed_policy_id).execute(&mut tx).await?;
+        }
+
+        tx.commit().await?;
+        Ok(version)
+    }
+
+    async fn delete_policy(&self, account_id: &str, policy_name: &str) -> Result<(), StoreError> {
+        let mut tx = self.pool.begin().await?;
+        let query = format!(
+            "SELECT managed_policy_id FROM {}managed_policy WHERE account_id = $1 AND managed_policy_name_lower = $2",
+            self.prefix
+        );
+        let row = sqlx::query(&query)
+            .bind(account_id)
+            .bind(policy_name.to_lowercase())
+            .fetch_optional(&mut tx)
+            .await?
+            .ok_or_else(|| StoreError::no_such_entity(EntityKind::Policy, policy_name))?;
+        let managed_policy_id: String = row.try_get("managed_policy_id")?;
+
+        let conflict = |e: SqlxError| {
+            if is_foreign_key_violation(&e) {
+                StoreError::delete_conflict(EntityKind::Policy, policy_name)
+            } else {
+                e.into()
+            }
+        };
+
+        let query = format!("DELETE FROM {}managed_policy_version WHERE managed_policy_id = $1", self.prefix);
+        sqlx::query(&query).bind(&managed_policy_id).execute(&mut tx).await.map_err(conflict)?;
+        let query = format!("DELETE FROM {}managed_policy WHERE managed_policy_id = $1", self.prefix);
+        sqlx::query(&query).bind(&managed_policy_id).execute(&mut tx).await.map_err(conflict)?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn attach_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        managed_policy_id: &str,
+        max_attached: usize,
+    ) -> Result<(), StoreError> {
+        let (table, id_column) = attached_policy_table(holder);
+        let mut tx = self.pool.begin().await?;
+
+        let query = format!("SELECT managed_policy_id FROM {}managed_policy WHERE managed_policy_id = $1", self.prefix);
+        if sqlx::query(&query).bind(managed_policy_id).fetch_optional(&mut tx).await?.is_none() {
+            return Err(StoreError::no_such_entity(EntityKind::Policy, managed_policy_id));
+        }
+
+        let query = format!("SELECT managed_policy_id FROM {}{table} WHERE {id_column} = $1", self.prefix);
+        let rows = sqlx::query(&query).bind(holder_id).fetch_all(&mut tx).await?;
+        for row in &rows {
+            let attached: String = row.try_get("managed_policy_id")?;
+            if attached == managed_policy_id {
+                return Ok(());
+            }
+        }
+        if rows.len() >= max_attached {
+            return Err(StoreError::limit_exceeded(holder.kind(), holder_id, max_attached));
+        }
+
+        let query = format!("INSERT INTO {}{table}({id_column}, managed_policy_id) VALUES($1, $2)", self.prefix);
+        sqlx::query(&query).bind(holder_id).bind(managed_policy_id).execute(&mut tx).await.map_err(|e| {
+            // The policy was checked above, so the holder is missing.
+            if is_foreign_key_violation(&e) {
+                StoreError::no_such_entity(holder.kind(), holder_id)
+            } else {
+                e.into()
+            }
+        })?;
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn detach_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        managed_policy_id: &str,
+    ) -> Result<(), StoreError> {
+        let (table, id_column) = attached_policy_table(holder);
+        let query = format!("DELETE FROM {}{table} WHERE {id_column} = $1 AND managed_policy_id = $2", self.prefix);
+        let result = sqlx::query(&query).bind(holder_id).bind(managed_policy_id).execute(self.pool.as_ref()).await?;
+        if result.rows_affected() == 0 {
+            Err(StoreError::no_such_entity(EntityKind::Policy, managed_policy_id))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn list_attached_policies(&self, holder: PolicyHolder, holder_id: &str) -> Result<Vec<String>, StoreError> {
+        let (table, id_column) = attached_policy_table(holder);
+        let query = format!(
+            "SELECT managed_policy_id FROM {}{table} WHERE {id_column} = $1 ORDER BY managed_policy_id",
+            self.prefix
+        );
+        let rows = sqlx::query(&query).bind(holder_id).fetch_all(self.pool.as_ref()).await?;
+        let mut managed_policy_ids = Vec::with_capacity(rows.len());
+        for row in rows {
+            managed_policy_ids.push(row.try_get("managed_policy_id")?);
+        }
+        Ok(managed_policy_ids)
+    }
+
+    async fn count_policy_attachments(&self, managed_policy_id: &str) -> Result<usize, StoreError> {
+        let mut n_attached = 0;
+        for holder in ALL_HOLDERS {
+            let (table, _) = attached_policy_table(holder);
+            let query =
+                format!("SELECT COUNT(*) AS n_attached FROM {}{table} WHERE managed_policy_id = $1", self.prefix);
+            let row = sqlx::query(&query).bind(managed_policy_id).fetch_one(self.pool.as_ref()).await?;
+            let count: i64 = row.try_get("n_attached")?;
+            n_attached += count as usize;
+        }
+        Ok(n_attached)
+    }
+
+    async fn detach_policy_from_all(&self, managed_policy_id: &str) -> Result<usize, StoreError> {
+        let mut tx = self.pool.begin().await?;
+        let mut n_detached = 0;
+        for holder in ALL_HOLDERS {
+            let (table, _) = attached_policy_table(holder);
+            let query = format!("DELETE FROM {}{table} WHERE managed_policy_id = $1", self.prefix);
+            let result = sqlx::query(&query).bind(managed_policy_id).execute(&mut tx).await?;
+            n_detached += result.rows_affected() as usize;
+        }
+        tx.commit().await?;
+        Ok(n_detached)
+    }
+
+    async fn put_inline_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        policy: &InlinePolicy,
+    ) -> Result<(), StoreError> {
+        let (table, id_column) = inline_policy_table(holder);
+        let mut tx = self.pool.begin().await?;
+
+        // Replacing keeps the name's original case, as in AWS.
+        let query = format!(
+            "UPDATE {}{table} SET policy_document = $1 WHERE {id_column} = $2 AND policy_name_lower = $3",
+            self.prefix
+        );
+        let result = sqlx::query(&query)
+            .bind(&policy.policy_document)
+            .bind(holder_id)
+            .bind(policy.policy_name.to_lowercase())
+            .execute(&mut tx)
+            .await?;
+
+        if result.rows_affected() == 0 {
+            let query = format!(
+                "INSERT INTO {}{table}({id_column}, policy_name_lower, policy_name_cased, policy_document) \
+                 VALUES($1, $2, $3, $4)",
+                self.prefix
+            );
+            sqlx::query(&query)
+                .bind(holder_id)
+                .bind(policy.policy_name.to_lowercase())
+                .bind(&policy.policy_name)
+                .bind(&policy.policy_document)
+                .execute(&mut tx)
+                .await
+                .map_err(|e| {
+                    if is_foreign_key_violation(&e) {
+                        StoreError::no_such_entity(holder.kind(), holder_id)
+                    } else {
+                        e.into()
+                    }
+                })?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn get_inline_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        policy_name: &str,
+    ) -> Result<InlinePolicy, StoreError> {
+        let (table, id_column) = inline_policy_table(holder);
+        let query = format!(
+            "SELECT policy_name_cased, policy_document FROM {}{table} \
+             WHERE {id_column} = $1 AND policy_name_lower = $2",
+            self.prefix
+        );
+        let row = sqlx::query(&query)
+            .bind(holder_id)
+            .bind(policy_name.to_lowercase())
+            .fetch_optional(self.pool.as_ref())
+            .await?
+            .ok_or_else(|| StoreError::no_such_entity(EntityKind::Policy, policy_name))?;
+        Ok(InlinePolicy {
+            policy_name: row.try_get("policy_name_cased")?,
+            policy_document: row.try_get("policy_document")?,
+        })
+    }
+
+    async fn list_inline_policies(&self, holder: PolicyHolder, holder_id: &str) -> Result<Vec<String>, StoreError> {
+        let (table, id_column) = inline_policy_table(holder);
+        let query = format!(
+            "SELECT policy_name_cased FROM {}{table} WHERE {id_column} = $1 ORDER BY policy_name_lower",
+            self.prefix
+        );
+        let rows = sqlx::query(&query).bind(holder_id).fetch_all(self.pool.as_ref()).await?;
+        let mut policy_names = Vec::with_capacity(rows.len());
+        for row in rows {
+            policy_names.push(row.try_get("policy_name_cased")?);
+        }
+        Ok(policy_names)
+    }
+
+    async fn delete_inline_policy(
+        &self,
+        holder: PolicyHolder,
+        holder_id: &str,
+        policy_name: &str,
+    ) -> Result<(), StoreError> {
+        let (table, id_column) = inline_policy_table(holder);
+        let query = format!("DELETE FROM {}{table} WHERE {id_column} = $1 AND policy_name_lower = $2", self.prefix);
+        let result =
+            sqlx::query(&query).bind(holder_id).bind(policy_name.to_lowercase()).execute(self.pool.as_ref()).await?;
+        if result.rows_affected() == 0 {
+            Err(StoreError::no_such_entity(EntityKind::Policy, policy_name))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn list_tags(&self, kind: EntityKind, entity_id: &str) -> Result<Vec<Tag>, StoreError> {
+        let (table, id_column) = match tag_table(kind) {
+            Some(table) => table,
+            None => return Ok(Vec::new()),
+        };
+        let query = format!(
+            "SELECT tag_key_cased, tag_value FROM {}{table} WHERE {id_column} = $1 ORDER BY tag_key_lower",
+            self.prefix
+        );
+        let rows = sqlx::query(&query).bind(entity_id).fetch_all(self.pool.as_ref()).await?;
+        let mut tags = Vec::with_capacity(rows.len());
+        for row in rows {
+            tags.push(Tag {
+                key: row.try_get("tag_key_cased")?,
+                value: row.try_get("tag_value")?,
+            });
+        }
+        Ok(tags)
+    }
+
+    async fn create_access_key(&self, access_key: &AccessKey) -> Result<(), StoreError> {
+        let query = format!(
+            "INSERT INTO {}iam_user_credential(user_id, access_key_id, secret_key, active, created_at) \
+             VALUES($1, $2, $3, $4, {})",
+            self.prefix,
+            self.timestamp_param(5)
+        );
+        sqlx::query(&query)
+            .bind(&access_key.user_id)
+            .bind(&access_key.access_key_id)
+            .bind(&access_key.secret_key)
+            .bind(access_key.active)
+            .bind(format_timestamp(&access_key.created_at))
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| {
+                if is_unique_violation(&e) {
+                    StoreError::already_exists(EntityKind::AccessKey, &access_key.access_key_id)
+                } else if is_foreign_key_violation(&e) {
+                    StoreError::no_such_entity(EntityKind::User, &access_key.user_id)
+                } else {
+                    e.into()
+                }
+            })?;
+        Ok(())
+    }
+
+    async fn get_access_key(&self, access_key_id: &str) -> Result<AccessKey, StoreError> {
+        let query = format!(
+            "SELECT {ACCESS_KEY_COLUMNS}, {} FROM {}iam_user_credential WHERE access_key_id = $1",
+            self.created_at(),
+            self.prefix
+        );
+        let row = sqlx::query(&query)
+            .bind(access_key_id)
+            .fetch_optional(self.pool.as_ref())
+            .await?
+            .ok_or_else(|| StoreError::no_such_entity(EntityKind::AccessKey, access_key_id))?;
+        Ok(access_key_from_row(&row)?)
+    }
+
+    async fn list_access_keys(&self, user_id: &str) -> Result<Vec<AccessKey>, StoreError> {
+        let query = format!(
+            "SELECT {ACCESS_KEY_COLUMNS}, {} FROM {}iam_user_credential WHERE user_id = $1 \
+             ORDER BY created_at, access_key_id",
+            self.created_at(),
+            self.prefix
+        );
+        let rows = sqlx::query(&query).bind(user_id).fetch_all(self.pool.as_ref()).await?;
+        let mut access_keys = Vec::with_capacity(rows.len());
+        for row in rows {
+            access_keys.push(access_key_from_row(&row)?);
+        }
+        Ok(access_keys)
+    }
+
+    async fn set_access_key_active(&self, user_id: &str, access_key_id: &str, active: bool) -> Result<(), StoreError> {
+        let query = format!(
+            "UPDATE {}iam_user_credential SET active = $1 WHERE user_id = $2 AND access_key_id = $3",
+            self.prefix
+        );
+        let result =
+            sqlx::query(&query).bind(active).bind(user_id).bind(access_key_id).execute(self.pool.as_ref()).await?;
+        if result.rows_affected() == 0 {
+            Err(StoreError::no_such_entity(EntityKind::AccessKey, access_key_id))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn delete_access_key(&self, user_id: &str, access_key_id: &str) -> Result<(), StoreError> {
+        let query = format!("DELETE FROM {}iam_user_credential WHERE user_id = $1 AND access_key_id = $2", self.prefix);
+        let result = sqlx::query(&query).bind(user_id).bind(access_key_id).execute(self.pool.as_ref()).await?;
+        if result.rows_affected() == 0 {
+            Err(StoreError::no_such_entity(EntityKind::AccessKey, access_key_id))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn create_ssh_public_key(&self, ssh_public_key: &SshPublicKey) -> Result<(), StoreError> {
+        let query = format!(
+            "INSERT INTO {}iam_user_ssh_public_key(user_id, public_key_id, fingerprint, ssh_public_key_body, active, \
+             created_at) VALUES($1, $2, $3, $4, $5, {})",
+            self.prefix,
+            self.timestamp_param(6)
+        );
+        sqlx::query(&query)
+            .bind(&ssh_public_key.user_id)
+            .bind(&ssh_public_key.ssh_public_key_id)
+            .bind(&ssh_public_key.fingerprint)
+            .bind(&ssh_public_key.ssh_public_key_body)
+            .bind(ssh_public_key.active)
+            .bind(format_timestamp(&ssh_public_key.created_at))
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| {
+                if is_unique_violation(&e) {
+                    StoreError::already_exists(EntityKind::SshPublicKey, &ssh_public_key.ssh_public_key_id)
+                } else if is_foreign_key_violation(&e) {
+                    StoreError::no_such_entity(EntityKind::User, &ssh_public_key.user_id)
+                } else {
+                    e.into()
+                }
+            })?;
+        Ok(())
+    }
+
+    async fn list_ssh_public_keys(&self, user_id: &str) -> Result<Vec<SshPublicKey>, StoreError> {
+        let query = format!(
+            "SELECT {SSH_PUBLIC_KEY_COLUMNS}, {} FROM {}iam_user_ssh_public_key WHERE user_id = $1 \
+             ORDER BY created_at, public_key_id",
+            self.created_at(),
+            self.prefix
+        );
+        let rows = sqlx::query(&query).bind(user_id).fetch_all(self.pool.as_ref()).await?;
+        let mut ssh_public_keys = Vec::with_capacity(rows.len());
+        for row in rows {
+            ssh_public_keys.push(ssh_public_key_from_row(&row)?);
+        }
+        Ok(ssh_public_keys)
+    }
+
+    async fn set_ssh_public_key_active(
+        &self,
+        user_id: &str,
+        ssh_public_key_id: &str,
+        active: bool,
+    ) -> Result<(), StoreError> {
+        let query = format!(
+            "UPDATE {}iam_user_ssh_public_key SET active = $1 WHERE user_id = $2 AND public_key_id = $3",
+            self.prefix
+        );
+        let result =
+            sqlx::query(&query).bind(active).bind(user_id).bind(ssh_public_key_id).execute(self.pool.as_ref()).await?;
+        if result.rows_affected() == 0 {
+            Err(StoreError::no_such_entity(EntityKind::SshPublicKey, ssh_public_key_id))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn delete_ssh_public_key(&self, user_id: &str, ssh_public_key_id: &str) -> Result<(), StoreError> {
+        let query =
+            format!("DELETE FROM {}iam_user_ssh_public_key WHERE user_id = $1 AND public_key_id = $2", self.prefix);
+        let result = sqlx::query(&query).bind(user_id).bind(ssh_public_key_id).execute(self.pool.as_ref()).await?;
+        if result.rows_affected() == 0 {
+            Err(StoreError::no_such_entity(EntityKind::SshPublicKey, ssh_public_key_id))
+        } else {
+            Ok(())
+        }
+    }
+
+    async fn create_service_specific_credential(
+        &self,
+        credential: &ServiceSpecificCredential,
+    ) -> Result<(), StoreError> {
+        let query = format!(
+            "INSERT INTO {}iam_user_service_specific_credential(user_id, service_specific_credential_id, \
+             service_name, service_password, active, created_at) VALUES($1, $2, $3, $4, $5, {})",
+            self.prefix,
+            self.timestamp_param(6)
+        );
+        sqlx::query(&query)
+            .bind(&credential.user_id)
+            .bind(&credential.service_specific_credential_id)
+            .bind(&credential.service_name)
+            .bind(&credential.service_password)
+            .bind(credential.active)
+            .bind(format_timestamp(&credential.created_at))
+            .execute(self.pool.as_ref())
+            .await
+            .map_err(|e| {
+                if is_unique_violation(&e) {
+                    StoreError::already_exists(
+                        EntityKind::ServiceSpecificCredential,
+                        &credential.service_specific_credential_id,
+                    )
+                } else if is_foreign_key_violation(&e) {
+                    StoreError::no_such_entity(EntityKind::User, &credential.user_id)
+                } else {
+                    e.into()
+                }
+            })?;
+        Ok(())
+    }
+
+    async fn list_service_specific_credentials(
+        &self,
+        user_id: &str,
+    ) -> Result<Vec<ServiceSpecificCredential>, StoreError> {
+        let query = format!(
+            "SELECT {SERVICE_SPECIFIC_CREDENTIAL_COLUMNS}, {} FROM {}iam_user_service_specific_credential \
+             WHERE user_id = $1 ORDER BY created_at, service_specific_credential_id",
+            self.created_at(),
+            self.prefix
+        );
+        let rows = sqlx::query(&query).bind(user_id).fetch_all(self.pool.as_ref()).await?;
+        let mut credentials = Vec::with_capacity(rows.len());
+        for row in rows {
+            credentials.push(service_specific_credential_from_row(&row)?);
+        }
+        Ok(credentials)
+    }
+
+    async fn reset_service_specific_credential(
+        &self,
+        user_id: &str,
+        service_specific_credential_id: &str,
+        service_password: &str,
+    ) -> Result<ServiceSpecificCredential, StoreError> {
+        let not_found =
+            || StoreError::no_such_entity(EntityKind::ServiceSpecificCredential, service_specific_credential_id);
+        let mut tx = self.pool.begin().await?;
+        let query = format!(
+            "UPDATE {}iam_user_service_specific_credential SET service_password = $1 \
+             WHERE user_id = $2 AND service_specific_credential_id = $3",
+            self.prefix
+        );
+        let result = sqlx::query(&query)
+            .bind(service_password)
+            .bind(user_id)
+            .bind(service_specific_credential_id)
+            .execute(&mut tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Err(not_found());
+        }
+
+        let query = format!(
+            "SELECT {SERVICE_SPECIFIC_CREDENTIAL_COLUMNS}, {} FROM {}iam_user_service_specific_credential \
+             WHERE user_id = $1 AND service_specific_credential_id = $2",
+            self.created_at(),
+            self.prefix
+        );
+        let row = sqlx::query(&query)
+            .bind(user_id)
+            .bind(service_specific_credential_id)
+            .fetch_optional(&mut tx)
+            .await?
+            .ok_or_else(not_found)?;
+        let credential = service_specific_credential_from_row(&row)?;
+        tx.commit().await?;
+        Ok(credential)
+    }
+
+    async fn delete_service_specific_credential(
+        &self,
+        user_id: &str,
+        service_specific_credential_id: &str,
+    ) -> Result<(), StoreError> {
+        let query = format!(
+            "DELETE FROM {}iam_user_service_specific_credential \
+             WHERE user_id = $1 AND service_specific_credential_id = $2",
+            self.prefix
+        );
+        let result =
+            sqlx::query(&query).bind(user_id).bind(service_specific_credential_id).execute(self.pool.as_ref()).await?;
+        if result.rows_affected() == 0 {
+            Err(StoreError::no_such_entity(EntityKind::ServiceSpecificCredential, service_specific_credential_id))
+        } else {
+            Ok(())
+        }
+    }
+}
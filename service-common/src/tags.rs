@@ -0,0 +1,241 @@
+//! Tags given to users and roles when they are created.
+//!
+//! Tags arrive as the query protocol list `Tags.member.N.Key` / `Tags.member.N.Value`, numbered from 1. Keys are
+//! compared case-insensitively, as in AWS: `Team` and `team` are the same tag and cannot both be given. The `aws:`
+//! prefix is reserved for tags AWS applies itself.
+use {
+    crate::{
+        operation::{
+            constraint::{length, pattern},
+            output::{write_element, ToXml},
+            FromParameter, ParameterError, ValidationError,
+        },
+        store::Tag,
+    },
+    http::StatusCode,
+    std::{
+        collections::{HashMap, HashSet},
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// The most tags a user or role may have.
+pub const MAX_TAGS: usize = 50;
+
+/// Characters allowed in tag keys; values may also be empty.
+const TAG_KEY_PATTERN: &str = r"[\p{L}\p{Z}\p{N}_.:/=+\-@]+";
+const TAG_VALUE_PATTERN: &str = r"[\p{L}\p{Z}\p{N}_.:/=+\-@]*";
+
+/// The prefix reserved for tags applied by AWS.
+const RESERVED_PREFIX: &str = "aws:";
+
+impl FromParameter for Vec<Tag> {
+    const KIND: &'static str = "list";
+    const REQUIRED: bool = false;
+
+    fn from_parameter(parameters: &HashMap<String, String>, name: &str) -> Result<Self, ParameterError> {
+        let mut tags = Vec::new();
+        for n in 1.. {
+            let key = parameters.get(&format!("{name}.member.{n}.Key"));
+            let value = parameters.get(&format!("{name}.member.{n}.Value"));
+            match (key, value) {
+                (None, None) => break,
+                (Some(key), Some(value)) => tags.push(Tag {
+                    key: key.clone(),
+                    value: value.clone(),
+                }),
+                (None, Some(_)) => return Err(ParameterError::Missing(format!("{name}.{n}.member.Key"))),
+                (Some(_), None) => return Err(ParameterError::Missing(format!("{name}.{n}.member.Value"))),
+            }
+        }
+
+        Ok(tags)
+    }
+}
+
+/// Check tags against the AWS limits before they are stored.
+///
+/// Malformed keys and values are reported together as a [ValidationError], with the field paths AWS uses
+/// (`tags.1.member.key`); too many tags, duplicate keys, and reserved keys are reported on their own.
+pub fn validate_tags(tags: &[Tag]) -> Result<(), TagError> {
+    if tags.len() > MAX_TAGS {
+        return Err(TagError::TooManyTags(tags.len()));
+    }
+
+    let mut violations = Vec::new();
+    for (i, tag) in tags.iter().enumerate() {
+        let key_name = format!("Tags.{}.member.Key", i + 1);
+        let value_name = format!("Tags.{}.member.Value", i + 1);
+        let checks = [
+            length(&key_name, &tag.key, 1, 128),
+            pattern(&key_name, &tag.key, TAG_KEY_PATTERN),
+            length(&value_name, &tag.value, 0, 256),
+            pattern(&value_name, &tag.value, TAG_VALUE_PATTERN),
+        ];
+        violations.extend(checks.into_iter().filter_map(Result::err));
+    }
+
+    if !violations.is_empty() {
+        return Err(ValidationError::new(violations).into());
+    }
+
+    let mut seen = HashSet::with_capacity(tags.len());
+    for tag in tags {
+        let key = tag.key.to_lowercase();
+        if key.starts_with(RESERVED_PREFIX) {
+            return Err(TagError::ReservedKey(tag.key.clone()));
+        }
+
+        if !seen.insert(key) {
+            return Err(TagError::DuplicateKey(tag.key.clone()));
+        }
+    }
+
+    Ok(())
+}
+
+/// The tags of a response: the `<Tags>` element is omitted if there are none, as in AWS.
+pub fn response_tags(tags: &[Tag]) -> Option<&[Tag]> {
+    (!tags.is_empty()).then_some(tags)
+}
+
+impl ToXml for Tag {
+    fn write_xml(&self, xml: &mut String) {
+        write_element(xml, "Key", &self.key);
+        write_element(xml, "Value", &self.value);
+    }
+}
+
+#[derive(Debug)]
+pub enum TagError {
+    Validation(ValidationError),
+
+    /// More than [MAX_TAGS] tags were given; holds the number given.
+    TooManyTags(usize),
+
+    /// The same key was given twice, ignoring case.
+    DuplicateKey(String),
+
+    /// The key starts with `aws:`.
+    ReservedKey(String),
+}
+
+impl TagError {
+    /// The IAM error code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Validation(e) => e.code(),
+            Self::TooManyTags(_) => "LimitExceeded",
+            Self::DuplicateKey(_) | Self::ReservedKey(_) => "InvalidInput",
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::TooManyTags(_) => StatusCode::CONFLICT,
+            Self::Validation(_) | Self::DuplicateKey(_) | Self::ReservedKey(_) => StatusCode::BAD_REQUEST,
+        }
+    }
+}
+
+impl Error for TagError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Validation(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for TagError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Validation(e) => write!(f, "{e}"),
+            Self::TooManyTags(n) => write!(f, "The number of tags ({n}) cannot exceed {MAX_TAGS}."),
+            Self::DuplicateKey(key) => write!(f, "Duplicate tag keys found: {key}"),
+            Self::ReservedKey(key) => write!(f, "User-defined tag keys cannot start with {RESERVED_PREFIX}: {key}"),
+        }
+    }
+}
+
+impl From<ValidationError> for TagError {
+    fn from(e: ValidationError) -> Self {
+        Self::Validation(e)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{response_tags, validate_tags, TagError, MAX_TAGS},
+        crate::{
+            operation::{output::write_element, parameters, FromParameter, ParameterError},
+            store::Tag,
+        },
+        pretty_assertions::assert_eq,
+        std::collections::HashMap,
+    };
+
+    fn tag(key: &str, value: &str) -> Tag {
+        Tag {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test_log::test]
+    fn test_parse_tags() {
+        let given = parameters(&[
+            ("Tags.member.1.Key", "Team"),
+            ("Tags.member.1.Value", "Platform"),
+            ("Tags.member.2.Key", "Empty"),
+            ("Tags.member.2.Value", ""),
+            // Members after a gap are not part of the list.
+            ("Tags.member.4.Key", "Ignored"),
+            ("Tags.member.4.Value", "x"),
+        ]);
+        let tags = <Vec<Tag>>::from_parameter(&given, "Tags").unwrap();
+        assert_eq!(tags, vec![tag("Team", "Platform"), tag("Empty", "")]);
+        assert_eq!(<Vec<Tag>>::from_parameter(&HashMap::new(), "Tags").unwrap(), vec![]);
+
+        let e = <Vec<Tag>>::from_parameter(&parameters(&[("Tags.member.1.Key", "Team")]), "Tags").unwrap_err();
+        assert_eq!(e, ParameterError::Missing("Tags.1.member.Value".to_string()));
+    }
+
+    #[test_log::test]
+    fn test_validate_tags() {
+        validate_tags(&[tag("Team", "Platform"), tag("cost-center", "1234"), tag("Name", "")]).unwrap();
+
+        let e = validate_tags(&[tag("", "x"), tag("Team", "a\u{7}b")]).unwrap_err();
+        assert_eq!(e.code(), "ValidationError");
+        assert_eq!(
+            e.to_string(),
+            "3 validation errors detected: \
+             Value '' at 'tags.1.member.key' failed to satisfy constraint: Member must have length greater than or equal to 1; \
+             Value '' at 'tags.1.member.key' failed to satisfy constraint: Member must satisfy regular expression pattern: [\\p{L}\\p{Z}\\p{N}_.:/=+\\-@]+; \
+             Value 'a\u{7}b' at 'tags.2.member.value' failed to satisfy constraint: Member must satisfy regular expression pattern: [\\p{L}\\p{Z}\\p{N}_.:/=+\\-@]*"
+        );
+
+        let e = validate_tags(&[tag("Team", "a"), tag("team", "b")]).unwrap_err();
+        assert!(matches!(e, TagError::DuplicateKey(ref key) if key == "team"));
+        assert_eq!((e.code(), e.status().as_u16()), ("InvalidInput", 400));
+
+        let e = validate_tags(&[tag("AWS:CloudFormation", "x")]).unwrap_err();
+        assert_eq!(e.code(), "InvalidInput");
+
+        let many: Vec<Tag> = (0..=MAX_TAGS).map(|n| tag(&format!("Key{n}"), "")).collect();
+        let e = validate_tags(&many).unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("LimitExceeded", 409));
+    }
+
+    #[test_log::test]
+    fn test_response_tags() {
+        let mut xml = String::new();
+        write_element(&mut xml, "Tags", &response_tags(&[]));
+        assert_eq!(xml, "");
+
+        write_element(&mut xml, "Tags", &response_tags(&[tag("Team", "R&D")]));
+        assert_eq!(xml, "<Tags><member><Key>Team</Key><Value>R&amp;D</Value></member></Tags>");
+    }
+}
@@ -0,0 +1,348 @@
+//! A Prometheus `/metrics` endpoint, served on its own listener.
+//!
+//! [Telemetry] gathers the metrics the service keeps — requests by action ([RequestMetrics]), signature verification
+//! ([VerificationMetrics]), control plane store calls ([QueryMetrics]), TLS handshakes ([TlsMetrics]), deprecated
+//! usage ([Deprecations]) and the state of the database pool — and [serve_metrics] answers `GET /metrics` with all of
+//! them. The listener is separate from the API listener so it can be bound to a private address and scraped without
+//! signing requests.
+use {
+    crate::{
+        deprecation::Deprecations,
+        health::CancellationToken,
+        histogram::Histogram,
+        metrics::VerificationMetrics,
+        net::{Connection, Incoming, TlsMetrics},
+        policy_metrics::PolicyMetrics,
+        store::QueryMetrics,
+    },
+    http::{header::CONTENT_TYPE, Method, StatusCode},
+    hyper::{
+        body::to_bytes,
+        server::Server,
+        service::{make_service_fn, service_fn, Service},
+        Body, Request, Response,
+    },
+    log::{error, info},
+    serde::Deserialize,
+    sqlx::AnyPool,
+    std::{
+        collections::BTreeMap,
+        convert::Infallible,
+        fmt::Write,
+        future::Future,
+        net::SocketAddr,
+        pin::Pin,
+        sync::{Arc, Mutex},
+        task::{Context, Poll},
+        time::{Duration, Instant},
+    },
+    tokio::task::JoinHandle,
+    tower::BoxError,
+};
+
+/// The path the metrics are served on.
+pub const METRICS_PATH: &str = "/metrics";
+
+/// Upper bounds of the request time histogram buckets, in seconds.
+pub const REQUEST_BUCKETS: &[f64] = &[0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.5, 5.0];
+
+/// The label for requests whose action is missing or not a plausible action name.
+const UNKNOWN_ACTION: &str = "unknown";
+
+/// Settings for the metrics endpoint.
+#[derive(Clone, Debug, Deserialize)]
+pub struct MetricsEndpointConfig {
+    /// The address to serve `/metrics` on, e.g. `127.0.0.1:9100`.
+    pub address: SocketAddr,
+}
+
+/// Request counts by action and status, and request time by action.
+#[derive(Debug, Default)]
+pub struct RequestMetrics {
+    state: Mutex<RequestState>,
+}
+
+#[derive(Debug, Default)]
+struct RequestState {
+    requests: BTreeMap<(String, u16), u64>,
+    latency: BTreeMap<String, Histogram>,
+}
+
+impl RequestMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn record(&self, action: Option<&str>, status: StatusCode, elapsed: Duration) {
+        let action = action_label(action);
+        let mut state = self.state.lock().expect("request metrics poisoned");
+        *state.requests.entry((action.clone(), status.as_u16())).or_default() += 1;
+
+        state.latency.entry(action).or_insert_with(|| Histogram::new(REQUEST_BUCKETS)).observe(elapsed.as_secs_f64());
+    }
+
+    /// The number of requests recorded for `action` with the given status.
+    pub fn count(&self, action: &str, status: StatusCode) -> u64 {
+        let state = self.state.lock().expect("request metrics poisoned");
+        state.requests.get(&(action.to_string(), status.as_u16())).copied().unwrap_or_default()
+    }
+
+    /// Request counts by action and status, and a request time histogram per action.
+    pub fn render(&self) -> String {
+        let state = self.state.lock().expect("request metrics poisoned");
+        let mut out = String::new();
+
+        let _ = writeln!(out, "# HELP scratchstack_requests_total Requests that reached the service implementation.");
+        let _ = writeln!(out, "# TYPE scratchstack_requests_total counter");
+        for ((action, status), count) in &state.requests {
+            let _ = writeln!(out, "scratchstack_requests_total{{action=\"{action}\",status=\"{status}\"}} {count}");
+        }
+
+        let _ = writeln!(out, "# HELP scratchstack_request_seconds Time spent handling requests.");
+        let _ = writeln!(out, "# TYPE scratchstack_request_seconds histogram");
+        for (action, histogram) in &state.latency {
+            histogram.render(&mut out, "scratchstack_request_seconds", &format!("action=\"{action}\""));
+        }
+        out
+    }
+}
+
+/// `action` as a label value. Anything that cannot be an action name is counted as [UNKNOWN_ACTION], so clients
+/// cannot create arbitrary series.
+fn action_label(action: Option<&str>) -> String {
+    match action {
+        Some(action)
+            if !action.is_empty() && action.len() <= 64 && action.bytes().all(|b| b.is_ascii_alphanumeric()) =>
+        {
+            action.to_string()
+        }
+        _ => UNKNOWN_ACTION.to_string(),
+    }
+}
+
+/// A service wrapper for the service implementation that records each request in [RequestMetrics].
+#[derive(Clone, Debug)]
+pub struct RecordRequests<S> {
+    inner: S,
+    metrics: Arc<RequestMetrics>,
+}
+
+impl<S> RecordRequests<S> {
+    pub fn new(inner: S, metrics: Arc<RequestMetrics>) -> Self {
+        Self {
+            inner,
+            metrics,
+        }
+    }
+}
+
+impl<S> Service<Request<Body>> for RecordRequests<S>
+where
+    S: Service<Request<Body>, Response = Response<Body>> + Clone + Send + 'static,
+    S::Error: Into<BoxError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<Body>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        // The action is usually in the form parameters, so the body is needed.
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let metrics = self.metrics.clone();
+
+        Box::pin(async move {
+            let start = Instant::now();
+            let (parts, body) = req.into_parts();
+            let body = to_bytes(body).await?;
+            let query = form_urlencoded::parse(parts.uri.query().unwrap_or("").as_bytes());
+            let action = query
+                .chain(form_urlencoded::parse(&body))
+                .find(|(key, _)| key == "Action")
+                .map(|(_, value)| value.into_owned());
+
+            let result = inner.call(Request::from_parts(parts, Body::from(body))).await.map_err(Into::into);
+            let status = match &result {
+                Ok(response) => response.status(),
+                Err(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            };
+            metrics.record(action.as_deref(), status, start.elapsed());
+            result
+        })
+    }
+}
+
+/// The metrics a service exposes on its metrics endpoint.
+#[derive(Debug, Default)]
+pub struct Telemetry {
+    requests: Arc<RequestMetrics>,
+    verification: Option<Arc<VerificationMetrics>>,
+    queries: Option<Arc<QueryMetrics>>,
+    tls: Option<Arc<TlsMetrics>>,
+    policies: Option<Arc<PolicyMetrics>>,
+    deprecations: Option<Arc<Deprecations>>,
+    pool: Option<Arc<AnyPool>>,
+}
+
+impl Telemetry {
+    pub fn new(requests: Arc<RequestMetrics>) -> Self {
+        Self {
+            requests,
+            ..Self::default()
+        }
+    }
+
+    pub fn with_verification_metrics(mut self, metrics: Arc<VerificationMetrics>) -> Self {
+        self.verification = Some(metrics);
+        self
+    }
+
+    pub fn with_query_metrics(mut self, metrics: Arc<QueryMetrics>) -> Self {
+        self.queries = Some(metrics);
+        self
+    }
+
+    pub fn with_tls_metrics(mut self, metrics: Arc<TlsMetrics>) -> Self {
+        self.tls = Some(metrics);
+        self
+    }
+
+    pub fn with_policy_metrics(mut self, metrics: Arc<PolicyMetrics>) -> Self {
+        self.policies = Some(metrics);
+        self
+    }
+
+    pub fn with_deprecations(mut self, deprecations: Arc<Deprecations>) -> Self {
+        self.deprecations = Some(deprecations);
+        self
+    }
+
+    /// Report the size and idle connections of `pool`.
+    pub fn with_pool(mut self, pool: Arc<AnyPool>) -> Self {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// Render every metric in the Prometheus text exposition format.
+    pub fn render(&self) -> String {
+        let mut out = self.requests.render();
+        if let Some(verification) = &self.verification {
+            out.push_str(&verification.render());
+        }
+        if let Some(queries) = &self.queries {
+            out.push_str(&queries.render());
+        }
+        if let Some(tls) = &self.tls {
+            out.push_str(&tls.render());
+        }
+        if let Some(policies) = &self.policies {
+            out.push_str(&policies.render());
+        }
+        if let Some(deprecations) = &self.deprecations {
+            out.push_str(&deprecations.render());
+        }
+        if let Some(pool) = &self.pool {
+            let size = pool.size();
+            let idle = u32::try_from(pool.num_idle()).unwrap_or(u32::MAX).min(size);
+            let _ = writeln!(out, "# HELP scratchstack_db_pool_connections Open database connections by state.");
+            let _ = writeln!(out, "# TYPE scratchstack_db_pool_connections gauge");
+            let _ = writeln!(out, "scratchstack_db_pool_connections{{state=\"idle\"}} {idle}");
+            let _ = writeln!(out, "scratchstack_db_pool_connections{{state=\"in_use\"}} {}", size - idle);
+            let _ = writeln!(out, "# HELP scratchstack_db_pool_max_connections The most connections the pool opens.");
+            let _ = writeln!(out, "# TYPE scratchstack_db_pool_max_connections gauge");
+            let _ = writeln!(out, "scratchstack_db_pool_max_connections {}", pool.options().get_max_connections());
+        }
+        out
+    }
+
+    fn response(&self, req: &Request<Body>) -> Response<Body> {
+        let (status, body) = if req.uri().path() != METRICS_PATH {
+            (StatusCode::NOT_FOUND, "Not found\n".to_string())
+        } else if req.method() != Method::GET && req.method() != Method::HEAD {
+            (StatusCode::METHOD_NOT_ALLOWED, "Method not allowed\n".to_string())
+        } else {
+            (StatusCode::OK, self.render())
+        };
+
+        let mut response = Response::new(Body::from(body));
+        *response.status_mut() = status;
+        response.headers_mut().insert(CONTENT_TYPE, "text/plain; version=0.0.4".parse().unwrap());
+        response
+    }
+}
+
+/// Serve `telemetry` on `incoming` until `shutdown` is cancelled.
+pub fn serve_metrics(incoming: Incoming, telemetry: Arc<Telemetry>, shutdown: CancellationToken) -> JoinHandle<()> {
+    if let Ok(address) = incoming.local_addr() {
+        info!("Serving metrics at http://{}{}", address, METRICS_PATH);
+    }
+
+    let make_service = make_service_fn(move |_: &Connection| {
+        let telemetry = telemetry.clone();
+        async move {
+            Ok::<_, Infallible>(service_fn(move |req: Request<Body>| {
+                let response = telemetry.response(&req);
+                async move { Ok::<_, Infallible>(response) }
+            }))
+        }
+    });
+
+    tokio::spawn(async move {
+        let server = Server::builder(incoming).serve(make_service);
+        if let Err(e) = server.with_graceful_shutdown(async move { shutdown.cancelled().await }).await {
+            error!("Metrics endpoint failed: {}", e);
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{serve_metrics, RequestMetrics, Telemetry},
+        crate::{health::CancellationToken, net::Incoming},
+        http::StatusCode,
+        hyper::{body::to_bytes, Client},
+        pretty_assertions::assert_eq,
+        sqlx::any::AnyPoolOptions,
+        std::{sync::Arc, time::Duration},
+        tokio::net::TcpListener,
+    };
+
+    #[test_log::test(tokio::test)]
+    async fn test_metrics_endpoint() {
+        let requests = Arc::new(RequestMetrics::new());
+        requests.record(Some("GetUser"), StatusCode::OK, Duration::from_millis(3));
+        requests.record(Some("GetUser"), StatusCode::OK, Duration::from_millis(30));
+        requests.record(Some("<script>"), StatusCode::BAD_REQUEST, Duration::from_millis(1));
+        assert_eq!(requests.count("GetUser", StatusCode::OK), 2);
+        assert_eq!(requests.count("unknown", StatusCode::BAD_REQUEST), 1);
+
+        let pool = AnyPoolOptions::new().max_connections(4).connect("sqlite::memory:").await.unwrap();
+        let telemetry = Arc::new(Telemetry::new(requests).with_pool(Arc::new(pool)));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let incoming = Incoming::new(listener, None, Default::default());
+        let address = incoming.local_addr().unwrap();
+        let shutdown = CancellationToken::new();
+        let server = serve_metrics(incoming, telemetry, shutdown.clone());
+
+        let client = Client::new();
+        let response = client.get(format!("http://{address}/metrics").parse().unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = String::from_utf8(to_bytes(response.into_body()).await.unwrap().to_vec()).unwrap();
+        assert!(body.contains("scratchstack_requests_total{action=\"GetUser\",status=\"200\"} 2\n"), "{body}");
+        assert!(body.contains("scratchstack_request_seconds_bucket{action=\"GetUser\",le=\"0.005\"} 1\n"), "{body}");
+        assert!(body.contains("scratchstack_db_pool_max_connections 4\n"), "{body}");
+
+        let response = client.get(format!("http://{address}/").parse().unwrap()).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+
+        drop(client);
+        shutdown.cancel();
+        server.await.unwrap();
+    }
+}
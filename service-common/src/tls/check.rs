@@ -0,0 +1,260 @@
+use {
+    super::TlsError,
+    chrono::{DateTime, Duration, TimeZone, Utc},
+    rustls::{sign::any_supported_type, Certificate, PrivateKey, SignatureScheme},
+    std::{
+        fmt::{Display, Formatter, Result as FmtResult},
+        net::IpAddr,
+    },
+    webpki::{EndEntityCert, SignatureAlgorithm},
+    x509_parser::{certificate::X509Certificate, extensions::GeneralName, prelude::FromDer},
+};
+
+/// Message signed with the private key to check that it matches the certificate.
+const KEY_CHECK_MESSAGE: &[u8] = b"scratchstack certificate key check";
+
+/// Signature schemes tried when checking the private key, with the matching verification algorithm.
+const KEY_CHECK_SCHEMES: &[(SignatureScheme, &SignatureAlgorithm)] = &[
+    (SignatureScheme::ECDSA_NISTP256_SHA256, &webpki::ECDSA_P256_SHA256),
+    (SignatureScheme::ECDSA_NISTP384_SHA384, &webpki::ECDSA_P384_SHA384),
+    (SignatureScheme::ED25519, &webpki::ED25519),
+    (SignatureScheme::RSA_PKCS1_SHA256, &webpki::RSA_PKCS1_2048_8192_SHA256),
+];
+
+/// A problem with a certificate chain that does not prevent the endpoint from starting.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum CertificateWarning {
+    /// The certificate expires within the warning period.
+    ExpiresSoon {
+        subject: String,
+        not_after: DateTime<Utc>,
+    },
+
+    /// The certificate at `index` was not issued by the certificate that follows it. Some clients will not be able
+    /// to build a path to a trusted root.
+    OutOfOrder {
+        index: usize,
+        subject: String,
+        issuer: String,
+    },
+}
+
+impl Display for CertificateWarning {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::ExpiresSoon {
+                subject,
+                not_after,
+            } => write!(f, "Certificate {subject} expires at {not_after}"),
+            Self::OutOfOrder {
+                index,
+                subject,
+                issuer,
+            } => write!(
+                f,
+                "Certificate {index} ({subject}) is followed by a certificate other than its issuer ({issuer}); the chain should be ordered from the server certificate to the root"
+            ),
+        }
+    }
+}
+
+/// A name from a certificate's SubjectAltName extension.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum SubjectAltName {
+    Dns(String),
+    Ip(IpAddr),
+    Email(String),
+    Uri(String),
+}
+
+impl Display for SubjectAltName {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Dns(name) => write!(f, "DNS:{name}"),
+            Self::Ip(addr) => write!(f, "IP:{addr}"),
+            Self::Email(email) => write!(f, "email:{email}"),
+            Self::Uri(uri) => write!(f, "URI:{uri}"),
+        }
+    }
+}
+
+/// Returns the names in `certificate`'s SubjectAltName extension. Name types other than those in
+/// [SubjectAltName] are skipped.
+pub fn subject_alt_names(certificate: &Certificate) -> Result<Vec<SubjectAltName>, TlsError> {
+    let (_, x509) =
+        X509Certificate::from_der(&certificate.0).map_err(|e| TlsError::InvalidCertificate(e.to_string()))?;
+    let extension = match x509.subject_alternative_name().map_err(|e| TlsError::InvalidCertificate(e.to_string()))? {
+        None => return Ok(Vec::new()),
+        Some(extension) => extension,
+    };
+
+    let mut names = Vec::new();
+    for name in &extension.value.general_names {
+        match name {
+            GeneralName::DNSName(name) => names.push(SubjectAltName::Dns(name.to_string())),
+            GeneralName::RFC822Name(email) => names.push(SubjectAltName::Email(email.to_string())),
+            GeneralName::URI(uri) => names.push(SubjectAltName::Uri(uri.to_string())),
+            GeneralName::IPAddress(bytes) => {
+                if let Ok(octets) = <[u8; 4]>::try_from(*bytes) {
+                    names.push(SubjectAltName::Ip(IpAddr::from(octets)));
+                } else if let Ok(octets) = <[u8; 16]>::try_from(*bytes) {
+                    names.push(SubjectAltName::Ip(IpAddr::from(octets)));
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(names)
+}
+
+/// Check a certificate chain and its private key before they are used.
+///
+/// This fails if a certificate cannot be parsed, is expired or not yet valid at `now`, or if `key` does not belong
+/// to the first (server) certificate. Certificates expiring within `warn_within` and chains that are not in issuer
+/// order are returned as warnings.
+pub fn check_certificate_chain(
+    chain: &[Certificate],
+    key: &PrivateKey,
+    now: DateTime<Utc>,
+    warn_within: Duration,
+) -> Result<Vec<CertificateWarning>, TlsError> {
+    let mut parsed = Vec::with_capacity(chain.len());
+    for certificate in chain {
+        let (_, x509) =
+            X509Certificate::from_der(&certificate.0).map_err(|e| TlsError::InvalidCertificate(e.to_string()))?;
+        parsed.push(x509);
+    }
+
+    let mut warnings = Vec::new();
+
+    for (index, x509) in parsed.iter().enumerate() {
+        let subject = x509.subject().to_string();
+        let validity = x509.validity();
+        let not_before = timestamp(validity.not_before.timestamp());
+        let not_after = timestamp(validity.not_after.timestamp());
+
+        if now < not_before {
+            return Err(TlsError::CertificateNotYetValid {
+                subject,
+                not_before: not_before.to_rfc3339(),
+            });
+        }
+
+        if now > not_after {
+            return Err(TlsError::CertificateExpired {
+                subject,
+                not_after: not_after.to_rfc3339(),
+            });
+        }
+
+        if not_after - now < warn_within {
+            warnings.push(CertificateWarning::ExpiresSoon {
+                subject: subject.clone(),
+                not_after,
+            });
+        }
+
+        if let Some(next) = parsed.get(index + 1) {
+            if x509.issuer().as_raw() != next.subject().as_raw() {
+                warnings.push(CertificateWarning::OutOfOrder {
+                    index,
+                    subject,
+                    issuer: x509.issuer().to_string(),
+                });
+            }
+        }
+    }
+
+    if let Some(server) = chain.first() {
+        check_key_matches(server, key)?;
+    }
+
+    Ok(warnings)
+}
+
+/// Sign a message with `key` and verify it with the public key in `certificate`.
+fn check_key_matches(certificate: &Certificate, key: &PrivateKey) -> Result<(), TlsError> {
+    let signing_key = any_supported_type(key).map_err(|_| TlsError::KeyMismatch)?;
+    let end_entity = EndEntityCert::try_from(certificate.0.as_slice())
+        .map_err(|e| TlsError::InvalidCertificate(format!("{e:?}")))?;
+
+    for (scheme, algorithm) in KEY_CHECK_SCHEMES {
+        if let Some(signer) = signing_key.choose_scheme(&[*scheme]) {
+            let signature = signer.sign(KEY_CHECK_MESSAGE)?;
+            return end_entity
+                .verify_signature(algorithm, KEY_CHECK_MESSAGE, &signature)
+                .map_err(|_| TlsError::KeyMismatch);
+        }
+    }
+
+    // The key type is usable by rustls but not one we can check; leave it to the handshake.
+    Ok(())
+}
+
+fn timestamp(seconds: i64) -> DateTime<Utc> {
+    Utc.timestamp_opt(seconds, 0).single().unwrap_or(DateTime::<Utc>::MIN_UTC)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{check_certificate_chain, subject_alt_names, CertificateWarning, SubjectAltName},
+        crate::tls::TlsError,
+        chrono::{Duration, Utc},
+        pretty_assertions::assert_eq,
+        rustls::{Certificate, PrivateKey},
+    };
+
+    fn self_signed(name: &str) -> (Certificate, PrivateKey) {
+        let cert = rcgen::generate_simple_self_signed(vec![name.to_string()]).unwrap();
+        (Certificate(cert.serialize_der().unwrap()), PrivateKey(cert.serialize_private_key_der()))
+    }
+
+    #[test_log::test]
+    fn test_check_certificate_chain() {
+        let (cert, key) = self_signed("iam.local");
+        let (_, other_key) = self_signed("sts.local");
+        let now = Utc::now();
+
+        let warnings = check_certificate_chain(&[cert.clone()], &key, now, Duration::days(30)).unwrap();
+        assert_eq!(warnings, vec![]);
+
+        let e = check_certificate_chain(&[cert.clone()], &other_key, now, Duration::days(30)).unwrap_err();
+        assert!(matches!(e, TlsError::KeyMismatch));
+
+        // rcgen certificates are valid until 4096.
+        let later = "4095-12-01T00:00:00Z".parse().unwrap();
+        let warnings = check_certificate_chain(&[cert.clone()], &key, later, Duration::days(60)).unwrap();
+        assert!(matches!(warnings[0], CertificateWarning::ExpiresSoon { .. }));
+
+        let much_later = "4097-01-01T00:00:00Z".parse().unwrap();
+        let e = check_certificate_chain(&[cert], &key, much_later, Duration::days(30)).unwrap_err();
+        assert!(matches!(e, TlsError::CertificateExpired { .. }));
+    }
+
+    #[test_log::test]
+    fn test_subject_alt_names() {
+        let cert = rcgen::generate_simple_self_signed(vec!["iam.local".to_string(), "sts.local".to_string()]).unwrap();
+        let names = subject_alt_names(&Certificate(cert.serialize_der().unwrap())).unwrap();
+        assert_eq!(
+            names,
+            vec![SubjectAltName::Dns("iam.local".to_string()), SubjectAltName::Dns("sts.local".to_string())]
+        );
+        assert_eq!(names[0].to_string(), "DNS:iam.local");
+        assert_eq!(SubjectAltName::Ip("127.0.0.1".parse().unwrap()).to_string(), "IP:127.0.0.1");
+    }
+
+    #[test_log::test]
+    fn test_warning_display() {
+        let warning = CertificateWarning::OutOfOrder {
+            index: 0,
+            subject: "CN=iam.local".to_string(),
+            issuer: "CN=Scratchstack Test CA".to_string(),
+        };
+        assert_eq!(
+            warning.to_string(),
+            "Certificate 0 (CN=iam.local) is followed by a certificate other than its issuer (CN=Scratchstack Test CA); the chain should be ordered from the server certificate to the root"
+        );
+    }
+}
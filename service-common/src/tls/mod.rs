@@ -0,0 +1,168 @@
+mod check;
+mod pem;
+
+pub use self::{
+    check::{check_certificate_chain, subject_alt_names, CertificateWarning, SubjectAltName},
+    pem::{parse_certificates, parse_private_keys, read_certificate_chain, read_private_key, read_private_keys},
+};
+
+use {
+    chrono::{Duration, Utc},
+    log::{info, warn},
+    rustls::{server::AllowAnyAuthenticatedClient, Error as RustlsError, RootCertStore, ServerConfig},
+    serde::Deserialize,
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+        fs::read_to_string,
+        io::Error as IOError,
+        path::PathBuf,
+    },
+};
+
+/// Certificate and private key files for a TLS endpoint.
+///
+/// Unlike the `tls` section handled by `scratchstack-config`, which only accepts unencrypted PKCS#1 RSA keys, the
+/// private key here may be PKCS#1 RSA, PKCS#8 (RSA, ECDSA or Ed25519), SEC1 EC, or password-protected PKCS#8. If
+/// both are configured, these files are used.
+#[derive(Clone, Debug, Deserialize)]
+pub struct TlsFiles {
+    /// PEM file containing the server certificate followed by any intermediate certificates.
+    pub certificate_chain_file: PathBuf,
+
+    /// PEM file containing the private key.
+    pub private_key_file: PathBuf,
+
+    /// File containing the passphrase for an encrypted private key. Trailing whitespace is ignored.
+    pub private_key_passphrase_file: Option<PathBuf>,
+
+    /// If present, PEM file containing the CA certificates that client certificates must be issued by. Clients that
+    /// do not present a certificate from one of these CAs fail the handshake, and the verified identity is available
+    /// to requests as a [ClientIdentity][crate::net::ClientIdentity] extension.
+    pub client_ca_file: Option<PathBuf>,
+
+    /// Log a warning at startup if a certificate in the chain expires within this many days.
+    #[serde(default = "TlsFiles::default_expiry_warning_days")]
+    pub expiry_warning_days: u32,
+}
+
+impl TlsFiles {
+    fn default_expiry_warning_days() -> u32 {
+        30
+    }
+
+    /// Load the certificate chain and private key and create a server configuration. HTTP/2 and HTTP/1.1 are
+    /// offered via ALPN, and client certificates are required if [TlsFiles::client_ca_file] is set.
+    ///
+    /// The chain is checked with [check_certificate_chain] first, so an expired certificate or a key that does not
+    /// belong to the certificate is reported here rather than as a failed handshake later.
+    pub fn server_config(&self) -> Result<ServerConfig, TlsError> {
+        let chain = read_certificate_chain(&self.certificate_chain_file)?;
+        let passphrase = match &self.private_key_passphrase_file {
+            None => None,
+            Some(path) => Some(read_to_string(path)?.trim_end().to_string()),
+        };
+        let key = read_private_key(&self.private_key_file, passphrase.as_deref())?;
+
+        let warn_within = Duration::days(self.expiry_warning_days.into());
+        for warning in check_certificate_chain(&chain, &key, Utc::now(), warn_within)? {
+            warn!("{}: {}", self.certificate_chain_file.display(), warning);
+        }
+        let names = subject_alt_names(&chain[0])?.iter().map(ToString::to_string).collect::<Vec<_>>();
+        info!(
+            "Loaded {} certificate(s) from {}; subject alternative names: {}",
+            chain.len(),
+            self.certificate_chain_file.display(),
+            if names.is_empty() {
+                "none".to_string()
+            } else {
+                names.join(", ")
+            }
+        );
+
+        let builder = ServerConfig::builder().with_safe_defaults();
+        let builder = match &self.client_ca_file {
+            None => builder.with_no_client_auth(),
+            Some(client_ca_file) => {
+                let mut roots = RootCertStore::empty();
+                for certificate in read_certificate_chain(client_ca_file)? {
+                    roots.add(&certificate).map_err(|e| TlsError::InvalidCertificate(e.to_string()))?;
+                }
+                info!("Requiring client certificates issued by {} CA(s) in {}", roots.len(), client_ca_file.display());
+                builder.with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots))
+            }
+        };
+        let mut config = builder.with_single_cert(chain, key)?;
+        config.alpn_protocols = vec![b"h2".to_vec(), b"http/1.1".to_vec()];
+        Ok(config)
+    }
+}
+
+#[derive(Debug)]
+pub enum TlsError {
+    IO(IOError),
+    NoCertificates(PathBuf),
+    NoPrivateKey(PathBuf),
+    MultiplePrivateKeys(PathBuf),
+    PassphraseRequired(PathBuf),
+    Decrypt(String),
+    InvalidCertificate(String),
+    CertificateExpired {
+        subject: String,
+        not_after: String,
+    },
+    CertificateNotYetValid {
+        subject: String,
+        not_before: String,
+    },
+    KeyMismatch,
+    Rustls(RustlsError),
+}
+
+impl Error for TlsError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::IO(e) => Some(e),
+            Self::Rustls(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl Display for TlsError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::IO(e) => write!(f, "IO error: {e}"),
+            Self::NoCertificates(path) => write!(f, "No certificates found in {}", path.display()),
+            Self::NoPrivateKey(path) => write!(f, "No private key found in {}", path.display()),
+            Self::MultiplePrivateKeys(path) => write!(f, "More than one private key found in {}", path.display()),
+            Self::PassphraseRequired(path) => {
+                write!(f, "Private key in {} is encrypted but no passphrase was configured", path.display())
+            }
+            Self::Decrypt(e) => write!(f, "Unable to decrypt private key: {e}"),
+            Self::InvalidCertificate(e) => write!(f, "Invalid certificate: {e}"),
+            Self::CertificateExpired {
+                subject,
+                not_after,
+            } => write!(f, "Certificate {subject} expired at {not_after}"),
+            Self::CertificateNotYetValid {
+                subject,
+                not_before,
+            } => write!(f, "Certificate {subject} is not valid until {not_before}"),
+            Self::KeyMismatch => f.write_str("Private key does not match the certificate"),
+            Self::Rustls(e) => write!(f, "TLS error: {e}"),
+        }
+    }
+}
+
+impl From<IOError> for TlsError {
+    fn from(e: IOError) -> Self {
+        Self::IO(e)
+    }
+}
+
+impl From<RustlsError> for TlsError {
+    fn from(e: RustlsError) -> Self {
+        Self::Rustls(e)
+    }
+}
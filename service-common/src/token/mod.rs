@@ -0,0 +1,93 @@
+//! Session tokens, as encoded by [scratchstack_session_token], and the token keys stored in `iam_role_token_key`.
+mod validate;
+
+pub use {
+    self::validate::{validate_session_token, SessionTokenError, ValidateSessionTokens, X_AMZ_SECURITY_TOKEN},
+    scratchstack_session_token::{
+        decode_session_token, encode_session_token, inspect_envelope, EncodedSessionToken, EnvelopeHeader,
+        PackedClaims, SessionClaims, TokenAlgorithm, TokenError, TokenKey, TokenKeyRing, MAX_SESSION_TOKEN_LENGTH,
+        PACKED_POLICY_LIMIT, TOKEN_ENVELOPE_VERSION, TOKEN_MAGIC,
+    },
+};
+
+use {
+    chrono::{DateTime, NaiveDateTime, Utc},
+    log::{error, info},
+    scratchstack_session_token::decode_hex,
+    sqlx::{
+        any::{AnyKind, AnyPool},
+        Row,
+    },
+    std::{
+        sync::{Arc, RwLock},
+        time::Duration,
+    },
+    tokio::task::JoinHandle,
+};
+
+/// Timestamps are read from the database as text, as in [SqlStore][crate::store::SqlStore].
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S%.f";
+
+/// Read all unexpired keys from `iam_role_token_key`.
+pub async fn load_token_keys(pool: &AnyPool) -> Result<TokenKeyRing, TokenError> {
+    let sql = match pool.any_kind() {
+        AnyKind::Postgres => "SELECT access_key_id, encryption_algorithm, encode(encryption_key, 'hex') AS encryption_key, CAST(valid_at AS TEXT) AS valid_at, CAST(expires_at AS TEXT) AS expires_at FROM iam.iam_role_token_key",
+        _ => "SELECT access_key_id, encryption_algorithm, hex(encryption_key) AS encryption_key, valid_at, expires_at FROM iam_role_token_key",
+    };
+
+    let now = Utc::now();
+    let mut keys = Vec::new();
+    let rows = sqlx::query(sql).fetch_all(pool).await.map_err(|e| TokenError::KeyStore(e.into()))?;
+    for row in rows {
+        let get = |column: &str| row.try_get::<String, _>(column).map_err(|e| TokenError::KeyStore(e.into()));
+        let key_id = get("access_key_id")?;
+        let algorithm = get("encryption_algorithm")?;
+        let algorithm = TokenAlgorithm::from_name(&algorithm)
+            .ok_or_else(|| TokenError::Invalid(format!("unknown algorithm {algorithm} for token key {key_id}")))?;
+        let key = decode_hex(&get("encryption_key")?)
+            .ok_or_else(|| TokenError::Invalid(format!("invalid token key {key_id}")))?;
+        let valid_at = parse_timestamp(&get("valid_at")?)?;
+        let expires_at = parse_timestamp(&get("expires_at")?)?;
+
+        if expires_at > now {
+            keys.push(TokenKey {
+                key_id: key_id.trim_end().to_string(),
+                algorithm,
+                key,
+                valid_at,
+                expires_at,
+            });
+        }
+    }
+
+    Ok(TokenKeyRing::new(keys))
+}
+
+/// Reload the token keys from the database every `interval`, so keys added for a rotation are picked up without a
+/// restart.
+pub fn spawn_token_key_refresh(
+    pool: Arc<AnyPool>,
+    keys: Arc<RwLock<TokenKeyRing>>,
+    interval: Duration,
+) -> JoinHandle<()> {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(interval);
+        loop {
+            interval.tick().await;
+            match load_token_keys(&pool).await {
+                Ok(ring) => {
+                    let count = ring.keys().len();
+                    *keys.write().expect("token key ring poisoned") = ring;
+                    info!("Loaded {} session token key(s)", count);
+                }
+                Err(e) => error!("Unable to reload session token keys: {}", e),
+            }
+        }
+    })
+}
+
+fn parse_timestamp(value: &str) -> Result<DateTime<Utc>, TokenError> {
+    let timestamp = NaiveDateTime::parse_from_str(value, TIMESTAMP_FORMAT)
+        .map_err(|e| TokenError::Invalid(format!("invalid timestamp {value}: {e}")))?;
+    Ok(DateTime::from_utc(timestamp, Utc))
+}
@@ -0,0 +1,527 @@
+//! Evaluation of role trust policies for AssumeRole.
+//!
+//! A trust policy names the principals that may assume a role, optionally with conditions. Only the parts of the
+//! policy language that apply to trust policies are supported: `Principal` with `AWS` entries (account ids,
+//! account root ARNs, role and user ARNs, or `*`) and `Federated` entries (OIDC provider ARNs, matched only by
+//! AssumeRoleWithWebIdentity), `Action`, and `StringEquals`, `StringNotEquals`, `StringLike`, `StringNotLike` and
+//! `Null` conditions.
+//!
+//! This only checks the role's side of the trust. A caller from another account also needs its own account to
+//! allow `sts:AssumeRole`; identity policies are not evaluated here.
+use {
+    crate::{
+        actions::STS_ROLE_SESSION_NAME,
+        authz::Decision,
+        engine::action_matches,
+        session_keys::{
+            get_string, AWS_FEDERATED_PROVIDER, AWS_PRINCIPAL_ACCOUNT, AWS_PRINCIPAL_ARN, AWS_SOURCE_IDENTITY,
+        },
+    },
+    scratchstack_arn::Arn,
+    scratchstack_aws_principal::SessionData,
+    serde_json::Value,
+    std::{
+        collections::HashMap,
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+/// The condition key for the `SourceIdentity` parameter of AssumeRole.
+pub const STS_SOURCE_IDENTITY: &str = "sts:SourceIdentity";
+
+/// The condition key for the `ExternalId` parameter of AssumeRole.
+pub const STS_EXTERNAL_ID: &str = "sts:ExternalId";
+
+/// The condition operators that policies evaluated in Scratchstack may use.
+pub(crate) const CONDITION_OPERATORS: &[&str] =
+    &["StringEquals", "StringNotEquals", "StringLike", "StringNotLike", "Null"];
+
+/// A parsed trust policy.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TrustPolicy {
+    statements: Vec<TrustStatement>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+struct TrustStatement {
+    /// The `Sid`, or the statement index if there is none.
+    id: String,
+    allow: bool,
+    principals: Vec<String>,
+    federated: Vec<String>,
+    actions: Vec<String>,
+
+    /// (operator, key, values); keys are lowercased.
+    conditions: Vec<(String, String, Vec<String>)>,
+}
+
+/// The parts of an AssumeRole request that a trust policy is evaluated against.
+#[derive(Clone, Debug)]
+pub struct TrustRequest<'a> {
+    pub action: &'a str,
+    pub caller: &'a Arn,
+    pub external_id: Option<&'a str>,
+
+    /// The `RoleSessionName` parameter, available to conditions as `sts:RoleSessionName`.
+    pub role_session_name: Option<&'a str>,
+
+    /// The source identity the new session will carry; see [resolve_source_identity].
+    pub source_identity: Option<&'a str>,
+
+    /// For AssumeRoleWithWebIdentity, the condition keys of the identity token, e.g. `<provider>:sub`. The caller is
+    /// then the identity provider, and matches only `Federated` principals.
+    pub web_identity: Option<&'a [(String, String)]>,
+}
+
+impl TrustPolicy {
+    pub fn parse(document: &str) -> Result<Self, TrustError> {
+        let value: Value = serde_json::from_str(document).map_err(|e| TrustError::MalformedPolicy(e.to_string()))?;
+        let statements = match value.get("Statement") {
+            Some(Value::Array(statements)) => statements.iter().collect(),
+            Some(statement @ Value::Object(_)) => vec![statement],
+            _ => return Err(TrustError::MalformedPolicy("Missing required field Statement".to_string())),
+        };
+
+        let mut parsed = Vec::with_capacity(statements.len());
+        for (index, statement) in statements.into_iter().enumerate() {
+            parsed.push(TrustStatement::parse(statement, index)?);
+        }
+
+        Ok(Self {
+            statements: parsed,
+        })
+    }
+
+    /// Evaluate the policy. An explicit deny overrides any allow; if no statement applies, the result is an implicit
+    /// deny.
+    pub fn evaluate(&self, request: &TrustRequest) -> Decision {
+        let context = condition_context(request);
+        let mut allowed = false;
+
+        for statement in &self.statements {
+            if !statement.matches(request, &context) {
+                continue;
+            }
+
+            if !statement.allow {
+                return Decision::ExplicitDeny {
+                    policy: "trust policy".to_string(),
+                    statement: statement.id.clone(),
+                };
+            }
+
+            allowed = true;
+        }
+
+        if allowed {
+            Decision::Allow
+        } else {
+            Decision::ImplicitDeny
+        }
+    }
+}
+
+impl TrustStatement {
+    fn parse(statement: &Value, index: usize) -> Result<Self, TrustError> {
+        let id =
+            statement.get("Sid").and_then(Value::as_str).map(ToString::to_string).unwrap_or_else(|| index.to_string());
+        let allow = match statement.get("Effect").and_then(Value::as_str) {
+            Some("Allow") => true,
+            Some("Deny") => false,
+            _ => return Err(TrustError::MalformedPolicy(format!("Invalid Effect in statement {id}"))),
+        };
+
+        if statement.get("NotPrincipal").is_some() || statement.get("NotAction").is_some() {
+            return Err(TrustError::MalformedPolicy(format!(
+                "NotPrincipal and NotAction are not supported in trust policies (statement {id})"
+            )));
+        }
+
+        let (principals, federated) = match statement.get("Principal") {
+            Some(Value::String(star)) if star == "*" => (vec!["*".to_string()], vec!["*".to_string()]),
+            Some(Value::Object(principal)) => {
+                let entries = |key: &str| match principal.get(key) {
+                    Some(value) => strings(value, &id),
+                    None => Ok(Vec::new()),
+                };
+                (entries("AWS")?, entries("Federated")?)
+            }
+            _ => return Err(TrustError::MalformedPolicy(format!("Missing Principal in statement {id}"))),
+        };
+
+        let actions = match statement.get("Action") {
+            Some(action) => strings(action, &id)?,
+            None => return Err(TrustError::MalformedPolicy(format!("Missing Action in statement {id}"))),
+        };
+
+        let mut conditions = Vec::new();
+        if let Some(condition) = statement.get("Condition") {
+            let operators = condition
+                .as_object()
+                .ok_or_else(|| TrustError::MalformedPolicy(format!("Invalid Condition in statement {id}")))?;
+            for (operator, keys) in operators {
+                if !CONDITION_OPERATORS.contains(&operator.as_str()) {
+                    return Err(TrustError::MalformedPolicy(format!("Unsupported condition operator {operator}")));
+                }
+
+                let keys = keys
+                    .as_object()
+                    .ok_or_else(|| TrustError::MalformedPolicy(format!("Invalid Condition in statement {id}")))?;
+                for (key, values) in keys {
+                    conditions.push((operator.clone(), key.to_lowercase(), strings(values, &id)?));
+                }
+            }
+        }
+
+        Ok(Self {
+            id,
+            allow,
+            principals,
+            federated,
+            actions,
+            conditions,
+        })
+    }
+
+    fn matches(&self, request: &TrustRequest, context: &HashMap<String, String>) -> bool {
+        let principal_matches = match request.web_identity {
+            None => self.principals.iter().any(|principal| principal_matches(principal, request.caller)),
+            Some(_) => {
+                let provider = request.caller.to_string();
+                self.federated.iter().any(|federated| federated == "*" || *federated == provider)
+            }
+        };
+
+        self.actions.iter().any(|action| action_matches(action, request.action))
+            && principal_matches
+            && self
+                .conditions
+                .iter()
+                .all(|(operator, key, values)| condition_matches(operator, context.get(key), values))
+    }
+}
+
+/// A string or array of strings.
+fn strings(value: &Value, statement: &str) -> Result<Vec<String>, TrustError> {
+    string_values(value).ok_or_else(|| {
+        TrustError::MalformedPolicy(format!("Expected a string or list of strings in statement {statement}"))
+    })
+}
+
+/// The values of a policy element that may be a single string or an array of strings. Booleans, as some condition
+/// values are written, are converted to strings.
+pub(crate) fn string_values(value: &Value) -> Option<Vec<String>> {
+    let scalar = |value: &Value| match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    };
+
+    match value {
+        Value::Array(values) => values.iter().map(scalar).collect(),
+        value => scalar(value).map(|s| vec![s]),
+    }
+}
+
+/// Returns whether a `Principal` `AWS` entry matches the caller.
+///
+/// An account id or account root ARN matches every principal in the account. A role ARN also matches sessions of
+/// that role.
+fn principal_matches(principal: &str, caller: &Arn) -> bool {
+    if principal == "*" {
+        return true;
+    }
+
+    if principal.len() == 12 && principal.bytes().all(|b| b.is_ascii_digit()) {
+        return principal == caller.account_id();
+    }
+
+    let principal: Arn = match principal.parse() {
+        Ok(arn) => arn,
+        Err(_) => return false,
+    };
+
+    if principal.partition() != caller.partition() || principal.account_id() != caller.account_id() {
+        return false;
+    }
+
+    if principal.service() == "iam" && principal.resource() == "root" {
+        return true;
+    }
+
+    if principal.service() == caller.service() && principal.resource() == caller.resource() {
+        return true;
+    }
+
+    // arn:aws:iam::<account>:role/<path>/<name> matches arn:aws:sts::<account>:assumed-role/<name>/<session>.
+    match (principal.resource().strip_prefix("role/"), caller.resource().strip_prefix("assumed-role/")) {
+        (Some(role), Some(session)) if caller.service() == "sts" => {
+            let role_name = role.rsplit('/').next().unwrap_or(role);
+            session.split('/').next() == Some(role_name)
+        }
+        _ => false,
+    }
+}
+
+fn condition_context(request: &TrustRequest) -> HashMap<String, String> {
+    let mut context = HashMap::new();
+    context.insert(AWS_PRINCIPAL_ACCOUNT.to_lowercase(), request.caller.account_id().to_string());
+    context.insert(AWS_PRINCIPAL_ARN.to_lowercase(), request.caller.to_string());
+    if let Some(external_id) = request.external_id {
+        context.insert(STS_EXTERNAL_ID.to_lowercase(), external_id.to_string());
+    }
+    if let Some(role_session_name) = request.role_session_name {
+        context.insert(STS_ROLE_SESSION_NAME.to_lowercase(), role_session_name.to_string());
+    }
+    if let Some(source_identity) = request.source_identity {
+        context.insert(STS_SOURCE_IDENTITY.to_lowercase(), source_identity.to_string());
+    }
+    if let Some(web_identity) = request.web_identity {
+        context.insert(AWS_FEDERATED_PROVIDER.to_lowercase(), request.caller.to_string());
+        for (key, value) in web_identity {
+            context.insert(key.to_lowercase(), value.clone());
+        }
+    }
+    context
+}
+
+pub(crate) fn condition_matches(operator: &str, value: Option<&String>, expected: &[String]) -> bool {
+    match (operator, value) {
+        ("Null", value) => expected.iter().any(|e| (e == "true") == value.is_none()),
+
+        // As in AWS, a negated operator matches when the key is absent.
+        ("StringNotEquals" | "StringNotLike", None) => true,
+        (_, None) => false,
+        ("StringEquals", Some(value)) => expected.iter().any(|e| e == value),
+        ("StringNotEquals", Some(value)) => expected.iter().all(|e| e != value),
+        ("StringLike", Some(value)) => expected.iter().any(|e| wildcard_match(e, value)),
+        ("StringNotLike", Some(value)) => expected.iter().all(|e| !wildcard_match(e, value)),
+        _ => false,
+    }
+}
+
+/// Match `value` against `pattern`, where `*` matches any sequence of characters and `?` any single character.
+pub(crate) fn wildcard_match(pattern: &str, value: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let value: Vec<char> = value.chars().collect();
+    let (mut p, mut v) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while v < value.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == value[v]) {
+            p += 1;
+            v += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, v));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            v = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Determine the source identity of a new role session.
+///
+/// Once set, a source identity is carried through role chaining and cannot be changed: a session whose caller has
+/// one inherits it, and requesting a different value is denied.
+pub fn resolve_source_identity(caller: &SessionData, requested: Option<&str>) -> Result<Option<String>, TrustError> {
+    if let Some(requested) = requested {
+        validate_source_identity(requested)?;
+    }
+
+    // A source identity that cannot be read cannot be carried forward, so the new session is refused rather than
+    // issued without it.
+    let existing = get_string(caller, AWS_SOURCE_IDENTITY).map_err(|_| TrustError::SourceIdentityChanged)?;
+
+    match (existing, requested) {
+        (Some(existing), Some(requested)) if existing != requested => Err(TrustError::SourceIdentityChanged),
+        (Some(existing), _) => Ok(Some(existing.to_string())),
+        (None, requested) => Ok(requested.map(ToString::to_string)),
+    }
+}
+
+fn validate_source_identity(source_identity: &str) -> Result<(), TrustError> {
+    let valid_chars = source_identity.chars().all(|c| c.is_ascii_alphanumeric() || "_+=,.@-".contains(c));
+    if !(2..=64).contains(&source_identity.len()) || !valid_chars {
+        return Err(TrustError::InvalidSourceIdentity(source_identity.to_string()));
+    }
+
+    if source_identity.to_lowercase().starts_with("aws:") {
+        return Err(TrustError::InvalidSourceIdentity(source_identity.to_string()));
+    }
+
+    Ok(())
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum TrustError {
+    MalformedPolicy(String),
+    InvalidSourceIdentity(String),
+
+    /// The caller's session carries a source identity and a different one was requested.
+    SourceIdentityChanged,
+}
+
+impl TrustError {
+    /// The AWS error code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::MalformedPolicy(_) => "MalformedPolicyDocument",
+            Self::InvalidSourceIdentity(_) => "ValidationError",
+            Self::SourceIdentityChanged => "AccessDenied",
+        }
+    }
+}
+
+impl Error for TrustError {}
+
+impl Display for TrustError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::MalformedPolicy(e) => write!(f, "Malformed trust policy: {e}"),
+            Self::InvalidSourceIdentity(value) => write!(
+                f,
+                "1 validation error detected: Value '{value}' at 'sourceIdentity' failed to satisfy constraint: Member must satisfy regular expression pattern: [\\w+=,.@-]*"
+            ),
+            Self::SourceIdentityChanged => {
+                f.write_str("The source identity of a role session cannot be changed when chaining roles.")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{resolve_source_identity, wildcard_match, TrustError, TrustPolicy, TrustRequest, AWS_SOURCE_IDENTITY},
+        crate::authz::Decision,
+        pretty_assertions::assert_eq,
+        scratchstack_arn::Arn,
+        scratchstack_aws_principal::{SessionData, SessionValue},
+    };
+
+    const POLICY: &str = r#"{
+        "Version": "2012-10-17",
+        "Statement": [
+            {
+                "Sid": "Partner",
+                "Effect": "Allow",
+                "Principal": {"AWS": ["210987654321", "arn:aws:iam::123456789012:role/ops/deploy"]},
+                "Action": "sts:AssumeRole",
+                "Condition": {"StringEquals": {"sts:ExternalId": "partner-7"}}
+            },
+            {
+                "Sid": "NoContractors",
+                "Effect": "Deny",
+                "Principal": {"AWS": "*"},
+                "Action": "sts:*",
+                "Condition": {"StringLike": {"aws:PrincipalArn": "*:user/contractors/*"}}
+            }
+        ]
+    }"#;
+
+    fn request<'a>(caller: &'a Arn, external_id: Option<&'a str>) -> TrustRequest<'a> {
+        TrustRequest {
+            action: "sts:AssumeRole",
+            caller,
+            external_id,
+            role_session_name: None,
+            source_identity: None,
+            web_identity: None,
+        }
+    }
+
+    #[test_log::test]
+    fn test_evaluate() {
+        let policy = TrustPolicy::parse(POLICY).unwrap();
+
+        let partner: Arn = "arn:aws:iam::210987654321:user/alice".parse().unwrap();
+        assert_eq!(policy.evaluate(&request(&partner, Some("partner-7"))), Decision::Allow);
+        assert_eq!(policy.evaluate(&request(&partner, Some("partner-8"))), Decision::ImplicitDeny);
+        assert_eq!(policy.evaluate(&request(&partner, None)), Decision::ImplicitDeny);
+
+        let session: Arn = "arn:aws:sts::123456789012:assumed-role/deploy/ci".parse().unwrap();
+        assert_eq!(policy.evaluate(&request(&session, Some("partner-7"))), Decision::Allow);
+
+        let other: Arn = "arn:aws:iam::111111111111:user/alice".parse().unwrap();
+        assert_eq!(policy.evaluate(&request(&other, Some("partner-7"))), Decision::ImplicitDeny);
+
+        let contractor: Arn = "arn:aws:iam::210987654321:user/contractors/bob".parse().unwrap();
+        assert!(matches!(
+            policy.evaluate(&request(&contractor, Some("partner-7"))),
+            Decision::ExplicitDeny { statement, .. } if statement == "NoContractors"
+        ));
+
+        assert!(TrustPolicy::parse(r#"{"Statement": [{"Effect": "Allow"}]}"#).is_err());
+
+        let named = TrustPolicy::parse(
+            r#"{"Statement": {"Effect": "Allow", "Principal": {"AWS": "*"}, "Action": "sts:AssumeRole",
+                "Condition": {"StringLike": {"sts:RoleSessionName": "ci-*"}}}}"#,
+        )
+        .unwrap();
+        let mut ci = request(&partner, None);
+        assert_eq!(named.evaluate(&ci), Decision::ImplicitDeny);
+        ci.role_session_name = Some("ci-1234");
+        assert_eq!(named.evaluate(&ci), Decision::Allow);
+        ci.role_session_name = Some("alice");
+        assert_eq!(named.evaluate(&ci), Decision::ImplicitDeny);
+    }
+
+    #[test_log::test]
+    fn test_web_identity() {
+        let policy = TrustPolicy::parse(
+            r#"{"Statement": {"Effect": "Allow", "Action": "sts:AssumeRoleWithWebIdentity",
+                "Principal": {"Federated": "arn:aws:iam::123456789012:oidc-provider/oidc.example.com"},
+                "Condition": {"StringLike": {"oidc.example.com:sub": "system:serviceaccount:ci:*"}}}}"#,
+        )
+        .unwrap();
+        let provider: Arn = "arn:aws:iam::123456789012:oidc-provider/oidc.example.com".parse().unwrap();
+        let claims = vec![("oidc.example.com:sub".to_string(), "system:serviceaccount:ci:deploy".to_string())];
+        let mut request = TrustRequest {
+            action: "sts:AssumeRoleWithWebIdentity",
+            web_identity: Some(&claims),
+            ..request(&provider, None)
+        };
+        assert_eq!(policy.evaluate(&request), Decision::Allow);
+
+        let other = vec![("oidc.example.com:sub".to_string(), "system:serviceaccount:prod:deploy".to_string())];
+        request.web_identity = Some(&other);
+        assert_eq!(policy.evaluate(&request), Decision::ImplicitDeny);
+
+        // Federated principals are not matched by signed callers, and AWS principals are not matched by tokens.
+        request.web_identity = None;
+        assert_eq!(policy.evaluate(&request), Decision::ImplicitDeny);
+        let aws =
+            TrustPolicy::parse(r#"{"Statement": {"Effect": "Allow", "Principal": {"AWS": "*"}, "Action": "sts:*"}}"#)
+                .unwrap();
+        request.web_identity = Some(&claims);
+        assert_eq!(aws.evaluate(&request), Decision::ImplicitDeny);
+    }
+
+    #[test_log::test]
+    fn test_source_identity() {
+        let mut caller = SessionData::new();
+        assert_eq!(resolve_source_identity(&caller, None).unwrap(), None);
+        assert_eq!(resolve_source_identity(&caller, Some("alice")).unwrap(), Some("alice".to_string()));
+        assert!(matches!(resolve_source_identity(&caller, Some("aws:x")), Err(TrustError::InvalidSourceIdentity(_))));
+
+        caller.insert(AWS_SOURCE_IDENTITY, SessionValue::String("alice".to_string()));
+        assert_eq!(resolve_source_identity(&caller, None).unwrap(), Some("alice".to_string()));
+        assert_eq!(resolve_source_identity(&caller, Some("bob")).unwrap_err(), TrustError::SourceIdentityChanged);
+    }
+
+    #[test_log::test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("sts:*", "sts:assumerole"));
+        assert!(wildcard_match("a?c*", "abcdef"));
+        assert!(!wildcard_match("a?c", "abcd"));
+        assert!(wildcard_match("*", ""));
+    }
+}
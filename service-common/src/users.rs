@@ -0,0 +1,483 @@
+//! IAM user operations, written against [ControlPlaneStore].
+//!
+//! These follow the AWS behaviors that clients depend on. Terraform's AWS provider, in particular, calls `GetUser`
+//! without a user name to find out who it is running as, and distinguishes a deleted resource from other failures
+//! by the `NoSuchEntity` code and 404 status.
+//!
+//! `ListUsers` pages through users in the order the store lists them, by lowercase name. The marker returned with a
+//! truncated page is the lowercase name of the next user, so a page starts in the right place even if users were
+//! created or deleted since the previous one.
+use {
+    crate::{
+        context::RequestContext,
+        ids::{unique_id, USER_ID_PREFIX},
+        operation::{output::query_response, ValidationError},
+        operation_input, operation_output,
+        protocol::IAM_XML_NS,
+        store::{ControlPlaneStore, EntityKind, StoreError, Tag, User},
+        tags::{response_tags, validate_tags, TagError},
+    },
+    chrono::{DateTime, Utc},
+    http::StatusCode,
+    std::{
+        error::Error,
+        fmt::{Display, Formatter, Result as FmtResult},
+    },
+};
+
+operation_input! {
+    /// Input for the CreateUser operation.
+    pub struct CreateUserInput {
+        "UserName" => pub user_name: String where length(1, 64), pattern(r"[\w+=,.@-]+"),
+        "Path" => pub path: Option<String> where length(1, 512), pattern(r"(/)|(/[\x21-\x7e]+/)"),
+        "Tags" => pub tags: Vec<Tag>,
+    }
+}
+
+operation_input! {
+    /// Input for the GetUser operation. Without a user name, the calling user is returned.
+    pub struct GetUserInput {
+        "UserName" => pub user_name: Option<String> where length(1, 128), pattern(r"[\w+=,.@-]+"),
+    }
+}
+
+operation_input! {
+    /// Input for the DeleteUser operation.
+    pub struct DeleteUserInput {
+        "UserName" => pub user_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+    }
+}
+
+operation_input! {
+    /// Input for the ListUsers operation.
+    pub struct ListUsersInput {
+        "PathPrefix" => pub path_prefix: Option<String> where length(1, 512), pattern(r"/[\x21-\x7e]*"),
+        "Marker" => pub marker: Option<String> where length(1, 320),
+        "MaxItems" => pub max_items: Option<i64> where range(1, 1000),
+    }
+}
+
+operation_input! {
+    /// Input for the UpdateUser operation.
+    pub struct UpdateUserInput {
+        "UserName" => pub user_name: String where length(1, 128), pattern(r"[\w+=,.@-]+"),
+        "NewUserName" => pub new_user_name: Option<String> where length(1, 64), pattern(r"[\w+=,.@-]+"),
+        "NewPath" => pub new_path: Option<String> where length(1, 512), pattern(r"(/)|(/[\x21-\x7e]+/)"),
+    }
+}
+
+/// Errors from user operations.
+#[derive(Debug)]
+pub enum UserError {
+    Validation(ValidationError),
+
+    /// No user name was given and the caller is not an IAM user.
+    UserNameRequired,
+    Tag(TagError),
+    Store(StoreError),
+}
+
+impl UserError {
+    /// The IAM error code for this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Validation(e) => e.code(),
+            Self::UserNameRequired => "ValidationError",
+            Self::Tag(e) => e.code(),
+            Self::Store(e) => e.code(),
+        }
+    }
+
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Self::Validation(_) | Self::UserNameRequired => StatusCode::BAD_REQUEST,
+            Self::Tag(e) => e.status(),
+            Self::Store(e) => e.status(),
+        }
+    }
+}
+
+impl Error for UserError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Validation(e) => Some(e),
+            Self::UserNameRequired => None,
+            Self::Tag(e) => Some(e),
+            Self::Store(e) => Some(e),
+        }
+    }
+}
+
+impl Display for UserError {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Validation(e) => write!(f, "{e}"),
+            Self::UserNameRequired => f.write_str("Must specify userName when calling with non-User credentials"),
+            Self::Tag(e) => write!(f, "{e}"),
+            Self::Store(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<ValidationError> for UserError {
+    fn from(e: ValidationError) -> Self {
+        Self::Validation(e)
+    }
+}
+
+impl From<TagError> for UserError {
+    fn from(e: TagError) -> Self {
+        Self::Tag(e)
+    }
+}
+
+impl From<StoreError> for UserError {
+    fn from(e: StoreError) -> Self {
+        Self::Store(e)
+    }
+}
+
+/// A newly created user and the tags it was created with.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct CreateUserOutput {
+    pub user: User,
+    pub tags: Vec<Tag>,
+}
+
+impl CreateUserOutput {
+    /// The `CreateUserResponse` body.
+    pub fn to_xml(&self, partition: &str, request_id: &str) -> String {
+        let result = UserResult {
+            user: UserElement::new(&self.user, partition, &self.tags),
+        };
+        query_response(IAM_XML_NS, "CreateUser", &result, request_id)
+    }
+}
+
+/// A user and its tags, as returned by GetUser.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct GetUserOutput {
+    pub user: User,
+    pub tags: Vec<Tag>,
+}
+
+impl GetUserOutput {
+    /// The `GetUserResponse` body.
+    pub fn to_xml(&self, partition: &str, request_id: &str) -> String {
+        let result = UserResult {
+            user: UserElement::new(&self.user, partition, &self.tags),
+        };
+        query_response(IAM_XML_NS, "GetUser", &result, request_id)
+    }
+}
+
+/// The number of users returned by ListUsers when `MaxItems` is not given.
+pub const DEFAULT_LIST_USERS_MAX_ITEMS: usize = 100;
+
+/// One page of users, as returned by ListUsers.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ListUsersOutput {
+    pub users: Vec<User>,
+
+    /// If the list is truncated, the marker to pass for the next page.
+    pub marker: Option<String>,
+}
+
+impl ListUsersOutput {
+    /// The `ListUsersResponse` body. As in AWS, tags are not included.
+    pub fn to_xml(&self, partition: &str, request_id: &str) -> String {
+        let result = ListUsersResult {
+            users: self.users.iter().map(|user| UserElement::new(user, partition, &[])).collect(),
+            is_truncated: self.marker.is_some(),
+            marker: self.marker.as_deref(),
+        };
+        query_response(IAM_XML_NS, "ListUsers", &result, request_id)
+    }
+}
+
+operation_output! {
+    /// A user, with the children in the order AWS writes them.
+    struct UserElement<'a> {
+        "Path" => path: &'a str,
+        "UserName" => user_name: &'a str,
+        "UserId" => user_id: &'a str,
+        "Arn" => arn: String,
+        "CreateDate" => create_date: DateTime<Utc>,
+        "Tags" => tags: Option<&'a [Tag]>,
+    }
+}
+
+impl<'a> UserElement<'a> {
+    fn new(user: &'a User, partition: &str, tags: &'a [Tag]) -> Self {
+        Self {
+            path: &user.path,
+            user_name: &user.user_name,
+            user_id: &user.user_id,
+            arn: format!("arn:{partition}:iam::{}:user{}{}", user.account_id, user.path, user.user_name),
+            create_date: user.created_at,
+            tags: response_tags(tags),
+        }
+    }
+}
+
+operation_output! {
+    /// The result of CreateUser and GetUser.
+    struct UserResult<'a> {
+        "User" => user: UserElement<'a>,
+    }
+}
+
+operation_output! {
+    struct ListUsersResult<'a> {
+        "Users" => users: Vec<UserElement<'a>>,
+        "IsTruncated" => is_truncated: bool,
+        "Marker" => marker: Option<&'a str>,
+    }
+}
+
+/// The name of the calling IAM user, or `None` if the caller is not an IAM user.
+pub fn caller_user_name(context: &RequestContext) -> Option<String> {
+    let arn = context.caller_arn()?;
+    if arn.service() != "iam" {
+        return None;
+    }
+
+    // user/<path>/<name>
+    let user = arn.resource().strip_prefix("user/")?;
+    user.rsplit('/').next().map(ToString::to_string)
+}
+
+/// Create a user in the caller's account with the tags given, which are validated before anything is stored.
+pub async fn create_user(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    input: &CreateUserInput,
+) -> Result<CreateUserOutput, UserError> {
+    validate_tags(&input.tags)?;
+
+    let user = User {
+        user_id: unique_id(USER_ID_PREFIX),
+        account_id: context.account_id().unwrap_or_default(),
+        user_name: input.user_name.clone(),
+        path: input.path.clone().unwrap_or_else(|| "/".to_string()),
+        permissions_boundary: None,
+        created_at: Utc::now(),
+    };
+    store.create_user(&user, &input.tags).await?;
+
+    Ok(CreateUserOutput {
+        user,
+        tags: input.tags.clone(),
+    })
+}
+
+/// Look up a user and its tags in the caller's account, defaulting to the caller.
+pub async fn get_user(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    input: &GetUserInput,
+) -> Result<GetUserOutput, UserError> {
+    let user_name = match &input.user_name {
+        Some(user_name) => user_name.clone(),
+        None => caller_user_name(context).ok_or(UserError::UserNameRequired)?,
+    };
+
+    let account_id = context.account_id().unwrap_or_default();
+    let user = store.get_user(&account_id, &user_name).await?;
+    let tags = store.list_tags(EntityKind::User, &user.user_id).await?;
+    Ok(GetUserOutput {
+        user,
+        tags,
+    })
+}
+
+/// List one page of the users in the caller's account whose path starts with the prefix given.
+pub async fn list_users(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    input: &ListUsersInput,
+) -> Result<ListUsersOutput, UserError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let users = store.list_users(&account_id, input.path_prefix.as_deref().unwrap_or("/")).await?;
+    let start = match &input.marker {
+        Some(marker) => users.iter().take_while(|user| user.user_name.to_lowercase() < *marker).count(),
+        None => 0,
+    };
+    let max_items = input.max_items.map(|max_items| max_items as usize).unwrap_or(DEFAULT_LIST_USERS_MAX_ITEMS);
+
+    let mut users = users.into_iter().skip(start);
+    let page: Vec<User> = users.by_ref().take(max_items).collect();
+    Ok(ListUsersOutput {
+        users: page,
+        marker: users.next().map(|user| user.user_name.to_lowercase()),
+    })
+}
+
+/// Delete a user. As in AWS, a user with access keys, group memberships, or policies cannot be deleted until they
+/// are removed, and fails with `DeleteConflict`.
+pub async fn delete_user(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    input: &DeleteUserInput,
+) -> Result<(), UserError> {
+    let account_id = context.account_id().unwrap_or_default();
+    Ok(store.delete_user(&account_id, &input.user_name).await?)
+}
+
+/// Rename a user or change its path. Giving neither a new name nor a new path is allowed and changes nothing, as in
+/// AWS.
+pub async fn update_user(
+    store: &dyn ControlPlaneStore,
+    context: &RequestContext,
+    input: &UpdateUserInput,
+) -> Result<User, UserError> {
+    let account_id = context.account_id().unwrap_or_default();
+    Ok(store
+        .update_user(&account_id, &input.user_name, input.new_user_name.as_deref(), input.new_path.as_deref())
+        .await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{
+            create_user, delete_user, get_user, list_users, update_user, CreateUserInput, DeleteUserInput,
+            GetUserInput, ListUsersInput, UpdateUserInput, UserError,
+        },
+        crate::{
+            context::RequestContext,
+            operation::{parameters, FromParameters},
+            store::{ControlPlaneStore, EntityKind, MemoryStore, Tag, User},
+        },
+        chrono::Utc,
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{Principal, PrincipalIdentity, User as PrincipalUser},
+        std::collections::HashMap,
+    };
+
+    #[test_log::test(tokio::test)]
+    async fn test_get_and_update_user() {
+        let store = MemoryStore::new();
+        store
+            .create_user(
+                &User {
+                    user_id: "AIDAEXAMPLEUSER1".to_string(),
+                    account_id: "123456789012".to_string(),
+                    user_name: "Alice".to_string(),
+                    path: "/".to_string(),
+                    permissions_boundary: None,
+                    created_at: Utc::now(),
+                },
+                &[],
+            )
+            .await
+            .unwrap();
+
+        let alice = PrincipalUser::new("aws", "123456789012", "/", "Alice").unwrap();
+        let context =
+            RequestContext::builder().principal(Principal::from(vec![PrincipalIdentity::from(alice)])).build().unwrap();
+
+        // Without a user name, GetUser returns the caller.
+        let input = GetUserInput::from_parameters(&HashMap::new()).unwrap();
+        assert_eq!(get_user(&store, &context, &input).await.unwrap().user.user_id, "AIDAEXAMPLEUSER1");
+
+        let anonymous = RequestContext::builder().principal(Principal::from(vec![])).build().unwrap();
+        let e = get_user(&store, &anonymous, &input).await.unwrap_err();
+        assert!(matches!(e, UserError::UserNameRequired));
+        assert_eq!(e.code(), "ValidationError");
+
+        let input =
+            UpdateUserInput::from_parameters(&parameters(&[("UserName", "alice"), ("NewPath", "/admins/")])).unwrap();
+        assert_eq!(update_user(&store, &context, &input).await.unwrap().path, "/admins/");
+
+        let input =
+            UpdateUserInput::from_parameters(&parameters(&[("UserName", "bob"), ("NewUserName", "Carol")])).unwrap();
+        let e = update_user(&store, &context, &input).await.unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("NoSuchEntity", 404));
+
+        let e =
+            UpdateUserInput::from_parameters(&parameters(&[("UserName", "alice"), ("NewPath", "admins")])).unwrap_err();
+        assert_eq!(e.violations()[0].name(), "NewPath");
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_create_user_with_tags() {
+        let store = MemoryStore::new();
+        let root = PrincipalUser::new("aws", "123456789012", "/", "Admin").unwrap();
+        let context =
+            RequestContext::builder().principal(Principal::from(vec![PrincipalIdentity::from(root)])).build().unwrap();
+
+        let input = CreateUserInput::from_parameters(&parameters(&[
+            ("UserName", "Bob"),
+            ("Path", "/engineering/"),
+            ("Tags.member.1.Key", "Team"),
+            ("Tags.member.1.Value", "Platform"),
+            ("Tags.member.2.Key", "Cost-Center"),
+            ("Tags.member.2.Value", "1234"),
+        ]))
+        .unwrap();
+        let output = create_user(&store, &context, &input).await.unwrap();
+        assert!(output.user.user_id.starts_with("AIDA"));
+        assert_eq!(output.tags.len(), 2);
+
+        // Tags are stored with the user, ordered by key, and come back in the response in the order given.
+        let stored = store.list_tags(EntityKind::User, &output.user.user_id).await.unwrap();
+        assert_eq!(stored.iter().map(|tag| tag.key.as_str()).collect::<Vec<_>>(), vec!["Cost-Center", "Team"]);
+        let xml = output.to_xml("aws", "01234567-89ab-cdef-0123-456789abcdef");
+        assert!(xml.contains("<Arn>arn:aws:iam::123456789012:user/engineering/Bob</Arn>"), "{xml}");
+        assert!(
+            xml.contains(
+                "<Tags><member><Key>Team</Key><Value>Platform</Value></member>\
+                 <member><Key>Cost-Center</Key><Value>1234</Value></member></Tags></User>"
+            ),
+            "{xml}"
+        );
+
+        // Invalid tags are rejected before the user is created.
+        let input = CreateUserInput::from_parameters(&parameters(&[
+            ("UserName", "Carol"),
+            ("Tags.member.1.Key", "aws:reserved"),
+            ("Tags.member.1.Value", "x"),
+        ]))
+        .unwrap();
+        let e = create_user(&store, &context, &input).await.unwrap_err();
+        assert!(matches!(e, UserError::Tag(_)));
+        assert_eq!(e.code(), "InvalidInput");
+        assert!(store.get_user("123456789012", "Carol").await.is_err());
+
+        store.delete_user("123456789012", "Bob").await.unwrap();
+        assert_eq!(store.list_tags(EntityKind::User, &output.user.user_id).await.unwrap(), Vec::<Tag>::new());
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_list_and_delete_users() {
+        let store = MemoryStore::new();
+        let admin = PrincipalUser::new("aws", "123456789012", "/", "Admin").unwrap();
+        let context =
+            RequestContext::builder().principal(Principal::from(vec![PrincipalIdentity::from(admin)])).build().unwrap();
+        for (name, path) in [("carol", "/"), ("Alice", "/engineering/"), ("bob", "/engineering/")] {
+            let input = CreateUserInput::from_parameters(&parameters(&[("UserName", name), ("Path", path)])).unwrap();
+            create_user(&store, &context, &input).await.unwrap();
+        }
+
+        let input = ListUsersInput::from_parameters(&parameters(&[("MaxItems", "2")])).unwrap();
+        let page = list_users(&store, &context, &input).await.unwrap();
+        assert_eq!(page.users.iter().map(|user| user.user_name.as_str()).collect::<Vec<_>>(), vec!["Alice", "bob"]);
+        assert_eq!(page.marker.as_deref(), Some("carol"));
+        let xml = page.to_xml("aws", "01234567-89ab-cdef-0123-456789abcdef");
+        assert!(xml.contains("<IsTruncated>true</IsTruncated><Marker>carol</Marker>"), "{xml}");
+
+        let input = ListUsersInput::from_parameters(&parameters(&[("Marker", "carol")])).unwrap();
+        let page = list_users(&store, &context, &input).await.unwrap();
+        assert_eq!(page.users.len(), 1);
+        assert_eq!(page.marker, None);
+
+        let input = ListUsersInput::from_parameters(&parameters(&[("PathPrefix", "/eng")])).unwrap();
+        assert_eq!(list_users(&store, &context, &input).await.unwrap().users.len(), 2);
+
+        let input = DeleteUserInput::from_parameters(&parameters(&[("UserName", "bob")])).unwrap();
+        delete_user(&store, &context, &input).await.unwrap();
+        let e = delete_user(&store, &context, &input).await.unwrap_err();
+        assert_eq!((e.code(), e.status().as_u16()), ("NoSuchEntity", 404));
+    }
+}
@@ -0,0 +1,32 @@
+//! The edge router: a single localstack-style endpoint that forwards each request to the service it is for.
+//!
+//! The `scratchstack-service-edge` binary reads its configuration and calls [run_edge]; other programs can call it
+//! directly to run the router in-process. It stops when the [CancellationToken] it is given is cancelled.
+pub use scratchstack_service_common::health::CancellationToken;
+use {
+    hyper::{server::Server as HyperServer, service::make_service_fn},
+    log::info,
+    scratchstack_service_common::{
+        edge::{EdgeConfig, EdgeRouter},
+        net::Incoming,
+    },
+    std::convert::Infallible,
+    tower::BoxError,
+};
+
+/// Run the edge router until `shutdown` is cancelled.
+pub async fn run_edge(config: EdgeConfig, shutdown: CancellationToken) -> Result<(), BoxError> {
+    let router = EdgeRouter::new(&config)?;
+    for (name, url) in &config.services {
+        info!("Routing {} requests to {}", name, url);
+    }
+
+    let incoming = Incoming::bind(&config.address, None, config.listener.ip_filter()).await?;
+    info!("Listening on {}", config.address);
+    let make_service = make_service_fn(move |_| {
+        let router = router.clone();
+        async move { Ok::<_, Infallible>(router) }
+    });
+    HyperServer::builder(incoming).serve(make_service).with_graceful_shutdown(shutdown.cancelled()).await?;
+    Ok(())
+}
@@ -0,0 +1,97 @@
+use {
+    getopts::Options,
+    log::{error, info},
+    scratchstack_service_common::{
+        audit, config::read_layered_config, edge::EdgeConfig, health::cancel_on_termination,
+    },
+    scratchstack_service_edge::{run_edge, CancellationToken},
+    std::{
+        env,
+        io::{self, Write},
+        process::exit,
+    },
+    tokio::runtime::Builder as RuntimeBuilder,
+};
+
+const DEFAULT_CONFIG_FILENAME: &str = "scratchstack.cfg";
+
+#[allow(unused_must_use)]
+fn print_usage(stream: &mut dyn Write, program: &str, opts: Options) {
+    let brief = format!("Usage: {program} [options]");
+    write!(stream, "{}", opts.usage(&brief));
+}
+
+fn main() {
+    audit::init_logging();
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optopt("c", "config", "configuration file", "FILENAME");
+    opts.optflag("h", "help", "print this usage information");
+    opts.optopt("", "profile", "configuration profile to apply (default: $SCRATCHSTACK_PROFILE)", "NAME");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            error!("{}", f);
+            exit(2);
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(&mut io::stdout(), &program, opts);
+        return;
+    }
+
+    let config_required = matches.opt_present("c");
+    let config_filename = match matches.opt_str("c") {
+        Some(filename) => filename,
+        None => DEFAULT_CONFIG_FILENAME.to_string(),
+    };
+
+    if !matches.free.is_empty() {
+        print_usage(&mut io::stderr(), &program, opts);
+        exit(0);
+    }
+
+    info!("Reading configuration from {}", config_filename);
+    let profile = matches.opt_str("profile");
+    if let Some(profile) = &profile {
+        info!("Applying configuration profile {}", profile);
+    }
+    let config_value = match read_layered_config(&config_filename, config_required, profile.as_deref()) {
+        Ok(v) => v,
+        Err(e) => {
+            error!("Unable to read configuration file {}: {}", config_filename, e);
+            exit(2);
+        }
+    };
+
+    let config = match EdgeConfig::from_value(&config_value) {
+        Ok(Some(c)) => c,
+        Ok(None) => {
+            error!("No [edge] section found in configuration file {}", config_filename);
+            exit(2);
+        }
+        Err(e) => {
+            error!("Error in configuration file {}: {}", config_filename, e);
+            exit(2);
+        }
+    };
+
+    let runtime = match RuntimeBuilder::new_multi_thread().thread_name("edge").enable_all().build() {
+        Ok(rt) => rt,
+        Err(e) => {
+            error!("Unable to create runtime: {}", e);
+            exit(1);
+        }
+    };
+
+    let shutdown = CancellationToken::new();
+    runtime.spawn(cancel_on_termination(shutdown.clone()));
+    if let Err(e) = runtime.block_on(run_edge(config, shutdown)) {
+        error!("{}", e);
+        exit(1);
+    }
+}
@@ -0,0 +1,435 @@
+//! The IAM service.
+//!
+//! The `scratchstack-service-iam` binary reads its configuration and calls [run_server_from_config]; integration
+//! tests and other programs can call it directly to run the service in-process. Nothing here exits the process, and
+//! the server stops when the [CancellationToken] it is given is cancelled.
+mod error;
+mod operations;
+mod service;
+
+pub use {crate::error::ServiceError, scratchstack_service_common::health::CancellationToken};
+use {
+    crate::service::{request_resource, IamService, IAM_DEPRECATIONS, IAM_UNAUTHORIZED_ACTIONS, IAM_XML_NS},
+    http::method::Method,
+    hyper::server::Server as HyperServer,
+    log::{error, info, warn},
+    scratchstack_config::service::ResolvedIam,
+    scratchstack_http_framework::{GetSigningKeyFromDatabase, SpawnService, XmlErrorMapper},
+    scratchstack_service_common::{
+        access_key::AccessKeyPrefixes,
+        access_log::{RecordPrincipal, WithAccessLog},
+        audit,
+        authorizer::AuthorizeRequests,
+        authz::{AuthorizationMode, DefaultDecision},
+        backup::{restore, spawn_backups},
+        bootstrap::{create_account, AccountBootstrap},
+        concurrency::ConcurrencyLimit,
+        config::ServiceOptions,
+        consistency::DelayNewCredentials,
+        crypto,
+        deployment::{BuildInfo, Deployment, WithVersionEndpoint},
+        deprecation::{DeprecationMode, Deprecations},
+        encoding::{DecodeRequestBody, WithRequestDecoding},
+        flags::{ApplyFeatureFlags, FeatureFlags, WithFeatureFlagAdmin, ADMIN_PATH},
+        gsk::{
+            CanonicalAccessKeys, CaptureSigningKey, CircuitBreaker, ProviderSigningKeys, RegionValidation,
+            RejectInactiveCredentials, RejectRevokedCredentials, RootCredentials, SessionCredentials, SigningKeyCache,
+            SigningKeyCircuitBreaker, WithCircuitBreaker,
+        },
+        health::{serve_until_shutdown, shutdown_signal, Health, WithHealthChecks},
+        integrity::ResponseSigning,
+        limit_catalog::{seed_limits, LimitCatalog, SeedSummary},
+        limits::Limits,
+        load_shed::{LoadMonitor, WithLoadShedding},
+        metrics::{MarkVerified, VerificationMetrics, WithVerificationMetrics},
+        mirror::Mirror,
+        net::{Incoming, IpFilter, TlsMetrics, WithConnectionInfo},
+        outbound::OutboundClient,
+        payload_hash::WithPayloadHashValidation,
+        protocol,
+        region::{Partition, Region},
+        request_id::WithRequestIds,
+        request_time::WithRequestTimeValidation,
+        response_cache::ResponseCache,
+        revocation::{self, Propagation, Revocations, WithCredentialAdmin},
+        route::{Proxy, Split},
+        schema::{check_schema_version, ExpectedSchema},
+        sigv2::{SigV2Verifier, WithSigV2},
+        startup::{CredentialHint, DatabaseSummary, EndpointSummary, StartupSummary},
+        store::{ControlPlaneStore, InstrumentedStore, QueryMetrics, ScopeRequestId, SqlStore},
+        telemetry::{serve_metrics, RecordRequests, RequestMetrics, Telemetry},
+        token::{spawn_token_key_refresh, TokenKeyRing, ValidateSessionTokens},
+    },
+    std::{
+        iter::Iterator,
+        path::Path,
+        sync::{Arc, RwLock},
+        time::Duration,
+    },
+};
+
+/// How often session token keys are reloaded from the database.
+const TOKEN_KEY_REFRESH_INTERVAL: Duration = Duration::from_secs(300);
+
+/// Hyper refuses HTTP/1 buffer sizes smaller than this.
+const MIN_MAX_HEADER_BYTES: usize = 8192;
+// const CONTENT_LENGTH_LIMIT: u64 = 10 << 20;
+
+/// Replace the contents of the IAM database with `backup`, returning the number of rows restored.
+pub async fn restore_from_config(config: ResolvedIam, backup: &Path) -> Result<usize, ServiceError> {
+    let pool = config.database.pool_options.connect(&config.database.url).await?;
+    Ok(restore(&pool, ExpectedSchema::IAM, backup).await?)
+}
+
+/// Apply the limits catalog in `catalog` to the database; see [seed_limits].
+pub async fn seed_limits_from_config(config: ResolvedIam, catalog: &Path) -> Result<SeedSummary, ServiceError> {
+    let catalog = LimitCatalog::read_file(catalog)?;
+    let pool = config.database.pool_options.connect(&config.database.url).await?;
+    Ok(seed_limits(&pool, &catalog).await?)
+}
+
+/// Create an account for `email` with an administrator user named `admin_user_name` and an access key for it; see
+/// [create_account].
+pub async fn create_account_from_config(
+    config: ResolvedIam,
+    options: ServiceOptions,
+    email: &str,
+    account_id: Option<&str>,
+    admin_user_name: &str,
+) -> Result<AccountBootstrap, ServiceError> {
+    let pool = Arc::new(config.database.pool_options.connect(&config.database.url).await?);
+    let store = SqlStore::new(pool);
+    Ok(create_account(&store, &options.access_key_prefixes, email, account_id, admin_user_name).await?)
+}
+
+/// Deactivate `access_key` on behalf of `actor` and wait for every replica to stop accepting it; see
+/// [Revocations::deactivate].
+pub async fn deactivate_from_config(
+    config: ResolvedIam,
+    options: ServiceOptions,
+    access_key: &str,
+    actor: &str,
+) -> Result<Propagation, ServiceError> {
+    let pool = Arc::new(config.database.pool_options.connect(&config.database.url).await?);
+    let revocations = Revocations::new(pool.clone(), options.credential_revocation.unwrap_or_default())
+        .with_access_key_prefixes(options.access_key_prefixes);
+    let store = SqlStore::new(pool);
+    Ok(revocations.deactivate(&store, access_key, actor).await?)
+}
+
+/// Build metadata for this crate; see `build.rs`.
+fn build_info() -> BuildInfo {
+    BuildInfo::new(
+        "iam",
+        env!("CARGO_PKG_NAME"),
+        env!("CARGO_PKG_VERSION"),
+        option_env!("VERGEN_GIT_SHA"),
+        option_env!("VERGEN_BUILD_TIMESTAMP"),
+    )
+}
+
+/// What the IAM service started from `config` serves, for the startup log and `--print-endpoints`.
+pub fn startup_summary(config: &ResolvedIam, options: &ServiceOptions, region: &Region) -> StartupSummary {
+    let tls = options.tls_files.is_some() || config.service.tls.is_some();
+    StartupSummary {
+        endpoints: vec![EndpointSummary::new("iam", region.to_string(), config.service.address, tls)]
+            .into_iter()
+            .chain(
+                options
+                    .metrics_endpoint
+                    .as_ref()
+                    .map(|metrics| EndpointSummary::new("metrics", region.to_string(), metrics.address, false)),
+            )
+            .collect(),
+        database: Some(DatabaseSummary::new(&config.database.url, ExpectedSchema::IAM, !options.skip_schema_check)),
+        credentials: CredentialHint::root_credentials(&options.root_credentials),
+    }
+}
+
+/// Run the IAM service until `shutdown` is cancelled.
+///
+/// Background tasks started for the service, such as backups and the revocation watcher, are stopped before this
+/// returns, so several services can be started and stopped within one process.
+pub async fn run_server_from_config(
+    config: ResolvedIam,
+    options: ServiceOptions,
+    region: Region,
+    partition: Partition,
+    shutdown: CancellationToken,
+) -> Result<(), ServiceError> {
+    let deployment = Arc::new(Deployment::new(build_info(), &region));
+    info!("Starting {}", deployment);
+    info!("Signature cryptography provided by {}", crypto::BACKEND);
+    let outbound = OutboundClient::new(&options.outbound)?;
+    if !options.audit.sinks.is_empty() {
+        audit::start(&options.audit, "iam", &outbound)?;
+        info!("Delivering audit events to {} sinks", options.audit.sinks.len());
+    }
+    let pool = config.database.pool_options.connect(&config.database.url).await?;
+    if options.skip_schema_check {
+        warn!("Skipping database schema version check");
+    } else if let Err(e) = check_schema_version(&pool, ExpectedSchema::IAM).await {
+        error!("{}", e);
+        return Err(e.into());
+    }
+    if let Some(catalog) = &options.limits_catalog {
+        info!("Seeding limits from {}", catalog.display());
+        seed_limits(&pool, &LimitCatalog::read_file(catalog)?).await?;
+    }
+    let pool = Arc::new(pool);
+    let health = Arc::new(Health::new(pool.clone(), (!options.skip_schema_check).then_some(ExpectedSchema::IAM)));
+    if options.authorization == AuthorizationMode::Permissive {
+        warn!("Authorization is permissive: denials are logged but not enforced");
+    }
+    if options.default_decision == DefaultDecision::Allow {
+        warn!("Requests that no policy statement applies to are allowed");
+    }
+    let mut tasks = Vec::new();
+    // Until the first refresh, requests signed with temporary credentials are rejected with InvalidClientTokenId.
+    let token_keys = Arc::new(RwLock::new(TokenKeyRing::default()));
+    tasks.push(spawn_token_key_refresh(pool.clone(), token_keys.clone(), TOKEN_KEY_REFRESH_INTERVAL));
+    if let Some(backup) = &options.backup {
+        info!("Backing up the database to {} every {} seconds", backup.target, backup.interval_seconds);
+        tasks.push(spawn_backups(pool.clone(), ExpectedSchema::IAM, backup.clone(), outbound.clone()));
+    }
+    let allowed_request_methods = vec![Method::GET, Method::POST, Method::PUT];
+    let allowed_content_types = vec!["application/x-www-form-urlencoded".to_string()];
+    let regions = Arc::new(options.region_registry(&region));
+    info!("Accepting requests scoped to regions: {:?}", regions.iter().collect::<Vec<_>>());
+    let gsk = GetSigningKeyFromDatabase::new(pool.clone(), partition.as_str(), region.as_str(), "iam");
+    let signing_key_provider = options.signing_key_provider.provider()?;
+    if let Some(provider) = &signing_key_provider {
+        info!("Deriving signing keys with {:?}", provider);
+    }
+    let gsk = ProviderSigningKeys::new(gsk, signing_key_provider);
+    if let Some(consistency) = &options.eventual_consistency {
+        info!("Simulating eventual consistency for new access keys: {:?}", consistency);
+    }
+    if options.query_log.slow_query_ms > 0 {
+        info!("Logging store calls slower than {}ms", options.query_log.slow_query_ms);
+    }
+    let query_metrics = Arc::new(QueryMetrics::new());
+    let store: Arc<dyn ControlPlaneStore> =
+        Arc::new(InstrumentedStore::new(SqlStore::new(pool.clone()), query_metrics.clone(), &options.query_log));
+    let gsk = DelayNewCredentials::new(gsk, store.clone(), options.eventual_consistency.clone());
+    let gsk = RejectInactiveCredentials::new(gsk, store.clone()).with_access_key_prefixes(&options.access_key_prefixes);
+    let gsk = RootCredentials::new(gsk, &partition, &options.root_credentials)
+        .with_access_key_prefixes(&options.access_key_prefixes);
+    if !gsk.is_empty() {
+        info!("Accepting root credentials for {} access keys", options.root_credentials.len());
+    }
+    let breaker = options.circuit_breaker.clone().map(CircuitBreaker::new);
+    if let Some(breaker) = &breaker {
+        info!("Signing key circuit breaker enabled: {:?}", breaker.config());
+    }
+    let gsk = SigningKeyCircuitBreaker::new(gsk, breaker.clone());
+    let gsk = SessionCredentials::new(gsk, token_keys.clone());
+    let gsk = RegionValidation::new(gsk, regions.clone());
+    let signing_keys = if options.response_signing.enabled {
+        info!("Response signing enabled");
+        Some(SigningKeyCache::new())
+    } else {
+        None
+    };
+    let revocations = options.credential_revocation.clone().map(|revocation| {
+        Revocations::new(pool.clone(), revocation)
+            .with_signing_keys(signing_keys.clone())
+            .with_access_key_prefixes(options.access_key_prefixes.clone())
+    });
+    if let Some(revocations) = &revocations {
+        info!("Checking for credential revocations: {:?}", revocations.config());
+        tasks.push(revocations.spawn_watcher());
+        if revocations.config().admin_token.is_some() {
+            info!("Credential admin endpoint enabled at {}", revocation::ADMIN_PATH);
+        }
+    }
+    let gsk = RejectRevokedCredentials::new(gsk, revocations.as_ref().map(Revocations::revoked).unwrap_or_default());
+    if options.access_key_prefixes != AccessKeyPrefixes::default() {
+        info!("Accepting access key prefixes: {:?}", options.access_key_prefixes);
+    }
+    let gsk = CanonicalAccessKeys::new(gsk, options.access_key_prefixes.clone());
+    let gsk = CaptureSigningKey::new(gsk, signing_keys.clone());
+    let deprecations = Arc::new(Deprecations::new(IAM_DEPRECATIONS, &options.deprecations));
+    if deprecations.mode() == DeprecationMode::Strict {
+        info!("Rejecting requests that use deprecated operations or parameters");
+    }
+    let iam = IamService::new(store.clone())
+        .with_access_key_prefixes(options.access_key_prefixes.clone())
+        .with_limits(Limits::new(pool.clone()))
+        .with_deprecations(deprecations.clone());
+    let iam = match &options.response_cache {
+        None => iam,
+        Some(response_cache) => {
+            info!("Caching read operation responses: {:?}", response_cache);
+            iam.with_response_cache(ResponseCache::new(response_cache.clone()))
+        }
+    };
+    let flags = Arc::new(FeatureFlags::new(&options.feature_flags));
+    if options.feature_flags.admin_token.is_some() {
+        info!("Feature flag admin endpoint enabled at {}", ADMIN_PATH);
+    }
+    let service_impl = AuthorizeRequests::new(iam, store.clone(), "iam", protocol::IAM)
+        .with_resource(request_resource)
+        .with_exempt_actions(IAM_UNAUTHORIZED_ACTIONS)
+        .with_mode(options.authorization)
+        .with_feature_flags(flags.clone())
+        .with_default_decision(options.default_decision)
+        .with_regions(regions);
+    let service_impl = DecodeRequestBody::new(service_impl, options.request_decoding.as_ref(), IAM_XML_NS);
+    let service_impl = match &options.routing {
+        None => Split::new(service_impl),
+        Some(routing) => {
+            info!("Routing requests to alternate implementation at {}: {:?}", routing.alternate_url, routing);
+            Split::new(service_impl).with_alternate(Proxy::new(&routing.alternate_url)?, routing)
+        }
+    };
+    let service_impl = Mirror::new(service_impl, options.mirror.as_ref())?;
+    let service_impl = ApplyFeatureFlags::new(service_impl, flags.clone(), IAM_XML_NS);
+    let request_metrics = Arc::new(RequestMetrics::new());
+    let service_impl = RecordRequests::new(service_impl, request_metrics.clone());
+    if let Some(concurrency) = &options.concurrency {
+        info!("Limiting each principal to {} requests in flight: {:?}", concurrency.max_in_flight, concurrency);
+    }
+    let service_impl = ConcurrencyLimit::new(service_impl, options.concurrency.as_ref(), IAM_XML_NS);
+    let service_impl = ResponseSigning::new(service_impl, signing_keys);
+    let service_impl = ValidateSessionTokens::new(service_impl, token_keys.clone(), protocol::IAM)
+        .with_access_key_prefixes(options.access_key_prefixes.clone());
+    let verification_metrics = Arc::new(VerificationMetrics::new());
+    let service_impl = MarkVerified::new(service_impl, verification_metrics.clone());
+    let service_impl = RecordPrincipal::new(ScopeRequestId::new(service_impl));
+    if let Some(mirror) = &options.mirror {
+        info!("Mirroring {}% of requests to {}", mirror.percent, mirror.url);
+    }
+    let error_mapper = XmlErrorMapper::new(IAM_XML_NS);
+
+    let filter = options.listener.ip_filter();
+    if !filter.is_empty() {
+        info!("Listener IP filter configured: {:?}", options.listener);
+    }
+    let proxies = options.listener.trusted_proxies();
+    if !proxies.is_empty() {
+        info!("Trusting X-Forwarded-For and X-Forwarded-Proto from {:?}", options.listener.trusted_proxies);
+    }
+
+    let summary = startup_summary(&config, &options, &region);
+    let tls = match &options.tls_files {
+        Some(tls_files) => {
+            info!("Loading TLS certificate from {}", tls_files.certificate_chain_file.display());
+            Some(tls_files.server_config()?)
+        }
+        None => config.service.tls,
+    };
+
+    let tls_metrics = Arc::new(TlsMetrics::new());
+    let incoming = Incoming::bind(&config.service.address, tls, filter).await?.with_tls_metrics(tls_metrics.clone());
+    if let Some(metrics) = &options.metrics_endpoint {
+        let telemetry = Telemetry::new(request_metrics)
+            .with_verification_metrics(verification_metrics.clone())
+            .with_query_metrics(query_metrics)
+            .with_tls_metrics(tls_metrics)
+            .with_deprecations(deprecations)
+            .with_pool(pool.clone());
+        let metrics_incoming = Incoming::bind(&metrics.address, None, IpFilter::default()).await?;
+        tasks.push(serve_metrics(metrics_incoming, Arc::new(telemetry), shutdown.clone()));
+    }
+    let service_maker: SpawnService<
+        CaptureSigningKey<
+            CanonicalAccessKeys<
+                RejectRevokedCredentials<
+                    RegionValidation<
+                        SessionCredentials<
+                            SigningKeyCircuitBreaker<
+                                RootCredentials<
+                                    RejectInactiveCredentials<
+                                        DelayNewCredentials<ProviderSigningKeys<GetSigningKeyFromDatabase>>,
+                                    >,
+                                >,
+                            >,
+                        >,
+                    >,
+                >,
+            >,
+        >,
+        RecordPrincipal<
+            ScopeRequestId<
+                MarkVerified<
+                    ValidateSessionTokens<
+                        ResponseSigning<
+                            ConcurrencyLimit<
+                                RecordRequests<
+                                    ApplyFeatureFlags<
+                                        Mirror<Split<DecodeRequestBody<AuthorizeRequests<IamService>>, Proxy>>,
+                                    >,
+                                >,
+                            >,
+                        >,
+                    >,
+                >,
+            >,
+        >,
+        XmlErrorMapper,
+    > = SpawnService::builder()
+        .region(region.to_string())
+        .service("iam")
+        .allowed_request_methods(allowed_request_methods)
+        .allowed_content_types(allowed_content_types)
+        .get_signing_key(gsk)
+        .implementation(service_impl)
+        .error_mapper(error_mapper)
+        .build()
+        .expect("Unable to create service maker");
+
+    summary.log();
+    let mut server = HyperServer::builder(incoming);
+    if let Some(max_header_bytes) = options.max_header_bytes {
+        let max_header_bytes = max_header_bytes.max(MIN_MAX_HEADER_BYTES);
+        info!("Accepting request headers up to {} bytes", max_header_bytes);
+        server = server
+            .http1_max_buf_size(max_header_bytes)
+            .http2_max_header_list_size(u32::try_from(max_header_bytes).unwrap_or(u32::MAX));
+    }
+    let sigv2 = options.signature.allow_sigv2.then(|| {
+        info!("Accepting Signature Version 2 requests");
+        SigV2Verifier::new(store.clone(), region.as_str(), "iam", options.request_time().clock_skew_seconds)
+            .with_root_credentials(&options.root_credentials)
+            .with_session_tokens(token_keys.clone())
+    });
+    let service_maker = WithSigV2::new(service_maker, sigv2, protocol::IAM);
+    let service_maker = WithRequestDecoding::new(service_maker, options.request_decoding.clone(), IAM_XML_NS);
+    let service_maker = WithPayloadHashValidation::new(service_maker, options.payload_hash.clone(), protocol::IAM);
+    let service_maker = WithCircuitBreaker::new(service_maker, breaker, IAM_XML_NS);
+    let service_maker = WithVerificationMetrics::new(service_maker, verification_metrics)
+        .with_access_key_prefixes(options.access_key_prefixes.clone());
+    let service_maker = WithHealthChecks::new(service_maker, health.clone(), &options.health);
+    let service_maker = WithVersionEndpoint::new(service_maker, deployment);
+    let service_maker = WithFeatureFlagAdmin::new(service_maker, flags);
+    let service_maker = WithCredentialAdmin::new(service_maker, revocations, store);
+    let service_maker = WithRequestTimeValidation::new(service_maker, options.request_time(), protocol::IAM);
+    let load_monitor = options.load_shedding.clone().map(|config| {
+        info!("Shedding load while saturated: {:?}", config);
+        let monitor = Arc::new(LoadMonitor::new(config));
+        tasks.push(monitor.spawn_probe());
+        monitor
+    });
+    let service_maker =
+        WithLoadShedding::new(service_maker, load_monitor, protocol::IAM).with_exempt_paths(options.health.paths());
+    let service_maker = WithRequestIds::new(service_maker, protocol::IAM);
+    if !options.access_log.enabled {
+        info!("Access log disabled");
+    }
+    let service_maker = WithAccessLog::new(service_maker, &options.access_log);
+    let drain = Duration::from_secs(options.health.drain_seconds);
+    let shutdown_timeout = Duration::from_secs(options.health.shutdown_timeout_seconds);
+    let service_maker = WithConnectionInfo::new(service_maker).with_trusted_proxies(proxies);
+    let server = server.serve(service_maker).with_graceful_shutdown(shutdown_signal(health, drain, shutdown.clone()));
+    let result = serve_until_shutdown(server, shutdown, drain, shutdown_timeout).await;
+    for task in tasks {
+        task.abort();
+    }
+    pool.close().await;
+    if let Some(result) = result {
+        result?;
+    }
+    info!("Server stopped");
+    Ok(())
+}
@@ -1,30 +1,28 @@
-mod error;
-mod service;
-
 use {
-    crate::{
-        error::ServiceError,
-        service::{IamService, IAM_XML_NS},
-    },
     getopts::Options,
-    http::method::Method,
-    hyper::server::Server as HyperServer,
     log::{debug, error, info},
-    scratchstack_config::{service::ResolvedIam, Config},
-    scratchstack_http_framework::{GetSigningKeyFromDatabase, SpawnService, TlsIncoming, XmlErrorMapper},
+    scratchstack_config::Config,
+    scratchstack_service_common::{
+        audit,
+        bootstrap::DEFAULT_ADMIN_USER_NAME,
+        config::{read_layered_config, ServiceOptions},
+        health::cancel_on_termination,
+        region::{Partition, Region},
+    },
+    scratchstack_service_iam::{
+        create_account_from_config, deactivate_from_config, restore_from_config, run_server_from_config,
+        seed_limits_from_config, startup_summary, CancellationToken,
+    },
     std::{
         env,
         io::{self, Write},
-        iter::Iterator,
+        path::Path,
         process::exit,
-        sync::Arc,
     },
-    tokio::{net::TcpListener, runtime::Builder as RuntimeBuilder},
-    tokio_rustls::TlsAcceptor,
+    tokio::runtime::Builder as RuntimeBuilder,
 };
 
 const DEFAULT_CONFIG_FILENAME: &str = "scratchstack.cfg";
-// const CONTENT_LENGTH_LIMIT: u64 = 10 << 20;
 
 #[allow(unused_must_use)]
 fn print_usage(stream: &mut dyn Write, program: &str, opts: Options) {
@@ -33,13 +31,26 @@ fn print_usage(stream: &mut dyn Write, program: &str, opts: Options) {
 }
 
 fn main() {
-    env_logger::init();
+    audit::init_logging();
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
 
     let mut opts = Options::new();
     opts.optopt("c", "config", "configuration file", "FILENAME");
     opts.optflag("h", "help", "print this usage information");
+    opts.optopt("", "profile", "configuration profile to apply (default: $SCRATCHSTACK_PROFILE)", "NAME");
+    opts.optflag("", "print-endpoints", "print the endpoints, database and credential hints as JSON, then exit");
+    opts.optopt("", "restore", "replace the database contents with a backup, then exit", "FILENAME");
+    opts.optopt("", "seed-limits", "apply a limits catalog to the database, then exit", "FILENAME");
+    opts.optopt(
+        "",
+        "create-account",
+        "create an account with an administrator user and access key, print them as JSON, then exit",
+        "EMAIL",
+    );
+    opts.optopt("", "account-id", "id of the account --create-account creates (default: random)", "ACCOUNT_ID");
+    opts.optopt("", "admin-user", "name of the user --create-account creates (default: admin)", "USER_NAME");
+    opts.optopt("", "deactivate-access-key", "deactivate an access key on every replica, then exit", "ACCESS_KEY_ID");
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
@@ -54,6 +65,8 @@ fn main() {
         return;
     }
 
+    // An explicitly named file must exist; without one, settings may come entirely from the environment.
+    let config_required = matches.opt_present("c");
     let config_filename = match matches.opt_str("c") {
         Some(filename) => filename,
         None => DEFAULT_CONFIG_FILENAME.to_string(),
@@ -67,13 +80,24 @@ fn main() {
 
     // Parse the configuration.
     info!("Reading configuration from {}", config_filename);
-    let config = match Config::read_file(&config_filename) {
-        Ok(c) => c,
+    let profile = matches.opt_str("profile");
+    if let Some(profile) = &profile {
+        info!("Applying configuration profile {}", profile);
+    }
+    let config_value = match read_layered_config(&config_filename, config_required, profile.as_deref()) {
+        Ok(v) => v,
         Err(e) => {
             error!("Unable to read configuration file {}: {}", config_filename, e);
             exit(2);
         }
     };
+    let config: Config = match config_value.clone().try_into() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Error in configuration file {}: {}", config_filename, e);
+            exit(2);
+        }
+    };
     info!("Configuration read from {}", config_filename);
     debug!("Configuration: {:?}", config);
 
@@ -105,6 +129,35 @@ fn main() {
     info!("Configuration resolved");
     debug!("Resolved configuration: {:?}", config);
 
+    let options = match ServiceOptions::from_value(&config_value, "iam") {
+        Ok(o) => o,
+        Err(e) => {
+            error!("Error in configuration file {}: {}", config_filename, e);
+            exit(2);
+        }
+    };
+    debug!("Service options: {:?}", options);
+
+    let region: Region = match config.service.region.parse() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Error in configuration file {}: {}", config_filename, e);
+            exit(2);
+        }
+    };
+    let partition: Partition = match config.service.partition.parse() {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Error in configuration file {}: {}", config_filename, e);
+            exit(2);
+        }
+    };
+
+    if matches.opt_present("print-endpoints") {
+        println!("{}", startup_summary(&config, &options, &region).to_json());
+        return;
+    }
+
     info!("Creating runtime");
     let runtime = match RuntimeBuilder::new_multi_thread()
         .worker_threads(config.service.threads)
@@ -119,60 +172,74 @@ fn main() {
         }
     };
 
-    println!("{:#?}", runtime.block_on(run_server_from_config(config)));
-}
+    if let Some(backup) = matches.opt_str("restore") {
+        match runtime.block_on(restore_from_config(config, Path::new(&backup))) {
+            Ok(n_rows) => info!("Restored {} rows from {}", n_rows, backup),
+            Err(e) => {
+                error!("Unable to restore {}: {}", backup, e);
+                exit(1);
+            }
+        }
+        return;
+    }
 
-async fn run_server_from_config(config: ResolvedIam) -> Result<(), ServiceError> {
-    let pool = config.database.pool_options.connect(&config.database.url).await?;
-    let pool = Arc::new(pool);
-    let region = config.service.region.clone();
-    let allowed_request_methods = vec![Method::GET, Method::POST, Method::PUT];
-    let allowed_content_types = vec!["application/x-www-form-urlencoded".to_string()];
-    let gsk = GetSigningKeyFromDatabase::new(pool, &config.service.partition, &config.service.region, "iam");
-    let service_impl = IamService {};
-    let error_mapper = XmlErrorMapper::new(IAM_XML_NS);
-
-    match config.service.tls {
-        Some(t) => {
-            info!("TLS configuration detected; creating acceptor and listener");
-            let acceptor = TlsAcceptor::from(Arc::new(t));
-            let tcp_listener = TcpListener::bind(&config.service.address).await?;
-            let incoming = TlsIncoming::new(tcp_listener, acceptor);
-
-            let service_maker: SpawnService<GetSigningKeyFromDatabase, IamService, XmlErrorMapper> =
-                SpawnService::builder()
-                    .region(region)
-                    .service("iam")
-                    .allowed_request_methods(allowed_request_methods)
-                    .allowed_content_types(allowed_content_types)
-                    .get_signing_key(gsk)
-                    .implementation(service_impl)
-                    .error_mapper(error_mapper)
-                    .build()
-                    .expect("Unable to create service maker");
-
-            info!("Starting Hyper");
-
-            HyperServer::builder(incoming).serve(service_maker).await?;
-            Ok(())
+    if let Some(catalog) = matches.opt_str("seed-limits") {
+        match runtime.block_on(seed_limits_from_config(config, Path::new(&catalog))) {
+            Ok(summary) => {
+                info!("Applied {}: {} limits inserted, {} updated", catalog, summary.inserted, summary.updated)
+            }
+            Err(e) => {
+                error!("Unable to apply limits catalog {}: {}", catalog, e);
+                exit(1);
+            }
         }
-        None => {
-            info!("Non-TLS configuration detected");
-            let service_maker: SpawnService<GetSigningKeyFromDatabase, IamService, XmlErrorMapper> =
-                SpawnService::builder()
-                    .region(region)
-                    .service("iam")
-                    .allowed_request_methods(allowed_request_methods)
-                    .allowed_content_types(allowed_content_types)
-                    .get_signing_key(gsk)
-                    .implementation(service_impl)
-                    .error_mapper(error_mapper)
-                    .build()
-                    .expect("Unable to create service maker");
-
-            info!("Starting Hyper");
-            HyperServer::bind(&config.service.address).serve(service_maker).await?;
-            Ok(())
+        return;
+    }
+
+    if let Some(email) = matches.opt_str("create-account") {
+        let account_id = matches.opt_str("account-id");
+        let admin_user_name = matches.opt_str("admin-user").unwrap_or_else(|| DEFAULT_ADMIN_USER_NAME.to_string());
+        match runtime.block_on(create_account_from_config(
+            config,
+            options,
+            &email,
+            account_id.as_deref(),
+            &admin_user_name,
+        )) {
+            Ok(bootstrap) => {
+                info!("Created account {} with user {}", bootstrap.account_id, bootstrap.user_name);
+                println!("{}", serde_json::to_string_pretty(&bootstrap).expect("Unable to serialize account"));
+            }
+            Err(e) => {
+                error!("Unable to create account for {}: {}", email, e);
+                exit(1);
+            }
         }
+        return;
     }
+
+    if let Some(access_key) = matches.opt_str("deactivate-access-key") {
+        let actor = format!("{} (command line)", env::var("USER").unwrap_or_else(|_| "unknown".to_string()));
+        match runtime.block_on(deactivate_from_config(config, options, &access_key, &actor)) {
+            Ok(propagation) => {
+                println!("{}", serde_json::to_string_pretty(&propagation).expect("Unable to serialize propagation"));
+                if !propagation.complete {
+                    error!(
+                        "Access key {} is deactivated, but only {} of {} replicas confirmed before the timeout",
+                        access_key, propagation.acknowledged, propagation.replicas
+                    );
+                    exit(1);
+                }
+            }
+            Err(e) => {
+                error!("Unable to deactivate access key {}: {}", access_key, e);
+                exit(1);
+            }
+        }
+        return;
+    }
+
+    let shutdown = CancellationToken::new();
+    runtime.spawn(cancel_on_termination(shutdown.clone()));
+    println!("{:#?}", runtime.block_on(run_server_from_config(config, options, region, partition, shutdown)));
 }
@@ -0,0 +1,64 @@
+use {
+    super::{empty_xml_response, error_response, xml_response},
+    hyper::{Body, Response},
+    scratchstack_service_common::{
+        access_key::AccessKeyPrefixes,
+        access_keys::{self, AccessKeyError, CreateAccessKeyInput, DeleteAccessKeyInput, ListAccessKeysInput},
+        context::RequestContext,
+        operation::FromParameters,
+        store::ControlPlaneStore,
+    },
+    tower::BoxError,
+};
+
+pub(crate) async fn create_access_key(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+    prefixes: &AccessKeyPrefixes,
+) -> Result<Response<Body>, BoxError> {
+    let result = match CreateAccessKeyInput::from_parameters(context.parameters()) {
+        Ok(input) => access_keys::create_access_key(store, context, prefixes, &input).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(&context.request_id().to_string())),
+        Err(e) => access_key_error(context, e),
+    }
+}
+
+pub(crate) async fn list_access_keys(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+    prefixes: &AccessKeyPrefixes,
+) -> Result<Response<Body>, BoxError> {
+    let result = match ListAccessKeysInput::from_parameters(context.parameters()) {
+        Ok(input) => access_keys::list_access_keys(store, context, prefixes, &input).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(&context.request_id().to_string())),
+        Err(e) => access_key_error(context, e),
+    }
+}
+
+pub(crate) async fn delete_access_key(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+    prefixes: &AccessKeyPrefixes,
+) -> Result<Response<Body>, BoxError> {
+    let result = match DeleteAccessKeyInput::from_parameters(context.parameters()) {
+        Ok(input) => access_keys::delete_access_key(store, context, prefixes, &input).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(()) => empty_xml_response(context, "DeleteAccessKey"),
+        Err(e) => access_key_error(context, e),
+    }
+}
+
+fn access_key_error(context: &RequestContext, e: AccessKeyError) -> Result<Response<Body>, BoxError> {
+    error_response(context, e.code(), e.status(), &e)
+}
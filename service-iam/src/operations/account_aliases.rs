@@ -0,0 +1,69 @@
+use {
+    super::{empty_xml_response, error_response, xml_response},
+    hyper::{Body, Response},
+    scratchstack_service_common::{
+        account_aliases::{
+            self, AccountAliasError, CreateAccountAliasInput, DeleteAccountAliasInput, ListAccountAliasesInput,
+        },
+        context::RequestContext,
+        operation::FromParameters,
+        store::ControlPlaneStore,
+    },
+    tower::BoxError,
+};
+
+pub(crate) async fn create_account_alias(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match CreateAccountAliasInput::from_parameters(context.parameters()) {
+        Ok(input) => account_aliases::create_account_alias(store, &account_id, &input).await,
+        Err(e) => Err(e.into()),
+    };
+    empty_response(context, "CreateAccountAlias", result)
+}
+
+pub(crate) async fn delete_account_alias(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match DeleteAccountAliasInput::from_parameters(context.parameters()) {
+        Ok(input) => account_aliases::delete_account_alias(store, &account_id, &input).await,
+        Err(e) => Err(e.into()),
+    };
+    empty_response(context, "DeleteAccountAlias", result)
+}
+
+pub(crate) async fn list_account_aliases(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match ListAccountAliasesInput::from_parameters(context.parameters()) {
+        Ok(input) => account_aliases::list_account_aliases(store, &account_id, &input).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(&context.request_id().to_string())),
+        Err(e) => account_alias_error(context, e),
+    }
+}
+
+/// The response to `operation`, which has no result.
+fn empty_response(
+    context: &RequestContext,
+    operation: &str,
+    result: Result<(), AccountAliasError>,
+) -> Result<Response<Body>, BoxError> {
+    match result {
+        Ok(()) => empty_xml_response(context, operation),
+        Err(e) => account_alias_error(context, e),
+    }
+}
+
+fn account_alias_error(context: &RequestContext, e: AccountAliasError) -> Result<Response<Body>, BoxError> {
+    error_response(context, e.code(), e.status(), &e)
+}
@@ -0,0 +1,243 @@
+use {
+    super::{empty_xml_response, error_response, xml_response},
+    hyper::{Body, Response},
+    scratchstack_service_common::{
+        attached_policies::{
+            self, AttachedPolicyError, GroupPolicyAttachmentInput, ListAttachedGroupPoliciesInput,
+            ListAttachedPoliciesOutput, ListAttachedRolePoliciesInput, ListAttachedUserPoliciesInput,
+            RolePolicyAttachmentInput, UserPolicyAttachmentInput,
+        },
+        context::RequestContext,
+        limits::Limits,
+        operation::FromParameters,
+        store::{ControlPlaneStore, PolicyHolder},
+    },
+    tower::BoxError,
+};
+
+pub(crate) async fn attach_user_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+    limits: &Limits,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match UserPolicyAttachmentInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            attached_policies::attach_policy(
+                store,
+                limits,
+                &account_id,
+                PolicyHolder::User,
+                &input.user_name,
+                &input.policy_arn,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    empty_response(context, "AttachUserPolicy", result)
+}
+
+pub(crate) async fn detach_user_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match UserPolicyAttachmentInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            attached_policies::detach_policy(
+                store,
+                &account_id,
+                PolicyHolder::User,
+                &input.user_name,
+                &input.policy_arn,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    empty_response(context, "DetachUserPolicy", result)
+}
+
+pub(crate) async fn list_attached_user_policies(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match ListAttachedUserPoliciesInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            attached_policies::list_attached_policies(
+                store,
+                &account_id,
+                PolicyHolder::User,
+                &input.user_name,
+                input.path_prefix.as_deref(),
+                input.marker.as_deref(),
+                input.max_items,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    list_response(context, result)
+}
+
+pub(crate) async fn attach_group_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+    limits: &Limits,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match GroupPolicyAttachmentInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            attached_policies::attach_policy(
+                store,
+                limits,
+                &account_id,
+                PolicyHolder::Group,
+                &input.group_name,
+                &input.policy_arn,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    empty_response(context, "AttachGroupPolicy", result)
+}
+
+pub(crate) async fn detach_group_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match GroupPolicyAttachmentInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            attached_policies::detach_policy(
+                store,
+                &account_id,
+                PolicyHolder::Group,
+                &input.group_name,
+                &input.policy_arn,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    empty_response(context, "DetachGroupPolicy", result)
+}
+
+pub(crate) async fn list_attached_group_policies(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match ListAttachedGroupPoliciesInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            attached_policies::list_attached_policies(
+                store,
+                &account_id,
+                PolicyHolder::Group,
+                &input.group_name,
+                input.path_prefix.as_deref(),
+                input.marker.as_deref(),
+                input.max_items,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    list_response(context, result)
+}
+
+pub(crate) async fn attach_role_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+    limits: &Limits,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match RolePolicyAttachmentInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            attached_policies::attach_policy(
+                store,
+                limits,
+                &account_id,
+                PolicyHolder::Role,
+                &input.role_name,
+                &input.policy_arn,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    empty_response(context, "AttachRolePolicy", result)
+}
+
+pub(crate) async fn detach_role_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match RolePolicyAttachmentInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            attached_policies::detach_policy(
+                store,
+                &account_id,
+                PolicyHolder::Role,
+                &input.role_name,
+                &input.policy_arn,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    empty_response(context, "DetachRolePolicy", result)
+}
+
+pub(crate) async fn list_attached_role_policies(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match ListAttachedRolePoliciesInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            attached_policies::list_attached_policies(
+                store,
+                &account_id,
+                PolicyHolder::Role,
+                &input.role_name,
+                input.path_prefix.as_deref(),
+                input.marker.as_deref(),
+                input.max_items,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    list_response(context, result)
+}
+
+/// The response to `operation`, which has no result.
+fn empty_response(
+    context: &RequestContext,
+    operation: &str,
+    result: Result<(), AttachedPolicyError>,
+) -> Result<Response<Body>, BoxError> {
+    match result {
+        Ok(()) => empty_xml_response(context, operation),
+        Err(e) => attached_policy_error(context, e),
+    }
+}
+
+fn list_response(
+    context: &RequestContext,
+    result: Result<ListAttachedPoliciesOutput, AttachedPolicyError>,
+) -> Result<Response<Body>, BoxError> {
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(&context.partition(), &context.request_id().to_string())),
+        Err(e) => attached_policy_error(context, e),
+    }
+}
+
+fn attached_policy_error(context: &RequestContext, e: AttachedPolicyError) -> Result<Response<Body>, BoxError> {
+    error_response(context, e.code(), e.status(), &e)
+}
@@ -0,0 +1,17 @@
+use {
+    super::api_docs,
+    http::{header::HeaderValue, StatusCode},
+    hyper::{Body, Response},
+    scratchstack_service_common::context::RequestContext,
+    tower::BoxError,
+};
+
+/// Describe the implemented operations as JSON; see [scratchstack_service_common::api_docs].
+pub(crate) async fn get_api_docs(context: &RequestContext) -> Result<Response<Body>, BoxError> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", HeaderValue::from_static("application/json"))
+        .header("X-Amzn-RequestId", context.request_id().to_string())
+        .body(Body::from(api_docs().to_json()))
+        .map_err(Into::into)
+}
@@ -0,0 +1,299 @@
+use {
+    super::{empty_xml_response, error_response, xml_response},
+    hyper::{Body, Response},
+    scratchstack_service_common::{
+        context::RequestContext,
+        inline_policies::{
+            self, GetInlinePolicyOutput, GroupPolicyInput, InlinePolicyError, ListGroupPoliciesInput,
+            ListInlinePoliciesOutput, ListRolePoliciesInput, ListUserPoliciesInput, PutGroupPolicyInput,
+            PutRolePolicyInput, PutUserPolicyInput, RolePolicyInput, UserPolicyInput,
+        },
+        operation::FromParameters,
+        store::{ControlPlaneStore, PolicyHolder},
+    },
+    tower::BoxError,
+};
+
+pub(crate) async fn put_user_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match PutUserPolicyInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            inline_policies::put_inline_policy(
+                store,
+                &account_id,
+                PolicyHolder::User,
+                &input.user_name,
+                &input.policy_name,
+                &input.policy_document,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    empty_response(context, "PutUserPolicy", result)
+}
+
+pub(crate) async fn get_user_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match UserPolicyInput::from_parameters(context.parameters()) {
+        Ok(input) => inline_policies::get_inline_policy(
+            store,
+            &account_id,
+            PolicyHolder::User,
+            &input.user_name,
+            &input.policy_name,
+        )
+        .await
+        .map(|policy| GetInlinePolicyOutput {
+            holder: PolicyHolder::User,
+            holder_name: input.user_name,
+            policy,
+        }),
+        Err(e) => Err(e.into()),
+    };
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(&context.request_id().to_string())),
+        Err(e) => inline_policy_error(context, e),
+    }
+}
+
+pub(crate) async fn delete_user_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match UserPolicyInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            inline_policies::delete_inline_policy(
+                store,
+                &account_id,
+                PolicyHolder::User,
+                &input.user_name,
+                &input.policy_name,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    empty_response(context, "DeleteUserPolicy", result)
+}
+
+pub(crate) async fn list_user_policies(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match ListUserPoliciesInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            inline_policies::list_inline_policies(store, &account_id, PolicyHolder::User, &input.user_name).await
+        }
+        Err(e) => Err(e.into()),
+    };
+    list_response(context, PolicyHolder::User, result)
+}
+
+pub(crate) async fn put_group_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match PutGroupPolicyInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            inline_policies::put_inline_policy(
+                store,
+                &account_id,
+                PolicyHolder::Group,
+                &input.group_name,
+                &input.policy_name,
+                &input.policy_document,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    empty_response(context, "PutGroupPolicy", result)
+}
+
+pub(crate) async fn get_group_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match GroupPolicyInput::from_parameters(context.parameters()) {
+        Ok(input) => inline_policies::get_inline_policy(
+            store,
+            &account_id,
+            PolicyHolder::Group,
+            &input.group_name,
+            &input.policy_name,
+        )
+        .await
+        .map(|policy| GetInlinePolicyOutput {
+            holder: PolicyHolder::Group,
+            holder_name: input.group_name,
+            policy,
+        }),
+        Err(e) => Err(e.into()),
+    };
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(&context.request_id().to_string())),
+        Err(e) => inline_policy_error(context, e),
+    }
+}
+
+pub(crate) async fn delete_group_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match GroupPolicyInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            inline_policies::delete_inline_policy(
+                store,
+                &account_id,
+                PolicyHolder::Group,
+                &input.group_name,
+                &input.policy_name,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    empty_response(context, "DeleteGroupPolicy", result)
+}
+
+pub(crate) async fn list_group_policies(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match ListGroupPoliciesInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            inline_policies::list_inline_policies(store, &account_id, PolicyHolder::Group, &input.group_name).await
+        }
+        Err(e) => Err(e.into()),
+    };
+    list_response(context, PolicyHolder::Group, result)
+}
+
+pub(crate) async fn put_role_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match PutRolePolicyInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            inline_policies::put_inline_policy(
+                store,
+                &account_id,
+                PolicyHolder::Role,
+                &input.role_name,
+                &input.policy_name,
+                &input.policy_document,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    empty_response(context, "PutRolePolicy", result)
+}
+
+pub(crate) async fn get_role_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match RolePolicyInput::from_parameters(context.parameters()) {
+        Ok(input) => inline_policies::get_inline_policy(
+            store,
+            &account_id,
+            PolicyHolder::Role,
+            &input.role_name,
+            &input.policy_name,
+        )
+        .await
+        .map(|policy| GetInlinePolicyOutput {
+            holder: PolicyHolder::Role,
+            holder_name: input.role_name,
+            policy,
+        }),
+        Err(e) => Err(e.into()),
+    };
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(&context.request_id().to_string())),
+        Err(e) => inline_policy_error(context, e),
+    }
+}
+
+pub(crate) async fn delete_role_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match RolePolicyInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            inline_policies::delete_inline_policy(
+                store,
+                &account_id,
+                PolicyHolder::Role,
+                &input.role_name,
+                &input.policy_name,
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+    empty_response(context, "DeleteRolePolicy", result)
+}
+
+pub(crate) async fn list_role_policies(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match ListRolePoliciesInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            inline_policies::list_inline_policies(store, &account_id, PolicyHolder::Role, &input.role_name).await
+        }
+        Err(e) => Err(e.into()),
+    };
+    list_response(context, PolicyHolder::Role, result)
+}
+
+fn empty_response(
+    context: &RequestContext,
+    operation: &str,
+    result: Result<(), InlinePolicyError>,
+) -> Result<Response<Body>, BoxError> {
+    match result {
+        Ok(()) => empty_xml_response(context, operation),
+        Err(e) => inline_policy_error(context, e),
+    }
+}
+
+fn list_response(
+    context: &RequestContext,
+    holder: PolicyHolder,
+    result: Result<Vec<String>, InlinePolicyError>,
+) -> Result<Response<Body>, BoxError> {
+    match result {
+        Ok(policy_names) => {
+            let output = ListInlinePoliciesOutput {
+                holder,
+                policy_names,
+            };
+            xml_response(context, output.to_xml(&context.request_id().to_string()))
+        }
+        Err(e) => inline_policy_error(context, e),
+    }
+}
+
+fn inline_policy_error(context: &RequestContext, e: InlinePolicyError) -> Result<Response<Body>, BoxError> {
+    error_response(context, e.code(), e.status(), &e)
+}
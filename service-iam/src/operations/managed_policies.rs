@@ -0,0 +1,59 @@
+use {
+    super::{empty_xml_response, error_response, xml_response},
+    hyper::{Body, Response},
+    scratchstack_service_common::{
+        context::RequestContext,
+        limits::Limits,
+        managed_policies::{self, CreatePolicyVersionInput, DeletePolicyInput, ManagedPolicyError},
+        operation::FromParameters,
+        store::ControlPlaneStore,
+    },
+    tower::BoxError,
+};
+
+pub(crate) async fn create_policy_version(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+    limits: &Limits,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match CreatePolicyVersionInput::from_parameters(context.parameters()) {
+        Ok(input) => {
+            managed_policies::create_policy_version(
+                store,
+                limits,
+                &account_id,
+                &input.policy_arn,
+                &input.policy_document,
+                input.set_as_default.unwrap_or(false),
+            )
+            .await
+        }
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(&context.request_id().to_string())),
+        Err(e) => managed_policy_error(context, e),
+    }
+}
+
+pub(crate) async fn delete_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let account_id = context.account_id().unwrap_or_default();
+    let result = match DeletePolicyInput::from_parameters(context.parameters()) {
+        Ok(input) => managed_policies::delete_policy(store, &account_id, &input.policy_arn).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(()) => empty_xml_response(context, "DeletePolicy"),
+        Err(e) => managed_policy_error(context, e),
+    }
+}
+
+fn managed_policy_error(context: &RequestContext, e: ManagedPolicyError) -> Result<Response<Body>, BoxError> {
+    error_response(context, e.code(), e.status(), &e)
+}
@@ -0,0 +1,187 @@
+mod access_keys;
+mod account_aliases;
+mod attached_policies;
+mod get_api_docs;
+mod inline_policies;
+mod managed_policies;
+mod roles;
+mod service_specific_credentials;
+mod simulation;
+mod ssh_public_keys;
+mod users;
+
+use {
+    crate::service::IAM_VERSION_20100508,
+    http::{header::HeaderValue, StatusCode},
+    hyper::{Body, Response},
+    log::error,
+    scratchstack_http_framework::RequestId,
+    scratchstack_service_common::{
+        access_key::AccessKeyPrefixes,
+        access_keys::{CreateAccessKeyInput, DeleteAccessKeyInput, ListAccessKeysInput},
+        account_aliases::{CreateAccountAliasInput, DeleteAccountAliasInput, ListAccountAliasesInput},
+        actions,
+        attached_policies::{
+            GroupPolicyAttachmentInput, ListAttachedGroupPoliciesInput, ListAttachedRolePoliciesInput,
+            ListAttachedUserPoliciesInput, RolePolicyAttachmentInput, UserPolicyAttachmentInput,
+        },
+        context::RequestContext,
+        inline_policies::{
+            GroupPolicyInput, ListGroupPoliciesInput, ListRolePoliciesInput, ListUserPoliciesInput,
+            PutGroupPolicyInput, PutRolePolicyInput, PutUserPolicyInput, RolePolicyInput, UserPolicyInput,
+        },
+        limits::Limits,
+        managed_policies::{CreatePolicyVersionInput, DeletePolicyInput},
+        operation::output::empty_query_response,
+        operations,
+        protocol::{self, AwsError, IAM_XML_NS},
+        roles::CreateRoleInput,
+        service_specific_credentials::{
+            CreateServiceSpecificCredentialInput, DeleteServiceSpecificCredentialInput,
+            ResetServiceSpecificCredentialInput,
+        },
+        simulation::{SimulateCustomPolicyInput, SimulatePrincipalPolicyInput},
+        ssh_public_keys::{
+            DeleteSshPublicKeyInput, ListSshPublicKeysInput, UpdateSshPublicKeyInput, UploadSshPublicKeyInput,
+        },
+        store::ControlPlaneStore,
+        users::{CreateUserInput, DeleteUserInput, GetUserInput, ListUsersInput, UpdateUserInput},
+    },
+    std::fmt::Display,
+    tower::BoxError,
+};
+
+use {
+    access_keys::{create_access_key, delete_access_key, list_access_keys},
+    account_aliases::{create_account_alias, delete_account_alias, list_account_aliases},
+    attached_policies::{
+        attach_group_policy, attach_role_policy, attach_user_policy, detach_group_policy, detach_role_policy,
+        detach_user_policy, list_attached_group_policies, list_attached_role_policies, list_attached_user_policies,
+    },
+    get_api_docs::get_api_docs,
+    inline_policies::{
+        delete_group_policy, delete_role_policy, delete_user_policy, get_group_policy, get_role_policy,
+        get_user_policy, list_group_policies, list_role_policies, list_user_policies, put_group_policy,
+        put_role_policy, put_user_policy,
+    },
+    managed_policies::{create_policy_version, delete_policy},
+    roles::create_role,
+    service_specific_credentials::{
+        create_service_specific_credential, delete_service_specific_credential, reset_service_specific_credential,
+    },
+    simulation::{simulate_custom_policy, simulate_principal_policy},
+    ssh_public_keys::{delete_ssh_public_key, list_ssh_public_keys, update_ssh_public_key, upload_ssh_public_key},
+    users::{create_user, delete_user, get_user, list_users, update_user},
+};
+
+operations! {
+    service: "iam", version: IAM_VERSION_20100508;
+
+    /// Run the IAM operation for `action`, or return `None` if IAM has no such operation.
+    pub(crate) async fn dispatch(
+        context: &RequestContext,
+        store: &dyn ControlPlaneStore,
+        limits: &Limits,
+        access_key_prefixes: &AccessKeyPrefixes,
+    ) -> Result<Response<Body>, BoxError>;
+
+    /// The operations [dispatch] runs, as reported by `GetApiDocs`.
+    pub(crate) fn api_docs();
+
+    actions::ATTACH_GROUP_POLICY: GroupPolicyAttachmentInput => attach_group_policy(context, store, limits),
+    actions::ATTACH_ROLE_POLICY: RolePolicyAttachmentInput => attach_role_policy(context, store, limits),
+    actions::ATTACH_USER_POLICY: UserPolicyAttachmentInput => attach_user_policy(context, store, limits),
+    actions::CREATE_ACCESS_KEY: CreateAccessKeyInput => create_access_key(context, store, access_key_prefixes),
+    actions::CREATE_ACCOUNT_ALIAS: CreateAccountAliasInput => create_account_alias(context, store),
+    actions::CREATE_POLICY_VERSION: CreatePolicyVersionInput => create_policy_version(context, store, limits),
+    actions::CREATE_ROLE: CreateRoleInput => create_role(context, store),
+    actions::CREATE_SERVICE_SPECIFIC_CREDENTIAL: CreateServiceSpecificCredentialInput =>
+        create_service_specific_credential(context, store),
+    actions::CREATE_USER: CreateUserInput => create_user(context, store),
+    actions::DELETE_ACCESS_KEY: DeleteAccessKeyInput => delete_access_key(context, store, access_key_prefixes),
+    actions::DELETE_ACCOUNT_ALIAS: DeleteAccountAliasInput => delete_account_alias(context, store),
+    actions::DELETE_GROUP_POLICY: GroupPolicyInput => delete_group_policy(context, store),
+    actions::DELETE_POLICY: DeletePolicyInput => delete_policy(context, store),
+    actions::DELETE_ROLE_POLICY: RolePolicyInput => delete_role_policy(context, store),
+    actions::DELETE_SSH_PUBLIC_KEY: DeleteSshPublicKeyInput => delete_ssh_public_key(context, store),
+    actions::DELETE_SERVICE_SPECIFIC_CREDENTIAL: DeleteServiceSpecificCredentialInput =>
+        delete_service_specific_credential(context, store),
+    actions::DELETE_USER: DeleteUserInput => delete_user(context, store),
+    actions::DELETE_USER_POLICY: UserPolicyInput => delete_user_policy(context, store),
+    actions::DETACH_GROUP_POLICY: GroupPolicyAttachmentInput => detach_group_policy(context, store),
+    actions::DETACH_ROLE_POLICY: RolePolicyAttachmentInput => detach_role_policy(context, store),
+    actions::DETACH_USER_POLICY: UserPolicyAttachmentInput => detach_user_policy(context, store),
+    actions::GET_IAM_API_DOCS => get_api_docs(context),
+    actions::GET_GROUP_POLICY: GroupPolicyInput => get_group_policy(context, store),
+    actions::GET_ROLE_POLICY: RolePolicyInput => get_role_policy(context, store),
+    actions::GET_USER: GetUserInput => get_user(context, store),
+    actions::GET_USER_POLICY: UserPolicyInput => get_user_policy(context, store),
+    actions::LIST_ACCESS_KEYS: ListAccessKeysInput => list_access_keys(context, store, access_key_prefixes),
+    actions::LIST_ACCOUNT_ALIASES: ListAccountAliasesInput => list_account_aliases(context, store),
+    actions::LIST_ATTACHED_GROUP_POLICIES: ListAttachedGroupPoliciesInput =>
+        list_attached_group_policies(context, store),
+    actions::LIST_ATTACHED_ROLE_POLICIES: ListAttachedRolePoliciesInput =>
+        list_attached_role_policies(context, store),
+    actions::LIST_ATTACHED_USER_POLICIES: ListAttachedUserPoliciesInput =>
+        list_attached_user_policies(context, store),
+    actions::LIST_GROUP_POLICIES: ListGroupPoliciesInput => list_group_policies(context, store),
+    actions::LIST_ROLE_POLICIES: ListRolePoliciesInput => list_role_policies(context, store),
+    actions::LIST_SSH_PUBLIC_KEYS: ListSshPublicKeysInput => list_ssh_public_keys(context, store),
+    actions::LIST_USER_POLICIES: ListUserPoliciesInput => list_user_policies(context, store),
+    actions::LIST_USERS: ListUsersInput => list_users(context, store),
+    actions::PUT_GROUP_POLICY: PutGroupPolicyInput => put_group_policy(context, store),
+    actions::PUT_ROLE_POLICY: PutRolePolicyInput => put_role_policy(context, store),
+    actions::PUT_USER_POLICY: PutUserPolicyInput => put_user_policy(context, store),
+    actions::RESET_SERVICE_SPECIFIC_CREDENTIAL: ResetServiceSpecificCredentialInput =>
+        reset_service_specific_credential(context, store),
+    actions::SIMULATE_CUSTOM_POLICY: SimulateCustomPolicyInput => simulate_custom_policy(context),
+    actions::SIMULATE_PRINCIPAL_POLICY: SimulatePrincipalPolicyInput => simulate_principal_policy(context, store),
+    actions::UPDATE_SSH_PUBLIC_KEY: UpdateSshPublicKeyInput => update_ssh_public_key(context, store),
+    actions::UPDATE_USER: UpdateUserInput => update_user(context, store),
+    actions::UPLOAD_SSH_PUBLIC_KEY: UploadSshPublicKeyInput => upload_ssh_public_key(context, store),
+}
+
+/// A successful query protocol response with the XML body `xml`.
+fn xml_response(context: &RequestContext, xml: String) -> Result<Response<Body>, BoxError> {
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", HeaderValue::from_static("text/xml"))
+        .header("X-Amzn-RequestId", context.request_id().to_string())
+        .body(Body::from(xml))
+        .map_err(Into::into)
+}
+
+/// A successful response to `operation`, which returns no result.
+fn empty_xml_response(context: &RequestContext, operation: &str) -> Result<Response<Body>, BoxError> {
+    xml_response(context, empty_query_response(IAM_XML_NS, operation, &context.request_id().to_string()))
+}
+
+/// An IAM error response. Server-side failures are logged, and reported to the caller without their details.
+fn error_response<E: Display>(
+    context: &RequestContext,
+    code: &str,
+    status: StatusCode,
+    e: &E,
+) -> Result<Response<Body>, BoxError> {
+    let error = if status.is_server_error() {
+        error!("{} {}: {}", context.request_id(), context.parameter("Action").unwrap_or_default(), e);
+        AwsError::receiver(
+            status,
+            code,
+            "The request processing has failed because of an unknown error, exception or failure.",
+        )
+    } else {
+        AwsError::sender(status, code, e.to_string())
+    };
+
+    protocol::IAM.response(&error, context.request_id())
+}
+
+pub(crate) fn security_token_invalid(request_id: RequestId) -> Result<Response<Body>, BoxError> {
+    let error = AwsError::sender(
+        StatusCode::FORBIDDEN,
+        "InvalidClientTokenId",
+        "The security token included in the request is invalid.",
+    );
+    protocol::IAM.response(&error, request_id)
+}
@@ -0,0 +1,26 @@
+use {
+    super::{error_response, xml_response},
+    hyper::{Body, Response},
+    scratchstack_service_common::{
+        context::RequestContext,
+        operation::FromParameters,
+        roles::{self, CreateRoleInput},
+        store::ControlPlaneStore,
+    },
+    tower::BoxError,
+};
+
+pub(crate) async fn create_role(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let result = match CreateRoleInput::from_parameters(context.parameters()) {
+        Ok(input) => roles::create_role(store, context, &input).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(&context.partition(), &context.request_id().to_string())),
+        Err(e) => error_response(context, e.code(), e.status(), &e),
+    }
+}
@@ -0,0 +1,66 @@
+use {
+    super::{empty_xml_response, error_response, xml_response},
+    hyper::{Body, Response},
+    scratchstack_service_common::{
+        context::RequestContext,
+        operation::FromParameters,
+        service_specific_credentials::{
+            self, CreateServiceSpecificCredentialInput, DeleteServiceSpecificCredentialInput,
+            ResetServiceSpecificCredentialInput, ServiceSpecificCredentialError,
+        },
+        store::ControlPlaneStore,
+    },
+    tower::BoxError,
+};
+
+pub(crate) async fn create_service_specific_credential(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let result = match CreateServiceSpecificCredentialInput::from_parameters(context.parameters()) {
+        Ok(input) => service_specific_credentials::create_service_specific_credential(store, context, &input).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(&context.request_id().to_string())),
+        Err(e) => service_specific_credential_error(context, e),
+    }
+}
+
+pub(crate) async fn reset_service_specific_credential(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let result = match ResetServiceSpecificCredentialInput::from_parameters(context.parameters()) {
+        Ok(input) => service_specific_credentials::reset_service_specific_credential(store, context, &input).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(&context.request_id().to_string())),
+        Err(e) => service_specific_credential_error(context, e),
+    }
+}
+
+pub(crate) async fn delete_service_specific_credential(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let result = match DeleteServiceSpecificCredentialInput::from_parameters(context.parameters()) {
+        Ok(input) => service_specific_credentials::delete_service_specific_credential(store, context, &input).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(()) => empty_xml_response(context, "DeleteServiceSpecificCredential"),
+        Err(e) => service_specific_credential_error(context, e),
+    }
+}
+
+fn service_specific_credential_error(
+    context: &RequestContext,
+    e: ServiceSpecificCredentialError,
+) -> Result<Response<Body>, BoxError> {
+    error_response(context, e.code(), e.status(), &e)
+}
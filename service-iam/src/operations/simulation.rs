@@ -0,0 +1,43 @@
+use {
+    super::{error_response, xml_response},
+    hyper::{Body, Response},
+    scratchstack_service_common::{
+        context::RequestContext,
+        operation::FromParameters,
+        simulation::{
+            self, SimulateCustomPolicyInput, SimulatePolicyOutput, SimulatePrincipalPolicyInput, SimulationError,
+        },
+        store::ControlPlaneStore,
+    },
+    tower::BoxError,
+};
+
+pub(crate) async fn simulate_principal_policy(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let result = match SimulatePrincipalPolicyInput::from_parameters(context.parameters()) {
+        Ok(input) => simulation::simulate_principal_policy(store, context, &input).await,
+        Err(e) => Err(e.into()),
+    };
+    simulation_response(context, "SimulatePrincipalPolicy", result)
+}
+
+pub(crate) async fn simulate_custom_policy(context: &RequestContext) -> Result<Response<Body>, BoxError> {
+    let result = match SimulateCustomPolicyInput::from_parameters(context.parameters()) {
+        Ok(input) => simulation::simulate_custom_policy(context, &input).await,
+        Err(e) => Err(e.into()),
+    };
+    simulation_response(context, "SimulateCustomPolicy", result)
+}
+
+fn simulation_response(
+    context: &RequestContext,
+    operation: &str,
+    result: Result<SimulatePolicyOutput, SimulationError>,
+) -> Result<Response<Body>, BoxError> {
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(operation, &context.request_id().to_string())),
+        Err(e) => error_response(context, e.code(), e.status(), &e),
+    }
+}
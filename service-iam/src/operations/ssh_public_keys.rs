@@ -0,0 +1,82 @@
+use {
+    super::{empty_xml_response, error_response, xml_response},
+    hyper::{Body, Response},
+    scratchstack_service_common::{
+        context::RequestContext,
+        operation::FromParameters,
+        ssh_public_keys::{
+            self, DeleteSshPublicKeyInput, ListSshPublicKeysInput, SshPublicKeyError, UpdateSshPublicKeyInput,
+            UploadSshPublicKeyInput,
+        },
+        store::ControlPlaneStore,
+    },
+    tower::BoxError,
+};
+
+pub(crate) async fn upload_ssh_public_key(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let result = match UploadSshPublicKeyInput::from_parameters(context.parameters()) {
+        Ok(input) => ssh_public_keys::upload_ssh_public_key(store, context, &input).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(&context.request_id().to_string())),
+        Err(e) => ssh_public_key_error(context, e),
+    }
+}
+
+pub(crate) async fn list_ssh_public_keys(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let result = match ListSshPublicKeysInput::from_parameters(context.parameters()) {
+        Ok(input) => ssh_public_keys::list_ssh_public_keys(store, context, &input).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(&context.request_id().to_string())),
+        Err(e) => ssh_public_key_error(context, e),
+    }
+}
+
+pub(crate) async fn update_ssh_public_key(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let result = match UpdateSshPublicKeyInput::from_parameters(context.parameters()) {
+        Ok(input) => ssh_public_keys::update_ssh_public_key(store, context, &input).await,
+        Err(e) => Err(e.into()),
+    };
+    empty_response(context, "UpdateSSHPublicKey", result)
+}
+
+pub(crate) async fn delete_ssh_public_key(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let result = match DeleteSshPublicKeyInput::from_parameters(context.parameters()) {
+        Ok(input) => ssh_public_keys::delete_ssh_public_key(store, context, &input).await,
+        Err(e) => Err(e.into()),
+    };
+    empty_response(context, "DeleteSSHPublicKey", result)
+}
+
+/// The response to `operation`, which has no result.
+fn empty_response(
+    context: &RequestContext,
+    operation: &str,
+    result: Result<(), SshPublicKeyError>,
+) -> Result<Response<Body>, BoxError> {
+    match result {
+        Ok(()) => empty_xml_response(context, operation),
+        Err(e) => ssh_public_key_error(context, e),
+    }
+}
+
+fn ssh_public_key_error(context: &RequestContext, e: SshPublicKeyError) -> Result<Response<Body>, BoxError> {
+    error_response(context, e.code(), e.status(), &e)
+}
@@ -0,0 +1,90 @@
+use {
+    super::{empty_xml_response, error_response, xml_response},
+    hyper::{Body, Response},
+    scratchstack_service_common::{
+        context::RequestContext,
+        operation::FromParameters,
+        store::ControlPlaneStore,
+        users::{self, CreateUserInput, DeleteUserInput, GetUserInput, ListUsersInput, UpdateUserInput, UserError},
+    },
+    tower::BoxError,
+};
+
+pub(crate) async fn create_user(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let result = match CreateUserInput::from_parameters(context.parameters()) {
+        Ok(input) => users::create_user(store, context, &input).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(&context.partition(), &context.request_id().to_string())),
+        Err(e) => user_error(context, e),
+    }
+}
+
+pub(crate) async fn get_user(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let result = match GetUserInput::from_parameters(context.parameters()) {
+        Ok(input) => users::get_user(store, context, &input).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(&context.partition(), &context.request_id().to_string())),
+        Err(e) => user_error(context, e),
+    }
+}
+
+pub(crate) async fn list_users(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let result = match ListUsersInput::from_parameters(context.parameters()) {
+        Ok(input) => users::list_users(store, context, &input).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(output) => xml_response(context, output.to_xml(&context.partition(), &context.request_id().to_string())),
+        Err(e) => user_error(context, e),
+    }
+}
+
+pub(crate) async fn delete_user(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let result = match DeleteUserInput::from_parameters(context.parameters()) {
+        Ok(input) => users::delete_user(store, context, &input).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(()) => empty_xml_response(context, "DeleteUser"),
+        Err(e) => user_error(context, e),
+    }
+}
+
+pub(crate) async fn update_user(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+) -> Result<Response<Body>, BoxError> {
+    let result = match UpdateUserInput::from_parameters(context.parameters()) {
+        Ok(input) => users::update_user(store, context, &input).await,
+        Err(e) => Err(e.into()),
+    };
+
+    match result {
+        Ok(_) => empty_xml_response(context, "UpdateUser"),
+        Err(e) => user_error(context, e),
+    }
+}
+
+fn user_error(context: &RequestContext, e: UserError) -> Result<Response<Body>, BoxError> {
+    error_response(context, e.code(), e.status(), &e)
+}
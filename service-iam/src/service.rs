@@ -1,19 +1,120 @@
 use {
-    http::{header::HeaderValue, StatusCode},
+    crate::operations,
+    http::StatusCode,
     hyper::{service::Service, Body, Request, Response},
+    log::warn,
+    scratchstack_aws_signature::{canonical::get_content_type_and_charset, signature::IntoRequestBytes},
+    scratchstack_http_framework::RequestId,
+    scratchstack_service_common::{
+        access_key::AccessKeyPrefixes,
+        authorizer::RequestResource,
+        context::RequestContext,
+        deprecation::{add_warnings, Deprecation, Deprecations},
+        limits::Limits,
+        parameters::{is_query_only, request_parameters},
+        protocol::{self, AwsError},
+        response_cache::{is_read_only, ResponseCache},
+        store::{ControlPlaneStore, PolicyHolder},
+    },
     std::{
         fmt::Debug,
         future::Future,
         pin::Pin,
+        sync::Arc,
         task::{Context, Poll},
     },
     tower::BoxError,
 };
 
+/// Content-Type string for HTML forms
+const APPLICATION_X_WWW_FORM_URLENCODED: &str = "application/x-www-form-urlencoded";
+
 pub const IAM_XML_NS: &str = "https://iam.amazonaws.com/doc/2010-05-08/";
 
+pub const IAM_VERSION_20100508: &str = "2010-05-08";
+
+/// Operations and parameters being phased out; none yet. See [scratchstack_service_common::deprecation].
+pub const IAM_DEPRECATIONS: &[Deprecation] = &[];
+
+/// Actions that are not authorized against identity policies.
+pub const IAM_UNAUTHORIZED_ACTIONS: &[&str] = &["GetApiDocs"];
+
+/// Actions that act on the calling user when no `UserName` is given.
+const CALLER_USER_ACTIONS: &[&str] =
+    &["CreateAccessKey", "DeleteAccessKey", "GetUser", "ListAccessKeys", "ListSSHPublicKeys"];
+
+/// The resource an IAM request acts on: the principal being simulated, or the role, group or user named in its
+/// parameters, in that order, with its path. Actions that default to the caller act on it when no user is named.
+/// Otherwise the managed policy is the resource, and requests that name none act on `*`. A `PolicyArn` being
+/// attached or detached is not the resource; policies see it as `iam:PolicyARN`.
+pub fn request_resource(context: &RequestContext) -> RequestResource {
+    // SimulatePrincipalPolicy names its principal by ARN, path included.
+    if let Some(arn) = context.parameter("PolicySourceArn") {
+        return RequestResource::Arn(arn.to_string());
+    }
+
+    for (parameter, holder) in
+        [("RoleName", PolicyHolder::Role), ("GroupName", PolicyHolder::Group), ("UserName", PolicyHolder::User)]
+    {
+        if let Some(name) = context.parameter(parameter) {
+            return RequestResource::Entity(holder, name.to_string());
+        }
+    }
+
+    if context.parameter("Action").map(|action| CALLER_USER_ACTIONS.contains(&action)).unwrap_or(false) {
+        return RequestResource::Caller;
+    }
+
+    match context.parameter("PolicyArn") {
+        Some(arn) => RequestResource::Arn(arn.to_string()),
+        None => RequestResource::Any,
+    }
+}
+
 #[derive(Clone, Debug)]
-pub struct IamService {}
+pub struct IamService {
+    store: Arc<dyn ControlPlaneStore>,
+    access_key_prefixes: Arc<AccessKeyPrefixes>,
+    limits: Limits,
+    response_cache: Option<Arc<ResponseCache>>,
+    deprecations: Option<Arc<Deprecations>>,
+}
+
+impl IamService {
+    pub fn new(store: Arc<dyn ControlPlaneStore>) -> Self {
+        Self {
+            store,
+            access_key_prefixes: Arc::new(AccessKeyPrefixes::default()),
+            limits: Limits::defaults(),
+            response_cache: None,
+            deprecations: None,
+        }
+    }
+
+    /// Generate access keys with, and accept, the prefixes of `access_key_prefixes` instead of `AKIA`.
+    pub fn with_access_key_prefixes(mut self, access_key_prefixes: AccessKeyPrefixes) -> Self {
+        self.access_key_prefixes = Arc::new(access_key_prefixes);
+        self
+    }
+
+    /// Enforce the per-account quotas in `limits` rather than the defaults.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Reuse responses to read operations from `response_cache`.
+    pub fn with_response_cache(mut self, response_cache: ResponseCache) -> Self {
+        self.response_cache = Some(Arc::new(response_cache));
+        self
+    }
+
+    /// Warn about, or reject, requests that use one of the deprecations in `deprecations`.
+    pub fn with_deprecations(mut self, deprecations: Arc<Deprecations>) -> Self {
+        self.deprecations = Some(deprecations);
+        self
+    }
+}
 
 impl Service<Request<Body>> for IamService {
     type Response = Response<Body>;
@@ -24,13 +125,388 @@ impl Service<Request<Body>> for IamService {
         Poll::Ready(Ok(()))
     }
 
-    fn call(&mut self, _req: Request<Body>) -> Self::Future {
-        Box::pin(async {
-            Ok(Response::builder()
-                .status(StatusCode::OK)
-                .header("Content-Type", HeaderValue::from_static("text/plain"))
-                .body(Body::from("Hello IAM"))
-                .unwrap())
+    fn call(&mut self, req: Request<Body>) -> Self::Future {
+        let store = self.store.clone();
+        let access_key_prefixes = self.access_key_prefixes.clone();
+        let limits = self.limits.clone();
+        let response_cache = self.response_cache.clone();
+        let deprecations = self.deprecations.clone();
+        Box::pin(async move {
+            let (mut parts, body) = req.into_parts();
+            let request_id = match parts.extensions.get::<RequestId>() {
+                Some(request_id) => *request_id,
+                None => {
+                    let new_request_id = RequestId::new();
+                    parts.extensions.insert(new_request_id);
+                    new_request_id
+                }
+            };
+
+            let query = parts.uri.query().unwrap_or("").to_string();
+            let content_type = get_content_type_and_charset(&parts.headers);
+            let is_form =
+                content_type.as_ref().map(|ctc| ctc.content_type == APPLICATION_X_WWW_FORM_URLENCODED).unwrap_or(false);
+
+            if content_type.is_some() && !is_form && !is_query_only(&parts.method) {
+                let error = AwsError::sender(
+                    StatusCode::BAD_REQUEST,
+                    "InvalidRequest",
+                    format!("Unsupported content type; expected {APPLICATION_X_WWW_FORM_URLENCODED}"),
+                );
+                return protocol::AWS_FAULT.response(&error, request_id);
+            }
+
+            // GET and HEAD bodies are read whatever their content type, so a non-empty one can be rejected.
+            let body = if is_form || is_query_only(&parts.method) {
+                match body.into_request_bytes().await {
+                    Ok(bytes) => Some(bytes),
+                    Err(e) => {
+                        warn!("{} Error reading request body: {}", request_id, e);
+                        let error = AwsError::receiver(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            "ServiceFailure",
+                            "The request processing has failed because of an unknown error, exception or failure.",
+                        );
+                        return protocol::IAM.response(&error, request_id);
+                    }
+                }
+            } else {
+                None
+            };
+
+            let parameters = match request_parameters(&parts.method, &query, body.as_deref()) {
+                Ok(parameters) => parameters,
+                Err(e) => {
+                    let error = AwsError::sender(StatusCode::BAD_REQUEST, "InvalidRequest", e.to_string());
+                    return protocol::AWS_FAULT.response(&error, request_id);
+                }
+            };
+
+            // Action is required.
+            let action = match parameters.get("Action") {
+                Some(action) => action.clone(),
+                None => {
+                    let error = AwsError::sender(
+                        StatusCode::BAD_REQUEST,
+                        "InvalidRequest",
+                        "Missing required parameter: Action",
+                    );
+                    return protocol::AWS_FAULT.response(&error, request_id);
+                }
+            };
+
+            let version =
+                parameters.get("Version").map(Clone::clone).unwrap_or_else(|| "NO_VERSION_SPECIFIED".to_string());
+
+            let context = match RequestContext::from_parts(&parts, parameters) {
+                Some(context) => context,
+                // The framework should have rejected unauthenticated requests already.
+                None => return operations::security_token_invalid(request_id),
+            };
+
+            let deprecated = match deprecations.as_ref().map(|deprecations| deprecations.check(&context, &action)) {
+                None => Vec::new(),
+                Some(Ok(deprecated)) => deprecated,
+                Some(Err(error)) => return protocol::IAM.response(&error, request_id),
+            };
+
+            if let Some(response) = response_cache
+                .as_ref()
+                .filter(|cache| cache.is_cacheable(&action))
+                .and_then(|cache| cache.get(&context))
+            {
+                return Ok(response);
+            }
+
+            let result = match version.as_str() {
+                IAM_VERSION_20100508 => {
+                    operations::dispatch(&action, &context, store.as_ref(), &limits, &access_key_prefixes).await
+                }
+                _ => None,
+            };
+            let result = match result {
+                Some(result) => result,
+                None => {
+                    let error = AwsError::sender(
+                        StatusCode::BAD_REQUEST,
+                        "InvalidAction",
+                        format!("Could not find operation {action} for version {version}"),
+                    );
+                    protocol::AWS_FAULT.response(&error, request_id)
+                }
+            };
+            let result = result.map(|response| add_warnings(response, &deprecated));
+
+            let response_cache = match response_cache {
+                Some(response_cache) => response_cache,
+                None => return result,
+            };
+            let response = result?;
+            if !response.status().is_success() {
+                Ok(response)
+            } else if response_cache.is_cacheable(&action) {
+                response_cache.insert(&context, response).await
+            } else {
+                if !is_read_only(&action) {
+                    if let Some(account_id) = context.account_id() {
+                        response_cache.invalidate_account(&account_id);
+                    }
+                }
+                Ok(response)
+            }
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{request_resource, IamService},
+        hyper::{body::to_bytes, service::Service, Body, Request},
+        pretty_assertions::assert_eq,
+        scratchstack_aws_principal::{Principal, PrincipalIdentity, User},
+        scratchstack_service_common::{
+            actions::ACTIONS,
+            authorizer::RequestResource,
+            context::RequestContext,
+            store::{MemoryStore, PolicyHolder},
+        },
+        std::sync::Arc,
+    };
+
+    /// A policy document, URL-encoded as IAM returns it.
+    const DOCUMENT: &str =
+        "%7B%22Version%22%3A%222012-10-17%22%2C%22Statement%22%3A%5B%7B%22Effect%22%3A%22Allow%22%2C\
+                            %22Action%22%3A%22s3%3AGetObject%22%2C%22Resource%22%3A%22%2A%22%7D%5D%7D";
+
+    /// A trust policy letting the account assume a role, URL-encoded.
+    const TRUST_POLICY: &str =
+        "%7B%22Version%22%3A%222012-10-17%22%2C%22Statement%22%3A%5B%7B%22Effect%22%3A%22Allow%22\
+                                %2C%22Principal%22%3A%7B%22AWS%22%3A%22123456789012%22%7D%2C%22Action%22%3A\
+                                %22sts%3AAssumeRole%22%7D%5D%7D";
+
+    async fn call(service: &mut IamService, body: &str) -> (u16, String) {
+        let mut request = Request::post("/")
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .body(Body::from(body.to_string()))
+            .unwrap();
+        let admin = User::new("aws", "123456789012", "/", "Admin").unwrap();
+        request.extensions_mut().insert(Principal::from(vec![PrincipalIdentity::from(admin)]));
+
+        let response = service.call(request).await.unwrap();
+        let status = response.status().as_u16();
+        let body = to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_user_lifecycle() {
+        let mut service = IamService::new(Arc::new(MemoryStore::new()));
+
+        let (status, body) = call(&mut service, "Action=CreateUser&Version=2010-05-08&UserName=Alice").await;
+        assert_eq!(status, 200, "{body}");
+        assert!(body.contains("<Arn>arn:aws:iam::123456789012:user/Alice</Arn>"), "{body}");
+
+        let (status, body) = call(&mut service, "Action=CreateUser&Version=2010-05-08&UserName=alice").await;
+        assert_eq!(status, 409);
+        assert!(body.contains("<Code>EntityAlreadyExists</Code>"), "{body}");
+
+        let (status, body) = call(&mut service, "Action=GetUser&Version=2010-05-08&UserName=ALICE").await;
+        assert_eq!(status, 200, "{body}");
+        assert!(body.contains("<GetUserResult><User><Path>/</Path><UserName>Alice</UserName>"), "{body}");
+
+        let (status, body) = call(&mut service, "Action=ListUsers&Version=2010-05-08").await;
+        assert_eq!(status, 200, "{body}");
+        assert!(body.contains("<Users><member><Path>/</Path><UserName>Alice</UserName>"), "{body}");
+        assert!(body.contains("<IsTruncated>false</IsTruncated>"), "{body}");
+
+        let (status, body) = call(&mut service, "Action=CreateAccessKey&Version=2010-05-08&UserName=Alice").await;
+        assert_eq!(status, 200, "{body}");
+        assert!(body.contains("<Status>Active</Status><SecretAccessKey>"), "{body}");
+        let start = body.find("<AccessKeyId>").unwrap() + "<AccessKeyId>".len();
+        let access_key_id = body[start..start + 20].to_string();
+        assert!(access_key_id.starts_with("AKIA"), "{body}");
+
+        let (status, body) = call(&mut service, "Action=ListAccessKeys&Version=2010-05-08&UserName=Alice").await;
+        assert_eq!(status, 200, "{body}");
+        assert!(body.contains(&format!("<AccessKeyId>{access_key_id}</AccessKeyId>")), "{body}");
+        assert!(!body.contains("SecretAccessKey"), "{body}");
+
+        let (status, body) = call(&mut service, "Action=DeleteUser&Version=2010-05-08&UserName=Alice").await;
+        assert_eq!(status, 409);
+        assert!(body.contains("<Code>DeleteConflict</Code>"), "{body}");
+
+        let request = format!("Action=DeleteAccessKey&Version=2010-05-08&UserName=Alice&AccessKeyId={access_key_id}");
+        let (status, body) = call(&mut service, &request).await;
+        assert_eq!(status, 200, "{body}");
+
+        let (status, body) = call(&mut service, "Action=DeleteUser&Version=2010-05-08&UserName=Alice").await;
+        assert_eq!(status, 200, "{body}");
+        assert!(body.starts_with("<DeleteUserResponse"), "{body}");
+
+        let (status, body) = call(&mut service, "Action=GetUser&Version=2010-05-08&UserName=Alice").await;
+        assert_eq!(status, 404);
+        assert!(body.contains("<Code>NoSuchEntity</Code>"), "{body}");
+        assert!(body.contains("The user with name Alice cannot be found."), "{body}");
+
+        let (status, body) = call(&mut service, "Action=CreateUser&Version=2010-05-08").await;
+        assert_eq!(status, 400);
+        assert!(body.contains("<Code>ValidationError</Code>"), "{body}");
+
+        let (status, body) = call(&mut service, "Action=GetApiDocs&Version=2010-05-08").await;
+        assert_eq!(status, 200, "{body}");
+        let docs: serde_json::Value = serde_json::from_str(&body).unwrap();
+        let operations = docs["operations"].as_array().unwrap();
+        let names = operations.iter().map(|operation| operation["name"].as_str().unwrap()).collect::<Vec<_>>();
+
+        // Every registered IAM action is documented.
+        let mut registered = ACTIONS
+            .iter()
+            .filter(|action| action.service_name == "iam")
+            .map(|action| action.action_name)
+            .collect::<Vec<_>>();
+        registered.sort_unstable();
+        assert_eq!(names, registered);
+        let create_user = operations.iter().find(|operation| operation["name"] == "CreateUser").unwrap();
+        assert_eq!(create_user["parameters"][0]["name"], "UserName");
+        assert_eq!(create_user["parameters"][0]["required"], true);
+
+        let (status, body) = call(&mut service, "Action=CreateSAMLProvider&Version=2010-05-08").await;
+        assert_eq!(status, 400);
+        assert!(body.contains("<Code>InvalidAction</Code>"), "{body}");
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_inline_policies() {
+        let mut service = IamService::new(Arc::new(MemoryStore::new()));
+        let (status, body) = call(&mut service, "Action=CreateUser&Version=2010-05-08&UserName=Alice").await;
+        assert_eq!(status, 200, "{body}");
+
+        let request = format!(
+            "Action=PutUserPolicy&Version=2010-05-08&UserName=alice&PolicyName=ReadObjects&PolicyDocument={DOCUMENT}"
+        );
+        let (status, body) = call(&mut service, &request).await;
+        assert_eq!(status, 200, "{body}");
+        assert!(body.starts_with("<PutUserPolicyResponse"), "{body}");
+
+        let request = "Action=GetUserPolicy&Version=2010-05-08&UserName=Alice&PolicyName=ReadObjects";
+        let (status, body) = call(&mut service, request).await;
+        assert_eq!(status, 200, "{body}");
+        assert!(body.contains("<GetUserPolicyResult><UserName>Alice</UserName><PolicyName>ReadObjects</PolicyName>"));
+        assert!(body.contains(&format!("<PolicyDocument>{DOCUMENT}</PolicyDocument>")), "{body}");
+
+        let (status, body) = call(&mut service, "Action=ListUserPolicies&Version=2010-05-08&UserName=Alice").await;
+        assert_eq!(status, 200, "{body}");
+        assert!(body.contains("<PolicyNames><member>ReadObjects</member></PolicyNames>"), "{body}");
+
+        let request = "Action=PutUserPolicy&Version=2010-05-08&UserName=Alice&PolicyName=Bad\
+                       &PolicyDocument=%7B%22Statement%22%3A1%7D";
+        let (status, body) = call(&mut service, request).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("<Code>MalformedPolicyDocument</Code>"), "{body}");
+
+        let request = "Action=DeleteUserPolicy&Version=2010-05-08&UserName=Alice&PolicyName=ReadObjects";
+        let (status, body) = call(&mut service, request).await;
+        assert_eq!(status, 200, "{body}");
+        assert!(body.starts_with("<DeleteUserPolicyResponse"), "{body}");
+
+        let (status, body) = call(&mut service, "Action=ListUserPolicies&Version=2010-05-08&UserName=Alice").await;
+        assert_eq!(status, 200, "{body}");
+        assert!(body.contains("<PolicyNames></PolicyNames>"), "{body}");
+
+        for request in [
+            "Action=GetGroupPolicy&Version=2010-05-08&GroupName=Staff&PolicyName=ReadObjects",
+            "Action=ListRolePolicies&Version=2010-05-08&RoleName=Deployer",
+        ] {
+            let (status, body) = call(&mut service, request).await;
+            assert_eq!(status, 404);
+            assert!(body.contains("<Code>NoSuchEntity</Code>"), "{body}");
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_dispatch_registry() {
+        let mut service = IamService::new(Arc::new(MemoryStore::new()));
+        for action in ACTIONS.iter().filter(|action| action.service_name == "iam") {
+            let (_, body) = call(&mut service, &format!("Action={}&Version=2010-05-08", action.action_name)).await;
+            assert!(!body.contains("<Code>InvalidAction</Code>"), "{} is not dispatched: {body}", action.action_name);
+        }
+    }
+
+    #[test_log::test(tokio::test)]
+    async fn test_user_and_role_updates() {
+        let mut service = IamService::new(Arc::new(MemoryStore::new()));
+        let (status, body) = call(&mut service, "Action=CreateUser&Version=2010-05-08&UserName=Alice").await;
+        assert_eq!(status, 200, "{body}");
+
+        let request = "Action=UpdateUser&Version=2010-05-08&UserName=Alice&NewUserName=Alicia&NewPath=%2Fstaff%2F";
+        let (status, body) = call(&mut service, request).await;
+        assert_eq!(status, 200, "{body}");
+        assert!(body.starts_with("<UpdateUserResponse"), "{body}");
+        let (status, body) = call(&mut service, "Action=GetUser&Version=2010-05-08&UserName=Alicia").await;
+        assert_eq!(status, 200, "{body}");
+        assert!(body.contains("<Arn>arn:aws:iam::123456789012:user/staff/Alicia</Arn>"), "{body}");
+
+        let request =
+            format!("Action=CreateRole&Version=2010-05-08&RoleName=Deployer&AssumeRolePolicyDocument={TRUST_POLICY}");
+        let (status, body) = call(&mut service, &request).await;
+        assert_eq!(status, 200, "{body}");
+        assert!(body.contains("<Arn>arn:aws:iam::123456789012:role/Deployer</Arn>"), "{body}");
+        let (status, body) = call(&mut service, &request).await;
+        assert_eq!(status, 409);
+        assert!(body.contains("<Code>EntityAlreadyExists</Code>"), "{body}");
+
+        let request = format!(
+            "Action=CreatePolicyVersion&Version=2010-05-08\
+             &PolicyArn=arn%3Aaws%3Aiam%3A%3A123456789012%3Apolicy%2FDeploy&PolicyDocument={DOCUMENT}"
+        );
+        let (status, body) = call(&mut service, &request).await;
+        assert_eq!(status, 404);
+        assert!(body.contains("Policy arn:aws:iam::123456789012:policy/Deploy was not found."), "{body}");
+
+        let request = "Action=DeletePolicy&Version=2010-05-08&PolicyArn=arn%3Aaws%3As3%3A%3A%3Aexample-bucket";
+        let (status, body) = call(&mut service, request).await;
+        assert_eq!(status, 400);
+        assert!(body.contains("<Code>InvalidInput</Code>"), "{body}");
+    }
+
+    #[test_log::test]
+    fn test_request_resource() {
+        let resource = |parameters: &[(&str, &str)]| {
+            let admin = User::new("aws", "123456789012", "/", "Admin").unwrap();
+            let context = RequestContext::builder()
+                .principal(Principal::from(vec![PrincipalIdentity::from(admin)]))
+                .parameters(parameters.iter().map(|(key, value)| (key.to_string(), value.to_string())).collect())
+                .build()
+                .unwrap();
+            request_resource(&context)
+        };
+
+        // The entity a policy is attached to is the resource, not the policy.
+        assert_eq!(
+            resource(&[
+                ("Action", "AttachUserPolicy"),
+                ("UserName", "Alice"),
+                ("PolicyArn", "arn:aws:iam::aws:policy/ReadOnlyAccess")
+            ]),
+            RequestResource::Entity(PolicyHolder::User, "Alice".to_string())
+        );
+        assert_eq!(
+            resource(&[("Action", "AttachRolePolicy"), ("RoleName", "Deployer"), ("UserName", "Alice")]),
+            RequestResource::Entity(PolicyHolder::Role, "Deployer".to_string())
+        );
+        assert_eq!(resource(&[("Action", "ListAccessKeys")]), RequestResource::Caller);
+        assert_eq!(
+            resource(&[
+                ("Action", "SimulatePrincipalPolicy"),
+                ("PolicySourceArn", "arn:aws:iam::123456789012:user/staff/Alice")
+            ]),
+            RequestResource::Arn("arn:aws:iam::123456789012:user/staff/Alice".to_string())
+        );
+        assert_eq!(resource(&[("Action", "ListUsers")]), RequestResource::Any);
+        assert_eq!(
+            resource(&[("Action", "DeletePolicy"), ("PolicyArn", "arn:aws:iam::123456789012:policy/Deploy")]),
+            RequestResource::Arn("arn:aws:iam::123456789012:policy/Deploy".to_string())
+        );
+    }
+}
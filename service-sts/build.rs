@@ -0,0 +1,8 @@
+use vergen::{vergen, Config};
+
+fn main() {
+    // Outside of a git checkout the commit is unknown; build anyway and report it as such.
+    if let Err(e) = vergen(Config::default()) {
+        println!("cargo:warning=Unable to generate build information: {e}");
+    }
+}
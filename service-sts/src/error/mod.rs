@@ -1,3 +1,3 @@
 mod service;
 
-pub(crate) use self::service::ServiceError;
+pub use self::service::ServiceError;
@@ -1,6 +1,10 @@
 use {
-    hyper::Error as HyperError,
+    hyper::{http::uri::InvalidUri, Error as HyperError},
     scratchstack_aws_signature::SignatureError,
+    scratchstack_service_common::{
+        audit::AuditError, outbound::OutboundError, schema::SchemaError, signing::SigningKeyProviderError,
+        tls::TlsError,
+    },
     sqlx::Error as SqlxError,
     std::{
         error::Error,
@@ -10,20 +14,32 @@ use {
 };
 
 #[derive(Debug)]
-pub(crate) enum ServiceError {
+pub enum ServiceError {
+    Audit(AuditError),
     Hyper(HyperError),
     IO(IOError),
+    InvalidUri(InvalidUri),
+    Outbound(OutboundError),
+    Schema(SchemaError),
     SignatureError(SignatureError),
+    SigningKeyProvider(SigningKeyProviderError),
     SqlxError(SqlxError),
+    Tls(TlsError),
 }
 
 impl Error for ServiceError {
     fn source(&self) -> Option<&(dyn Error + 'static)> {
         match self {
+            Self::Audit(e) => Some(e),
             Self::Hyper(e) => Some(e),
             Self::IO(e) => Some(e),
+            Self::InvalidUri(e) => Some(e),
+            Self::Outbound(e) => Some(e),
+            Self::Schema(e) => Some(e),
             Self::SignatureError(e) => Some(e),
+            Self::SigningKeyProvider(e) => Some(e),
             Self::SqlxError(e) => Some(e),
+            Self::Tls(e) => Some(e),
         }
     }
 }
@@ -31,14 +47,26 @@ impl Error for ServiceError {
 impl Display for ServiceError {
     fn fmt(&self, f: &mut Formatter) -> FmtResult {
         match self {
+            Self::Audit(e) => write!(f, "Audit error: {e}"),
             Self::Hyper(e) => write!(f, "Hyper error: {e}"),
             Self::IO(e) => write!(f, "IO error: {e}"),
+            Self::InvalidUri(e) => write!(f, "Invalid URI: {e}"),
+            Self::Outbound(e) => write!(f, "Outbound request error: {e}"),
+            Self::Schema(e) => write!(f, "Schema error: {e}"),
             Self::SignatureError(e) => write!(f, "Signature error: {e}"),
+            Self::SigningKeyProvider(e) => write!(f, "Signing key provider error: {e}"),
             Self::SqlxError(e) => write!(f, "Sqlx error: {e}"),
+            Self::Tls(e) => write!(f, "TLS error: {e}"),
         }
     }
 }
 
+impl From<AuditError> for ServiceError {
+    fn from(e: AuditError) -> Self {
+        Self::Audit(e)
+    }
+}
+
 impl From<HyperError> for ServiceError {
     fn from(e: HyperError) -> Self {
         Self::Hyper(e)
@@ -51,14 +79,44 @@ impl From<IOError> for ServiceError {
     }
 }
 
+impl From<InvalidUri> for ServiceError {
+    fn from(e: InvalidUri) -> Self {
+        Self::InvalidUri(e)
+    }
+}
+
+impl From<OutboundError> for ServiceError {
+    fn from(e: OutboundError) -> Self {
+        Self::Outbound(e)
+    }
+}
+
+impl From<SchemaError> for ServiceError {
+    fn from(e: SchemaError) -> Self {
+        Self::Schema(e)
+    }
+}
+
 impl From<SignatureError> for ServiceError {
     fn from(e: SignatureError) -> Self {
         Self::SignatureError(e)
     }
 }
 
+impl From<SigningKeyProviderError> for ServiceError {
+    fn from(e: SigningKeyProviderError) -> Self {
+        Self::SigningKeyProvider(e)
+    }
+}
+
 impl From<SqlxError> for ServiceError {
     fn from(e: SqlxError) -> Self {
         Self::SqlxError(e)
     }
 }
+
+impl From<TlsError> for ServiceError {
+    fn from(e: TlsError) -> Self {
+        Self::Tls(e)
+    }
+}
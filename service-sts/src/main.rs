@@ -1,32 +1,23 @@
-pub(crate) mod error;
-pub(crate) mod model;
-pub(crate) mod operations;
-pub(crate) mod service;
-
 use {
-    crate::{
-        error::ServiceError,
-        service::{StsService, STS_XML_NS},
-    },
     getopts::Options,
-    http::method::Method,
-    hyper::server::Server as HyperServer,
     log::{debug, error, info},
-    scratchstack_config::{service::ResolvedSts, Config},
-    scratchstack_http_framework::{GetSigningKeyFromDatabase, SpawnService, TlsIncoming, XmlErrorMapper},
+    scratchstack_config::Config,
+    scratchstack_service_common::{
+        audit,
+        config::{read_layered_config, ServiceOptions},
+        health::cancel_on_termination,
+        region::{Partition, Region},
+    },
+    scratchstack_service_sts::{run_server_from_config, startup_summary, CancellationToken},
     std::{
         env,
         io::{self, Write},
-        iter::Iterator,
         process::exit,
-        sync::Arc,
     },
-    tokio::{net::TcpListener, runtime::Builder as RuntimeBuilder},
-    tokio_rustls::TlsAcceptor,
+    tokio::runtime::Builder as RuntimeBuilder,
 };
 
 const DEFAULT_CONFIG_FILENAME: &str = "scratchstack.cfg";
-// const CONTENT_LENGTH_LIMIT: u64 = 10 << 20;
 
 #[allow(unused_must_use)]
 fn print_usage(stream: &mut dyn Write, program: &str, opts: Options) {
@@ -35,13 +26,15 @@ fn print_usage(stream: &mut dyn Write, program: &str, opts: Options) {
 }
 
 fn main() {
-    env_logger::init();
+    audit::init_logging();
     let args: Vec<String> = env::args().collect();
     let program = args[0].clone();
 
     let mut opts = Options::new();
     opts.optopt("c", "config", "configuration file", "FILENAME");
     opts.optflag("h", "help", "print this usage information");
+    opts.optopt("", "profile", "configuration profile to apply (default: $SCRATCHSTACK_PROFILE)", "NAME");
+    opts.optflag("", "print-endpoints", "print the endpoints, database and credential hints as JSON, then exit");
 
     let matches = match opts.parse(&args[1..]) {
         Ok(m) => m,
@@ -56,6 +49,8 @@ fn main() {
         return;
     }
 
+    // An explicitly named file must exist; without one, settings may come entirely from the environment.
+    let config_required = matches.opt_present("c");
     let config_filename = match matches.opt_str("c") {
         Some(filename) => filename,
         None => DEFAULT_CONFIG_FILENAME.to_string(),
@@ -69,13 +64,24 @@ fn main() {
 
     // Parse the configuration.
     info!("Reading configuration from {}", config_filename);
-    let config = match Config::read_file(&config_filename) {
-        Ok(c) => c,
+    let profile = matches.opt_str("profile");
+    if let Some(profile) = &profile {
+        info!("Applying configuration profile {}", profile);
+    }
+    let config_value = match read_layered_config(&config_filename, config_required, profile.as_deref()) {
+        Ok(v) => v,
         Err(e) => {
             error!("Unable to read configuration file {}: {}", config_filename, e);
             exit(2);
         }
     };
+    let config: Config = match config_value.clone().try_into() {
+        Ok(c) => c,
+        Err(e) => {
+            error!("Error in configuration file {}: {}", config_filename, e);
+            exit(2);
+        }
+    };
     info!("Configuration read from {}", config_filename);
     debug!("Configuration: {:?}", config);
 
@@ -107,6 +113,35 @@ fn main() {
     info!("Configuration resolved");
     debug!("Resolved configuration: {:?}", config);
 
+    let options = match ServiceOptions::from_value(&config_value, "sts") {
+        Ok(o) => o,
+        Err(e) => {
+            error!("Error in configuration file {}: {}", config_filename, e);
+            exit(2);
+        }
+    };
+    debug!("Service options: {:?}", options);
+
+    let region: Region = match config.service.region.parse() {
+        Ok(r) => r,
+        Err(e) => {
+            error!("Error in configuration file {}: {}", config_filename, e);
+            exit(2);
+        }
+    };
+    let partition: Partition = match config.service.partition.parse() {
+        Ok(p) => p,
+        Err(e) => {
+            error!("Error in configuration file {}: {}", config_filename, e);
+            exit(2);
+        }
+    };
+
+    if matches.opt_present("print-endpoints") {
+        println!("{}", startup_summary(&config, &options, &region).to_json());
+        return;
+    }
+
     info!("Creating runtime");
     let runtime = match RuntimeBuilder::new_multi_thread()
         .worker_threads(config.service.threads)
@@ -121,60 +156,7 @@ fn main() {
         }
     };
 
-    println!("{:#?}", runtime.block_on(run_server_from_config(config)));
-}
-
-async fn run_server_from_config(config: ResolvedSts) -> Result<(), ServiceError> {
-    let pool = config.database.pool_options.connect(&config.database.url).await?;
-    let pool = Arc::new(pool);
-    let region = config.service.region.clone();
-    let allowed_request_methods = vec![Method::GET, Method::POST, Method::PUT];
-    let allowed_content_types = vec!["application/x-www-form-urlencoded".to_string()];
-    let gsk = GetSigningKeyFromDatabase::new(pool, &config.service.partition, &config.service.region, "sts");
-    let service_impl = StsService {};
-    let error_mapper = XmlErrorMapper::new(STS_XML_NS);
-
-    match config.service.tls {
-        Some(t) => {
-            info!("TLS configuration detected; creating acceptor and listener");
-            let acceptor = TlsAcceptor::from(Arc::new(t));
-            let tcp_listener = TcpListener::bind(&config.service.address).await?;
-            let incoming = TlsIncoming::new(tcp_listener, acceptor);
-
-            let service_maker: SpawnService<GetSigningKeyFromDatabase, StsService, XmlErrorMapper> =
-                SpawnService::builder()
-                    .region(region)
-                    .service("sts")
-                    .allowed_request_methods(allowed_request_methods)
-                    .allowed_content_types(allowed_content_types)
-                    .get_signing_key(gsk)
-                    .implementation(service_impl)
-                    .error_mapper(error_mapper)
-                    .build()
-                    .expect("Unable to create service maker");
-
-            info!("Starting Hyper");
-
-            HyperServer::builder(incoming).serve(service_maker).await?;
-            Ok(())
-        }
-        None => {
-            info!("Non-TLS configuration detected");
-            let service_maker: SpawnService<GetSigningKeyFromDatabase, StsService, XmlErrorMapper> =
-                SpawnService::builder()
-                    .region(region)
-                    .service("sts")
-                    .allowed_request_methods(allowed_request_methods)
-                    .allowed_content_types(allowed_content_types)
-                    .get_signing_key(gsk)
-                    .implementation(service_impl)
-                    .error_mapper(error_mapper)
-                    .build()
-                    .expect("Unable to create service maker");
-
-            info!("Starting Hyper");
-            HyperServer::bind(&config.service.address).serve(service_maker).await?;
-            Ok(())
-        }
-    }
+    let shutdown = CancellationToken::new();
+    runtime.spawn(cancel_on_termination(shutdown.clone()));
+    println!("{:#?}", runtime.block_on(run_server_from_config(config, options, region, partition, shutdown)));
 }
@@ -0,0 +1,518 @@
+use {
+    super::{security_token_invalid, xml_response},
+    chrono::{DateTime, Duration, Utc},
+    http::StatusCode,
+    hyper::{Body, Response},
+    log::error,
+    scratchstack_arn::Arn,
+    scratchstack_service_common::{
+        access_key::{generate_secret_key, AccessKeyKind, AccessKeyPrefixes},
+        authz::Decision,
+        context::RequestContext,
+        oidc::OidcError,
+        operation::FromParameters,
+        operation_output,
+        protocol::{self, AwsError},
+        session::{
+            is_role_session, next_role_chain, role_chaining_session_duration, role_session_duration, SessionValidity,
+        },
+        session_policy::{pack_session_claims, PolicyArns, SessionPolicyError},
+        store::{ControlPlaneStore, Role, StoreError, Tag},
+        token::{EncodedSessionToken, PackedClaims, SessionClaims, TokenError, TokenKeyRing},
+        trust::{resolve_source_identity, TrustError, TrustPolicy, TrustRequest},
+    },
+    std::{collections::BTreeMap, sync::RwLock},
+    tower::BoxError,
+};
+
+/// The action trust policies are evaluated for.
+const ASSUME_ROLE_ACTION: &str = "sts:AssumeRole";
+
+scratchstack_service_common::operation_input! {
+    /// Input for the AssumeRole operation. `DurationSeconds` is checked against the role's `MaxSessionDuration` once
+    /// the role is known, and against the role chaining limit if the caller is a role session; `SourceIdentity` is
+    /// checked by [resolve_source_identity], and the session policies and tags by [pack_session_claims].
+    pub(crate) struct AssumeRoleInput {
+        "RoleArn" => pub role_arn: String where length(20, 2048),
+        "RoleSessionName" => pub role_session_name: String where length(2, 64), pattern(r"[\w+=,.@-]*"),
+        "DurationSeconds" => pub duration_seconds: Option<i64>,
+        "ExternalId" => pub external_id: Option<String> where length(2, 1224), pattern(r"[\w+=,.@:/-]*"),
+        "SourceIdentity" => pub source_identity: Option<String>,
+        "Policy" => pub policy: Option<String> where length(1, 2048),
+        "PolicyArns" => pub policy_arns: PolicyArns,
+        "Tags" => pub tags: Vec<Tag>,
+        "TransitiveTagKeys" => pub transitive_tag_keys: Vec<String>,
+    }
+}
+
+/// Why a session could not be issued, and how that is reported to the caller.
+#[derive(Debug)]
+pub(super) enum AssumeRoleError {
+    Validation(String),
+
+    /// The role does not exist, its ARN does not match, or its trust policy does not allow the caller. AWS does
+    /// not distinguish these, so that callers cannot probe for roles in other accounts.
+    AccessDenied,
+    Trust(TrustError),
+    SessionPolicy(SessionPolicyError),
+    Store(StoreError),
+    Token(TokenError),
+    WebIdentity(OidcError),
+}
+
+impl AssumeRoleError {
+    fn code(&self) -> &'static str {
+        match self {
+            Self::Validation(_) => "ValidationError",
+            Self::AccessDenied => "AccessDenied",
+            Self::Trust(e) => e.code(),
+            Self::SessionPolicy(e) => e.code(),
+            Self::Store(_) => "InternalFailure",
+            Self::Token(e) => e.code(),
+            Self::WebIdentity(e) => e.code(),
+        }
+    }
+
+    fn status(&self) -> StatusCode {
+        match self.code() {
+            "AccessDenied" => StatusCode::FORBIDDEN,
+            "InternalFailure" => StatusCode::INTERNAL_SERVER_ERROR,
+            _ => StatusCode::BAD_REQUEST,
+        }
+    }
+
+    /// The message returned to the caller. A web identity caller has no ARN, so `caller` is `None` for it.
+    fn message(&self, action: &str, caller: Option<&Arn>, role_arn: &str) -> String {
+        match self {
+            Self::Validation(message) => message.clone(),
+            Self::AccessDenied => match caller {
+                Some(caller) => {
+                    format!("User: {caller} is not authorized to perform: {action} on resource: {role_arn}")
+                }
+                None => format!("Not authorized to perform {action}"),
+            },
+            Self::Trust(e) => e.to_string(),
+            Self::SessionPolicy(e) => e.to_string(),
+            Self::WebIdentity(e) => e.to_string(),
+            // Key ring and sealing failures are not the caller's to see; a session that does not fit is.
+            Self::Token(e) if !self.status().is_server_error() => e.to_string(),
+            Self::Store(_) | Self::Token(_) => "An internal error occurred.".to_string(),
+        }
+    }
+}
+
+impl From<TrustError> for AssumeRoleError {
+    fn from(e: TrustError) -> Self {
+        Self::Trust(e)
+    }
+}
+
+impl From<SessionPolicyError> for AssumeRoleError {
+    fn from(e: SessionPolicyError) -> Self {
+        Self::SessionPolicy(e)
+    }
+}
+
+impl From<OidcError> for AssumeRoleError {
+    fn from(e: OidcError) -> Self {
+        Self::WebIdentity(e)
+    }
+}
+
+impl From<StoreError> for AssumeRoleError {
+    fn from(e: StoreError) -> Self {
+        match e {
+            StoreError::NoSuchEntity {
+                ..
+            } => Self::AccessDenied,
+            e => Self::Store(e),
+        }
+    }
+}
+
+impl From<TokenError> for AssumeRoleError {
+    fn from(e: TokenError) -> Self {
+        Self::Token(e)
+    }
+}
+
+/// Issue temporary credentials for a role whose trust policy allows the caller.
+///
+/// Only the role's side of the trust is checked; see [TrustPolicy]. The session token is sealed with the current
+/// key in `token_keys`, so this fails with `InternalFailure` until a token key has been provisioned.
+pub(crate) async fn assume_role(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+    token_keys: &RwLock<TokenKeyRing>,
+    access_key_prefixes: &AccessKeyPrefixes,
+) -> Result<Response<Body>, BoxError> {
+    let caller = match context.caller_arn() {
+        Some(caller) => caller,
+        None => return security_token_invalid(context.request_id()),
+    };
+
+    let input = match AssumeRoleInput::from_parameters(context.parameters()) {
+        Ok(input) => input,
+        Err(e) => {
+            let e = AssumeRoleError::Validation(e.to_string());
+            return error_response(context, ASSUME_ROLE_ACTION, Some(&caller), "", e);
+        }
+    };
+
+    let session = match issue_session(context, &caller, &input, store, token_keys, access_key_prefixes).await {
+        Ok(session) => session,
+        Err(e) => return error_response(context, ASSUME_ROLE_ACTION, Some(&caller), &input.role_arn, e),
+    };
+
+    let result = AssumeRoleResult {
+        source_identity: session.source_identity.as_deref(),
+        assumed_role_user: session.assumed_role_user(),
+        credentials: session.credentials(),
+        packed_policy_size: session.token.packed_policy_size,
+    };
+    xml_response(context, "AssumeRole", &result)
+}
+
+operation_output! {
+    struct AssumeRoleResult<'a> {
+        "SourceIdentity" => source_identity: Option<&'a str>,
+        "AssumedRoleUser" => assumed_role_user: AssumedRoleUser<'a>,
+        "Credentials" => credentials: Credentials<'a>,
+        "PackedPolicySize" => packed_policy_size: u32,
+    }
+}
+
+operation_output! {
+    pub(super) struct AssumedRoleUser<'a> {
+        "Arn" => arn: &'a str,
+
+        /// The role id and session name, separated by a colon.
+        "AssumedRoleId" => assumed_role_id: &'a str,
+    }
+}
+
+operation_output! {
+    pub(super) struct Credentials<'a> {
+        "AccessKeyId" => access_key_id: &'a str,
+        "SecretAccessKey" => secret_access_key: &'a str,
+        "SessionToken" => session_token: &'a str,
+        "Expiration" => expiration: DateTime<Utc>,
+    }
+}
+
+/// A session that has been allowed and sealed, ready to be returned to the caller.
+pub(super) struct IssuedSession {
+    pub access_key_id: String,
+    pub secret_access_key: String,
+    pub assumed_role_arn: String,
+    pub assumed_role_id: String,
+    pub source_identity: Option<String>,
+    pub expires_at: DateTime<Utc>,
+    pub token: EncodedSessionToken,
+}
+
+impl IssuedSession {
+    pub fn assumed_role_user(&self) -> AssumedRoleUser<'_> {
+        AssumedRoleUser {
+            arn: &self.assumed_role_arn,
+            assumed_role_id: &self.assumed_role_id,
+        }
+    }
+
+    /// The temporary credentials returned to the caller. The secret key is the one sealed in the session token.
+    pub fn credentials(&self) -> Credentials<'_> {
+        Credentials {
+            access_key_id: &self.access_key_id,
+            secret_access_key: &self.secret_access_key,
+            session_token: &self.token.token,
+            expiration: self.expires_at,
+        }
+    }
+}
+
+/// A role session that has been allowed, before it is sealed.
+pub(super) struct NewSession<'a> {
+    pub role_arn: &'a Arn,
+    pub role: &'a Role,
+    pub role_session_name: &'a str,
+    pub duration: Duration,
+    pub source_identity: Option<String>,
+    pub role_chain: Vec<String>,
+    pub session_data: BTreeMap<String, String>,
+
+    /// The session policies and tags, which must fit in the packed allotment.
+    pub packed: PackedClaims,
+}
+
+async fn issue_session(
+    context: &RequestContext,
+    caller: &Arn,
+    input: &AssumeRoleInput,
+    store: &dyn ControlPlaneStore,
+    token_keys: &RwLock<TokenKeyRing>,
+    access_key_prefixes: &AccessKeyPrefixes,
+) -> Result<IssuedSession, AssumeRoleError> {
+    let (role_arn, role, trust_policy) = target_role(store, &input.role_arn).await?;
+    if role_arn.partition() != caller.partition() {
+        return Err(AssumeRoleError::AccessDenied);
+    }
+
+    let source_identity = resolve_source_identity(context.session_data(), input.source_identity.as_deref())?;
+    let request = TrustRequest {
+        action: ASSUME_ROLE_ACTION,
+        caller,
+        external_id: input.external_id.as_deref(),
+        role_session_name: Some(&input.role_session_name),
+        source_identity: source_identity.as_deref(),
+        web_identity: None,
+    };
+    if trust_policy.evaluate(&request) != Decision::Allow {
+        return Err(AssumeRoleError::AccessDenied);
+    }
+
+    let duration = if is_role_session(caller) {
+        role_chaining_session_duration(input.duration_seconds, role.max_session_duration)
+    } else {
+        role_session_duration(input.duration_seconds, role.max_session_duration)
+    }
+    .map_err(|e| AssumeRoleError::Validation(e.to_string()))?;
+    let packed = pack_session_claims(
+        context.packed_claims(),
+        input.policy.as_deref(),
+        &input.policy_arns.0,
+        &input.tags,
+        &input.transitive_tag_keys,
+    )?;
+
+    let session = NewSession {
+        role_arn: &role_arn,
+        role: &role,
+        role_session_name: &input.role_session_name,
+        duration,
+        source_identity,
+        role_chain: next_role_chain(context.session_data()),
+        session_data: BTreeMap::new(),
+        packed,
+    };
+    seal_session(session, token_keys, access_key_prefixes)
+}
+
+/// Look up the role `role_arn` names and parse its trust policy. The ARN must name the role exactly, including its
+/// path.
+pub(super) async fn target_role(
+    store: &dyn ControlPlaneStore,
+    role_arn: &str,
+) -> Result<(Arn, Role, TrustPolicy), AssumeRoleError> {
+    let invalid = || AssumeRoleError::Validation(format!("{role_arn} is invalid"));
+    let arn: Arn = role_arn.parse().map_err(|_| invalid())?;
+    let role_name = match arn.resource().strip_prefix("role/") {
+        Some(path_and_name) if arn.service() == "iam" => path_and_name.rsplit('/').next().unwrap_or_default(),
+        _ => return Err(invalid()),
+    };
+
+    let role = store.get_role(arn.account_id(), role_name).await?;
+    if arn.resource() != format!("role{}{}", role.path, role.role_name) {
+        return Err(AssumeRoleError::AccessDenied);
+    }
+
+    let trust_policy = TrustPolicy::parse(&role.assume_role_policy_document).map_err(|e| {
+        error!("Role {} has an invalid trust policy: {}", arn, e);
+        AssumeRoleError::AccessDenied
+    })?;
+    Ok((arn, role, trust_policy))
+}
+
+/// Generate the temporary credentials of `session` and seal its token with the current key in `token_keys`.
+///
+/// Nothing stores temporary credentials; the secret key travels in the encrypted token, where
+/// [SessionCredentials][scratchstack_service_common::gsk::SessionCredentials] finds it to verify requests signed
+/// with them.
+pub(super) fn seal_session(
+    session: NewSession,
+    token_keys: &RwLock<TokenKeyRing>,
+    access_key_prefixes: &AccessKeyPrefixes,
+) -> Result<IssuedSession, AssumeRoleError> {
+    let validity = SessionValidity::new(Utc::now(), session.duration);
+    let access_key_id = access_key_prefixes.generate(AccessKeyKind::Temporary);
+    let secret_access_key = generate_secret_key();
+    let assumed_role_arn = format!(
+        "arn:{}:sts::{}:assumed-role/{}/{}",
+        session.role_arn.partition(),
+        session.role.account_id,
+        session.role.role_name,
+        session.role_session_name
+    );
+    let assumed_role_id = format!("{}:{}", session.role.role_id, session.role_session_name);
+
+    // Tokens are matched to the access key in its canonical spelling, as the signing key services see it.
+    let claims = SessionClaims {
+        access_key_id: access_key_prefixes.canonical(&access_key_id).unwrap_or_else(|| access_key_id.clone()),
+        secret_access_key: Some(secret_access_key.clone()),
+        principal_arn: assumed_role_arn.clone(),
+        user_id: assumed_role_id.clone(),
+        issued_at: validity.issued_at().timestamp(),
+        expires_at: validity.expires_at().timestamp(),
+        source_identity: session.source_identity.clone(),
+        role_chain: session.role_chain,
+        session_data: session.session_data,
+        packed: session.packed,
+    };
+    let token = token_keys.read().expect("token key ring poisoned").seal(&claims, validity.issued_at())?;
+
+    Ok(IssuedSession {
+        access_key_id,
+        secret_access_key,
+        assumed_role_arn,
+        assumed_role_id,
+        source_identity: session.source_identity,
+        expires_at: validity.expires_at(),
+        token,
+    })
+}
+
+pub(super) fn error_response(
+    context: &RequestContext,
+    action: &str,
+    caller: Option<&Arn>,
+    role_arn: &str,
+    e: AssumeRoleError,
+) -> Result<Response<Body>, BoxError> {
+    let status = e.status();
+    if let AssumeRoleError::Store(e) = &e {
+        error!("Unable to look up role {}: {}", role_arn, e);
+    } else if let AssumeRoleError::Token(e) = &e {
+        if status.is_server_error() {
+            error!("Unable to issue session token for {}: {}", role_arn, e);
+        }
+    }
+
+    let error = if status.is_server_error() {
+        AwsError::receiver(status, e.code(), e.message(action, caller, role_arn))
+    } else {
+        AwsError::sender(status, e.code(), e.message(action, caller, role_arn))
+    };
+    protocol::STS.response(&error, context.request_id())
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::{seal_session, AssumeRoleError, AssumeRoleResult, AssumedRoleUser, Credentials, NewSession},
+        crate::service::STS_XML_NS,
+        chrono::{Duration, TimeZone, Utc},
+        pretty_assertions::assert_eq,
+        scratchstack_arn::Arn,
+        scratchstack_service_common::{
+            access_key::AccessKeyPrefixes,
+            operation::output::query_response,
+            store::Role,
+            token::{PackedClaims, TokenAlgorithm, TokenError, TokenKey, TokenKeyRing},
+        },
+        std::{collections::BTreeMap, sync::RwLock},
+    };
+
+    #[test_log::test]
+    fn test_packed_policy_size() {
+        let now = Utc::now();
+        let token_keys = RwLock::new(TokenKeyRing::new(vec![TokenKey {
+            key_id: "tokenkey00000001".to_string(),
+            algorithm: TokenAlgorithm::Aes256Gcm,
+            key: vec![7; 32],
+            valid_at: now - Duration::days(1),
+            expires_at: now + Duration::days(1),
+        }]));
+        let role_arn: Arn = "arn:aws:iam::123456789012:role/Deployer".parse().unwrap();
+        let role = Role {
+            role_id: "AROAEXAMPLEROLE1".to_string(),
+            account_id: "123456789012".to_string(),
+            role_name: "Deployer".to_string(),
+            path: "/".to_string(),
+            permissions_boundary: None,
+            description: None,
+            assume_role_policy_document: "{}".to_string(),
+            max_session_duration: 3600,
+            created_at: now,
+        };
+        let seal = |packed: PackedClaims| {
+            let session = NewSession {
+                role_arn: &role_arn,
+                role: &role,
+                role_session_name: "build",
+                duration: Duration::hours(1),
+                source_identity: None,
+                role_chain: Vec::new(),
+                session_data: BTreeMap::new(),
+                packed,
+            };
+            seal_session(session, &token_keys, &AccessKeyPrefixes::default())
+        };
+
+        let mut packed = PackedClaims::default();
+        packed.tags.insert("Project".to_string(), "scratchstack".to_string());
+        let session = seal(packed).unwrap();
+        assert!((1..=100).contains(&session.token.packed_policy_size), "{}", session.token.packed_policy_size);
+
+        // Values that do not compress push the packed claims over the allotment.
+        let alphabet = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789";
+        let mut state = 0x2545f491u32;
+        let mut random_value = || {
+            (0..256)
+                .map(|_| {
+                    state = state.wrapping_mul(1664525).wrapping_add(1013904223);
+                    char::from(alphabet[(state >> 24) as usize % alphabet.len()])
+                })
+                .collect::<String>()
+        };
+        let mut packed = PackedClaims::default();
+        for i in 0..50 {
+            packed.tags.insert(format!("Tag{i}"), random_value());
+        }
+        let e = match seal(packed) {
+            Ok(_) => panic!("session sealed despite exceeding the packed allotment"),
+            Err(e) => e,
+        };
+        assert!(
+            matches!(e, AssumeRoleError::Token(TokenError::PackedPolicyTooLarge(percent)) if percent > 100),
+            "{e:?}"
+        );
+        assert_eq!((e.code(), e.status().as_u16()), ("PackedPolicyTooLarge", 400));
+        assert!(e.message("sts:AssumeRole", None, "").starts_with("Packed policy consumes "));
+    }
+
+    #[test_log::test]
+    fn test_token_errors() {
+        let e = AssumeRoleError::Token(TokenError::PackedPolicyTooLarge(112));
+        assert_eq!((e.code(), e.status().as_u16()), ("PackedPolicyTooLarge", 400));
+        assert_eq!(
+            e.message("sts:AssumeRole", None, ""),
+            "Packed policy consumes 112% of allotted space, please use smaller policy."
+        );
+
+        let e = AssumeRoleError::Token(TokenError::NoKey);
+        assert_eq!((e.code(), e.status().as_u16()), ("InternalFailure", 500));
+        assert_eq!(e.message("sts:AssumeRole", None, ""), "An internal error occurred.");
+    }
+
+    #[test_log::test]
+    fn test_assume_role_result() {
+        let result = AssumeRoleResult {
+            source_identity: None,
+            assumed_role_user: AssumedRoleUser {
+                arn: "arn:aws:sts::123456789012:assumed-role/Deployer/build",
+                assumed_role_id: "AROAEXAMPLEROLE1:build",
+            },
+            credentials: Credentials {
+                access_key_id: "ASIAEXAMPLEKEY12345",
+                secret_access_key: "secret",
+                session_token: "token",
+                expiration: Utc.with_ymd_and_hms(2022, 10, 14, 1, 0, 0).unwrap(),
+            },
+            packed_policy_size: 6,
+        };
+
+        assert_eq!(
+            query_response(STS_XML_NS, "AssumeRole", &result, "1234"),
+            r#"<AssumeRoleResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/"><AssumeRoleResult><AssumedRoleUser><Arn>arn:aws:sts::123456789012:assumed-role/Deployer/build</Arn><AssumedRoleId>AROAEXAMPLEROLE1:build</AssumedRoleId></AssumedRoleUser><Credentials><AccessKeyId>ASIAEXAMPLEKEY12345</AccessKeyId><SecretAccessKey>secret</SecretAccessKey><SessionToken>token</SessionToken><Expiration>2022-10-14T01:00:00Z</Expiration></Credentials><PackedPolicySize>6</PackedPolicySize></AssumeRoleResult><ResponseMetadata><RequestId>1234</RequestId></ResponseMetadata></AssumeRoleResponse>"#
+        );
+    }
+}
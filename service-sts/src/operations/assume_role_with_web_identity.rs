@@ -0,0 +1,138 @@
+use {
+    super::{
+        assume_role::{
+            error_response, seal_session, target_role, AssumeRoleError, AssumedRoleUser, Credentials, IssuedSession,
+            NewSession,
+        },
+        xml_response,
+    },
+    chrono::Utc,
+    hyper::{Body, Response},
+    scratchstack_arn::Arn,
+    scratchstack_service_common::{
+        access_key::AccessKeyPrefixes,
+        authz::Decision,
+        context::RequestContext,
+        oidc::{OidcError, OidcProviders, WebIdentity},
+        operation::FromParameters,
+        operation_output,
+        session::role_session_duration,
+        session_keys::AWS_FEDERATED_PROVIDER,
+        store::ControlPlaneStore,
+        token::{PackedClaims, TokenKeyRing},
+        trust::TrustRequest,
+    },
+    std::{collections::BTreeMap, sync::RwLock},
+    tower::BoxError,
+};
+
+/// The action trust policies are evaluated for.
+const ASSUME_ROLE_WITH_WEB_IDENTITY_ACTION: &str = "sts:AssumeRoleWithWebIdentity";
+
+scratchstack_service_common::operation_input! {
+    /// Input for the AssumeRoleWithWebIdentity operation. `DurationSeconds` is checked against the role's
+    /// `MaxSessionDuration` once the role is known.
+    pub(crate) struct AssumeRoleWithWebIdentityInput {
+        "RoleArn" => pub role_arn: String where length(20, 2048),
+        "RoleSessionName" => pub role_session_name: String where length(2, 64), pattern(r"[\w+=,.@-]*"),
+        "WebIdentityToken" => pub web_identity_token: String where length(4, 20000),
+        "DurationSeconds" => pub duration_seconds: Option<i64>,
+    }
+}
+
+/// Issue temporary credentials for a role whose trust policy allows the OIDC provider of a web identity token.
+///
+/// The request is not signed; the caller is authenticated by the token, which `oidc` verifies. The provider is the
+/// caller that the role's trust policy must name in a `Federated` principal, and the token's `sub` and `aud` claims
+/// are available to its conditions. Requests made with the new session carry `aws:FederatedProvider` and the claims
+/// as session data.
+pub(crate) async fn assume_role_with_web_identity(
+    context: &RequestContext,
+    store: &dyn ControlPlaneStore,
+    oidc: &OidcProviders,
+    token_keys: &RwLock<TokenKeyRing>,
+    access_key_prefixes: &AccessKeyPrefixes,
+) -> Result<Response<Body>, BoxError> {
+    let input = match AssumeRoleWithWebIdentityInput::from_parameters(context.parameters()) {
+        Ok(input) => input,
+        Err(e) => {
+            let e = AssumeRoleError::Validation(e.to_string());
+            return error_response(context, ASSUME_ROLE_WITH_WEB_IDENTITY_ACTION, None, "", e);
+        }
+    };
+
+    let (identity, session) = match issue_session(&input, store, oidc, token_keys, access_key_prefixes).await {
+        Ok(issued) => issued,
+        Err(e) => return error_response(context, ASSUME_ROLE_WITH_WEB_IDENTITY_ACTION, None, &input.role_arn, e),
+    };
+
+    let result = AssumeRoleWithWebIdentityResult {
+        subject_from_web_identity_token: &identity.subject,
+        audience: &identity.audience,
+        provider: &identity.provider,
+        assumed_role_user: session.assumed_role_user(),
+        credentials: session.credentials(),
+        packed_policy_size: session.token.packed_policy_size,
+    };
+    xml_response(context, "AssumeRoleWithWebIdentity", &result)
+}
+
+operation_output! {
+    struct AssumeRoleWithWebIdentityResult<'a> {
+        /// The `sub` claim of the identity token.
+        "SubjectFromWebIdentityToken" => subject_from_web_identity_token: &'a str,
+
+        /// The `aud` value of the identity token that was accepted.
+        "Audience" => audience: &'a str,
+
+        /// The issuer of the identity token.
+        "Provider" => provider: &'a str,
+        "AssumedRoleUser" => assumed_role_user: AssumedRoleUser<'a>,
+        "Credentials" => credentials: Credentials<'a>,
+        "PackedPolicySize" => packed_policy_size: u32,
+    }
+}
+
+async fn issue_session(
+    input: &AssumeRoleWithWebIdentityInput,
+    store: &dyn ControlPlaneStore,
+    oidc: &OidcProviders,
+    token_keys: &RwLock<TokenKeyRing>,
+    access_key_prefixes: &AccessKeyPrefixes,
+) -> Result<(WebIdentity, IssuedSession), AssumeRoleError> {
+    let identity = oidc.verify(&input.web_identity_token, Utc::now())?;
+    let (role_arn, role, trust_policy) = target_role(store, &input.role_arn).await?;
+
+    let provider_arn = identity.provider_arn(role_arn.partition(), role_arn.account_id());
+    let caller: Arn = provider_arn.parse().map_err(|_| OidcError::UnknownIssuer(identity.provider.clone()))?;
+    let condition_keys = identity.condition_keys();
+    let request = TrustRequest {
+        action: ASSUME_ROLE_WITH_WEB_IDENTITY_ACTION,
+        caller: &caller,
+        external_id: None,
+        role_session_name: Some(&input.role_session_name),
+        source_identity: None,
+        web_identity: Some(&condition_keys),
+    };
+    if trust_policy.evaluate(&request) != Decision::Allow {
+        return Err(AssumeRoleError::AccessDenied);
+    }
+
+    let duration = role_session_duration(input.duration_seconds, role.max_session_duration)
+        .map_err(|e| AssumeRoleError::Validation(e.to_string()))?;
+    let mut session_data: BTreeMap<String, String> = condition_keys.into_iter().collect();
+    session_data.insert(AWS_FEDERATED_PROVIDER.to_string(), provider_arn);
+
+    let session = NewSession {
+        role_arn: &role_arn,
+        role: &role,
+        role_session_name: &input.role_session_name,
+        duration,
+        source_identity: None,
+        role_chain: Vec::new(),
+        session_data,
+        packed: PackedClaims::default(),
+    };
+    let session = seal_session(session, token_keys, access_key_prefixes)?;
+    Ok((identity, session))
+}
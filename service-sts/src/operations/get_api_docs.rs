@@ -0,0 +1,22 @@
+use {
+    super::{api_docs, security_token_invalid},
+    http::{header::HeaderValue, StatusCode},
+    hyper::{Body, Response},
+    scratchstack_service_common::context::RequestContext,
+    tower::BoxError,
+};
+
+/// Describe the implemented operations as JSON, to any authenticated caller; see
+/// [scratchstack_service_common::api_docs].
+pub(crate) async fn get_api_docs(context: &RequestContext) -> Result<Response<Body>, BoxError> {
+    if context.caller_arn().is_none() {
+        return security_token_invalid(context.request_id());
+    }
+
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", HeaderValue::from_static("application/json"))
+        .header("X-Amzn-RequestId", context.request_id().to_string())
+        .body(Body::from(api_docs().to_json()))
+        .map_err(Into::into)
+}
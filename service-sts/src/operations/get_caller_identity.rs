@@ -1,63 +1,53 @@
 use {
-    crate::model,
-    http::{request::Parts, StatusCode},
+    super::{security_token_invalid, xml_response},
     hyper::{Body, Response},
-    scratchstack_arn::Arn,
-    scratchstack_aws_principal::{Principal, SessionData, SessionValue},
-    std::collections::HashMap,
+    scratchstack_service_common::{context::RequestContext, operation_output},
     tower::BoxError,
 };
 
-fn security_token_invalid(parts: Parts) -> Result<Response<Body>, BoxError> {
-    model::response::ErrorResponse::builder()
-        .xmlns(model::STS_XML_NS)
-        .error(
-            model::Error::builder()
-                .r#type("Sender")
-                .code("InvalidClientTokenId")
-                .message("The security token included in the request is invalid.")
-                .build()?,
-        )
-        .build()?
-        .respond(&parts, StatusCode::FORBIDDEN)
+operation_output! {
+    struct GetCallerIdentityResult {
+        "Arn" => arn: String,
+        "UserId" => user_id: String,
+        "Account" => account: String,
+    }
 }
 
-pub(crate) async fn get_caller_identity(
-    parts: Parts,
-    _parameters: HashMap<String, String>,
-) -> Result<Response<Body>, BoxError> {
-    let session_data = parts.extensions.get::<SessionData>();
-    let user_id = match session_data {
-        None => None,
-        Some(session_data) => match session_data.get("aws:userid") {
-            Some(SessionValue::String(user_id)) => Some(user_id.clone()),
-            _ => None,
-        },
+pub(crate) async fn get_caller_identity(context: &RequestContext) -> Result<Response<Body>, BoxError> {
+    match context.caller_arn() {
+        Some(arn) => {
+            let result = GetCallerIdentityResult {
+                account: arn.account_id().to_string(),
+                arn: arn.to_string(),
+                user_id: context.user_id().unwrap_or_default().to_string(),
+            };
+            xml_response(context, "GetCallerIdentity", &result)
+        }
+
+        // If no ARN was found, return an error.
+        None => security_token_invalid(context.request_id()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::GetCallerIdentityResult, crate::service::STS_XML_NS, pretty_assertions::assert_eq,
+        scratchstack_service_common::operation::output::query_response,
     };
 
-    match parts.extensions.get::<Principal>() {
-        // This shouldn't happen.
-        None => security_token_invalid(parts),
-        Some(principal) => {
-            // Return the first principal that has an ARN.
-            for principal_identity in principal {
-                if principal_identity.has_arn() {
-                    let arn: Arn = principal_identity.try_into().unwrap();
-                    return model::response::GetCallerIdentityResponse::builder()
-                        .get_caller_identity_result(
-                            model::GetCallerIdentityResult::builder()
-                                .account(arn.account_id())
-                                .arn(arn.to_string())
-                                .user_id(user_id.unwrap_or_default())
-                                .build()?,
-                        )
-                        .build()?
-                        .respond(&parts, StatusCode::OK);
-                }
-            }
+    #[test_log::test]
+    fn test_field_order() {
+        // Elements are written in declaration order, which must match the order AWS uses.
+        let result = GetCallerIdentityResult {
+            arn: "arn:aws:iam::123456789012:user/Alice".to_string(),
+            user_id: "AIDAEXAMPLEUSER1".to_string(),
+            account: "123456789012".to_string(),
+        };
 
-            // If no ARN was found, return an error.
-            security_token_invalid(parts)
-        }
+        assert_eq!(
+            query_response(STS_XML_NS, "GetCallerIdentity", &result, "1234"),
+            r#"<GetCallerIdentityResponse xmlns="https://sts.amazonaws.com/doc/2011-06-15/"><GetCallerIdentityResult><Arn>arn:aws:iam::123456789012:user/Alice</Arn><UserId>AIDAEXAMPLEUSER1</UserId><Account>123456789012</Account></GetCallerIdentityResult><ResponseMetadata><RequestId>1234</RequestId></ResponseMetadata></GetCallerIdentityResponse>"#
+        );
     }
 }
@@ -0,0 +1,50 @@
+use {
+    super::{security_token_invalid, xml_response},
+    hyper::{Body, Response},
+    scratchstack_service_common::{context::RequestContext, deployment::Deployment, operation_output},
+    tower::BoxError,
+};
+
+operation_output! {
+    /// Scratchstack extension: what build of the service answered the request.
+    struct GetDeploymentInfoResult {
+        "Service" => service: String,
+        "Version" => version: String,
+        "GitSha" => git_sha: Option<String>,
+        "BuildTimestamp" => build_timestamp: Option<String>,
+
+        /// Crate names and versions, as `name=version`, comma separated.
+        "Crates" => crates: String,
+
+        /// Enabled optional features, comma separated.
+        "Features" => features: String,
+        "Region" => region: String,
+        "StartedAt" => started_at: String,
+        "UptimeSeconds" => uptime_seconds: u64,
+    }
+}
+
+/// Report the build and uptime of this instance, as `GET /version` does, to any authenticated caller.
+pub(crate) async fn get_deployment_info(
+    context: &RequestContext,
+    deployment: &Deployment,
+) -> Result<Response<Body>, BoxError> {
+    if context.caller_arn().is_none() {
+        return security_token_invalid(context.request_id());
+    }
+
+    let info = deployment.info();
+    let crates = info.crates.iter().map(|(name, version)| format!("{name}={version}")).collect::<Vec<_>>();
+    let result = GetDeploymentInfoResult {
+        service: info.service,
+        version: info.version,
+        git_sha: info.git_sha,
+        build_timestamp: info.build_timestamp,
+        crates: crates.join(","),
+        features: info.features.join(","),
+        region: info.region,
+        started_at: info.started_at,
+        uptime_seconds: info.uptime_seconds,
+    };
+    xml_response(context, "GetDeploymentInfo", &result)
+}
@@ -1,3 +1,78 @@
+mod assume_role;
+mod assume_role_with_web_identity;
+mod get_api_docs;
 mod get_caller_identity;
+mod get_deployment_info;
 
-pub(crate) use get_caller_identity::get_caller_identity;
+use {
+    crate::service::{STS_VERSION_20110615, STS_XML_NS},
+    http::{header::HeaderValue, StatusCode},
+    hyper::{Body, Response},
+    scratchstack_http_framework::RequestId,
+    scratchstack_service_common::{
+        access_key::AccessKeyPrefixes,
+        actions,
+        context::RequestContext,
+        deployment::Deployment,
+        oidc::OidcProviders,
+        operation::output::{query_response, ToXml},
+        operations,
+        protocol::{self, AwsError},
+        store::ControlPlaneStore,
+        token::TokenKeyRing,
+    },
+    std::sync::RwLock,
+    tower::BoxError,
+};
+
+use {
+    assume_role::{assume_role, AssumeRoleInput},
+    assume_role_with_web_identity::{assume_role_with_web_identity, AssumeRoleWithWebIdentityInput},
+    get_api_docs::get_api_docs,
+    get_caller_identity::get_caller_identity,
+    get_deployment_info::get_deployment_info,
+};
+
+operations! {
+    service: "sts", version: STS_VERSION_20110615;
+
+    /// Run the STS operation for `action`, or return `None` if STS has no such operation.
+    pub(crate) async fn dispatch(
+        context: &RequestContext,
+        deployment: &Deployment,
+        store: &dyn ControlPlaneStore,
+        oidc: &OidcProviders,
+        token_keys: &RwLock<TokenKeyRing>,
+        access_key_prefixes: &AccessKeyPrefixes,
+    ) -> Result<Response<Body>, BoxError>;
+
+    /// The operations [dispatch] runs, as reported by `GetApiDocs`.
+    pub(crate) fn api_docs();
+
+    actions::ASSUME_ROLE: AssumeRoleInput => assume_role(context, store, token_keys, access_key_prefixes),
+    actions::ASSUME_ROLE_WITH_WEB_IDENTITY: AssumeRoleWithWebIdentityInput =>
+        assume_role_with_web_identity(context, store, oidc, token_keys, access_key_prefixes),
+    actions::GET_STS_API_DOCS => get_api_docs(context),
+    actions::GET_CALLER_IDENTITY => get_caller_identity(context),
+    actions::GET_DEPLOYMENT_INFO => get_deployment_info(context, deployment),
+}
+
+/// A successful response to `operation` with the result `result`.
+fn xml_response<T: ToXml>(context: &RequestContext, operation: &str, result: &T) -> Result<Response<Body>, BoxError> {
+    let request_id = context.request_id().to_string();
+    Response::builder()
+        .status(StatusCode::OK)
+        .header("Content-Type", HeaderValue::from_static("text/xml"))
+        .header("X-Amzn-RequestId", &request_id)
+        .body(Body::from(query_response(STS_XML_NS, operation, result, &request_id)))
+        .map_err(Into::into)
+}
+
+pub(crate) fn security_token_invalid(request_id: RequestId) -> Result<Response<Body>, BoxError> {
+    let error = AwsError::sender(
+        StatusCode::FORBIDDEN,
+        "InvalidClientTokenId",
+        "The security token included in the request is invalid.",
+    );
+    protocol::STS.response(&error, request_id)
+}
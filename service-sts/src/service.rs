@@ -1,15 +1,27 @@
 use {
-    crate::{model, operations},
-    http::{header::HeaderValue, StatusCode},
+    crate::operations,
+    http::StatusCode,
     hyper::{service::Service, Body, Request, Response},
     log::warn,
     scratchstack_aws_signature::{canonical::get_content_type_and_charset, signature::IntoRequestBytes},
     scratchstack_http_framework::RequestId,
+    scratchstack_service_common::{
+        access_key::AccessKeyPrefixes,
+        authorizer::RequestResource,
+        context::RequestContext,
+        deployment::Deployment,
+        deprecation::{add_warnings, Deprecation, Deprecations},
+        oidc::OidcProviders,
+        parameters::{is_query_only, request_parameters},
+        protocol::{self, AwsError},
+        store::ControlPlaneStore,
+        token::TokenKeyRing,
+    },
     std::{
-        collections::HashMap,
         fmt::Debug,
         future::Future,
         pin::Pin,
+        sync::{Arc, RwLock},
         task::{Context, Poll},
     },
     tower::BoxError,
@@ -22,8 +34,72 @@ pub const STS_XML_NS: &str = "https://sts.amazonaws.com/doc/2011-06-15/";
 
 pub const STS_VERSION_20110615: &str = "2011-06-15";
 
+/// Operations whose callers are authenticated by a parameter rather than a signature; see
+/// [scratchstack_service_common::anonymous].
+pub const STS_ANONYMOUS_ACTIONS: &[&str] = &["AssumeRoleWithWebIdentity"];
+
+/// Operations and parameters being phased out; none yet. See [scratchstack_service_common::deprecation].
+pub const STS_DEPRECATIONS: &[Deprecation] = &[];
+
+/// Operations any authenticated caller may use, which AWS does not authorize against identity policies.
+pub const STS_UNAUTHORIZED_ACTIONS: &[&str] = &["GetApiDocs", "GetCallerIdentity", "GetDeploymentInfo"];
+
+/// The resource an STS request acts on: the role being assumed, or `*`.
+pub fn request_resource(context: &RequestContext) -> RequestResource {
+    match context.parameter("RoleArn") {
+        Some(role_arn) => RequestResource::Arn(role_arn.to_string()),
+        None => RequestResource::Any,
+    }
+}
+
 #[derive(Clone, Debug)]
-pub struct StsService {}
+pub struct StsService {
+    deployment: Arc<Deployment>,
+    store: Arc<dyn ControlPlaneStore>,
+
+    /// The keys session tokens are sealed with, kept current by the service's token key refresh task.
+    token_keys: Arc<RwLock<TokenKeyRing>>,
+    access_key_prefixes: Arc<AccessKeyPrefixes>,
+    deprecations: Option<Arc<Deprecations>>,
+
+    /// The identity providers trusted for AssumeRoleWithWebIdentity.
+    oidc: Arc<OidcProviders>,
+}
+
+impl StsService {
+    pub fn new(
+        deployment: Arc<Deployment>,
+        store: Arc<dyn ControlPlaneStore>,
+        token_keys: Arc<RwLock<TokenKeyRing>>,
+    ) -> Self {
+        Self {
+            deployment,
+            store,
+            token_keys,
+            access_key_prefixes: Arc::new(AccessKeyPrefixes::default()),
+            deprecations: None,
+            oidc: Arc::new(OidcProviders::default()),
+        }
+    }
+
+    /// Generate temporary access key ids with the configured prefix instead of `ASIA`.
+    pub fn with_access_key_prefixes(mut self, access_key_prefixes: AccessKeyPrefixes) -> Self {
+        self.access_key_prefixes = Arc::new(access_key_prefixes);
+        self
+    }
+
+    /// Warn about, or reject, requests that use one of the deprecations in `deprecations`.
+    pub fn with_deprecations(mut self, deprecations: Arc<Deprecations>) -> Self {
+        self.deprecations = Some(deprecations);
+        self
+    }
+
+    /// Accept AssumeRoleWithWebIdentity tokens from the providers in `oidc`.
+    pub fn with_oidc_providers(mut self, oidc: Arc<OidcProviders>) -> Self {
+        self.oidc = oidc;
+        self
+    }
+}
 
 impl Service<Request<Body>> for StsService {
     type Response = Response<Body>;
@@ -35,7 +111,13 @@ impl Service<Request<Body>> for StsService {
     }
 
     fn call(&mut self, req: Request<Body>) -> Self::Future {
-        Box::pin(async {
+        let deployment = self.deployment.clone();
+        let store = self.store.clone();
+        let token_keys = self.token_keys.clone();
+        let access_key_prefixes = self.access_key_prefixes.clone();
+        let deprecations = self.deprecations.clone();
+        let oidc = self.oidc.clone();
+        Box::pin(async move {
             let (mut parts, body) = req.into_parts();
             let request_id = match parts.extensions.get::<RequestId>() {
                 Some(request_id) => *request_id,
@@ -47,91 +129,106 @@ impl Service<Request<Body>> for StsService {
             };
 
             let query = parts.uri.query().unwrap_or("").to_string();
-            let mut parameters: HashMap<String, String> = HashMap::new();
-            for pair in form_urlencoded::parse(query.as_bytes()) {
-                let key = pair.0.to_string();
-                let value = pair.1.to_string();
-
-                // Only use the first value found. If an entry already exists, do not update it.
-                parameters.entry(key).or_insert(value);
+            let content_type = get_content_type_and_charset(&parts.headers);
+            let is_form =
+                content_type.as_ref().map(|ctc| ctc.content_type == APPLICATION_X_WWW_FORM_URLENCODED).unwrap_or(false);
+
+            if content_type.is_some() && !is_form && !is_query_only(&parts.method) {
+                let error = AwsError::sender(
+                    StatusCode::BAD_REQUEST,
+                    "InvalidRequest",
+                    format!("Unsupported content type; expected {APPLICATION_X_WWW_FORM_URLENCODED}"),
+                );
+                return protocol::AWS_FAULT.response(&error, request_id);
             }
 
-            if let Some(ctc) = get_content_type_and_charset(&parts.headers) {
-                // This should not happen.
-                if ctc.content_type != APPLICATION_X_WWW_FORM_URLENCODED {
-                    // FIXME: Format result.
-                    return Response::builder()
-                        .status(StatusCode::BAD_REQUEST)
-                        .header("Content-Type", HeaderValue::from_static("text/plain"))
-                        .header("X-Amzn-RequestId", request_id.to_string())
-                        .body(Body::from("Bad request"))
-                        .map_err(Into::into);
-                }
-
-                let body = match body.into_request_bytes().await {
-                    Ok(bytes) => bytes,
+            // GET and HEAD bodies are read whatever their content type, so a non-empty one can be rejected.
+            let body = if is_form || is_query_only(&parts.method) {
+                match body.into_request_bytes().await {
+                    Ok(bytes) => Some(bytes),
                     Err(e) => {
                         warn!("{} Error reading request body: {}", request_id, e);
-                        return Response::builder()
-                            .status(StatusCode::INTERNAL_SERVER_ERROR)
-                            .header("Content-Type", HeaderValue::from_static("text/plain"))
-                            .header("X-Amzn-RequestId", request_id.to_string())
-                            .body(Body::from("Internal server error"))
-                            .map_err(Into::into);
+                        let error = AwsError::receiver(
+                            StatusCode::INTERNAL_SERVER_ERROR,
+                            "ServiceFailure",
+                            "The request processing has failed because of an unknown error, exception or failure.",
+                        );
+                        return protocol::STS.response(&error, request_id);
                     }
-                };
-
-                for pair in form_urlencoded::parse(&body) {
-                    let key = pair.0.to_string();
-                    let value = pair.1.to_string();
+                }
+            } else {
+                None
+            };
 
-                    // Again, only use the first value found. If an entry already exists, do not update it.
-                    parameters.entry(key).or_insert(value);
+            let parameters = match request_parameters(&parts.method, &query, body.as_deref()) {
+                Ok(parameters) => parameters,
+                Err(e) => {
+                    let error = AwsError::sender(StatusCode::BAD_REQUEST, "InvalidRequest", e.to_string());
+                    return protocol::AWS_FAULT.response(&error, request_id);
                 }
-            }
+            };
 
             // Action is required.
             let action = match parameters.get("Action") {
-                Some(action) => action,
+                Some(action) => action.clone(),
                 None => {
                     // AWS returns HTML here; we always return an XML body instead.
-                    let error = model::Error::builder()
-                        .code("InvalidRequest")
-                        .message("Missing required parameter: Action")
-                        .r#type("Sender")
-                        .build()?;
-
-                    let error_response = model::response::ErrorResponse::builder()
-                        .xmlns(model::AWSFAULT_XML_NS)
-                        .request_id(request_id)
-                        .error(error)
-                        .build()?;
-
-                    return error_response.respond(&parts, StatusCode::BAD_REQUEST);
+                    let error = AwsError::sender(
+                        StatusCode::BAD_REQUEST,
+                        "InvalidRequest",
+                        "Missing required parameter: Action",
+                    );
+                    return protocol::AWS_FAULT.response(&error, request_id);
                 }
             };
 
             let version =
                 parameters.get("Version").map(Clone::clone).unwrap_or_else(|| "NO_VERSION_SPECIFIED".to_string());
 
-            match (action.as_str(), version.as_str()) {
-                ("GetCallerIdentity", STS_VERSION_20110615) => operations::get_caller_identity(parts, parameters).await,
-                _ => {
-                    let error = model::Error::builder()
-                        .code("InvalidAction")
-                        .message(format!("Could not find operation {action} for version {version}"))
-                        .r#type("Sender")
-                        .build()?;
-
-                    let error_response = model::response::ErrorResponse::builder()
-                        .xmlns(model::AWSFAULT_XML_NS)
-                        .request_id(request_id)
-                        .error(error)
-                        .build()?;
-
-                    error_response.respond(&parts, StatusCode::BAD_REQUEST)
+            // The caller of AssumeRoleWithWebIdentity is authenticated by its token rather than a signature.
+            let context = if STS_ANONYMOUS_ACTIONS.contains(&action.as_str()) {
+                RequestContext::anonymous(&parts, parameters)
+            } else {
+                match RequestContext::from_parts(&parts, parameters) {
+                    Some(context) => context,
+                    // The framework should have rejected unauthenticated requests already.
+                    None => return operations::security_token_invalid(request_id),
                 }
-            }
+            };
+
+            let deprecated = match deprecations.as_ref().map(|deprecations| deprecations.check(&context, &action)) {
+                None => Vec::new(),
+                Some(Ok(deprecated)) => deprecated,
+                Some(Err(error)) => return protocol::STS.response(&error, request_id),
+            };
+
+            let result = match version.as_str() {
+                STS_VERSION_20110615 => {
+                    operations::dispatch(
+                        &action,
+                        &context,
+                        &deployment,
+                        store.as_ref(),
+                        &oidc,
+                        &token_keys,
+                        &access_key_prefixes,
+                    )
+                    .await
+                }
+                _ => None,
+            };
+            let result = match result {
+                Some(result) => result,
+                None => {
+                    let error = AwsError::sender(
+                        StatusCode::BAD_REQUEST,
+                        "InvalidAction",
+                        format!("Could not find operation {action} for version {version}"),
+                    );
+                    protocol::AWS_FAULT.response(&error, request_id)
+                }
+            };
+            result.map(|response| add_warnings(response, &deprecated))
         })
     }
 }
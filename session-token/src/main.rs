@@ -0,0 +1,170 @@
+//! Decode a session token and print its claims.
+//!
+//! Encrypted tokens need the key they were sealed with, read from a key file with one key per line:
+//! `<key id> <algorithm> <hex key>`, e.g. `TOKENKEY00000001 AES-256-GCM 00112233...`. Keys are taken from the file
+//! rather than the command line so they do not appear in process listings. Without a key, only the envelope header
+//! of an encrypted token is printed.
+//!
+//! Output is JSON, so quotes and control characters in tags or policies are escaped rather than written to the
+//! terminal. Key material is never printed.
+use {
+    chrono::{DateTime, NaiveDateTime, Utc},
+    getopts::Options,
+    log::error,
+    scratchstack_session_token::{
+        decode_hex, decode_session_token, inspect_envelope, SessionClaims, TokenAlgorithm, TokenKey, TokenKeyRing,
+    },
+    serde_json::{json, Value},
+    std::{
+        env,
+        fs::read_to_string,
+        io::{self, Read, Write},
+        process::exit,
+    },
+};
+
+#[allow(unused_must_use)]
+fn print_usage(stream: &mut dyn Write, program: &str, opts: Options) {
+    let brief =
+        format!("Usage: {program} [options] [TOKEN]\n\nReads the token from standard input if it is omitted or \"-\".");
+    write!(stream, "{}", opts.usage(&brief));
+}
+
+fn main() {
+    env_logger::init();
+    let args: Vec<String> = env::args().collect();
+    let program = args[0].clone();
+
+    let mut opts = Options::new();
+    opts.optflag("h", "help", "print this usage information");
+    opts.optopt("k", "key-file", "file of token keys for decrypting encrypted tokens", "FILENAME");
+
+    let matches = match opts.parse(&args[1..]) {
+        Ok(m) => m,
+        Err(f) => {
+            error!("{}", f);
+            exit(2);
+        }
+    };
+
+    if matches.opt_present("h") {
+        print_usage(&mut io::stdout(), &program, opts);
+        return;
+    }
+
+    if matches.free.len() > 1 {
+        print_usage(&mut io::stderr(), &program, opts);
+        exit(2);
+    }
+
+    let token = match matches.free.first().map(String::as_str) {
+        None | Some("-") => {
+            let mut token = String::new();
+            if let Err(e) = io::stdin().read_to_string(&mut token) {
+                error!("Unable to read token from standard input: {}", e);
+                exit(2);
+            }
+            token
+        }
+        Some(token) => token.to_string(),
+    };
+    let token = token.trim();
+
+    let keys = match matches.opt_str("k") {
+        None => TokenKeyRing::default(),
+        Some(filename) => match read_key_file(&filename) {
+            Ok(keys) => keys,
+            Err(e) => {
+                error!("Unable to read key file {}: {}", filename, e);
+                exit(2);
+            }
+        },
+    };
+
+    match describe(token, &keys) {
+        Ok(output) => println!("{}", serde_json::to_string_pretty(&output).expect("JSON values always serialize")),
+        Err(e) => {
+            error!("{}", e);
+            exit(1);
+        }
+    }
+}
+
+/// Read a key file. The keys are treated as valid at all times, so expired tokens can still be inspected.
+fn read_key_file(filename: &str) -> Result<TokenKeyRing, String> {
+    let contents = read_to_string(filename).map_err(|e| e.to_string())?;
+    let mut keys = Vec::new();
+    for (line_no, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        let (key_id, algorithm, key) = match parts.as_slice() {
+            [key_id, algorithm, key] => (key_id, algorithm, key),
+            _ => return Err(format!("line {}: expected <key id> <algorithm> <hex key>", line_no + 1)),
+        };
+        let algorithm = TokenAlgorithm::from_name(algorithm)
+            .ok_or_else(|| format!("line {}: unknown algorithm {algorithm}", line_no + 1))?;
+        let key = decode_hex(key).ok_or_else(|| format!("line {}: key is not hex", line_no + 1))?;
+
+        keys.push(TokenKey {
+            key_id: key_id.to_string(),
+            algorithm,
+            key,
+            valid_at: DateTime::<Utc>::MIN_UTC,
+            expires_at: DateTime::<Utc>::MAX_UTC,
+        });
+    }
+
+    Ok(TokenKeyRing::new(keys))
+}
+
+/// Decode `token` into a JSON description of its envelope and claims.
+fn describe(token: &str, keys: &TokenKeyRing) -> Result<Value, String> {
+    let header = match inspect_envelope(token) {
+        Ok(header) => header,
+        Err(_) => {
+            let claims = decode_session_token(token).map_err(|e| format!("Not a session token: {e:?}"))?;
+            return Ok(json!({ "encrypted": false, "claims": redacted(&claims), "times": times(&claims) }));
+        }
+    };
+
+    let envelope = json!({
+        "version": header.version,
+        "algorithm": header.algorithm.name(),
+        "key_id": header.key_id,
+    });
+
+    if !keys.keys().iter().any(|key| key.key_id == header.key_id) {
+        return Ok(json!({ "encrypted": true, "envelope": envelope }));
+    }
+
+    let claims = keys.open(token, Utc::now()).map_err(|e| format!("Unable to decrypt token: {e:?}"))?;
+    Ok(json!({ "encrypted": true, "envelope": envelope, "claims": redacted(&claims), "times": times(&claims) }))
+}
+
+/// The claims as JSON, with the secret access key of the session replaced.
+fn redacted(claims: &SessionClaims) -> Value {
+    let mut value = json!(claims);
+    if let Some(secret_access_key) = value.get_mut("secret_access_key") {
+        *secret_access_key = json!("<redacted>");
+    }
+    value
+}
+
+/// The token's timestamps in RFC 3339 form, and whether it has expired.
+fn times(claims: &SessionClaims) -> Value {
+    let format = |seconds: i64| {
+        NaiveDateTime::from_timestamp_opt(seconds, 0)
+            .map(|t| DateTime::<Utc>::from_utc(t, Utc).to_rfc3339())
+            .unwrap_or_else(|| seconds.to_string())
+    };
+
+    json!({
+        "issued_at": format(claims.issued_at),
+        "expires_at": format(claims.expires_at),
+        "expired": claims.expires_at <= Utc::now().timestamp(),
+    })
+}